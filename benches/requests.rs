@@ -0,0 +1,79 @@
+//! Full request throughput through the router as a `tower::Service`
+//!
+//! Requests go through routing, extraction, middleware and serialization,
+//! without a network socket.
+//!
+//! ```bash
+//! cargo bench --bench requests
+//! ```
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::{body::Body, http::Request};
+use criterion::{criterion_group, criterion_main, Criterion};
+use rust_api::{mock, router, AppState};
+use tower::ServiceExt;
+
+fn requests(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let state = AppState::new();
+    let users = runtime.block_on(mock::seed(&state, 1, 1_000));
+    let app = router(state);
+    let id = users[0].id;
+
+    let mut group = c.benchmark_group("requests");
+
+    group.bench_function("health", |b| {
+        b.to_async(&runtime).iter(|| {
+            let request = Request::get("/").body(Body::empty()).unwrap();
+            app.clone().oneshot(request)
+        })
+    });
+
+    group.bench_function("get_user", |b| {
+        b.to_async(&runtime).iter(|| {
+            let request = Request::get(format!("/api/v1/users/{}", id))
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(request)
+        })
+    });
+
+    group.bench_function("get_user_rfc3339", |b| {
+        b.to_async(&runtime).iter(|| {
+            let request = Request::get(format!("/api/v1/users/{}", id))
+                .header("accept", "application/json; timestamps=rfc3339")
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(request)
+        })
+    });
+
+    group.bench_function("list_users_1000", |b| {
+        b.to_async(&runtime).iter(|| {
+            let request = Request::get("/api/v1/users").body(Body::empty()).unwrap();
+            app.clone().oneshot(request)
+        })
+    });
+
+    let counter = AtomicU64::new(0);
+    group.bench_function("create_user", |b| {
+        b.to_async(&runtime).iter(|| {
+            let n = counter.fetch_add(1, Ordering::Relaxed);
+            let body = format!(
+                r#"{{"name": "Bench User", "email": "bench{}@example.com"}}"#,
+                n
+            );
+            let request = Request::post("/api/v1/users")
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            app.clone().oneshot(request)
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, requests);
+criterion_main!(benches);
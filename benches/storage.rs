@@ -0,0 +1,276 @@
+//! Storage benchmarks
+//!
+//! `storage/*` measures single operations on [`Storage`] at different sizes.
+//!
+//! `list/*` serializes a large store the way `GET /api/v1/users` does, from
+//! the shared users `get_all` returns and, for comparison, from deep copies
+//! as before users were shared. The allocations each variant makes per
+//! call are printed before it runs:
+//!
+//! ```bash
+//! cargo bench --bench storage -- list
+//! ```
+//!
+//! `contention/*` is the perf mode for the concurrency redesign: it runs the
+//! same mixed workload (90% reads, 10% writes, spread over concurrent tasks)
+//! against today's single `RwLock<Storage>` and against a sharded prototype
+//! that splits users over independently locked shards by ID.
+//!
+//! ```bash
+//! cargo bench --bench storage -- contention
+//! ```
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use chrono::Utc;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rust_api::models::User;
+use rust_api::responses::ApiResponse;
+use rust_api::Storage;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// The system allocator, counting allocations
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Returns the number of allocations `f` makes
+fn allocations<T>(f: impl FnOnce() -> T) -> u64 {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    drop(f());
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn user(n: u64) -> User {
+    User::new(
+        format!("User {}", n),
+        format!("user{}@example.com", n),
+        Utc::now(),
+    )
+    .with_id(Uuid::from_u64_pair(0, n))
+    .with_phone(format!("+1212555{:04}", n % 10_000))
+}
+
+fn filled(count: u64) -> Storage {
+    let mut storage = Storage::new();
+    for n in 0..count {
+        storage.create(user(n)).expect("unique ID");
+    }
+    storage
+}
+
+fn single_operations(c: &mut Criterion) {
+    let mut group = c.benchmark_group("storage");
+
+    for size in [1_000u64, 10_000] {
+        let storage = filled(size);
+        let id = Uuid::from_u64_pair(0, size / 2);
+        let email = format!("user{}@example.com", size - 1);
+
+        group.bench_with_input(BenchmarkId::new("get", size), &storage, |b, storage| {
+            b.iter(|| storage.get(&id))
+        });
+        group.bench_with_input(BenchmarkId::new("get_all", size), &storage, |b, storage| {
+            b.iter(|| storage.get_all())
+        });
+        group.bench_with_input(
+            BenchmarkId::new("email_exists", size),
+            &storage,
+            |b, storage| b.iter(|| storage.email_exists(&email)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("find_by_phone", size),
+            &storage,
+            |b, storage| b.iter(|| storage.find_by_phone("+12125559999")),
+        );
+        group.bench_with_input(BenchmarkId::new("create", size), &size, |b, &size| {
+            b.iter_batched(
+                || filled(size),
+                |mut storage| storage.create(user(size)),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+/// Serializes all users into a list response body
+fn list_body(users: Vec<Arc<User>>) -> Vec<u8> {
+    serde_json::to_vec(&ApiResponse::collection(users)).unwrap()
+}
+
+fn list(c: &mut Criterion) {
+    let mut group = c.benchmark_group("list");
+    group.sample_size(20);
+
+    let size = 100_000;
+    let storage = filled(size);
+    let shared = || list_body(storage.get_all());
+    let copied = || {
+        let users = storage
+            .get_all()
+            .iter()
+            .map(|user| Arc::new(User::clone(user)))
+            .collect();
+        list_body(users)
+    };
+
+    for (name, variant) in [
+        ("shared", &shared as &dyn Fn() -> Vec<u8>),
+        ("copied", &copied),
+    ] {
+        println!(
+            "list/{}/{}: {} allocations per call",
+            name,
+            size,
+            allocations(variant)
+        );
+        group.bench_function(BenchmarkId::new(name, size), |b| b.iter(variant));
+    }
+
+    group.finish();
+}
+
+/// Users split over independently locked shards, selected by ID
+///
+/// A prototype for comparison only: cross-shard constraints such as email
+/// uniqueness are not enforced.
+struct ShardedStorage {
+    shards: Vec<RwLock<Storage>>,
+}
+
+impl ShardedStorage {
+    fn new(shards: usize, users: u64) -> Self {
+        let shards: Vec<_> = (0..shards).map(|_| RwLock::new(Storage::new())).collect();
+        let sharded = Self { shards };
+        for n in 0..users {
+            let user = user(n);
+            sharded
+                .shard(&user.id)
+                .try_write()
+                .expect("uncontended")
+                .create(user)
+                .expect("unique ID");
+        }
+        sharded
+    }
+
+    fn shard(&self, id: &Uuid) -> &RwLock<Storage> {
+        let (_, low) = id.as_u64_pair();
+        &self.shards[(low % self.shards.len() as u64) as usize]
+    }
+}
+
+const USERS: u64 = 10_000;
+const TASKS: u64 = 8;
+const OPS_PER_TASK: u64 = 500;
+
+/// Picks the operation for step `i`: every tenth one is a write
+fn is_write(i: u64) -> bool {
+    i % 10 == 0
+}
+
+fn contention(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("contention");
+    let next_id = Arc::new(AtomicU64::new(USERS));
+
+    let single = Arc::new(RwLock::new(filled(USERS)));
+    group.bench_function("rwlock", |b| {
+        b.to_async(&runtime).iter(|| {
+            let storage = single.clone();
+            let next_id = next_id.clone();
+            async move {
+                let tasks: Vec<_> = (0..TASKS)
+                    .map(|task| {
+                        let storage = storage.clone();
+                        let next_id = next_id.clone();
+                        tokio::spawn(async move {
+                            for i in 0..OPS_PER_TASK {
+                                if is_write(i) {
+                                    let n = next_id.fetch_add(1, Ordering::Relaxed);
+                                    storage.write().await.create(user(n)).expect("unique ID");
+                                } else {
+                                    let id = Uuid::from_u64_pair(0, (task * 997 + i) % USERS);
+                                    storage.read().await.get(&id);
+                                }
+                            }
+                        })
+                    })
+                    .collect();
+                for task in tasks {
+                    task.await.unwrap();
+                }
+            }
+        })
+    });
+
+    for shards in [4, 16] {
+        let sharded = Arc::new(ShardedStorage::new(shards, USERS));
+        group.bench_function(BenchmarkId::new("sharded", shards), |b| {
+            b.to_async(&runtime).iter(|| {
+                let storage = sharded.clone();
+                let next_id = next_id.clone();
+                async move {
+                    let tasks: Vec<_> = (0..TASKS)
+                        .map(|task| {
+                            let storage = storage.clone();
+                            let next_id = next_id.clone();
+                            tokio::spawn(async move {
+                                for i in 0..OPS_PER_TASK {
+                                    if is_write(i) {
+                                        let n = next_id.fetch_add(1, Ordering::Relaxed);
+                                        let user = user(n);
+                                        storage
+                                            .shard(&user.id)
+                                            .write()
+                                            .await
+                                            .create(user)
+                                            .expect("unique ID");
+                                    } else {
+                                        let id = Uuid::from_u64_pair(0, (task * 997 + i) % USERS);
+                                        storage.shard(&id).read().await.get(&id);
+                                    }
+                                }
+                            })
+                        })
+                        .collect();
+                    for task in tasks {
+                        task.await.unwrap();
+                    }
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, single_operations, list, contention);
+criterion_main!(benches);
@@ -0,0 +1,145 @@
+// Admin dashboard: reads the admin API with the token entered in the form.
+// The token stays in this tab's session storage and is sent as a bearer
+// token; the page itself holds no data.
+"use strict";
+
+const REFRESH_MS = 15000;
+const BREAKER_STATES = ["closed", "open", "half-open"];
+
+const $ = (id) => document.getElementById(id);
+
+function token() {
+  return sessionStorage.getItem("rust-api-token") || "";
+}
+
+// Fetches `path`; `options.text` reads the body as text, and
+// `options.unavailable` accepts a 503 carrying a report
+async function get(path, options = {}) {
+  const headers = { Accept: options.text ? "text/plain" : "application/json" };
+  if (token()) {
+    headers.Authorization = "Bearer " + token();
+  }
+  const response = await fetch(path, { headers });
+  if (!response.ok && !(options.unavailable && response.status === 503)) {
+    let message = response.status + " " + response.statusText;
+    try {
+      const body = await response.json();
+      message = body.message || body.error || message;
+    } catch (_) {
+      // Keep the status line
+    }
+    throw new Error(path + ": " + message);
+  }
+  return options.text ? response.text() : response.json();
+}
+
+function cell(row, text, className) {
+  const td = row.insertCell();
+  td.textContent = text == null ? "" : String(text);
+  if (className) {
+    td.className = className;
+  }
+}
+
+function fill(tbody, items, render) {
+  tbody.replaceChildren();
+  for (const item of items) {
+    render(tbody.insertRow(), item);
+  }
+  if (!items.length) {
+    cell(tbody.insertRow(), "none");
+  }
+}
+
+function renderUsers(health, trash) {
+  const dl = $("users");
+  dl.replaceChildren();
+  const entries = [
+    ["Stored", health.storage.users],
+    ["Limit", health.storage.max_users == null ? "none" : health.storage.max_users],
+    ["In the trash", trash.count],
+  ];
+  for (const [term, value] of entries) {
+    const dt = document.createElement("dt");
+    dt.textContent = term;
+    const dd = document.createElement("dd");
+    dd.textContent = String(value);
+    dl.append(dt, dd);
+  }
+}
+
+function renderHealth(readiness) {
+  $("status").textContent = readiness.status;
+  $("status").className = readiness.status;
+  fill($("checks"), Object.entries(readiness.checks), (row, [name, check]) => {
+    cell(row, name);
+    cell(row, check.status, check.status);
+    cell(row, check.detail);
+  });
+}
+
+// Breakers are only exposed as metrics, e.g.
+// circuit_breaker_state{name="mailer"} 1
+function renderBreakers(metrics) {
+  const breakers = new Map();
+  const sample = /^circuit_breaker_(state|rejected_total)\{name="([^"]*)"\} (\d+)/;
+  for (const line of metrics.split("\n")) {
+    const match = sample.exec(line);
+    if (match) {
+      const breaker = breakers.get(match[2]) || {};
+      breaker[match[1]] = Number(match[3]);
+      breakers.set(match[2], breaker);
+    }
+  }
+  fill($("breakers"), [...breakers], (row, [name, breaker]) => {
+    const state = BREAKER_STATES[breaker.state] || "unknown";
+    cell(row, name);
+    cell(row, state, state === "closed" ? "healthy" : "degraded");
+    cell(row, breaker.rejected_total || 0);
+  });
+}
+
+function renderAudit(audit) {
+  fill($("audit"), audit.entries, (row, entry) => {
+    cell(row, new Date(entry.at).toLocaleString());
+    const principal = entry.impersonator
+      ? entry.impersonator + " as " + entry.principal
+      : entry.principal || "anonymous";
+    cell(row, principal);
+    cell(row, entry.method + " " + entry.path);
+    cell(row, entry.status, entry.status >= 400 ? "degraded" : "");
+    cell(row, entry.client_ip);
+  });
+}
+
+async function refresh() {
+  const error = $("error");
+  try {
+    const [health, readiness, trash, metrics, audit] = await Promise.all([
+      get("/health/deep"),
+      get("/readyz", { unavailable: true }),
+      get("/api/v1/users/trash"),
+      get("/metrics", { text: true }),
+      get("/api/v1/admin/audit?limit=20"),
+    ]);
+    renderUsers(health, trash);
+    renderHealth(readiness);
+    renderBreakers(metrics);
+    renderAudit(audit);
+    error.hidden = true;
+  } catch (err) {
+    error.textContent = err.message;
+    error.hidden = false;
+  }
+}
+
+document.addEventListener("DOMContentLoaded", () => {
+  $("token").value = token();
+  $("token-form").addEventListener("submit", (event) => {
+    event.preventDefault();
+    sessionStorage.setItem("rust-api-token", $("token").value.trim());
+    refresh();
+  });
+  refresh();
+  setInterval(refresh, REFRESH_MS);
+});
@@ -0,0 +1,15 @@
+//! Sends generated create, update, read and delete requests through the
+//! router, failing on any server error
+//!
+//! Run with `cargo +nightly fuzz run requests` from the repository root.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_api::fuzz::{run_blocking, Operation};
+
+fuzz_target!(|operations: Vec<Operation>| {
+    if let Err(finding) = run_blocking(&operations) {
+        panic!("{}", finding);
+    }
+});
@@ -0,0 +1,235 @@
+//! Audit trail of changes made through the API
+//!
+//! Every successful request that may change state (any method but GET,
+//! HEAD and OPTIONS) is recorded with the authenticated principal (and the
+//! administrator behind an impersonation token), the
+//! client address and, when a GeoIP database is configured, the address's
+//! country and city. Entries are kept in memory up to
+//! `APP_AUDIT_CAPACITY`, dropping the oldest first, and can be reviewed at
+//! `GET /api/v1/admin/audit`.
+//!
+//! Dry runs ([`crate::dry_run`]) change nothing and are not recorded.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+
+use crate::auth::Principal;
+use crate::dry_run;
+use crate::extract::ClientIp;
+use crate::models::{AuditEntry, AuditQuery};
+use crate::AppState;
+
+#[derive(Debug, Default)]
+struct Entries {
+    entries: VecDeque<AuditEntry>,
+    next_id: u64,
+}
+
+/// The retained audit entries, oldest first
+#[derive(Debug)]
+pub struct AuditLog {
+    inner: Mutex<Entries>,
+    capacity: usize,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self::new(10_000)
+    }
+}
+
+impl AuditLog {
+    /// Creates an empty log retaining at most `capacity` entries
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::default(),
+            capacity,
+        }
+    }
+
+    /// Appends an entry, assigning its ID
+    pub fn record(&self, mut entry: AuditEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.lock();
+        inner.next_id += 1;
+        entry.id = inner.next_id;
+        if inner.entries.len() >= self.capacity {
+            inner.entries.pop_front();
+        }
+        inner.entries.push_back(entry);
+    }
+
+    /// Returns the entries matching `query`, newest first
+    pub fn query(&self, query: &AuditQuery) -> Vec<AuditEntry> {
+        let inner = self.lock();
+        inner
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| {
+                query.principal.as_ref().map_or(true, |principal| {
+                    entry.principal.as_ref() == Some(principal)
+                })
+            })
+            .filter(|entry| {
+                query.country.as_ref().map_or(true, |country| {
+                    entry
+                        .location
+                        .as_ref()
+                        .and_then(|location| location.country.as_ref())
+                        .is_some_and(|code| code.eq_ignore_ascii_case(country))
+                })
+            })
+            .filter(|entry| query.since.map_or(true, |since| entry.at >= since))
+            .take(query.limit())
+            .cloned()
+            .collect()
+    }
+
+    /// Attributes the entries made by principal `from` to `to`
+    ///
+    /// Used when accounts are merged, so the remaining account's history
+    /// includes the removed one's.
+    pub fn reassign(&self, from: &str, to: &str) {
+        let mut inner = self.lock();
+        for entry in inner.entries.iter_mut() {
+            if entry.principal.as_deref() == Some(from) {
+                entry.principal = Some(to.to_string());
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Middleware recording successful state-changing requests
+///
+/// The principal is read from the response, where [`crate::auth::require`]
+/// leaves it, since authentication runs inside this layer.
+pub async fn record(
+    State(state): State<AppState>,
+    client_ip: Option<ClientIp>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return next.run(request).await;
+    }
+
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let response = next.run(request).await;
+    if !response.status().is_success() || dry_run::is_preview(&response) {
+        return response;
+    }
+
+    let client_ip = client_ip.map(|ClientIp(ip)| ip);
+    let principal = response.extensions().get::<Principal>();
+    state.audit.record(AuditEntry {
+        id: 0,
+        at: state.clock.now(),
+        principal: principal.map(|principal| principal.name.clone()),
+        impersonator: principal.and_then(|principal| principal.impersonator.clone()),
+        method,
+        path,
+        status: response.status().as_u16(),
+        client_ip,
+        location: client_ip.and_then(|ip| state.geoip.locate(ip)),
+    });
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Location;
+    use chrono::{Duration, Utc};
+
+    fn entry(principal: &str, country: Option<&str>) -> AuditEntry {
+        AuditEntry {
+            id: 0,
+            at: Utc::now(),
+            principal: Some(principal.to_string()),
+            impersonator: None,
+            method: "DELETE".to_string(),
+            path: "/api/v1/users/1".to_string(),
+            status: 204,
+            client_ip: None,
+            location: country.map(|country| Location {
+                country: Some(country.to_string()),
+                city: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_capacity_drops_oldest() {
+        let log = AuditLog::new(2);
+        log.record(entry("a", None));
+        log.record(entry("b", None));
+        log.record(entry("c", None));
+
+        let entries = log.query(&AuditQuery::default());
+        let ids: Vec<u64> = entries.iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, vec![3, 2]);
+    }
+
+    #[test]
+    fn test_query_filters() {
+        let log = AuditLog::default();
+        log.record(entry("ops", Some("DE")));
+        log.record(entry("ops", Some("FR")));
+        log.record(entry("billing", Some("DE")));
+
+        let query = |principal: Option<&str>, country: Option<&str>| {
+            log.query(&AuditQuery {
+                principal: principal.map(str::to_string),
+                country: country.map(str::to_string),
+                ..AuditQuery::default()
+            })
+            .len()
+        };
+        assert_eq!(query(None, None), 3);
+        assert_eq!(query(Some("ops"), None), 2);
+        assert_eq!(query(None, Some("de")), 2);
+        assert_eq!(query(Some("ops"), Some("DE")), 1);
+
+        let future = AuditQuery {
+            since: Some(Utc::now() + Duration::minutes(1)),
+            ..AuditQuery::default()
+        };
+        assert!(log.query(&future).is_empty());
+    }
+
+    #[test]
+    fn test_reassign() {
+        let log = AuditLog::default();
+        log.record(entry("old", None));
+        log.record(entry("other", None));
+        log.reassign("old", "new");
+
+        let principal = |name: &str| AuditQuery {
+            principal: Some(name.to_string()),
+            ..AuditQuery::default()
+        };
+        assert!(log.query(&principal("old")).is_empty());
+        assert_eq!(log.query(&principal("new")).len(), 1);
+        assert_eq!(log.query(&principal("other")).len(), 1);
+    }
+}
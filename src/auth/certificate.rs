@@ -0,0 +1,73 @@
+//! Client certificate authentication
+//!
+//! When the server terminates TLS and asks for client certificates (see
+//! [`crate::tls`]), the subject common name of a verified certificate is
+//! attached to every request of the connection as a [`ClientCertificate`].
+//! `APP_CLIENT_CERT_PRINCIPALS` maps common names to principals, written
+//! like API tokens as `principal:common-name=scope+scope`.
+//!
+//! A certificate whose common name is not mapped grants nothing; the
+//! caller can still authenticate with a token or a signature.
+
+use std::collections::HashMap;
+
+use super::{ApiToken, Principal};
+
+/// The verified certificate presented on the request's connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertificate {
+    /// The certificate's subject common name
+    pub common_name: String,
+}
+
+/// Principals by certificate common name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Subjects(HashMap<String, Principal>);
+
+impl FromIterator<ApiToken> for Subjects {
+    fn from_iter<I: IntoIterator<Item = ApiToken>>(subjects: I) -> Self {
+        Subjects(
+            subjects
+                .into_iter()
+                .map(|subject| (subject.token, subject.principal))
+                .collect(),
+        )
+    }
+}
+
+impl Subjects {
+    /// Returns `true` if no certificate subject is mapped
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the principal a certificate identifies
+    pub fn principal(&self, certificate: &ClientCertificate) -> Option<&Principal> {
+        self.0.get(&certificate.common_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scope;
+
+    #[test]
+    fn test_subjects_map_common_names() {
+        let subjects: Subjects = ["billing:billing.internal=users:read"
+            .parse::<ApiToken>()
+            .unwrap()]
+        .into_iter()
+        .collect();
+        let certificate = |common_name: &str| ClientCertificate {
+            common_name: common_name.to_string(),
+        };
+
+        let principal = subjects
+            .principal(&certificate("billing.internal"))
+            .unwrap();
+        assert_eq!(principal.name, "billing");
+        assert_eq!(principal.scopes, vec![Scope::UsersRead]);
+        assert!(subjects.principal(&certificate("billing")).is_none());
+    }
+}
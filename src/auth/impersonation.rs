@@ -0,0 +1,108 @@
+//! Time-limited tokens for acting as a user
+//!
+//! An administrator can obtain a bearer token that acts as a given user,
+//! for example to reproduce a problem the user reported. The token's
+//! [`Principal`] names the user and carries the administrator as
+//! `impersonator`, so every audit entry made with it records both. Tokens
+//! expire after `APP_IMPERSONATION_TTL_SECONDS` and never grant the admin
+//! scope.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::{Principal, Scope};
+
+/// Prefix of impersonation tokens, distinguishing them from configured
+/// tokens in logs and support tickets
+const TOKEN_PREFIX: &str = "imp_";
+
+#[derive(Debug, Clone)]
+struct Session {
+    principal: Principal,
+    expires_at: DateTime<Utc>,
+}
+
+/// Issued impersonation tokens
+#[derive(Debug, Default)]
+pub struct Sessions {
+    inner: Mutex<HashMap<String, Session>>,
+}
+
+impl Sessions {
+    /// Issues a token acting as `user_id` on behalf of `impersonator`
+    ///
+    /// Tokens expired at `now` are dropped on the way.
+    pub fn issue(
+        &self,
+        user_id: Uuid,
+        impersonator: &str,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> String {
+        let token = format!(
+            "{}{}{}",
+            TOKEN_PREFIX,
+            Uuid::new_v4().simple(),
+            Uuid::new_v4().simple()
+        );
+        let session = Session {
+            principal: Principal {
+                name: user_id.to_string(),
+                scopes: vec![Scope::UsersRead, Scope::UsersWrite],
+                impersonator: Some(impersonator.to_string()),
+            },
+            expires_at,
+        };
+
+        let mut inner = self.lock();
+        inner.retain(|_, session| session.expires_at > now);
+        inner.insert(token.clone(), session);
+        token
+    }
+
+    /// Returns the principal of an unexpired token
+    pub fn principal(&self, token: &str, now: DateTime<Utc>) -> Option<Principal> {
+        if !token.starts_with(TOKEN_PREFIX) {
+            return None;
+        }
+        self.lock()
+            .get(token)
+            .filter(|session| session.expires_at > now)
+            .map(|session| session.principal.clone())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Session>> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_token_acts_as_user_until_expiry() {
+        let sessions = Sessions::default();
+        let user_id = Uuid::new_v4();
+        let now = Utc::now();
+        let token = sessions.issue(user_id, "ops", now, now + Duration::minutes(15));
+        assert!(token.starts_with(TOKEN_PREFIX));
+
+        let principal = sessions.principal(&token, now).unwrap();
+        assert_eq!(principal.name, user_id.to_string());
+        assert_eq!(principal.impersonator.as_deref(), Some("ops"));
+        assert!(principal.has_scope(Scope::UsersWrite));
+        assert!(!principal.has_scope(Scope::Admin));
+
+        assert!(sessions
+            .principal(&token, now + Duration::minutes(16))
+            .is_none());
+        assert!(sessions.principal("imp_unknown", now).is_none());
+    }
+}
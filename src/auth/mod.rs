@@ -0,0 +1,516 @@
+//! Bearer token authentication with scopes
+//!
+//! API tokens are configured through `APP_API_TOKENS`. Each token belongs
+//! to a named principal and grants a set of [`Scope`]s; every protected
+//! route requires one scope, checked by [`require`]. Read-only integrations
+//! can therefore be issued tokens that cannot modify anything.
+//!
+//! Server-to-server callers may sign requests with a shared secret
+//! instead; see [`signing`]. Behind the server's own TLS termination they
+//! can also present a client certificate; see [`certificate`].
+//!
+//! A principal named after the ID of a stored user, such as the one of an
+//! [`impersonation`] token, acts as that user. Its requests are refused
+//! while the user's status does not allow logging in; the admitted ones
+//! are recorded by [`record_activity`] in the user's `last_seen_at`, and
+//! `last_login_at` after [`LOGIN_IDLE`].
+//!
+//! Without any configured token, signing key or certificate subject,
+//! authentication is disabled and every route is open.
+
+pub mod certificate;
+pub mod impersonation;
+pub mod signing;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+        HeaderValue,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::error::ApiError;
+use crate::events::{Event, FieldDiff};
+use crate::i18n::Message;
+use crate::mock::Clock;
+use crate::models::User;
+use crate::replication::Role;
+use crate::timing::TimedLock;
+use crate::{AppState, Config, Storage};
+
+use self::certificate::ClientCertificate;
+use self::impersonation::Sessions;
+use self::signing::{NonceCache, SignatureError};
+
+/// Largest body buffered to verify a request signature
+const MAX_SIGNED_BODY: usize = 2 * 1024 * 1024;
+
+/// How long a user has to be inactive for their next request to count as
+/// a new login
+pub const LOGIN_IDLE: std::time::Duration = std::time::Duration::from_secs(30 * 60);
+
+/// How old a user's `last_seen_at` has to be for a request to move it
+///
+/// Every recorded request changes the store, and with it the collection's
+/// `ETag`, so activity is only kept to this precision.
+pub const SEEN_GRANULARITY: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+/// Permission granted by a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    /// Read users (`users:read`)
+    UsersRead,
+    /// Create, modify and delete users (`users:write`)
+    UsersWrite,
+    /// Operator endpoints; implies every other scope (`admin`)
+    Admin,
+}
+
+impl Scope {
+    /// Returns the scope's name as used in configuration and errors
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::UsersRead => "users:read",
+            Scope::UsersWrite => "users:write",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "users:read" => Ok(Scope::UsersRead),
+            "users:write" => Ok(Scope::UsersWrite),
+            "admin" => Ok(Scope::Admin),
+            other => Err(format!(
+                "unknown scope '{}' (expected users:read, users:write or admin)",
+                other
+            )),
+        }
+    }
+}
+
+/// The authenticated caller, available to handlers as a request extension
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    /// Name of the token's owner
+    pub name: String,
+    /// Scopes granted to the token
+    pub scopes: Vec<Scope>,
+    /// The administrator acting as this principal, for impersonation
+    /// tokens
+    pub impersonator: Option<String>,
+}
+
+impl Principal {
+    /// Returns `true` if the principal may act within `scope`
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+
+    /// Returns the ID of the user the principal acts as, if it is named
+    /// after one
+    pub fn user_id(&self) -> Option<Uuid> {
+        self.name.parse().ok()
+    }
+}
+
+/// A configured token, written as `principal:token=scope+scope`
+#[derive(Clone, PartialEq, Eq)]
+pub struct ApiToken {
+    /// The secret presented as `Authorization: Bearer <token>`
+    pub token: String,
+    /// The caller the token identifies
+    pub principal: Principal,
+}
+
+impl std::fmt::Debug for ApiToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApiToken")
+            .field("token", &"<redacted>")
+            .field("principal", &self.principal)
+            .finish()
+    }
+}
+
+impl FromStr for ApiToken {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected principal:token=scope+scope, got '{}'", s);
+        let (credentials, scopes) = s.rsplit_once('=').ok_or_else(invalid)?;
+        let (name, token) = credentials.split_once(':').ok_or_else(invalid)?;
+        let (name, token) = (name.trim(), token.trim());
+        if name.is_empty() || token.is_empty() {
+            return Err(invalid());
+        }
+        let scopes = scopes
+            .split('+')
+            .filter(|scope| !scope.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<Scope>, _>>()?;
+        if scopes.is_empty() {
+            return Err(format!("token of '{}' grants no scope", name));
+        }
+
+        Ok(ApiToken {
+            token: token.to_string(),
+            principal: Principal {
+                name: name.to_string(),
+                scopes,
+                impersonator: None,
+            },
+        })
+    }
+}
+
+/// The configured tokens, looked up by secret
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Tokens(HashMap<String, Principal>);
+
+impl std::fmt::Debug for Tokens {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.0.values().map(|principal| &principal.name))
+            .finish()
+    }
+}
+
+impl FromIterator<ApiToken> for Tokens {
+    fn from_iter<I: IntoIterator<Item = ApiToken>>(tokens: I) -> Self {
+        Tokens(
+            tokens
+                .into_iter()
+                .map(|token| (token.token, token.principal))
+                .collect(),
+        )
+    }
+}
+
+impl Tokens {
+    /// Returns `true` if no token is configured, i.e. authentication is off
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the principal a token belongs to
+    pub fn principal(&self, token: &str) -> Option<&Principal> {
+        self.0.get(token)
+    }
+}
+
+/// The scope a route requires, used as middleware state
+#[derive(Debug, Clone)]
+pub struct Permission {
+    config: Arc<Config>,
+    nonces: Arc<NonceCache>,
+    impersonations: Arc<Sessions>,
+    storage: Arc<TimedLock<Storage>>,
+    clock: Clock,
+    scope: Scope,
+}
+
+impl Permission {
+    /// Requires `scope` for a route
+    pub fn new(state: &AppState, scope: Scope) -> Self {
+        Self {
+            config: state.config.clone(),
+            nonces: state.nonces.clone(),
+            impersonations: state.impersonations.clone(),
+            storage: state.storage.clone(),
+            clock: state.clock,
+            scope,
+        }
+    }
+}
+
+/// Returns `true` if callers have to authenticate: tokens, signing keys
+/// or client certificate principals are configured
+pub fn is_enabled(config: &Config) -> bool {
+    !(config.api_tokens.is_empty()
+        && config.signing_keys.is_empty()
+        && config.client_cert_principals.is_empty())
+}
+
+/// Middleware authenticating the caller and checking its scope
+///
+/// Callers present a client certificate or a bearer token, or sign the
+/// request. On success the caller's [`Principal`] is added to the request
+/// and response extensions. Requests without valid credentials are
+/// rejected with 401, and callers acting as a user who may not log in or
+/// lacking the route's scope with 403.
+pub async fn require(
+    State(permission): State<Permission>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let config = &permission.config;
+    if !is_enabled(config) {
+        return next.run(request).await;
+    }
+
+    let certified = request
+        .extensions()
+        .get::<ClientCertificate>()
+        .and_then(|certificate| config.client_cert_principals.principal(certificate))
+        .cloned();
+    let (principal, mut request) = if let Some(principal) = certified {
+        (principal, request)
+    } else if signing::is_signed(request.headers()) {
+        match verify_signature(&permission, request).await {
+            Ok(verified) => verified,
+            Err(response) => return response,
+        }
+    } else {
+        let Some(token) = bearer_token(&request) else {
+            return challenge(
+                ApiError::Unauthorized(Message::new("auth.missing_token")),
+                "Bearer".to_string(),
+            );
+        };
+        let principal = config.api_tokens.principal(token).cloned().or_else(|| {
+            permission
+                .impersonations
+                .principal(token, permission.clock.now())
+        });
+        let Some(principal) = principal else {
+            return challenge(
+                ApiError::Unauthorized(Message::new("auth.invalid_token")),
+                "Bearer error=\"invalid_token\"".to_string(),
+            );
+        };
+        (principal, request)
+    };
+
+    if let Err(response) = admit_user(&permission, &principal).await {
+        return response;
+    }
+    if !principal.has_scope(permission.scope) {
+        return challenge(
+            ApiError::Forbidden(
+                Message::new("auth.insufficient_scope").with("scope", permission.scope),
+            ),
+            format!(
+                "Bearer error=\"insufficient_scope\", scope=\"{}\"",
+                permission.scope
+            ),
+        );
+    }
+
+    request.extensions_mut().insert(principal.clone());
+    let mut response = next.run(request).await;
+    // Lets outer layers such as the audit log attribute the request
+    response.extensions_mut().insert(principal);
+    response
+}
+
+/// Refuses principals acting as a user whose status does not allow logging
+/// in, such as a suspended one
+async fn admit_user(permission: &Permission, principal: &Principal) -> Result<(), Response> {
+    let Some(id) = principal.user_id() else {
+        return Ok(());
+    };
+    let Some(user) = permission.storage.read().await.get(&id) else {
+        return Ok(());
+    };
+    if !user.status.can_log_in() {
+        return Err(ApiError::Forbidden(
+            Message::new("auth.account_inactive").with("status", user.status),
+        )
+        .into_response());
+    }
+    Ok(())
+}
+
+/// Activity a request of a user records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Activity {
+    /// The user was not seen for [`LOGIN_IDLE`]
+    Login,
+    /// `last_seen_at` is [`SEEN_GRANULARITY`] old
+    Seen,
+}
+
+impl Activity {
+    /// Returns what a request at `now` records for `user`, if anything
+    fn of(user: &User, now: DateTime<Utc>) -> Option<Self> {
+        let Some(seen) = user.last_seen_at else {
+            return Some(Activity::Login);
+        };
+        match (now - seen).to_std() {
+            Ok(idle) if idle >= LOGIN_IDLE => Some(Activity::Login),
+            Ok(idle) if idle >= SEEN_GRANULARITY => Some(Activity::Seen),
+            _ => None,
+        }
+    }
+}
+
+/// Middleware recording the activity of principals acting as a user
+///
+/// Runs inside [`require`] and [`crate::rate_limit::check`], so refused
+/// and throttled requests do not count. A request counts as a login when
+/// the user was not seen for [`LOGIN_IDLE`]; otherwise it moves
+/// `last_seen_at` once that is [`SEEN_GRANULARITY`] old. The change is
+/// published like any other update, so cached responses are invalidated
+/// and followers copy it. Followers and replicas leave their copy to
+/// replication and record nothing.
+pub async fn record_activity(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let replicates = state.replication.role().is_some_and(Role::replicates);
+    let id = request
+        .extensions()
+        .get::<Principal>()
+        .and_then(Principal::user_id)
+        .filter(|_| !replicates);
+    if let Some(id) = id {
+        record(&state, &id).await;
+    }
+    next.run(request).await
+}
+
+async fn record(state: &AppState, id: &Uuid) {
+    let now = state.clock.now();
+    // Most requests record nothing, and need no exclusive lock
+    let due = |user: User| Activity::of(&user, now);
+    if state.storage.read().await.get(id).and_then(due).is_none() {
+        return;
+    }
+
+    let mut storage = state.storage.write().await;
+    let Some(previous) = storage.get(id) else {
+        return;
+    };
+    let recorded = match Activity::of(&previous, now) {
+        Some(Activity::Login) => storage.record_login(id, now),
+        Some(Activity::Seen) => storage.record_seen(id, now),
+        None => return,
+    };
+    if let Ok(user) = recorded {
+        let diff = FieldDiff::between(&previous, user);
+        state.events.publish(Event::UserUpdated(user.clone(), diff));
+    }
+}
+
+/// Buffers the body of a signed request and verifies its signature
+///
+/// Returns the signing principal and the request with its body restored.
+async fn verify_signature(
+    permission: &Permission,
+    request: Request,
+) -> Result<(Principal, Request), Response> {
+    let (parts, body) = request.into_parts();
+    let body = axum::body::to_bytes(body, MAX_SIGNED_BODY)
+        .await
+        .map_err(|_| {
+            ApiError::BadRequest(Message::new("auth.body_too_large").with("max", MAX_SIGNED_BODY))
+                .into_response()
+        })?;
+
+    let principal = signing::verify(
+        &permission.config.signing_keys,
+        &permission.nonces,
+        permission.config.signature_window,
+        permission.clock.now(),
+        &parts,
+        &body,
+    )
+    .map_err(|err| {
+        let key = match err {
+            SignatureError::Invalid => "auth.invalid_signature",
+            SignatureError::Expired => "auth.expired_signature",
+            SignatureError::Replayed => "auth.replayed_request",
+        };
+        ApiError::Unauthorized(Message::new(key)).into_response()
+    })?;
+
+    Ok((principal, Request::from_parts(parts, Body::from(body))))
+}
+
+/// Extracts the token from an `Authorization: Bearer` header
+fn bearer_token(request: &Request) -> Option<&str> {
+    let value = request.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.trim().split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| token.trim())
+        .filter(|token| !token.is_empty())
+}
+
+fn challenge(error: ApiError, authenticate: String) -> Response {
+    let mut response = error.into_response();
+    if let Ok(value) = HeaderValue::from_str(&authenticate) {
+        response.headers_mut().insert(WWW_AUTHENTICATE, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_token() {
+        let token: ApiToken = "reporting:s3cr3t==users:read".parse().unwrap();
+        assert_eq!(token.token, "s3cr3t=");
+        assert_eq!(token.principal.name, "reporting");
+        assert_eq!(token.principal.scopes, vec![Scope::UsersRead]);
+
+        let token: ApiToken = "ops:abc=users:write+admin".parse().unwrap();
+        assert_eq!(
+            token.principal.scopes,
+            vec![Scope::UsersWrite, Scope::Admin]
+        );
+
+        assert!("ops:abc".parse::<ApiToken>().is_err());
+        assert!("abc=admin".parse::<ApiToken>().is_err());
+        assert!("ops:abc=".parse::<ApiToken>().is_err());
+        assert!("ops:abc=users:delete".parse::<ApiToken>().is_err());
+    }
+
+    #[test]
+    fn test_admin_implies_every_scope() {
+        let principal = |scopes| Principal {
+            name: "test".to_string(),
+            scopes,
+            impersonator: None,
+        };
+        let reader = principal(vec![Scope::UsersRead]);
+        assert!(reader.has_scope(Scope::UsersRead));
+        assert!(!reader.has_scope(Scope::UsersWrite));
+        assert!(!reader.has_scope(Scope::Admin));
+
+        let admin = principal(vec![Scope::Admin]);
+        assert!(admin.has_scope(Scope::UsersWrite));
+    }
+
+    #[test]
+    fn test_debug_hides_secrets() {
+        let tokens: Tokens = ["ops:s3cr3t=admin".parse::<ApiToken>().unwrap()]
+            .into_iter()
+            .collect();
+        assert_eq!(format!("{:?}", tokens), "[\"ops\"]");
+    }
+}
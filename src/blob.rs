@@ -0,0 +1,144 @@
+//! Storing generated files
+//!
+//! Files the API produces in the background, such as the reports of
+//! [`crate::reports`], are kept in a [`BlobStore`] under a key, with their
+//! content type and when they were written, until something reads them.
+//! [`crate::AppState::blobs`] holds the store; it is a [`MemoryBlobs`]
+//! unless replaced by one backed by object storage such as S3 or GCS.
+//! Blobs in memory are lost on restart.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// A stored file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blob {
+    /// Media type of the contents, e.g. `text/csv`
+    pub content_type: String,
+    /// When the blob was written
+    pub created_at: DateTime<Utc>,
+    /// The contents
+    pub data: Vec<u8>,
+}
+
+impl Blob {
+    /// Describes the blob stored under `key`
+    pub fn info(&self, key: &str) -> BlobInfo {
+        BlobInfo {
+            key: key.to_string(),
+            content_type: self.content_type.clone(),
+            size: self.data.len(),
+            created_at: self.created_at,
+        }
+    }
+}
+
+/// A stored file, without its contents
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct BlobInfo {
+    /// Key the blob is stored under
+    pub key: String,
+    /// Media type of the contents
+    pub content_type: String,
+    /// Size of the contents in bytes
+    pub size: usize,
+    /// When the blob was written
+    #[serde(with = "crate::timestamps")]
+    #[schema(schema_with = crate::timestamps::schema)]
+    pub created_at: DateTime<Utc>,
+}
+
+/// A store of generated files
+pub trait BlobStore: Send + Sync + 'static {
+    /// Identifies the store in logs
+    fn name(&self) -> &str;
+
+    /// Stores `blob` under `key`, replacing any previous blob
+    fn put<'a>(&'a self, key: &'a str, blob: Blob) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Reads the blob stored under `key`
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Option<Blob>>>;
+
+    /// Describes all blobs whose key starts with `prefix`, ordered by key
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, io::Result<Vec<BlobInfo>>>;
+}
+
+/// A blob store in memory, for tests and local runs
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBlobs {
+    blobs: Arc<Mutex<BTreeMap<String, Blob>>>,
+}
+
+impl MemoryBlobs {
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Blob>> {
+        self.blobs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl BlobStore for MemoryBlobs {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn put<'a>(&'a self, key: &'a str, blob: Blob) -> BoxFuture<'a, io::Result<()>> {
+        self.lock().insert(key.to_string(), blob);
+        Box::pin(std::future::ready(Ok(())))
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Option<Blob>>> {
+        let blob = self.lock().get(key).cloned();
+        Box::pin(std::future::ready(Ok(blob)))
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, io::Result<Vec<BlobInfo>>> {
+        let blobs = self
+            .lock()
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, blob)| blob.info(key))
+            .collect();
+        Box::pin(std::future::ready(Ok(blobs)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blob(data: &str) -> Blob {
+        Blob {
+            content_type: "text/plain".to_string(),
+            created_at: DateTime::default(),
+            data: data.as_bytes().to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_blobs() {
+        let blobs = MemoryBlobs::default();
+        blobs.put("reports/b", blob("second")).await.unwrap();
+        blobs.put("reports/a", blob("first")).await.unwrap();
+        blobs.put("other", blob("other")).await.unwrap();
+        blobs.put("reports/a", blob("replaced")).await.unwrap();
+
+        assert_eq!(
+            blobs.get("reports/a").await.unwrap(),
+            Some(blob("replaced"))
+        );
+        assert_eq!(blobs.get("reports/c").await.unwrap(), None);
+
+        let listed = blobs.list("reports/").await.unwrap();
+        let keys: Vec<&str> = listed.iter().map(|info| info.key.as_str()).collect();
+        assert_eq!(keys, ["reports/a", "reports/b"]);
+        assert_eq!(listed[0].size, "replaced".len());
+        assert_eq!(blobs.list("").await.unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,400 @@
+//! In-process response cache for GET endpoints
+//!
+//! Successful GET responses are kept for a per-route TTL, keyed by path,
+//! query, `Accept` header (timestamps are negotiated from it),
+//! `X-Field-Case` header and the caller's credentials, so one caller is never served another's cached
+//! response. Every user [`Event`] drops the cached user responses, so
+//! clients read their own writes; the TTL only bounds how long a response
+//! is reused while nothing changes.
+//!
+//! Responses carry an `X-Cache: hit` or `X-Cache: miss` header, and hits
+//! and misses are counted in [`crate::metrics::Metrics`]. A hit names the
+//! request it answers in `meta.request_id`, not the one it was computed
+//! for.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{
+        header::{ACCEPT, AUTHORIZATION, RANGE},
+        response::Parts,
+        HeaderName, HeaderValue, Method, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::auth::Principal;
+use crate::consistency::ConsistencyToken;
+use crate::error::ApiError;
+use crate::events::Event;
+use crate::field_case::X_FIELD_CASE;
+use crate::i18n::Message;
+use crate::metrics::Metrics;
+use crate::models::Storage;
+use crate::timing::TimedLock;
+use crate::AppState;
+
+/// Header reporting whether a response was served from the cache
+pub const X_CACHE: HeaderName = HeaderName::from_static("x-cache");
+
+/// Path prefix of the responses invalidated by user events
+const USERS_PATH: &str = "/api/v1/users";
+
+/// Expired entries are purged once the cache holds this many
+const PURGE_THRESHOLD: usize = 1024;
+
+/// Per-route TTLs; a zero TTL disables caching for the route
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Settings {
+    /// TTL of `GET /api/v1/users`
+    pub list_ttl: Duration,
+    /// TTL of `GET /api/v1/users/:id`
+    pub user_ttl: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    path: String,
+    query: Option<String>,
+    accept: Option<HeaderValue>,
+    field_case: Option<HeaderValue>,
+    /// Ranged requests are answered with `206`, which is never cached, so
+    /// they must not find a cached full response either
+    range: Option<HeaderValue>,
+    /// Hash of the authenticated principal's name, or of the
+    /// `Authorization` header when authentication is disabled
+    principal: Option<u64>,
+}
+
+impl CacheKey {
+    fn new(request: &Request) -> Self {
+        let headers = request.headers();
+        Self {
+            path: request.uri().path().to_string(),
+            query: request.uri().query().map(str::to_string),
+            accept: headers.get(ACCEPT).cloned(),
+            field_case: headers.get(X_FIELD_CASE).cloned(),
+            range: headers.get(RANGE).cloned(),
+            principal: match request.extensions().get::<Principal>() {
+                Some(principal) => Some(hash(&principal.name)),
+                None => headers.get(AUTHORIZATION).map(hash),
+            },
+        }
+    }
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Debug)]
+struct Entry {
+    parts: Parts,
+    body: Bytes,
+    expires_at: Instant,
+    /// Storage version the response was computed at
+    version: ConsistencyToken,
+    /// Offset of the envelope's `meta.request_id` value in `body`
+    request_id_at: Option<usize>,
+}
+
+impl Entry {
+    /// Returns the body with the envelope's `meta.request_id` set to
+    /// `request_id`
+    fn body_for(&self, request_id: Option<Uuid>) -> Bytes {
+        let (Some(at), Some(request_id)) = (self.request_id_at, request_id) else {
+            return self.body.clone();
+        };
+        // Both are 36 characters, so Content-Length stays valid
+        let mut body = self.body.to_vec();
+        body[at..at + 36].copy_from_slice(request_id.to_string().as_bytes());
+        body.into()
+    }
+}
+
+/// Returns the offset of `request_id` in the envelope's `meta` in `body`
+///
+/// `meta` is serialized after `data`, with `request_id` as its first
+/// field, and quotes inside strings are escaped, so the last match is the
+/// envelope's even if user fields hold the same ID.
+fn request_id_offset(body: &[u8], request_id: Uuid) -> Option<usize> {
+    let body = std::str::from_utf8(body).ok()?;
+    ["request_id", "requestId"]
+        .into_iter()
+        .filter_map(|field| {
+            let prefix = format!(r#""meta":{{"{}":""#, field);
+            body.rfind(&format!(r#"{}{}""#, prefix, request_id))
+                .map(|at| at + prefix.len())
+        })
+        .max()
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<CacheKey, Entry>,
+    /// Bumped by every invalidation, so responses computed before it are
+    /// not stored afterwards
+    generation: u64,
+}
+
+/// Cached responses shared by all routes
+#[derive(Debug, Default)]
+pub struct ResponseCache {
+    inner: Mutex<Inner>,
+}
+
+impl ResponseCache {
+    /// Returns the number of cached responses, including expired ones
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Returns `true` if no responses are cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops the cached responses affected by `event`
+    pub fn invalidate(&self, event: &Event) {
+        match event {
+            Event::UserCreated(_) | Event::UserUpdated(..) | Event::UserDeleted(_) => {
+                self.invalidate_prefix(USERS_PATH)
+            }
+        }
+    }
+
+    fn invalidate_prefix(&self, prefix: &str) {
+        let mut inner = self.lock();
+        inner.generation += 1;
+        inner.entries.retain(|key, _| !key.path.starts_with(prefix));
+    }
+
+    /// Returns the cached response for `key`, unless it predates `required`
+    fn get(&self, key: &CacheKey, required: Option<&ConsistencyToken>) -> Option<Response> {
+        let mut inner = self.lock();
+        let entry = inner.entries.get(key)?;
+        if entry.expires_at <= Instant::now() {
+            inner.entries.remove(key);
+            return None;
+        }
+        if required.is_some_and(|required| !entry.version.includes(required)) {
+            return None;
+        }
+        let mut parts = entry.parts.clone();
+        parts
+            .headers
+            .insert(X_CACHE, HeaderValue::from_static("hit"));
+        let body = entry.body_for(crate::error::request_id());
+        Some(Response::from_parts(parts, Body::from(body)))
+    }
+
+    fn generation(&self) -> u64 {
+        self.lock().generation
+    }
+
+    fn insert(&self, key: CacheKey, entry: Entry, generation: u64) {
+        let mut inner = self.lock();
+        if inner.generation != generation {
+            return;
+        }
+        if inner.entries.len() >= PURGE_THRESHOLD {
+            let now = Instant::now();
+            inner.entries.retain(|_, entry| entry.expires_at > now);
+        }
+        inner.entries.insert(key, entry);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Caching behavior of one route, used as middleware state
+#[derive(Debug, Clone)]
+pub struct Policy {
+    cache: Arc<ResponseCache>,
+    storage: Arc<TimedLock<Storage>>,
+    metrics: Arc<Metrics>,
+    ttl: Duration,
+}
+
+impl Policy {
+    /// Caches the route's responses in the application's cache for `ttl`
+    pub fn new(state: &AppState, ttl: Duration) -> Self {
+        Self {
+            cache: state.cache.clone(),
+            storage: state.storage.clone(),
+            metrics: state.metrics.clone(),
+            ttl,
+        }
+    }
+}
+
+/// Middleware serving GET and HEAD responses from the cache
+pub async fn respond(State(policy): State<Policy>, request: Request, next: Next) -> Response {
+    // HEAD shares entries with GET; axum strips the body afterwards
+    if policy.ttl.is_zero() || !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return next.run(request).await;
+    }
+
+    let key = CacheKey::new(&request);
+    let required = request.extensions().get::<ConsistencyToken>().copied();
+    if let Some(response) = policy.cache.get(&key, required.as_ref()) {
+        policy.metrics.record_cache_hit();
+        return response;
+    }
+    policy.metrics.record_cache_miss();
+
+    let generation = policy.cache.generation();
+    let version = ConsistencyToken::from(policy.storage.read().await.version());
+    let response = next.run(request).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(err) => {
+            return ApiError::failed(Message::new("server.response_failed"), err).into_response()
+        }
+    };
+    parts
+        .headers
+        .insert(X_CACHE, HeaderValue::from_static("miss"));
+    let entry = Entry {
+        parts: parts.clone(),
+        body: body.clone(),
+        expires_at: Instant::now() + policy.ttl,
+        version,
+        request_id_at: crate::error::request_id().and_then(|id| request_id_offset(&body, id)),
+    };
+    policy.cache.insert(key, entry, generation);
+
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn key(path: &str) -> CacheKey {
+        CacheKey::new(&Request::get(path).body(Body::empty()).unwrap())
+    }
+
+    fn entry() -> Entry {
+        let (parts, _) = Response::new(()).into_parts();
+        Entry {
+            parts,
+            body: Bytes::from_static(b"{}"),
+            expires_at: Instant::now() + Duration::from_secs(60),
+            version: ConsistencyToken::from((1, 5)),
+            request_id_at: None,
+        }
+    }
+
+    #[test]
+    fn test_key_separates_callers_and_representations() {
+        let request = |auth: &str, accept: &str| {
+            Request::get("/api/v1/users?status=active")
+                .header(AUTHORIZATION, auth)
+                .header(ACCEPT, accept)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let key = CacheKey::new(&request("Bearer a", "application/json"));
+        assert_eq!(key, CacheKey::new(&request("Bearer a", "application/json")));
+        assert_ne!(key, CacheKey::new(&request("Bearer b", "application/json")));
+        assert_ne!(
+            key,
+            CacheKey::new(&request("Bearer a", "application/json; timestamps=rfc3339"))
+        );
+        let mut camel = request("Bearer a", "application/json");
+        camel
+            .headers_mut()
+            .insert(X_FIELD_CASE, HeaderValue::from_static("camelCase"));
+        assert_ne!(key, CacheKey::new(&camel));
+
+        // Signed requests carry no Authorization header
+        let signed = |name: &str| {
+            let mut request = request("", "application/json");
+            request.headers_mut().remove(AUTHORIZATION);
+            request.extensions_mut().insert(Principal {
+                name: name.to_string(),
+                scopes: Vec::new(),
+                impersonator: None,
+            });
+            CacheKey::new(&request)
+        };
+        assert_eq!(signed("billing"), signed("billing"));
+        assert_ne!(signed("billing"), signed("partner"));
+    }
+
+    #[tokio::test]
+    async fn test_invalidation_discards_stale_inserts() {
+        let cache = ResponseCache::default();
+        let generation = cache.generation();
+        cache.insert(key("/api/v1/users"), entry(), generation);
+        cache.insert(key("/health"), entry(), generation);
+        assert_eq!(cache.len(), 2);
+
+        cache.invalidate(&Event::UserDeleted(Uuid::new_v4()));
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get(&key("/health"), None).is_some());
+
+        // A response computed before the invalidation is not stored
+        cache.insert(key("/api/v1/users"), entry(), generation);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_entries_older_than_token_are_bypassed() {
+        let cache = ResponseCache::default();
+        cache.insert(key("/api/v1/users"), entry(), cache.generation());
+
+        let seen = ConsistencyToken::from((1, 5));
+        let newer = ConsistencyToken::from((1, 6));
+        assert!(cache.get(&key("/api/v1/users"), Some(&seen)).is_some());
+        assert!(cache.get(&key("/api/v1/users"), Some(&newer)).is_none());
+    }
+
+    #[test]
+    fn test_hits_carry_current_request_id() {
+        let old = Uuid::new_v4();
+        let new = Uuid::new_v4();
+        let cached = |field: &str, id: Uuid| {
+            format!(
+                r#"{{"data":{{"custom":{{"ref":"{old}","meta":{{"{field}":"{old}"}}}}}},"meta":{{"{field}":"{id}"}}}}"#,
+            )
+        };
+        for field in ["request_id", "requestId"] {
+            let body = cached(field, old);
+            let entry = Entry {
+                request_id_at: request_id_offset(body.as_bytes(), old),
+                body: body.into(),
+                ..entry()
+            };
+            // User fields holding the same ID are left alone
+            assert_eq!(entry.body_for(Some(new)), cached(field, new));
+            assert_eq!(entry.body_for(None), entry.body);
+        }
+
+        // Bare resources have no envelope to update
+        let bare = format!(r#"{{"id":"{}"}}"#, old);
+        assert_eq!(request_id_offset(bare.as_bytes(), old), None);
+    }
+}
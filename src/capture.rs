@@ -0,0 +1,433 @@
+//! Recording requests and replaying them against another instance
+//!
+//! With `APP_CAPTURE_FILE` set and admin endpoints enabled,
+//! `PUT /api/v1/admin/capture` starts appending every API request to the
+//! file, one JSON object per line, together with the status it was
+//! answered with. Requests are sanitized before they are written: only the
+//! headers in [`CAPTURED_HEADERS`] are kept, so bearer tokens, signatures,
+//! cookies and forwarding headers never reach the file. Admin requests and
+//! requests with bodies larger than [`MAX_CAPTURED_BODY`] are not recorded.
+//!
+//! `rust-api replay <file> <base-url>` (with the `client` feature) sends
+//! the recorded requests, in order, to another instance and reports every
+//! request answered with a different status. Capturing from and replaying
+//! against instances started with `--mock` keeps generated user IDs equal
+//! on both sides, so recorded paths address the same users.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use axum::{
+    body::{Body, HttpBody},
+    extract::{Request, State},
+    http::HeaderName,
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+/// Headers kept in recorded requests; all others are dropped
+pub const CAPTURED_HEADERS: &[&str] = &[
+    "accept",
+    "accept-language",
+    "content-type",
+    "if-match",
+    "if-none-match",
+];
+
+/// Largest request body recorded, in bytes
+pub const MAX_CAPTURED_BODY: u64 = 1024 * 1024;
+
+/// A recorded request, one line of the capture file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapturedRequest {
+    /// When the request was received
+    pub at: DateTime<Utc>,
+    /// HTTP method
+    pub method: String,
+    /// Path and query string
+    pub path: String,
+    /// The sanitized request headers
+    pub headers: Vec<(String, String)>,
+    /// The request body, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Status the request was answered with
+    pub status: u16,
+}
+
+/// Requested capture state, body of `PUT /api/v1/admin/capture`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CaptureSettings {
+    /// Whether requests are recorded
+    pub enabled: bool,
+}
+
+/// Response of `GET` and `PUT /api/v1/admin/capture`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CaptureStatus {
+    /// Whether requests are recorded
+    pub enabled: bool,
+    /// File requests are appended to, if configured
+    #[schema(value_type = Option<String>)]
+    pub file: Option<PathBuf>,
+    /// Requests recorded since the server started
+    pub captured: u64,
+}
+
+/// The capture file and whether requests are currently recorded
+#[derive(Debug, Default)]
+pub struct Capture {
+    path: Option<PathBuf>,
+    enabled: AtomicBool,
+    captured: AtomicU64,
+    file: tokio::sync::Mutex<Option<crate::fs::File>>,
+}
+
+impl Capture {
+    /// Creates a disabled capture appending to `path` once enabled
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            ..Self::default()
+        }
+    }
+
+    /// Returns `true` if requests are recorded
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Starts or stops recording
+    ///
+    /// # Errors
+    ///
+    /// Returns [`std::io::ErrorKind::NotFound`] if no capture file is
+    /// configured, or the error opening the file.
+    pub async fn set_enabled(&self, enabled: bool) -> std::io::Result<()> {
+        let mut file = self.file.lock().await;
+        if !enabled {
+            self.enabled.store(false, Ordering::Relaxed);
+            if let Some(mut file) = file.take() {
+                file.flush().await?;
+            }
+            return Ok(());
+        }
+        let Some(path) = &self.path else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "no capture file configured",
+            ));
+        };
+        if file.is_none() {
+            *file = Some(
+                crate::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?,
+            );
+        }
+        self.enabled.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns the current state
+    pub fn status(&self) -> CaptureStatus {
+        CaptureStatus {
+            enabled: self.is_enabled(),
+            file: self.path.clone(),
+            captured: self.captured.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Appends `request` to the file, if recording
+    pub async fn record(&self, request: &CapturedRequest) {
+        let mut line = match serde_json::to_vec(request) {
+            Ok(line) => line,
+            Err(err) => {
+                tracing::warn!(%err, "failed to serialize captured request");
+                return;
+            }
+        };
+        line.push(b'\n');
+        let mut file = self.file.lock().await;
+        // Recording may have stopped while the request was handled
+        let Some(file) = file.as_mut() else {
+            return;
+        };
+        match file.write_all(&line).await {
+            Ok(()) => {
+                self.captured.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(err) => tracing::warn!(%err, "failed to write captured request"),
+        }
+    }
+}
+
+/// Returns the headers of `request` worth replaying
+fn sanitized_headers(request: &Request) -> Vec<(String, String)> {
+    CAPTURED_HEADERS
+        .iter()
+        .filter_map(|name| {
+            let value = request.headers().get(HeaderName::from_static(name))?;
+            Some((name.to_string(), value.to_str().ok()?.to_string()))
+        })
+        .collect()
+}
+
+/// Middleware recording API requests while capture is enabled
+pub async fn record(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let path = request.uri().path();
+    if !state.capture.is_enabled()
+        || !path.starts_with("/api/")
+        || path.starts_with("/api/v1/admin/")
+    {
+        return next.run(request).await;
+    }
+    // Bodies of unknown length could be arbitrarily large
+    match request.body().size_hint().exact() {
+        Some(length) if length <= MAX_CAPTURED_BODY => {}
+        _ => {
+            tracing::debug!(path, "request body too large to capture");
+            return next.run(request).await;
+        }
+    }
+
+    let at = state.clock.now();
+    let method = request.method().to_string();
+    let path = request
+        .uri()
+        .path_and_query()
+        .map_or_else(|| path.to_string(), |path| path.as_str().to_string());
+    let headers = sanitized_headers(&request);
+    let (parts, body) = request.into_parts();
+    // The length is known and within the limit, so this only fails if the
+    // client goes away
+    let Ok(body) = axum::body::to_bytes(body, MAX_CAPTURED_BODY as usize).await else {
+        return next.run(Request::from_parts(parts, Body::empty())).await;
+    };
+    let captured_body = if body.is_empty() {
+        None
+    } else {
+        // Binary bodies are not recorded
+        std::str::from_utf8(&body).ok().map(str::to_string)
+    };
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    state
+        .capture
+        .record(&CapturedRequest {
+            at,
+            method,
+            path,
+            headers,
+            body: captured_body,
+            status: response.status().as_u16(),
+        })
+        .await;
+    response
+}
+
+/// A replayed request answered differently than when it was recorded
+#[cfg(feature = "client")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Line of the request in the capture file, starting at 1
+    pub line: usize,
+    /// HTTP method
+    pub method: String,
+    /// Path and query string
+    pub path: String,
+    /// Status the request was answered with when recorded
+    pub expected: u16,
+    /// Status it was answered with now, or why it could not be sent
+    pub actual: Result<u16, String>,
+}
+
+#[cfg(feature = "client")]
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}: {} {} expected {}, ",
+            self.line, self.method, self.path, self.expected
+        )?;
+        match &self.actual {
+            Ok(status) => write!(f, "got {}", status),
+            Err(err) => write!(f, "failed: {}", err),
+        }
+    }
+}
+
+/// Outcome of replaying a capture file
+#[cfg(feature = "client")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Requests sent
+    pub replayed: usize,
+    /// Requests answered with a different status, in file order
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// Sends every request recorded in `path` to `base_url`, in order
+///
+/// Requests are sent with `token` as bearer token, if given, since
+/// recorded requests carry no credentials. A request that cannot be sent
+/// is retried with the default [`crate::resilience::Settings`]; once the
+/// target keeps failing, the remaining requests fail fast.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read, a line is not a recorded
+/// request, or `base_url` is not a URL.
+#[cfg(feature = "client")]
+pub async fn replay(
+    path: &std::path::Path,
+    base_url: &str,
+    token: Option<&str>,
+) -> std::io::Result<ReplayReport> {
+    use std::io::{Error, ErrorKind};
+
+    let contents = tokio::fs::read_to_string(path).await?;
+    let requests = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str::<CapturedRequest>(line)
+                .map(|request| (index + 1, request))
+                .map_err(|err| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("line {}: {}", index + 1, err),
+                    )
+                })
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let base_url = reqwest::Url::parse(base_url)
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, format!("{}: {}", base_url, err)))?;
+
+    let client = reqwest::Client::new();
+    let resilient =
+        crate::resilience::Resilient::new("replay", &crate::resilience::Settings::default());
+    let mut report = ReplayReport::default();
+    for (line, request) in requests {
+        let actual = send(&client, &resilient, &base_url, token, &request).await;
+        report.replayed += 1;
+        if actual.as_ref() != Ok(&request.status) {
+            report.mismatches.push(Mismatch {
+                line,
+                method: request.method,
+                path: request.path,
+                expected: request.status,
+                actual,
+            });
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(feature = "client")]
+async fn send(
+    client: &reqwest::Client,
+    resilient: &crate::resilience::Resilient,
+    base_url: &reqwest::Url,
+    token: Option<&str>,
+    request: &CapturedRequest,
+) -> Result<u16, String> {
+    let method: reqwest::Method = request
+        .method
+        .parse()
+        .map_err(|_| format!("invalid method '{}'", request.method))?;
+    let url = base_url
+        .join(&request.path)
+        .map_err(|err| err.to_string())?;
+    let mut builder = client.request(method, url);
+    for (name, value) in &request.headers {
+        builder = builder.header(name, value);
+    }
+    if let Some(token) = token {
+        builder = builder.bearer_auth(token);
+    }
+    if let Some(body) = &request.body {
+        builder = builder.body(body.clone());
+    }
+    let request = builder.build().map_err(|err| err.to_string())?;
+    let response = resilient
+        .call(|| {
+            // Recorded bodies are strings, so requests can always be cloned
+            let request = request.try_clone().expect("buffered request body");
+            client.execute(request)
+        })
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(response.status().as_u16())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(status: u16) -> CapturedRequest {
+        CapturedRequest {
+            at: DateTime::UNIX_EPOCH,
+            method: "GET".to_string(),
+            path: "/api/v1/users?limit=1".to_string(),
+            headers: vec![("accept".to_string(), "application/json".to_string())],
+            body: None,
+            status,
+        }
+    }
+
+    #[test]
+    fn test_sanitized_headers() {
+        let request = Request::builder()
+            .header("authorization", "Bearer secret")
+            .header("cookie", "session=secret")
+            .header("x-signature", "abc")
+            .header("content-type", "application/json")
+            .header("accept-language", "de")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            sanitized_headers(&request),
+            vec![
+                ("accept-language".to_string(), "de".to_string()),
+                ("content-type".to_string(), "application/json".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_records_only_while_enabled() {
+        let path = std::env::temp_dir().join(format!("capture-{}.jsonl", uuid::Uuid::new_v4()));
+        let capture = Capture::new(Some(path.clone()));
+
+        capture.record(&request(200)).await;
+        capture.set_enabled(true).await.unwrap();
+        capture.record(&request(201)).await;
+        capture.record(&request(404)).await;
+        capture.set_enabled(false).await.unwrap();
+        capture.record(&request(500)).await;
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let recorded: Vec<CapturedRequest> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(recorded, vec![request(201), request(404)]);
+        assert_eq!(capture.status().captured, 2);
+        assert!(!capture.status().enabled);
+
+        let unconfigured = Capture::default();
+        let err = unconfigured.set_enabled(true).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,192 @@
+//! Fault injection for testing clients
+//!
+//! With `APP_DEV_ENDPOINTS=true`, requests under `/api/` can be made slow
+//! or failing on purpose, so consumers can exercise their timeouts and
+//! retry logic against this API:
+//!
+//! - **latency**: the request is delayed before it is handled
+//! - **errors**: the request fails with `500 Internal Server Error`
+//! - **storage errors**: the request fails with `503 Service Unavailable`
+//!   and `Retry-After`, as when the store cannot be reached
+//!
+//! Faults are configured for all requests with the `APP_CHAOS_*` variables,
+//! or per request with the `X-Chaos` header, whose settings take
+//! precedence:
+//!
+//! ```text
+//! X-Chaos: latency_ms=250, latency_rate=0.5, error_rate=0.1, storage_error_rate=0.05
+//! ```
+
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::{header::RETRY_AFTER, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::i18n::Message;
+use crate::AppState;
+
+/// Header configuring faults for a single request
+pub const X_CHAOS: &str = "x-chaos";
+
+/// Faults and how often they are injected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// Delay added to delayed requests
+    pub latency: Duration,
+    /// Share of requests delayed by `latency`, between 0 and 1
+    pub latency_rate: f64,
+    /// Share of requests failing with `500`, between 0 and 1
+    pub error_rate: f64,
+    /// Share of requests failing with a storage error, between 0 and 1
+    pub storage_error_rate: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            latency_rate: 1.0,
+            error_rate: 0.0,
+            storage_error_rate: 0.0,
+        }
+    }
+}
+
+impl Settings {
+    /// Returns `true` if any fault is injected
+    pub fn is_enabled(&self) -> bool {
+        (!self.latency.is_zero() && self.latency_rate > 0.0)
+            || self.error_rate > 0.0
+            || self.storage_error_rate > 0.0
+    }
+
+    /// Applies the `key=value` pairs of an `X-Chaos` header
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the first invalid pair.
+    pub fn with_header(mut self, header: &str) -> Result<Self, String> {
+        for pair in header
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got '{}'", pair))?;
+            let (key, value) = (key.trim(), value.trim());
+            let rate = || match value.parse::<f64>() {
+                Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+                _ => Err(format!("{} must be between 0 and 1", key)),
+            };
+            match key {
+                "latency_ms" => {
+                    let millis = value
+                        .parse()
+                        .map_err(|_| format!("{} must be a number of milliseconds", key))?;
+                    self.latency = Duration::from_millis(millis);
+                }
+                "latency_rate" => self.latency_rate = rate()?,
+                "error_rate" => self.error_rate = rate()?,
+                "storage_error_rate" => self.storage_error_rate = rate()?,
+                other => return Err(format!("unknown setting '{}'", other)),
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// Returns `true` with probability `rate`
+fn roll(rate: f64) -> bool {
+    if rate <= 0.0 {
+        return false;
+    }
+    let (random, _) = Uuid::new_v4().as_u64_pair();
+    (random as f64 / u64::MAX as f64) < rate
+}
+
+/// Middleware injecting the configured faults into API requests
+pub async fn inject(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if !state.config.dev_endpoints || !request.uri().path().starts_with("/api/") {
+        return next.run(request).await;
+    }
+    let settings = match request.headers().get(X_CHAOS) {
+        Some(header) => match header
+            .to_str()
+            .map_err(|err| err.to_string())
+            .and_then(|header| state.config.chaos.with_header(header))
+        {
+            Ok(settings) => settings,
+            Err(reason) => {
+                return ApiError::BadRequest(
+                    Message::new("chaos.invalid_header").with("reason", reason),
+                )
+                .into_response()
+            }
+        },
+        None => state.config.chaos,
+    };
+    if !settings.is_enabled() {
+        return next.run(request).await;
+    }
+
+    if !settings.latency.is_zero() && roll(settings.latency_rate) {
+        tokio::time::sleep(settings.latency).await;
+    }
+    if roll(settings.storage_error_rate) {
+        tracing::debug!(path = request.uri().path(), "injecting a storage error");
+        let error = ApiError::ServiceUnavailable(Message::new("chaos.storage_unavailable"));
+        return ([(RETRY_AFTER, HeaderValue::from(1))], error).into_response();
+    }
+    if roll(settings.error_rate) {
+        tracing::debug!(path = request.uri().path(), "injecting an error");
+        return ApiError::Internal(Message::new("chaos.error")).into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_overrides_settings() {
+        let configured = Settings {
+            error_rate: 0.5,
+            ..Settings::default()
+        };
+        assert!(configured.is_enabled());
+        assert_eq!(configured.with_header(""), Ok(configured));
+
+        let settings = configured
+            .with_header("latency_ms=250, latency_rate=0.25, storage_error_rate=1")
+            .unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                latency: Duration::from_millis(250),
+                latency_rate: 0.25,
+                error_rate: 0.5,
+                storage_error_rate: 1.0,
+            }
+        );
+        assert!(!configured.with_header("error_rate=0").unwrap().is_enabled());
+
+        assert!(configured.with_header("error_rate=2").is_err());
+        assert!(configured.with_header("latency_ms=soon").is_err());
+        assert!(configured.with_header("latency").is_err());
+        assert!(configured.with_header("timeout_rate=1").is_err());
+    }
+
+    #[test]
+    fn test_roll_extremes() {
+        assert!((0..100).all(|_| roll(1.0)));
+        assert!((0..100).all(|_| !roll(0.0)));
+    }
+}
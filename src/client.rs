@@ -0,0 +1,352 @@
+//! Typed HTTP client for the API
+//!
+//! Enabled with the `client` feature. The client reuses the crate's own
+//! request and response models, so consumers cannot drift from the server's
+//! wire format.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), rust_api::client::ClientError> {
+//! use rust_api::client::Client;
+//! use rust_api::models::CreateUserRequest;
+//!
+//! let client = Client::new("http://localhost:3000")?.with_bearer_token("secret");
+//! let user = client
+//!     .create_user(&CreateUserRequest {
+//!         name: "Jane Doe".to_string(),
+//!         email: "jane@example.com".to_string(),
+//!         ..Default::default()
+//!     })
+//!     .await?;
+//! client.suspend_user(user.id).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::{header, Method, RequestBuilder, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use uuid::Uuid;
+
+use crate::error::{ErrorCode, ErrorResponse};
+use crate::field_case::{FieldCase, X_FIELD_CASE};
+use crate::models::{CreateUserRequest, UpdateUserRequest, User, UserStatus};
+use crate::responses::ApiResponse;
+
+/// Errors returned by [`Client`] methods
+#[derive(Debug)]
+pub enum ClientError {
+    /// The base URL could not be parsed
+    InvalidUrl(String),
+    /// The request could not be sent or the response could not be read
+    Transport(reqwest::Error),
+    /// The API answered with an error response
+    Api {
+        /// HTTP status of the response
+        status: StatusCode,
+        /// Error message from the response body
+        message: String,
+        /// Stable kind of the error, when the body is a standard error
+        /// response
+        code: Option<ErrorCode>,
+        /// Whether the API reported that repeating the request may succeed
+        retryable: bool,
+    },
+}
+
+impl ClientError {
+    /// Returns the HTTP status for API errors
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            ClientError::Api { status, .. } => Some(*status),
+            ClientError::Transport(err) => err.status(),
+            ClientError::InvalidUrl(_) => None,
+        }
+    }
+
+    /// Returns whether the API reported that the resource does not exist,
+    /// either never or no longer
+    pub fn is_not_found(&self) -> bool {
+        matches!(
+            self.status(),
+            Some(StatusCode::NOT_FOUND | StatusCode::GONE)
+        )
+    }
+
+    /// Returns the stable kind of API errors
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            ClientError::Api { code, .. } => *code,
+            _ => None,
+        }
+    }
+
+    /// Returns whether repeating the request may succeed
+    ///
+    /// True for API errors the API marked retryable and for requests that
+    /// timed out or could not connect.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ClientError::Api { retryable, .. } => *retryable,
+            ClientError::Transport(err) => err.is_timeout() || err.is_connect(),
+            ClientError::InvalidUrl(_) => false,
+        }
+    }
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::InvalidUrl(reason) => write!(f, "invalid base URL: {}", reason),
+            ClientError::Transport(err) => write!(f, "request failed: {}", err),
+            ClientError::Api {
+                status, message, ..
+            } => write!(f, "{}: {}", status, message),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Transport(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(err: reqwest::Error) -> Self {
+        ClientError::Transport(err)
+    }
+}
+
+/// Filters for [`Client::list_users`], mirroring the endpoint's query
+/// parameters
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserFilter {
+    /// Only return the user with this phone number
+    pub phone: Option<String>,
+    /// Only return users with this status
+    pub status: Option<UserStatus>,
+    /// Only return users with no activity since this time
+    pub inactive_since: Option<DateTime<Utc>>,
+    /// Only return users matching this filter expression, such as
+    /// `name eq 'John' and created_at gt 2024-01-01`
+    pub filter: Option<String>,
+    /// Only return this page of the matching users, starting at 1
+    pub page: Option<usize>,
+    /// Number of users per page
+    pub per_page: Option<usize>,
+}
+
+impl UserFilter {
+    fn query(&self) -> Vec<(&'static str, String)> {
+        let mut query = Vec::new();
+        if let Some(phone) = &self.phone {
+            query.push(("phone", phone.clone()));
+        }
+        if let Some(status) = self.status {
+            query.push(("status", status.to_string()));
+        }
+        if let Some(since) = self.inactive_since {
+            query.push((
+                "inactive_since",
+                since.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            ));
+        }
+        if let Some(filter) = &self.filter {
+            query.push(("filter", filter.clone()));
+        }
+        if let Some(page) = self.page {
+            query.push(("page", page.to_string()));
+        }
+        if let Some(per_page) = self.per_page {
+            query.push(("per_page", per_page.to_string()));
+        }
+        query
+    }
+}
+
+/// Client for the users API
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: Url,
+    token: Option<String>,
+}
+
+impl Client {
+    /// Creates a client for the API at `base_url`, e.g. `http://localhost:3000`
+    pub fn new(base_url: &str) -> Result<Self, ClientError> {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    /// Creates a client that sends requests through a preconfigured
+    /// `reqwest` client (timeouts, proxies, TLS settings)
+    pub fn with_http_client(base_url: &str, http: reqwest::Client) -> Result<Self, ClientError> {
+        let base_url =
+            Url::parse(base_url).map_err(|err| ClientError::InvalidUrl(err.to_string()))?;
+        Ok(Self {
+            http,
+            base_url,
+            token: None,
+        })
+    }
+
+    /// Sends `token` as a bearer token with every request
+    pub fn with_bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Lists users matching `filter`
+    pub async fn list_users(&self, filter: &UserFilter) -> Result<Vec<User>, ClientError> {
+        let request = self
+            .request(Method::GET, "/api/v1/users")?
+            .query(&filter.query());
+        self.send::<ApiResponse<Vec<User>>>(request)
+            .await
+            .map(|response| response.data)
+    }
+
+    /// Retrieves a user by ID
+    pub async fn get_user(&self, id: Uuid) -> Result<User, ClientError> {
+        let request = self.request(Method::GET, &format!("/api/v1/users/{}", id))?;
+        self.send::<ApiResponse<User>>(request)
+            .await
+            .map(|response| response.data)
+    }
+
+    /// Creates a user
+    pub async fn create_user(&self, user: &CreateUserRequest) -> Result<User, ClientError> {
+        let request = self.request(Method::POST, "/api/v1/users")?.json(user);
+        self.send::<ApiResponse<User>>(request)
+            .await
+            .map(|response| response.data)
+    }
+
+    /// Updates a user; fields left as `None` are unchanged
+    pub async fn update_user(
+        &self,
+        id: Uuid,
+        changes: &UpdateUserRequest,
+    ) -> Result<User, ClientError> {
+        let request = self
+            .request(Method::PUT, &format!("/api/v1/users/{}", id))?
+            .json(changes);
+        self.send::<ApiResponse<User>>(request)
+            .await
+            .map(|response| response.data)
+    }
+
+    /// Deletes a user
+    pub async fn delete_user(&self, id: Uuid) -> Result<(), ClientError> {
+        let request = self.request(Method::DELETE, &format!("/api/v1/users/{}", id))?;
+        self.execute(request).await.map(drop)
+    }
+
+    /// Suspends an active user
+    pub async fn suspend_user(&self, id: Uuid) -> Result<User, ClientError> {
+        self.change_status(id, "suspend").await
+    }
+
+    /// Activates a pending or suspended user
+    pub async fn activate_user(&self, id: Uuid) -> Result<User, ClientError> {
+        self.change_status(id, "activate").await
+    }
+
+    /// Permanently deactivates a user
+    pub async fn deactivate_user(&self, id: Uuid) -> Result<User, ClientError> {
+        self.change_status(id, "deactivate").await
+    }
+
+    async fn change_status(&self, id: Uuid, action: &str) -> Result<User, ClientError> {
+        let request = self.request(Method::POST, &format!("/api/v1/users/{}/{}", id, action))?;
+        self.send::<ApiResponse<User>>(request)
+            .await
+            .map(|response| response.data)
+    }
+
+    fn request(&self, method: Method, path: &str) -> Result<RequestBuilder, ClientError> {
+        let url = self
+            .base_url
+            .join(path)
+            .map_err(|err| ClientError::InvalidUrl(err.to_string()))?;
+
+        let request = self.http.request(method, url);
+        Ok(match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        })
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, ClientError> {
+        Ok(self.execute(request).await?.json().await?)
+    }
+
+    /// Sends a request and turns error statuses into [`ClientError::Api`]
+    ///
+    /// Asks for the response envelope and snake_case fields, whatever the
+    /// server's defaults.
+    async fn execute(&self, request: RequestBuilder) -> Result<reqwest::Response, ClientError> {
+        let response = request
+            .header(header::ACCEPT, "application/json; envelope=true")
+            .header(X_FIELD_CASE, FieldCase::Snake.as_str())
+            .send()
+            .await?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let text = response.text().await.unwrap_or_default();
+        let error = match serde_json::from_str::<ErrorResponse>(&text) {
+            Ok(body) => ClientError::Api {
+                status,
+                message: body.error.message,
+                code: Some(body.error.code),
+                retryable: body.error.retryable,
+            },
+            Err(_) => ClientError::Api {
+                status,
+                message: text,
+                code: None,
+                retryable: false,
+            },
+        };
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_query() {
+        let filter = UserFilter {
+            status: Some(UserStatus::Suspended),
+            inactive_since: DateTime::from_timestamp(1709296245, 0),
+            filter: Some("name eq 'John'".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.query(),
+            vec![
+                ("status", "suspended".to_string()),
+                ("inactive_since", "2024-03-01T12:30:45Z".to_string()),
+                ("filter", "name eq 'John'".to_string()),
+            ]
+        );
+        assert!(UserFilter::default().query().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_base_url() {
+        assert!(matches!(
+            Client::new("not a url"),
+            Err(ClientError::InvalidUrl(_))
+        ));
+    }
+}
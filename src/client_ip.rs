@@ -0,0 +1,221 @@
+//! Client address resolution behind reverse proxies
+//!
+//! The caller's address is the connection's peer, unless the peer is one
+//! of the proxies in `APP_TRUSTED_PROXIES`. Then the forwarding headers
+//! are consulted: `Forwarded` (RFC 7239) if present, `X-Forwarded-For`
+//! otherwise. Their hops are read from the right, skipping trusted
+//! proxies, and the first other address is the client; anything to its
+//! left was supplied by the client and cannot be trusted. Headers from
+//! untrusted peers are ignored, since any client can send them.
+//!
+//! Handlers and middleware obtain the result through the
+//! [`ClientIp`](crate::extract::ClientIp) extractor.
+
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use axum::http::{header::FORWARDED, HeaderMap};
+use ipnet::IpNet;
+
+/// Header listing the addresses a request was forwarded for
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// An address range, written as a CIDR range or a single address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr(IpNet);
+
+impl Cidr {
+    /// Returns `true` if `ip` is in the range
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.contains(ip)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        s.parse::<IpNet>()
+            .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+            .map(Cidr)
+            .map_err(|_| format!("'{}' is not an IP address or CIDR range", s))
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Proxies whose forwarding headers are trusted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies(pub Vec<Cidr>);
+
+impl FromIterator<Cidr> for TrustedProxies {
+    fn from_iter<I: IntoIterator<Item = Cidr>>(ranges: I) -> Self {
+        TrustedProxies(ranges.into_iter().collect())
+    }
+}
+
+impl TrustedProxies {
+    /// Returns `true` if `ip` is a trusted proxy
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.iter().any(|range| range.contains(ip))
+    }
+
+    /// Resolves the client address of a request received from `peer`
+    pub fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.contains(&peer) {
+            return peer;
+        }
+
+        let hops = if headers.contains_key(FORWARDED) {
+            forwarded_for(headers)
+        } else {
+            x_forwarded_for(headers)
+        };
+        let mut client = peer;
+        for hop in hops.into_iter().rev() {
+            // An obfuscated or malformed hop ends the trusted chain
+            let Some(ip) = hop else {
+                break;
+            };
+            client = ip;
+            if !self.contains(&ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// Returns the `for` addresses of all `Forwarded` elements, in order
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    header_items(headers, FORWARDED.as_str())
+        .map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| parse_node(value))
+            })?
+        })
+        .collect()
+}
+
+/// Returns the addresses of all `X-Forwarded-For` entries, in order
+fn x_forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    header_items(headers, X_FORWARDED_FOR)
+        .map(parse_node)
+        .collect()
+}
+
+/// Splits every value of a header at commas
+fn header_items<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+}
+
+/// Parses a node such as `192.0.2.1`, `192.0.2.1:8080` or
+/// `"[2001:db8::1]:8080"`; obfuscated identifiers yield `None`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            let host = node.strip_prefix('[')?.split(']').next()?;
+            host.parse().ok()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn proxies(ranges: &[&str]) -> TrustedProxies {
+        ranges.iter().map(|range| range.parse().unwrap()).collect()
+    }
+
+    fn with_header(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn test_parse_cidr() {
+        assert!("10.0.0.0/8"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains(&ip("10.1.2.3")));
+        assert!("192.0.2.1"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains(&ip("192.0.2.1")));
+        assert!("2001:db8::/32"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains(&ip("2001:db8::1")));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("example.com".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_parse_node() {
+        assert_eq!(parse_node(" 192.0.2.1 "), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("192.0.2.1:8080"), Some(ip("192.0.2.1")));
+        assert_eq!(
+            parse_node("\"[2001:db8::1]:8080\""),
+            Some(ip("2001:db8::1"))
+        );
+        assert_eq!(parse_node("\"[2001:db8::1]\""), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("2001:db8::1"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+    }
+
+    #[test]
+    fn test_x_forwarded_for_only_from_trusted_proxies() {
+        let proxies = proxies(&["10.0.0.0/8"]);
+        let headers = with_header(X_FORWARDED_FOR, "198.51.100.7, 203.0.113.9, 10.0.0.2");
+
+        // The rightmost untrusted hop is the client; earlier entries are
+        // whatever the client claimed
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers), ip("203.0.113.9"));
+        // Spoofed headers from untrusted peers are ignored
+        assert_eq!(proxies.resolve(ip("192.0.2.1"), &headers), ip("192.0.2.1"));
+        assert_eq!(
+            proxies.resolve(ip("10.0.0.1"), &HeaderMap::new()),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn test_forwarded_takes_precedence() {
+        let proxies = proxies(&["10.0.0.0/8"]);
+        let mut headers = with_header(
+            "forwarded",
+            "for=198.51.100.7;proto=https, for=\"[2001:db8::17]:4711\";by=10.0.0.2, For=10.0.0.2",
+        );
+        headers.insert(X_FORWARDED_FOR, HeaderValue::from_static("192.0.2.99"));
+        assert_eq!(
+            proxies.resolve(ip("10.0.0.1"), &headers),
+            ip("2001:db8::17")
+        );
+
+        // Obfuscated hops stop the walk at the last trusted address
+        let headers = with_header("forwarded", "for=198.51.100.7, for=_hidden, for=10.0.0.2");
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers), ip("10.0.0.2"));
+    }
+}
@@ -0,0 +1,198 @@
+//! Computed user fields
+//!
+//! Requests with `?include_computed=true` get users with fields derived
+//! from what is stored, which are never stored themselves:
+//!
+//! - `display_name`: the name, followed by the status for accounts that
+//!   are not active, e.g. `Jane Doe (suspended)`
+//! - `initials`: the first letters of the first and last word of the name
+//! - `account_age_days`: whole days since the account was created
+//! - `gravatar_url`: the [Gravatar](https://gravatar.com) image of the
+//!   email address
+//!
+//! The parameter is applied by [`negotiate`], which stores the time of the
+//! request in a task-local read when users are serialized, the same way
+//! timestamp options are.
+
+use axum::{
+    extract::{Query, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::error::ApiError;
+use crate::i18n::Message;
+use crate::models::{User, UserStatus};
+use crate::AppState;
+
+/// Fields derived from a stored user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ComputedFields {
+    /// Name to show for the user, with the status of inactive accounts
+    pub display_name: String,
+    /// First letters of the first and last word of the name, uppercased
+    pub initials: String,
+    /// Whole days since the account was created
+    pub account_age_days: i64,
+    /// URL of the email address's Gravatar image
+    pub gravatar_url: String,
+}
+
+impl ComputedFields {
+    /// Derives the fields of `user` at time `now`
+    pub fn of(user: &User, now: DateTime<Utc>) -> Self {
+        let display_name = match user.status {
+            UserStatus::Active => user.name.clone(),
+            status => format!("{} ({})", user.name, status),
+        };
+        let mut words = user
+            .name
+            .split_whitespace()
+            .filter_map(|word| word.chars().find(|c| c.is_alphanumeric()));
+        let first = words.next();
+        let initials = first
+            .into_iter()
+            .chain(words.next_back())
+            .flat_map(char::to_uppercase)
+            .collect();
+        let hash = Sha256::digest(user.email.trim().to_lowercase().as_bytes());
+        Self {
+            display_name,
+            initials,
+            account_age_days: (now - user.created_at).num_days().max(0),
+            gravatar_url: format!("https://gravatar.com/avatar/{}", hex::encode(hash)),
+        }
+    }
+}
+
+tokio::task_local! {
+    static NOW: DateTime<Utc>;
+}
+
+/// Returns the time computed fields are derived at, if the current request
+/// asked for them
+pub fn requested_at() -> Option<DateTime<Utc>> {
+    NOW.try_with(|now| *now).ok()
+}
+
+/// Runs `f` synchronously with computed fields derived at `now`, or
+/// without them if `now` is `None`
+///
+/// For serializing after the request's scope was left, as streamed bodies
+/// do.
+pub(crate) fn sync_scope<R>(now: Option<DateTime<Utc>>, f: impl FnOnce() -> R) -> R {
+    match now {
+        Some(now) => NOW.sync_scope(now, f),
+        None => f(),
+    }
+}
+
+/// A user serialized with its computed fields
+#[derive(Serialize)]
+struct Projection<'a> {
+    #[serde(flatten)]
+    user: Stored<'a>,
+    #[serde(flatten)]
+    computed: ComputedFields,
+}
+
+/// The stored fields of a user
+struct Stored<'a>(&'a User);
+
+impl Serialize for Stored<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        User::serialize(self.0, serializer)
+    }
+}
+
+/// Serializes `user` with the fields derived at `now`
+pub(crate) fn serialize<S: Serializer>(
+    user: &User,
+    now: DateTime<Utc>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    Projection {
+        user: Stored(user),
+        computed: ComputedFields::of(user, now),
+    }
+    .serialize(serializer)
+}
+
+#[derive(Deserialize)]
+struct ComputedQuery {
+    include_computed: Option<String>,
+}
+
+/// Returns whether `request` asks for computed fields
+fn requested(request: &Request) -> Result<bool, ApiError> {
+    let query = Query::<ComputedQuery>::try_from_uri(request.uri())
+        .map(|Query(query)| query.include_computed)
+        .unwrap_or_default();
+    match query.as_deref() {
+        Some("true") => Ok(true),
+        Some("false") | None => Ok(false),
+        Some(other) => Err(ApiError::BadRequest(
+            Message::new("computed.invalid").with("value", other),
+        )),
+    }
+}
+
+/// Middleware adding computed fields to the users of responses that asked
+/// for them
+pub async fn negotiate(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    match requested(&request) {
+        Ok(true) => NOW.scope(state.clock.now(), next.run(request)).await,
+        Ok(false) => next.run(request).await,
+        Err(err) => err.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn test_computed_fields() {
+        let created_at = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let jane = User::new("jane van der Berg", " Jane@Example.com", created_at);
+        let now = jane.created_at + Duration::days(3) + Duration::hours(23);
+        let computed = ComputedFields::of(&jane, now);
+        assert_eq!(computed.display_name, "jane van der Berg");
+        assert_eq!(computed.initials, "JB");
+        assert_eq!(computed.account_age_days, 3);
+        // SHA-256 of "jane@example.com"
+        assert_eq!(
+            computed.gravatar_url,
+            "https://gravatar.com/avatar/8c87b489ce35cf2e2f39f80e282cb2e804932a56a213983eeeb428407d43b52d"
+        );
+
+        let computed = ComputedFields::of(
+            &User::new("Zoë", "zoe@example.com", created_at).with_status(UserStatus::Suspended),
+            now,
+        );
+        assert_eq!(computed.display_name, "Zoë (suspended)");
+        assert_eq!(computed.initials, "Z");
+    }
+
+    #[tokio::test]
+    async fn test_users_serialize_computed_fields_when_requested() {
+        let user = User::new("Jane Doe", "jane@example.com", Utc::now());
+        let stored = serde_json::to_value(&user).unwrap();
+        assert!(stored.get("initials").is_none());
+
+        let projected = NOW
+            .scope(user.created_at, async {
+                serde_json::to_value(&user).unwrap()
+            })
+            .await;
+        assert_eq!(projected["initials"], "JD");
+        assert_eq!(projected["account_age_days"], 0);
+        assert_eq!(projected["name"], stored["name"]);
+        assert_eq!(projected["created_at"], stored["created_at"]);
+    }
+}
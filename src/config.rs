@@ -0,0 +1,1377 @@
+//! Runtime configuration
+//!
+//! Configuration is read from `APP_`-prefixed environment variables.
+//! Every setting has a default, so the server runs without any
+//! configuration at all.
+
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::auth;
+use crate::cache;
+use crate::chaos;
+use crate::client_ip;
+use crate::cors;
+use crate::field_case::FieldCase;
+use crate::i18n::Language;
+use crate::ip_filter;
+use crate::metrics;
+use crate::models;
+use crate::paths::TrailingSlash;
+use crate::profile::Profile;
+use crate::rate_limit::RateLimit;
+use crate::replication;
+use crate::reports;
+use crate::reserved;
+use crate::resilience;
+use crate::shadow;
+use crate::shard;
+use crate::slo;
+use crate::timestamps::TimestampFormat;
+use crate::tls;
+use crate::validation::name::{CharClass, NameRules};
+use crate::validation::{custom, email};
+
+/// Error raised when an environment variable holds an invalid value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError(pub String);
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid configuration: {}", self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Application configuration
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Environment the server runs in, picking the defaults of other
+    /// settings
+    pub profile: Profile,
+    /// Origins allowed to make cross-origin requests
+    pub cors: cors::Settings,
+    /// Rules applied to user names
+    pub name_rules: NameRules,
+    /// Region assumed for phone numbers given without a country code
+    pub phone_default_region: Option<phonenumber::country::Id>,
+    /// Timestamp representation used when the client does not ask for one
+    pub timestamp_format: TimestampFormat,
+    /// Language of responses when neither the request nor the caller's
+    /// profile names a supported one
+    pub default_language: Language,
+    /// Custom fields users carry in their `custom` object
+    pub custom_fields: custom::Schema,
+    /// Whether successful responses are wrapped in the `data`/`meta`/`links`
+    /// envelope when the client does not ask otherwise
+    pub response_envelope: bool,
+    /// Case of response fields when the client does not ask for one
+    pub field_case: FieldCase,
+    /// Whether paths with a trailing slash are rewritten or redirected
+    pub trailing_slash: TrailingSlash,
+    /// Seed for the users generated in mock mode
+    pub mock_seed: u64,
+    /// Number of users generated in mock mode
+    pub mock_users: usize,
+    /// Whether development-only endpoints under `/api/v1/dev` are served
+    pub dev_endpoints: bool,
+    /// Whether operator endpoints under `/api/v1/admin` are served
+    pub admin_endpoints: bool,
+    /// Whether internal error responses include the original message and
+    /// its causes instead of a generic message
+    pub error_details: bool,
+    /// Requests each authenticated caller may make per window, unless an
+    /// admin set another limit for it; unlimited when `None`
+    pub rate_limit: Option<RateLimit>,
+    /// Maximum number of requests handled at once; unlimited when `None`
+    pub max_concurrent_requests: Option<usize>,
+    /// Delay suggested to clients rejected because the server is saturated
+    pub retry_after: Duration,
+    /// Circuit breaker and retry thresholds for outbound calls
+    pub resilience: resilience::Settings,
+    /// Response cache TTLs per route
+    pub cache: cache::Settings,
+    /// API tokens accepted as bearer credentials; authentication is off
+    /// when empty
+    pub api_tokens: auth::Tokens,
+    /// Shared secrets of callers signing their requests
+    pub signing_keys: auth::signing::Keys,
+    /// How far a signed request's timestamp may be from the server's clock
+    pub signature_window: Duration,
+    /// Certificate subjects accepted from TLS client certificates
+    pub client_cert_principals: auth::certificate::Subjects,
+    /// TLS termination; plain HTTP is served when disabled
+    pub tls: tls::Settings,
+    /// Proxies whose forwarding headers identify the client
+    pub trusted_proxies: client_ip::TrustedProxies,
+    /// Client address allow and deny lists
+    pub ip_filter: ip_filter::Settings,
+    /// Number of audit entries retained; `0` disables the audit log
+    pub audit_capacity: usize,
+    /// MaxMind City database used to locate client addresses
+    pub geoip_database: Option<PathBuf>,
+    /// Lifetime of impersonation tokens
+    pub impersonation_ttl: Duration,
+    /// Limit on stored users and what happens when it is reached
+    pub storage_capacity: models::Capacity,
+    /// Strategies used to find duplicate users
+    pub duplicate_strategies: Vec<models::DuplicateStrategy>,
+    /// How emails are folded before rejecting duplicates on create,
+    /// update and restore
+    pub email_canonicalization: email::Canonicalization,
+    /// Names and emails no user may take, and terms no name may contain
+    pub reserved: reserved::ReservedLists,
+    /// How long a deletion can be undone; deletions issue no undo tokens
+    /// when unset
+    pub undo_window: Option<Duration>,
+    /// StatsD and Pushgateway destinations metrics are pushed to
+    pub metrics_export: metrics::sinks::Settings,
+    /// Latency and error rate objectives per route
+    pub slo: slo::Settings,
+    /// Requests taking at least this long are logged with a timing
+    /// breakdown; none are when `None`
+    pub slow_request_threshold: Option<Duration>,
+    /// Longest a request may take before it is abandoned with a 504;
+    /// clients can only ask for less. Unlimited when `None`
+    pub request_timeout: Option<Duration>,
+    /// Faults injected into API requests; requires `dev_endpoints`
+    pub chaos: chaos::Settings,
+    /// File requests are recorded to while capture is enabled; requires
+    /// `admin_endpoints`
+    pub capture_file: Option<PathBuf>,
+    /// Storage backend writes are shadowed to, and whether reads are
+    /// compared
+    pub shadow: shadow::Settings,
+    /// Directory snapshots of the store are written to and restored from;
+    /// requires `admin_endpoints`
+    pub snapshot_dir: Option<PathBuf>,
+    /// Instances the users are partitioned across
+    pub shard: shard::Settings,
+    /// Role in replication and the leader followers and replicas copy
+    pub replication: replication::Settings,
+    /// Number of background operations run at once
+    pub job_workers: usize,
+    /// When reports on the users are written and the weeks they cover
+    pub reports: reports::Settings,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            profile: Profile::default(),
+            cors: cors::Settings::default(),
+            name_rules: NameRules::default(),
+            phone_default_region: None,
+            timestamp_format: TimestampFormat::default(),
+            default_language: Language::default(),
+            custom_fields: custom::Schema::default(),
+            response_envelope: true,
+            field_case: FieldCase::default(),
+            trailing_slash: TrailingSlash::default(),
+            mock_seed: 1,
+            mock_users: 50,
+            dev_endpoints: false,
+            admin_endpoints: false,
+            error_details: false,
+            rate_limit: None,
+            max_concurrent_requests: None,
+            retry_after: Duration::from_secs(1),
+            resilience: resilience::Settings::default(),
+            cache: cache::Settings::default(),
+            api_tokens: auth::Tokens::default(),
+            signing_keys: auth::signing::Keys::default(),
+            signature_window: Duration::from_secs(300),
+            client_cert_principals: auth::certificate::Subjects::default(),
+            tls: tls::Settings::default(),
+            trusted_proxies: client_ip::TrustedProxies::default(),
+            ip_filter: ip_filter::Settings::default(),
+            audit_capacity: 10_000,
+            geoip_database: None,
+            impersonation_ttl: Duration::from_secs(15 * 60),
+            storage_capacity: models::Capacity::default(),
+            duplicate_strategies: vec![
+                models::DuplicateStrategy::Email,
+                models::DuplicateStrategy::Name,
+            ],
+            email_canonicalization: email::Canonicalization::default(),
+            reserved: reserved::ReservedLists::default(),
+            undo_window: None,
+            metrics_export: metrics::sinks::Settings::default(),
+            slo: slo::Settings::default(),
+            slow_request_threshold: Some(Duration::from_secs(1)),
+            request_timeout: None,
+            chaos: chaos::Settings::default(),
+            capture_file: None,
+            shadow: shadow::Settings::default(),
+            snapshot_dir: None,
+            shard: shard::Settings::default(),
+            replication: replication::Settings::default(),
+            job_workers: 2,
+            reports: reports::Settings::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads configuration from the process environment
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Loads configuration using the given variable lookup
+    ///
+    /// # Arguments
+    ///
+    /// * `lookup` - Returns the value of a variable, or `None` if unset
+    ///
+    /// # Returns
+    ///
+    /// Returns the configuration, or a [`ConfigError`] naming the first
+    /// variable that failed to parse
+    pub fn from_lookup<F>(lookup: F) -> Result<Self, ConfigError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let env = Env(&lookup);
+        let mut config = Config::default();
+
+        config.profile = env.parse("APP_ENV")?.unwrap_or(config.profile);
+        config.cors = env
+            .parse("APP_CORS_ORIGINS")?
+            .unwrap_or_else(|| config.profile.cors());
+
+        let rules = &mut config.name_rules;
+        rules.min_length = env
+            .parse("APP_NAME_MIN_LENGTH")?
+            .unwrap_or(rules.min_length);
+        rules.max_length = env
+            .parse("APP_NAME_MAX_LENGTH")?
+            .unwrap_or(rules.max_length);
+        rules.collapse_whitespace = env
+            .parse("APP_NAME_COLLAPSE_WHITESPACE")?
+            .unwrap_or(rules.collapse_whitespace);
+        if let Some(allowed) = env.list::<CharClass>("APP_NAME_ALLOWED_CLASSES")? {
+            rules.allowed = allowed;
+        }
+        if rules.min_length > rules.max_length {
+            return Err(ConfigError(
+                "APP_NAME_MIN_LENGTH exceeds APP_NAME_MAX_LENGTH".to_string(),
+            ));
+        }
+
+        config.phone_default_region = env.parse("APP_PHONE_DEFAULT_REGION")?;
+        config.timestamp_format = env
+            .parse("APP_TIMESTAMP_FORMAT")?
+            .unwrap_or(config.timestamp_format);
+        config.default_language = env
+            .parse("APP_DEFAULT_LANGUAGE")?
+            .unwrap_or(config.default_language);
+        config.custom_fields = env
+            .parse("APP_CUSTOM_FIELDS")?
+            .unwrap_or(config.custom_fields);
+        config.response_envelope = env
+            .parse("APP_RESPONSE_ENVELOPE")?
+            .unwrap_or(config.response_envelope);
+        config.field_case = env.parse("APP_FIELD_CASE")?.unwrap_or(config.field_case);
+        config.trailing_slash = env
+            .parse("APP_TRAILING_SLASH")?
+            .unwrap_or(config.trailing_slash);
+        config.mock_seed = env.parse("APP_MOCK_SEED")?.unwrap_or(config.mock_seed);
+        config.mock_users = env.parse("APP_MOCK_USERS")?.unwrap_or(config.mock_users);
+        config.dev_endpoints = env
+            .parse("APP_DEV_ENDPOINTS")?
+            .unwrap_or(config.dev_endpoints);
+        config.admin_endpoints = env
+            .parse("APP_ADMIN_ENDPOINTS")?
+            .unwrap_or(config.admin_endpoints);
+        config.error_details = env
+            .parse("APP_ERROR_DETAILS")?
+            .unwrap_or(config.error_details);
+        if config.profile == Profile::Prod && (config.dev_endpoints || config.error_details) {
+            return Err(ConfigError(
+                "APP_DEV_ENDPOINTS and APP_ERROR_DETAILS are refused with APP_ENV=prod".to_string(),
+            ));
+        }
+        let window_seconds = env.parse("APP_RATE_LIMIT_WINDOW_SECONDS")?.unwrap_or(60);
+        if window_seconds == 0 {
+            return Err(ConfigError(
+                "APP_RATE_LIMIT_WINDOW_SECONDS must be at least 1".to_string(),
+            ));
+        }
+        config.rate_limit = env
+            .parse("APP_RATE_LIMIT_REQUESTS")?
+            .map(|requests| RateLimit {
+                requests,
+                window_seconds,
+            });
+        config.max_concurrent_requests = env.parse("APP_MAX_CONCURRENT_REQUESTS")?;
+        if config.max_concurrent_requests == Some(0) {
+            return Err(ConfigError(
+                "APP_MAX_CONCURRENT_REQUESTS must be at least 1".to_string(),
+            ));
+        }
+        if let Some(seconds) = env.parse("APP_RETRY_AFTER_SECONDS")? {
+            config.retry_after = Duration::from_secs(seconds);
+        }
+
+        let resilience = &mut config.resilience;
+        resilience.failure_threshold = env
+            .parse("APP_BREAKER_FAILURE_THRESHOLD")?
+            .unwrap_or(resilience.failure_threshold);
+        if let Some(seconds) = env.parse("APP_BREAKER_OPEN_SECONDS")? {
+            resilience.open_duration = Duration::from_secs(seconds);
+        }
+        resilience.max_attempts = env
+            .parse("APP_RETRY_MAX_ATTEMPTS")?
+            .unwrap_or(resilience.max_attempts);
+        if let Some(millis) = env.parse("APP_RETRY_BACKOFF_MS")? {
+            resilience.backoff = Duration::from_millis(millis);
+        }
+        if resilience.failure_threshold == 0 || resilience.max_attempts == 0 {
+            return Err(ConfigError(
+                "APP_BREAKER_FAILURE_THRESHOLD and APP_RETRY_MAX_ATTEMPTS must be at least 1"
+                    .to_string(),
+            ));
+        }
+
+        if let Some(seconds) = env.parse("APP_CACHE_LIST_TTL_SECONDS")? {
+            config.cache.list_ttl = Duration::from_secs(seconds);
+        }
+        if let Some(seconds) = env.parse("APP_CACHE_USER_TTL_SECONDS")? {
+            config.cache.user_ttl = Duration::from_secs(seconds);
+        }
+        if let Some(tokens) = env.list::<auth::ApiToken>("APP_API_TOKENS")? {
+            config.api_tokens = tokens.into_iter().collect();
+        }
+        if let Some(keys) = env.list::<auth::ApiToken>("APP_SIGNING_KEYS")? {
+            config.signing_keys = keys.into_iter().collect();
+        }
+        if let Some(seconds) = env.parse("APP_SIGNATURE_WINDOW_SECONDS")? {
+            config.signature_window = Duration::from_secs(seconds);
+        }
+        if let Some(subjects) = env.list::<auth::ApiToken>("APP_CLIENT_CERT_PRINCIPALS")? {
+            config.client_cert_principals = subjects.into_iter().collect();
+        }
+
+        let tls = &mut config.tls;
+        tls.certificate = env.parse("APP_TLS_CERT")?;
+        tls.private_key = env.parse("APP_TLS_KEY")?;
+        tls.client_ca = env.parse("APP_TLS_CLIENT_CA")?;
+        tls.client_auth = env.parse("APP_TLS_CLIENT_AUTH")?.unwrap_or(tls.client_auth);
+        tls.http3 = env.parse("APP_TLS_HTTP3")?.unwrap_or(tls.http3);
+        if tls.certificate.is_some() != tls.private_key.is_some() {
+            return Err(ConfigError(
+                "APP_TLS_CERT and APP_TLS_KEY must be set together".to_string(),
+            ));
+        }
+        if tls.client_auth != tls::ClientAuth::Off && (!tls.is_enabled() || tls.client_ca.is_none())
+        {
+            return Err(ConfigError(
+                "APP_TLS_CLIENT_AUTH requires APP_TLS_CERT, APP_TLS_KEY and APP_TLS_CLIENT_CA"
+                    .to_string(),
+            ));
+        }
+        if tls.http3 && !tls.is_enabled() {
+            return Err(ConfigError(
+                "APP_TLS_HTTP3 requires APP_TLS_CERT and APP_TLS_KEY".to_string(),
+            ));
+        }
+
+        let ip_filter = &mut config.ip_filter;
+        ip_filter.allow = env.list("APP_IP_ALLOW")?.unwrap_or_default();
+        ip_filter.deny = env.list("APP_IP_DENY")?.unwrap_or_default();
+        if let Some(proxies) = env.list::<client_ip::Cidr>("APP_TRUSTED_PROXIES")? {
+            config.trusted_proxies = proxies.into_iter().collect();
+        }
+
+        config.audit_capacity = env
+            .parse("APP_AUDIT_CAPACITY")?
+            .unwrap_or(config.audit_capacity);
+        config.geoip_database = env.parse("APP_GEOIP_DATABASE")?;
+        if let Some(seconds) = env.parse("APP_IMPERSONATION_TTL_SECONDS")? {
+            config.impersonation_ttl = Duration::from_secs(seconds);
+        }
+
+        let storage_capacity = &mut config.storage_capacity;
+        storage_capacity.max_users = env.parse("APP_MAX_USERS")?;
+        if storage_capacity.max_users == Some(0) {
+            return Err(ConfigError("APP_MAX_USERS must be at least 1".to_string()));
+        }
+        storage_capacity.eviction = env
+            .parse("APP_STORAGE_EVICTION")?
+            .unwrap_or(storage_capacity.eviction);
+
+        if let Some(strategies) = env.list("APP_DUPLICATE_STRATEGIES")? {
+            if strategies.is_empty() {
+                return Err(ConfigError(
+                    "APP_DUPLICATE_STRATEGIES must name at least one strategy".to_string(),
+                ));
+            }
+            config.duplicate_strategies = strategies;
+        }
+        config.email_canonicalization = env
+            .parse("APP_EMAIL_CANONICALIZATION")?
+            .unwrap_or(config.email_canonicalization);
+        let reserved = &mut config.reserved;
+        if let Some(emails) = env.list("APP_RESERVED_EMAILS")? {
+            reserved.emails = emails;
+        }
+        if let Some(names) = env.list("APP_RESERVED_NAMES")? {
+            reserved.names = names;
+        }
+        if let Some(terms) = env.list("APP_BLOCKED_TERMS")? {
+            reserved.blocked_terms = terms;
+        }
+
+        config.undo_window = env
+            .parse("APP_UNDO_WINDOW_SECONDS")?
+            .map(Duration::from_secs);
+        if config.undo_window == Some(Duration::ZERO) {
+            return Err(ConfigError(
+                "APP_UNDO_WINDOW_SECONDS must be at least 1".to_string(),
+            ));
+        }
+
+        let export = &mut config.metrics_export;
+        export.statsd_addr = env.parse("APP_STATSD_ADDR")?;
+        if let Some(addr) = &export.statsd_addr {
+            let valid = addr
+                .rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+            if !valid {
+                return Err(ConfigError(format!(
+                    "APP_STATSD_ADDR: expected host:port, got '{}'",
+                    addr
+                )));
+            }
+        }
+        export.statsd_prefix = env
+            .parse("APP_STATSD_PREFIX")?
+            .unwrap_or(export.statsd_prefix.clone());
+        export.pushgateway = env.parse("APP_PUSHGATEWAY_URL")?;
+        export.pushgateway_job = env
+            .parse("APP_PUSHGATEWAY_JOB")?
+            .unwrap_or(export.pushgateway_job.clone());
+        if let Some(seconds) = env.parse("APP_METRICS_EXPORT_INTERVAL_SECONDS")? {
+            export.interval = Duration::from_secs(seconds);
+        }
+        if export.interval.is_zero() {
+            return Err(ConfigError(
+                "APP_METRICS_EXPORT_INTERVAL_SECONDS must be at least 1".to_string(),
+            ));
+        }
+
+        let slo = &mut config.slo;
+        if let Some(seconds) = env.parse("APP_SLO_WINDOW_SECONDS")? {
+            slo.window = Duration::from_secs(seconds);
+        }
+        for (key, limit) in [
+            ("APP_SLO_P50_MS", &mut slo.p50),
+            ("APP_SLO_P95_MS", &mut slo.p95),
+            ("APP_SLO_P99_MS", &mut slo.p99),
+        ] {
+            if let Some(millis) = env.parse(key)? {
+                *limit = Some(Duration::from_millis(millis)).filter(|limit| !limit.is_zero());
+            }
+        }
+        if let Some(rate) = env.parse::<f64>("APP_SLO_ERROR_RATE")? {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(ConfigError(
+                    "APP_SLO_ERROR_RATE must be between 0 and 1".to_string(),
+                ));
+            }
+            slo.error_rate = Some(rate).filter(|rate| *rate > 0.0);
+        }
+        if slo.window.is_zero() {
+            return Err(ConfigError(
+                "APP_SLO_WINDOW_SECONDS must be at least 1".to_string(),
+            ));
+        }
+
+        if let Some(millis) = env.parse("APP_SLOW_REQUEST_MS")? {
+            config.slow_request_threshold =
+                Some(Duration::from_millis(millis)).filter(|threshold| !threshold.is_zero());
+        }
+
+        if let Some(millis) = env.parse("APP_REQUEST_TIMEOUT_MS")? {
+            config.request_timeout =
+                Some(Duration::from_millis(millis)).filter(|timeout| !timeout.is_zero());
+        }
+
+        let chaos = &mut config.chaos;
+        if let Some(millis) = env.parse("APP_CHAOS_LATENCY_MS")? {
+            chaos.latency = Duration::from_millis(millis);
+        }
+        for (key, rate) in [
+            ("APP_CHAOS_LATENCY_RATE", &mut chaos.latency_rate),
+            ("APP_CHAOS_ERROR_RATE", &mut chaos.error_rate),
+            (
+                "APP_CHAOS_STORAGE_ERROR_RATE",
+                &mut chaos.storage_error_rate,
+            ),
+        ] {
+            if let Some(value) = env.parse::<f64>(key)? {
+                if !(0.0..=1.0).contains(&value) {
+                    return Err(ConfigError(format!("{} must be between 0 and 1", key)));
+                }
+                *rate = value;
+            }
+        }
+        if chaos.is_enabled() && !config.dev_endpoints {
+            return Err(ConfigError(
+                "APP_CHAOS_* fault injection requires APP_DEV_ENDPOINTS=true".to_string(),
+            ));
+        }
+
+        config.capture_file = env.parse("APP_CAPTURE_FILE")?;
+        if config.capture_file.is_some() && !config.admin_endpoints {
+            return Err(ConfigError(
+                "APP_CAPTURE_FILE requires APP_ADMIN_ENDPOINTS=true".to_string(),
+            ));
+        }
+
+        config.snapshot_dir = env.parse("APP_SNAPSHOT_DIR")?;
+        if config.snapshot_dir.is_some() && !config.admin_endpoints {
+            return Err(ConfigError(
+                "APP_SNAPSHOT_DIR requires APP_ADMIN_ENDPOINTS=true".to_string(),
+            ));
+        }
+
+        config.shadow.backend = env.parse("APP_SHADOW_BACKEND")?;
+        if let Some(compare_reads) = env.parse("APP_SHADOW_COMPARE_READS")? {
+            config.shadow.compare_reads = compare_reads;
+        }
+
+        if let Some(peers) = env.list("APP_SHARD_PEERS")? {
+            config.shard.peers = peers;
+        }
+        config.shard.this = env.parse("APP_SHARD_SELF")?;
+        config.shard.token = env.parse("APP_SHARD_TOKEN")?;
+        if config.shard.is_enabled() && config.shard.token.is_none() {
+            return Err(ConfigError(
+                "APP_SHARD_PEERS requires APP_SHARD_TOKEN".to_string(),
+            ));
+        }
+        match &config.shard.this {
+            Some(this) if !config.shard.peers.contains(this) => {
+                return Err(ConfigError(
+                    "APP_SHARD_SELF must be one of APP_SHARD_PEERS".to_string(),
+                ));
+            }
+            None if config.shard.is_enabled() => {
+                return Err(ConfigError(
+                    "APP_SHARD_PEERS requires APP_SHARD_SELF".to_string(),
+                ));
+            }
+            _ => {}
+        }
+
+        config.replication.role = env.parse("APP_REPLICATION_ROLE")?;
+        config.replication.leader = env.parse("APP_REPLICATION_LEADER")?;
+        config.replication.token = env.parse("APP_REPLICATION_TOKEN")?;
+        let replicates = config
+            .replication
+            .role
+            .is_some_and(replication::Role::replicates);
+        if replicates != config.replication.leader.is_some() {
+            return Err(ConfigError(
+                "APP_REPLICATION_LEADER is required for, and only allowed with, APP_REPLICATION_ROLE=follower or replica"
+                    .to_string(),
+            ));
+        }
+
+        config.job_workers = env.parse("APP_JOB_WORKERS")?.unwrap_or(config.job_workers);
+        if config.job_workers == 0 {
+            return Err(ConfigError(
+                "APP_JOB_WORKERS must be at least 1".to_string(),
+            ));
+        }
+
+        let reports = &mut config.reports;
+        if let Some(seconds) = env.parse("APP_REPORT_INTERVAL_SECONDS")? {
+            if seconds == 0 {
+                return Err(ConfigError(
+                    "APP_REPORT_INTERVAL_SECONDS must be at least 1".to_string(),
+                ));
+            }
+            reports.interval = Some(Duration::from_secs(seconds));
+        }
+        reports.weeks = env.parse("APP_REPORT_WEEKS")?.unwrap_or(reports.weeks);
+        if reports.weeks == 0 {
+            return Err(ConfigError(
+                "APP_REPORT_WEEKS must be at least 1".to_string(),
+            ));
+        }
+
+        Ok(config)
+    }
+}
+
+/// Typed access to configuration variables
+struct Env<'a>(&'a dyn Fn(&str) -> Option<String>);
+
+impl Env<'_> {
+    /// Parses a single value
+    fn parse<T: FromStr>(&self, key: &str) -> Result<Option<T>, ConfigError>
+    where
+        T::Err: std::fmt::Display,
+    {
+        (self.0)(key)
+            .map(|value| {
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|err| ConfigError(format!("{}: {}", key, err)))
+            })
+            .transpose()
+    }
+
+    /// Parses a comma-separated list of values
+    fn list<T: FromStr>(&self, key: &str) -> Result<Option<Vec<T>>, ConfigError>
+    where
+        T::Err: std::fmt::Display,
+    {
+        (self.0)(key)
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(|item| {
+                        item.parse()
+                            .map_err(|err| ConfigError(format!("{}: {}", key, err)))
+                    })
+                    .collect()
+            })
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load(vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Config::from_lookup(|key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn test_defaults_without_variables() {
+        let config = load(&[]).unwrap();
+        assert_eq!(config.name_rules, NameRules::default());
+    }
+
+    #[test]
+    fn test_name_rules_from_variables() {
+        let config = load(&[
+            ("APP_NAME_MIN_LENGTH", "2"),
+            ("APP_NAME_MAX_LENGTH", "50"),
+            ("APP_NAME_ALLOWED_CLASSES", "letter, number ,space"),
+            ("APP_NAME_COLLAPSE_WHITESPACE", "false"),
+        ])
+        .unwrap();
+
+        assert_eq!(config.name_rules.min_length, 2);
+        assert_eq!(config.name_rules.max_length, 50);
+        assert_eq!(
+            config.name_rules.allowed,
+            vec![CharClass::Letter, CharClass::Number, CharClass::Space]
+        );
+        assert!(!config.name_rules.collapse_whitespace);
+    }
+
+    #[test]
+    fn test_phone_default_region() {
+        assert_eq!(load(&[]).unwrap().phone_default_region, None);
+
+        let config = load(&[("APP_PHONE_DEFAULT_REGION", "GB")]).unwrap();
+        assert_eq!(
+            config.phone_default_region,
+            Some(phonenumber::country::Id::GB)
+        );
+
+        assert!(load(&[("APP_PHONE_DEFAULT_REGION", "XX")]).is_err());
+    }
+
+    #[test]
+    fn test_timestamp_format() {
+        assert_eq!(load(&[]).unwrap().timestamp_format, TimestampFormat::Unix);
+
+        let config = load(&[("APP_TIMESTAMP_FORMAT", "RFC3339")]).unwrap();
+        assert_eq!(config.timestamp_format, TimestampFormat::Rfc3339);
+
+        assert!(load(&[("APP_TIMESTAMP_FORMAT", "iso")]).is_err());
+    }
+
+    #[test]
+    fn test_default_language() {
+        assert_eq!(load(&[]).unwrap().default_language, Language::En);
+
+        let config = load(&[("APP_DEFAULT_LANGUAGE", "de")]).unwrap();
+        assert_eq!(config.default_language, Language::De);
+
+        assert!(load(&[("APP_DEFAULT_LANGUAGE", "ja")]).is_err());
+    }
+
+    #[test]
+    fn test_custom_fields() {
+        assert!(load(&[]).unwrap().custom_fields.fields().is_empty());
+
+        let config = load(&[(
+            "APP_CUSTOM_FIELDS",
+            r#"[{"name": "department", "type": "string", "required": true}]"#,
+        )])
+        .unwrap();
+        let field = config.custom_fields.get("department").unwrap();
+        assert!(field.required);
+
+        let err = load(&[("APP_CUSTOM_FIELDS", "department:string")]).unwrap_err();
+        assert!(err
+            .0
+            .starts_with("APP_CUSTOM_FIELDS: invalid custom field schema"));
+    }
+
+    #[test]
+    fn test_response_envelope() {
+        assert!(load(&[]).unwrap().response_envelope);
+        assert!(
+            !load(&[("APP_RESPONSE_ENVELOPE", "false")])
+                .unwrap()
+                .response_envelope
+        );
+        assert!(load(&[("APP_RESPONSE_ENVELOPE", "no")]).is_err());
+    }
+
+    #[test]
+    fn test_field_case() {
+        assert_eq!(load(&[]).unwrap().field_case, FieldCase::Snake);
+
+        let config = load(&[("APP_FIELD_CASE", "camelCase")]).unwrap();
+        assert_eq!(config.field_case, FieldCase::Camel);
+
+        assert!(load(&[("APP_FIELD_CASE", "PascalCase")]).is_err());
+    }
+
+    #[test]
+    fn test_trailing_slash() {
+        assert_eq!(load(&[]).unwrap().trailing_slash, TrailingSlash::Rewrite);
+
+        let config = load(&[("APP_TRAILING_SLASH", "redirect")]).unwrap();
+        assert_eq!(config.trailing_slash, TrailingSlash::Redirect);
+
+        assert!(load(&[("APP_TRAILING_SLASH", "strict")]).is_err());
+    }
+
+    #[test]
+    fn test_mock_settings() {
+        let config = load(&[]).unwrap();
+        assert_eq!((config.mock_seed, config.mock_users), (1, 50));
+
+        let config = load(&[("APP_MOCK_SEED", "99"), ("APP_MOCK_USERS", "0")]).unwrap();
+        assert_eq!((config.mock_seed, config.mock_users), (99, 0));
+
+        assert!(load(&[("APP_MOCK_USERS", "-1")]).is_err());
+    }
+
+    #[test]
+    fn test_dev_endpoints() {
+        assert!(!load(&[]).unwrap().dev_endpoints);
+        assert!(
+            load(&[("APP_DEV_ENDPOINTS", "true")])
+                .unwrap()
+                .dev_endpoints
+        );
+        assert!(load(&[("APP_DEV_ENDPOINTS", "yes")]).is_err());
+    }
+
+    #[test]
+    fn test_admin_endpoints() {
+        assert!(!load(&[]).unwrap().admin_endpoints);
+        assert!(
+            load(&[("APP_ADMIN_ENDPOINTS", "true")])
+                .unwrap()
+                .admin_endpoints
+        );
+    }
+
+    #[test]
+    fn test_error_details() {
+        assert!(!load(&[]).unwrap().error_details);
+        assert!(
+            load(&[("APP_ERROR_DETAILS", "true")])
+                .unwrap()
+                .error_details
+        );
+        assert!(load(&[("APP_ERROR_DETAILS", "verbose")]).is_err());
+    }
+
+    #[test]
+    fn test_concurrency_limit() {
+        let config = load(&[]).unwrap();
+        assert_eq!(config.max_concurrent_requests, None);
+        assert_eq!(config.retry_after, Duration::from_secs(1));
+
+        let config = load(&[
+            ("APP_MAX_CONCURRENT_REQUESTS", "256"),
+            ("APP_RETRY_AFTER_SECONDS", "5"),
+        ])
+        .unwrap();
+        assert_eq!(config.max_concurrent_requests, Some(256));
+        assert_eq!(config.retry_after, Duration::from_secs(5));
+
+        assert!(load(&[("APP_MAX_CONCURRENT_REQUESTS", "0")]).is_err());
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        assert_eq!(load(&[]).unwrap().rate_limit, None);
+
+        let config = load(&[("APP_RATE_LIMIT_REQUESTS", "100")]).unwrap();
+        assert_eq!(
+            config.rate_limit,
+            Some(RateLimit {
+                requests: 100,
+                window_seconds: 60
+            })
+        );
+        let config = load(&[
+            ("APP_RATE_LIMIT_REQUESTS", "10"),
+            ("APP_RATE_LIMIT_WINDOW_SECONDS", "1"),
+        ])
+        .unwrap();
+        assert_eq!(config.rate_limit.unwrap().window_seconds, 1);
+
+        assert!(load(&[("APP_RATE_LIMIT_WINDOW_SECONDS", "0")]).is_err());
+        assert!(load(&[("APP_RATE_LIMIT_REQUESTS", "-1")]).is_err());
+    }
+
+    #[test]
+    fn test_resilience_settings() {
+        assert_eq!(
+            load(&[]).unwrap().resilience,
+            resilience::Settings::default()
+        );
+
+        let config = load(&[
+            ("APP_BREAKER_FAILURE_THRESHOLD", "10"),
+            ("APP_BREAKER_OPEN_SECONDS", "60"),
+            ("APP_RETRY_MAX_ATTEMPTS", "1"),
+            ("APP_RETRY_BACKOFF_MS", "250"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.resilience,
+            resilience::Settings {
+                failure_threshold: 10,
+                open_duration: Duration::from_secs(60),
+                max_attempts: 1,
+                backoff: Duration::from_millis(250),
+            }
+        );
+
+        assert!(load(&[("APP_RETRY_MAX_ATTEMPTS", "0")]).is_err());
+    }
+
+    #[test]
+    fn test_cache_ttls() {
+        assert_eq!(load(&[]).unwrap().cache, cache::Settings::default());
+
+        let config = load(&[
+            ("APP_CACHE_LIST_TTL_SECONDS", "5"),
+            ("APP_CACHE_USER_TTL_SECONDS", "30"),
+        ])
+        .unwrap();
+        assert_eq!(config.cache.list_ttl, Duration::from_secs(5));
+        assert_eq!(config.cache.user_ttl, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_api_tokens() {
+        assert!(load(&[]).unwrap().api_tokens.is_empty());
+
+        let config = load(&[(
+            "APP_API_TOKENS",
+            "reporting:r-123=users:read, ops:o-456=users:read+users:write+admin",
+        )])
+        .unwrap();
+        let reporting = config.api_tokens.principal("r-123").unwrap();
+        assert_eq!(reporting.name, "reporting");
+        assert_eq!(reporting.scopes, vec![auth::Scope::UsersRead]);
+        assert_eq!(config.api_tokens.principal("o-456").unwrap().name, "ops");
+        assert!(config.api_tokens.principal("unknown").is_none());
+
+        assert!(load(&[("APP_API_TOKENS", "ops:o-456=root")]).is_err());
+    }
+
+    #[test]
+    fn test_signing_keys() {
+        let config = load(&[]).unwrap();
+        assert!(config.signing_keys.is_empty());
+        assert_eq!(config.signature_window, Duration::from_secs(300));
+
+        let config = load(&[
+            ("APP_SIGNING_KEYS", "billing:k3y=users:read+users:write"),
+            ("APP_SIGNATURE_WINDOW_SECONDS", "60"),
+        ])
+        .unwrap();
+        assert!(!config.signing_keys.is_empty());
+        assert_eq!(config.signature_window, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_tls() {
+        let config = load(&[]).unwrap();
+        assert!(!config.tls.is_enabled());
+        assert!(config.client_cert_principals.is_empty());
+
+        let config = load(&[
+            ("APP_TLS_CERT", "/etc/rust-api/server.pem"),
+            ("APP_TLS_KEY", "/etc/rust-api/server.key"),
+            ("APP_TLS_CLIENT_CA", "/etc/rust-api/ca.pem"),
+            ("APP_TLS_CLIENT_AUTH", "required"),
+            (
+                "APP_CLIENT_CERT_PRINCIPALS",
+                "billing:billing.internal=users:read",
+            ),
+        ])
+        .unwrap();
+        assert!(config.tls.is_enabled());
+        assert_eq!(config.tls.client_auth, tls::ClientAuth::Required);
+        assert!(!config.client_cert_principals.is_empty());
+
+        assert!(load(&[("APP_TLS_CERT", "/etc/rust-api/server.pem")]).is_err());
+        assert!(load(&[
+            ("APP_TLS_CERT", "/etc/rust-api/server.pem"),
+            ("APP_TLS_KEY", "/etc/rust-api/server.key"),
+            ("APP_TLS_CLIENT_AUTH", "required"),
+        ])
+        .is_err());
+
+        let config = load(&[
+            ("APP_TLS_CERT", "/etc/rust-api/server.pem"),
+            ("APP_TLS_KEY", "/etc/rust-api/server.key"),
+            ("APP_TLS_HTTP3", "true"),
+        ])
+        .unwrap();
+        assert!(config.tls.http3);
+        assert!(load(&[("APP_TLS_HTTP3", "true")]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_values_are_rejected() {
+        assert!(load(&[("APP_NAME_MAX_LENGTH", "many")]).is_err());
+        assert!(load(&[("APP_NAME_ALLOWED_CLASSES", "letter,emoji")]).is_err());
+        assert!(load(&[("APP_NAME_MIN_LENGTH", "10"), ("APP_NAME_MAX_LENGTH", "5")]).is_err());
+    }
+
+    #[test]
+    fn test_ip_filter() {
+        assert!(load(&[]).unwrap().ip_filter.is_empty());
+
+        let config = load(&[
+            ("APP_IP_ALLOW", "10.0.0.0/8, 192.0.2.1"),
+            ("APP_IP_DENY", "10.0.0.13"),
+            ("APP_TRUSTED_PROXIES", "10.0.0.1"),
+        ])
+        .unwrap();
+        let ip_filter = &config.ip_filter;
+        assert_eq!(ip_filter.allow.len(), 2);
+        assert_eq!(ip_filter.deny.len(), 1);
+        assert!(config
+            .trusted_proxies
+            .contains(&"10.0.0.1".parse().unwrap()));
+
+        assert!(load(&[("APP_IP_DENY", "10.0.0.0/40")]).is_err());
+    }
+
+    #[test]
+    fn test_audit() {
+        let config = load(&[]).unwrap();
+        assert_eq!(config.audit_capacity, 10_000);
+        assert_eq!(config.geoip_database, None);
+        assert_eq!(config.impersonation_ttl, Duration::from_secs(900));
+
+        let config = load(&[
+            ("APP_AUDIT_CAPACITY", "500"),
+            ("APP_GEOIP_DATABASE", "/var/lib/GeoLite2-City.mmdb"),
+            ("APP_IMPERSONATION_TTL_SECONDS", "60"),
+        ])
+        .unwrap();
+        assert_eq!(config.audit_capacity, 500);
+        assert_eq!(config.impersonation_ttl, Duration::from_secs(60));
+        assert_eq!(
+            config.geoip_database,
+            Some(PathBuf::from("/var/lib/GeoLite2-City.mmdb"))
+        );
+    }
+
+    #[test]
+    fn test_storage_capacity() {
+        let config = load(&[]).unwrap();
+        assert_eq!(config.storage_capacity.max_users, None);
+        assert_eq!(config.storage_capacity.eviction, models::Eviction::Reject);
+
+        let config = load(&[("APP_MAX_USERS", "1000"), ("APP_STORAGE_EVICTION", "LRU")]).unwrap();
+        assert_eq!(config.storage_capacity.max_users, Some(1000));
+        assert_eq!(config.storage_capacity.eviction, models::Eviction::Lru);
+
+        assert!(load(&[("APP_MAX_USERS", "0")]).is_err());
+        assert!(load(&[("APP_STORAGE_EVICTION", "fifo")]).is_err());
+    }
+
+    #[test]
+    fn test_email_canonicalization() {
+        use email::Canonicalization;
+
+        let config = load(&[]).unwrap();
+        assert_eq!(config.email_canonicalization, Canonicalization::Exact);
+
+        let config = load(&[("APP_EMAIL_CANONICALIZATION", "gmail")]).unwrap();
+        assert_eq!(config.email_canonicalization, Canonicalization::Gmail);
+
+        assert!(load(&[("APP_EMAIL_CANONICALIZATION", "dots")]).is_err());
+    }
+
+    #[test]
+    fn test_reserved() {
+        let config = load(&[]).unwrap();
+        assert_eq!(config.reserved, reserved::ReservedLists::default());
+
+        let config = load(&[
+            ("APP_RESERVED_EMAILS", "noreply@example.com, admin@"),
+            ("APP_RESERVED_NAMES", ""),
+            ("APP_BLOCKED_TERMS", "darn,heck"),
+        ])
+        .unwrap();
+        assert_eq!(config.reserved.emails, ["noreply@example.com", "admin@"]);
+        assert!(config.reserved.names.is_empty());
+        assert_eq!(config.reserved.blocked_terms, ["darn", "heck"]);
+    }
+
+    #[test]
+    fn test_duplicate_strategies() {
+        use models::DuplicateStrategy::{Email, Name};
+
+        assert_eq!(load(&[]).unwrap().duplicate_strategies, vec![Email, Name]);
+        let config = load(&[("APP_DUPLICATE_STRATEGIES", "name")]).unwrap();
+        assert_eq!(config.duplicate_strategies, vec![Name]);
+
+        assert!(load(&[("APP_DUPLICATE_STRATEGIES", "")]).is_err());
+        assert!(load(&[("APP_DUPLICATE_STRATEGIES", "email,phonetic")]).is_err());
+    }
+
+    #[test]
+    fn test_undo_window() {
+        assert_eq!(load(&[]).unwrap().undo_window, None);
+        let config = load(&[("APP_UNDO_WINDOW_SECONDS", "30")]).unwrap();
+        assert_eq!(config.undo_window, Some(Duration::from_secs(30)));
+
+        assert!(load(&[("APP_UNDO_WINDOW_SECONDS", "0")]).is_err());
+        assert!(load(&[("APP_UNDO_WINDOW_SECONDS", "soon")]).is_err());
+    }
+
+    #[test]
+    fn test_metrics_export() {
+        let config = load(&[]).unwrap();
+        assert_eq!(config.metrics_export, metrics::sinks::Settings::default());
+
+        let config = load(&[
+            ("APP_STATSD_ADDR", "localhost:8125"),
+            ("APP_STATSD_PREFIX", "users"),
+            ("APP_PUSHGATEWAY_URL", "http://gateway:9091"),
+            ("APP_PUSHGATEWAY_JOB", "users"),
+            ("APP_METRICS_EXPORT_INTERVAL_SECONDS", "60"),
+        ])
+        .unwrap();
+        let export = config.metrics_export;
+        assert_eq!(export.statsd_addr.as_deref(), Some("localhost:8125"));
+        assert_eq!(export.statsd_prefix, "users");
+        assert_eq!(export.pushgateway, "http://gateway:9091".parse().ok());
+        assert_eq!(export.pushgateway_job, "users");
+        assert_eq!(export.interval, Duration::from_secs(60));
+
+        assert!(load(&[("APP_STATSD_ADDR", "localhost")]).is_err());
+        assert!(load(&[("APP_PUSHGATEWAY_URL", "gateway:9091")]).is_err());
+        assert!(load(&[("APP_METRICS_EXPORT_INTERVAL_SECONDS", "0")]).is_err());
+    }
+
+    #[test]
+    fn test_slo() {
+        assert_eq!(load(&[]).unwrap().slo, slo::Settings::default());
+
+        let config = load(&[
+            ("APP_SLO_WINDOW_SECONDS", "60"),
+            ("APP_SLO_P50_MS", "50"),
+            ("APP_SLO_P99_MS", "0"),
+            ("APP_SLO_ERROR_RATE", "0.05"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.slo,
+            slo::Settings {
+                window: Duration::from_secs(60),
+                p50: Some(Duration::from_millis(50)),
+                p95: None,
+                p99: None,
+                error_rate: Some(0.05),
+            }
+        );
+        assert_eq!(
+            load(&[("APP_SLO_ERROR_RATE", "0")]).unwrap().slo.error_rate,
+            None
+        );
+
+        assert!(load(&[("APP_SLO_WINDOW_SECONDS", "0")]).is_err());
+        assert!(load(&[("APP_SLO_ERROR_RATE", "5")]).is_err());
+        assert!(load(&[("APP_SLO_P95_MS", "fast")]).is_err());
+    }
+
+    #[test]
+    fn test_slow_request_threshold() {
+        assert_eq!(
+            load(&[]).unwrap().slow_request_threshold,
+            Some(Duration::from_secs(1))
+        );
+        let config = load(&[("APP_SLOW_REQUEST_MS", "250")]).unwrap();
+        assert_eq!(
+            config.slow_request_threshold,
+            Some(Duration::from_millis(250))
+        );
+        let config = load(&[("APP_SLOW_REQUEST_MS", "0")]).unwrap();
+        assert_eq!(config.slow_request_threshold, None);
+
+        assert!(load(&[("APP_SLOW_REQUEST_MS", "-1")]).is_err());
+    }
+
+    #[test]
+    fn test_request_timeout() {
+        assert_eq!(load(&[]).unwrap().request_timeout, None);
+        let config = load(&[("APP_REQUEST_TIMEOUT_MS", "5000")]).unwrap();
+        assert_eq!(config.request_timeout, Some(Duration::from_secs(5)));
+        let config = load(&[("APP_REQUEST_TIMEOUT_MS", "0")]).unwrap();
+        assert_eq!(config.request_timeout, None);
+
+        assert!(load(&[("APP_REQUEST_TIMEOUT_MS", "soon")]).is_err());
+    }
+
+    #[test]
+    fn test_chaos() {
+        assert!(!load(&[]).unwrap().chaos.is_enabled());
+
+        let config = load(&[
+            ("APP_DEV_ENDPOINTS", "true"),
+            ("APP_CHAOS_LATENCY_MS", "200"),
+            ("APP_CHAOS_LATENCY_RATE", "0.5"),
+            ("APP_CHAOS_ERROR_RATE", "0.1"),
+            ("APP_CHAOS_STORAGE_ERROR_RATE", "0.05"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.chaos,
+            chaos::Settings {
+                latency: Duration::from_millis(200),
+                latency_rate: 0.5,
+                error_rate: 0.1,
+                storage_error_rate: 0.05,
+            }
+        );
+
+        assert!(load(&[("APP_CHAOS_ERROR_RATE", "0.1")]).is_err());
+        assert!(load(&[
+            ("APP_DEV_ENDPOINTS", "true"),
+            ("APP_CHAOS_ERROR_RATE", "1.5")
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_capture_file() {
+        assert_eq!(load(&[]).unwrap().capture_file, None);
+
+        let config = load(&[
+            ("APP_ADMIN_ENDPOINTS", "true"),
+            ("APP_CAPTURE_FILE", "/var/tmp/capture.jsonl"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.capture_file,
+            Some(PathBuf::from("/var/tmp/capture.jsonl"))
+        );
+
+        assert!(load(&[("APP_CAPTURE_FILE", "/var/tmp/capture.jsonl")]).is_err());
+    }
+
+    #[test]
+    fn test_shadow() {
+        assert_eq!(load(&[]).unwrap().shadow, shadow::Settings::default());
+
+        let config = load(&[
+            ("APP_SHADOW_BACKEND", "Memory"),
+            ("APP_SHADOW_COMPARE_READS", "false"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.shadow,
+            shadow::Settings {
+                backend: Some(shadow::Backend::Memory),
+                compare_reads: false,
+            }
+        );
+
+        assert!(load(&[("APP_SHADOW_BACKEND", "postgres")]).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_dir() {
+        assert_eq!(load(&[]).unwrap().snapshot_dir, None);
+
+        let config = load(&[
+            ("APP_ADMIN_ENDPOINTS", "true"),
+            ("APP_SNAPSHOT_DIR", "/var/backups/rust-api"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.snapshot_dir,
+            Some(PathBuf::from("/var/backups/rust-api"))
+        );
+
+        assert!(load(&[("APP_SNAPSHOT_DIR", "/var/backups/rust-api")]).is_err());
+    }
+
+    #[test]
+    fn test_shard() {
+        assert!(!load(&[]).unwrap().shard.is_enabled());
+
+        let config = load(&[
+            (
+                "APP_SHARD_PEERS",
+                "http://10.0.0.1:3000, http://10.0.0.2:3000/",
+            ),
+            ("APP_SHARD_SELF", "http://10.0.0.2:3000"),
+            ("APP_SHARD_TOKEN", "p33r"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.shard,
+            shard::Settings {
+                peers: vec![
+                    "http://10.0.0.1:3000".parse().unwrap(),
+                    "http://10.0.0.2:3000".parse().unwrap(),
+                ],
+                this: Some("http://10.0.0.2:3000".parse().unwrap()),
+                token: Some("p33r".to_string()),
+            }
+        );
+
+        assert!(load(&[
+            ("APP_SHARD_PEERS", "http://10.0.0.1:3000"),
+            ("APP_SHARD_TOKEN", "p33r"),
+        ])
+        .is_err());
+        assert!(load(&[
+            ("APP_SHARD_PEERS", "http://10.0.0.1:3000"),
+            ("APP_SHARD_SELF", "http://10.0.0.3:3000"),
+            ("APP_SHARD_TOKEN", "p33r"),
+        ])
+        .is_err());
+        assert!(load(&[
+            ("APP_SHARD_PEERS", "http://10.0.0.1:3000"),
+            ("APP_SHARD_SELF", "http://10.0.0.1:3000"),
+        ])
+        .is_err());
+        assert!(load(&[("APP_SHARD_PEERS", "10.0.0.1:3000")]).is_err());
+    }
+
+    #[test]
+    fn test_replication() {
+        assert_eq!(
+            load(&[]).unwrap().replication,
+            replication::Settings::default()
+        );
+
+        let config = load(&[
+            ("APP_REPLICATION_ROLE", "follower"),
+            ("APP_REPLICATION_LEADER", "http://10.0.0.1:3000"),
+            ("APP_REPLICATION_TOKEN", "replica-token"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.replication,
+            replication::Settings {
+                role: Some(replication::Role::Follower),
+                leader: Some("http://10.0.0.1:3000".parse().unwrap()),
+                token: Some("replica-token".to_string()),
+            }
+        );
+
+        assert!(load(&[("APP_REPLICATION_ROLE", "leader")]).is_ok());
+        assert!(load(&[("APP_REPLICATION_ROLE", "follower")]).is_err());
+        assert!(load(&[("APP_REPLICATION_ROLE", "replica")]).is_err());
+        assert_eq!(
+            load(&[
+                ("APP_REPLICATION_ROLE", "replica"),
+                ("APP_REPLICATION_LEADER", "http://10.0.0.1:3000"),
+            ])
+            .unwrap()
+            .replication
+            .role,
+            Some(replication::Role::Replica)
+        );
+        assert!(load(&[
+            ("APP_REPLICATION_ROLE", "leader"),
+            ("APP_REPLICATION_LEADER", "http://10.0.0.1:3000"),
+        ])
+        .is_err());
+        assert!(load(&[("APP_REPLICATION_ROLE", "candidate")]).is_err());
+    }
+
+    #[test]
+    fn test_job_workers() {
+        assert_eq!(load(&[]).unwrap().job_workers, 2);
+        assert_eq!(load(&[("APP_JOB_WORKERS", "8")]).unwrap().job_workers, 8);
+        assert!(load(&[("APP_JOB_WORKERS", "0")]).is_err());
+    }
+
+    #[test]
+    fn test_reports() {
+        assert_eq!(load(&[]).unwrap().reports, reports::Settings::default());
+        let config = load(&[
+            ("APP_REPORT_INTERVAL_SECONDS", "86400"),
+            ("APP_REPORT_WEEKS", "52"),
+        ])
+        .unwrap();
+        assert_eq!(config.reports.interval, Some(Duration::from_secs(86400)));
+        assert_eq!(config.reports.weeks, 52);
+        assert!(load(&[("APP_REPORT_INTERVAL_SECONDS", "0")]).is_err());
+        assert!(load(&[("APP_REPORT_WEEKS", "0")]).is_err());
+    }
+
+    #[test]
+    fn test_profile() {
+        let dev = load(&[]).unwrap();
+        assert_eq!(dev.profile, Profile::Dev);
+        assert_eq!(dev.cors, cors::Settings::Permissive);
+
+        let prod = load(&[("APP_ENV", "prod")]).unwrap();
+        assert_eq!(prod.profile, Profile::Prod);
+        assert_eq!(prod.cors, cors::Settings::Origins(Vec::new()));
+        let prod = load(&[
+            ("APP_ENV", "prod"),
+            ("APP_CORS_ORIGINS", "https://admin.example.com"),
+        ])
+        .unwrap();
+        assert_eq!(prod.cors.to_string(), "https://admin.example.com");
+
+        assert!(load(&[("APP_ENV", "prod"), ("APP_DEV_ENDPOINTS", "true")]).is_err());
+        assert!(load(&[("APP_ENV", "prod"), ("APP_ERROR_DETAILS", "true")]).is_err());
+        assert!(load(&[("APP_ENV", "staging"), ("APP_DEV_ENDPOINTS", "true")]).is_ok());
+        assert!(load(&[("APP_ENV", "qa")]).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! Read-your-writes consistency tokens
+//!
+//! Every successful write returns an `X-Consistency-Token` naming the
+//! storage version that includes it. A client sending the token back on a
+//! later `GET` is guaranteed a response at least that fresh: responses
+//! cached at an older version are bypassed, and if the storage has not
+//! reached the version yet the request fails with `503 Service Unavailable`
+//! and a `Retry-After` header instead of returning stale data.
+//!
+//! With the single in-memory store the version is always reached; the
+//! check is what lets replicas or caches placed behind the API keep the
+//! guarantee. Tokens from another instance (a different storage epoch)
+//! describe data that no longer exists and are ignored.
+
+use std::str::FromStr;
+
+use axum::{
+    extract::{Request, State},
+    http::{header::RETRY_AFTER, HeaderName, HeaderValue, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::error::ApiError;
+use crate::i18n::Message;
+use crate::AppState;
+
+/// Header carrying a consistency token in both directions
+pub const X_CONSISTENCY_TOKEN: HeaderName = HeaderName::from_static("x-consistency-token");
+
+/// A storage version, as returned by [`crate::models::Storage::version`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsistencyToken {
+    epoch: u64,
+    version: u64,
+}
+
+impl From<(u64, u64)> for ConsistencyToken {
+    fn from((epoch, version): (u64, u64)) -> Self {
+        Self { epoch, version }
+    }
+}
+
+impl ConsistencyToken {
+    /// Returns `true` if data at this version includes every write
+    /// `required` was issued for
+    pub fn includes(&self, required: &ConsistencyToken) -> bool {
+        self.epoch != required.epoch || self.version >= required.version
+    }
+}
+
+impl std::fmt::Display for ConsistencyToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:x}-{:x}", self.epoch, self.version)
+    }
+}
+
+impl FromStr for ConsistencyToken {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{}' is not a consistency token", s);
+        let (epoch, version) = s.trim().split_once('-').ok_or_else(invalid)?;
+        Ok(Self {
+            epoch: u64::from_str_radix(epoch, 16).map_err(|_| invalid())?,
+            version: u64::from_str_radix(version, 16).map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Middleware issuing tokens on writes and enforcing them on reads
+///
+/// A token accepted on a read is left in the request extensions for
+/// [`crate::cache::respond`].
+pub async fn track(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let reads = matches!(*request.method(), Method::GET | Method::HEAD);
+    let header = request
+        .headers()
+        .get(X_CONSISTENCY_TOKEN)
+        .filter(|_| reads)
+        .map(|value| value.to_str().ok().and_then(|value| value.parse().ok()));
+
+    if let Some(token) = header {
+        let Some(token) = token else {
+            return ApiError::BadRequest(Message::new("consistency.invalid_token")).into_response();
+        };
+        let current = ConsistencyToken::from(state.storage.read().await.version());
+        if !current.includes(&token) {
+            let seconds = state.config.retry_after.as_secs().max(1);
+            let error = ApiError::ServiceUnavailable(Message::new("consistency.not_reached"));
+            return ([(RETRY_AFTER, HeaderValue::from(seconds))], error).into_response();
+        }
+        request.extensions_mut().insert(token);
+    }
+
+    let mut response = next.run(request).await;
+    if !reads && response.status().is_success() {
+        let token = ConsistencyToken::from(state.storage.read().await.version());
+        if let Ok(value) = HeaderValue::from_str(&token.to_string()) {
+            response.headers_mut().insert(X_CONSISTENCY_TOKEN, value);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let token = ConsistencyToken::from((0xabc, 42));
+        assert_eq!(token.to_string(), "abc-2a");
+        assert_eq!("abc-2a".parse::<ConsistencyToken>(), Ok(token));
+        assert!("abc".parse::<ConsistencyToken>().is_err());
+        assert!("abc-xyz".parse::<ConsistencyToken>().is_err());
+    }
+
+    #[test]
+    fn test_includes() {
+        let required = ConsistencyToken::from((1, 5));
+        assert!(ConsistencyToken::from((1, 5)).includes(&required));
+        assert!(ConsistencyToken::from((1, 6)).includes(&required));
+        assert!(!ConsistencyToken::from((1, 4)).includes(&required));
+        // Tokens from another instance cannot be waited for
+        assert!(ConsistencyToken::from((2, 0)).includes(&required));
+    }
+}
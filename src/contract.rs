@@ -0,0 +1,307 @@
+//! Response contract checks against the OpenAPI document
+//!
+//! [`Contract`] validates an actual response — status and JSON body — against
+//! what [`crate::openapi`] documents for the request's method and path.
+//! The integration tests run every handler through it, so a serialization
+//! change that is not reflected in the schema fails the build.
+//!
+//! Object schemas are checked strictly: a response field the document does
+//! not mention is a violation, just like a missing or mistyped one.
+
+use axum::{
+    http::{Method, StatusCode},
+    response::Response,
+};
+use serde_json::Value;
+
+use crate::openapi;
+
+/// A way in which a response departs from the documented contract
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContractError {
+    /// No operation is documented for the method and path
+    UndocumentedOperation { method: Method, path: String },
+    /// The operation does not document the response status
+    UndocumentedStatus {
+        method: Method,
+        path: String,
+        status: StatusCode,
+    },
+    /// The body is not valid JSON although the response documents a schema
+    InvalidJson { path: String, reason: String },
+    /// The body does not match the documented schema
+    Violations { path: String, errors: Vec<String> },
+}
+
+impl std::fmt::Display for ContractError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContractError::UndocumentedOperation { method, path } => {
+                write!(f, "{} {} is not documented", method, path)
+            }
+            ContractError::UndocumentedStatus {
+                method,
+                path,
+                status,
+            } => write!(f, "{} {} does not document status {}", method, path, status),
+            ContractError::InvalidJson { path, reason } => {
+                write!(f, "response for {} is not JSON: {}", path, reason)
+            }
+            ContractError::Violations { path, errors } => {
+                write!(
+                    f,
+                    "response for {} violates its schema: {}",
+                    path,
+                    errors.join("; ")
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ContractError {}
+
+/// The documented contract of every operation
+#[derive(Debug, Clone)]
+pub struct Contract {
+    spec: Value,
+}
+
+impl Default for Contract {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Contract {
+    /// Loads the contract from the generated OpenAPI document
+    pub fn new() -> Self {
+        Self::from_spec(openapi::spec())
+    }
+
+    /// Loads the contract from an OpenAPI 3.1 document
+    pub fn from_spec(mut spec: Value) -> Self {
+        if let Some(schemas) = spec.pointer_mut("/components/schemas") {
+            forbid_undocumented_fields(schemas);
+        }
+        Self { spec }
+    }
+
+    /// Checks a response against the documented contract
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The request method
+    /// * `path` - The concrete request path, such as `/api/v1/users/<id>`
+    /// * `status` - The response status
+    /// * `body` - The raw response body
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the response is documented and its body matches
+    /// the schema, or the first [`ContractError`] found
+    pub fn check(
+        &self,
+        method: &Method,
+        path: &str,
+        status: StatusCode,
+        body: &[u8],
+    ) -> Result<(), ContractError> {
+        let operation =
+            self.operation(method, path)
+                .ok_or_else(|| ContractError::UndocumentedOperation {
+                    method: method.clone(),
+                    path: path.to_string(),
+                })?;
+
+        let response = operation
+            .get("responses")
+            .and_then(|responses| responses.get(status.as_str()))
+            .ok_or_else(|| ContractError::UndocumentedStatus {
+                method: method.clone(),
+                path: path.to_string(),
+                status,
+            })?;
+
+        let Some(schema) = response.pointer("/content/application~1json/schema") else {
+            return Ok(());
+        };
+
+        let instance: Value =
+            serde_json::from_slice(body).map_err(|err| ContractError::InvalidJson {
+                path: path.to_string(),
+                reason: err.to_string(),
+            })?;
+
+        // Resolve `#/components/...` references against the whole document
+        let mut root = schema.clone();
+        if let (Value::Object(root), Some(components)) = (&mut root, self.spec.get("components")) {
+            root.insert("components".to_string(), components.clone());
+        }
+
+        let validator =
+            jsonschema::draft202012::new(&root).map_err(|err| ContractError::Violations {
+                path: path.to_string(),
+                errors: vec![format!("unusable schema: {}", err)],
+            })?;
+
+        let errors: Vec<String> = validator
+            .iter_errors(&instance)
+            .map(|err| format!("{} at '{}'", err, err.instance_path()))
+            .collect();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ContractError::Violations {
+                path: path.to_string(),
+                errors,
+            })
+        }
+    }
+
+    /// Reads a response and checks it against the documented contract
+    ///
+    /// Returns the body so callers can make further assertions.
+    pub async fn check_response(
+        &self,
+        method: &Method,
+        path: &str,
+        response: Response,
+    ) -> Result<(StatusCode, Vec<u8>), ContractError> {
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .map(|bytes| bytes.to_vec())
+            .unwrap_or_default();
+
+        self.check(method, path, status, &body)?;
+        Ok((status, body))
+    }
+
+    /// Finds the documented operation whose path template matches `path`
+    fn operation(&self, method: &Method, path: &str) -> Option<&Value> {
+        let paths = self.spec.get("paths")?.as_object()?;
+        let method = method.as_str().to_ascii_lowercase();
+
+        paths
+            .iter()
+            .filter(|(template, _)| matches_template(template, path))
+            // Prefer literal segments over parameters, e.g. `/users/search`
+            // over `/users/{id}`
+            .min_by_key(|(template, _)| template.matches('{').count())
+            .and_then(|(_, item)| item.get(&method))
+    }
+}
+
+/// Returns whether a concrete path matches an OpenAPI path template
+fn matches_template(template: &str, path: &str) -> bool {
+    let mut template = template.split('/');
+    let mut path = path.split('?').next().unwrap_or_default().split('/');
+
+    loop {
+        match (template.next(), path.next()) {
+            (None, None) => return true,
+            (Some(expected), Some(actual)) => {
+                let is_param = expected.starts_with('{') && expected.ends_with('}');
+                if !(is_param && !actual.is_empty() || expected == actual) {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// Marks every object schema with properties as closed to other fields
+fn forbid_undocumented_fields(schema: &mut Value) {
+    match schema {
+        Value::Object(map) => {
+            if map.contains_key("properties") && !map.contains_key("additionalProperties") {
+                map.insert("additionalProperties".to_string(), Value::Bool(false));
+            }
+            map.values_mut().for_each(forbid_undocumented_fields);
+        }
+        Value::Array(items) => items.iter_mut().for_each(forbid_undocumented_fields),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn error_body(status: u16) -> Vec<u8> {
+        json!({ "error": { "message": "nope", "status": status, "code": "not_found", "retryable": false } })
+            .to_string()
+            .into_bytes()
+    }
+
+    #[test]
+    fn test_matches_template() {
+        assert!(matches_template("/api/v1/users/{id}", "/api/v1/users/abc"));
+        assert!(matches_template(
+            "/api/v1/users",
+            "/api/v1/users?status=active"
+        ));
+        assert!(!matches_template("/api/v1/users/{id}", "/api/v1/users/"));
+        assert!(!matches_template("/api/v1/users/{id}", "/api/v1/users/a/b"));
+        assert!(!matches_template("/", "/api"));
+    }
+
+    #[test]
+    fn test_check_accepts_documented_responses() {
+        let contract = Contract::new();
+        let path = "/api/v1/users/not-found";
+
+        assert_eq!(
+            contract.check(&Method::GET, path, StatusCode::NOT_FOUND, &error_body(404)),
+            Ok(())
+        );
+        assert_eq!(
+            contract.check(&Method::DELETE, path, StatusCode::NO_CONTENT, b""),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_check_rejects_drift() {
+        let contract = Contract::new();
+        let path = "/api/v1/users/not-found";
+
+        assert!(matches!(
+            contract.check(&Method::PATCH, path, StatusCode::OK, b"{}"),
+            Err(ContractError::UndocumentedOperation { .. })
+        ));
+        assert!(matches!(
+            contract.check(&Method::GET, path, StatusCode::IM_A_TEAPOT, b"{}"),
+            Err(ContractError::UndocumentedStatus { .. })
+        ));
+
+        // Renamed and extra fields are both violations
+        let renamed = json!({ "error": { "msg": "nope", "status": 404, "code": "not_found", "retryable": false } })
+            .to_string();
+        assert!(matches!(
+            contract.check(
+                &Method::GET,
+                path,
+                StatusCode::NOT_FOUND,
+                renamed.as_bytes()
+            ),
+            Err(ContractError::Violations { .. })
+        ));
+        let extra = json!({
+            "error": { "message": "nope", "status": 404, "code": "not_found", "retryable": false, "id": 1 }
+        });
+        assert!(matches!(
+            contract.check(
+                &Method::GET,
+                path,
+                StatusCode::NOT_FOUND,
+                extra.to_string().as_bytes()
+            ),
+            Err(ContractError::Violations { .. })
+        ));
+    }
+}
@@ -0,0 +1,149 @@
+//! Cross-origin requests
+//!
+//! Browsers only let pages of other origins call the API if the responses
+//! allow it. `APP_CORS_ORIGINS` lists the origins allowed, or is `*` to
+//! allow every origin. Unset, it follows the profile: the `dev` profile
+//! allows every origin, so local front ends work out of the box, while
+//! `staging` and `prod` allow none. Responses to listed origins expose the
+//! `Content-Range`, `Link` and `X-Total-Count` headers of paged lists.
+//!
+//! Only `OPTIONS` requests carrying `Access-Control-Request-Method` are
+//! answered as preflights; other `OPTIONS` requests reach the router, which
+//! answers them with the route's `Allow` header.
+
+use std::str::FromStr;
+
+use axum::{
+    extract::{Request, State},
+    http::{
+        header::{ACCESS_CONTROL_REQUEST_METHOD, CONTENT_RANGE, LINK},
+        HeaderValue, Method,
+    },
+    middleware::Next,
+    response::Response,
+};
+use tower::{Layer, ServiceExt};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+use crate::paging::X_TOTAL_COUNT;
+
+/// An origin allowed to call the API, e.g. `https://admin.example.com`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin(HeaderValue);
+
+impl FromStr for Origin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let origin = s.trim().trim_end_matches('/');
+        let host = origin
+            .strip_prefix("http://")
+            .or_else(|| origin.strip_prefix("https://"))
+            .ok_or_else(|| format!("'{}' is not an http:// or https:// origin", s))?;
+        if host.is_empty() || host.contains('/') {
+            return Err(format!("'{}' must be an origin without a path", s));
+        }
+        HeaderValue::from_str(origin)
+            .map(Origin)
+            .map_err(|_| format!("'{}' is not a valid origin", s))
+    }
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0.to_str().unwrap_or_default())
+    }
+}
+
+/// Origins allowed to make cross-origin requests
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Settings {
+    /// Every origin, with any method and header
+    #[default]
+    Permissive,
+    /// Only these origins; none when empty
+    Origins(Vec<Origin>),
+}
+
+impl FromStr for Settings {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == "*" {
+            return Ok(Settings::Permissive);
+        }
+        s.split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Settings::Origins)
+    }
+}
+
+impl std::fmt::Display for Settings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Settings::Permissive => f.write_str("any origin"),
+            Settings::Origins(origins) if origins.is_empty() => f.write_str("no origin"),
+            Settings::Origins(origins) => {
+                let origins: Vec<String> = origins.iter().map(Origin::to_string).collect();
+                f.write_str(&origins.join(", "))
+            }
+        }
+    }
+}
+
+impl Settings {
+    /// Returns the layer answering preflight requests and adding the CORS
+    /// headers
+    pub fn layer(&self) -> CorsLayer {
+        match self {
+            Settings::Permissive => CorsLayer::permissive(),
+            Settings::Origins(origins) => CorsLayer::new()
+                .allow_origin(AllowOrigin::list(
+                    origins.iter().map(|origin| origin.0.clone()),
+                ))
+                .allow_methods(Any)
+                .allow_headers(Any)
+                .expose_headers([CONTENT_RANGE, LINK, X_TOTAL_COUNT]),
+        }
+    }
+}
+
+/// Middleware applying `cors` to every request but plain `OPTIONS` ones
+///
+/// [`CorsLayer`] takes any `OPTIONS` request for a preflight.
+pub async fn apply(State(cors): State<CorsLayer>, request: Request, next: Next) -> Response {
+    if request.method() == Method::OPTIONS
+        && !request
+            .headers()
+            .contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+    {
+        return next.run(request).await;
+    }
+    match cors.layer(next).oneshot(request).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_settings() {
+        assert_eq!("*".parse(), Ok(Settings::Permissive));
+        assert_eq!("".parse(), Ok(Settings::Origins(Vec::new())));
+        let settings: Settings = "https://admin.example.com/, http://localhost:5173"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            settings.to_string(),
+            "https://admin.example.com, http://localhost:5173"
+        );
+        assert!("admin.example.com".parse::<Settings>().is_err());
+        assert!("https://example.com/admin".parse::<Settings>().is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! Embedded admin dashboard
+//!
+//! Built with the `dashboard` feature, the server serves a small
+//! single-page UI at `/admin`, embedded in the binary from the
+//! `dashboard/` directory. It shows user counts, health checks, the state
+//! of outbound deliveries and recent audit entries.
+//!
+//! The page itself holds no data: browsers cannot attach a bearer token
+//! to a navigation, so the assets are served without authentication and
+//! the page reads everything from the admin API with the token the
+//! operator enters, which needs the `admin` scope. Like the other operator
+//! endpoints, the dashboard is only served when `APP_ADMIN_ENDPOINTS` is
+//! enabled.
+
+use axum::{
+    extract::State,
+    http::{
+        header::{
+            CACHE_CONTROL, CONTENT_SECURITY_POLICY, CONTENT_TYPE, ETAG, IF_NONE_MATCH,
+            X_CONTENT_TYPE_OPTIONS,
+        },
+        HeaderMap, HeaderValue, StatusCode, Uri,
+    },
+    response::{IntoResponse, Response},
+};
+use rust_embed::RustEmbed;
+
+use crate::{etag, handlers, AppState};
+
+/// Path the dashboard is served under
+pub const PATH: &str = "/admin";
+
+/// Asset served for [`PATH`] itself
+const INDEX: &str = "index.html";
+
+/// Scripts, styles and API calls only go to this server, and the page
+/// cannot be framed
+const POLICY: &str = "default-src 'self'; frame-ancestors 'none'";
+
+#[derive(RustEmbed)]
+#[folder = "dashboard/"]
+struct Assets;
+
+/// Serves the dashboard's assets
+///
+/// Answers like an unknown path when `APP_ADMIN_ENDPOINTS` is disabled or
+/// the asset does not exist. Assets carry an `ETag` and are revalidated on
+/// every load, so a new release is picked up immediately.
+pub async fn serve(State(state): State<AppState>, uri: Uri, headers: HeaderMap) -> Response {
+    if !state.config.admin_endpoints {
+        return handlers::not_found(uri).await.into_response();
+    }
+    let name = uri
+        .path()
+        .strip_prefix(PATH)
+        .unwrap_or_default()
+        .trim_start_matches('/');
+    let name = if name.is_empty() { INDEX } else { name };
+    let Some(asset) = Assets::get(name) else {
+        return handlers::not_found(uri).await.into_response();
+    };
+
+    let tag = format!("\"{}\"", hex::encode(asset.metadata.sha256_hash()));
+    let tag = HeaderValue::from_str(&tag).unwrap_or_else(|_| HeaderValue::from_static("\"\""));
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    response_headers.insert(ETAG, tag.clone());
+    if headers
+        .get(IF_NONE_MATCH)
+        .is_some_and(|condition| etag::matches(condition, &tag))
+    {
+        return (StatusCode::NOT_MODIFIED, response_headers).into_response();
+    }
+    response_headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type(name)));
+    response_headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    response_headers.insert(CONTENT_SECURITY_POLICY, HeaderValue::from_static(POLICY));
+    (response_headers, asset.data).into_response()
+}
+
+/// Returns the media type of an asset, from its extension
+fn content_type(name: &str) -> &'static str {
+    match name.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(content_type("index.html"), "text/html; charset=utf-8");
+        assert_eq!(content_type("app.js"), "text/javascript; charset=utf-8");
+        assert_eq!(content_type("style.css"), "text/css; charset=utf-8");
+        assert_eq!(content_type("LICENSE"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_index_references_embedded_assets() {
+        let index = Assets::get(INDEX).unwrap();
+        let index = std::str::from_utf8(&index.data).unwrap();
+        let referenced: Vec<&str> = index
+            .split('"')
+            .filter_map(|attribute| attribute.strip_prefix("/admin/"))
+            .collect();
+        assert_eq!(referenced, ["style.css", "app.js"]);
+        for name in referenced {
+            assert!(Assets::get(name).is_some(), "{} is not embedded", name);
+        }
+    }
+}
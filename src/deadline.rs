@@ -0,0 +1,163 @@
+//! Request deadlines
+//!
+//! A client tells how long it is willing to wait with
+//! [`X_REQUEST_TIMEOUT`], in milliseconds or with an `ms` or `s` unit
+//! (`1500`, `250ms`, `2s`). The server caps it at `APP_REQUEST_TIMEOUT_MS`,
+//! and route groups can tighten it further with
+//! [`RouteGroup::deadline`](crate::routes::RouteGroup::deadline).
+//!
+//! The deadline is kept in a task-local for the rest of the request. Once
+//! it passes, the handler is no longer polled and the request is answered
+//! with `504` and `deadline_exceeded`: whatever the handler was waiting
+//! on, such as the storage lock or a peer, is dropped instead of being
+//! finished for a client that gave up. Storage writes run without an await
+//! point once the lock is held, so they are never cut in half.
+//!
+//! Outbound calls made for the request bound their own timeouts with
+//! [`bound`], and forwarded requests carry what is left in
+//! [`X_REQUEST_TIMEOUT`], so peers give up at the same time.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::HeaderName,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::time::Instant;
+
+use crate::error::ApiError;
+use crate::i18n::Message;
+use crate::AppState;
+
+/// Request header giving how long the client waits for an answer
+pub const X_REQUEST_TIMEOUT: HeaderName = HeaderName::from_static("x-request-timeout");
+
+tokio::task_local! {
+    static DEADLINE: Instant;
+}
+
+/// Returns when the current request has to be answered by, if it has a
+/// deadline
+pub fn current() -> Option<Instant> {
+    DEADLINE.try_with(|deadline| *deadline).ok()
+}
+
+/// Returns how much time the current request has left, if it has a
+/// deadline
+pub fn remaining() -> Option<Duration> {
+    current().map(|deadline| deadline.saturating_duration_since(Instant::now()))
+}
+
+/// Shortens `timeout` to what is left of the current request's deadline
+pub fn bound(timeout: Duration) -> Duration {
+    remaining().map_or(timeout, |remaining| remaining.min(timeout))
+}
+
+/// Parses a timeout given as milliseconds, optionally with an `ms` or `s`
+/// unit
+fn parse(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (number, unit) = match value.strip_suffix("ms") {
+        Some(number) => (number, Duration::from_millis(1)),
+        None => match value.strip_suffix('s') {
+            Some(number) => (number, Duration::from_secs(1)),
+            None => (value, Duration::from_millis(1)),
+        },
+    };
+    let count: u32 = number.trim().parse().ok()?;
+    Some(unit * count).filter(|timeout| !timeout.is_zero())
+}
+
+/// Returns the timeout `request` asks for in [`X_REQUEST_TIMEOUT`]
+fn requested(request: &Request) -> Result<Option<Duration>, ApiError> {
+    let Some(value) = request.headers().get(X_REQUEST_TIMEOUT) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(parse)
+        .map(Some)
+        .ok_or_else(|| {
+            ApiError::BadRequest(
+                Message::new("deadline.invalid")
+                    .with("value", String::from_utf8_lossy(value.as_bytes())),
+            )
+        })
+}
+
+/// Runs the rest of the request until `deadline`, answering `504` if it
+/// is not done by then
+async fn run_until(deadline: Instant, request: Request, next: Next) -> Response {
+    let path = request.uri().path().to_string();
+    let running = DEADLINE.scope(deadline, next.run(request));
+    match tokio::time::timeout_at(deadline, running).await {
+        Ok(response) => response,
+        Err(_) => {
+            tracing::warn!(path = %path, "request abandoned at its deadline");
+            ApiError::GatewayTimeout(Message::new("deadline.exceeded")).into_response()
+        }
+    }
+}
+
+/// Middleware giving requests the deadline they ask for, capped at
+/// `APP_REQUEST_TIMEOUT_MS`
+pub async fn enforce(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let requested = match requested(&request) {
+        Ok(requested) => requested,
+        Err(err) => return err.into_response(),
+    };
+    let timeout = match (requested, state.config.request_timeout) {
+        (Some(requested), Some(max)) => Some(requested.min(max)),
+        (requested, max) => requested.or(max),
+    };
+    match timeout {
+        Some(timeout) => run_until(Instant::now() + timeout, request, next).await,
+        None => next.run(request).await,
+    }
+}
+
+/// Middleware answering the requests of a route group within `limit`, or
+/// earlier if their deadline is sooner
+pub async fn limit(State(limit): State<Duration>, request: Request, next: Next) -> Response {
+    let deadline = Instant::now() + limit;
+    let deadline = current().map_or(deadline, |current| current.min(deadline));
+    run_until(deadline, request, next).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("1500"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse(" 2s "), Some(Duration::from_secs(2)));
+        assert_eq!(parse("0"), None);
+        assert_eq!(parse("-1"), None);
+        assert_eq!(parse("2m"), None);
+        assert_eq!(parse("soon"), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bound_by_remaining_time() {
+        assert_eq!(remaining(), None);
+        assert_eq!(bound(Duration::from_secs(10)), Duration::from_secs(10));
+
+        let deadline = Instant::now() + Duration::from_secs(3);
+        DEADLINE
+            .scope(deadline, async {
+                assert_eq!(bound(Duration::from_secs(10)), Duration::from_secs(3));
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                assert_eq!(remaining(), Some(Duration::from_secs(1)));
+                assert_eq!(
+                    bound(Duration::from_millis(500)),
+                    Duration::from_millis(500)
+                );
+            })
+            .await;
+    }
+}
@@ -0,0 +1,232 @@
+//! Announcing deprecated routes
+//!
+//! Routes are marked deprecated in the [`Deprecations`] registry in
+//! [`AppState`], usually through
+//! [`RouterBuilder::deprecate`](crate::routes::RouterBuilder::deprecate).
+//! Every response of a deprecated route then carries:
+//!
+//! - `Deprecation` (RFC 9745), when the route was deprecated
+//! - `Sunset` (RFC 8594), when it stops being served
+//! - `Link` with `rel="successor-version"`, pointing at its replacement,
+//!   if there is one
+//!
+//! Requests to deprecated routes are counted per route in
+//! `http_deprecated_requests_total` at `GET /metrics`, so operators can see
+//! who still calls a route before it is removed.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::metrics::Sample;
+use crate::AppState;
+
+/// The `Deprecation` response header
+pub const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+/// The `Sunset` response header
+pub const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// When a route was deprecated, when it goes away and what replaces it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Deprecation {
+    /// When the route was deprecated
+    #[serde(with = "crate::timestamps")]
+    #[schema(schema_with = crate::timestamps::schema)]
+    pub since: DateTime<Utc>,
+    /// When the route stops being served
+    #[serde(with = "crate::timestamps")]
+    #[schema(schema_with = crate::timestamps::schema)]
+    pub sunset: DateTime<Utc>,
+    /// Link to the route replacing it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub successor: Option<String>,
+}
+
+impl Deprecation {
+    /// Deprecates a route from now on, until `sunset`
+    pub fn new(sunset: DateTime<Utc>) -> Self {
+        Self {
+            since: Utc::now(),
+            sunset,
+            successor: None,
+        }
+    }
+
+    /// Sets when the route was deprecated
+    pub fn since(mut self, since: DateTime<Utc>) -> Self {
+        self.since = since;
+        self
+    }
+
+    /// Names the route replacing it, as a path or URL
+    pub fn successor(mut self, link: impl Into<String>) -> Self {
+        self.successor = Some(link.into());
+        self
+    }
+
+    /// Adds the headers announcing the deprecation to `headers`
+    pub fn announce(&self, headers: &mut HeaderMap) {
+        let since = format!("@{}", self.since.timestamp());
+        let sunset = self.sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        let link = self
+            .successor
+            .as_ref()
+            .map(|link| format!("<{}>; rel=\"successor-version\"", link));
+        for (name, value) in [
+            (DEPRECATION, Some(since)),
+            (SUNSET, Some(sunset)),
+            (header::LINK, link),
+        ] {
+            if let Some(Ok(value)) = value.map(HeaderValue::try_from) {
+                headers.append(name, value);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    deprecation: Deprecation,
+    requests: AtomicU64,
+}
+
+/// Deprecated routes, by method and route pattern
+#[derive(Debug, Default)]
+pub struct Deprecations {
+    routes: Mutex<BTreeMap<String, Entry>>,
+}
+
+impl Deprecations {
+    /// Marks `method path` deprecated, replacing an earlier deprecation of
+    /// the same route
+    ///
+    /// `path` is the pattern the route was added with, such as
+    /// `/api/v1/users/:id`.
+    pub fn deprecate(&self, method: Method, path: &str, deprecation: Deprecation) {
+        self.lock().insert(
+            route(&method, path),
+            Entry {
+                deprecation,
+                requests: AtomicU64::new(0),
+            },
+        );
+    }
+
+    /// Returns the deprecation of `method path`, if it is deprecated
+    pub fn get(&self, method: &Method, path: &str) -> Option<Deprecation> {
+        let routes = self.lock();
+        routes
+            .get(&route(method, path))
+            .map(|entry| entry.deprecation.clone())
+    }
+
+    /// Returns all deprecated routes, as `METHOD /pattern`
+    pub fn list(&self) -> Vec<(String, Deprecation)> {
+        self.lock()
+            .iter()
+            .map(|(route, entry)| (route.clone(), entry.deprecation.clone()))
+            .collect()
+    }
+
+    /// Counts a request to `method path` and returns its deprecation, if
+    /// it is deprecated
+    fn used(&self, method: &Method, path: &str) -> Option<Deprecation> {
+        let routes = self.lock();
+        let entry = routes.get(&route(method, path))?;
+        entry.requests.fetch_add(1, Ordering::Relaxed);
+        Some(entry.deprecation.clone())
+    }
+
+    /// Returns the request counts of the deprecated routes
+    pub fn samples(&self) -> Vec<Sample> {
+        self.lock()
+            .iter()
+            .map(|(route, entry)| {
+                Sample::counter(
+                    "http_deprecated_requests_total",
+                    "Requests to deprecated routes",
+                    entry.requests.load(Ordering::Relaxed),
+                )
+                .label("route", route.clone())
+            })
+            .collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, Entry>> {
+        self.routes
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn route(method: &Method, path: &str) -> String {
+    format!("{} {}", method, path)
+}
+
+/// Middleware adding the deprecation headers to responses of deprecated
+/// routes and counting their requests
+pub async fn announce(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let deprecation = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|path| state.deprecations.used(request.method(), path.as_str()));
+    let mut response = next.run(request).await;
+    if let Some(deprecation) = deprecation {
+        deprecation.announce(response.headers_mut());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_announce() {
+        let deprecation = Deprecation::new(Utc.with_ymd_and_hms(2027, 1, 31, 12, 0, 0).unwrap())
+            .since(Utc.with_ymd_and_hms(2026, 7, 1, 0, 0, 0).unwrap())
+            .successor("/api/v2/users");
+        let mut headers = HeaderMap::new();
+        deprecation.announce(&mut headers);
+        assert_eq!(headers[DEPRECATION], "@1782864000");
+        assert_eq!(headers[SUNSET], "Sun, 31 Jan 2027 12:00:00 GMT");
+        assert_eq!(
+            headers[header::LINK],
+            "</api/v2/users>; rel=\"successor-version\""
+        );
+
+        let mut headers = HeaderMap::new();
+        Deprecation::new(Utc::now()).announce(&mut headers);
+        assert!(!headers.contains_key(header::LINK));
+    }
+
+    #[test]
+    fn test_registry_counts_requests() {
+        let deprecations = Deprecations::default();
+        let deprecation = Deprecation::new(Utc::now());
+        deprecations.deprecate(Method::GET, "/old/:id", deprecation.clone());
+
+        assert_eq!(
+            deprecations.used(&Method::GET, "/old/:id"),
+            Some(deprecation)
+        );
+        assert_eq!(deprecations.used(&Method::POST, "/old/:id"), None);
+        assert_eq!(deprecations.get(&Method::GET, "/other"), None);
+
+        let samples = deprecations.samples();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].value, 1.0);
+        assert_eq!(samples[0].labels, [("route", "GET /old/:id".to_string())]);
+    }
+}
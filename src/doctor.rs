@@ -0,0 +1,407 @@
+//! Deploy-time sanity checks
+//!
+//! `rust-api doctor` checks what would keep the server from starting or
+//! serving, without starting it:
+//!
+//! - `config`: the `APP_` variables parse, and name no feature the binary
+//!   was built without
+//! - `storage`: the snapshot directory is writable, and the shard peers and
+//!   the replication leader accept connections
+//! - `port`: the address the server binds is free
+//! - `tls`: the certificate loads and is not about to expire
+//! - `migrations`: the latest snapshot passes the validation
+//!   `migrate-data` and restores apply, under the current rules
+//!
+//! The report is printed for humans, or as JSON with `--json`; the command
+//! fails if any check does.
+
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::proxy::Peer;
+use crate::{migrate, snapshot, Config};
+
+/// How long a peer has to accept a connection
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Days before its expiry from which the TLS certificate is warned about
+pub const CERT_WARNING_DAYS: i64 = 30;
+
+/// Outcome of a check, ordered from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Not applicable to this configuration
+    Skipped,
+    /// Nothing wrong
+    Ok,
+    /// Works, but needs attention soon
+    Warn,
+    /// Keeps the server from starting or serving
+    Fail,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Status::Skipped => "SKIP",
+            Status::Ok => "OK",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        })
+    }
+}
+
+/// Result of one check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Check {
+    /// What was checked
+    pub name: String,
+    /// How it went
+    pub status: Status,
+    /// What was found
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &str, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Results of all checks, in the order they ran
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Report {
+    /// The worst status of all checks
+    pub status: Status,
+    /// Every check
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Returns `true` unless a check failed
+    pub fn passed(&self) -> bool {
+        self.status < Status::Fail
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for check in &self.checks {
+            let status = format!("[{}]", check.status);
+            writeln!(f, "{:<6} {:<10} {}", status, check.name, check.detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs every check against the configuration read through `lookup`, for
+/// a server binding `addr`
+///
+/// The checks needing a configuration are skipped if it does not load.
+pub async fn run<F>(lookup: F, addr: SocketAddr) -> Report
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let config = Config::from_lookup(lookup);
+    let mut checks = vec![match &config {
+        Ok(config) => match crate::server::check(config) {
+            Ok(()) => Check::new("config", Status::Ok, "configuration is valid"),
+            Err(err) => Check::new("config", Status::Fail, err.to_string()),
+        },
+        Err(err) => Check::new("config", Status::Fail, err.to_string()),
+    }];
+    match &config {
+        Ok(config) => {
+            checks.push(storage(config).await);
+            checks.push(port(addr).await);
+            checks.push(tls(config));
+            checks.push(migrations(config).await);
+        }
+        Err(_) => {
+            for name in ["storage", "port", "tls", "migrations"] {
+                checks.push(Check::new(
+                    name,
+                    Status::Skipped,
+                    "configuration is invalid",
+                ));
+            }
+        }
+    }
+    Report {
+        status: checks
+            .iter()
+            .map(|check| check.status)
+            .max()
+            .unwrap_or(Status::Ok),
+        checks,
+    }
+}
+
+async fn storage(config: &Config) -> Check {
+    let mut problems = Vec::new();
+    if let Some(dir) = &config.snapshot_dir {
+        if let Err(err) = writable(dir).await {
+            problems.push(format!("APP_SNAPSHOT_DIR {}: {}", dir.display(), err));
+        }
+    }
+    let peers = config
+        .shard
+        .peers
+        .iter()
+        .filter(|peer| Some(*peer) != config.shard.this.as_ref())
+        .chain(&config.replication.leader);
+    for peer in peers {
+        if let Err(err) = connect(peer).await {
+            problems.push(format!("{}: {}", peer, err));
+        }
+    }
+    if problems.is_empty() {
+        Check::new(
+            "storage",
+            Status::Ok,
+            "in-memory store; all locations reachable",
+        )
+    } else {
+        Check::new("storage", Status::Fail, problems.join("; "))
+    }
+}
+
+/// Creates `dir` if needed and writes and removes a file in it
+async fn writable(dir: &Path) -> std::io::Result<()> {
+    tokio::fs::create_dir_all(dir).await?;
+    let probe = dir.join(".doctor");
+    tokio::fs::write(&probe, b"").await?;
+    tokio::fs::remove_file(&probe).await
+}
+
+/// Opens a TCP connection to the host and port of `peer`
+async fn connect(peer: &Peer) -> std::io::Result<()> {
+    let (default_port, authority) = match peer.as_str().split_once("://") {
+        Some(("https", authority)) => (443, authority),
+        Some((_, authority)) => (80, authority),
+        None => (80, peer.as_str()),
+    };
+    let has_port = authority
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+    let address = match has_port {
+        true => authority.to_string(),
+        false => format!("{}:{}", authority, default_port),
+    };
+    match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(address)).await {
+        Ok(connected) => connected.map(drop),
+        Err(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "connection timed out",
+        )),
+    }
+}
+
+async fn port(addr: SocketAddr) -> Check {
+    match TcpListener::bind(addr).await {
+        Ok(_) => Check::new("port", Status::Ok, format!("{} is free", addr)),
+        Err(err) => Check::new(
+            "port",
+            Status::Fail,
+            format!("cannot bind {}: {}", addr, err),
+        ),
+    }
+}
+
+#[cfg(feature = "tls")]
+fn tls(config: &Config) -> Check {
+    let Some(certificate) = &config.tls.certificate else {
+        return Check::new(
+            "tls",
+            Status::Skipped,
+            "TLS is not terminated by the server",
+        );
+    };
+    let expires_at = match crate::tls::certificate_expiry(certificate) {
+        Ok(expires_at) => expires_at,
+        Err(err) => return Check::new("tls", Status::Fail, err.to_string()),
+    };
+    let now = chrono::Utc::now();
+    if expires_at <= now {
+        let detail = format!("certificate expired at {}", expires_at);
+        return Check::new("tls", Status::Fail, detail);
+    }
+    let days = (expires_at - now).num_days();
+    let detail = format!("certificate expires at {} (in {} days)", expires_at, days);
+    if days < CERT_WARNING_DAYS {
+        Check::new("tls", Status::Warn, detail)
+    } else {
+        Check::new("tls", Status::Ok, detail)
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+fn tls(_config: &Config) -> Check {
+    // A configured certificate already failed the config check
+    Check::new("tls", Status::Skipped, "built without the `tls` feature")
+}
+
+async fn migrations(config: &Config) -> Check {
+    let Some(dir) = &config.snapshot_dir else {
+        return Check::new("migrations", Status::Skipped, "APP_SNAPSHOT_DIR is not set");
+    };
+    let latest = match latest_snapshot(dir).await {
+        Ok(Some(name)) => name,
+        Ok(None) => return Check::new("migrations", Status::Ok, "no snapshots to migrate"),
+        Err(err) => {
+            return Check::new(
+                "migrations",
+                Status::Fail,
+                format!("{}: {}", dir.display(), err),
+            )
+        }
+    };
+    let contents = match snapshot::read(dir, &latest).await {
+        Ok((_, contents)) => contents,
+        Err(err) => return Check::new("migrations", Status::Fail, format!("{}: {}", latest, err)),
+    };
+    let users = contents.users.len();
+    let problems = match migrate::load(contents) {
+        Ok(storage) => migrate::validate(&storage, &config.name_rules, |_| {}),
+        Err(problems) => problems,
+    };
+    match problems.first() {
+        None => Check::new(
+            "migrations",
+            Status::Ok,
+            format!("{} holds {} valid users", latest, users),
+        ),
+        Some(first) => Check::new(
+            "migrations",
+            Status::Fail,
+            format!(
+                "{} users of {} fail validation, first {}",
+                problems.len(),
+                latest,
+                first
+            ),
+        ),
+    }
+}
+
+/// Returns the name of the newest snapshot in `dir`
+///
+/// Snapshot names embed the time they were taken, so the newest sorts
+/// last.
+async fn latest_snapshot(dir: &Path) -> std::io::Result<Option<String>> {
+    if !tokio::fs::try_exists(dir).await? {
+        return Ok(None);
+    }
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    let mut latest: Option<String> = None;
+    while let Some(entry) = entries.next_entry().await? {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name.starts_with("snapshot-")
+            && name.ends_with(".json")
+            && latest.as_ref().map_or(true, |latest| name > *latest)
+        {
+            latest = Some(name);
+        }
+    }
+    Ok(latest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Snapshot, User};
+    use chrono::Utc;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn lookup(vars: &[(&str, String)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    fn free_addr() -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_invalid_config_skips_the_other_checks() {
+        let report = run(lookup(&[("APP_MAX_USERS", "many".into())]), free_addr()).await;
+        assert!(!report.passed());
+        assert_eq!(report.checks[0].status, Status::Fail);
+        assert!(report.checks[1..]
+            .iter()
+            .all(|check| check.status == Status::Skipped));
+    }
+
+    #[tokio::test]
+    async fn test_port_in_use_fails() {
+        let report = run(lookup(&[]), free_addr()).await;
+        assert!(report.passed(), "{}", report);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let report = run(lookup(&[]), listener.local_addr().unwrap()).await;
+        assert_eq!(report.status, Status::Fail);
+        let port = report.checks.iter().find(|check| check.name == "port");
+        assert_eq!(port.unwrap().status, Status::Fail);
+    }
+
+    #[tokio::test]
+    async fn test_latest_snapshot_is_validated() {
+        let dir = std::env::temp_dir().join(format!("doctor-{}", Uuid::new_v4()));
+        let now = Utc::now();
+        let snapshot = |users| Snapshot {
+            taken_at: now,
+            users,
+            trash: Vec::new(),
+            tombstones: Default::default(),
+            rate_limits: Default::default(),
+        };
+        let vars = [
+            ("APP_ADMIN_ENDPOINTS", "true".to_string()),
+            ("APP_SNAPSHOT_DIR", dir.display().to_string()),
+        ];
+        let migrations = |report: Report| report.checks.into_iter().nth(4).unwrap();
+
+        let check = migrations(run(lookup(&vars), free_addr()).await);
+        assert_eq!(check.status, Status::Ok);
+        let path = |name| snapshot::path(&dir, name).unwrap();
+        snapshot::save(
+            &path("snapshot-20260101T000000.000Z.json"),
+            &snapshot(vec![User::new("Jane Doe", "jane@example.com", now)]),
+        )
+        .await
+        .unwrap();
+        let check = migrations(run(lookup(&vars), free_addr()).await);
+        assert_eq!(check.status, Status::Ok, "{}", check.detail);
+
+        snapshot::save(
+            &path("snapshot-20260201T000000.000Z.json"),
+            &snapshot(vec![User::new("Jane Doe", "not-an-email", now)]),
+        )
+        .await
+        .unwrap();
+        let check = migrations(run(lookup(&vars), free_addr()).await);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(check.status, Status::Fail);
+        assert!(
+            check.detail.contains("snapshot-20260201"),
+            "{}",
+            check.detail
+        );
+    }
+}
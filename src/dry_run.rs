@@ -0,0 +1,152 @@
+//! Previewing writes without committing them
+//!
+//! A write sent with `?dry_run=true` or a `Prefer: dry-run` header (RFC
+//! 7240) goes through the same validation, conflict and capacity checks as
+//! a real one and answers with the status and body it would have, but
+//! nothing is stored, no event is published and no audit entry is
+//! written. Such responses carry `Preference-Applied: dry-run`.
+//!
+//! Routes opt in with the [`honor`] layer, which applies the preference in
+//! a task-local read by the handlers through [`is_requested`]. Routes that
+//! cannot preview their effects are wrapped in [`refuse`] instead, so a
+//! dry run is never carried out for real.
+
+use axum::{
+    extract::{Query, Request},
+    http::{header::HeaderName, HeaderValue, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::error::ApiError;
+use crate::i18n::Message;
+
+/// Response header naming the preferences a response honored
+pub const PREFERENCE_APPLIED: HeaderName = HeaderName::from_static("preference-applied");
+
+/// The preference token of a dry run, in `Prefer` and `Preference-Applied`
+pub const DRY_RUN: &str = "dry-run";
+
+tokio::task_local! {
+    static REQUESTED: bool;
+}
+
+/// Whether the current request is a dry run
+///
+/// Always `false` outside routes wrapped in [`honor`].
+pub fn is_requested() -> bool {
+    REQUESTED.try_with(|requested| *requested).unwrap_or(false)
+}
+
+/// Whether `response` is the preview of a dry run
+pub fn is_preview(response: &Response) -> bool {
+    response
+        .headers()
+        .get(PREFERENCE_APPLIED)
+        .is_some_and(|value| value == DRY_RUN)
+}
+
+#[derive(Deserialize)]
+struct DryRunQuery {
+    dry_run: Option<String>,
+}
+
+/// Returns whether `request` asks for a dry run
+///
+/// Read requests never do; they have nothing to commit.
+fn requested(request: &Request) -> Result<bool, ApiError> {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return Ok(false);
+    }
+    let query = Query::<DryRunQuery>::try_from_uri(request.uri())
+        .map(|Query(query)| query.dry_run)
+        .unwrap_or_default();
+    match query.as_deref() {
+        Some("true") => return Ok(true),
+        Some("false") | None => {}
+        Some(other) => {
+            return Err(ApiError::BadRequest(
+                Message::new("dry_run.invalid").with("value", other),
+            ))
+        }
+    }
+    Ok(request
+        .headers()
+        .get_all("prefer")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|preference| {
+            let token = preference.split(';').next().unwrap_or_default();
+            token.trim().eq_ignore_ascii_case(DRY_RUN)
+        }))
+}
+
+/// Middleware applying the dry-run preference for handlers supporting it
+pub async fn honor(request: Request, next: Next) -> Response {
+    let dry_run = match requested(&request) {
+        Ok(dry_run) => dry_run,
+        Err(err) => return err.into_response(),
+    };
+    if !dry_run {
+        return next.run(request).await;
+    }
+    let mut response = REQUESTED.scope(true, next.run(request)).await;
+    if response.status().is_success() {
+        response
+            .headers_mut()
+            .insert(PREFERENCE_APPLIED, HeaderValue::from_static(DRY_RUN));
+    }
+    response
+}
+
+/// Middleware rejecting dry runs of writes that cannot be previewed
+pub async fn refuse(request: Request, next: Next) -> Response {
+    match requested(&request) {
+        Ok(false) => next.run(request).await,
+        Ok(true) => ApiError::BadRequest(
+            Message::new("dry_run.unsupported").with("path", request.uri().path()),
+        )
+        .into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+
+    fn request(method: Method, uri: &str, prefer: Option<&str>) -> Request {
+        let mut builder = Request::builder().method(method).uri(uri);
+        if let Some(prefer) = prefer {
+            builder = builder.header("prefer", prefer);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_requested() {
+        let post = |uri, prefer| requested(&request(Method::POST, uri, prefer)).unwrap();
+        assert!(post("/api/v1/users?dry_run=true", None));
+        assert!(!post("/api/v1/users?dry_run=false", None));
+        assert!(!post("/api/v1/users", None));
+        assert!(post("/api/v1/users", Some("return=minimal, Dry-Run")));
+        assert!(!post("/api/v1/users", Some("respond-async")));
+        assert!(requested(&request(Method::POST, "/api/v1/users?dry_run=yes", None)).is_err());
+
+        // Reads have nothing to preview
+        let get = request(Method::GET, "/api/v1/users?dry_run=true", None);
+        assert!(!requested(&get).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_is_requested_in_scope() {
+        assert!(!is_requested());
+        assert!(REQUESTED.scope(true, async { is_requested() }).await);
+    }
+}
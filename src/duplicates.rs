@@ -0,0 +1,209 @@
+//! Detection and merging of duplicate accounts
+//!
+//! Users are grouped by a key derived with each [`DuplicateStrategy`]:
+//!
+//! * `email` - the address with its local part lowercased and any
+//!   `+tag` suffix removed, so `Jane+news@example.com` matches
+//!   `jane@example.com`
+//! * `name` - the name's words with case and diacritics folded, in
+//!   alphabetical order, so `Zoë Smith` matches `smith, zoe`
+//!
+//! The strategies in `APP_DUPLICATE_STRATEGIES` are used unless a request
+//! picks one. [`merge`] combines two accounts into one.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use unicode_general_category::{get_general_category, GeneralCategory};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::models::{DuplicateGroup, DuplicateStrategy, MergePrecedence, MergeSource, User};
+
+/// Returns the grouping key of `user` under `strategy`
+pub fn key(strategy: DuplicateStrategy, user: &User) -> String {
+    match strategy {
+        DuplicateStrategy::Email => email_key(&user.email),
+        DuplicateStrategy::Name => name_key(&user.name),
+    }
+}
+
+fn email_key(email: &str) -> String {
+    let (local, domain) = email.rsplit_once('@').unwrap_or((email, ""));
+    let local = local.split('+').next().unwrap_or(local);
+    format!("{}@{}", local.to_lowercase(), domain.to_lowercase())
+}
+
+fn name_key(name: &str) -> String {
+    let folded: String = name
+        .nfkd()
+        .filter(|c| {
+            !matches!(
+                get_general_category(*c),
+                GeneralCategory::NonspacingMark
+                    | GeneralCategory::SpacingMark
+                    | GeneralCategory::EnclosingMark
+            )
+        })
+        .flat_map(char::to_lowercase)
+        .collect();
+    let mut words: Vec<&str> = folded
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    words.sort_unstable();
+    words.join(" ")
+}
+
+/// Groups users sharing a key under any of `strategies`
+///
+/// Groups hold at least two users, oldest first, and are ordered by
+/// strategy and key. A user can appear in a group for each strategy.
+pub fn find(users: &[Arc<User>], strategies: &[DuplicateStrategy]) -> Vec<DuplicateGroup> {
+    let mut groups = Vec::new();
+    for &strategy in strategies {
+        let mut by_key: HashMap<String, Vec<Arc<User>>> = HashMap::new();
+        for user in users {
+            let key = key(strategy, user);
+            if !key.is_empty() {
+                by_key.entry(key).or_default().push(user.clone());
+            }
+        }
+
+        let mut found: Vec<DuplicateGroup> = by_key
+            .into_iter()
+            .filter(|(_, users)| users.len() > 1)
+            .map(|(key, mut users)| {
+                users.sort_by_key(|user| (user.created_at, user.id));
+                DuplicateGroup {
+                    strategy,
+                    key,
+                    users,
+                }
+            })
+            .collect();
+        found.sort_by(|a, b| a.key.cmp(&b.key));
+        groups.extend(found);
+    }
+    groups
+}
+
+/// Combines `remove` into `keep`
+///
+/// Each field comes from the user `precedence` picks; optional fields
+/// missing there are taken from the other user. Custom field values come
+/// from `keep`, completed with those only `remove` has. The result has
+/// `keep`'s ID, the earliest creation time and the latest activity of the
+/// two.
+pub fn merge(keep: &User, remove: &User, precedence: &MergePrecedence, now: DateTime<Utc>) -> User {
+    let pick = |source: MergeSource| match source {
+        MergeSource::Keep => (keep, remove),
+        MergeSource::Remove => (remove, keep),
+    };
+    let optional = |source: MergeSource, field: fn(&User) -> &Option<String>| {
+        let (first, second) = pick(source);
+        field(first).clone().or_else(|| field(second).clone())
+    };
+
+    User {
+        id: keep.id,
+        name: pick(precedence.name).0.name.clone(),
+        email: pick(precedence.email).0.email.clone(),
+        phone: optional(precedence.phone, |user| &user.phone),
+        status: pick(precedence.status).0.status,
+        locale: optional(precedence.locale, |user| &user.locale),
+        timezone: optional(precedence.timezone, |user| &user.timezone),
+        custom: remove
+            .custom
+            .iter()
+            .chain(&keep.custom)
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect(),
+        created_at: keep.created_at.min(remove.created_at),
+        updated_at: now,
+        last_login_at: keep.last_login_at.max(remove.last_login_at),
+        last_seen_at: keep.last_seen_at.max(remove.last_seen_at),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::UserStatus;
+    use chrono::Duration;
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(email_key("Jane+news@example.com"), "jane@example.com");
+        assert_eq!(email_key("jane@example.com"), "jane@example.com");
+        assert_eq!(name_key("Zoë  Smith"), "smith zoe");
+        assert_eq!(name_key("smith, ZOE"), "smith zoe");
+        assert_ne!(name_key("Zoe Smithe"), name_key("Zoe Smith"));
+    }
+
+    #[test]
+    fn test_find_groups_per_strategy() {
+        let users: Vec<Arc<User>> = [
+            User::new("Jane Doe", "jane@example.com", Utc::now()),
+            User::new("Doe, Jane", "jane+work@example.com", Utc::now()),
+            User::new("John Roe", "john@example.com", Utc::now()),
+        ]
+        .into_iter()
+        .map(Arc::new)
+        .collect();
+
+        let groups = find(&users, &[DuplicateStrategy::Email]);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].key, "jane@example.com");
+        assert_eq!(groups[0].users.len(), 2);
+
+        let groups = find(&users, &[DuplicateStrategy::Email, DuplicateStrategy::Name]);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[1].strategy, DuplicateStrategy::Name);
+        assert_eq!(groups[1].key, "doe jane");
+    }
+
+    #[test]
+    fn test_merge_fills_gaps_from_removed() {
+        let now = Utc::now();
+        let mut keep = User::new("Jane Doe", "jane@example.com", now);
+        keep.locale = Some("en-US".to_string());
+        let mut remove = User::new("Jane D.", "jane+old@example.com", now);
+        remove.phone = Some("+14155552671".to_string());
+        remove.locale = Some("de-DE".to_string());
+        remove.created_at = keep.created_at - Duration::days(30);
+        remove.last_login_at = Some(now);
+
+        let merged = merge(&keep, &remove, &MergePrecedence::default(), now);
+        assert_eq!(merged.id, keep.id);
+        assert_eq!(merged.name, "Jane Doe");
+        assert_eq!(merged.phone.as_deref(), Some("+14155552671"));
+        assert_eq!(merged.locale.as_deref(), Some("en-US"));
+        assert_eq!(merged.created_at, remove.created_at);
+        assert_eq!(merged.last_login_at, Some(now));
+        assert_eq!(merged.updated_at, now);
+    }
+
+    #[test]
+    fn test_merge_precedence() {
+        let now = Utc::now();
+        let keep = User::new("Jane Doe", "jane@example.com", now);
+        let mut remove = User::new("Jane D.", "jane+old@example.com", now);
+        remove.locale = Some("de-DE".to_string());
+        remove.status = UserStatus::Suspended;
+
+        let precedence = MergePrecedence {
+            email: MergeSource::Remove,
+            status: MergeSource::Remove,
+            timezone: MergeSource::Remove,
+            ..MergePrecedence::default()
+        };
+        let merged = merge(&keep, &remove, &precedence, now);
+        assert_eq!(merged.id, keep.id);
+        assert_eq!(merged.name, "Jane Doe");
+        assert_eq!(merged.email, "jane+old@example.com");
+        assert_eq!(merged.status, UserStatus::Suspended);
+        assert_eq!(merged.locale.as_deref(), Some("de-DE"));
+        assert_eq!(merged.timezone, None);
+    }
+}
@@ -2,70 +2,601 @@
 //!
 //! This module provides a unified error type that can be converted
 //! into appropriate HTTP responses.
+//!
+//! Every error response carries a stable [`ErrorCode`] and whether the
+//! request may succeed when retried unchanged, so clients can decide how
+//! to react without matching on the localized message.
+//!
+//! Failures of the server itself keep the error that caused them as their
+//! [`source`](std::error::Error::source). The chain of causes is logged
+//! when the response is built, together with the request's ID from
+//! [`X_REQUEST_ID`].
+//!
+//! What clients learn about internal errors depends on
+//! `APP_ERROR_DETAILS`. By default they only get a generic message and the
+//! request ID to quote when reporting the problem, so file paths and parser
+//! details never leak. With details enabled, as in development, the
+//! original message and the chain of causes are included as well.
+
+use std::sync::Arc;
 
 use axum::{
-    http::StatusCode,
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
     response::{IntoResponse, Response},
     Json,
 };
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::field_case::Cased;
+use crate::i18n::{Language, Message};
+use crate::models::StorageError;
+use crate::validation::Violations;
+use crate::AppState;
+
+/// Header carrying the ID of a request, taken from the client when it is a
+/// UUID and generated otherwise
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
 
 /// Main error type for the API
 ///
 /// This enum represents all possible errors that can occur during
-/// request processing. Each variant maps to an appropriate HTTP status code.
-#[derive(Debug)]
+/// request processing. Each variant maps to an appropriate HTTP status code
+/// and carries a localizable [`Message`].
+#[derive(Debug, Clone, thiserror::Error)]
 pub enum ApiError {
     /// Resource not found (404)
-    NotFound(String),
+    #[error("{0}")]
+    NotFound(Message),
     /// Bad request - validation or input errors (400)
-    BadRequest(String),
+    #[error("{0}")]
+    BadRequest(Message),
+    /// Unprocessable entity - the request body violates validation rules
+    /// (422)
+    #[error("{message}: {violations}")]
+    Unprocessable {
+        /// Summary sent to the client
+        message: Message,
+        /// Every rule the request violates
+        violations: Violations,
+    },
     /// Internal server error (500)
-    Internal(String),
+    #[error("{0}")]
+    Internal(Message),
+    /// Internal server error (500) caused by another error, which is
+    /// logged but not sent to the client
+    #[error("{message}")]
+    Failed {
+        /// Message sent to the client
+        message: Message,
+        /// The error that caused the failure
+        #[source]
+        source: Cause,
+    },
     /// Conflict - resource already exists (409)
-    Conflict(String),
+    #[error("{0}")]
+    Conflict(Message),
+    /// Gone - the resource was deleted or merged away (410)
+    #[error("{0}")]
+    Gone(Message),
+    /// Unauthorized - missing or unknown credentials (401)
+    #[error("{0}")]
+    Unauthorized(Message),
+    /// Forbidden - the credentials lack a required permission (403)
+    #[error("{0}")]
+    Forbidden(Message),
+    /// Method not allowed - the route exists but not for this method (405)
+    #[error("{0}")]
+    MethodNotAllowed(Message),
+    /// Service unavailable - the server is saturated or degraded (503)
+    #[error("{0}")]
+    ServiceUnavailable(Message),
+    /// Insufficient storage - the user store is full (507)
+    #[error("{0}")]
+    InsufficientStorage(Message),
+    /// Gateway timeout - the request's deadline passed before it was
+    /// answered (504)
+    #[error("{0}")]
+    GatewayTimeout(Message),
+    /// Unprocessable entity - the name or email is reserved, or the name
+    /// contains a blocked term (422)
+    #[error("{0}")]
+    Reserved(Message),
+    /// Too many requests - the caller exceeded its rate limit (429)
+    #[error("{0}")]
+    TooManyRequests(Message),
+    /// Range not satisfiable - the requested items are past the end of
+    /// the collection (416)
+    #[error("{0}")]
+    RangeNotSatisfiable(Message),
+}
+
+/// The error behind an [`ApiError::Failed`]
+///
+/// Shared, so errors stay cheap to clone into response extensions.
+pub type Cause = Arc<dyn std::error::Error + Send + Sync>;
+
+impl From<Violations> for ApiError {
+    fn from(violations: Violations) -> Self {
+        ApiError::Unprocessable {
+            message: Message::new("validation.failed").with("count", violations.len()),
+            violations,
+        }
+    }
+}
+
+impl From<StorageError> for ApiError {
+    fn from(err: StorageError) -> Self {
+        match err {
+            StorageError::NotFound(id) => {
+                ApiError::NotFound(Message::new("user.not_found").with("id", id))
+            }
+            StorageError::DuplicateId(id) => {
+                ApiError::Conflict(Message::new("user.id_exists").with("id", id))
+            }
+            StorageError::DuplicateEmail { email, .. } => {
+                ApiError::Conflict(Message::new("user.email_in_use").with("email", email))
+            }
+            StorageError::DuplicatePhone { phone, .. } => {
+                ApiError::Conflict(Message::new("user.phone_in_use").with("phone", phone))
+            }
+            StorageError::Conflict { id, expected, .. } => ApiError::Conflict(
+                Message::new("user.modified")
+                    .with("id", id)
+                    .with("expected", expected),
+            ),
+        }
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(err: std::io::Error) -> Self {
+        ApiError::failed(Message::new("server.io_failed"), err)
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(err: serde_json::Error) -> Self {
+        ApiError::failed(Message::new("server.serialization_failed"), err)
+    }
+}
+
+/// Stable, machine-readable kind of an error
+///
+/// Codes are never renamed or removed; clients may match on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request is malformed or fails validation
+    InvalidRequest,
+    /// The request body violates validation rules, listed in the response
+    ValidationFailed,
+    /// No credentials, or unknown ones, were given
+    Unauthenticated,
+    /// The credentials lack a required permission
+    Forbidden,
+    /// The resource does not exist
+    NotFound,
+    /// The route does not support the method
+    MethodNotAllowed,
+    /// The request conflicts with the current state of the resource
+    Conflict,
+    /// The resource existed but was deleted or merged away
+    Gone,
+    /// The server failed unexpectedly
+    Internal,
+    /// The server is saturated, degraded or not ready yet
+    Unavailable,
+    /// The user store is full
+    StorageFull,
+    /// The request's deadline passed before it was answered
+    DeadlineExceeded,
+    /// The name or email is reserved, or the name contains a blocked term
+    Reserved,
+    /// The caller exceeded its rate limit
+    RateLimited,
+    /// The requested range of items is past the end of the collection
+    RangeNotSatisfiable,
+}
+
+impl ErrorCode {
+    /// Returns the code as written in JSON
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::ValidationFailed => "validation_failed",
+            ErrorCode::Unauthenticated => "unauthenticated",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::MethodNotAllowed => "method_not_allowed",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::Gone => "gone",
+            ErrorCode::Internal => "internal",
+            ErrorCode::Unavailable => "unavailable",
+            ErrorCode::StorageFull => "storage_full",
+            ErrorCode::DeadlineExceeded => "deadline_exceeded",
+            ErrorCode::Reserved => "reserved",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::RangeNotSatisfiable => "range_not_satisfiable",
+        }
+    }
+
+    /// Whether repeating the same request may succeed
+    ///
+    /// Only errors caused by a passing condition of the server are: the
+    /// others repeat until the request, the credentials or the stored data
+    /// change. Clients should wait before retrying, honoring `Retry-After`
+    /// where given.
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorCode::Unavailable | ErrorCode::DeadlineExceeded | ErrorCode::RateLimited
+        )
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// JSON body of every error response
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ErrorResponse {
+    /// Details of the error
+    pub error: ErrorBody,
+}
+
+/// Details of an error response
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ErrorBody {
+    /// Human-readable message in the negotiated language
+    pub message: String,
+    /// HTTP status code, repeated from the response status line
+    pub status: u16,
+    /// Stable kind of the error
+    pub code: ErrorCode,
+    /// Whether repeating the same request may succeed
+    pub retryable: bool,
+    /// ID of the request, given for internal errors to quote when
+    /// reporting them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<Uuid>,
+    /// Causes of an internal error, outermost first; only given when
+    /// error details are enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Vec<String>>,
+    /// Every validation rule the request violates; only given for
+    /// `validation_failed` errors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub violations: Option<Vec<ViolationBody>>,
+}
+
+/// A validation rule violated by one field of the request
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ViolationBody {
+    /// Name of the field, as in the request body
+    pub field: String,
+    /// Stable name of the violated rule
+    pub rule: String,
+    /// Human-readable message in the negotiated language
+    pub message: String,
+}
+
+/// The request an error is answered in, set by [`context`]
+#[derive(Debug, Clone, Copy)]
+struct RequestContext {
+    id: Uuid,
+    details: bool,
+}
+
+tokio::task_local! {
+    static CONTEXT: RequestContext;
+}
+
+/// Returns the ID of the current request, if within [`context`]
+pub fn request_id() -> Option<Uuid> {
+    CONTEXT.try_with(|context| context.id).ok()
+}
+
+/// Middleware assigning the request its ID and applying
+/// `APP_ERROR_DETAILS` to the errors answered within it
+///
+/// The ID is sent back in [`X_REQUEST_ID`] on every response.
+pub async fn context(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| Uuid::parse_str(value).ok())
+        .unwrap_or_else(Uuid::new_v4);
+    let context = RequestContext {
+        id,
+        details: state.config.error_details,
+    };
+
+    let mut response = CONTEXT.scope(context, next.run(request)).await;
+    if let Ok(value) = HeaderValue::try_from(id.to_string()) {
+        response.headers_mut().insert(X_REQUEST_ID, value);
+    }
+    response
 }
 
 impl ApiError {
+    /// Creates an internal server error answered with `message` and caused
+    /// by `source`
+    pub fn failed(
+        message: Message,
+        source: impl std::error::Error + Send + Sync + 'static,
+    ) -> Self {
+        ApiError::Failed {
+            message,
+            source: Arc::new(source),
+        }
+    }
+
+    /// Returns the messages of the errors that caused this one, outermost
+    /// first
+    pub fn causes(&self) -> Vec<String> {
+        let mut causes = Vec::new();
+        let mut source = std::error::Error::source(self);
+        while let Some(err) = source {
+            causes.push(err.to_string());
+            source = err.source();
+        }
+        causes
+    }
+
     /// Returns the HTTP status code for this error
     pub fn status_code(&self) -> StatusCode {
         match self {
             ApiError::NotFound(_) => StatusCode::NOT_FOUND,
             ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
-            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Unprocessable { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Internal(_) | ApiError::Failed { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Gone(_) => StatusCode::GONE,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::InsufficientStorage(_) => StatusCode::INSUFFICIENT_STORAGE,
+            ApiError::GatewayTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::Reserved(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::RangeNotSatisfiable(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+        }
+    }
+
+    /// Returns the stable kind of this error
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ApiError::NotFound(_) => ErrorCode::NotFound,
+            ApiError::BadRequest(_) => ErrorCode::InvalidRequest,
+            ApiError::Unprocessable { .. } => ErrorCode::ValidationFailed,
+            ApiError::Internal(_) | ApiError::Failed { .. } => ErrorCode::Internal,
+            ApiError::Conflict(_) => ErrorCode::Conflict,
+            ApiError::Gone(_) => ErrorCode::Gone,
+            ApiError::Unauthorized(_) => ErrorCode::Unauthenticated,
+            ApiError::Forbidden(_) => ErrorCode::Forbidden,
+            ApiError::MethodNotAllowed(_) => ErrorCode::MethodNotAllowed,
+            ApiError::ServiceUnavailable(_) => ErrorCode::Unavailable,
+            ApiError::InsufficientStorage(_) => ErrorCode::StorageFull,
+            ApiError::GatewayTimeout(_) => ErrorCode::DeadlineExceeded,
+            ApiError::Reserved(_) => ErrorCode::Reserved,
+            ApiError::TooManyRequests(_) => ErrorCode::RateLimited,
+            ApiError::RangeNotSatisfiable(_) => ErrorCode::RangeNotSatisfiable,
         }
     }
 
-    /// Returns a user-friendly error message
-    pub fn message(&self) -> &str {
+    /// Returns the localizable error message
+    pub fn message(&self) -> &Message {
         match self {
             ApiError::NotFound(msg) => msg,
             ApiError::BadRequest(msg) => msg,
+            ApiError::Unprocessable { message, .. } => message,
             ApiError::Internal(msg) => msg,
+            ApiError::Failed { message, .. } => message,
             ApiError::Conflict(msg) => msg,
+            ApiError::Gone(msg) => msg,
+            ApiError::Unauthorized(msg) => msg,
+            ApiError::Forbidden(msg) => msg,
+            ApiError::MethodNotAllowed(msg) => msg,
+            ApiError::ServiceUnavailable(msg) => msg,
+            ApiError::InsufficientStorage(msg) => msg,
+            ApiError::GatewayTimeout(msg) => msg,
+            ApiError::Reserved(msg) => msg,
+            ApiError::TooManyRequests(msg) => msg,
+            ApiError::RangeNotSatisfiable(msg) => msg,
+        }
+    }
+
+    /// Renders the error as its `Display` output does, in `language`
+    pub fn render(&self, language: Language) -> String {
+        match self {
+            ApiError::Unprocessable {
+                message,
+                violations,
+            } => format!(
+                "{}: {}",
+                message.render(language),
+                violations.render(language)
+            ),
+            _ => self.message().render(language),
         }
     }
+
+    /// Returns the JSON error body with the message rendered in `language`
+    ///
+    /// Internal errors are redacted to a generic message unless error
+    /// details are enabled for the current request.
+    pub fn body(&self, language: Language) -> serde_json::Value {
+        let context = CONTEXT.try_with(|context| *context).ok();
+        let internal = self.code() == ErrorCode::Internal;
+        let details = internal && context.is_some_and(|context| context.details);
+        let message = if internal && !details {
+            Message::new("server.internal_error")
+        } else {
+            self.message().clone()
+        };
+        let violations = match self {
+            ApiError::Unprocessable { violations, .. } => Some(
+                violations
+                    .iter()
+                    .map(|violation| ViolationBody {
+                        field: violation.field.to_string(),
+                        rule: violation.message.key().to_string(),
+                        message: violation.message.render(language),
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        };
+
+        json!(Cased(&ErrorResponse {
+            error: ErrorBody {
+                message: message.render(language),
+                status: self.status_code().as_u16(),
+                code: self.code(),
+                retryable: self.code().is_retryable(),
+                request_id: context.filter(|_| internal).map(|context| context.id),
+                details: details.then(|| self.causes()),
+                violations,
+            },
+        }))
+    }
 }
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
         let status = self.status_code();
-        let body = Json(json!({
-            "error": {
-                "message": self.message(),
-                "status": status.as_u16(),
-            }
-        }));
+        if self.code() == ErrorCode::Internal {
+            let request_id = request_id().map(|id| id.to_string()).unwrap_or_default();
+            tracing::error!(error = %self, causes = ?self.causes(), %request_id, "request failed");
+        }
+        let body = Json(self.body(Language::En));
 
-        (status, body).into_response()
+        // Keep the error itself so the i18n layer can re-render the body
+        let mut response = (status, body).into_response();
+        response.extensions_mut().insert(self);
+        response
     }
 }
 
-impl std::fmt::Display for ApiError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_body_carries_code_and_retryable() {
+        let body =
+            ApiError::NotFound(Message::new("user.not_found").with("id", 1)).body(Language::En);
+        assert_eq!(body["error"]["status"], 404);
+        assert_eq!(body["error"]["code"], "not_found");
+        assert_eq!(body["error"]["retryable"], false);
+
+        let body =
+            ApiError::ServiceUnavailable(Message::new("shard.unavailable")).body(Language::En);
+        assert_eq!(body["error"]["code"], "unavailable");
+        assert_eq!(body["error"]["retryable"], true);
     }
-}
 
-impl std::error::Error for ApiError {}
+    #[test]
+    fn test_causes_are_kept_but_not_sent() {
+        let io = std::io::Error::new(std::io::ErrorKind::Other, "/var/lib/secret: disk full");
+        let err = ApiError::failed(Message::new("snapshot.failed"), io);
+        assert_eq!(err.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(err.causes(), vec!["/var/lib/secret: disk full".to_string()]);
+
+        let err = ApiError::from(serde_json::from_str::<u8>("x").unwrap_err());
+        assert_eq!(err.code(), ErrorCode::Internal);
+        assert_eq!(err.causes().len(), 1);
+        assert!(ApiError::Internal(Message::new("chaos.error"))
+            .causes()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_storage_errors_map_to_client_errors() {
+        let id = Uuid::new_v4();
+        let err = ApiError::from(StorageError::NotFound(id));
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(err.message().key(), "user.not_found");
+
+        let err = ApiError::from(StorageError::DuplicateId(id));
+        assert_eq!(err.status_code(), StatusCode::CONFLICT);
+        assert_eq!(err.message().key(), "user.id_exists");
+
+        let err = ApiError::from(StorageError::Conflict {
+            id,
+            expected: 1,
+            current: 2,
+        });
+        assert_eq!(err.code(), ErrorCode::Conflict);
+        assert_eq!(err.message().key(), "user.modified");
+    }
+
+    #[test]
+    fn test_internal_errors_are_redacted_unless_details_are_enabled() {
+        let io = std::io::Error::new(std::io::ErrorKind::Other, "/var/lib/secret: disk full");
+        let err = ApiError::failed(Message::new("snapshot.failed"), io);
+        let id = Uuid::new_v4();
+        let body = |details| {
+            let context = RequestContext { id, details };
+            CONTEXT.sync_scope(context, || err.body(Language::En))
+        };
+
+        let redacted = body(false);
+        assert_eq!(redacted["error"]["message"], "An internal error occurred");
+        assert_eq!(redacted["error"]["request_id"], id.to_string());
+        assert!(redacted["error"].get("details").is_none());
+        assert!(!redacted.to_string().contains("secret"));
+        assert!(!err.body(Language::En).to_string().contains("secret"));
+
+        let detailed = body(true);
+        assert_eq!(
+            detailed["error"]["message"],
+            "The snapshot cannot be read or written"
+        );
+        assert_eq!(
+            detailed["error"]["details"][0],
+            "/var/lib/secret: disk full"
+        );
+
+        // Other errors are never redacted and carry no request ID
+        let body = CONTEXT.sync_scope(RequestContext { id, details: false }, || {
+            ApiError::Conflict(Message::new("capture.not_configured")).body(Language::En)
+        });
+        assert_eq!(
+            body["error"]["message"],
+            "Request capture requires APP_CAPTURE_FILE to be set"
+        );
+        assert!(body["error"].get("request_id").is_none());
+    }
+
+    #[test]
+    fn test_codes_serialize_as_str() {
+        for code in [
+            ErrorCode::InvalidRequest,
+            ErrorCode::Unauthenticated,
+            ErrorCode::Forbidden,
+            ErrorCode::NotFound,
+            ErrorCode::MethodNotAllowed,
+            ErrorCode::Conflict,
+            ErrorCode::Gone,
+            ErrorCode::Internal,
+            ErrorCode::Unavailable,
+            ErrorCode::StorageFull,
+            ErrorCode::DeadlineExceeded,
+            ErrorCode::Reserved,
+            ErrorCode::RateLimited,
+        ] {
+            assert_eq!(serde_json::to_value(code).unwrap(), code.as_str());
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Entity tags for the user collection
+//!
+//! `GET /api/v1/users` carries a strong `ETag` derived from the storage
+//! version rather than from the response body, so a client revalidating
+//! with `If-None-Match` gets `304 Not Modified` without the list being
+//! filtered or serialized.
+//!
+//! The version is read before the handler runs. If a mutation lands in
+//! between, the response carries the older tag; the client's next
+//! revalidation then simply misses, so a stale `304` is never sent.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use axum::{
+    extract::{Request, State},
+    http::{
+        header::{ACCEPT, ETAG, IF_NONE_MATCH, RANGE},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::field_case::X_FIELD_CASE;
+use crate::AppState;
+
+/// Middleware adding an `ETag` to collection responses and answering
+/// matching `If-None-Match` requests with `304 Not Modified`
+pub async fn collection(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let version = state.storage.read().await.version();
+    let etag = tag(version, &request);
+
+    if request
+        .headers()
+        .get(IF_NONE_MATCH)
+        .is_some_and(|condition| matches(condition, &etag))
+    {
+        let mut headers = HeaderMap::new();
+        headers.insert(ETAG, etag);
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    let mut response = next.run(request).await;
+    if response.status() == StatusCode::OK {
+        response.headers_mut().insert(ETAG, etag);
+    }
+    response
+}
+
+/// Builds the tag for a storage version and request
+///
+/// The query (filters and page), `Accept` header (timestamp format),
+/// `Range` header and `X-Field-Case` header select a different
+/// representation of the same version, so they are part of the tag.
+fn tag((epoch, version): (u64, u64), request: &Request) -> HeaderValue {
+    let mut hasher = DefaultHasher::new();
+    request.uri().query().hash(&mut hasher);
+    for name in [ACCEPT, RANGE, X_FIELD_CASE] {
+        request
+            .headers()
+            .get(name)
+            .map(HeaderValue::as_bytes)
+            .hash(&mut hasher);
+    }
+
+    let tag = format!("\"{:x}-{:x}-{:x}\"", epoch, version, hasher.finish());
+    HeaderValue::from_str(&tag).unwrap_or_else(|_| HeaderValue::from_static("\"\""))
+}
+
+/// Evaluates an `If-None-Match` header against `etag`
+///
+/// Uses weak comparison, as RFC 9110 requires for `If-None-Match`.
+pub(crate) fn matches(condition: &HeaderValue, etag: &HeaderValue) -> bool {
+    let Ok(condition) = condition.to_str() else {
+        return false;
+    };
+    let etag = etag.to_str().unwrap_or_default();
+
+    condition.trim() == "*"
+        || condition
+            .split(',')
+            .map(|candidate| candidate.trim())
+            .map(|candidate| candidate.strip_prefix("W/").unwrap_or(candidate))
+            .any(|candidate| candidate == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+
+    fn request(uri: &str, accept: Option<&str>) -> Request {
+        let mut builder = Request::get(uri);
+        if let Some(accept) = accept {
+            builder = builder.header(ACCEPT, accept);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_tag_depends_on_version_and_representation() {
+        let base = tag((1, 1), &request("/api/v1/users", None));
+        assert_eq!(base, tag((1, 1), &request("/api/v1/users", None)));
+        assert_ne!(base, tag((1, 2), &request("/api/v1/users", None)));
+        assert_ne!(base, tag((2, 1), &request("/api/v1/users", None)));
+        assert_ne!(
+            base,
+            tag((1, 1), &request("/api/v1/users?status=active", None))
+        );
+        assert_ne!(
+            base,
+            tag(
+                (1, 1),
+                &request(
+                    "/api/v1/users",
+                    Some("application/json; timestamps=rfc3339")
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let etag = HeaderValue::from_static("\"a-1-f\"");
+        let check = |condition: &'static str| matches(&HeaderValue::from_static(condition), &etag);
+
+        assert!(check("\"a-1-f\""));
+        assert!(check("W/\"a-1-f\""));
+        assert!(check("\"b-2-0\", \"a-1-f\""));
+        assert!(check("*"));
+        assert!(!check("\"a-2-f\""));
+    }
+}
@@ -0,0 +1,284 @@
+//! In-process domain events
+//!
+//! Mutation handlers publish an [`Event`] after a change has been stored.
+//! Subscribers are called synchronously, in registration order, before
+//! the handler responds, so anything they maintain (such as the response
+//! cache) is consistent with storage by the time the client sees the
+//! result.
+//!
+//! Updates carry a [`FieldDiff`] naming the fields they changed, so
+//! subscribers interested in some fields only, such as a notifier for
+//! email changes, can subscribe with an [`EventFilter`]:
+//!
+//! ```
+//! # let events = rust_api::events::EventBus::default();
+//! use rust_api::events::EventFilter;
+//!
+//! events.subscribe_filtered(EventFilter::changed(["email"]), |event| {
+//!     println!("email of {} changed", event.user_id());
+//! });
+//! ```
+
+use std::collections::BTreeSet;
+use std::sync::RwLock;
+
+use uuid::Uuid;
+
+use crate::models::User;
+
+/// A change to stored data
+// Named after the resource so events for other resources can join later
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A user was created
+    UserCreated(User),
+    /// A user's fields or status changed; holds the new state and the
+    /// fields that changed
+    UserUpdated(User, FieldDiff),
+    /// A user was deleted
+    UserDeleted(Uuid),
+}
+
+impl Event {
+    /// Returns the ID of the affected user
+    pub fn user_id(&self) -> Uuid {
+        match self {
+            Event::UserCreated(user) | Event::UserUpdated(user, _) => user.id,
+            Event::UserDeleted(id) => *id,
+        }
+    }
+}
+
+/// The fields of a user an update changed
+///
+/// Fields are named as in JSON; custom fields as `custom.<name>`.
+/// `updated_at` changes with every update and is left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldDiff(BTreeSet<String>);
+
+impl FieldDiff {
+    /// Fields a stored user can differ in, besides its custom fields
+    const FIELDS: [&'static str; 9] = [
+        "name",
+        "email",
+        "phone",
+        "status",
+        "locale",
+        "timezone",
+        "created_at",
+        "last_login_at",
+        "last_seen_at",
+    ];
+
+    /// Compares two states of a user
+    pub fn between(old: &User, new: &User) -> Self {
+        let mut fields: BTreeSet<String> = Self::FIELDS
+            .into_iter()
+            .filter(|field| match *field {
+                "name" => old.name != new.name,
+                "email" => old.email != new.email,
+                "phone" => old.phone != new.phone,
+                "status" => old.status != new.status,
+                "locale" => old.locale != new.locale,
+                "timezone" => old.timezone != new.timezone,
+                "created_at" => old.created_at != new.created_at,
+                "last_login_at" => old.last_login_at != new.last_login_at,
+                _ => old.last_seen_at != new.last_seen_at,
+            })
+            .map(str::to_string)
+            .collect();
+        let names: BTreeSet<&String> = old.custom.keys().chain(new.custom.keys()).collect();
+        fields.extend(
+            names
+                .into_iter()
+                .filter(|name| old.custom.get(*name) != new.custom.get(*name))
+                .map(|name| format!("custom.{}", name)),
+        );
+        Self(fields)
+    }
+
+    /// Every field of `user`, for updates whose previous state is unknown
+    pub fn all(user: &User) -> Self {
+        let fields = Self::FIELDS.into_iter().map(str::to_string);
+        let custom = user.custom.keys().map(|name| format!("custom.{}", name));
+        Self(fields.chain(custom).collect())
+    }
+
+    /// Whether `field` changed; `custom` stands for any custom field
+    pub fn contains(&self, field: &str) -> bool {
+        if field == "custom" {
+            return self.0.iter().any(|changed| changed.starts_with("custom."));
+        }
+        self.0.contains(field)
+    }
+
+    /// Returns the changed fields, in alphabetical order
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+
+    /// Returns `true` if nothing but `updated_at` changed
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Which events a subscriber receives
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    /// Fields an update must change, if only updates are wanted
+    changed: Option<BTreeSet<String>>,
+}
+
+impl EventFilter {
+    /// Every event
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Only updates changing at least one of `fields`, named as in
+    /// [`FieldDiff`]
+    pub fn changed<I, S>(fields: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            changed: Some(fields.into_iter().map(Into::into).collect()),
+        }
+    }
+
+    /// Whether a subscriber with this filter receives `event`
+    pub fn accepts(&self, event: &Event) -> bool {
+        match (&self.changed, event) {
+            (None, _) => true,
+            (Some(fields), Event::UserUpdated(_, diff)) => {
+                fields.iter().any(|field| diff.contains(field))
+            }
+            (Some(_), _) => false,
+        }
+    }
+}
+
+type Subscriber = (EventFilter, Box<dyn Fn(&Event) + Send + Sync>);
+
+/// Dispatches events to subscribers
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: RwLock<Vec<Subscriber>>,
+}
+
+impl std::fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventBus")
+            .field("subscribers", &self.read().len())
+            .finish()
+    }
+}
+
+impl EventBus {
+    /// Registers a subscriber for all future events
+    pub fn subscribe<F>(&self, subscriber: F)
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.subscribe_filtered(EventFilter::all(), subscriber);
+    }
+
+    /// Registers a subscriber for the future events `filter` accepts
+    pub fn subscribe_filtered<F>(&self, filter: EventFilter, subscriber: F)
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.subscribers
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push((filter, Box::new(subscriber)));
+    }
+
+    /// Delivers `event` to every subscriber whose filter accepts it
+    pub fn publish(&self, event: Event) {
+        for (filter, subscriber) in self.read().iter() {
+            if filter.accepts(&event) {
+                subscriber(&event);
+            }
+        }
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Vec<Subscriber>> {
+        self.subscribers
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_publish_reaches_every_subscriber() {
+        let bus = EventBus::default();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        for _ in 0..2 {
+            let seen = seen.clone();
+            bus.subscribe(move |event| seen.lock().unwrap().push(event.user_id()));
+        }
+
+        let id = Uuid::new_v4();
+        bus.publish(Event::UserDeleted(id));
+        assert_eq!(*seen.lock().unwrap(), vec![id, id]);
+    }
+
+    #[test]
+    fn test_field_diff() {
+        let old = User {
+            custom: [("floor".to_string(), serde_json::json!(3))].into(),
+            ..User::new("Jane Doe", "jane@example.com", chrono::Utc::now())
+        };
+        let mut new = old.clone();
+        new.email = "jane.doe@example.com".to_string();
+        new.phone = Some("+14155552671".to_string());
+        new.updated_at += chrono::Duration::seconds(1);
+        new.custom.remove("floor");
+        new.custom
+            .insert("desk".to_string(), serde_json::json!("A1"));
+
+        let diff = FieldDiff::between(&old, &new);
+        assert_eq!(
+            diff.iter().collect::<Vec<_>>(),
+            ["custom.desk", "custom.floor", "email", "phone"]
+        );
+        assert!(diff.contains("custom"));
+        assert!(!diff.contains("name"));
+        assert!(FieldDiff::between(&old, &old).is_empty());
+        assert!(FieldDiff::all(&old).contains("custom.floor"));
+    }
+
+    #[test]
+    fn test_filtered_subscribers_see_matching_updates() {
+        let bus = EventBus::default();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        for fields in [vec!["email"], vec!["name", "custom"]] {
+            let seen = seen.clone();
+            let label = fields.join(",");
+            bus.subscribe_filtered(EventFilter::changed(fields), move |_| {
+                seen.lock().unwrap().push(label.clone())
+            });
+        }
+
+        let old = User::new("Jane Doe", "jane@example.com", chrono::Utc::now());
+        let mut renamed = old.clone();
+        renamed.name = "Jane Roe".to_string();
+        bus.publish(Event::UserUpdated(
+            renamed.clone(),
+            FieldDiff::between(&old, &renamed),
+        ));
+        bus.publish(Event::UserUpdated(old.clone(), FieldDiff::default()));
+        bus.publish(Event::UserCreated(old.clone()));
+        bus.publish(Event::UserDeleted(old.id));
+        assert_eq!(*seen.lock().unwrap(), vec!["name,custom".to_string()]);
+    }
+}
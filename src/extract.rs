@@ -0,0 +1,143 @@
+//! Request extractors with API-formatted rejections
+//!
+//! axum's built-in extractors reject malformed input with plain-text
+//! responses. The extractors here do the same job but fail with an
+//! [`ApiError`], so clients always receive the standard JSON error body.
+
+use std::net::{IpAddr, SocketAddr};
+
+#[cfg(feature = "server")]
+use axum::extract::ConnectInfo;
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, RawPathParams},
+    http::request::Parts,
+};
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::i18n::Message;
+use crate::AppState;
+
+/// The user ID from a route's `:id` path segment
+///
+/// Hyphenated, simple, braced and URN forms are accepted in any letter case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserId(pub Uuid);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for UserId
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        path_uuid(parts, state, "id", "user.invalid_id")
+            .await
+            .map(UserId)
+    }
+}
+
+/// The user ID from a merge route's `:remove_id` path segment
+///
+/// Accepts the same forms as [`UserId`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemovedUserId(pub Uuid);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RemovedUserId
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        path_uuid(parts, state, "remove_id", "user.invalid_id")
+            .await
+            .map(RemovedUserId)
+    }
+}
+
+/// The operation ID from a route's `:id` path segment
+///
+/// Accepts the same forms as [`UserId`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperationId(pub Uuid);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for OperationId
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        path_uuid(parts, state, "id", "operation.invalid_id")
+            .await
+            .map(OperationId)
+    }
+}
+
+/// Parses the path parameter `name` as a UUID, rejecting malformed IDs
+/// with the message `invalid`
+async fn path_uuid<S: Send + Sync>(
+    parts: &mut Parts,
+    state: &S,
+    name: &'static str,
+    invalid: &'static str,
+) -> Result<Uuid, ApiError> {
+    let invalid_id = |raw: &str| ApiError::BadRequest(Message::new(invalid).with("id", raw));
+    let params = RawPathParams::from_request_parts(parts, state)
+        .await
+        .map_err(|_| invalid_id(""))?;
+
+    let raw = params
+        .iter()
+        .find_map(|(param, value)| (param == name).then_some(value))
+        .ok_or_else(|| {
+            ApiError::Internal(Message::new("route.missing_parameter").with("name", name))
+        })?;
+
+    Uuid::parse_str(raw).map_err(|_| invalid_id(raw))
+}
+
+/// The caller's address, resolved through trusted proxies
+///
+/// See [`crate::client_ip`] for how forwarding headers are treated.
+/// Requires the server to provide the peer address as
+/// `ConnectInfo<SocketAddr>`, or without the `server` feature, as a plain
+/// [`SocketAddr`] request extension inserted by the host's adapter;
+/// without it, extraction fails with `500 Internal Server Error`, so use
+/// `Option<ClientIp>` where the address is optional.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        #[cfg(feature = "server")]
+        let peer = ConnectInfo::<SocketAddr>::from_request_parts(parts, state)
+            .await
+            .ok()
+            .map(|ConnectInfo(peer)| peer);
+        #[cfg(not(feature = "server"))]
+        let peer = parts.extensions.get::<SocketAddr>().copied();
+        let peer =
+            peer.ok_or_else(|| ApiError::Internal(Message::new("server.client_ip_unavailable")))?;
+        let state = AppState::from_ref(state);
+
+        Ok(ClientIp(
+            state
+                .config
+                .trusted_proxies
+                .resolve(peer.ip(), &parts.headers),
+        ))
+    }
+}
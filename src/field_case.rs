@@ -0,0 +1,652 @@
+//! Field naming of responses
+//!
+//! Fields are named in snake_case, as in the Rust types. JavaScript clients
+//! can ask for camelCase with an `X-Field-Case: camelCase` header, and
+//! `APP_FIELD_CASE` sets the case used for requests without one:
+//!
+//! ```json
+//! { "data": { "id": "...", "createdAt": 1700000000, "lastLoginAt": null } }
+//! ```
+//!
+//! Rather than duplicating every response type, [`Cased`] wraps the
+//! serializer: a [`CaseSerializer`] forwards everything to the one it wraps
+//! and renames struct fields and map keys on the way. Maps keyed by data
+//! rather than by field, such as custom field values, opt out with
+//! [`verbatim`]. The case is applied by [`negotiate`], which stores it in a
+//! task-local read whenever a [`Cased`] value is serialized, the same way
+//! timestamp options are.
+//!
+//! Request bodies are always read in snake_case.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+
+use axum::{
+    extract::{Request, State},
+    http::HeaderName,
+    middleware::Next,
+    response::Response,
+};
+use serde::ser::{
+    Serialize, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
+    SerializeTupleStruct, SerializeTupleVariant, Serializer,
+};
+
+use crate::AppState;
+
+/// Header selecting the case of response fields
+pub const X_FIELD_CASE: HeaderName = HeaderName::from_static("x-field-case");
+
+/// Newtype name [`verbatim`] marks values with
+const VERBATIM: &str = "$field_case::verbatim";
+
+/// How response fields are named
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FieldCase {
+    /// `last_login_at`, as in the Rust types
+    #[default]
+    Snake,
+    /// `lastLoginAt`, as is usual in JavaScript
+    Camel,
+}
+
+impl FieldCase {
+    /// Returns the name used in the header and configuration
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FieldCase::Snake => "snake_case",
+            FieldCase::Camel => "camelCase",
+        }
+    }
+
+    /// Returns the case of the current request, or snake_case outside of a
+    /// request scope
+    pub fn current() -> Self {
+        CURRENT.try_with(|case| *case).unwrap_or_default()
+    }
+
+    /// Runs `f` with this case applied
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        CURRENT.scope(self, f).await
+    }
+
+    /// Runs `f` synchronously with this case applied
+    pub fn sync_scope<R>(self, f: impl FnOnce() -> R) -> R {
+        CURRENT.sync_scope(self, f)
+    }
+
+    /// Renames a snake_case struct field
+    fn field(self, name: &'static str) -> &'static str {
+        match self {
+            FieldCase::Snake => name,
+            FieldCase::Camel => intern(name),
+        }
+    }
+
+    /// Renames a snake_case map key
+    fn key(self, key: &str) -> Cow<'_, str> {
+        match self {
+            FieldCase::Camel if key.contains('_') => Cow::Owned(camel_case(key)),
+            _ => Cow::Borrowed(key),
+        }
+    }
+}
+
+impl fmt::Display for FieldCase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for FieldCase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "snake_case" => Ok(FieldCase::Snake),
+            "camelcase" => Ok(FieldCase::Camel),
+            other => Err(format!("unknown field case '{}'", other)),
+        }
+    }
+}
+
+tokio::task_local! {
+    static CURRENT: FieldCase;
+}
+
+/// Converts `last_login_at` to `lastLoginAt`
+///
+/// Leading underscores are kept.
+fn camel_case(name: &str) -> String {
+    let mut camel = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' && !camel.trim_start_matches('_').is_empty() {
+            upper = true;
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    camel
+}
+
+/// Returns the camelCase form of a struct field
+///
+/// Serializers take struct fields as `&'static str`; the few distinct
+/// field names of the API's types are converted once and kept.
+fn intern(name: &'static str) -> &'static str {
+    if !name.contains('_') {
+        return name;
+    }
+    static NAMES: OnceLock<Mutex<HashMap<&'static str, &'static str>>> = OnceLock::new();
+    let mut names = NAMES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    names
+        .entry(name)
+        .or_insert_with(|| Box::leak(camel_case(name).into_boxed_str()))
+}
+
+/// Serializes a value with its keys as they are, whatever the case
+///
+/// For maps keyed by data, such as custom field values; use with
+/// `#[serde(serialize_with = "crate::field_case::verbatim")]`. Other
+/// serializers write the value unchanged.
+pub fn verbatim<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize + ?Sized,
+    S: Serializer,
+{
+    serializer.serialize_newtype_struct(VERBATIM, value)
+}
+
+/// Serializes the wrapped value with fields in the current request's case
+pub struct Cased<'a, T: ?Sized>(pub &'a T);
+
+impl<T: Serialize + ?Sized> Serialize for Cased<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match FieldCase::current() {
+            FieldCase::Snake => self.0.serialize(serializer),
+            case => self.0.serialize(CaseSerializer::new(serializer, case)),
+        }
+    }
+}
+
+/// A value serialized through a [`CaseSerializer`]
+struct Wrap<'a, T: ?Sized> {
+    value: &'a T,
+    case: FieldCase,
+    /// Whether the value is a map key, whose strings are renamed
+    key: bool,
+}
+
+impl<'a, T: ?Sized> Wrap<'a, T> {
+    fn value(value: &'a T, case: FieldCase) -> Self {
+        Self {
+            value,
+            case,
+            key: false,
+        }
+    }
+
+    fn key(value: &'a T, case: FieldCase) -> Self {
+        Self {
+            value,
+            case,
+            key: true,
+        }
+    }
+}
+
+impl<T: Serialize + ?Sized> Serialize for Wrap<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(CaseSerializer {
+            inner: serializer,
+            case: self.case,
+            key: self.key,
+        })
+    }
+}
+
+/// Serializer renaming struct fields and map keys before passing them on
+pub struct CaseSerializer<S> {
+    inner: S,
+    case: FieldCase,
+    key: bool,
+}
+
+impl<S> CaseSerializer<S> {
+    /// Wraps `inner`, naming fields in `case`
+    pub fn new(inner: S, case: FieldCase) -> Self {
+        Self {
+            inner,
+            case,
+            key: false,
+        }
+    }
+}
+
+/// A compound value being serialized through a [`CaseSerializer`]
+pub struct Compound<C> {
+    inner: C,
+    case: FieldCase,
+}
+
+impl<C> Compound<C> {
+    fn new(inner: C, case: FieldCase) -> Self {
+        Self { inner, case }
+    }
+}
+
+impl<S: Serializer> Serializer for CaseSerializer<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = Compound<S::SerializeSeq>;
+    type SerializeTuple = Compound<S::SerializeTuple>;
+    type SerializeTupleStruct = Compound<S::SerializeTupleStruct>;
+    type SerializeTupleVariant = Compound<S::SerializeTupleVariant>;
+    type SerializeMap = Compound<S::SerializeMap>;
+    type SerializeStruct = Compound<S::SerializeStruct>;
+    type SerializeStructVariant = Compound<S::SerializeStructVariant>;
+
+    fn serialize_bool(self, v: bool) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_bool(v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i8(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i16(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i32(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i64(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_i128(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u8(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u16(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u32(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u64(v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_u128(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_f32(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_f64(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_char(v)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<S::Ok, S::Error> {
+        if self.key {
+            self.inner.serialize_str(&self.case.key(v))
+        } else {
+            self.inner.serialize_str(v)
+        }
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_bytes(v)
+    }
+
+    fn serialize_none(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_none()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_some(&Wrap::value(value, self.case))
+    }
+
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit()
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit_struct(name)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit_variant(name, index, variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        if name == VERBATIM {
+            return value.serialize(self.inner);
+        }
+        self.inner
+            .serialize_newtype_struct(name, &Wrap::value(value, self.case))
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner
+            .serialize_newtype_variant(name, index, variant, &Wrap::value(value, self.case))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, S::Error> {
+        let seq = self.inner.serialize_seq(len)?;
+        Ok(Compound::new(seq, self.case))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, S::Error> {
+        let tuple = self.inner.serialize_tuple(len)?;
+        Ok(Compound::new(tuple, self.case))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, S::Error> {
+        let tuple = self.inner.serialize_tuple_struct(name, len)?;
+        Ok(Compound::new(tuple, self.case))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, S::Error> {
+        let tuple = self
+            .inner
+            .serialize_tuple_variant(name, index, variant, len)?;
+        Ok(Compound::new(tuple, self.case))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, S::Error> {
+        let map = self.inner.serialize_map(len)?;
+        Ok(Compound::new(map, self.case))
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, S::Error> {
+        let fields = self.inner.serialize_struct(name, len)?;
+        Ok(Compound::new(fields, self.case))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, S::Error> {
+        let fields = self
+            .inner
+            .serialize_struct_variant(name, index, variant, len)?;
+        Ok(Compound::new(fields, self.case))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+impl<C: SerializeSeq> SerializeSeq for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        self.inner.serialize_element(&Wrap::value(value, self.case))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeTuple> SerializeTuple for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        self.inner.serialize_element(&Wrap::value(value, self.case))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeTupleStruct> SerializeTupleStruct for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        self.inner.serialize_field(&Wrap::value(value, self.case))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeTupleVariant> SerializeTupleVariant for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        self.inner.serialize_field(&Wrap::value(value, self.case))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeMap> SerializeMap for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), C::Error> {
+        self.inner.serialize_key(&Wrap::key(key, self.case))
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        self.inner.serialize_value(&Wrap::value(value, self.case))
+    }
+
+    fn serialize_entry<K, V>(&mut self, key: &K, value: &V) -> Result<(), C::Error>
+    where
+        K: Serialize + ?Sized,
+        V: Serialize + ?Sized,
+    {
+        self.inner
+            .serialize_entry(&Wrap::key(key, self.case), &Wrap::value(value, self.case))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeStruct> SerializeStruct for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), C::Error> {
+        self.inner
+            .serialize_field(self.case.field(key), &Wrap::value(value, self.case))
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), C::Error> {
+        self.inner.skip_field(self.case.field(key))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeStructVariant> SerializeStructVariant for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), C::Error> {
+        self.inner
+            .serialize_field(self.case.field(key), &Wrap::value(value, self.case))
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), C::Error> {
+        self.inner.skip_field(self.case.field(key))
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+/// Middleware applying the client's field case
+///
+/// Falls back to `APP_FIELD_CASE` when the request has no `X-Field-Case`
+/// header or names an unknown case.
+pub async fn negotiate(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let case = request
+        .headers()
+        .get(X_FIELD_CASE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(state.config.field_case);
+    case.scope(next.run(request)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize)]
+    struct Account {
+        user_id: u32,
+        display_name: Option<String>,
+        last_seen: Vec<Session>,
+        #[serde(flatten)]
+        extra: Extra,
+        #[serde(serialize_with = "verbatim")]
+        custom: BTreeMap<&'static str, u32>,
+    }
+
+    #[derive(Serialize)]
+    struct Session {
+        started_at: u32,
+    }
+
+    #[derive(Serialize)]
+    struct Extra {
+        login_count: u32,
+    }
+
+    fn account() -> Account {
+        Account {
+            user_id: 1,
+            display_name: Some("snake_case value".to_string()),
+            last_seen: vec![Session { started_at: 2 }],
+            extra: Extra { login_count: 3 },
+            custom: BTreeMap::from([("employee_id", 4)]),
+        }
+    }
+
+    #[test]
+    fn test_camel_case() {
+        assert_eq!(camel_case("last_login_at"), "lastLoginAt");
+        assert_eq!(camel_case("id"), "id");
+        assert_eq!(camel_case("_private_name"), "_privateName");
+        assert_eq!(intern("request_id"), "requestId");
+        assert!(std::ptr::eq(intern("request_id"), intern("request_id")));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("camelCase".parse::<FieldCase>(), Ok(FieldCase::Camel));
+        assert_eq!(" snake_case ".parse::<FieldCase>(), Ok(FieldCase::Snake));
+        assert!("kebab-case".parse::<FieldCase>().is_err());
+    }
+
+    #[test]
+    fn test_cased_renames_fields_and_keys() {
+        let snake = serde_json::to_value(Cased(&account())).unwrap();
+        assert_eq!(snake["last_seen"][0]["started_at"], 2);
+        assert_eq!(snake["custom"]["employee_id"], 4);
+
+        let camel =
+            FieldCase::Camel.sync_scope(|| serde_json::to_value(Cased(&account())).unwrap());
+        assert_eq!(
+            camel,
+            json!({
+                "userId": 1,
+                "displayName": "snake_case value",
+                "lastSeen": [{ "startedAt": 2 }],
+                "loginCount": 3,
+                "custom": { "employee_id": 4 }
+            })
+        );
+
+        // Untouched by other serializers
+        assert_eq!(serde_json::to_value(account()).unwrap(), snake);
+    }
+}
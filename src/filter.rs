@@ -0,0 +1,759 @@
+//! Filter expressions for listing users
+//!
+//! `GET /api/v1/users?filter=...` takes a small expression language in the
+//! style of OData's `$filter`:
+//!
+//! ```text
+//! name eq 'John' and created_at gt 2024-01-01
+//! (status eq 'suspended' or status eq 'pending') and not startswith(email, 'test')
+//! phone ne null
+//! ```
+//!
+//! - comparisons `field op value`, with the operators `eq`, `ne`, `gt`,
+//!   `ge`, `lt` and `le`
+//! - the string functions `contains`, `startswith` and `endswith`
+//! - `not`, `and` and `or`, from the tightest binding to the loosest, and
+//!   parentheses
+//!
+//! Strings are single-quoted, with `''` for a quote inside them; times are
+//! dates (`2024-01-01`, midnight UTC) or RFC 3339 timestamps, quoted or
+//! not. String comparisons are case-sensitive. A missing optional value
+//! only equals `null`, so `phone ne '+14155552671'` matches users without a
+//! phone number.
+//!
+//! Expressions are parsed into a typed [`Filter`] before anything is read:
+//! unknown fields, values of the wrong type and operators a field does not
+//! support are rejected with the position of the mistake. Length and
+//! nesting are bounded, so parsing and evaluation stay cheap.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use uuid::Uuid;
+
+use crate::i18n::Message;
+use crate::models::{User, UserStatus};
+
+/// Longest filter accepted, in characters
+pub const MAX_LENGTH: usize = 1024;
+
+/// Deepest nesting of `not` and parentheses accepted
+pub const MAX_DEPTH: usize = 16;
+
+/// A user field filters can refer to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// `id`
+    Id,
+    /// `name`
+    Name,
+    /// `email`
+    Email,
+    /// `phone`
+    Phone,
+    /// `status`
+    Status,
+    /// `locale`
+    Locale,
+    /// `timezone`
+    Timezone,
+    /// `created_at`
+    CreatedAt,
+    /// `updated_at`
+    UpdatedAt,
+    /// `last_login_at`
+    LastLoginAt,
+    /// `last_seen_at`
+    LastSeenAt,
+}
+
+/// What values a field holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Id,
+    Text,
+    Status,
+    Time,
+}
+
+impl Field {
+    const ALL: [Field; 11] = [
+        Field::Id,
+        Field::Name,
+        Field::Email,
+        Field::Phone,
+        Field::Status,
+        Field::Locale,
+        Field::Timezone,
+        Field::CreatedAt,
+        Field::UpdatedAt,
+        Field::LastLoginAt,
+        Field::LastSeenAt,
+    ];
+
+    /// Returns the field's name in filters and JSON
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Field::Id => "id",
+            Field::Name => "name",
+            Field::Email => "email",
+            Field::Phone => "phone",
+            Field::Status => "status",
+            Field::Locale => "locale",
+            Field::Timezone => "timezone",
+            Field::CreatedAt => "created_at",
+            Field::UpdatedAt => "updated_at",
+            Field::LastLoginAt => "last_login_at",
+            Field::LastSeenAt => "last_seen_at",
+        }
+    }
+
+    fn kind(self) -> Kind {
+        match self {
+            Field::Id => Kind::Id,
+            Field::Status => Kind::Status,
+            Field::CreatedAt | Field::UpdatedAt | Field::LastLoginAt | Field::LastSeenAt => {
+                Kind::Time
+            }
+            Field::Name | Field::Email | Field::Phone | Field::Locale | Field::Timezone => {
+                Kind::Text
+            }
+        }
+    }
+
+    /// Whether users may have no value for the field
+    fn is_optional(self) -> bool {
+        matches!(
+            self,
+            Field::Phone | Field::Locale | Field::Timezone | Field::LastLoginAt | Field::LastSeenAt
+        )
+    }
+
+    /// Returns the value of the field for `user`
+    fn value(self, user: &User) -> Option<Actual<'_>> {
+        match self {
+            Field::Id => Some(Actual::Id(user.id)),
+            Field::Name => Some(Actual::Text(&user.name)),
+            Field::Email => Some(Actual::Text(&user.email)),
+            Field::Phone => user.phone.as_deref().map(Actual::Text),
+            Field::Status => Some(Actual::Status(user.status)),
+            Field::Locale => user.locale.as_deref().map(Actual::Text),
+            Field::Timezone => user.timezone.as_deref().map(Actual::Text),
+            Field::CreatedAt => Some(Actual::Time(user.created_at)),
+            Field::UpdatedAt => Some(Actual::Time(user.updated_at)),
+            Field::LastLoginAt => user.last_login_at.map(Actual::Time),
+            Field::LastSeenAt => user.last_seen_at.map(Actual::Time),
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A comparison operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// `eq`
+    Eq,
+    /// `ne`
+    Ne,
+    /// `gt`
+    Gt,
+    /// `ge`
+    Ge,
+    /// `lt`
+    Lt,
+    /// `le`
+    Le,
+}
+
+impl Op {
+    fn parse(word: &str) -> Option<Self> {
+        match word.to_ascii_lowercase().as_str() {
+            "eq" => Some(Op::Eq),
+            "ne" => Some(Op::Ne),
+            "gt" => Some(Op::Gt),
+            "ge" => Some(Op::Ge),
+            "lt" => Some(Op::Lt),
+            "le" => Some(Op::Le),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Op::Eq => "eq",
+            Op::Ne => "ne",
+            Op::Gt => "gt",
+            Op::Ge => "ge",
+            Op::Lt => "lt",
+            Op::Le => "le",
+        }
+    }
+
+    fn is_ordering(self) -> bool {
+        !matches!(self, Op::Eq | Op::Ne)
+    }
+
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Op::Eq => ordering.is_eq(),
+            Op::Ne => ordering.is_ne(),
+            Op::Gt => ordering.is_gt(),
+            Op::Ge => ordering.is_ge(),
+            Op::Lt => ordering.is_lt(),
+            Op::Le => ordering.is_le(),
+        }
+    }
+}
+
+/// A string function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    /// `contains(field, 'text')`
+    Contains,
+    /// `startswith(field, 'text')`
+    StartsWith,
+    /// `endswith(field, 'text')`
+    EndsWith,
+}
+
+impl Function {
+    fn parse(word: &str) -> Option<Self> {
+        match word.to_ascii_lowercase().as_str() {
+            "contains" => Some(Function::Contains),
+            "startswith" => Some(Function::StartsWith),
+            "endswith" => Some(Function::EndsWith),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Function::Contains => "contains",
+            Function::StartsWith => "startswith",
+            Function::EndsWith => "endswith",
+        }
+    }
+
+    fn holds(self, value: &str, text: &str) -> bool {
+        match self {
+            Function::Contains => value.contains(text),
+            Function::StartsWith => value.starts_with(text),
+            Function::EndsWith => value.ends_with(text),
+        }
+    }
+}
+
+/// A value compared against, of the type of the field it is compared with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// A user ID
+    Id(Uuid),
+    /// A string
+    Text(String),
+    /// An account status
+    Status(UserStatus),
+    /// A point in time
+    Time(DateTime<Utc>),
+}
+
+/// A user's value of a field, borrowed from the user
+enum Actual<'a> {
+    Id(Uuid),
+    Text(&'a str),
+    Status(UserStatus),
+    Time(DateTime<Utc>),
+}
+
+impl Actual<'_> {
+    /// Orders the user's value relative to `value`, if they have a type in
+    /// common
+    fn compare(&self, value: &Value) -> Option<Ordering> {
+        match (self, value) {
+            (Actual::Id(a), Value::Id(b)) => Some(a.cmp(b)),
+            (Actual::Text(a), Value::Text(b)) => Some((*a).cmp(b.as_str())),
+            (Actual::Status(a), Value::Status(b)) => Some(a.as_str().cmp(b.as_str())),
+            (Actual::Time(a), Value::Time(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+/// A parsed filter expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    /// Both hold
+    And(Box<Expr>, Box<Expr>),
+    /// Either holds
+    Or(Box<Expr>, Box<Expr>),
+    /// The expression does not hold
+    Not(Box<Expr>),
+    /// `field op value`; `None` is `null`
+    Compare {
+        /// Field compared
+        field: Field,
+        /// How it is compared
+        op: Op,
+        /// What it is compared with
+        value: Option<Value>,
+    },
+    /// `function(field, 'text')`
+    Call {
+        /// String function applied
+        function: Function,
+        /// Text field it is applied to
+        field: Field,
+        /// Text looked for
+        text: String,
+    },
+}
+
+impl Expr {
+    fn matches(&self, user: &User) -> bool {
+        match self {
+            Expr::And(left, right) => left.matches(user) && right.matches(user),
+            Expr::Or(left, right) => left.matches(user) || right.matches(user),
+            Expr::Not(expr) => !expr.matches(user),
+            Expr::Compare { field, op, value } => match (field.value(user), value) {
+                (Some(actual), Some(value)) => actual
+                    .compare(value)
+                    .is_some_and(|ordering| op.holds(ordering)),
+                (actual, value) => match op {
+                    Op::Eq => actual.is_none() && value.is_none(),
+                    Op::Ne => actual.is_some() || value.is_some(),
+                    _ => false,
+                },
+            },
+            Expr::Call {
+                function,
+                field,
+                text,
+            } => match field.value(user) {
+                Some(Actual::Text(value)) => function.holds(value, text),
+                _ => false,
+            },
+        }
+    }
+}
+
+/// A filter over users
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter(Expr);
+
+impl Filter {
+    /// Parses a filter expression
+    ///
+    /// # Errors
+    ///
+    /// Returns the message describing the first mistake in `input`.
+    pub fn parse(input: &str) -> Result<Self, Message> {
+        if input.chars().count() > MAX_LENGTH {
+            return Err(Message::new("filter.too_long").with("max", MAX_LENGTH));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            next: 0,
+            depth: 0,
+        };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(Self(expr)),
+            Some(token) => Err(token.unexpected()),
+        }
+    }
+
+    /// Returns the parsed expression
+    pub fn expr(&self) -> &Expr {
+        &self.0
+    }
+
+    /// Whether `user` passes the filter
+    pub fn matches(&self, user: &User) -> bool {
+        self.0.matches(user)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    Open,
+    Close,
+    Comma,
+    Text(String),
+    Word(String),
+}
+
+/// A token and its 1-based character position in the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    kind: TokenKind,
+    position: usize,
+}
+
+impl Token {
+    fn unexpected(&self) -> Message {
+        let token = match &self.kind {
+            TokenKind::Open => "(".to_string(),
+            TokenKind::Close => ")".to_string(),
+            TokenKind::Comma => ",".to_string(),
+            TokenKind::Text(text) => format!("'{}'", text.replace('\'', "''")),
+            TokenKind::Word(word) => word.clone(),
+        };
+        Message::new("filter.unexpected_token")
+            .with("token", token)
+            .with("position", self.position)
+    }
+
+    /// Returns the word, if the token is one
+    fn word(&self) -> Option<&str> {
+        match &self.kind {
+            TokenKind::Word(word) => Some(word),
+            _ => None,
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        self.word()
+            .is_some_and(|word| word.eq_ignore_ascii_case(keyword))
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, Message> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().zip(1..).peekable();
+    while let Some((c, position)) = chars.next() {
+        let kind = match c {
+            c if c.is_whitespace() => continue,
+            '(' => TokenKind::Open,
+            ')' => TokenKind::Close,
+            ',' => TokenKind::Comma,
+            '\'' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some(('\'', _)) if chars.peek().is_some_and(|(c, _)| *c == '\'') => {
+                            chars.next();
+                            text.push('\'');
+                        }
+                        Some(('\'', _)) => break,
+                        Some((c, _)) => text.push(c),
+                        None => {
+                            return Err(Message::new("filter.unterminated_string")
+                                .with("position", position))
+                        }
+                    }
+                }
+                TokenKind::Text(text)
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some((c, _)) = chars.next_if(|(c, _)| !is_delimiter(*c)) {
+                    word.push(c);
+                }
+                TokenKind::Word(word)
+            }
+        };
+        tokens.push(Token { kind, position });
+    }
+    Ok(tokens)
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | ',' | '\'')
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn advance(&mut self) -> Result<Token, Message> {
+        let token = self
+            .tokens
+            .get(self.next)
+            .cloned()
+            .ok_or_else(|| Message::new("filter.unexpected_end"))?;
+        self.next += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> Result<(), Message> {
+        let token = self.advance()?;
+        if token.kind == kind {
+            Ok(())
+        } else {
+            Err(token.unexpected())
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek().is_some_and(|token| token.is_keyword(keyword));
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn nest(&mut self) -> Result<(), Message> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(Message::new("filter.too_deep").with("max", MAX_DEPTH));
+        }
+        Ok(())
+    }
+
+    fn or(&mut self) -> Result<Expr, Message> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, Message> {
+        let mut expr = self.unary()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, Message> {
+        if self.keyword("not") {
+            self.nest()?;
+            let expr = Expr::Not(Box::new(self.unary()?));
+            self.depth -= 1;
+            return Ok(expr);
+        }
+        let token = self.advance()?;
+        match &token.kind {
+            TokenKind::Open => {
+                self.nest()?;
+                let expr = self.or()?;
+                self.expect(TokenKind::Close)?;
+                self.depth -= 1;
+                Ok(expr)
+            }
+            TokenKind::Word(word) => {
+                let call = self.peek().is_some_and(|next| next.kind == TokenKind::Open);
+                match Function::parse(word) {
+                    Some(function) if call => self.call(function),
+                    _ => self.comparison(&token),
+                }
+            }
+            _ => Err(token.unexpected()),
+        }
+    }
+
+    fn field(token: &Token) -> Result<Field, Message> {
+        let Some(word) = token.word() else {
+            return Err(token.unexpected());
+        };
+        Field::ALL
+            .into_iter()
+            .find(|field| field.as_str() == word)
+            .ok_or_else(|| Message::new("filter.unknown_field").with("field", word))
+    }
+
+    fn comparison(&mut self, token: &Token) -> Result<Expr, Message> {
+        let field = Self::field(token)?;
+        let token = self.advance()?;
+        let op = token
+            .word()
+            .and_then(Op::parse)
+            .ok_or_else(|| token.unexpected())?;
+        let token = self.advance()?;
+        let value = if token.is_keyword("null") {
+            if !field.is_optional() {
+                return Err(invalid_value(field, "null"));
+            }
+            None
+        } else {
+            Some(literal(field, &token)?)
+        };
+        let ordered = matches!(field.kind(), Kind::Text | Kind::Time);
+        if op.is_ordering() && (value.is_none() || !ordered) {
+            return Err(Message::new("filter.unsupported_operator")
+                .with("operator", op.as_str())
+                .with("field", field));
+        }
+        Ok(Expr::Compare { field, op, value })
+    }
+
+    fn call(&mut self, function: Function) -> Result<Expr, Message> {
+        self.expect(TokenKind::Open)?;
+        let field = Self::field(&self.advance()?)?;
+        if field.kind() != Kind::Text {
+            return Err(Message::new("filter.unsupported_operator")
+                .with("operator", function.as_str())
+                .with("field", field));
+        }
+        self.expect(TokenKind::Comma)?;
+        let token = self.advance()?;
+        let TokenKind::Text(text) = token.kind else {
+            return Err(token.unexpected());
+        };
+        self.expect(TokenKind::Close)?;
+        Ok(Expr::Call {
+            function,
+            field,
+            text,
+        })
+    }
+}
+
+/// Reads the value `token` gives for `field`
+fn literal(field: Field, token: &Token) -> Result<Value, Message> {
+    let (text, quoted) = match &token.kind {
+        TokenKind::Text(text) => (text.as_str(), true),
+        TokenKind::Word(word) => (word.as_str(), false),
+        _ => return Err(token.unexpected()),
+    };
+    let value = match field.kind() {
+        Kind::Text if quoted => Some(Value::Text(text.to_string())),
+        Kind::Text => None,
+        Kind::Id => text.parse().ok().map(Value::Id),
+        Kind::Status => [
+            UserStatus::Pending,
+            UserStatus::Active,
+            UserStatus::Suspended,
+            UserStatus::Deactivated,
+        ]
+        .into_iter()
+        .find(|status| status.as_str() == text)
+        .map(Value::Status),
+        Kind::Time => time(text).map(Value::Time),
+    };
+    value.ok_or_else(|| invalid_value(field, text))
+}
+
+/// Parses a date, as midnight UTC, or an RFC 3339 timestamp
+fn time(text: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        return date.and_hms_opt(0, 0, 0).map(|time| time.and_utc());
+    }
+    DateTime::parse_from_rfc3339(text)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+fn invalid_value(field: Field, value: &str) -> Message {
+    Message::new("filter.invalid_value")
+        .with("value", value)
+        .with("field", field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(filter: &str, user: &User) -> bool {
+        Filter::parse(filter).unwrap().matches(user)
+    }
+
+    fn error(filter: &str) -> (&'static str, String) {
+        let message = Filter::parse(filter).unwrap_err();
+        (message.key(), message.render(crate::i18n::Language::En))
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let filter = Filter::parse("name eq 'a' or not name eq 'b' and phone eq null").unwrap();
+        let compare = |name: &str| Expr::Compare {
+            field: Field::Name,
+            op: Op::Eq,
+            value: Some(Value::Text(name.to_string())),
+        };
+        let expected = Expr::Or(
+            Box::new(compare("a")),
+            Box::new(Expr::And(
+                Box::new(Expr::Not(Box::new(compare("b")))),
+                Box::new(Expr::Compare {
+                    field: Field::Phone,
+                    op: Op::Eq,
+                    value: None,
+                }),
+            )),
+        );
+        assert_eq!(filter.expr(), &expected);
+    }
+
+    #[test]
+    fn test_matches() {
+        let john = User::new(
+            "John",
+            "john@example.com",
+            time("2024-03-01T12:00:00Z").unwrap(),
+        );
+        assert!(matches(
+            "name eq 'John' and created_at gt 2024-01-01",
+            &john
+        ));
+        assert!(!matches("name eq 'john'", &john));
+        assert!(matches("name EQ 'John'", &john));
+        assert!(matches("created_at le '2024-03-01T12:00:00Z'", &john));
+        assert!(matches("not (created_at lt 2024-03-01)", &john));
+        assert!(matches(
+            "startswith(email, 'john@') and contains(name, 'oh')",
+            &john
+        ));
+        assert!(!matches("endswith(email, '.org')", &john));
+        assert!(matches(
+            "status eq 'active' or status eq deactivated",
+            &john
+        ));
+        assert!(matches(&format!("id eq {}", john.id), &john));
+
+        // Missing values only equal null
+        assert!(matches("phone eq null and phone ne '+14155552671'", &john));
+        assert!(!matches("phone gt '+1' or startswith(phone, '+1')", &john));
+        let jane = User::new("Jane", "jane@example.com", time("2023-06-01").unwrap())
+            .with_phone("+14155552671");
+        assert!(matches("phone ne null and phone eq '+14155552671'", &jane));
+        assert!(matches("name eq 'O''Brien' or name gt 'Ja'", &jane));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            error("name eq 'John' and"),
+            ("filter.unexpected_end", "Filter ends unexpectedly".into())
+        );
+        assert_eq!(
+            error("name eq 'John' nor phone eq null"),
+            (
+                "filter.unexpected_token",
+                "Unexpected 'nor' at position 16 of the filter".into()
+            )
+        );
+        assert_eq!(error("age gt 3").0, "filter.unknown_field");
+        assert_eq!(error("name eq John").0, "filter.invalid_value");
+        assert_eq!(error("created_at gt yesterday").0, "filter.invalid_value");
+        assert_eq!(error("status eq 'gone'").0, "filter.invalid_value");
+        assert_eq!(error("name eq null").0, "filter.invalid_value");
+        assert_eq!(error("status gt 'active'").0, "filter.unsupported_operator");
+        assert_eq!(error("phone lt null").0, "filter.unsupported_operator");
+        assert_eq!(
+            error("contains(status, 'act')").0,
+            "filter.unsupported_operator"
+        );
+        assert_eq!(error("name eq 'John").0, "filter.unterminated_string");
+        assert_eq!(error("(name eq 'a'").0, "filter.unexpected_end");
+        assert_eq!(error("").0, "filter.unexpected_end");
+
+        let deep = format!("{}name eq 'a'{}", "(".repeat(17), ")".repeat(17));
+        assert_eq!(error(&deep).0, "filter.too_deep");
+        let long = format!("name eq '{}'", "a".repeat(MAX_LENGTH));
+        assert_eq!(error(&long).0, "filter.too_long");
+    }
+}
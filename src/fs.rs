@@ -0,0 +1,92 @@
+//! Files, through tokio or the standard library
+//!
+//! With the `server` feature, file operations run on tokio's blocking
+//! pool. Without it, tokio has no file system support; WASI hosts expose
+//! their preopened directories through `std::fs` instead, whose calls
+//! block the task. Only operator actions touch files, snapshots, migrations
+//! and request capture, so the wrappers below call it directly.
+
+#[cfg(feature = "server")]
+pub(crate) use tokio::fs::{create_dir_all, read, rename, try_exists, write, File, OpenOptions};
+
+#[cfg(not(feature = "server"))]
+pub(crate) use blocking::*;
+
+#[cfg(not(feature = "server"))]
+mod blocking {
+    use std::io::{self, Write as _};
+    use std::path::Path;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use tokio::io::AsyncWrite;
+
+    pub(crate) async fn create_dir_all(path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    pub(crate) async fn read(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    pub(crate) async fn rename(from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    pub(crate) async fn try_exists(path: impl AsRef<Path>) -> io::Result<bool> {
+        path.as_ref().try_exists()
+    }
+
+    pub(crate) async fn write(
+        path: impl AsRef<Path>,
+        contents: impl AsRef<[u8]>,
+    ) -> io::Result<()> {
+        std::fs::write(path, contents)
+    }
+
+    /// An open file, written to as it is called
+    #[derive(Debug)]
+    pub(crate) struct File(std::fs::File);
+
+    impl AsyncWrite for File {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Ready(self.get_mut().0.write(buf))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(self.get_mut().0.flush())
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.poll_flush(cx)
+        }
+    }
+
+    /// Options for opening a [`File`]
+    #[derive(Debug)]
+    pub(crate) struct OpenOptions(std::fs::OpenOptions);
+
+    impl OpenOptions {
+        pub(crate) fn new() -> Self {
+            Self(std::fs::OpenOptions::new())
+        }
+
+        pub(crate) fn create(&mut self, create: bool) -> &mut Self {
+            self.0.create(create);
+            self
+        }
+
+        pub(crate) fn append(&mut self, append: bool) -> &mut Self {
+            self.0.append(append);
+            self
+        }
+
+        pub(crate) async fn open(&self, path: impl AsRef<Path>) -> io::Result<File> {
+            self.0.open(path).map(File)
+        }
+    }
+}
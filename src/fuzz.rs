@@ -0,0 +1,331 @@
+//! Fuzzing the request validation path
+//!
+//! With the `fuzz` feature, [`CreateUserRequest`] and [`UpdateUserRequest`]
+//! implement [`Arbitrary`], and [`Operation`] sequences can be driven
+//! through the full router with [`run`]. Generated fields are either
+//! arbitrary strings or built from near-valid pieces (names with
+//! combining marks, emails with odd domains, phone numbers with stray
+//! characters), so inputs reach past the first validation rule.
+//!
+//! A cargo-fuzz target needs only a few lines:
+//!
+//! ```ignore
+//! #![no_main]
+//! use libfuzzer_sys::fuzz_target;
+//! use rust_api::fuzz::{run_blocking, Operation};
+//!
+//! fuzz_target!(|operations: Vec<Operation>| {
+//!     if let Err(finding) = run_blocking(&operations) {
+//!         panic!("{}", finding);
+//!     }
+//! });
+//! ```
+//!
+//! The repository's own target is in `fuzz/fuzz_targets/requests.rs`.
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+};
+use serde_json::Value;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use crate::models::{CreateUserRequest, UpdateUserRequest, UserStatus};
+use crate::service::App;
+use crate::validation::custom::CustomFields;
+use crate::AppState;
+
+/// Largest number of custom fields generated for one request
+const MAX_CUSTOM_FIELDS: usize = 4;
+
+const NAMES: &[&str] = &[
+    "Jane Doe",
+    "José Álvarez",
+    "O'Brien",
+    "Zoë  Smith",
+    "李小龍",
+    "e\u{301}mile",
+    "admin",
+    "\u{202e}evil",
+    "",
+    " ",
+];
+const LOCAL_PARTS: &[&str] = &["jane", "jane.doe+tag", "JANE", "\"quoted\"", "a..b", ""];
+const DOMAINS: &[&str] = &[
+    "example.com",
+    "bücher.example",
+    "localhost",
+    "[127.0.0.1]",
+    "example..com",
+    "",
+];
+const PHONES: &[&str] = &[
+    "+14155552671",
+    "(415) 555-2671",
+    "+44 20 7946 0958",
+    "+1",
+    "0",
+];
+const LOCALES: &[&str] = &["en", "en-US", "zh-Hant-TW", "x-private", "en_US", "e"];
+const TIMEZONES: &[&str] = &[
+    "UTC",
+    "Europe/Berlin",
+    "America/New_York",
+    "Mars/Olympus",
+    "utc",
+];
+
+/// Picks an arbitrary string or, more often, one built from `pieces`
+fn text(u: &mut Unstructured<'_>, pieces: &[&str]) -> Result<String> {
+    if u.ratio(1, 4)? {
+        return String::arbitrary(u);
+    }
+    let mut text = u.choose(pieces)?.to_string();
+    if u.ratio(1, 4)? {
+        text.push(char::arbitrary(u)?);
+    }
+    Ok(text)
+}
+
+fn email(u: &mut Unstructured<'_>) -> Result<String> {
+    if u.ratio(1, 4)? {
+        return String::arbitrary(u);
+    }
+    Ok(format!("{}@{}", text(u, LOCAL_PARTS)?, text(u, DOMAINS)?))
+}
+
+fn optional(u: &mut Unstructured<'_>, pieces: &[&str]) -> Result<Option<String>> {
+    match bool::arbitrary(u)? {
+        true => text(u, pieces).map(Some),
+        false => Ok(None),
+    }
+}
+
+/// A change to an optional field: unchanged, removed or set
+fn change(u: &mut Unstructured<'_>, pieces: &[&str]) -> Result<Option<Option<String>>> {
+    Ok(match u.int_in_range(0..=2)? {
+        0 => None,
+        1 => Some(None),
+        _ => Some(Some(text(u, pieces)?)),
+    })
+}
+
+fn value(u: &mut Unstructured<'_>) -> Result<Value> {
+    Ok(match u.int_in_range(0..=4)? {
+        0 => Value::Null,
+        1 => Value::Bool(bool::arbitrary(u)?),
+        2 => Value::from(i64::arbitrary(u)?),
+        3 => serde_json::Number::from_f64(f64::arbitrary(u)?).map_or(Value::Null, Value::Number),
+        _ => Value::String(String::arbitrary(u)?),
+    })
+}
+
+fn custom(u: &mut Unstructured<'_>) -> Result<CustomFields> {
+    let mut fields = CustomFields::new();
+    for _ in 0..u.int_in_range(0..=MAX_CUSTOM_FIELDS)? {
+        fields.insert(String::arbitrary(u)?, value(u)?);
+    }
+    Ok(fields)
+}
+
+impl<'a> Arbitrary<'a> for UserStatus {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        u.choose(&[
+            UserStatus::Pending,
+            UserStatus::Active,
+            UserStatus::Suspended,
+            UserStatus::Deactivated,
+        ])
+        .copied()
+    }
+}
+
+impl<'a> Arbitrary<'a> for CreateUserRequest {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            name: text(u, NAMES)?,
+            email: email(u)?,
+            phone: optional(u, PHONES)?,
+            status: UserStatus::arbitrary(u)?,
+            locale: optional(u, LOCALES)?,
+            timezone: optional(u, TIMEZONES)?,
+            custom: custom(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for UpdateUserRequest {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            name: optional(u, NAMES)?,
+            email: match bool::arbitrary(u)? {
+                true => Some(email(u)?),
+                false => None,
+            },
+            phone: change(u, PHONES)?,
+            locale: change(u, LOCALES)?,
+            timezone: change(u, TIMEZONES)?,
+            custom: custom(u)?,
+        })
+    }
+}
+
+/// A request sent to the router
+///
+/// Operations on an existing user pick one of the users created so far by
+/// index, wrapping around, or an unknown ID if none was created.
+#[derive(Debug, Clone, Arbitrary)]
+pub enum Operation {
+    /// `POST /api/v1/users`
+    Create(CreateUserRequest),
+    /// `POST /api/v1/users` with an arbitrary body, which need not be JSON
+    CreateRaw(Vec<u8>),
+    /// `PUT /api/v1/users/:id`
+    Update(u8, UpdateUserRequest),
+    /// `GET /api/v1/users/:id`
+    Get(u8),
+    /// `DELETE /api/v1/users/:id`
+    Delete(u8),
+}
+
+/// An operation answered with a server error
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("{operation:?} was answered with {status}: {body}")]
+pub struct Finding {
+    /// The operation
+    pub operation: Box<Operation>,
+    /// The status it was answered with
+    pub status: StatusCode,
+    /// The response body
+    pub body: String,
+}
+
+/// Sends `operations` in order to a router over an empty store
+///
+/// Client errors are expected for most generated input; only server
+/// errors are findings. Panics in the application propagate, as fuzzers
+/// expect.
+///
+/// # Errors
+///
+/// Returns the first operation answered with a `5xx` status.
+pub async fn run(operations: &[Operation]) -> std::result::Result<(), Finding> {
+    let app = App::new(AppState::new());
+    let mut users: Vec<Uuid> = Vec::new();
+    for operation in operations {
+        let user = |index: &u8| match users.len() {
+            0 => Uuid::nil(),
+            len => users[usize::from(*index) % len],
+        };
+        let (method, uri, body) = match operation {
+            Operation::Create(request) => {
+                (Method::POST, "/api/v1/users".to_string(), json(request))
+            }
+            Operation::CreateRaw(bytes) => {
+                (Method::POST, "/api/v1/users".to_string(), bytes.clone())
+            }
+            Operation::Update(index, request) => (
+                Method::PUT,
+                format!("/api/v1/users/{}", user(index)),
+                json(request),
+            ),
+            Operation::Get(index) => (
+                Method::GET,
+                format!("/api/v1/users/{}", user(index)),
+                Vec::new(),
+            ),
+            Operation::Delete(index) => (
+                Method::DELETE,
+                format!("/api/v1/users/{}", user(index)),
+                Vec::new(),
+            ),
+        };
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .expect("generated requests are well-formed");
+        let response = app
+            .clone()
+            .oneshot(request)
+            .await
+            .unwrap_or_else(|never| match never {});
+        let status = response.status();
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|location| location.rsplit('/').next())
+            .and_then(|id| id.parse().ok());
+        if let Some(id) = location {
+            users.push(id);
+        }
+        if status.is_server_error() {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap_or_default();
+            return Err(Finding {
+                operation: Box::new(operation.clone()),
+                status,
+                body: String::from_utf8_lossy(&body).into_owned(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Runs [`run`] on a single-threaded runtime, for fuzz targets that are
+/// synchronous
+///
+/// # Errors
+///
+/// Returns the first operation answered with a `5xx` status.
+pub fn run_blocking(operations: &[Operation]) -> std::result::Result<(), Finding> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("a current-thread runtime can always be built")
+        .block_on(run(operations))
+}
+
+fn json(value: &impl serde::Serialize) -> Vec<u8> {
+    serde_json::to_vec(value).expect("requests serialize to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_generated_operations_get_no_server_errors() {
+        // A fixed pseudo-random input, so the test is deterministic
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let bytes: Vec<u8> = (0..8 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let mut u = Unstructured::new(&bytes);
+        let mut created = 0;
+        while !u.is_empty() {
+            let operations: Vec<Operation> = match Vec::arbitrary(&mut u) {
+                Ok(operations) => operations,
+                Err(_) => break,
+            };
+            created += operations
+                .iter()
+                .filter(|operation| matches!(operation, Operation::Create(_)))
+                .count();
+            if let Err(finding) = run(&operations).await {
+                panic!("{}", finding);
+            }
+        }
+        assert!(created > 0);
+    }
+}
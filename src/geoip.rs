@@ -0,0 +1,82 @@
+//! Country and city lookup for client addresses
+//!
+//! With `APP_GEOIP_DATABASE` pointing at a MaxMind GeoIP2 or GeoLite2 City
+//! database, audit entries record where a request came from. Lookups run
+//! against the database loaded at startup; without one, addresses are not
+//! located.
+
+use std::net::IpAddr;
+use std::path::Path;
+
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+
+use crate::client_ip::Cidr;
+use crate::models::Location;
+
+/// Source of locations for client addresses
+#[derive(Debug, Default)]
+pub enum GeoIp {
+    /// Addresses are not located
+    #[default]
+    Disabled,
+    /// A MaxMind City database
+    MaxMind(Reader<Vec<u8>>),
+    /// Fixed locations by address range, first match wins
+    Table(Vec<(Cidr, Location)>),
+}
+
+impl GeoIp {
+    /// Loads a MaxMind database file
+    pub fn open(path: &Path) -> Result<Self, MaxMindDBError> {
+        Reader::open_readfile(path).map(GeoIp::MaxMind)
+    }
+
+    /// Returns the location of `ip`, if known
+    pub fn locate(&self, ip: IpAddr) -> Option<Location> {
+        match self {
+            GeoIp::Disabled => None,
+            GeoIp::MaxMind(reader) => {
+                let record: geoip2::City = reader.lookup(ip).ok()?;
+                let location = Location {
+                    country: record
+                        .country
+                        .and_then(|country| country.iso_code)
+                        .map(str::to_string),
+                    city: record
+                        .city
+                        .and_then(|city| city.names)
+                        .and_then(|names| names.get("en").map(|name| name.to_string())),
+                };
+                (location.country.is_some() || location.city.is_some()).then_some(location)
+            }
+            GeoIp::Table(ranges) => ranges
+                .iter()
+                .find(|(range, _)| range.contains(&ip))
+                .map(|(_, location)| location.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate() {
+        assert_eq!(GeoIp::Disabled.locate("192.0.2.1".parse().unwrap()), None);
+
+        let berlin = Location {
+            country: Some("DE".to_string()),
+            city: Some("Berlin".to_string()),
+        };
+        let table = GeoIp::Table(vec![("192.0.2.0/24".parse().unwrap(), berlin.clone())]);
+        assert_eq!(table.locate("192.0.2.1".parse().unwrap()), Some(berlin));
+        assert_eq!(table.locate("198.51.100.1".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_open_rejects_invalid_database() {
+        assert!(GeoIp::open(Path::new("Cargo.toml")).is_err());
+        assert!(GeoIp::open(Path::new("missing.mmdb")).is_err());
+    }
+}
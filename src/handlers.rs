@@ -4,16 +4,52 @@
 //! incoming requests and return appropriate responses.
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    Json,
+    extract::{MatchedPath, Path, Query, State},
+    http::{
+        header::{ALLOW, CONTENT_DISPOSITION, CONTENT_TYPE, LOCATION},
+        Method, StatusCode, Uri,
+    },
+    response::{IntoResponse, Response},
+    Extension, Json,
 };
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::error::ApiError;
-use crate::models::{CreateUserRequest, UpdateUserRequest, User, UserResponse, UsersResponse};
-use crate::AppState;
+use crate::auth::{Principal, Scope};
+use crate::capture::{CaptureSettings, CaptureStatus};
+use crate::dry_run;
+use crate::duplicates;
+use crate::error::{ApiError, ErrorResponse};
+use crate::events::{Event, FieldDiff};
+use crate::extract::{OperationId, RemovedUserId, UserId};
+use crate::filter::Filter;
+use crate::health::{HealthStatus, ReadinessReport};
+use crate::i18n::{ActiveLocale, Message};
+use crate::jobs::{Operation, OperationKind, OperationResult, OperationStatus};
+use crate::mock;
+use crate::models::{
+    AuditEntry, AuditQuery, CreateUserRequest, DuplicateGroup, DuplicatesQuery, GenerateUsersQuery,
+    HealthReport, Impersonation, ImportFailure, ImportReport, ImportUsersRequest, ListUsersQuery,
+    LogLevel, MergePrecedence, MergeUsersRequest, RestoreUsersRequest, StorageError, StorageFull,
+    Tombstone, TrashedUser, UpdateUserRequest, User, UserStatus,
+};
+use crate::paging::{self, Page};
+use crate::rate_limit::{RateLimit, RateLimits};
+use crate::replication::{self, Changes, ChangesQuery, ReplicationSnapshot, Role};
+use crate::reports::{self, Report};
+use crate::reserved::ReservedLists;
+use crate::responses::{Accepted, ApiResponse, Created, Pagination};
+use crate::routes::RoutesResponse;
+use crate::slo::SloReport;
+use crate::snapshot;
+use crate::snapshot::{RestorePlan, RestoreRequest, SnapshotInfo};
+use crate::undo::X_UNDO_TOKEN;
+use crate::usage::UsageReport;
+use crate::validation::request::{NewUser, UserChanges};
+use crate::validation::{phone, Validate};
+use crate::{AppState, Storage};
 
 /// Health check endpoint
 ///
@@ -23,6 +59,12 @@ use crate::AppState;
 /// # Returns
 ///
 /// Returns a JSON response with status information
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "health",
+    responses((status = 200, description = "Service is healthy", body = serde_json::Value))
+)]
 pub async fn health_check() -> Json<serde_json::Value> {
     Json(serde_json::json!({
         "status": "healthy",
@@ -31,48 +73,264 @@ pub async fn health_check() -> Json<serde_json::Value> {
     }))
 }
 
+/// Deep health check endpoint
+///
+/// Reports the memory used by the user store alongside the service status,
+/// the worst status of the registered health checks; the store's check
+/// turns `degraded` once it is nearly full. The status code is always 200,
+/// so load balancers keep routing to a nearly full instance.
+#[utoipa::path(
+    get,
+    path = "/health/deep",
+    tag = "health",
+    responses((status = 200, description = "Service status and storage usage", body = HealthReport))
+)]
+pub async fn deep_health_check(State(state): State<AppState>) -> Json<HealthReport> {
+    let report = state.health.run(&state).await;
+    let storage = state.storage.read().await.usage();
+    Json(HealthReport {
+        status: report.status,
+        service: "rust-api".to_string(),
+        timestamp: state.clock.now(),
+        storage,
+    })
+}
+
+/// Readiness check endpoint
+///
+/// Runs every check registered in the [`crate::health::HealthRegistry`].
+/// Answers 503 while any check is unhealthy, so load balancers stop routing
+/// to the instance; degraded checks still count as ready.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, description = "Ready; the report may still show degraded checks", body = ReadinessReport),
+        (status = 503, description = "A check is unhealthy", body = ReadinessReport)
+    )
+)]
+pub async fn readiness_check(State(state): State<AppState>) -> (StatusCode, Json<ReadinessReport>) {
+    let report = state.health.run(&state).await;
+    let status = if report.status == HealthStatus::Unhealthy {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    (status, Json(report))
+}
+
+/// Fallback for requests that match no route
+///
+/// Returns the standard JSON error body instead of an empty 404.
+pub async fn not_found(uri: Uri) -> ApiError {
+    ApiError::NotFound(Message::new("route.not_found").with("path", uri.path()))
+}
+
+/// Fallback for requests whose path matches a route but whose method does not
+///
+/// The `Allow` header lists the methods the route was added with (see
+/// [`crate::routes::RouteTable::allow`]). `OPTIONS` requests are answered
+/// with that header and `204 No Content`. Routers not built by
+/// [`crate::routes::RouterBuilder`] get the header axum derives instead.
+pub async fn method_not_allowed(
+    State(state): State<AppState>,
+    method: Method,
+    uri: Uri,
+    matched: Option<MatchedPath>,
+) -> Response {
+    let allow = matched.and_then(|matched| state.routes.allow(matched.as_str()));
+    if let Some(allow) = allow.clone().filter(|_| method == Method::OPTIONS) {
+        return (StatusCode::NO_CONTENT, [(ALLOW, allow)]).into_response();
+    }
+    let mut response = ApiError::MethodNotAllowed(
+        Message::new("route.method_not_allowed")
+            .with("method", method)
+            .with("path", uri.path()),
+    )
+    .into_response();
+    if let Some(allow) = allow {
+        response.headers_mut().insert(ALLOW, allow);
+    }
+    response
+}
+
 /// Lists all users in the system
 ///
+/// Results can be narrowed with query parameters: `phone` accepts any
+/// format that normalizes to the stored E.164 number, `status` restricts
+/// results to one lifecycle status, `inactive_since` returns users with
+/// no recorded activity since the given time, and `filter` takes an
+/// expression in the [filter language](crate::filter). `page` and
+/// `per_page`, or a `Range: items=0-49` header, return one
+/// [page](crate::paging) of the matches.
+///
 /// # Arguments
 ///
 /// * `State(state)` - Application state containing the storage
+/// * `Query(query)` - Optional filters and page
 ///
 /// # Returns
 ///
-/// Returns a JSON response containing all users and the total count
-pub async fn list_users(State(state): State<AppState>) -> Result<Json<UsersResponse>, ApiError> {
+/// Returns a JSON response containing the matching users and the total
+/// count, which is also sent in `X-Total-Count`
+#[utoipa::path(
+    get,
+    path = "/api/v1/users",
+    tag = "users",
+    security(("bearer_token" = ["users:read"])),
+    params(ListUsersQuery, ("include_computed" = Option<bool>, Query, description = "Add fields derived from the stored ones, such as `initials`"), ("X-Consistency-Token" = Option<String>, Header, description = "Token from an earlier write that the response must include")),
+    responses(
+        (status = 200, description = "Matching users", body = ApiResponse<Vec<User>>,
+            headers(
+                ("ETag" = String, description = "Version of the collection and representation"),
+                ("X-Total-Count" = usize, description = "Number of matching users on all pages"),
+                ("Link" = String, description = "First, previous, next and last pages, when paged")
+            )),
+        (status = 206, description = "The users in the requested Range, e.g. items=0-49", body = ApiResponse<Vec<User>>,
+            headers(
+                ("Content-Range" = String, description = "The items returned and the number of matching users, e.g. items 0-49/180"),
+                ("X-Total-Count" = usize, description = "Number of matching users")
+            )),
+        (status = 304, description = "Unchanged since the ETag given in If-None-Match"),
+        (status = 400, description = "Invalid filter, page or consistency token", body = ErrorResponse),
+        (status = 416, description = "The Range starts past the last matching user", body = ErrorResponse,
+            headers(("Content-Range" = String, description = "The number of matching users, e.g. items */180"))),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the users:read scope", body = ErrorResponse),
+        (status = 503, description = "The consistency token's version is not available yet", body = ErrorResponse)
+    )
+)]
+pub async fn list_users(
+    State(state): State<AppState>,
+    Query(query): Query<ListUsersQuery>,
+) -> Result<ApiResponse<Vec<Arc<User>>>, ApiError> {
+    let page = Page::from_query(query.page, query.per_page)?;
+    let phone = query
+        .phone
+        .as_deref()
+        .map(|phone| phone::normalize(phone, state.config.phone_default_region))
+        .transpose()?;
+    let filter = query
+        .filter
+        .as_deref()
+        .map(Filter::parse)
+        .transpose()
+        .map_err(ApiError::BadRequest)?;
+
     let storage = state.storage.read().await;
-    let users = storage.get_all();
+    let users = match &filter {
+        Some(filter) => storage.filter(filter),
+        None => storage.get_all(),
+    };
+    let mut users: Vec<_> = users
+        .into_iter()
+        .filter(|user| phone.is_none() || user.phone == phone)
+        .filter(|user| query.status.map_or(true, |status| user.status == status))
+        .filter(|user| {
+            query
+                .inactive_since
+                .map_or(true, |since| user.last_active_at() < since)
+        })
+        .collect();
+    drop(storage);
 
-    Ok(Json(UsersResponse {
+    let total = users.len();
+    let Some(page) = page else {
+        let Some(items) = paging::requested_items() else {
+            return Ok(ApiResponse::collection(users));
+        };
+        users.sort_unstable_by_key(|user| (user.created_at, user.id));
+        let users: Vec<_> = users.drain(items.range(total)).collect();
+        let pagination = Pagination {
+            count: users.len(),
+            total,
+            ..Pagination::default()
+        };
+        return Ok(ApiResponse::new(users).with_pagination(pagination));
+    };
+    users.sort_unstable_by_key(|user| (user.created_at, user.id));
+    let users: Vec<_> = users.drain(page.range(total)).collect();
+    let pagination = Pagination {
         count: users.len(),
-        users,
-    }))
+        total,
+        page: Some(page.number),
+        per_page: Some(page.size),
+    };
+    let response = ApiResponse::new(users).with_pagination(pagination);
+    let links = match &response.links.self_link {
+        Some(self_link) => page.links(self_link, total),
+        None => Default::default(),
+    };
+    Ok(response.with_page_links(links))
 }
 
 /// Retrieves a specific user by ID
 ///
 /// # Arguments
 ///
-/// * `Path(id)` - The UUID of the user to retrieve
+/// * `UserId(id)` - The UUID of the user to retrieve
 /// * `State(state)` - Application state containing the storage
 ///
 /// # Returns
 ///
-/// Returns the user if found, or a 404 error if not found
+/// Returns the user if found, a 410 error if it was deleted or merged
+/// into another user, or a 404 error if it never existed
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/{id}",
+    tag = "users",
+    security(("bearer_token" = ["users:read"])),
+    params(
+        ("id" = Uuid, Path, description = "User ID"),
+        ("include_computed" = Option<bool>, Query, description = "Add fields derived from the stored ones, such as `initials`"),
+        ("X-Consistency-Token" = Option<String>, Header, description = "Token from an earlier write that the response must include")
+    ),
+    responses(
+        (status = 200, description = "The user", body = ApiResponse<User>),
+        (status = 400, description = "Malformed user ID or consistency token", body = ErrorResponse),
+        (status = 404, description = "No such user", body = ErrorResponse),
+        (status = 410, description = "The user was deleted, or merged into the user at Location", body = ErrorResponse,
+            headers(("Location" = String, description = "Path of the user it was merged into, if any"))),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the users:read scope", body = ErrorResponse),
+        (status = 503, description = "The consistency token's version is not available yet", body = ErrorResponse)
+    )
+)]
 pub async fn get_user(
-    Path(id): Path<Uuid>,
+    UserId(id): UserId,
     State(state): State<AppState>,
-) -> Result<Json<UserResponse>, ApiError> {
+) -> Result<Response, ApiError> {
     let storage = state.storage.read().await;
 
-    let user = storage
-        .get(&id)
-        .ok_or_else(|| ApiError::NotFound(format!("User with id {} not found", id)))?;
-
-    Ok(Json(UserResponse { user }))
+    let user = storage.get(&id);
+    state.shadow.compare(id, user.as_ref());
+    if let Some(user) = user {
+        return Ok(Json(ApiResponse::new(user)).into_response());
+    }
+    match storage.tombstone(&id) {
+        Some(Tombstone {
+            merged_into: Some(target),
+            ..
+        }) => {
+            let error = ApiError::Gone(
+                Message::new("user.merged")
+                    .with("id", id)
+                    .with("target", target),
+            );
+            let location = format!("/api/v1/users/{}", target);
+            Ok(([(LOCATION, location)], error).into_response())
+        }
+        Some(_) => Err(ApiError::Gone(Message::new("user.deleted").with("id", id))),
+        None => Err(ApiError::NotFound(
+            Message::new("user.not_found").with("id", id),
+        )),
+    }
 }
 
+/// Times a new user draws an ID before giving up on a collision
+const ID_ATTEMPTS: usize = 3;
+
 /// Creates a new user
 ///
 /// Validates the input and creates a new user with a generated UUID.
@@ -85,54 +343,112 @@ pub async fn get_user(
 ///
 /// # Returns
 ///
-/// Returns the created user with a 201 status code, or an error
-/// if validation fails or the email is already in use
+/// Returns the created user with a 201 status code and its path in the
+/// `Location` header, a 422 error listing every violated rule if
+/// validation fails or naming the reserved name or email, or a 409 error
+/// if the email is already in use
+#[utoipa::path(
+    post,
+    path = "/api/v1/users",
+    tag = "users",
+    security(("bearer_token" = ["users:write"])),
+    params(("dry_run" = Option<bool>, Query, description = "Only validate and preview the change, like `Prefer: dry-run`")),
+    request_body = CreateUserRequest,
+    responses(
+        (status = 201, description = "The created user", body = ApiResponse<User>,
+            headers(
+                ("Location" = String, description = "Path of the created user"),
+                ("X-Consistency-Token" = String, description = "Storage version including this write")
+            )),
+        (status = 400, description = "Invalid input", body = ErrorResponse),
+        (status = 422, description = "Invalid fields, listing every violated rule, or a reserved name or email", body = ErrorResponse),
+        (status = 409, description = "Email or phone already exists", body = ErrorResponse),
+        (status = 507, description = "The user store is full", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the users:write scope", body = ErrorResponse)
+    )
+)]
 pub async fn create_user(
     State(state): State<AppState>,
-    Json(payload): Json<CreateUserRequest>,
-) -> Result<(StatusCode, Json<UserResponse>), ApiError> {
-    // Validate input
-    if payload.name.trim().is_empty() {
-        return Err(ApiError::BadRequest("Name cannot be empty".to_string()));
-    }
-
-    if payload.email.trim().is_empty() {
-        return Err(ApiError::BadRequest("Email cannot be empty".to_string()));
-    }
+    Json(mut payload): Json<CreateUserRequest>,
+) -> Result<Created<ApiResponse<User>>, ApiError> {
+    state.plugins.before_create_user(&mut payload)?;
 
-    // Basic email validation
-    if !payload.email.contains('@') {
-        return Err(ApiError::BadRequest("Invalid email format".to_string()));
-    }
+    // Validate input, reporting every violated rule at once
+    let NewUser {
+        name,
+        email,
+        phone,
+        locale,
+        timezone,
+        custom,
+    } = payload.validate(&state.config)?;
+    state.reserved.check(Some(&name), Some(&email))?;
+    #[cfg(feature = "mx-lookup")]
+    verify_mx(&state, &email).await?;
 
     let mut storage = state.storage.write().await;
 
-    // Check if email already exists
-    if storage.email_exists(&payload.email) {
-        return Err(ApiError::Conflict(format!(
-            "User with email {} already exists",
-            payload.email
-        )));
-    }
-
-    // Create new user
-    let now = Utc::now();
-    let user = User {
-        id: Uuid::new_v4(),
-        name: payload.name.trim().to_string(),
-        email: payload.email.trim().to_lowercase(),
+    // Create new user; a dry run assigns no ID
+    let now = state.clock.now();
+    let dry_run = dry_run::is_requested();
+    let mut user = User {
+        id: if dry_run {
+            Uuid::nil()
+        } else {
+            state.shards.next_id(&state.ids)
+        },
+        name,
+        email,
+        phone,
+        status: payload.status,
+        locale,
+        timezone,
+        custom,
         created_at: now,
         updated_at: now,
+        last_login_at: None,
+        last_seen_at: None,
     };
 
-    // Store the user
-    if !storage.create(user.clone()) {
-        return Err(ApiError::Internal(
-            "Failed to create user due to ID collision".to_string(),
-        ));
+    // Refuse taken emails and phones before evicting anyone for room
+    storage.check_claims(&user)?;
+    if dry_run {
+        storage.check_room(1).map_err(storage_full)?;
+        return Ok(Created::new(ApiResponse::new(user)));
     }
+    make_room(&state, &mut storage, 1)?;
+    // Generated IDs only collide by chance, so another draw succeeds
+    let mut attempts = 1;
+    loop {
+        match storage.create_if_email_free(user.clone()) {
+            Err(StorageError::DuplicateId(_)) if attempts < ID_ATTEMPTS => {
+                attempts += 1;
+                user.id = state.shards.next_id(&state.ids);
+            }
+            result => break result?,
+        }
+    }
+    state.events.publish(Event::UserCreated(user.clone()));
+
+    let location = format!("/api/v1/users/{}", user.id);
+    Ok(Created::new(ApiResponse::new(user)).with_location(location))
+}
+
+/// Makes room for `count` more users, evicting others if the store is full
+/// and configured to
+fn make_room(state: &AppState, storage: &mut Storage, count: usize) -> Result<(), ApiError> {
+    let evicted = storage.make_room(count).map_err(storage_full)?;
+    for evicted in evicted {
+        tracing::info!(user_id = %evicted.id, "evicted least recently used user");
+        state.events.publish(Event::UserDeleted(evicted.id));
+    }
+    Ok(())
+}
 
-    Ok((StatusCode::CREATED, Json(UserResponse { user })))
+/// Answers a request that does not fit into the store
+fn storage_full(full: StorageFull) -> ApiError {
+    ApiError::InsufficientStorage(Message::new("storage.full").with("max", full.max_users))
 }
 
 /// Updates an existing user
@@ -142,90 +458,1952 @@ pub async fn create_user(
 ///
 /// # Arguments
 ///
-/// * `Path(id)` - The UUID of the user to update
+/// * `UserId(id)` - The UUID of the user to update
 /// * `State(state)` - Application state containing the storage
 /// * `Json(payload)` - The user update request payload
 ///
 /// # Returns
 ///
-/// Returns the updated user, or a 404 error if not found
+/// Returns the updated user, a 422 error if a field is invalid or the new
+/// name or email is reserved, or a 404 error if not found
+#[utoipa::path(
+    put,
+    path = "/api/v1/users/{id}",
+    tag = "users",
+    security(("bearer_token" = ["users:write"])),
+    params(
+        ("id" = Uuid, Path, description = "User ID"),
+        ("dry_run" = Option<bool>, Query, description = "Only validate and preview the change, like `Prefer: dry-run`")
+    ),
+    request_body = UpdateUserRequest,
+    responses(
+        (status = 200, description = "The updated user", body = ApiResponse<User>,
+            headers(("X-Consistency-Token" = String, description = "Storage version including this write"))),
+        (status = 400, description = "Invalid input", body = ErrorResponse),
+        (status = 422, description = "Invalid fields, listing every violated rule, or a reserved name or email", body = ErrorResponse),
+        (status = 404, description = "No such user", body = ErrorResponse),
+        (status = 409, description = "Email or phone already in use", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the users:write scope", body = ErrorResponse)
+    )
+)]
 pub async fn update_user(
-    Path(id): Path<Uuid>,
+    UserId(id): UserId,
     State(state): State<AppState>,
     Json(payload): Json<UpdateUserRequest>,
-) -> Result<Json<UserResponse>, ApiError> {
+) -> Result<Json<ApiResponse<User>>, ApiError> {
+    // Validate the provided fields, reporting every violated rule at once
+    let UserChanges {
+        name,
+        email,
+        phone,
+        locale,
+        timezone,
+        custom,
+    } = payload.validate(&state.config)?;
+    state.reserved.check(name.as_deref(), email.as_deref())?;
+    #[cfg(feature = "mx-lookup")]
+    if let Some(ref email) = email {
+        verify_mx(&state, email).await?;
+    }
+
     let mut storage = state.storage.write().await;
 
     // Validate that user exists
-    if storage.get(&id).is_none() {
-        return Err(ApiError::NotFound(format!("User with id {} not found", id)));
-    }
+    let Some(mut current) = storage.get(&id) else {
+        return Err(ApiError::NotFound(
+            Message::new("user.not_found").with("id", id),
+        ));
+    };
 
-    // Validate email if provided
-    if let Some(ref email) = payload.email {
-        if email.trim().is_empty() {
-            return Err(ApiError::BadRequest("Email cannot be empty".to_string()));
+    // Update the user, unless another user holds its new email or phone
+    let change = |user: &mut User| {
+        if let Some(name) = name {
+            user.name = name;
         }
-        if !email.contains('@') {
-            return Err(ApiError::BadRequest("Invalid email format".to_string()));
+        if let Some(email) = email {
+            user.email = email;
         }
-
-        // Check if email is already in use by another user
-        let email_lower = email.trim().to_lowercase();
-        if let Some(existing_user) = storage.get_all().iter().find(|u| u.email == email_lower) {
-            if existing_user.id != id {
-                return Err(ApiError::Conflict(format!(
-                    "Email {} is already in use",
-                    email
-                )));
-            }
+        if let Some(phone) = phone {
+            user.phone = phone;
         }
-    }
-
-    // Validate name if provided
-    if let Some(ref name) = payload.name {
-        if name.trim().is_empty() {
-            return Err(ApiError::BadRequest("Name cannot be empty".to_string()));
+        if let Some(locale) = locale {
+            user.locale = locale;
+        }
+        if let Some(timezone) = timezone {
+            user.timezone = timezone;
+        }
+        for (name, value) in custom {
+            if value.is_null() {
+                user.custom.remove(&name);
+            } else {
+                user.custom.insert(name, value);
+            }
         }
+        user.updated_at = state.clock.now();
+    };
+    let previous = current.clone();
+    change(&mut current);
+    if dry_run::is_requested() {
+        storage.check_claims(&current)?;
+        return Ok(Json(ApiResponse::new(current)));
     }
-
-    // Update the user
     let updated_user = storage
-        .update(&id, |user| {
-            if let Some(name) = &payload.name {
-                user.name = name.trim().to_string();
-            }
-            if let Some(email) = &payload.email {
-                user.email = email.trim().to_lowercase();
-            }
-            user.updated_at = Utc::now();
-        })
-        .then(|| storage.get(&id))
-        .flatten()
-        .ok_or_else(|| ApiError::Internal("Failed to update user".to_string()))?;
+        .update_with_email_claim(&id, |user| *user = current)?
+        .clone();
+    let diff = FieldDiff::between(&previous, &updated_user);
+    state
+        .events
+        .publish(Event::UserUpdated(updated_user.clone(), diff));
+
+    Ok(Json(ApiResponse::new(updated_user)))
+}
 
-    Ok(Json(UserResponse { user: updated_user }))
+/// Checks that the domain of a validated email address accepts mail
+///
+/// An undeliverable domain is reported like any other violation of the
+/// `email` field.
+#[cfg(feature = "mx-lookup")]
+async fn verify_mx(state: &AppState, address: &str) -> Result<(), ApiError> {
+    let mut violations = crate::validation::Violations::new();
+    violations.check(
+        "email",
+        crate::validation::email::verify_mx(address, state.mx_lookup.as_ref()).await,
+    );
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations.into())
+    }
 }
 
 /// Deletes a user from the system
 ///
+/// The user is moved to the trash. When `APP_UNDO_WINDOW_SECONDS` is set,
+/// the response carries a token that restores it within that window.
+///
 /// # Arguments
 ///
-/// * `Path(id)` - The UUID of the user to delete
+/// * `UserId(id)` - The UUID of the user to delete
 /// * `State(state)` - Application state containing the storage
+/// * `principal` - The caller, recorded as the deleter when authentication
+///   is enabled
 ///
 /// # Returns
 ///
 /// Returns a 204 No Content status on success, or a 404 error if not found
+#[utoipa::path(
+    delete,
+    path = "/api/v1/users/{id}",
+    tag = "users",
+    security(("bearer_token" = ["users:write"])),
+    params(
+        ("id" = Uuid, Path, description = "User ID"),
+        ("dry_run" = Option<bool>, Query, description = "Only validate and preview the change, like `Prefer: dry-run`")
+    ),
+    responses(
+        (status = 204, description = "The user was deleted",
+            headers(
+                ("X-Consistency-Token" = String, description = "Storage version including this write"),
+                ("X-Undo-Token" = String, description = "Token for POST /api/v1/undo/{token}, when undo is enabled")
+            )),
+        (status = 400, description = "Malformed user ID", body = ErrorResponse),
+        (status = 404, description = "No such user", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the users:write scope", body = ErrorResponse)
+    )
+)]
 pub async fn delete_user(
-    Path(id): Path<Uuid>,
+    UserId(id): UserId,
     State(state): State<AppState>,
-) -> Result<StatusCode, ApiError> {
+    principal: Option<Extension<Principal>>,
+) -> Result<Response, ApiError> {
     let mut storage = state.storage.write().await;
 
-    if !storage.delete(&id) {
-        return Err(ApiError::NotFound(format!("User with id {} not found", id)));
+    let now = state.clock.now();
+    let deleted_by = principal.map(|Extension(principal)| principal.name);
+    let dry_run = dry_run::is_requested();
+    if dry_run {
+        storage.get(&id).ok_or(StorageError::NotFound(id))?;
+        return Ok(StatusCode::NO_CONTENT.into_response());
     }
+    storage.remove(&id, now, deleted_by)?;
+    state.events.publish(Event::UserDeleted(id));
 
-    Ok(StatusCode::NO_CONTENT)
+    let Some(window) = state.config.undo_window else {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    };
+    let window = chrono::Duration::from_std(window).unwrap_or(chrono::Duration::MAX);
+    let expires_at = now
+        .checked_add_signed(window)
+        .unwrap_or(DateTime::<Utc>::MAX_UTC);
+    let token = state.undo.issue(id, now, expires_at);
+    Ok((StatusCode::NO_CONTENT, [(X_UNDO_TOKEN, token)]).into_response())
+}
+
+/// Restores a deleted user
+///
+/// The token comes from the `X-Undo-Token` header of the deletion and is
+/// spent once the user is back. Restoring fails while another user holds
+/// the deleted user's email address or phone number.
+///
+/// # Arguments
+///
+/// * `token` - The undo token
+/// * `State(state)` - Application state holding the issued tokens
+///
+/// # Returns
+///
+/// Returns the restored user, or a 404 error if the token is unknown,
+/// expired or already spent
+#[utoipa::path(
+    post,
+    path = "/api/v1/undo/{token}",
+    tag = "users",
+    security(("bearer_token" = ["users:write"])),
+    params(
+        ("token" = String, Path, description = "Undo token of a deletion"),
+        ("dry_run" = Option<bool>, Query, description = "Only validate and preview the change, like `Prefer: dry-run`")
+    ),
+    responses(
+        (status = 200, description = "The restored user", body = ApiResponse<User>,
+            headers(("X-Consistency-Token" = String, description = "Storage version including this write"))),
+        (status = 404, description = "Unknown, expired or spent token", body = ErrorResponse),
+        (status = 409, description = "Email or phone now belongs to another user", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the users:write scope", body = ErrorResponse),
+        (status = 507, description = "The user store is full", body = ErrorResponse)
+    )
+)]
+pub async fn undo(
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<User>>, ApiError> {
+    let unknown = || ApiError::NotFound(Message::new("undo.unknown_token"));
+    let id = state
+        .undo
+        .user_id(&token, state.clock.now())
+        .ok_or_else(unknown)?;
+
+    let mut storage = state.storage.write().await;
+    if storage.trashed(&id).is_none() {
+        return Err(unknown());
+    }
+    let user = restore(&state, &mut storage, &[id])?.remove(0);
+    if !dry_run::is_requested() {
+        state.undo.spend(&token);
+    }
+
+    Ok(Json(ApiResponse::new(user)))
+}
+
+/// Lists deleted users in the trash
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state containing the storage
+///
+/// # Returns
+///
+/// Returns the deleted users, most recently deleted first
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/trash",
+    tag = "users",
+    security(("bearer_token" = ["admin"])),
+    responses(
+        (status = 200, description = "Deleted users", body = ApiResponse<Vec<TrashedUser>>),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn list_trash(State(state): State<AppState>) -> Json<ApiResponse<Vec<TrashedUser>>> {
+    let users = state.storage.read().await.trash();
+
+    Json(ApiResponse::collection(users))
+}
+
+/// Restores a deleted user from the trash
+///
+/// # Arguments
+///
+/// * `UserId(id)` - The UUID of the deleted user
+/// * `State(state)` - Application state containing the storage
+///
+/// # Returns
+///
+/// Returns the restored user, a 404 error if it is not in the trash, or a
+/// 409 error if its email or phone now belongs to another user
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/trash/{id}/restore",
+    tag = "users",
+    security(("bearer_token" = ["admin"])),
+    params(
+        ("id" = Uuid, Path, description = "User ID"),
+        ("dry_run" = Option<bool>, Query, description = "Only validate and preview the change, like `Prefer: dry-run`")
+    ),
+    responses(
+        (status = 200, description = "The restored user", body = ApiResponse<User>,
+            headers(("X-Consistency-Token" = String, description = "Storage version including this write"))),
+        (status = 400, description = "Malformed user ID", body = ErrorResponse),
+        (status = 404, description = "The user is not in the trash", body = ErrorResponse),
+        (status = 409, description = "Email or phone now belongs to another user", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse),
+        (status = 507, description = "The user store is full", body = ErrorResponse)
+    )
+)]
+pub async fn restore_user(
+    UserId(id): UserId,
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<User>>, ApiError> {
+    let mut storage = state.storage.write().await;
+    let user = restore(&state, &mut storage, &[id])?.remove(0);
+
+    Ok(Json(ApiResponse::new(user)))
+}
+
+/// Restores several deleted users from the trash
+///
+/// Either all of the users are restored or, if any of them cannot be,
+/// none is.
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state containing the storage
+/// * `Json(payload)` - The IDs of the users to restore
+///
+/// # Returns
+///
+/// Returns the restored users, a 404 error if one is not in the trash, or
+/// a 409 error if an email or phone belongs to another user
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/trash/restore",
+    tag = "users",
+    security(("bearer_token" = ["admin"])),
+    params(("dry_run" = Option<bool>, Query, description = "Only validate and preview the change, like `Prefer: dry-run`")),
+    request_body = RestoreUsersRequest,
+    responses(
+        (status = 200, description = "The restored users", body = ApiResponse<Vec<User>>,
+            headers(("X-Consistency-Token" = String, description = "Storage version including this write"))),
+        (status = 400, description = "Malformed request body", body = ErrorResponse),
+        (status = 404, description = "A user is not in the trash", body = ErrorResponse),
+        (status = 409, description = "An email or phone belongs to another user", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse),
+        (status = 507, description = "The user store is full", body = ErrorResponse)
+    )
+)]
+pub async fn restore_users(
+    State(state): State<AppState>,
+    Json(payload): Json<RestoreUsersRequest>,
+) -> Result<ApiResponse<Vec<Arc<User>>>, ApiError> {
+    let mut ids = payload.ids;
+    let mut seen = HashSet::new();
+    ids.retain(|id| seen.insert(*id));
+
+    let mut storage = state.storage.write().await;
+    let users = restore(&state, &mut storage, &ids)?;
+
+    Ok(ApiResponse::collection(
+        users.into_iter().map(Arc::new).collect(),
+    ))
+}
+
+/// Moves deleted users back from the trash
+///
+/// All users are checked before any is restored: each must be in the
+/// trash, and its email and phone must belong neither to a stored user nor
+/// to another of the restored ones. A dry run stops after the checks and
+/// returns the users as they would be restored.
+fn restore(state: &AppState, storage: &mut Storage, ids: &[Uuid]) -> Result<Vec<User>, ApiError> {
+    let mut emails = HashSet::new();
+    let mut phones = HashSet::new();
+    let mut previews = Vec::with_capacity(ids.len());
+    for id in ids {
+        let Some(TrashedUser { user, .. }) = storage.trashed(id) else {
+            return Err(ApiError::NotFound(
+                Message::new("trash.not_found").with("id", id),
+            ));
+        };
+        if storage.email_exists(&user.email) || !emails.insert(storage.email_key(&user.email)) {
+            return Err(ApiError::Conflict(
+                Message::new("user.email_exists").with("email", &user.email),
+            ));
+        }
+        if let Some(ref phone) = user.phone {
+            if storage.find_by_phone(phone).is_some() || !phones.insert(phone.clone()) {
+                return Err(ApiError::Conflict(
+                    Message::new("user.phone_exists").with("phone", phone),
+                ));
+            }
+        }
+        previews.push(User::clone(&user));
+    }
+
+    if dry_run::is_requested() {
+        storage.check_room(ids.len()).map_err(storage_full)?;
+        return Ok(previews);
+    }
+    make_room(state, storage, ids.len())?;
+    let mut restored = Vec::with_capacity(ids.len());
+    for id in ids {
+        let user = storage.restore(id)?;
+        state.events.publish(Event::UserCreated(user.clone()));
+        restored.push(user);
+    }
+    Ok(restored)
+}
+
+/// Suspends an active user
+///
+/// Suspended users keep their data but cannot log in until reactivated.
+///
+/// # Arguments
+///
+/// * `UserId(id)` - The UUID of the user to suspend
+/// * `State(state)` - Application state containing the storage
+///
+/// # Returns
+///
+/// Returns the updated user, a 404 error if not found, or a 409 error
+/// if the user's current status cannot transition to `suspended`
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/{id}/suspend",
+    tag = "users",
+    security(("bearer_token" = ["users:write"])),
+    params(
+        ("id" = Uuid, Path, description = "User ID"),
+        ("dry_run" = Option<bool>, Query, description = "Only validate and preview the change, like `Prefer: dry-run`")
+    ),
+    responses(
+        (status = 200, description = "The updated user", body = ApiResponse<User>,
+            headers(("X-Consistency-Token" = String, description = "Storage version including this write"))),
+        (status = 400, description = "Malformed user ID", body = ErrorResponse),
+        (status = 404, description = "No such user", body = ErrorResponse),
+        (status = 409, description = "Transition not allowed", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the users:write scope", body = ErrorResponse)
+    )
+)]
+pub async fn suspend_user(
+    UserId(id): UserId,
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<User>>, ApiError> {
+    change_status(&state, id, UserStatus::Suspended).await
+}
+
+/// Activates a pending or suspended user
+///
+/// # Arguments
+///
+/// * `UserId(id)` - The UUID of the user to activate
+/// * `State(state)` - Application state containing the storage
+///
+/// # Returns
+///
+/// Returns the updated user, a 404 error if not found, or a 409 error
+/// if the user's current status cannot transition to `active`
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/{id}/activate",
+    tag = "users",
+    security(("bearer_token" = ["users:write"])),
+    params(
+        ("id" = Uuid, Path, description = "User ID"),
+        ("dry_run" = Option<bool>, Query, description = "Only validate and preview the change, like `Prefer: dry-run`")
+    ),
+    responses(
+        (status = 200, description = "The updated user", body = ApiResponse<User>,
+            headers(("X-Consistency-Token" = String, description = "Storage version including this write"))),
+        (status = 400, description = "Malformed user ID", body = ErrorResponse),
+        (status = 404, description = "No such user", body = ErrorResponse),
+        (status = 409, description = "Transition not allowed", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the users:write scope", body = ErrorResponse)
+    )
+)]
+pub async fn activate_user(
+    UserId(id): UserId,
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<User>>, ApiError> {
+    change_status(&state, id, UserStatus::Active).await
+}
+
+/// Permanently deactivates a user
+///
+/// Deactivation is final: a deactivated user cannot be reactivated.
+///
+/// # Arguments
+///
+/// * `UserId(id)` - The UUID of the user to deactivate
+/// * `State(state)` - Application state containing the storage
+///
+/// # Returns
+///
+/// Returns the updated user, a 404 error if not found, or a 409 error
+/// if the user is already deactivated
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/{id}/deactivate",
+    tag = "users",
+    security(("bearer_token" = ["users:write"])),
+    params(
+        ("id" = Uuid, Path, description = "User ID"),
+        ("dry_run" = Option<bool>, Query, description = "Only validate and preview the change, like `Prefer: dry-run`")
+    ),
+    responses(
+        (status = 200, description = "The updated user", body = ApiResponse<User>,
+            headers(("X-Consistency-Token" = String, description = "Storage version including this write"))),
+        (status = 400, description = "Malformed user ID", body = ErrorResponse),
+        (status = 404, description = "No such user", body = ErrorResponse),
+        (status = 409, description = "Transition not allowed", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the users:write scope", body = ErrorResponse)
+    )
+)]
+pub async fn deactivate_user(
+    UserId(id): UserId,
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<User>>, ApiError> {
+    change_status(&state, id, UserStatus::Deactivated).await
+}
+
+/// Moves a user to a new status, enforcing the allowed transitions
+async fn change_status(
+    state: &AppState,
+    id: Uuid,
+    next: UserStatus,
+) -> Result<Json<ApiResponse<User>>, ApiError> {
+    let mut storage = state.storage.write().await;
+
+    let mut user = storage
+        .get(&id)
+        .ok_or_else(|| ApiError::NotFound(Message::new("user.not_found").with("id", id)))?;
+    let current = user.status;
+
+    if !current.can_transition_to(next) {
+        return Err(ApiError::Conflict(
+            Message::new("user.invalid_transition")
+                .with("from", current)
+                .with("to", next),
+        ));
+    }
+
+    let change = |user: &mut User| {
+        user.status = next;
+        user.updated_at = state.clock.now();
+    };
+    if dry_run::is_requested() {
+        change(&mut user);
+        return Ok(Json(ApiResponse::new(user)));
+    }
+    let updated_user = storage.update(&id, change)?.clone();
+    let diff = FieldDiff::between(&user, &updated_user);
+    state
+        .events
+        .publish(Event::UserUpdated(updated_user.clone(), diff));
+
+    Ok(Json(ApiResponse::new(updated_user)))
+}
+
+/// Creates users in bulk in the background
+///
+/// Each user goes through the same validation and storage as
+/// [`create_user`]; users that fail are reported in the operation's result
+/// and do not stop the others. Dry runs are not supported.
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state containing the storage and jobs
+/// * `Json(payload)` - The users to create
+///
+/// # Returns
+///
+/// Returns the queued operation with a 202 status code and its path in the
+/// `Location` header, or a 400 error if there are more than
+/// [`ImportUsersRequest::MAX_USERS`] users
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/import",
+    tag = "users",
+    security(("bearer_token" = ["users:write"])),
+    request_body = ImportUsersRequest,
+    responses(
+        (status = 202, description = "The queued import", body = ApiResponse<Operation>,
+            headers(("Location" = String, description = "Path of the operation"))),
+        (status = 400, description = "Invalid input or too many users", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the users:write scope", body = ErrorResponse)
+    )
+)]
+pub async fn import_users(
+    State(state): State<AppState>,
+    ActiveLocale(language): ActiveLocale,
+    Json(payload): Json<ImportUsersRequest>,
+) -> Result<Accepted<ApiResponse<Operation>>, ApiError> {
+    if payload.users.len() > ImportUsersRequest::MAX_USERS {
+        return Err(ApiError::BadRequest(
+            Message::new("import.too_many").with("max", ImportUsersRequest::MAX_USERS),
+        ));
+    }
+
+    let total = payload.users.len();
+    let jobs = state.jobs.clone();
+    let operation = jobs.submit(
+        state.clock,
+        OperationKind::Import,
+        total,
+        move |progress| async move {
+            let mut report = ImportReport::default();
+            for (index, user) in payload.users.into_iter().enumerate() {
+                if progress.is_cancelled() {
+                    break;
+                }
+                match create_user(State(state.clone()), Json(user)).await {
+                    Ok(_) => report.created += 1,
+                    Err(err) => report.failed.push(ImportFailure {
+                        index,
+                        error: err.render(language),
+                    }),
+                }
+                progress.advance(1);
+            }
+            Ok(OperationResult::Import(report))
+        },
+    );
+    Ok(operation_accepted(operation))
+}
+
+/// Users an export copies between checks for cancellation
+const EXPORT_BATCH: usize = 1_000;
+
+/// Exports all users in the background
+///
+/// A cancelled export's result holds the users copied until then.
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state containing the storage and jobs
+///
+/// # Returns
+///
+/// Returns the queued operation with a 202 status code and its path in the
+/// `Location` header; the finished operation's result lists the users
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/export",
+    tag = "users",
+    security(("bearer_token" = ["users:read"])),
+    responses(
+        (status = 202, description = "The queued export", body = ApiResponse<Operation>,
+            headers(("Location" = String, description = "Path of the operation"))),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the users:read scope", body = ErrorResponse)
+    )
+)]
+pub async fn export_users(State(state): State<AppState>) -> Accepted<ApiResponse<Operation>> {
+    let jobs = state.jobs.clone();
+    let operation = jobs.submit(
+        state.clock,
+        OperationKind::Export,
+        0,
+        |progress| async move {
+            let all = state.storage.read().await.get_all();
+            progress.set_total(all.len());
+            let mut users = Vec::with_capacity(all.len());
+            for batch in all.chunks(EXPORT_BATCH) {
+                if progress.is_cancelled() {
+                    break;
+                }
+                users.extend_from_slice(batch);
+                progress.advance(batch.len());
+                tokio::task::yield_now().await;
+            }
+            Ok(OperationResult::Export {
+                count: users.len(),
+                users,
+            })
+        },
+    );
+    operation_accepted(operation)
+}
+
+fn operation_accepted(operation: Operation) -> Accepted<ApiResponse<Operation>> {
+    let location = format!("/api/v1/operations/{}", operation.id);
+    Accepted::new(location, ApiResponse::new(operation))
+}
+
+fn operation_not_found(id: Uuid) -> ApiError {
+    ApiError::NotFound(Message::new("operation.not_found").with("id", id))
+}
+
+/// Reports the progress and result of a background operation
+///
+/// # Arguments
+///
+/// * `OperationId(id)` - The UUID of the operation
+/// * `State(state)` - Application state containing the jobs
+///
+/// # Returns
+///
+/// Returns the operation, or a 404 error if it is unknown or has expired
+#[utoipa::path(
+    get,
+    path = "/api/v1/operations/{id}",
+    tag = "users",
+    security(("bearer_token" = ["users:read"])),
+    params(("id" = Uuid, Path, description = "Operation ID")),
+    responses(
+        (status = 200, description = "The operation", body = ApiResponse<Operation>),
+        (status = 400, description = "Malformed operation ID", body = ErrorResponse),
+        (status = 404, description = "No such operation", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the users:read scope", body = ErrorResponse)
+    )
+)]
+pub async fn get_operation(
+    OperationId(id): OperationId,
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Operation>>, ApiError> {
+    state
+        .jobs
+        .get(id)
+        .map(|operation| Json(ApiResponse::new(operation)))
+        .ok_or_else(|| operation_not_found(id))
+}
+
+/// Reports the recent usage of an API key
+///
+/// Covers the requests the key's principal made in the last hour: how many
+/// failed, and which routes it called most. Callers may only see the usage
+/// of their own key unless they are admins.
+///
+/// # Arguments
+///
+/// * `Path(id)` - Name of the key's principal, as in `APP_API_TOKENS`
+/// * `State(state)` - Application state containing the usage store
+/// * `principal` - The caller, set when authentication is enabled
+///
+/// # Returns
+///
+/// Returns the key's usage, empty for keys without recent requests, or a
+/// 403 error for another caller's key
+#[utoipa::path(
+    get,
+    path = "/api/v1/api-keys/{id}/usage",
+    tag = "users",
+    security(("bearer_token" = ["users:read"])),
+    params(("id" = String, Path, description = "Name of the key's principal")),
+    responses(
+        (status = 200, description = "The key's usage", body = ApiResponse<UsageReport>),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the users:read scope, or belongs to another caller", body = ErrorResponse)
+    )
+)]
+pub async fn api_key_usage(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+) -> Result<Json<ApiResponse<UsageReport>>, ApiError> {
+    if let Some(Extension(principal)) = principal {
+        if principal.name != id && !principal.has_scope(Scope::Admin) {
+            return Err(ApiError::Forbidden(
+                Message::new("usage.forbidden").with("key", id),
+            ));
+        }
+    }
+    let report = state.usage.report(&id, state.clock.now());
+    Ok(Json(ApiResponse::new(report)))
+}
+
+/// Cancels a background operation
+///
+/// Pending operations are cancelled right away; running ones are
+/// `cancelling` until their job stops, keeping what it finished until then
+/// as the result. Cancelling a cancelled operation again changes nothing.
+///
+/// # Arguments
+///
+/// * `OperationId(id)` - The UUID of the operation
+/// * `State(state)` - Application state containing the jobs
+///
+/// # Returns
+///
+/// Returns the operation with a 202 status code, a 404 error if it is
+/// unknown or has expired, or a 409 error if it has already succeeded or
+/// failed
+#[utoipa::path(
+    delete,
+    path = "/api/v1/operations/{id}",
+    tag = "users",
+    security(("bearer_token" = ["users:write"])),
+    params(("id" = Uuid, Path, description = "Operation ID")),
+    responses(
+        (status = 202, description = "The cancelled or cancelling operation", body = ApiResponse<Operation>,
+            headers(("Location" = String, description = "Path of the operation"))),
+        (status = 400, description = "Malformed operation ID", body = ErrorResponse),
+        (status = 404, description = "No such operation", body = ErrorResponse),
+        (status = 409, description = "The operation has already finished", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the users:write scope", body = ErrorResponse)
+    )
+)]
+pub async fn cancel_operation(
+    OperationId(id): OperationId,
+    State(state): State<AppState>,
+) -> Result<Accepted<ApiResponse<Operation>>, ApiError> {
+    let operation = state
+        .jobs
+        .cancel(id, state.clock.now())
+        .ok_or_else(|| operation_not_found(id))?;
+    if matches!(
+        operation.status,
+        OperationStatus::Succeeded | OperationStatus::Failed
+    ) {
+        return Err(ApiError::Conflict(
+            Message::new("operation.finished")
+                .with("id", id)
+                .with("status", operation.status),
+        ));
+    }
+    tracing::info!(operation = %id, status = ?operation.status, "operation cancelled");
+    Ok(operation_accepted(operation))
+}
+
+/// Bulk-creates realistic fake users for load and UI testing
+///
+/// Only served when `APP_DEV_ENDPOINTS` is enabled; otherwise the route
+/// answers like an unknown path. Users go through the same validation and
+/// storage as [`create_user`]; generated requests that collide with
+/// existing users are skipped, so fewer than `count` users may be created.
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state containing the storage
+/// * `uri` - The request URI, reported when the endpoint is disabled
+/// * `Query(query)` - Number of users and optional seed
+///
+/// # Returns
+///
+/// Returns the created users with a 201 status code, or a 400 error if
+/// `count` exceeds [`GenerateUsersQuery::MAX_COUNT`]
+#[utoipa::path(
+    post,
+    path = "/api/v1/dev/generate-users",
+    tag = "dev",
+    security(("bearer_token" = ["users:write"])),
+    params(GenerateUsersQuery),
+    responses(
+        (status = 201, description = "The generated users", body = ApiResponse<Vec<User>>),
+        (status = 400, description = "Count too large", body = ErrorResponse),
+        (status = 404, description = "Development endpoints are disabled", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the users:write scope", body = ErrorResponse)
+    )
+)]
+pub async fn generate_users(
+    State(state): State<AppState>,
+    uri: Uri,
+    Query(query): Query<GenerateUsersQuery>,
+) -> Result<(StatusCode, ApiResponse<Vec<Arc<User>>>), ApiError> {
+    if !state.config.dev_endpoints {
+        return Err(not_found(uri).await);
+    }
+    if query.count > GenerateUsersQuery::MAX_COUNT {
+        return Err(ApiError::BadRequest(
+            Message::new("dev.count_too_large").with("max", GenerateUsersQuery::MAX_COUNT),
+        ));
+    }
+
+    let seed = query
+        .seed
+        .unwrap_or_else(|| state.ids.next_id().as_u64_pair().0);
+    let users = mock::seed(&state, seed, query.count).await;
+
+    Ok((
+        StatusCode::CREATED,
+        ApiResponse::collection(users.into_iter().map(Arc::new).collect()),
+    ))
+}
+
+/// Changes the log filter at runtime
+///
+/// Only served when `APP_ADMIN_ENDPOINTS` is enabled; otherwise the route
+/// answers like an unknown path. The new filter replaces the previous one
+/// entirely, so include the base level (e.g. `rust_api::handlers=trace,info`).
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state holding the log filter
+/// * `uri` - The request URI, reported when the endpoint is disabled
+/// * `Json(payload)` - The new filter directives
+///
+/// # Returns
+///
+/// Returns the active filter, or a 400 error if the directives are invalid
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/log-level",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    request_body = LogLevel,
+    responses(
+        (status = 200, description = "The filter now in effect", body = LogLevel),
+        (status = 400, description = "Invalid filter directives", body = ErrorResponse),
+        (status = 404, description = "Admin endpoints are disabled", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn set_log_level(
+    State(state): State<AppState>,
+    uri: Uri,
+    Json(payload): Json<LogLevel>,
+) -> Result<Json<LogLevel>, ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+
+    state.log_filter.set(&payload.filter).map_err(|reason| {
+        ApiError::BadRequest(Message::new("admin.invalid_log_filter").with("reason", reason))
+    })?;
+    tracing::info!(filter = %state.log_filter.directives(), "log filter changed");
+
+    Ok(Json(LogLevel {
+        filter: state.log_filter.directives(),
+    }))
+}
+
+/// Issues a time-limited token acting as a user
+///
+/// Only served when `APP_ADMIN_ENDPOINTS` is enabled; otherwise the route
+/// answers like an unknown path. The token grants the `users:read` and
+/// `users:write` scopes and expires after `APP_IMPERSONATION_TTL_SECONDS`.
+/// Audit entries made with it name both the user and the administrator.
+///
+/// # Arguments
+///
+/// * `UserId(id)` - The UUID of the user to act as
+/// * `State(state)` - Application state holding the issued tokens
+/// * `principal` - The administrator, set when authentication is enabled
+/// * `uri` - The request URI, reported when the endpoint is disabled
+///
+/// # Returns
+///
+/// Returns the token, a 404 error if the user does not exist, or a 409
+/// error if the user may not log in
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/impersonate/{id}",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    params(("id" = Uuid, Path, description = "User ID")),
+    responses(
+        (status = 201, description = "Token acting as the user", body = Impersonation),
+        (status = 400, description = "Malformed user ID", body = ErrorResponse),
+        (status = 404, description = "No such user, or admin endpoints are disabled", body = ErrorResponse),
+        (status = 409, description = "Authentication is disabled, or the user may not log in", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn impersonate(
+    UserId(id): UserId,
+    State(state): State<AppState>,
+    principal: Option<Extension<Principal>>,
+    uri: Uri,
+) -> Result<(StatusCode, Json<Impersonation>), ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+    // Without authentication there is no one to impersonate on behalf of,
+    // and the token would not be checked anyway
+    let Some(Extension(admin)) = principal else {
+        return Err(ApiError::Conflict(Message::new(
+            "admin.impersonation_unavailable",
+        )));
+    };
+    let Some(status) = state.storage.read().await.get(&id).map(|user| user.status) else {
+        return Err(ApiError::NotFound(
+            Message::new("user.not_found").with("id", id),
+        ));
+    };
+    // The token would be refused anyway
+    if !status.can_log_in() {
+        return Err(ApiError::Conflict(
+            Message::new("admin.impersonation_inactive")
+                .with("id", id)
+                .with("status", status),
+        ));
+    }
+
+    let now = state.clock.now();
+    let ttl =
+        chrono::Duration::from_std(state.config.impersonation_ttl).unwrap_or(chrono::Duration::MAX);
+    let expires_at = now
+        .checked_add_signed(ttl)
+        .unwrap_or(DateTime::<Utc>::MAX_UTC);
+    let token = state.impersonations.issue(id, &admin.name, now, expires_at);
+    tracing::warn!(admin = %admin.name, user_id = %id, "impersonation token issued");
+
+    Ok((
+        StatusCode::CREATED,
+        Json(Impersonation {
+            token,
+            user_id: id,
+            impersonator: admin.name,
+            expires_at,
+        }),
+    ))
+}
+
+/// Returns recorded state-changing requests for review
+///
+/// Only served when `APP_ADMIN_ENDPOINTS` is enabled; otherwise the route
+/// answers like an unknown path.
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state holding the audit log
+/// * `uri` - The request URI, reported when the endpoint is disabled
+/// * `Query(query)` - Filters and the maximum number of entries
+///
+/// # Returns
+///
+/// Returns the matching entries, newest first
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/audit",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "Matching audit entries, newest first", body = ApiResponse<Vec<AuditEntry>>),
+        (status = 400, description = "Invalid query parameter", body = ErrorResponse),
+        (status = 404, description = "Admin endpoints are disabled", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn audit_log(
+    State(state): State<AppState>,
+    uri: Uri,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<ApiResponse<Vec<AuditEntry>>>, ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+
+    let entries = state.audit.query(&query);
+    Ok(Json(ApiResponse::collection(entries)))
+}
+
+/// Lists the mounted routes
+///
+/// Only available when `APP_ADMIN_ENDPOINTS` is enabled.
+///
+/// # Returns
+///
+/// Returns every route with its methods, the scope it requires, its
+/// deadline and its deprecation, along with the limits shared by all
+/// routes
+#[utoipa::path(
+    get,
+    path = "/api/v1/_routes",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    responses(
+        (status = 200, description = "The mounted routes", body = RoutesResponse),
+        (status = 404, description = "Admin endpoints are disabled", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn list_routes(
+    State(state): State<AppState>,
+    uri: Uri,
+) -> Result<Json<RoutesResponse>, ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+
+    Ok(Json(state.routes.report(&state)))
+}
+
+/// Reports each route's latency and error rate against the objectives
+///
+/// Only available when `APP_ADMIN_ENDPOINTS` is enabled.
+///
+/// # Returns
+///
+/// Returns every route with requests in the SLO window, breaching routes
+/// first
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/slo",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    responses(
+        (status = 200, description = "Latency and error rate per route", body = SloReport),
+        (status = 404, description = "Admin endpoints are disabled", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn slo_report(
+    State(state): State<AppState>,
+    uri: Uri,
+) -> Result<Json<SloReport>, ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+
+    Ok(Json(state.slo.report()))
+}
+
+/// Reports whether requests are being recorded
+///
+/// Only available when `APP_ADMIN_ENDPOINTS` is enabled.
+///
+/// # Returns
+///
+/// Returns the capture state and the number of recorded requests
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/capture",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    responses(
+        (status = 200, description = "The capture state", body = CaptureStatus),
+        (status = 404, description = "Admin endpoints are disabled", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn capture_status(
+    State(state): State<AppState>,
+    uri: Uri,
+) -> Result<Json<CaptureStatus>, ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+
+    Ok(Json(state.capture.status()))
+}
+
+/// Starts or stops recording requests to `APP_CAPTURE_FILE`
+///
+/// Only available when `APP_ADMIN_ENDPOINTS` is enabled. Recorded requests
+/// are appended to the file and can be sent to another instance with
+/// `rust-api replay`.
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state holding the capture
+/// * `uri` - The request URI, reported when the endpoint is disabled
+/// * `Json(payload)` - Whether to record
+///
+/// # Returns
+///
+/// Returns the capture state, or a 409 error if no capture file is
+/// configured
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/capture",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    request_body = CaptureSettings,
+    responses(
+        (status = 200, description = "The capture state now in effect", body = CaptureStatus),
+        (status = 404, description = "Admin endpoints are disabled", body = ErrorResponse),
+        (status = 409, description = "No capture file is configured", body = ErrorResponse),
+        (status = 500, description = "The capture file cannot be opened", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn set_capture(
+    State(state): State<AppState>,
+    uri: Uri,
+    Json(payload): Json<CaptureSettings>,
+) -> Result<Json<CaptureStatus>, ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+
+    state
+        .capture
+        .set_enabled(payload.enabled)
+        .await
+        .map_err(|err| match err.kind() {
+            std::io::ErrorKind::NotFound if state.config.capture_file.is_none() => {
+                ApiError::Conflict(Message::new("capture.not_configured"))
+            }
+            _ => ApiError::failed(Message::new("capture.unavailable"), err),
+        })?;
+    tracing::info!(enabled = payload.enabled, "request capture changed");
+
+    Ok(Json(state.capture.status()))
+}
+
+/// Lists the reserved names and emails and the blocked terms
+///
+/// Only available when `APP_ADMIN_ENDPOINTS` is enabled.
+///
+/// # Returns
+///
+/// Returns the lists in effect, normalized
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/reserved",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    responses(
+        (status = 200, description = "The lists in effect", body = ReservedLists),
+        (status = 404, description = "Admin endpoints are disabled", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn reserved_lists(
+    State(state): State<AppState>,
+    uri: Uri,
+) -> Result<Json<ReservedLists>, ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+
+    Ok(Json(state.reserved.lists()))
+}
+
+/// Replaces the reserved names and emails and the blocked terms
+///
+/// Only available when `APP_ADMIN_ENDPOINTS` is enabled. The lists apply
+/// to users created or changed from now on, until the server restarts
+/// with the configured lists. Omitted lists are emptied.
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state holding the lists
+/// * `uri` - The request URI, reported when the endpoint is disabled
+/// * `Json(payload)` - The new lists
+///
+/// # Returns
+///
+/// Returns the lists now in effect, normalized
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/reserved",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    request_body = ReservedLists,
+    responses(
+        (status = 200, description = "The lists now in effect", body = ReservedLists),
+        (status = 404, description = "Admin endpoints are disabled", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn set_reserved_lists(
+    State(state): State<AppState>,
+    uri: Uri,
+    Json(payload): Json<ReservedLists>,
+) -> Result<Json<ReservedLists>, ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+
+    let lists = state.reserved.replace(payload);
+    tracing::info!(
+        emails = lists.emails.len(),
+        names = lists.names.len(),
+        blocked_terms = lists.blocked_terms.len(),
+        "reserved lists replaced"
+    );
+    Ok(Json(lists))
+}
+
+/// Lists the default rate limit and the limits set for single principals
+///
+/// Only available when `APP_ADMIN_ENDPOINTS` is enabled.
+///
+/// # Returns
+///
+/// Returns the default limit, if any, and the overrides by principal
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/limits",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    responses(
+        (status = 200, description = "The rate limits", body = RateLimits),
+        (status = 404, description = "Admin endpoints are disabled", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn list_rate_limits(
+    State(state): State<AppState>,
+    uri: Uri,
+) -> Result<Json<RateLimits>, ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+
+    Ok(Json(RateLimits {
+        default: state.config.rate_limit,
+        overrides: state.storage.read().await.rate_limits().clone(),
+    }))
+}
+
+/// Sets the rate limit of a principal, overriding the default
+///
+/// Only available when `APP_ADMIN_ENDPOINTS` is enabled. The override is
+/// stored with the users and applies from the principal's next request.
+///
+/// # Arguments
+///
+/// * `Path(principal)` - Name of the principal, as in `APP_API_TOKENS`
+/// * `State(state)` - Application state containing the storage
+/// * `uri` - The request URI, reported when the endpoint is disabled
+/// * `Json(limit)` - The principal's limit
+///
+/// # Returns
+///
+/// Returns the limit now in effect for the principal, or a 400 error if
+/// its window is shorter than a second
+#[utoipa::path(
+    put,
+    path = "/api/v1/admin/limits/{principal}",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    params(("principal" = String, Path, description = "Name of the principal")),
+    request_body = RateLimit,
+    responses(
+        (status = 200, description = "The principal's limit", body = RateLimit),
+        (status = 400, description = "The window is shorter than a second", body = ErrorResponse),
+        (status = 404, description = "Admin endpoints are disabled", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn set_rate_limit(
+    Path(principal): Path<String>,
+    State(state): State<AppState>,
+    uri: Uri,
+    Json(limit): Json<RateLimit>,
+) -> Result<Json<RateLimit>, ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+    if limit.window_seconds == 0 {
+        return Err(ApiError::BadRequest(Message::new(
+            "rate_limit.invalid_window",
+        )));
+    }
+
+    state
+        .storage
+        .write()
+        .await
+        .set_rate_limit(principal.clone(), limit);
+    tracing::info!(
+        principal = %principal,
+        requests = limit.requests,
+        window_seconds = limit.window_seconds,
+        "rate limit set"
+    );
+    Ok(Json(limit))
+}
+
+/// Removes the rate limit set for a principal, which falls back to the
+/// default
+///
+/// Only available when `APP_ADMIN_ENDPOINTS` is enabled.
+///
+/// # Returns
+///
+/// Returns a 204 status code, or a 404 error if no limit was set for the
+/// principal
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/limits/{principal}",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    params(("principal" = String, Path, description = "Name of the principal")),
+    responses(
+        (status = 204, description = "The override was removed"),
+        (status = 404, description = "No limit is set for the principal, or admin endpoints are disabled", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn delete_rate_limit(
+    Path(principal): Path<String>,
+    State(state): State<AppState>,
+    uri: Uri,
+) -> Result<StatusCode, ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+
+    if state
+        .storage
+        .write()
+        .await
+        .remove_rate_limit(&principal)
+        .is_none()
+    {
+        return Err(ApiError::NotFound(
+            Message::new("rate_limit.not_found").with("principal", principal),
+        ));
+    }
+    tracing::info!(principal = %principal, "rate limit removed");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Returns `APP_SNAPSHOT_DIR`, or a 409 error if it is not set
+fn snapshot_dir(state: &AppState) -> Result<&std::path::Path, ApiError> {
+    state
+        .config
+        .snapshot_dir
+        .as_deref()
+        .ok_or_else(|| ApiError::Conflict(Message::new("snapshot.not_configured")))
+}
+
+/// Maps an error reading snapshot `name`
+fn snapshot_error(name: &str, err: std::io::Error) -> ApiError {
+    match err.kind() {
+        std::io::ErrorKind::InvalidInput => {
+            ApiError::BadRequest(Message::new("snapshot.invalid_name").with("snapshot", name))
+        }
+        std::io::ErrorKind::NotFound => {
+            ApiError::NotFound(Message::new("snapshot.not_found").with("snapshot", name))
+        }
+        _ => ApiError::failed(Message::new("snapshot.failed"), err),
+    }
+}
+
+/// Writes the full contents of the store to a snapshot file
+///
+/// Only available when `APP_ADMIN_ENDPOINTS` is enabled. The snapshot
+/// holds the users, the trash and the tombstones of removed users, and is
+/// written to a new file in `APP_SNAPSHOT_DIR`.
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state containing the storage
+/// * `uri` - The request URI, reported when the endpoint is disabled
+///
+/// # Returns
+///
+/// Returns the name and contents of the new snapshot with a 201 status
+/// code, or a 409 error if no snapshot directory is configured
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/snapshot",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    responses(
+        (status = 201, description = "The snapshot written", body = SnapshotInfo),
+        (status = 404, description = "Admin endpoints are disabled", body = ErrorResponse),
+        (status = 409, description = "No snapshot directory is configured", body = ErrorResponse),
+        (status = 500, description = "The snapshot cannot be written", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn create_snapshot(
+    State(state): State<AppState>,
+    uri: Uri,
+) -> Result<(StatusCode, Json<SnapshotInfo>), ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+    let dir = snapshot_dir(&state)?;
+
+    let snapshot = state.storage.read().await.snapshot(state.clock.now());
+    let info = snapshot::write(dir, &snapshot)
+        .await
+        .map_err(|err| ApiError::failed(Message::new("snapshot.failed"), err))?;
+    tracing::info!(snapshot = %info.snapshot, users = info.users, "snapshot written");
+
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+/// Replaces the contents of the store with a snapshot
+///
+/// Only available when `APP_ADMIN_ENDPOINTS` is enabled. Without a
+/// confirmation token nothing changes: the response describes the snapshot
+/// and issues a token. Repeating the request with the token within five
+/// minutes restores the snapshot, replacing all users, the trash and the
+/// tombstones.
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state containing the storage
+/// * `uri` - The request URI, reported when the endpoint is disabled
+/// * `Json(payload)` - The snapshot and, to restore it, the confirmation token
+///
+/// # Returns
+///
+/// Returns the restore plan with a 202 status code, or the restored
+/// snapshot once confirmed
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/restore",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    request_body = RestoreRequest,
+    responses(
+        (status = 200, description = "The snapshot was restored", body = SnapshotInfo),
+        (status = 202, description = "What the restore would do, with a token confirming it", body = RestorePlan),
+        (status = 400, description = "Invalid snapshot name or confirmation token", body = ErrorResponse),
+        (status = 404, description = "No such snapshot, or admin endpoints are disabled", body = ErrorResponse),
+        (status = 409, description = "No snapshot directory is configured", body = ErrorResponse),
+        (status = 500, description = "The snapshot cannot be read", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse),
+        (status = 507, description = "The snapshot holds more users than the store may", body = ErrorResponse)
+    )
+)]
+pub async fn restore_snapshot(
+    State(state): State<AppState>,
+    uri: Uri,
+    Json(payload): Json<RestoreRequest>,
+) -> Result<Response, ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+    let dir = snapshot_dir(&state)?;
+    let (info, snapshot) = snapshot::read(dir, &payload.snapshot)
+        .await
+        .map_err(|err| snapshot_error(&payload.snapshot, err))?;
+    let now = state.clock.now();
+
+    let Some(token) = payload.confirmation_token else {
+        let ttl =
+            chrono::Duration::from_std(snapshot::CONFIRMATION_TTL).unwrap_or(chrono::Duration::MAX);
+        let expires_at = now
+            .checked_add_signed(ttl)
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        let plan = RestorePlan {
+            current_users: state.storage.read().await.len(),
+            confirmation_token: state.snapshots.issue(&info.snapshot, now, expires_at),
+            expires_at,
+            snapshot: info,
+        };
+        return Ok((StatusCode::ACCEPTED, Json(plan)).into_response());
+    };
+    if !state.snapshots.confirm(&token, &info.snapshot, now) {
+        return Err(ApiError::BadRequest(Message::new(
+            "snapshot.invalid_confirmation",
+        )));
+    }
+
+    let mut storage = state.storage.write().await;
+    snapshot::replace(&mut storage, &state.events, snapshot).map_err(|full| {
+        ApiError::InsufficientStorage(Message::new("storage.full").with("max", full.max_users))
+    })?;
+    drop(storage);
+    tracing::warn!(snapshot = %info.snapshot, users = info.users, "snapshot restored");
+
+    Ok(Json(info).into_response())
+}
+
+/// Returns everything changed on the replication leader since an index
+///
+/// Only available on the leader (`APP_REPLICATION_ROLE=leader`). Waits up
+/// to `wait` seconds for a change if there is none after `after` yet.
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state containing the storage
+/// * `uri` - The request URI, reported when the endpoint is disabled
+/// * `Query(query)` - The last index applied and how long to wait
+///
+/// # Returns
+///
+/// Returns the current record of every user changed since `after`, or a
+/// 410 error if the log no longer reaches back that far
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/replication/changes",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    params(ChangesQuery),
+    responses(
+        (status = 200, description = "Changes since the index", body = Changes),
+        (status = 404, description = "This instance is not a replication leader", body = ErrorResponse),
+        (status = 410, description = "The changes since the index are no longer logged", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn replication_changes(
+    State(state): State<AppState>,
+    uri: Uri,
+    Query(query): Query<ChangesQuery>,
+) -> Result<Json<Changes>, ApiError> {
+    let replication = &state.replication;
+    if replication.role() != Some(Role::Leader) {
+        return Err(not_found(uri).await);
+    }
+    let wait = std::time::Duration::from_secs(query.wait).min(replication::MAX_WAIT);
+    if !wait.is_zero() {
+        replication.wait_for(query.after + 1, wait).await;
+    }
+
+    let storage = state.storage.read().await;
+    let (index, ids) = replication.changes(query.after).ok_or_else(|| {
+        ApiError::Gone(Message::new("replication.truncated").with("index", query.after))
+    })?;
+    let records = ids.iter().map(|id| storage.record(id)).collect();
+    Ok(Json(Changes {
+        log: replication.log_id(),
+        index,
+        records,
+    }))
+}
+
+/// Returns the replication leader's full store
+///
+/// Only available on the leader (`APP_REPLICATION_ROLE=leader`). Followers
+/// start from it, then apply the changes after its index.
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state containing the storage
+/// * `uri` - The request URI, reported when the endpoint is disabled
+///
+/// # Returns
+///
+/// Returns the users, the trash and the tombstones with the index of the
+/// last change included
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/replication/snapshot",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    responses(
+        (status = 200, description = "The full store", body = ReplicationSnapshot),
+        (status = 404, description = "This instance is not a replication leader", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn replication_snapshot(
+    State(state): State<AppState>,
+    uri: Uri,
+) -> Result<Json<ReplicationSnapshot>, ApiError> {
+    let replication = &state.replication;
+    if replication.role() != Some(Role::Leader) {
+        return Err(not_found(uri).await);
+    }
+
+    let storage = state.storage.read().await;
+    Ok(Json(ReplicationSnapshot {
+        log: replication.log_id(),
+        index: replication.index(),
+        snapshot: storage.snapshot(state.clock.now()),
+    }))
+}
+
+/// Queues a job writing a report on the users to the blob store
+///
+/// Only available when `APP_ADMIN_ENDPOINTS` is enabled. See
+/// [`crate::reports`] for what reports hold.
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state containing the storage, jobs and
+///   blob store
+/// * `uri` - The request URI, reported when the endpoint is disabled
+///
+/// # Returns
+///
+/// Returns the queued operation with a 202 status code and its path in the
+/// `Location` header; the finished operation's result describes the report
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/reports",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    responses(
+        (status = 202, description = "The queued report", body = ApiResponse<Operation>,
+            headers(("Location" = String, description = "Path of the operation"))),
+        (status = 404, description = "Admin endpoints are disabled", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn create_report(
+    State(state): State<AppState>,
+    uri: Uri,
+) -> Result<Accepted<ApiResponse<Operation>>, ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+
+    Ok(operation_accepted(reports::submit(&state)))
+}
+
+/// Lists the reports in the blob store, newest first
+///
+/// Only available when `APP_ADMIN_ENDPOINTS` is enabled.
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state containing the blob store
+/// * `uri` - The request URI, reported when the endpoint is disabled
+///
+/// # Returns
+///
+/// Returns the name, size and creation time of each report
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/reports",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    responses(
+        (status = 200, description = "The stored reports", body = ApiResponse<Vec<Report>>),
+        (status = 404, description = "Admin endpoints are disabled", body = ErrorResponse),
+        (status = 500, description = "The blob store cannot be read", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn list_reports(
+    State(state): State<AppState>,
+    uri: Uri,
+) -> Result<Json<ApiResponse<Vec<Report>>>, ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+
+    reports::list(&state)
+        .await
+        .map(|reports| Json(ApiResponse::collection(reports)))
+        .map_err(|err| ApiError::failed(Message::new("report.failed"), err))
+}
+
+/// Downloads a report
+///
+/// Only available when `APP_ADMIN_ENDPOINTS` is enabled.
+///
+/// # Arguments
+///
+/// * `Path(name)` - Name of the report, as listed by `GET /api/v1/admin/reports`
+/// * `State(state)` - Application state containing the blob store
+/// * `uri` - The request URI, reported when the endpoint is disabled
+///
+/// # Returns
+///
+/// Returns the report as CSV, or a 404 error if no report has the name
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/reports/{name}",
+    tag = "admin",
+    security(("bearer_token" = ["admin"])),
+    params(("name" = String, Path, description = "Name of the report")),
+    responses(
+        (status = 200, description = "The report", body = String, content_type = "text/csv"),
+        (status = 404, description = "No report has the name, or admin endpoints are disabled", body = ErrorResponse),
+        (status = 500, description = "The blob store cannot be read", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn get_report(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+    uri: Uri,
+) -> Result<Response, ApiError> {
+    if !state.config.admin_endpoints {
+        return Err(not_found(uri).await);
+    }
+
+    let blob = reports::get(&state, &name)
+        .await
+        .map_err(|err| ApiError::failed(Message::new("report.failed"), err))?
+        .ok_or_else(|| {
+            ApiError::NotFound(Message::new("report.not_found").with("report", &name))
+        })?;
+    let disposition = format!("attachment; filename=\"{}\"", name);
+    Ok((
+        [
+            (CONTENT_TYPE, blob.content_type),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        blob.data,
+    )
+        .into_response())
+}
+
+/// Lists groups of likely duplicate users
+///
+/// Users are grouped by each strategy in `APP_DUPLICATE_STRATEGIES`, or
+/// only by the one given in the query.
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state containing the storage
+/// * `Query(query)` - Optional strategy restriction
+///
+/// # Returns
+///
+/// Returns the groups of at least two users sharing a key
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/duplicates",
+    tag = "users",
+    security(("bearer_token" = ["admin"])),
+    params(DuplicatesQuery),
+    responses(
+        (status = 200, description = "Groups of likely duplicates", body = ApiResponse<Vec<DuplicateGroup>>),
+        (status = 400, description = "Unknown strategy", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn find_duplicates(
+    State(state): State<AppState>,
+    Query(query): Query<DuplicatesQuery>,
+) -> Json<ApiResponse<Vec<DuplicateGroup>>> {
+    let strategies = match query.strategy {
+        Some(strategy) => vec![strategy],
+        None => state.config.duplicate_strategies.clone(),
+    };
+    let users = state.storage.read().await.get_all();
+    let groups = duplicates::find(&users, &strategies);
+
+    Json(ApiResponse::collection(groups))
+}
+
+/// Merges one user into another
+///
+/// Fields are taken from the user `precedence` picks for each, the kept
+/// user by default, with optional fields missing there taken from the
+/// other. The removed user is deleted, its audit history is attributed to
+/// the kept user, and requests for its ID answer `410 Gone` naming the kept
+/// user.
+///
+/// # Arguments
+///
+/// * `State(state)` - Application state containing the storage
+/// * `Json(payload)` - The users to keep and to remove
+///
+/// # Returns
+///
+/// Returns the merged user, a 400 error if both IDs are the same, or a
+/// 404 error if either user does not exist
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/merge",
+    tag = "users",
+    security(("bearer_token" = ["admin"])),
+    params(("dry_run" = Option<bool>, Query, description = "Only validate and preview the change, like `Prefer: dry-run`")),
+    request_body = MergeUsersRequest,
+    responses(
+        (status = 200, description = "The merged user", body = ApiResponse<User>,
+            headers(("X-Consistency-Token" = String, description = "Storage version including this write"))),
+        (status = 400, description = "Invalid input, or both IDs name the same user", body = ErrorResponse),
+        (status = 404, description = "No such user", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn merge_users(
+    State(state): State<AppState>,
+    Json(payload): Json<MergeUsersRequest>,
+) -> Result<Json<ApiResponse<User>>, ApiError> {
+    let user = merge(&state, payload.keep, payload.remove, &payload.precedence).await?;
+    Ok(Json(ApiResponse::new(user)))
+}
+
+/// Merges the user in the path's `remove_id` into the one in `id`
+///
+/// The same operation as [`merge_users`], addressed by path; the body
+/// chooses which user each field is taken from.
+///
+/// # Arguments
+///
+/// * `UserId(id)` - The UUID of the user to keep
+/// * `RemovedUserId(remove_id)` - The UUID of the user to merge and delete
+/// * `State(state)` - Application state containing the storage
+/// * `Json(precedence)` - Which user each field is taken from
+///
+/// # Returns
+///
+/// Returns the merged user, a 400 error if both IDs are the same, or a
+/// 404 error if either user does not exist
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/{id}/merge/{remove_id}",
+    tag = "users",
+    security(("bearer_token" = ["admin"])),
+    params(
+        ("id" = Uuid, Path, description = "User to keep"),
+        ("remove_id" = Uuid, Path, description = "User to merge into it and delete"),
+        ("dry_run" = Option<bool>, Query, description = "Only validate and preview the change, like `Prefer: dry-run`")
+    ),
+    request_body = MergePrecedence,
+    responses(
+        (status = 200, description = "The merged user", body = ApiResponse<User>,
+            headers(("X-Consistency-Token" = String, description = "Storage version including this write"))),
+        (status = 400, description = "Malformed user ID, or both IDs name the same user", body = ErrorResponse),
+        (status = 404, description = "No such user", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the admin scope", body = ErrorResponse)
+    )
+)]
+pub async fn merge_user(
+    UserId(id): UserId,
+    RemovedUserId(remove_id): RemovedUserId,
+    State(state): State<AppState>,
+    Json(precedence): Json<MergePrecedence>,
+) -> Result<Json<ApiResponse<User>>, ApiError> {
+    let user = merge(&state, id, remove_id, &precedence).await?;
+    Ok(Json(ApiResponse::new(user)))
+}
+
+/// Merges `remove` into `keep`, shared by both merge endpoints
+async fn merge(
+    state: &AppState,
+    keep: Uuid,
+    remove: Uuid,
+    precedence: &MergePrecedence,
+) -> Result<User, ApiError> {
+    if keep == remove {
+        return Err(ApiError::BadRequest(
+            Message::new("user.merge_same").with("id", keep),
+        ));
+    }
+
+    let mut storage = state.storage.write().await;
+    let not_found = |id: Uuid| ApiError::NotFound(Message::new("user.not_found").with("id", id));
+    let kept = storage.get(&keep).ok_or_else(|| not_found(keep))?;
+    let removed = storage.get(&remove).ok_or_else(|| not_found(remove))?;
+
+    let now = state.clock.now();
+    let user = duplicates::merge(&kept, &removed, precedence, now);
+    if dry_run::is_requested() {
+        return Ok(user);
+    }
+    storage.merge(user.clone(), &remove, now)?;
+    drop(storage);
+
+    state.audit.reassign(&remove.to_string(), &keep.to_string());
+    state.events.publish(Event::UserDeleted(remove));
+    state.events.publish(Event::UserUpdated(
+        user.clone(),
+        FieldDiff::between(&kept, &user),
+    ));
+    tracing::info!(kept = %keep, removed = %remove, "users merged");
+
+    Ok(user)
 }
@@ -0,0 +1,256 @@
+//! Named health checks aggregated by `GET /readyz`
+//!
+//! Subsystems register their checks with the [`HealthRegistry`] in
+//! [`AppState`] instead of the handlers knowing about each of them. The
+//! storage registers its fill level; outbound dependencies can register
+//! their circuit breakers. All checks run concurrently, each limited to
+//! [`CHECK_TIMEOUT`], and the worst status is the service's.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::future::{join_all, BoxFuture};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::StorageUsage;
+use crate::resilience::{BreakerState, CircuitBreaker};
+use crate::AppState;
+
+/// How long a check may take before it counts as unhealthy
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Share of `APP_MAX_USERS` above which the storage reports `degraded`
+pub const STORAGE_DEGRADED_RATIO: f64 = 0.9;
+
+/// Health of a subsystem, ordered from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// Working normally
+    Healthy,
+    /// Working, but close to a limit or with a dependency failing
+    Degraded,
+    /// Not working; the service should not receive traffic
+    Unhealthy,
+}
+
+/// Outcome of a single check
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CheckResult {
+    /// The subsystem's health
+    pub status: HealthStatus,
+    /// Why the subsystem is not healthy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl CheckResult {
+    /// A healthy result
+    pub fn healthy() -> Self {
+        Self {
+            status: HealthStatus::Healthy,
+            detail: None,
+        }
+    }
+
+    /// A degraded result explained by `detail`
+    pub fn degraded(detail: impl Into<String>) -> Self {
+        Self {
+            status: HealthStatus::Degraded,
+            detail: Some(detail.into()),
+        }
+    }
+
+    /// An unhealthy result explained by `detail`
+    pub fn unhealthy(detail: impl Into<String>) -> Self {
+        Self {
+            status: HealthStatus::Unhealthy,
+            detail: Some(detail.into()),
+        }
+    }
+}
+
+/// Response of the readiness check
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ReadinessReport {
+    /// The worst status of all checks
+    pub status: HealthStatus,
+    /// Result of every check, by name
+    pub checks: BTreeMap<String, CheckResult>,
+}
+
+type Check = Arc<dyn Fn(AppState) -> BoxFuture<'static, CheckResult> + Send + Sync>;
+
+/// The registered health checks
+#[derive(Default)]
+pub struct HealthRegistry {
+    checks: Mutex<Vec<(String, Check)>>,
+}
+
+impl std::fmt::Debug for HealthRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let checks = self.lock();
+        f.debug_list()
+            .entries(checks.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+impl HealthRegistry {
+    /// Registers a check, replacing any check of the same name
+    pub fn register<F, Fut>(&self, name: impl Into<String>, check: F)
+    where
+        F: Fn(AppState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = CheckResult> + Send + 'static,
+    {
+        let name = name.into();
+        let check: Check = Arc::new(move |state| Box::pin(check(state)));
+        let mut checks = self.lock();
+        checks.retain(|(existing, _)| *existing != name);
+        checks.push((name, check));
+    }
+
+    /// Registers a check reporting `breaker`, under the breaker's name
+    ///
+    /// A dependency whose breaker is not closed degrades the service rather
+    /// than making it unhealthy, since requests not needing it still work.
+    pub fn register_breaker(&self, breaker: Arc<CircuitBreaker>) {
+        let name = breaker.name().to_string();
+        self.register(name, move |_| {
+            let state = breaker.state();
+            async move {
+                match state {
+                    BreakerState::Closed => CheckResult::healthy(),
+                    BreakerState::Open => CheckResult::degraded("circuit breaker is open"),
+                    BreakerState::HalfOpen => CheckResult::degraded("circuit breaker is half-open"),
+                }
+            }
+        });
+    }
+
+    /// Returns the names of the registered checks, in registration order
+    pub fn names(&self) -> Vec<String> {
+        self.lock().iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    /// Runs all checks concurrently
+    pub async fn run(&self, state: &AppState) -> ReadinessReport {
+        let checks: Vec<(String, Check)> = self.lock().clone();
+        let results = join_all(checks.into_iter().map(|(name, check)| {
+            let check = check(state.clone());
+            async move {
+                let result = tokio::time::timeout(CHECK_TIMEOUT, check)
+                    .await
+                    .unwrap_or_else(|_| CheckResult::unhealthy("check timed out"));
+                (name, result)
+            }
+        }))
+        .await;
+
+        ReadinessReport {
+            status: results
+                .iter()
+                .map(|(_, result)| result.status)
+                .max()
+                .unwrap_or(HealthStatus::Healthy),
+            checks: results.into_iter().collect(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<(String, Check)>> {
+        self.checks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Health of the user store, degraded once it is nearly full
+pub fn storage(usage: &StorageUsage) -> CheckResult {
+    match usage.fill_ratio() {
+        Some(ratio) if ratio >= STORAGE_DEGRADED_RATIO => CheckResult::degraded(format!(
+            "{} of {} users stored",
+            usage.users,
+            usage.max_users.unwrap_or_default()
+        )),
+        _ => CheckResult::healthy(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resilience::Settings;
+
+    #[tokio::test]
+    async fn test_worst_status_wins() {
+        let state = AppState::new();
+        let registry = HealthRegistry::default();
+        let report = registry.run(&state).await;
+        assert_eq!(report.status, HealthStatus::Healthy);
+        assert!(report.checks.is_empty());
+
+        registry.register("queue", |_| async { CheckResult::degraded("backlog") });
+        registry.register("mailer", |_| async { CheckResult::healthy() });
+        assert_eq!(registry.run(&state).await.status, HealthStatus::Degraded);
+
+        registry.register("mailer", |_| async { CheckResult::unhealthy("refused") });
+        assert_eq!(registry.names(), vec!["queue", "mailer"]);
+        let report = registry.run(&state).await;
+        assert_eq!(report.status, HealthStatus::Unhealthy);
+        assert_eq!(report.checks["mailer"].detail.as_deref(), Some("refused"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_check_times_out() {
+        let registry = HealthRegistry::default();
+        registry.register("slow", |_| async {
+            tokio::time::sleep(CHECK_TIMEOUT * 2).await;
+            CheckResult::healthy()
+        });
+        let report = registry.run(&AppState::new()).await;
+        assert_eq!(
+            report.checks["slow"],
+            CheckResult::unhealthy("check timed out")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_breaker_check() {
+        let settings = Settings {
+            failure_threshold: 1,
+            ..Settings::default()
+        };
+        let breaker = Arc::new(CircuitBreaker::new("mailer", &settings));
+        let registry = HealthRegistry::default();
+        registry.register_breaker(breaker.clone());
+        let state = AppState::new();
+        assert_eq!(registry.run(&state).await.status, HealthStatus::Healthy);
+
+        breaker.record_failure();
+        let report = registry.run(&state).await;
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert_eq!(
+            report.checks["mailer"].detail.as_deref(),
+            Some("circuit breaker is open")
+        );
+    }
+
+    #[test]
+    fn test_storage_degrades_when_nearly_full() {
+        let usage = |users, max_users| StorageUsage {
+            users,
+            max_users,
+            serialized_bytes: 0,
+            index_bytes: 0,
+        };
+        assert_eq!(storage(&usage(100, None)), CheckResult::healthy());
+        assert_eq!(storage(&usage(89, Some(100))), CheckResult::healthy());
+        assert_eq!(
+            storage(&usage(90, Some(100))),
+            CheckResult::degraded("90 of 100 users stored")
+        );
+    }
+}
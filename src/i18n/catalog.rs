@@ -0,0 +1,442 @@
+//! Message catalogs
+//!
+//! Each catalog maps a stable message key to a template. Placeholders are
+//! written as `{name}` and filled from the message arguments. English is
+//! the reference catalog: every key must be present there, and other
+//! languages fall back to it for missing entries.
+
+/// English (reference) catalog
+#[rustfmt::skip]
+pub static EN: &[(&str, &str)] = &[
+    ("user.not_found", "User with id {id} not found"),
+    ("user.email_exists", "User with email {email} already exists"),
+    ("user.email_in_use", "Email {email} is already in use"),
+    ("user.phone_exists", "User with phone {phone} already exists"),
+    ("user.phone_in_use", "Phone {phone} is already in use"),
+    ("user.invalid_initial_status", "Users cannot be created with status {status}"),
+    ("user.invalid_transition", "Cannot change status from {from} to {to}"),
+    ("user.id_exists", "User with id {id} already exists"),
+    ("user.modified", "User {id} has changed since revision {expected}"),
+    ("user.invalid_id", "Invalid user id '{id}': expected a UUID"),
+    ("email.empty", "Email cannot be empty"),
+    ("email.too_long", "Email is too long"),
+    ("email.invalid_format", "Invalid email format"),
+    ("email.invalid_local_part", "Invalid email local part"),
+    ("email.invalid_domain", "Invalid email domain"),
+    ("email.undeliverable", "Email domain does not accept mail"),
+    ("name.empty", "Name cannot be empty"),
+    ("name.too_short", "Name must be at least {min} characters"),
+    ("name.too_long", "Name must be at most {max} characters"),
+    ("name.control_character", "Name contains control characters"),
+    ("name.disallowed_character", "Name contains disallowed character '{char}'"),
+    ("phone.empty", "Phone cannot be empty"),
+    ("phone.invalid_format", "Invalid phone format"),
+    ("phone.invalid", "Invalid phone number"),
+    ("locale.invalid", "Invalid locale: expected a BCP 47 language tag such as en-US"),
+    ("timezone.invalid", "Invalid timezone: expected an IANA name such as Europe/Berlin"),
+    ("custom.unknown", "{name} is not a defined custom field"),
+    ("custom.required", "{name} is required"),
+    ("custom.invalid_type", "{name} must be a {type}"),
+    ("custom.pattern_mismatch", "{name} must match {pattern}"),
+    ("route.not_found", "No route matches {path}"),
+    ("route.method_not_allowed", "Method {method} is not allowed for {path}"),
+    ("route.missing_parameter", "Route is missing the {name} parameter"),
+    ("dev.count_too_large", "count must be at most {max}"),
+    ("admin.invalid_log_filter", "The log filter is invalid: {reason}"),
+    ("server.overloaded", "The server is busy; retry after {seconds} seconds"),
+    ("server.response_failed", "Failed to read the response"),
+    ("server.internal_error", "An internal error occurred"),
+    ("validation.failed", "The request violates {count} validation rule(s)"),
+    ("server.io_failed", "Reading or writing data failed"),
+    ("server.serialization_failed", "Encoding or decoding data failed"),
+    ("auth.missing_token", "A bearer token is required"),
+    ("auth.invalid_token", "The bearer token is not valid"),
+    ("auth.insufficient_scope", "The token lacks the {scope} scope"),
+    ("auth.invalid_signature", "The request signature is not valid"),
+    ("auth.expired_signature", "The request timestamp is outside the accepted window"),
+    ("auth.replayed_request", "The request nonce has already been used"),
+    ("auth.body_too_large", "Signed request bodies must be at most {max} bytes"),
+    ("auth.account_inactive", "The account is {status} and may not log in"),
+    ("ip.denied", "Requests from this address are not allowed"),
+    ("server.client_ip_unavailable", "The client address is not available"),
+    ("admin.impersonation_unavailable", "Impersonation requires authentication to be enabled"),
+    ("admin.impersonation_inactive", "User {id} is {status} and may not log in"),
+    ("storage.full", "The user store is full ({max} users)"),
+    ("consistency.invalid_token", "Invalid X-Consistency-Token header"),
+    ("consistency.not_reached", "The data for this consistency token is not available yet"),
+    ("user.merge_same", "Cannot merge user {id} into itself"),
+    ("user.deleted", "User {id} was deleted"),
+    ("user.merged", "User {id} was merged into {target}"),
+    ("undo.unknown_token", "Undo token is unknown or expired"),
+    ("trash.not_found", "User {id} is not in the trash"),
+    ("chaos.invalid_header", "Invalid X-Chaos header: {reason}"),
+    ("chaos.error", "Injected fault: internal error"),
+    ("chaos.storage_unavailable", "Injected fault: the user store is unavailable"),
+    ("capture.not_configured", "Request capture requires APP_CAPTURE_FILE to be set"),
+    ("capture.unavailable", "The capture file cannot be opened"),
+    ("snapshot.not_configured", "Snapshots require APP_SNAPSHOT_DIR to be set"),
+    ("snapshot.invalid_name", "'{snapshot}' is not a snapshot file name"),
+    ("snapshot.not_found", "Snapshot '{snapshot}' not found"),
+    ("snapshot.failed", "The snapshot cannot be read or written"),
+    ("snapshot.invalid_confirmation", "The confirmation token is unknown, expired or issued for another snapshot"),
+    ("shard.unavailable", "The instance owning this user is not reachable"),
+    ("replication.truncated", "Changes after index {index} are no longer logged; copy the full store"),
+    ("replication.leader_unavailable", "The replication leader is not reachable"),
+    ("replication.read_only", "This replica is read-only; send writes to the primary at {leader}"),
+    ("deadline.invalid", "Invalid X-Request-Timeout '{value}' (expected milliseconds, or a number with ms or s)"),
+    ("deadline.exceeded", "The request was not completed before its deadline"),
+    ("dry_run.invalid", "Invalid dry_run value '{value}' (expected true or false)"),
+    ("computed.invalid", "Invalid include_computed value '{value}' (expected true or false)"),
+    ("filter.too_long", "Filter is longer than {max} characters"),
+    ("filter.too_deep", "Filter nests deeper than {max} levels"),
+    ("filter.unexpected_end", "Filter ends unexpectedly"),
+    ("filter.unexpected_token", "Unexpected '{token}' at position {position} of the filter"),
+    ("filter.unterminated_string", "Unterminated string at position {position} of the filter"),
+    ("filter.unknown_field", "Unknown filter field '{field}'"),
+    ("filter.invalid_value", "'{value}' is not a valid value for {field}"),
+    ("filter.unsupported_operator", "{operator} cannot be applied to {field}"),
+    ("reserved.email", "The email address {email} is reserved"),
+    ("reserved.name", "The name '{name}' is reserved"),
+    ("reserved.blocked_term", "The name contains a blocked term"),
+    ("rate_limit.exceeded", "Rate limit of {requests} requests per {seconds} seconds exceeded"),
+    ("rate_limit.invalid_window", "A rate limit window must be at least one second"),
+    ("rate_limit.not_found", "No rate limit is set for principal '{principal}'"),
+    ("usage.forbidden", "Only admins may see the usage of a key other than their own, such as '{key}'"),
+    ("page.zero", "Pages are numbered from 1"),
+    ("page.size", "Page size must be between 1 and {max}"),
+    ("range.not_satisfiable", "The range starts at item {first}, past the {total} items of the collection"),
+    ("dry_run.unsupported", "{path} does not support dry runs"),
+    ("import.too_many", "An import may create at most {max} users"),
+    ("operation.invalid_id", "Invalid operation id '{id}': expected a UUID"),
+    ("operation.not_found", "Operation with id {id} not found"),
+    ("operation.finished", "Operation {id} has already finished as {status}"),
+    ("report.not_found", "Report '{report}' not found"),
+    ("report.failed", "The reports cannot be read"),
+];
+
+/// German catalog
+#[rustfmt::skip]
+pub static DE: &[(&str, &str)] = &[
+    ("user.not_found", "Benutzer mit der ID {id} wurde nicht gefunden"),
+    ("user.email_exists", "Ein Benutzer mit der E-Mail-Adresse {email} existiert bereits"),
+    ("user.email_in_use", "Die E-Mail-Adresse {email} wird bereits verwendet"),
+    ("user.phone_exists", "Ein Benutzer mit der Telefonnummer {phone} existiert bereits"),
+    ("user.phone_in_use", "Die Telefonnummer {phone} wird bereits verwendet"),
+    ("user.invalid_initial_status", "Benutzer können nicht mit dem Status {status} angelegt werden"),
+    ("user.invalid_transition", "Der Status kann nicht von {from} zu {to} geändert werden"),
+    ("user.id_exists", "Ein Benutzer mit der ID {id} existiert bereits"),
+    ("user.modified", "Benutzer {id} wurde seit Revision {expected} geändert"),
+    ("user.invalid_id", "Ungültige Benutzer-ID '{id}': erwartet wird eine UUID"),
+    ("email.empty", "Die E-Mail-Adresse darf nicht leer sein"),
+    ("email.too_long", "Die E-Mail-Adresse ist zu lang"),
+    ("email.invalid_format", "Ungültiges E-Mail-Format"),
+    ("email.invalid_local_part", "Ungültiger lokaler Teil der E-Mail-Adresse"),
+    ("email.invalid_domain", "Ungültige E-Mail-Domain"),
+    ("email.undeliverable", "Die E-Mail-Domain nimmt keine E-Mails an"),
+    ("name.empty", "Der Name darf nicht leer sein"),
+    ("name.too_short", "Der Name muss mindestens {min} Zeichen lang sein"),
+    ("name.too_long", "Der Name darf höchstens {max} Zeichen lang sein"),
+    ("name.control_character", "Der Name enthält Steuerzeichen"),
+    ("name.disallowed_character", "Der Name enthält das unzulässige Zeichen '{char}'"),
+    ("phone.empty", "Die Telefonnummer darf nicht leer sein"),
+    ("phone.invalid_format", "Ungültiges Telefonnummernformat"),
+    ("phone.invalid", "Ungültige Telefonnummer"),
+    ("locale.invalid", "Ungültige Locale: erwartet wird ein BCP-47-Sprach-Tag wie de-DE"),
+    ("timezone.invalid", "Ungültige Zeitzone: erwartet wird ein IANA-Name wie Europe/Berlin"),
+    ("custom.unknown", "{name} ist kein definiertes benutzerdefiniertes Feld"),
+    ("custom.required", "{name} ist erforderlich"),
+    ("custom.invalid_type", "{name} muss vom Typ {type} sein"),
+    ("custom.pattern_mismatch", "{name} muss dem Muster {pattern} entsprechen"),
+    ("route.not_found", "Keine Route passt zu {path}"),
+    ("route.method_not_allowed", "Die Methode {method} ist für {path} nicht erlaubt"),
+    ("route.missing_parameter", "Der Route fehlt der Parameter {name}"),
+    ("dev.count_too_large", "count darf höchstens {max} sein"),
+    ("admin.invalid_log_filter", "Der Log-Filter ist ungültig: {reason}"),
+    ("server.overloaded", "Der Server ist ausgelastet; bitte nach {seconds} Sekunden erneut versuchen"),
+    ("server.response_failed", "Die Antwort konnte nicht gelesen werden"),
+    ("server.internal_error", "Ein interner Fehler ist aufgetreten"),
+    ("validation.failed", "Die Anfrage verletzt {count} Validierungsregel(n)"),
+    ("server.io_failed", "Das Lesen oder Schreiben von Daten ist fehlgeschlagen"),
+    ("server.serialization_failed", "Das Kodieren oder Dekodieren von Daten ist fehlgeschlagen"),
+    ("auth.missing_token", "Ein Bearer-Token ist erforderlich"),
+    ("auth.invalid_token", "Das Bearer-Token ist ungültig"),
+    ("auth.insufficient_scope", "Dem Token fehlt der Scope {scope}"),
+    ("auth.invalid_signature", "Die Signatur der Anfrage ist ungültig"),
+    ("auth.expired_signature", "Der Zeitstempel der Anfrage liegt außerhalb des zulässigen Zeitfensters"),
+    ("auth.replayed_request", "Die Nonce der Anfrage wurde bereits verwendet"),
+    ("auth.body_too_large", "Signierte Anfragen dürfen höchstens {max} Bytes groß sein"),
+    ("auth.account_inactive", "Das Konto hat den Status {status} und darf sich nicht anmelden"),
+    ("ip.denied", "Anfragen von dieser Adresse sind nicht erlaubt"),
+    ("server.client_ip_unavailable", "Die Client-Adresse ist nicht verfügbar"),
+    ("admin.impersonation_unavailable", "Identitätswechsel erfordert eine aktivierte Authentifizierung"),
+    ("admin.impersonation_inactive", "Benutzer {id} hat den Status {status} und darf sich nicht anmelden"),
+    ("storage.full", "Der Benutzerspeicher ist voll ({max} Benutzer)"),
+    ("consistency.invalid_token", "Ungültiger X-Consistency-Token-Header"),
+    ("consistency.not_reached", "Die Daten zu diesem Konsistenz-Token sind noch nicht verfügbar"),
+    ("user.merge_same", "Benutzer {id} kann nicht mit sich selbst zusammengeführt werden"),
+    ("user.deleted", "Benutzer {id} wurde gelöscht"),
+    ("user.merged", "Benutzer {id} wurde mit {target} zusammengeführt"),
+    ("undo.unknown_token", "Rückgängig-Token ist unbekannt oder abgelaufen"),
+    ("trash.not_found", "Benutzer {id} ist nicht im Papierkorb"),
+    ("chaos.invalid_header", "Ungültiger X-Chaos-Header: {reason}"),
+    ("chaos.error", "Eingespeister Fehler: interner Fehler"),
+    ("chaos.storage_unavailable", "Eingespeister Fehler: der Benutzerspeicher ist nicht erreichbar"),
+    ("capture.not_configured", "Die Aufzeichnung von Anfragen erfordert APP_CAPTURE_FILE"),
+    ("capture.unavailable", "Die Aufzeichnungsdatei kann nicht geöffnet werden"),
+    ("snapshot.not_configured", "Snapshots erfordern APP_SNAPSHOT_DIR"),
+    ("snapshot.invalid_name", "'{snapshot}' ist kein gültiger Snapshot-Dateiname"),
+    ("snapshot.not_found", "Snapshot '{snapshot}' nicht gefunden"),
+    ("snapshot.failed", "Der Snapshot kann nicht gelesen oder geschrieben werden"),
+    ("snapshot.invalid_confirmation", "Das Bestätigungstoken ist unbekannt, abgelaufen oder gehört zu einem anderen Snapshot"),
+    ("shard.unavailable", "Die für diesen Benutzer zuständige Instanz ist nicht erreichbar"),
+    ("replication.truncated", "Änderungen nach Index {index} sind nicht mehr protokolliert; den vollständigen Bestand kopieren"),
+    ("replication.leader_unavailable", "Der Replikations-Leader ist nicht erreichbar"),
+    ("replication.read_only", "Dieses Replikat ist schreibgeschützt; Schreibzugriffe an den Primärserver {leader} senden"),
+    ("deadline.invalid", "Ungültiger X-Request-Timeout '{value}' (erwartet Millisekunden oder eine Zahl mit ms oder s)"),
+    ("deadline.exceeded", "Die Anfrage wurde nicht vor Ablauf ihrer Frist abgeschlossen"),
+    ("dry_run.invalid", "Ungültiger dry_run-Wert '{value}' (erwartet true oder false)"),
+    ("computed.invalid", "Ungültiger include_computed-Wert '{value}' (erwartet true oder false)"),
+    ("filter.too_long", "Der Filter ist länger als {max} Zeichen"),
+    ("filter.too_deep", "Der Filter ist tiefer als {max} Ebenen verschachtelt"),
+    ("filter.unexpected_end", "Der Filter endet unerwartet"),
+    ("filter.unexpected_token", "Unerwartetes '{token}' an Position {position} des Filters"),
+    ("filter.unterminated_string", "Nicht abgeschlossene Zeichenkette an Position {position} des Filters"),
+    ("filter.unknown_field", "Unbekanntes Filterfeld '{field}'"),
+    ("filter.invalid_value", "'{value}' ist kein gültiger Wert für {field}"),
+    ("filter.unsupported_operator", "{operator} kann nicht auf {field} angewendet werden"),
+    ("reserved.email", "Die E-Mail-Adresse {email} ist reserviert"),
+    ("reserved.name", "Der Name '{name}' ist reserviert"),
+    ("reserved.blocked_term", "Der Name enthält einen gesperrten Begriff"),
+    ("rate_limit.exceeded", "Ratenlimit von {requests} Anfragen pro {seconds} Sekunden überschritten"),
+    ("rate_limit.invalid_window", "Das Zeitfenster eines Ratenlimits muss mindestens eine Sekunde lang sein"),
+    ("rate_limit.not_found", "Für den Principal '{principal}' ist kein Ratenlimit festgelegt"),
+    ("usage.forbidden", "Nur Administratoren dürfen die Nutzung eines fremden Schlüssels wie '{key}' einsehen"),
+    ("page.zero", "Seiten werden ab 1 gezählt"),
+    ("page.size", "Die Seitengröße muss zwischen 1 und {max} liegen"),
+    ("range.not_satisfiable", "Der Bereich beginnt bei Eintrag {first}, nach den {total} Einträgen der Sammlung"),
+    ("dry_run.unsupported", "{path} unterstützt keine Probeläufe"),
+    ("import.too_many", "Ein Import darf höchstens {max} Benutzer anlegen"),
+    ("operation.invalid_id", "Ungültige Vorgangs-ID '{id}': erwartet wird eine UUID"),
+    ("operation.not_found", "Vorgang mit der ID {id} wurde nicht gefunden"),
+    ("operation.finished", "Vorgang {id} ist bereits mit dem Status {status} beendet"),
+    ("report.not_found", "Bericht '{report}' nicht gefunden"),
+    ("report.failed", "Die Berichte können nicht gelesen werden"),
+];
+
+/// French catalog
+#[rustfmt::skip]
+pub static FR: &[(&str, &str)] = &[
+    ("user.not_found", "Utilisateur avec l'identifiant {id} introuvable"),
+    ("user.email_exists", "Un utilisateur avec l'e-mail {email} existe déjà"),
+    ("user.email_in_use", "L'e-mail {email} est déjà utilisé"),
+    ("user.phone_exists", "Un utilisateur avec le téléphone {phone} existe déjà"),
+    ("user.phone_in_use", "Le téléphone {phone} est déjà utilisé"),
+    ("user.invalid_initial_status", "Impossible de créer un utilisateur avec le statut {status}"),
+    ("user.invalid_transition", "Impossible de passer du statut {from} au statut {to}"),
+    ("user.id_exists", "L'utilisateur avec l'identifiant {id} existe déjà"),
+    ("user.modified", "L'utilisateur {id} a changé depuis la révision {expected}"),
+    ("user.invalid_id", "Identifiant d'utilisateur '{id}' invalide : un UUID est attendu"),
+    ("email.empty", "L'e-mail ne peut pas être vide"),
+    ("email.too_long", "L'e-mail est trop long"),
+    ("email.invalid_format", "Format d'e-mail invalide"),
+    ("email.invalid_local_part", "Partie locale de l'e-mail invalide"),
+    ("email.invalid_domain", "Domaine de l'e-mail invalide"),
+    ("email.undeliverable", "Le domaine de l'e-mail n'accepte pas de courrier"),
+    ("name.empty", "Le nom ne peut pas être vide"),
+    ("name.too_short", "Le nom doit contenir au moins {min} caractères"),
+    ("name.too_long", "Le nom doit contenir au plus {max} caractères"),
+    ("name.control_character", "Le nom contient des caractères de contrôle"),
+    ("name.disallowed_character", "Le nom contient le caractère interdit '{char}'"),
+    ("phone.empty", "Le téléphone ne peut pas être vide"),
+    ("phone.invalid_format", "Format de téléphone invalide"),
+    ("phone.invalid", "Numéro de téléphone invalide"),
+    ("locale.invalid", "Locale invalide : une étiquette de langue BCP 47 comme fr-FR est attendue"),
+    ("timezone.invalid", "Fuseau horaire invalide : un nom IANA comme Europe/Paris est attendu"),
+    ("custom.unknown", "{name} n'est pas un champ personnalisé défini"),
+    ("custom.required", "{name} est obligatoire"),
+    ("custom.invalid_type", "{name} doit être de type {type}"),
+    ("custom.pattern_mismatch", "{name} doit correspondre à {pattern}"),
+    ("route.not_found", "Aucune route ne correspond à {path}"),
+    ("route.method_not_allowed", "La méthode {method} n'est pas autorisée pour {path}"),
+    ("route.missing_parameter", "Le paramètre {name} manque dans la route"),
+    ("dev.count_too_large", "count ne doit pas dépasser {max}"),
+    ("admin.invalid_log_filter", "Le filtre de journalisation est invalide : {reason}"),
+    ("server.overloaded", "Le serveur est occupé ; réessayez dans {seconds} secondes"),
+    ("server.response_failed", "Impossible de lire la réponse"),
+    ("server.internal_error", "Une erreur interne s'est produite"),
+    ("validation.failed", "La requête enfreint {count} règle(s) de validation"),
+    ("server.io_failed", "La lecture ou l'écriture des données a échoué"),
+    ("server.serialization_failed", "L'encodage ou le décodage des données a échoué"),
+    ("auth.missing_token", "Un jeton bearer est requis"),
+    ("auth.invalid_token", "Le jeton bearer n'est pas valide"),
+    ("auth.insufficient_scope", "Le jeton n'a pas la portée {scope}"),
+    ("auth.invalid_signature", "La signature de la requête n'est pas valide"),
+    ("auth.expired_signature", "L'horodatage de la requête est en dehors de la fenêtre acceptée"),
+    ("auth.replayed_request", "Le nonce de la requête a déjà été utilisé"),
+    ("auth.body_too_large", "Le corps d'une requête signée ne doit pas dépasser {max} octets"),
+    ("auth.account_inactive", "Le compte a le statut {status} et ne peut pas se connecter"),
+    ("ip.denied", "Les requêtes provenant de cette adresse ne sont pas autorisées"),
+    ("server.client_ip_unavailable", "L'adresse du client n'est pas disponible"),
+    ("admin.impersonation_unavailable", "L'usurpation d'identité nécessite que l'authentification soit activée"),
+    ("admin.impersonation_inactive", "L'utilisateur {id} a le statut {status} et ne peut pas se connecter"),
+    ("storage.full", "Le stockage des utilisateurs est plein ({max} utilisateurs)"),
+    ("consistency.invalid_token", "En-tête X-Consistency-Token invalide"),
+    ("consistency.not_reached", "Les données de ce jeton de cohérence ne sont pas encore disponibles"),
+    ("user.merge_same", "Impossible de fusionner l'utilisateur {id} avec lui-même"),
+    ("user.deleted", "L'utilisateur {id} a été supprimé"),
+    ("user.merged", "L'utilisateur {id} a été fusionné avec {target}"),
+    ("undo.unknown_token", "Le jeton d'annulation est inconnu ou expiré"),
+    ("trash.not_found", "L'utilisateur {id} n'est pas dans la corbeille"),
+    ("chaos.invalid_header", "En-tête X-Chaos invalide : {reason}"),
+    ("chaos.error", "Panne injectée : erreur interne"),
+    ("chaos.storage_unavailable", "Panne injectée : le stockage des utilisateurs est indisponible"),
+    ("capture.not_configured", "L'enregistrement des requêtes nécessite APP_CAPTURE_FILE"),
+    ("capture.unavailable", "Le fichier d'enregistrement ne peut pas être ouvert"),
+    ("snapshot.not_configured", "Les instantanés nécessitent APP_SNAPSHOT_DIR"),
+    ("snapshot.invalid_name", "'{snapshot}' n'est pas un nom de fichier d'instantané"),
+    ("snapshot.not_found", "Instantané '{snapshot}' introuvable"),
+    ("snapshot.failed", "L'instantané ne peut pas être lu ou écrit"),
+    ("snapshot.invalid_confirmation", "Le jeton de confirmation est inconnu, expiré ou émis pour un autre instantané"),
+    ("shard.unavailable", "L'instance responsable de cet utilisateur est injoignable"),
+    ("replication.truncated", "Les modifications après l'index {index} ne sont plus journalisées ; copiez le stockage complet"),
+    ("replication.leader_unavailable", "Le leader de réplication est injoignable"),
+    ("replication.read_only", "Ce réplica est en lecture seule ; envoyez les écritures au primaire {leader}"),
+    ("deadline.invalid", "X-Request-Timeout '{value}' invalide (millisecondes, ou un nombre suivi de ms ou s, attendu)"),
+    ("deadline.exceeded", "La requête n'a pas été traitée avant son échéance"),
+    ("dry_run.invalid", "Valeur dry_run '{value}' invalide (true ou false attendu)"),
+    ("computed.invalid", "Valeur include_computed '{value}' invalide (true ou false attendu)"),
+    ("filter.too_long", "Le filtre dépasse {max} caractères"),
+    ("filter.too_deep", "Le filtre est imbriqué sur plus de {max} niveaux"),
+    ("filter.unexpected_end", "Le filtre se termine de manière inattendue"),
+    ("filter.unexpected_token", "'{token}' inattendu à la position {position} du filtre"),
+    ("filter.unterminated_string", "Chaîne non terminée à la position {position} du filtre"),
+    ("filter.unknown_field", "Champ de filtre '{field}' inconnu"),
+    ("filter.invalid_value", "'{value}' n'est pas une valeur valide pour {field}"),
+    ("filter.unsupported_operator", "{operator} ne peut pas s'appliquer à {field}"),
+    ("reserved.email", "L'adresse e-mail {email} est réservée"),
+    ("reserved.name", "Le nom '{name}' est réservé"),
+    ("reserved.blocked_term", "Le nom contient un terme interdit"),
+    ("rate_limit.exceeded", "Limite de {requests} requêtes par {seconds} secondes dépassée"),
+    ("rate_limit.invalid_window", "La fenêtre d'une limite de débit doit durer au moins une seconde"),
+    ("rate_limit.not_found", "Aucune limite de débit n'est définie pour le principal '{principal}'"),
+    ("usage.forbidden", "Seuls les administrateurs peuvent consulter l'utilisation d'une autre clé, comme '{key}'"),
+    ("page.zero", "Les pages sont numérotées à partir de 1"),
+    ("page.size", "La taille de page doit être comprise entre 1 et {max}"),
+    ("range.not_satisfiable", "La plage commence à l'élément {first}, au-delà des {total} éléments de la collection"),
+    ("dry_run.unsupported", "{path} ne prend pas en charge les simulations"),
+    ("import.too_many", "Un import peut créer au plus {max} utilisateurs"),
+    ("operation.invalid_id", "Identifiant d'opération '{id}' invalide : un UUID est attendu"),
+    ("operation.not_found", "Opération avec l'identifiant {id} introuvable"),
+    ("operation.finished", "L'opération {id} est déjà terminée avec le statut {status}"),
+    ("report.not_found", "Rapport '{report}' introuvable"),
+    ("report.failed", "Les rapports ne peuvent pas être lus"),
+];
+
+/// Spanish catalog
+#[rustfmt::skip]
+pub static ES: &[(&str, &str)] = &[
+    ("user.not_found", "No se encontró el usuario con id {id}"),
+    ("user.email_exists", "Ya existe un usuario con el correo {email}"),
+    ("user.email_in_use", "El correo {email} ya está en uso"),
+    ("user.phone_exists", "Ya existe un usuario con el teléfono {phone}"),
+    ("user.phone_in_use", "El teléfono {phone} ya está en uso"),
+    ("user.invalid_initial_status", "No se pueden crear usuarios con el estado {status}"),
+    ("user.invalid_transition", "No se puede cambiar el estado de {from} a {to}"),
+    ("user.id_exists", "Ya existe un usuario con el id {id}"),
+    ("user.modified", "El usuario {id} ha cambiado desde la revisión {expected}"),
+    ("user.invalid_id", "Id de usuario '{id}' no válido: se espera un UUID"),
+    ("email.empty", "El correo no puede estar vacío"),
+    ("email.too_long", "El correo es demasiado largo"),
+    ("email.invalid_format", "Formato de correo no válido"),
+    ("email.invalid_local_part", "Parte local del correo no válida"),
+    ("email.invalid_domain", "Dominio del correo no válido"),
+    ("email.undeliverable", "El dominio del correo no acepta mensajes"),
+    ("name.empty", "El nombre no puede estar vacío"),
+    ("name.too_short", "El nombre debe tener al menos {min} caracteres"),
+    ("name.too_long", "El nombre debe tener como máximo {max} caracteres"),
+    ("name.control_character", "El nombre contiene caracteres de control"),
+    ("name.disallowed_character", "El nombre contiene el carácter no permitido '{char}'"),
+    ("phone.empty", "El teléfono no puede estar vacío"),
+    ("phone.invalid_format", "Formato de teléfono no válido"),
+    ("phone.invalid", "Número de teléfono no válido"),
+    ("locale.invalid", "Configuración regional no válida: se espera una etiqueta BCP 47 como es-ES"),
+    ("timezone.invalid", "Zona horaria no válida: se espera un nombre IANA como Europe/Madrid"),
+    ("custom.unknown", "{name} no es un campo personalizado definido"),
+    ("custom.required", "{name} es obligatorio"),
+    ("custom.invalid_type", "{name} debe ser de tipo {type}"),
+    ("custom.pattern_mismatch", "{name} debe coincidir con {pattern}"),
+    ("route.not_found", "Ninguna ruta coincide con {path}"),
+    ("route.method_not_allowed", "El método {method} no está permitido para {path}"),
+    ("route.missing_parameter", "Falta el parámetro {name} en la ruta"),
+    ("dev.count_too_large", "count debe ser como máximo {max}"),
+    ("admin.invalid_log_filter", "El filtro de registro no es válido: {reason}"),
+    ("server.overloaded", "El servidor está ocupado; reintente dentro de {seconds} segundos"),
+    ("server.response_failed", "No se pudo leer la respuesta"),
+    ("server.internal_error", "Se produjo un error interno"),
+    ("validation.failed", "La solicitud infringe {count} regla(s) de validación"),
+    ("server.io_failed", "No se pudieron leer o escribir los datos"),
+    ("server.serialization_failed", "No se pudieron codificar o decodificar los datos"),
+    ("auth.missing_token", "Se requiere un token bearer"),
+    ("auth.invalid_token", "El token bearer no es válido"),
+    ("auth.insufficient_scope", "El token no tiene el ámbito {scope}"),
+    ("auth.invalid_signature", "La firma de la solicitud no es válida"),
+    ("auth.expired_signature", "La marca de tiempo de la solicitud está fuera de la ventana aceptada"),
+    ("auth.replayed_request", "El nonce de la solicitud ya se ha utilizado"),
+    ("auth.body_too_large", "El cuerpo de una solicitud firmada debe tener como máximo {max} bytes"),
+    ("auth.account_inactive", "La cuenta tiene el estado {status} y no puede iniciar sesión"),
+    ("ip.denied", "No se permiten solicitudes desde esta dirección"),
+    ("server.client_ip_unavailable", "La dirección del cliente no está disponible"),
+    ("admin.impersonation_unavailable", "La suplantación requiere que la autenticación esté habilitada"),
+    ("admin.impersonation_inactive", "El usuario {id} tiene el estado {status} y no puede iniciar sesión"),
+    ("storage.full", "El almacenamiento de usuarios está lleno ({max} usuarios)"),
+    ("consistency.invalid_token", "Cabecera X-Consistency-Token no válida"),
+    ("consistency.not_reached", "Los datos de este token de consistencia aún no están disponibles"),
+    ("user.merge_same", "No se puede fusionar el usuario {id} consigo mismo"),
+    ("user.deleted", "El usuario {id} fue eliminado"),
+    ("user.merged", "El usuario {id} se fusionó con {target}"),
+    ("undo.unknown_token", "El token para deshacer es desconocido o ha caducado"),
+    ("trash.not_found", "El usuario {id} no está en la papelera"),
+    ("chaos.invalid_header", "Cabecera X-Chaos no válida: {reason}"),
+    ("chaos.error", "Fallo inyectado: error interno"),
+    ("chaos.storage_unavailable", "Fallo inyectado: el almacén de usuarios no está disponible"),
+    ("capture.not_configured", "La captura de solicitudes requiere APP_CAPTURE_FILE"),
+    ("capture.unavailable", "No se puede abrir el archivo de captura"),
+    ("snapshot.not_configured", "Las instantáneas requieren APP_SNAPSHOT_DIR"),
+    ("snapshot.invalid_name", "'{snapshot}' no es un nombre de archivo de instantánea"),
+    ("snapshot.not_found", "Instantánea '{snapshot}' no encontrada"),
+    ("snapshot.failed", "No se puede leer ni escribir la instantánea"),
+    ("snapshot.invalid_confirmation", "El token de confirmación es desconocido, ha caducado o pertenece a otra instantánea"),
+    ("shard.unavailable", "La instancia responsable de este usuario no está disponible"),
+    ("replication.truncated", "Los cambios posteriores al índice {index} ya no están registrados; copie el almacenamiento completo"),
+    ("replication.leader_unavailable", "El líder de replicación no está disponible"),
+    ("replication.read_only", "Esta réplica es de solo lectura; envíe las escrituras al primario {leader}"),
+    ("deadline.invalid", "X-Request-Timeout '{value}' no válido (se esperaban milisegundos, o un número con ms o s)"),
+    ("deadline.exceeded", "La solicitud no se completó antes de su plazo"),
+    ("dry_run.invalid", "Valor de dry_run '{value}' no válido (se esperaba true o false)"),
+    ("computed.invalid", "Valor de include_computed '{value}' no válido (se esperaba true o false)"),
+    ("filter.too_long", "El filtro supera los {max} caracteres"),
+    ("filter.too_deep", "El filtro se anida en más de {max} niveles"),
+    ("filter.unexpected_end", "El filtro termina de forma inesperada"),
+    ("filter.unexpected_token", "'{token}' inesperado en la posición {position} del filtro"),
+    ("filter.unterminated_string", "Cadena sin cerrar en la posición {position} del filtro"),
+    ("filter.unknown_field", "Campo de filtro '{field}' desconocido"),
+    ("filter.invalid_value", "'{value}' no es un valor válido para {field}"),
+    ("filter.unsupported_operator", "{operator} no se puede aplicar a {field}"),
+    ("reserved.email", "La dirección de correo electrónico {email} está reservada"),
+    ("reserved.name", "El nombre '{name}' está reservado"),
+    ("reserved.blocked_term", "El nombre contiene un término bloqueado"),
+    ("rate_limit.exceeded", "Se superó el límite de {requests} solicitudes cada {seconds} segundos"),
+    ("rate_limit.invalid_window", "La ventana de un límite de solicitudes debe durar al menos un segundo"),
+    ("rate_limit.not_found", "No hay ningún límite de solicitudes para el principal '{principal}'"),
+    ("usage.forbidden", "Solo los administradores pueden ver el uso de otra clave, como '{key}'"),
+    ("page.zero", "Las páginas se numeran desde 1"),
+    ("page.size", "El tamaño de página debe estar entre 1 y {max}"),
+    ("range.not_satisfiable", "El rango empieza en el elemento {first}, más allá de los {total} elementos de la colección"),
+    ("dry_run.unsupported", "{path} no admite simulaciones"),
+    ("import.too_many", "Una importación puede crear como máximo {max} usuarios"),
+    ("operation.invalid_id", "Id de operación '{id}' no válido: se espera un UUID"),
+    ("operation.not_found", "No se encontró la operación con id {id}"),
+    ("operation.finished", "La operación {id} ya terminó con el estado {status}"),
+    ("report.not_found", "Informe '{report}' no encontrado"),
+    ("report.failed", "No se pueden leer los informes"),
+];
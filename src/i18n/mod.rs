@@ -0,0 +1,348 @@
+//! Localization of API messages
+//!
+//! Error messages are identified by stable keys (such as `user.not_found`)
+//! rather than English text. A [`Message`] carries the key and its
+//! arguments and is rendered into a concrete [`Language`] only when the
+//! response is written, so the same error can be returned in whatever
+//! language the client asked for.
+//!
+//! The language of a request, its [`ActiveLocale`], is the best match for
+//! its `Accept-Language` header. Without one, it is the preferred locale of
+//! the user the caller authenticated as, such as an impersonated user, and
+//! otherwise `APP_DEFAULT_LANGUAGE`. Responses name it in
+//! `Content-Language`.
+
+mod catalog;
+
+use std::convert::Infallible;
+use std::str::FromStr;
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts, Request, State},
+    http::{
+        header::{ACCEPT_LANGUAGE, CONTENT_LANGUAGE, CONTENT_LENGTH},
+        request::Parts,
+        HeaderValue,
+    },
+    middleware::Next,
+    response::Response,
+};
+
+use crate::auth::Principal;
+use crate::error::ApiError;
+use crate::AppState;
+
+/// Languages with a message catalog
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Language {
+    /// English, the reference language
+    #[default]
+    En,
+    /// German
+    De,
+    /// French
+    Fr,
+    /// Spanish
+    Es,
+}
+
+impl Language {
+    /// All supported languages, reference language first
+    pub const ALL: [Language; 4] = [Language::En, Language::De, Language::Fr, Language::Es];
+
+    /// Returns the BCP 47 primary language subtag
+    pub fn tag(self) -> &'static str {
+        match self {
+            Language::En => "en",
+            Language::De => "de",
+            Language::Fr => "fr",
+            Language::Es => "es",
+        }
+    }
+
+    /// Looks up a language by its BCP 47 tag, ignoring region and script
+    ///
+    /// `de`, `de-AT` and `DE-ch` all resolve to [`Language::De`].
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let primary = tag.split(['-', '_']).next()?.trim();
+        Self::ALL
+            .into_iter()
+            .find(|language| language.tag().eq_ignore_ascii_case(primary))
+    }
+
+    fn catalog(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Language::En => catalog::EN,
+            Language::De => catalog::DE,
+            Language::Fr => catalog::FR,
+            Language::Es => catalog::ES,
+        }
+    }
+
+    fn lookup(self, key: &str) -> Option<&'static str> {
+        self.catalog()
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, template)| *template)
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_tag(s).ok_or_else(|| {
+            format!(
+                "unsupported language '{}', expected one of en, de, fr, es",
+                s
+            )
+        })
+    }
+}
+
+/// Picks the best supported language for an `Accept-Language` header
+///
+/// Ranges are ordered by quality value; ranges with `q=0` and unsupported
+/// languages are skipped. Returns `None` when nothing matches.
+pub fn negotiate(accept_language: &str) -> Option<Language> {
+    let mut ranges: Vec<(f32, Language)> = accept_language
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+
+            if quality <= 0.0 {
+                return None;
+            }
+            Language::from_tag(tag).map(|language| (quality, language))
+        })
+        .collect();
+
+    // Stable sort keeps header order for equal quality values
+    ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+    ranges.first().map(|(_, language)| *language)
+}
+
+/// A localizable message: a catalog key plus named arguments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    key: &'static str,
+    args: Vec<(&'static str, String)>,
+}
+
+impl Message {
+    /// Creates a message for a catalog key
+    pub fn new(key: &'static str) -> Self {
+        Self {
+            key,
+            args: Vec::new(),
+        }
+    }
+
+    /// Adds a named argument substituted for `{name}` in the template
+    pub fn with(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.args.push((name, value.to_string()));
+        self
+    }
+
+    /// Returns the stable catalog key
+    pub fn key(&self) -> &'static str {
+        self.key
+    }
+
+    /// Renders the message in the given language
+    ///
+    /// Falls back to English for keys missing from the language's catalog,
+    /// and to the bare key if even English has no entry.
+    pub fn render(&self, language: Language) -> String {
+        let template = language
+            .lookup(self.key)
+            .or_else(|| Language::En.lookup(self.key))
+            .unwrap_or(self.key);
+
+        self.args
+            .iter()
+            .fold(template.to_string(), |text, (name, value)| {
+                text.replace(&format!("{{{}}}", name), value)
+            })
+    }
+}
+
+impl std::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.render(Language::En))
+    }
+}
+
+/// The language a request is answered in
+///
+/// Handlers use it for text they render themselves; error responses are
+/// localized by [`localize`] in the same language.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ActiveLocale(pub Language);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ActiveLocale
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = AppState::from_ref(state);
+        let requested = requested(parts.headers.get(ACCEPT_LANGUAGE));
+        let principal = parts.extensions.get::<Principal>();
+        Ok(ActiveLocale(resolve(&state, requested, principal).await))
+    }
+}
+
+/// Returns the language `Accept-Language` asks for, if it is supported
+fn requested(accept_language: Option<&HeaderValue>) -> Option<Language> {
+    accept_language
+        .and_then(|value| value.to_str().ok())
+        .and_then(negotiate)
+}
+
+/// Picks the requested language, or the caller's preferred one, or the
+/// configured default
+async fn resolve(
+    state: &AppState,
+    requested: Option<Language>,
+    principal: Option<&Principal>,
+) -> Language {
+    if let Some(language) = requested {
+        return language;
+    }
+    let user = principal.and_then(Principal::user_id);
+    let preferred = match user {
+        Some(id) => state
+            .storage
+            .read()
+            .await
+            .get(&id)
+            .and_then(|user| user.locale)
+            .and_then(|locale| Language::from_tag(&locale)),
+        None => None,
+    };
+    preferred.unwrap_or(state.config.default_language)
+}
+
+/// Middleware answering in the request's [`ActiveLocale`]
+///
+/// [`ApiError`] responses carry the original error as a response
+/// extension; their body is re-rendered in the active language, keeping
+/// all other response headers. Every response names the language in
+/// `Content-Language`, unless its handler set one.
+pub async fn localize(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let requested = requested(request.headers().get(ACCEPT_LANGUAGE));
+
+    let mut response = next.run(request).await;
+
+    // Authentication runs within this layer, so the caller is only known
+    // from the response
+    let principal = response.extensions().get::<Principal>();
+    let language = resolve(&state, requested, principal).await;
+    if let Some(error) = response.extensions_mut().remove::<ApiError>() {
+        response.headers_mut().remove(CONTENT_LENGTH);
+        *response.body_mut() = error.body(language).to_string().into();
+    }
+    response
+        .headers_mut()
+        .entry(CONTENT_LANGUAGE)
+        .or_insert(HeaderValue::from_static(language.tag()));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalogs_cover_reference_keys() {
+        for language in Language::ALL {
+            for (key, template) in catalog::EN {
+                let translated = language
+                    .lookup(key)
+                    .unwrap_or_else(|| panic!("{:?} is missing {}", language, key));
+
+                // Every placeholder in the reference must survive translation
+                for placeholder in template.split('{').skip(1) {
+                    let name = placeholder.split('}').next().unwrap();
+                    assert!(
+                        translated.contains(&format!("{{{}}}", name)),
+                        "{:?} {} lacks {{{}}}",
+                        language,
+                        key,
+                        name
+                    );
+                }
+            }
+            assert_eq!(language.catalog().len(), catalog::EN.len());
+        }
+    }
+
+    #[test]
+    fn test_message_render() {
+        let message = Message::new("user.invalid_transition")
+            .with("from", "active")
+            .with("to", "pending");
+
+        assert_eq!(
+            message.render(Language::En),
+            "Cannot change status from active to pending"
+        );
+        assert_eq!(
+            message.render(Language::De),
+            "Der Status kann nicht von active zu pending geändert werden"
+        );
+        assert_eq!(Message::new("no.such.key").to_string(), "no.such.key");
+    }
+
+    #[test]
+    fn test_parse_language() {
+        assert_eq!("de-AT".parse(), Ok(Language::De));
+        assert!("ja".parse::<Language>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_falls_back_to_profile() {
+        let state = AppState::new();
+        let user = crate::models::User {
+            locale: Some("es-MX".to_string()),
+            ..crate::models::User::new("Profile User", "profile@example.com", chrono::Utc::now())
+        };
+        state.storage.write().await.create(user.clone()).unwrap();
+        let principal = Principal {
+            name: user.id.to_string(),
+            scopes: Vec::new(),
+            impersonator: Some("admin".to_string()),
+        };
+
+        assert_eq!(
+            resolve(&state, Some(Language::Fr), Some(&principal)).await,
+            Language::Fr
+        );
+        assert_eq!(resolve(&state, None, Some(&principal)).await, Language::Es);
+        let other = Principal {
+            name: "ops".to_string(),
+            ..principal
+        };
+        assert_eq!(resolve(&state, None, Some(&other)).await, Language::En);
+        assert_eq!(resolve(&state, None, None).await, Language::En);
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("de-DE,de;q=0.9,en;q=0.8"), Some(Language::De));
+        assert_eq!(negotiate("ja, fr-CA;q=0.5, es;q=0.7"), Some(Language::Es));
+        assert_eq!(negotiate("en;q=0.2, de;q=0"), Some(Language::En));
+        assert_eq!(negotiate("*"), None);
+        assert_eq!(negotiate("ja"), None);
+        assert_eq!(negotiate(""), None);
+    }
+}
@@ -0,0 +1,112 @@
+//! IP allow and deny lists
+//!
+//! `APP_IP_ALLOW` and `APP_IP_DENY` hold addresses and CIDR ranges. A
+//! request is rejected with `403 Forbidden` if its client address is in
+//! the deny list, or if an allow list is configured and the address is not
+//! in it. The deny list wins when both match.
+//!
+//! Lists are matched against the client address as resolved by
+//! [`crate::client_ip`], so callers behind a trusted proxy are filtered by
+//! their own address rather than the proxy's.
+//!
+//! Rejected requests are logged and counted in [`crate::metrics::Metrics`].
+
+use std::net::IpAddr;
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::client_ip::Cidr;
+use crate::error::ApiError;
+use crate::extract::ClientIp;
+use crate::i18n::Message;
+use crate::AppState;
+
+/// The configured address lists
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    /// Ranges allowed to call the API; everyone when empty
+    pub allow: Vec<Cidr>,
+    /// Ranges never allowed to call the API
+    pub deny: Vec<Cidr>,
+}
+
+impl Settings {
+    /// Returns `true` if no allow or deny list is configured
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Returns `true` if requests from `ip` are allowed
+    ///
+    /// An unknown client address only passes when no allow list is set.
+    pub fn permits(&self, ip: Option<IpAddr>) -> bool {
+        let Some(ip) = ip else {
+            return self.allow.is_empty();
+        };
+        let listed = |ranges: &[Cidr]| ranges.iter().any(|range| range.contains(&ip));
+        !listed(&self.deny) && (self.allow.is_empty() || listed(&self.allow))
+    }
+}
+
+/// Middleware rejecting requests from addresses the lists exclude
+pub async fn check(
+    State(state): State<AppState>,
+    client_ip: Option<ClientIp>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let settings = &state.config.ip_filter;
+    if settings.is_empty() {
+        return next.run(request).await;
+    }
+
+    let ip = client_ip.map(|ClientIp(ip)| ip);
+    if !settings.permits(ip) {
+        tracing::warn!(
+            client_ip = %ip.map_or_else(|| "unknown".to_string(), |ip| ip.to_string()),
+            path = %request.uri().path(),
+            "request denied by IP filter"
+        );
+        state.metrics.record_ip_denied();
+        return ApiError::Forbidden(Message::new("ip.denied")).into_response();
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(ranges: &[&str]) -> Vec<Cidr> {
+        ranges.iter().map(|range| range.parse().unwrap()).collect()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn test_allow_and_deny() {
+        let settings = Settings {
+            allow: ranges(&["10.0.0.0/8"]),
+            deny: ranges(&["10.0.0.13"]),
+        };
+        assert!(settings.permits(Some(ip("10.0.0.12"))));
+        assert!(!settings.permits(Some(ip("10.0.0.13"))));
+        assert!(!settings.permits(Some(ip("192.0.2.1"))));
+        assert!(!settings.permits(None));
+
+        let settings = Settings {
+            deny: ranges(&["192.0.2.0/24"]),
+            ..Settings::default()
+        };
+        assert!(settings.permits(Some(ip("10.0.0.1"))));
+        assert!(!settings.permits(Some(ip("192.0.2.1"))));
+        assert!(settings.permits(None));
+    }
+}
@@ -0,0 +1,484 @@
+//! Background jobs for long-running operations
+//!
+//! Requests that take too long to answer right away, such as bulk imports
+//! and exports of users or reports, are answered with `202 Accepted` and an
+//! [`Operation`] describing the work, which then runs as a job in the
+//! background. At most `APP_JOB_WORKERS` jobs run at once; the others wait
+//! in the queue as `pending`. Clients poll `GET /api/v1/operations/:id`
+//! for progress and, once the job has finished, its result.
+//!
+//! `DELETE /api/v1/operations/:id` cancels an operation. Pending ones are
+//! cancelled right away. Running ones are `cancelling` until the job
+//! notices: jobs check [`Progress::is_cancelled`] between items and stop
+//! early, keeping whatever they finished as the operation's result.
+//!
+//! Operations live in memory: the last [`RETAINED_OPERATIONS`] finished
+//! ones are kept, and all of them are lost on restart.
+
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::mock::Clock;
+use crate::models::{ImportReport, User};
+use crate::reports::Report;
+
+/// Finished operations kept for clients to collect their results
+pub const RETAINED_OPERATIONS: usize = 1000;
+
+/// What an operation does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationKind {
+    /// Creating users in bulk
+    Import,
+    /// Reading all users
+    Export,
+    /// Writing a report on the users, see [`crate::reports`]
+    Report,
+}
+
+/// Where an operation is in its lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationStatus {
+    /// Queued until a worker is free
+    Pending,
+    /// Being worked on
+    Running,
+    /// Asked to stop; the job stops at its next check
+    Cancelling,
+    /// Finished; `result` holds what it produced
+    Succeeded,
+    /// Stopped by an error; `error` describes it
+    Failed,
+    /// Stopped on request; `result` holds what was done until then, if the
+    /// job had started
+    Cancelled,
+}
+
+impl OperationStatus {
+    /// Whether the operation has finished, successfully or not
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            OperationStatus::Succeeded | OperationStatus::Failed | OperationStatus::Cancelled
+        )
+    }
+
+    /// Returns the status as written in JSON
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OperationStatus::Pending => "pending",
+            OperationStatus::Running => "running",
+            OperationStatus::Cancelling => "cancelling",
+            OperationStatus::Succeeded => "succeeded",
+            OperationStatus::Failed => "failed",
+            OperationStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl std::fmt::Display for OperationStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What a finished operation produced
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum OperationResult {
+    /// Outcome of an import
+    Import(ImportReport),
+    /// The exported users
+    Export {
+        /// The users, in export order
+        #[schema(value_type = Vec<User>)]
+        users: Vec<Arc<User>>,
+        /// Number of exported users
+        count: usize,
+    },
+    /// The report written
+    Report {
+        /// The report, as listed by `GET /api/v1/admin/reports`
+        report: Report,
+    },
+}
+
+/// A long-running operation, response of `GET /api/v1/operations/:id`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Operation {
+    /// Operation ID
+    pub id: Uuid,
+    /// What the operation does
+    pub kind: OperationKind,
+    /// Where the operation is in its lifecycle
+    pub status: OperationStatus,
+    /// Items processed so far
+    pub done: usize,
+    /// Items to process
+    pub total: usize,
+    /// When the operation was requested
+    #[serde(with = "crate::timestamps")]
+    #[schema(schema_with = crate::timestamps::schema)]
+    pub created_at: DateTime<Utc>,
+    /// When the operation finished
+    #[serde(default, with = "crate::timestamps::option")]
+    #[schema(schema_with = crate::timestamps::option::schema)]
+    pub finished_at: Option<DateTime<Utc>>,
+    /// What the operation produced, once it succeeded or was cancelled
+    pub result: Option<OperationResult>,
+    /// Why the operation failed
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Operations {
+    by_id: HashMap<Uuid, Operation>,
+    /// Cancellation tokens of unfinished operations
+    tokens: HashMap<Uuid, CancellationToken>,
+    /// IDs of finished operations, oldest first
+    finished: VecDeque<Uuid>,
+}
+
+impl Operations {
+    /// Forgets the token of the finished operation `id` and expires the
+    /// oldest finished operations beyond [`RETAINED_OPERATIONS`]
+    fn retire(&mut self, id: Uuid) {
+        self.tokens.remove(&id);
+        self.finished.push_back(id);
+        if self.finished.len() > RETAINED_OPERATIONS {
+            if let Some(expired) = self.finished.pop_front() {
+                self.by_id.remove(&expired);
+            }
+        }
+    }
+}
+
+/// The job queue, in [`crate::AppState::jobs`]
+#[derive(Debug)]
+pub struct Jobs {
+    operations: Mutex<Operations>,
+    workers: Arc<Semaphore>,
+}
+
+/// Reports a job's progress on its operation and tells it when to stop
+#[derive(Debug, Clone)]
+pub struct Progress {
+    jobs: Arc<Jobs>,
+    id: Uuid,
+    token: CancellationToken,
+}
+
+impl Progress {
+    /// Sets the number of items to process
+    pub fn set_total(&self, total: usize) {
+        self.jobs
+            .update(self.id, |operation| operation.total = total);
+    }
+
+    /// Counts `count` more items as processed
+    pub fn advance(&self, count: usize) {
+        self.jobs
+            .update(self.id, |operation| operation.done += count);
+    }
+
+    /// Whether the operation was cancelled; the job should stop as soon as
+    /// it can
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Waits until the operation is cancelled
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await
+    }
+}
+
+impl Jobs {
+    /// Creates a queue running at most `workers` jobs at once
+    pub fn new(workers: usize) -> Self {
+        Self {
+            operations: Mutex::default(),
+            workers: Arc::new(Semaphore::new(workers)),
+        }
+    }
+
+    /// Returns the operation `id`, unless it is unknown or expired
+    pub fn get(&self, id: Uuid) -> Option<Operation> {
+        self.lock().by_id.get(&id).cloned()
+    }
+
+    /// Queues `job` as a new operation of `kind` with `total` items and
+    /// returns the operation
+    ///
+    /// The job reports its progress through the [`Progress`] it is given,
+    /// which also tells it when the operation is cancelled, and returns its
+    /// result or a description of why it failed.
+    pub fn submit<F, Fut>(
+        self: &Arc<Self>,
+        clock: Clock,
+        kind: OperationKind,
+        total: usize,
+        job: F,
+    ) -> Operation
+    where
+        F: FnOnce(Progress) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<OperationResult, String>> + Send + 'static,
+    {
+        let operation = Operation {
+            id: Uuid::new_v4(),
+            kind,
+            status: OperationStatus::Pending,
+            done: 0,
+            total,
+            created_at: clock.now(),
+            finished_at: None,
+            result: None,
+            error: None,
+        };
+        let id = operation.id;
+        let token = CancellationToken::new();
+        {
+            let mut operations = self.lock();
+            operations.by_id.insert(id, operation.clone());
+            operations.tokens.insert(id, token.clone());
+        }
+
+        let jobs = self.clone();
+        tokio::spawn(async move {
+            let permit = tokio::select! {
+                permit = jobs.workers.clone().acquire_owned() => permit,
+                // Cancelled while pending; `cancel` has finished it
+                _ = token.cancelled() => return,
+            };
+            if permit.is_err() || !jobs.start(id) {
+                return;
+            }
+            let progress = Progress {
+                jobs: jobs.clone(),
+                id,
+                token,
+            };
+            // A panicking job fails its operation instead of leaving it
+            // running forever
+            let outcome = tokio::spawn(job(progress))
+                .await
+                .unwrap_or_else(|err| Err(format!("the job panicked: {}", err)));
+            jobs.finish(id, outcome, clock.now());
+        });
+        operation
+    }
+
+    /// Requests the cancellation of the operation `id` and returns it, or
+    /// `None` if it is unknown or expired
+    ///
+    /// Finished operations are returned unchanged.
+    pub fn cancel(&self, id: Uuid, at: DateTime<Utc>) -> Option<Operation> {
+        let mut operations = self.lock();
+        let operation = operations.by_id.get_mut(&id)?;
+        match operation.status {
+            OperationStatus::Pending => {
+                operation.status = OperationStatus::Cancelled;
+                operation.finished_at = Some(at);
+            }
+            OperationStatus::Running => operation.status = OperationStatus::Cancelling,
+            _ => return Some(operation.clone()),
+        }
+        let operation = operation.clone();
+        if let Some(token) = operations.tokens.get(&id) {
+            token.cancel();
+        }
+        if operation.status.is_finished() {
+            operations.retire(id);
+        }
+        Some(operation)
+    }
+
+    /// Marks the operation `id` running, unless it was cancelled while
+    /// pending
+    fn start(&self, id: Uuid) -> bool {
+        match self.lock().by_id.get_mut(&id) {
+            Some(operation) if operation.status == OperationStatus::Pending => {
+                operation.status = OperationStatus::Running;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn update(&self, id: Uuid, f: impl FnOnce(&mut Operation)) {
+        if let Some(operation) = self.lock().by_id.get_mut(&id) {
+            f(operation);
+        }
+    }
+
+    fn finish(&self, id: Uuid, outcome: Result<OperationResult, String>, at: DateTime<Utc>) {
+        let mut operations = self.lock();
+        let Some(operation) = operations.by_id.get_mut(&id) else {
+            return;
+        };
+        operation.finished_at = Some(at);
+        let cancelled = operation.status == OperationStatus::Cancelling;
+        match outcome {
+            Ok(result) => {
+                operation.status = if cancelled {
+                    OperationStatus::Cancelled
+                } else {
+                    OperationStatus::Succeeded
+                };
+                operation.result = Some(result);
+            }
+            Err(error) => {
+                tracing::warn!(operation = %id, %error, "operation failed");
+                operation.status = OperationStatus::Failed;
+                operation.error = Some(error);
+            }
+        }
+        operations.retire(id);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Operations> {
+        self.operations
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    async fn finished(jobs: &Jobs, id: Uuid) -> Operation {
+        for _ in 0..100 {
+            match jobs.get(id) {
+                Some(operation) if operation.status.is_finished() => return operation,
+                _ => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+        panic!("operation {} did not finish", id);
+    }
+
+    fn export(count: usize) -> OperationResult {
+        OperationResult::Export {
+            users: Vec::new(),
+            count,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_jobs_report_progress_and_results() {
+        let jobs = Arc::new(Jobs::new(1));
+        let operation = jobs.submit(
+            Clock::System,
+            OperationKind::Export,
+            0,
+            |progress| async move {
+                progress.set_total(3);
+                progress.advance(3);
+                Ok(export(3))
+            },
+        );
+        assert_eq!(operation.status, OperationStatus::Pending);
+
+        let operation = finished(&jobs, operation.id).await;
+        assert_eq!(operation.status, OperationStatus::Succeeded);
+        assert_eq!((operation.done, operation.total), (3, 3));
+        assert!(operation.finished_at.is_some());
+        assert!(matches!(
+            operation.result,
+            Some(OperationResult::Export { count: 3, .. })
+        ));
+
+        let failing = jobs.submit(Clock::System, OperationKind::Import, 1, |_| async {
+            Err("no luck".to_string())
+        });
+        let failing = finished(&jobs, failing.id).await;
+        assert_eq!(failing.status, OperationStatus::Failed);
+        assert_eq!(failing.error.as_deref(), Some("no luck"));
+
+        let panicking = jobs.submit(Clock::System, OperationKind::Import, 1, |_| async {
+            panic!("boom")
+        });
+        assert_eq!(
+            finished(&jobs, panicking.id).await.status,
+            OperationStatus::Failed
+        );
+        assert!(jobs.get(Uuid::new_v4()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_jobs_wait_for_a_free_worker() {
+        let jobs = Arc::new(Jobs::new(1));
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let first = jobs.submit(Clock::System, OperationKind::Export, 0, |_| async move {
+            let _ = released.await;
+            Ok(export(0))
+        });
+        let second = jobs.submit(Clock::System, OperationKind::Export, 0, |_| async {
+            Ok(export(0))
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(jobs.get(first.id).unwrap().status, OperationStatus::Running);
+        assert_eq!(
+            jobs.get(second.id).unwrap().status,
+            OperationStatus::Pending
+        );
+
+        release.send(()).unwrap();
+        assert_eq!(
+            finished(&jobs, second.id).await.status,
+            OperationStatus::Succeeded
+        );
+    }
+
+    #[tokio::test]
+    async fn test_jobs_stop_when_cancelled() {
+        let jobs = Arc::new(Jobs::new(1));
+        let running = jobs.submit(
+            Clock::System,
+            OperationKind::Export,
+            0,
+            |progress| async move {
+                progress.cancelled().await;
+                Ok(export(1))
+            },
+        );
+        let pending = jobs.submit(Clock::System, OperationKind::Export, 0, |_| async {
+            Ok(export(0))
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let now = Utc::now();
+        let cancelled = jobs.cancel(pending.id, now).unwrap();
+        assert_eq!(cancelled.status, OperationStatus::Cancelled);
+        assert_eq!(cancelled.finished_at, Some(now));
+
+        let cancelling = jobs.cancel(running.id, now).unwrap();
+        assert_eq!(cancelling.status, OperationStatus::Cancelling);
+        let running = finished(&jobs, running.id).await;
+        assert_eq!(running.status, OperationStatus::Cancelled);
+        assert!(matches!(
+            running.result,
+            Some(OperationResult::Export { count: 1, .. })
+        ));
+
+        // The pending job never ran, and finished operations stay as they are
+        assert!(jobs.get(pending.id).unwrap().result.is_none());
+        assert_eq!(
+            jobs.cancel(running.id, Utc::now()).unwrap().finished_at,
+            running.finished_at
+        );
+        assert!(jobs.cancel(Uuid::new_v4(), now).is_none());
+    }
+}
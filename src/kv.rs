@@ -0,0 +1,293 @@
+//! Keeping users in a key-value store
+//!
+//! Edge platforms run the API without a disk or a database, next to a
+//! key-value store such as Workers KV, Fastly's KV Store or Spin's
+//! key-value API. Implementing [`KvStore`] for one of them and calling
+//! [`start`] makes it the durable copy of the in-memory store: the users it
+//! holds are loaded first, then everything stored under a changed ID is
+//! written back as one JSON [`UserRecord`] under `users/<id>`, so deleted
+//! and merged users keep their trash entries and tombstones.
+//!
+//! Writes are made in the background, in the order of the changes, and
+//! responses do not wait for them. Hosts that suspend an instance once it
+//! answered call [`Persistence::flush`] before handing the response back.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use futures_util::future::BoxFuture;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+use crate::models::UserRecord;
+use crate::timestamps::{TimestampFormat, TimestampOptions};
+use crate::AppState;
+
+/// Prefix of the keys users are stored under
+pub const USERS_PREFIX: &str = "users/";
+
+/// A key-value store holding the users
+pub trait KvStore: Send + Sync + 'static {
+    /// Identifies the store in logs
+    fn name(&self) -> &str;
+
+    /// Reads the value stored under `key`
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Option<Vec<u8>>>>;
+
+    /// Stores `value` under `key`, replacing any previous value
+    fn put<'a>(&'a self, key: &'a str, value: Vec<u8>) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Removes `key`; removing a missing key is not an error
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Lists all keys starting with `prefix`
+    fn keys<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, io::Result<Vec<String>>>;
+}
+
+/// A key-value store in memory, for tests and local runs
+#[derive(Debug, Clone, Default)]
+pub struct MemoryKv {
+    entries: Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+}
+
+impl MemoryKv {
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Vec<u8>>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl KvStore for MemoryKv {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Option<Vec<u8>>>> {
+        let value = self.lock().get(key).cloned();
+        Box::pin(std::future::ready(Ok(value)))
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: Vec<u8>) -> BoxFuture<'a, io::Result<()>> {
+        self.lock().insert(key.to_string(), value);
+        Box::pin(std::future::ready(Ok(())))
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        self.lock().remove(key);
+        Box::pin(std::future::ready(Ok(())))
+    }
+
+    fn keys<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, io::Result<Vec<String>>> {
+        let keys = self
+            .lock()
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+        Box::pin(std::future::ready(Ok(keys)))
+    }
+}
+
+/// Returns the key the record of `id` is stored under
+pub fn key(id: Uuid) -> String {
+    format!("{}{}", USERS_PREFIX, id)
+}
+
+#[derive(Debug)]
+enum Op {
+    Write(Uuid),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Handle on the writes to a key-value store started by [`start`]
+#[derive(Debug, Clone)]
+pub struct Persistence {
+    queue: mpsc::UnboundedSender<Op>,
+}
+
+impl Persistence {
+    /// Waits until every change made so far was written to the store
+    ///
+    /// Writes that failed were logged and are not retried.
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.queue.send(Op::Flush(done)).is_ok() {
+            let _ = flushed.await;
+        }
+    }
+}
+
+/// Loads the users held by `store` and keeps it up to date with every
+/// later change
+///
+/// Records loaded from the store replace whatever the in-memory store
+/// holds under the same IDs.
+///
+/// # Errors
+///
+/// Returns an error if the keys cannot be listed, or a record cannot be
+/// read or is not a [`UserRecord`].
+pub async fn start<K: KvStore>(state: &AppState, store: K) -> io::Result<Persistence> {
+    // Holding the lock, no change can be made between loading and
+    // subscribing
+    let mut storage = state.storage.write().await;
+    let keys = store.keys(USERS_PREFIX).await?;
+    for key in &keys {
+        let Some(value) = store.get(key).await? else {
+            continue;
+        };
+        let record: UserRecord = serde_json::from_slice(&value).map_err(|err| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", key, err))
+        })?;
+        storage.apply(record);
+    }
+    let (queue, mut ops) = mpsc::unbounded_channel();
+    let changes = queue.clone();
+    state.events.subscribe(move |event| {
+        let _ = changes.send(Op::Write(event.user_id()));
+    });
+    drop(storage);
+    tracing::info!(
+        store = store.name(),
+        users = keys.len(),
+        "loaded users from key-value store"
+    );
+
+    let state = state.clone();
+    tokio::spawn(async move {
+        while let Some(op) = ops.recv().await {
+            match op {
+                Op::Write(id) => {
+                    if let Err(err) = write(&state, &store, id).await {
+                        tracing::warn!(store = store.name(), %id, %err, "failed to store user");
+                    }
+                }
+                Op::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    });
+    Ok(Persistence { queue })
+}
+
+/// Writes everything currently stored under `id`, or removes its key if
+/// nothing is
+async fn write<K: KvStore>(state: &AppState, store: &K, id: Uuid) -> io::Result<()> {
+    let record = state.storage.read().await.record(&id);
+    let key = key(id);
+    if record.user.is_none() && record.trashed.is_none() && record.tombstone.is_none() {
+        return store.delete(&key).await;
+    }
+    // Timestamps keep their full precision
+    let options = TimestampOptions {
+        format: TimestampFormat::Rfc3339,
+        timezone: None,
+    };
+    let value = options.sync_scope(|| serde_json::to_vec(&record))?;
+    store.put(&key, value).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+    use crate::models::User;
+    use chrono::Utc;
+
+    fn record(user: &User) -> Vec<u8> {
+        let record = UserRecord {
+            id: user.id,
+            user: Some(user.clone()),
+            trashed: None,
+            tombstone: None,
+        };
+        let options = TimestampOptions {
+            format: TimestampFormat::Rfc3339,
+            timezone: None,
+        };
+        options.sync_scope(|| serde_json::to_vec(&record)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_start_loads_stored_users() {
+        let store = MemoryKv::default();
+        let stored = User::new("Stored", "stored@example.com", Utc::now());
+        store.put(&key(stored.id), record(&stored)).await.unwrap();
+        store
+            .put("sessions/other", b"ignored".to_vec())
+            .await
+            .unwrap();
+
+        let state = AppState::new();
+        start(&state, store).await.unwrap();
+        assert_eq!(state.storage.read().await.get(&stored.id), Some(stored));
+    }
+
+    #[tokio::test]
+    async fn test_start_rejects_foreign_records() {
+        let store = MemoryKv::default();
+        store.put("users/broken", b"{}".to_vec()).await.unwrap();
+
+        let err = start(&AppState::new(), store).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("users/broken: "));
+    }
+
+    #[tokio::test]
+    async fn test_changes_are_written_back() {
+        let store = MemoryKv::default();
+        let state = AppState::new();
+        let persistence = start(&state, store.clone()).await.unwrap();
+
+        let created = User::new("Created", "created@example.com", Utc::now());
+        state.storage.write().await.create(created.clone()).unwrap();
+        state.events.publish(Event::UserCreated(created.clone()));
+        persistence.flush().await;
+        let value = store.get(&key(created.id)).await.unwrap().unwrap();
+        let stored: UserRecord = serde_json::from_slice(&value).unwrap();
+        assert_eq!(stored.user, Some(created.clone()));
+
+        // Deleted users stay stored, in the trash
+        state
+            .storage
+            .write()
+            .await
+            .remove(&created.id, chrono::Utc::now(), None)
+            .unwrap();
+        state.events.publish(Event::UserDeleted(created.id));
+        persistence.flush().await;
+        let value = store.get(&key(created.id)).await.unwrap().unwrap();
+        let stored: UserRecord = serde_json::from_slice(&value).unwrap();
+        assert_eq!(stored.user, None);
+        let trashed = stored.trashed.unwrap();
+        assert_eq!(User::clone(&trashed.user), created);
+    }
+
+    #[tokio::test]
+    async fn test_forgotten_users_are_removed() {
+        let store = MemoryKv::default();
+        let forgotten = User::new("Forgotten", "forgotten@example.com", Utc::now());
+        store
+            .put(&key(forgotten.id), record(&forgotten))
+            .await
+            .unwrap();
+        let state = AppState::new();
+        let persistence = start(&state, store.clone()).await.unwrap();
+
+        state.storage.write().await.apply(UserRecord {
+            id: forgotten.id,
+            user: None,
+            trashed: None,
+            tombstone: None,
+        });
+        state.events.publish(Event::UserDeleted(forgotten.id));
+        persistence.flush().await;
+        assert_eq!(
+            store.keys(USERS_PREFIX).await.unwrap(),
+            Vec::<String>::new()
+        );
+    }
+}
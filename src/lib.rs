@@ -3,25 +3,243 @@
 //! This library module exposes the core components of the API
 //! for use in tests and as a library.
 
+pub mod audit;
+pub mod auth;
+pub mod blob;
+pub mod cache;
+pub mod capture;
+pub mod chaos;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod client_ip;
+pub mod computed;
+pub mod config;
+pub mod consistency;
+pub mod contract;
+pub mod cors;
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
+pub mod deadline;
+pub mod deprecation;
+#[cfg(feature = "server")]
+pub mod doctor;
+pub mod dry_run;
+pub mod duplicates;
 pub mod error;
+pub mod etag;
+pub mod events;
+pub mod extract;
+pub mod field_case;
+pub mod filter;
+mod fs;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+pub mod geoip;
 pub mod handlers;
+pub mod health;
+pub mod i18n;
+pub mod ip_filter;
+pub mod jobs;
+pub mod kv;
+pub mod lifecycle;
+pub mod load_shed;
+pub mod logging;
+pub mod metrics;
+pub mod migrate;
+pub mod mock;
 pub mod models;
+pub mod openapi;
+pub mod paging;
+pub mod paths;
+pub mod plugins;
+pub mod profile;
+pub mod proxy;
+pub mod rate_limit;
+#[cfg(feature = "server")]
+pub mod reload;
+pub mod replication;
+pub mod reports;
+pub mod repository;
+pub mod reserved;
+pub mod resilience;
+pub mod responses;
+pub mod routes;
+pub mod schema;
+#[cfg(feature = "server")]
+pub mod self_test;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod service;
+pub mod shadow;
+pub mod shard;
+pub mod slo;
+pub mod snapshot;
+pub mod streaming;
+#[cfg(feature = "server")]
+pub mod systemd;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod timestamps;
+pub mod timing;
+pub mod tls;
+pub mod undo;
+pub mod usage;
+pub mod validation;
 
+pub use crate::config::Config;
 pub use crate::models::Storage;
+#[cfg(feature = "server")]
+pub use crate::server::Server;
+
+use axum::Router;
 
 /// Application state shared across all handlers
 #[derive(Clone)]
 pub struct AppState {
     /// In-memory storage for demonstration purposes
     /// In production, this would be a database connection pool
-    pub storage: std::sync::Arc<tokio::sync::RwLock<models::Storage>>,
+    pub storage: std::sync::Arc<timing::TimedLock<models::Storage>>,
+    /// Runtime configuration
+    pub config: std::sync::Arc<Config>,
+    /// Source of the current time
+    pub clock: mock::Clock,
+    /// Source of new user IDs
+    pub ids: std::sync::Arc<mock::IdSource>,
+    /// Runtime metrics
+    pub metrics: std::sync::Arc<metrics::Metrics>,
+    /// Active log filter
+    pub log_filter: std::sync::Arc<logging::LogFilter>,
+    /// Publisher of changes to stored data
+    pub events: std::sync::Arc<events::EventBus>,
+    /// Cached GET responses, invalidated through `events`
+    pub cache: std::sync::Arc<cache::ResponseCache>,
+    /// Nonces of recently verified signed requests
+    pub nonces: std::sync::Arc<auth::signing::NonceCache>,
+    /// Issued impersonation tokens
+    pub impersonations: std::sync::Arc<auth::impersonation::Sessions>,
+    /// Recorded state-changing requests
+    pub audit: std::sync::Arc<audit::AuditLog>,
+    /// Locations of client addresses
+    pub geoip: std::sync::Arc<geoip::GeoIp>,
+    /// Issued tokens undoing deletions
+    pub undo: std::sync::Arc<undo::UndoTokens>,
+    /// Registered extension hooks
+    pub plugins: std::sync::Arc<plugins::Plugins>,
+    /// Checks reported by `GET /readyz`
+    pub health: std::sync::Arc<health::HealthRegistry>,
+    /// Latency and error rates per route, reported by `GET /api/v1/admin/slo`
+    pub slo: std::sync::Arc<slo::SloTracker>,
+    /// Recorder of requests, toggled by `PUT /api/v1/admin/capture`
+    pub capture: std::sync::Arc<capture::Capture>,
+    /// Shadow traffic to a storage backend being migrated to
+    pub shadow: std::sync::Arc<shadow::Shadow>,
+    /// Issued tokens confirming snapshot restores
+    pub snapshots: std::sync::Arc<snapshot::Confirmations>,
+    /// Owners of the users when they are partitioned across instances
+    pub shards: std::sync::Arc<shard::Shards>,
+    /// Change log of a leader, or replication progress of a follower
+    pub replication: std::sync::Arc<replication::Replication>,
+    /// Background operations and the queue running them
+    pub jobs: std::sync::Arc<jobs::Jobs>,
+    /// Deprecated routes, announced in their responses
+    pub deprecations: std::sync::Arc<deprecation::Deprecations>,
+    /// Routes of the last router built, listed by `GET /api/v1/_routes`
+    pub routes: std::sync::Arc<routes::RouteTable>,
+    /// Reserved names and emails and blocked terms, replaced by
+    /// `PUT /api/v1/admin/reserved`
+    pub reserved: std::sync::Arc<reserved::Reserved>,
+    /// Requests counted against each caller's rate limit
+    pub rate_limiter: std::sync::Arc<rate_limit::RateLimiter>,
+    /// Recent requests per API key, reported by
+    /// `GET /api/v1/api-keys/:id/usage`
+    pub usage: std::sync::Arc<usage::UsageTracker>,
+    /// Circuit breakers and retries of outbound calls, by dependency
+    pub outbound: std::sync::Arc<resilience::Outbound>,
+    /// Generated files such as reports
+    pub blobs: std::sync::Arc<dyn blob::BlobStore>,
+    /// Resolver checking that email domains accept mail
+    #[cfg(feature = "mx-lookup")]
+    pub mx_lookup: std::sync::Arc<dyn validation::email::MxLookup>,
 }
 
 impl AppState {
     /// Creates a new application state with empty storage
     pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    /// Creates a new application state with empty storage and the given configuration
+    pub fn with_config(config: Config) -> Self {
+        let events = std::sync::Arc::new(events::EventBus::default());
+        let cache = std::sync::Arc::new(cache::ResponseCache::default());
+        events.subscribe({
+            let cache = cache.clone();
+            move |event| cache.invalidate(event)
+        });
+        let metrics = std::sync::Arc::new(metrics::Metrics::default());
+        let health = std::sync::Arc::new(health::HealthRegistry::default());
+        health.register("storage", |state: AppState| async move {
+            health::storage(&state.storage.read().await.usage())
+        });
+
+        let replication = std::sync::Arc::new(replication::Replication::new(&config.replication));
+        match replication.role() {
+            Some(replication::Role::Leader) => events.subscribe({
+                let replication = replication.clone();
+                move |event| replication.append(event.user_id())
+            }),
+            Some(replication::Role::Follower | replication::Role::Replica) => {
+                health.register("replication", |state: AppState| async move {
+                    if state.replication.is_synced() {
+                        health::CheckResult::healthy()
+                    } else {
+                        health::CheckResult::unhealthy("the leader's store has not been copied yet")
+                    }
+                })
+            }
+            None => {}
+        }
+
         Self {
-            storage: std::sync::Arc::new(tokio::sync::RwLock::new(models::Storage::default())),
+            storage: std::sync::Arc::new(timing::TimedLock::new(
+                "storage",
+                models::Storage::with_capacity(config.storage_capacity)
+                    .with_email_canonicalization(config.email_canonicalization),
+            )),
+            audit: std::sync::Arc::new(audit::AuditLog::new(config.audit_capacity)),
+            slo: std::sync::Arc::new(slo::SloTracker::new(config.slo)),
+            capture: std::sync::Arc::new(capture::Capture::new(config.capture_file.clone())),
+            shards: std::sync::Arc::new(shard::Shards::new(&config.shard)),
+            jobs: std::sync::Arc::new(jobs::Jobs::new(config.job_workers)),
+            reserved: std::sync::Arc::new(reserved::Reserved::new(config.reserved.clone())),
+            outbound: std::sync::Arc::new(resilience::Outbound::new(
+                config.resilience,
+                metrics.clone(),
+                health.clone(),
+            )),
+            replication,
+            config: std::sync::Arc::new(config),
+            clock: mock::Clock::System,
+            ids: std::sync::Arc::new(mock::IdSource::Random),
+            metrics,
+            log_filter: std::sync::Arc::default(),
+            events,
+            cache,
+            nonces: std::sync::Arc::default(),
+            impersonations: std::sync::Arc::default(),
+            geoip: std::sync::Arc::default(),
+            undo: std::sync::Arc::default(),
+            plugins: std::sync::Arc::default(),
+            health,
+            shadow: std::sync::Arc::default(),
+            snapshots: std::sync::Arc::default(),
+            deprecations: std::sync::Arc::default(),
+            routes: std::sync::Arc::default(),
+            rate_limiter: std::sync::Arc::default(),
+            usage: std::sync::Arc::default(),
+            blobs: std::sync::Arc::new(blob::MemoryBlobs::default()),
+            #[cfg(feature = "mx-lookup")]
+            mx_lookup: std::sync::Arc::new(validation::email::SystemResolver),
         }
     }
 }
@@ -31,3 +249,12 @@ impl Default for AppState {
         Self::new()
     }
 }
+
+/// Builds the application router with all routes and middleware
+///
+/// Path normalization is not included: it has to run before routing, so
+/// callers wrap the returned router with [`paths::normalize_trailing_slash`].
+/// Use [`routes::RouterBuilder`] to change the route groups.
+pub fn router(state: AppState) -> Router {
+    routes::RouterBuilder::with_defaults(state).build()
+}
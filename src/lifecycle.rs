@@ -0,0 +1,212 @@
+//! Startup and shutdown tasks
+//!
+//! Work that has to happen before the server accepts requests (migrations,
+//! index warm-up, cache priming) is registered as a startup task, and work
+//! that has to happen after the last request was answered (flushing logs,
+//! draining queues) as a shutdown task. Tasks run one at a time, in
+//! registration order, each limited by its own timeout.
+//!
+//! A failing or timed-out startup task aborts startup. Shutdown tasks are
+//! all attempted, since the process is exiting anyway; failures are logged.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use futures_util::future::BoxFuture;
+
+use crate::AppState;
+
+/// Outcome of a task
+pub type TaskResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+type Run = Box<dyn FnOnce(AppState) -> BoxFuture<'static, TaskResult> + Send>;
+
+struct Task {
+    name: String,
+    timeout: Duration,
+    run: Run,
+}
+
+impl Task {
+    fn new<F, Fut>(name: impl Into<String>, timeout: Duration, run: F) -> Self
+    where
+        F: FnOnce(AppState) -> Fut + Send + 'static,
+        Fut: Future<Output = TaskResult> + Send + 'static,
+    {
+        Self {
+            name: name.into(),
+            timeout,
+            run: Box::new(move |state| Box::pin(run(state))),
+        }
+    }
+
+    /// Runs the task, logging how it went
+    async fn run(self, phase: &str, state: &AppState) -> Result<(), LifecycleError> {
+        let started = Instant::now();
+        let error = match tokio::time::timeout(self.timeout, (self.run)(state.clone())).await {
+            Ok(Ok(())) => {
+                tracing::info!(
+                    phase,
+                    task = %self.name,
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    "lifecycle task finished"
+                );
+                return Ok(());
+            }
+            Ok(Err(err)) => err.to_string(),
+            Err(_) => format!("timed out after {:?}", self.timeout),
+        };
+        tracing::error!(phase, task = %self.name, %error, "lifecycle task failed");
+        Err(LifecycleError {
+            task: self.name,
+            error,
+        })
+    }
+}
+
+/// A startup task that failed or timed out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LifecycleError {
+    /// Name of the task
+    pub task: String,
+    /// What went wrong
+    pub error: String,
+}
+
+impl std::fmt::Display for LifecycleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "startup task '{}' failed: {}", self.task, self.error)
+    }
+}
+
+impl std::error::Error for LifecycleError {}
+
+/// The registered startup and shutdown tasks
+#[derive(Default)]
+pub struct Lifecycle {
+    startup: Vec<Task>,
+    shutdown: Vec<Task>,
+}
+
+impl std::fmt::Debug for Lifecycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = |tasks: &[Task]| {
+            tasks
+                .iter()
+                .map(|task| task.name.clone())
+                .collect::<Vec<_>>()
+        };
+        f.debug_struct("Lifecycle")
+            .field("startup", &names(&self.startup))
+            .field("shutdown", &names(&self.shutdown))
+            .finish()
+    }
+}
+
+impl Lifecycle {
+    /// Registers a task to run before the server accepts requests
+    pub fn on_startup<F, Fut>(mut self, name: impl Into<String>, timeout: Duration, task: F) -> Self
+    where
+        F: FnOnce(AppState) -> Fut + Send + 'static,
+        Fut: Future<Output = TaskResult> + Send + 'static,
+    {
+        self.startup.push(Task::new(name, timeout, task));
+        self
+    }
+
+    /// Registers a task to run once the server has stopped
+    pub fn on_shutdown<F, Fut>(
+        mut self,
+        name: impl Into<String>,
+        timeout: Duration,
+        task: F,
+    ) -> Self
+    where
+        F: FnOnce(AppState) -> Fut + Send + 'static,
+        Fut: Future<Output = TaskResult> + Send + 'static,
+    {
+        self.shutdown.push(Task::new(name, timeout, task));
+        self
+    }
+
+    /// Runs the startup tasks, stopping at the first that fails
+    pub async fn start(&mut self, state: &AppState) -> Result<(), LifecycleError> {
+        for task in std::mem::take(&mut self.startup) {
+            task.run("startup", state).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs every shutdown task, whether or not earlier ones failed
+    pub async fn stop(self, state: &AppState) {
+        for task in self.shutdown {
+            // Already logged; the remaining tasks still deserve a chance
+            let _ = task.run("shutdown", state).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    type Log = Arc<Mutex<Vec<&'static str>>>;
+
+    /// A task recording its name in `log`
+    fn record(
+        log: &Log,
+        name: &'static str,
+    ) -> impl FnOnce(AppState) -> std::future::Ready<TaskResult> + Send + 'static {
+        let log = log.clone();
+        move |_| {
+            log.lock().unwrap().push(name);
+            std::future::ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tasks_run_in_order() {
+        let log = Log::default();
+        let timeout = Duration::from_secs(1);
+        let mut lifecycle = Lifecycle::default()
+            .on_startup("migrate", timeout, record(&log, "migrate"))
+            .on_shutdown("flush", timeout, record(&log, "flush"))
+            .on_startup("warm-up", timeout, record(&log, "warm-up"));
+
+        let state = AppState::new();
+        lifecycle.start(&state).await.unwrap();
+        assert_eq!(*log.lock().unwrap(), vec!["migrate", "warm-up"]);
+        lifecycle.stop(&state).await;
+        assert_eq!(*log.lock().unwrap(), vec!["migrate", "warm-up", "flush"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_startup_aborts() {
+        let log = Log::default();
+        let mut lifecycle = Lifecycle::default()
+            .on_startup("prime", Duration::from_secs(1), |_| async {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                Ok(())
+            })
+            .on_startup("index", Duration::from_secs(1), record(&log, "index"));
+
+        let err = lifecycle.start(&AppState::new()).await.unwrap_err();
+        assert_eq!(err.task, "prime");
+        assert!(err.error.contains("timed out"));
+        assert!(log.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_continues_after_failure() {
+        let log = Log::default();
+        let lifecycle = Lifecycle::default()
+            .on_shutdown("flush", Duration::from_secs(1), |_| async {
+                Err("disk full".into())
+            })
+            .on_shutdown("drain", Duration::from_secs(1), record(&log, "drain"));
+
+        lifecycle.stop(&AppState::new()).await;
+        assert_eq!(*log.lock().unwrap(), vec!["drain"]);
+    }
+}
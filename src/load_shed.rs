@@ -0,0 +1,57 @@
+//! Concurrency limits and load shedding
+//!
+//! With `APP_MAX_CONCURRENT_REQUESTS` set, at most that many requests are
+//! handled at once. Instead of queueing behind the limit, excess requests
+//! are rejected immediately with `503 Service Unavailable` and a
+//! `Retry-After` header, so the service degrades predictably under load.
+
+use std::time::Duration;
+
+use axum::{
+    error_handling::HandleErrorLayer,
+    http::{header::RETRY_AFTER, HeaderValue},
+    response::{IntoResponse, Response},
+    BoxError, Router,
+};
+use tower::{limit::GlobalConcurrencyLimitLayer, load_shed::LoadShedLayer, ServiceBuilder};
+
+use crate::error::ApiError;
+use crate::i18n::Message;
+use crate::AppState;
+
+/// Applies the configured concurrency limit to every route of `router`
+///
+/// The limit is shared by all routes. Returns `router` unchanged when no
+/// limit is configured.
+pub fn apply(router: Router<AppState>, state: &AppState) -> Router<AppState> {
+    let Some(limit) = state.config.max_concurrent_requests else {
+        return router;
+    };
+
+    let retry_after = state.config.retry_after;
+    let metrics = state.metrics.clone();
+    let handle_error = move |err: BoxError| {
+        let metrics = metrics.clone();
+        async move {
+            if err.is::<tower::load_shed::error::Overloaded>() {
+                metrics.record_shed();
+            }
+            overloaded(retry_after)
+        }
+    };
+
+    router.layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(handle_error))
+            .layer(LoadShedLayer::new())
+            .layer(GlobalConcurrencyLimitLayer::new(limit)),
+    )
+}
+
+/// The response for a request rejected by load shedding
+fn overloaded(retry_after: Duration) -> Response {
+    let seconds = retry_after.as_secs().max(1);
+    let error =
+        ApiError::ServiceUnavailable(Message::new("server.overloaded").with("seconds", seconds));
+    ([(RETRY_AFTER, HeaderValue::from(seconds))], error).into_response()
+}
@@ -0,0 +1,119 @@
+//! Logging setup with a filter that can be changed at runtime
+//!
+//! The tracing subscriber is installed with a reloadable [`EnvFilter`], so
+//! operators can raise the level for a single module during an incident
+//! through `PUT /api/v1/admin/log-level` without restarting the server.
+
+use std::sync::Mutex;
+
+use tracing_subscriber::{prelude::*, reload, EnvFilter};
+
+/// Filter used when `RUST_LOG` is not set, in the `dev` profile
+pub const DEFAULT_FILTER: &str = "rust_api=debug,tower_http=debug";
+
+type Reload = Box<dyn Fn(EnvFilter) -> Result<(), String> + Send + Sync>;
+
+/// Handle to the active log filter
+pub struct LogFilter {
+    directives: Mutex<String>,
+    reload: Option<Reload>,
+}
+
+impl std::fmt::Debug for LogFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogFilter")
+            .field("directives", &self.directives())
+            .field("installed", &self.reload.is_some())
+            .finish()
+    }
+}
+
+impl Default for LogFilter {
+    /// A filter that is not attached to a subscriber; changes are only
+    /// recorded
+    fn default() -> Self {
+        Self {
+            directives: Mutex::new(DEFAULT_FILTER.to_string()),
+            reload: None,
+        }
+    }
+}
+
+impl LogFilter {
+    /// Returns the active filter directives
+    pub fn directives(&self) -> String {
+        self.directives
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Replaces the active filter
+    ///
+    /// # Arguments
+    ///
+    /// * `directives` - Filter in `RUST_LOG` syntax, e.g. `rust_api::handlers=trace,info`
+    ///
+    /// # Returns
+    ///
+    /// Returns an error describing the problem if the directives do not
+    /// parse or the subscriber is gone
+    pub fn set(&self, directives: &str) -> Result<(), String> {
+        let directives = directives.trim();
+        let filter = EnvFilter::builder()
+            .parse(directives)
+            .map_err(|err| err.to_string())?;
+        if let Some(reload) = &self.reload {
+            reload(filter)?;
+        }
+        *self
+            .directives
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = directives.to_string();
+        Ok(())
+    }
+}
+
+/// Installs the global tracing subscriber
+///
+/// The initial filter comes from `RUST_LOG`, falling back to `default`,
+/// usually the profile's [`Profile::log_filter`](crate::profile::Profile::log_filter).
+pub fn init(default: &str) -> LogFilter {
+    let directives = std::env::var(EnvFilter::DEFAULT_ENV)
+        .ok()
+        .filter(|value| EnvFilter::builder().parse(value).is_ok())
+        .unwrap_or_else(|| default.to_string());
+    let filter = EnvFilter::builder()
+        .parse(&directives)
+        .unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let (filter, handle) = reload::Layer::new(filter);
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    LogFilter {
+        directives: Mutex::new(directives),
+        reload: Some(Box::new(move |filter| {
+            handle.reload(filter).map_err(|err| err.to_string())
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_validates_directives() {
+        let filter = LogFilter::default();
+        assert_eq!(filter.directives(), DEFAULT_FILTER);
+
+        filter.set(" rust_api::handlers=trace,info ").unwrap();
+        assert_eq!(filter.directives(), "rust_api::handlers=trace,info");
+
+        assert!(filter.set("rust_api=loud").is_err());
+        assert_eq!(filter.directives(), "rust_api::handlers=trace,info");
+    }
+}
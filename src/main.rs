@@ -4,44 +4,159 @@
 //! This API demonstrates best practices for error handling, documentation,
 //! and maintainable code structure.
 
-use axum::{
-    routing::{delete, get, post, put},
-    Router,
-};
-use std::net::SocketAddr;
-use tower_http::cors::CorsLayer;
-
-use rust_api::{handlers, AppState};
+use rust_api::profile::Profile;
+use rust_api::schema::{self, SchemaFormat};
+use rust_api::{doctor, logging, migrate, self_test, server, Config, Server};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize tracing for structured logging
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "rust_api=debug,tower_http=debug".into()),
-        )
-        .init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mock = args.iter().any(|arg| arg == "--mock");
+    if args.iter().any(|arg| arg == "--self-test") {
+        return run_self_test().await;
+    }
+    let mut commands = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| !arg.starts_with("--"));
 
-    let app_state = AppState::new();
+    // `rust-api schema [json-schema|typescript]` prints payload definitions;
+    // `rust-api replay <file> <base-url>` re-sends captured requests;
+    // `rust-api migrate-data --from <source> --to <destination>` copies the
+    // stored data between backends; `rust-api doctor [--json]` checks the
+    // deployment
+    match commands.next() {
+        None | Some("serve") => {}
+        Some("schema") => {
+            let format: SchemaFormat = commands.next().unwrap_or("json-schema").parse()?;
+            println!("{}", schema::render(format));
+            return Ok(());
+        }
+        Some("replay") => {
+            let (Some(file), Some(base_url)) = (commands.next(), commands.next()) else {
+                return Err("usage: rust-api replay <file> <base-url>".into());
+            };
+            return replay(file, base_url).await;
+        }
+        Some("migrate-data") => {
+            let (Some(from), Some(to)) = (flag(&args, "--from"), flag(&args, "--to")) else {
+                return Err(
+                    "usage: rust-api migrate-data --from <source> --to <destination>".into(),
+                );
+            };
+            return migrate_data(from, to).await;
+        }
+        Some("doctor") => {
+            let json = args.iter().any(|arg| arg == "--json");
+            return run_doctor(json).await;
+        }
+        Some(other) => return Err(format!("unknown command '{}'", other).into()),
+    }
 
-    // Build the application router
-    let app = Router::new()
-        .route("/", get(handlers::health_check))
-        .route("/api/v1/users", get(handlers::list_users))
-        .route("/api/v1/users", post(handlers::create_user))
-        .route("/api/v1/users/:id", get(handlers::get_user))
-        .route("/api/v1/users/:id", put(handlers::update_user))
-        .route("/api/v1/users/:id", delete(handlers::delete_user))
-        .layer(CorsLayer::permissive())
-        .with_state(app_state);
+    // Initialize tracing for structured logging; the filter stays adjustable
+    let log_filter = logging::init(Profile::from_env().log_filter());
 
-    // Bind to address and start server
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
-    tracing::info!("Server listening on {}", addr);
-
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    let config = Config::from_env()?;
+    Server::from_config(config)
+        .mock(mock)
+        .log_filter(log_filter)
+        .run()
+        .await?;
+    Ok(())
+}
 
+/// Runs the scripted flow of [`self_test`] against a fresh application,
+/// failing if any step does
+async fn run_self_test() -> Result<(), Box<dyn std::error::Error>> {
+    let steps = self_test::run().await.map_err(|err| err.to_string())?;
+    for step in &steps {
+        println!("ok {}", step);
+    }
+    println!("self-test passed: {} steps", steps.len());
     Ok(())
 }
+
+/// Returns the value following `name` in `args`
+fn flag<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|index| args.get(index + 1))
+        .map(String::as_str)
+}
+
+/// Copies the stored data from one backend to another, reporting progress
+/// on stderr
+async fn migrate_data(from: &str, to: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let from: migrate::Location = from.parse()?;
+    let to: migrate::Location = to.parse()?;
+    let config = Config::from_env()?;
+    let result = migrate::migrate_locations(&from, &to, &config.name_rules, |progress| {
+        eprintln!("{}", progress);
+    })
+    .await;
+    match result {
+        Ok(report) => {
+            println!(
+                "migrated {} users, {} trashed users and {} tombstones from {} to {}",
+                report.users, report.trashed, report.tombstones, from, to
+            );
+            Ok(())
+        }
+        Err(migrate::MigrationError::Invalid(problems)) => {
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            Err(format!(
+                "{} users failed validation, nothing was written",
+                problems.len()
+            )
+            .into())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Checks the deployment, failing if any check does
+async fn run_doctor(json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let report = doctor::run(|key| std::env::var(key).ok(), server::DEFAULT_ADDR).await;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", report);
+    }
+    if report.passed() {
+        Ok(())
+    } else {
+        Err("doctor found failing checks".into())
+    }
+}
+
+/// Replays a capture file, failing if any response status differs
+#[cfg(feature = "client")]
+async fn replay(file: &str, base_url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let token = std::env::var("APP_REPLAY_TOKEN").ok();
+    let report =
+        rust_api::capture::replay(std::path::Path::new(file), base_url, token.as_deref()).await?;
+    for mismatch in &report.mismatches {
+        println!("{}", mismatch);
+    }
+    println!(
+        "{} requests replayed, {} mismatched",
+        report.replayed,
+        report.mismatches.len()
+    );
+    if report.mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} responses differ from the capture",
+            report.mismatches.len()
+        )
+        .into())
+    }
+}
+
+#[cfg(not(feature = "client"))]
+async fn replay(_file: &str, _base_url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    Err("replay requires the server to be built with the `client` feature".into())
+}
@@ -0,0 +1,474 @@
+//! Runtime metrics
+//!
+//! Counters and gauges are plain atomics updated by middleware and exported
+//! in the Prometheus text format at `GET /metrics`. Deployments without
+//! Prometheus scraping can also push them to [`sinks`].
+
+pub mod sinks;
+
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    extract::{Request, State},
+    http::header::CONTENT_TYPE,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::models::StorageUsage;
+use crate::resilience::CircuitBreaker;
+use crate::AppState;
+
+/// Media type of the Prometheus text exposition format
+pub const CONTENT_TYPE_TEXT: &str = "text/plain; version=0.0.4";
+
+/// Process-wide metrics
+#[derive(Debug, Default)]
+pub struct Metrics {
+    in_flight: AtomicI64,
+    requests_shed: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    ip_denied: AtomicU64,
+    breakers: Mutex<Vec<Arc<CircuitBreaker>>>,
+}
+
+impl Metrics {
+    /// Returns the number of requests currently being handled
+    pub fn in_flight(&self) -> i64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of requests rejected because the server was
+    /// saturated
+    pub fn requests_shed(&self) -> u64 {
+        self.requests_shed.load(Ordering::Relaxed)
+    }
+
+    /// Counts a request rejected by load shedding
+    pub fn record_shed(&self) {
+        self.requests_shed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of GET requests answered from the response cache
+    pub fn cache_hits(&self) -> u64 {
+        self.cache_hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of cacheable GET requests the cache could not answer
+    pub fn cache_misses(&self) -> u64 {
+        self.cache_misses.load(Ordering::Relaxed)
+    }
+
+    /// Counts a request answered from the response cache
+    pub fn record_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a cacheable request the response cache could not answer
+    pub fn record_cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of requests rejected by the IP allow and deny lists
+    pub fn ip_denied(&self) -> u64 {
+        self.ip_denied.load(Ordering::Relaxed)
+    }
+
+    /// Counts a request rejected by the IP allow and deny lists
+    pub fn record_ip_denied(&self) {
+        self.ip_denied.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Reports the state of `breaker` from now on
+    pub fn register_breaker(&self, breaker: Arc<CircuitBreaker>) {
+        self.breakers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(breaker);
+    }
+
+    /// Returns the current value of every metric
+    pub fn samples(&self) -> Vec<Sample> {
+        let mut samples = vec![
+            Sample::gauge(
+                "http_requests_in_flight",
+                "Requests currently being handled",
+                self.in_flight() as f64,
+            ),
+            Sample::counter(
+                "http_requests_shed_total",
+                "Requests rejected with 503 because the concurrency limit was reached",
+                self.requests_shed(),
+            ),
+            Sample::counter(
+                "http_cache_hits_total",
+                "GET requests answered from the response cache",
+                self.cache_hits(),
+            ),
+            Sample::counter(
+                "http_cache_misses_total",
+                "Cacheable GET requests the response cache could not answer",
+                self.cache_misses(),
+            ),
+            Sample::counter(
+                "http_requests_denied_total",
+                "Requests rejected with 403 by the IP allow and deny lists",
+                self.ip_denied(),
+            ),
+        ];
+
+        let breakers = self
+            .breakers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        for breaker in breakers.iter() {
+            samples.push(
+                Sample::gauge(
+                    "circuit_breaker_state",
+                    "Circuit breaker state (0 closed, 1 open, 2 half-open)",
+                    breaker.state().as_gauge() as f64,
+                )
+                .label("name", breaker.name()),
+            );
+        }
+        for breaker in breakers.iter() {
+            samples.push(
+                Sample::counter(
+                    "circuit_breaker_rejected_total",
+                    "Calls rejected by an open circuit breaker",
+                    breaker.rejected(),
+                )
+                .label("name", breaker.name()),
+            );
+        }
+        samples
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        render(&self.samples())
+    }
+}
+
+/// How a metric's values are to be interpreted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A value that goes up and down
+    Gauge,
+    /// A running total
+    Counter,
+    /// One of the running totals of a [`Histogram`]
+    Histogram,
+}
+
+/// The value of a metric at one point in time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// Metric name, in Prometheus style
+    pub name: &'static str,
+    /// Appended to the name for the series of a histogram (`_bucket`,
+    /// `_sum`, `_count`); empty otherwise
+    pub suffix: &'static str,
+    /// What the metric measures
+    pub help: &'static str,
+    /// Whether the metric is a gauge or a counter
+    pub kind: Kind,
+    /// Label names and values distinguishing series of the same metric
+    pub labels: Vec<(&'static str, String)>,
+    /// The current value
+    pub value: f64,
+}
+
+impl Sample {
+    /// A gauge without labels
+    pub fn gauge(name: &'static str, help: &'static str, value: f64) -> Self {
+        Self {
+            name,
+            suffix: "",
+            help,
+            kind: Kind::Gauge,
+            labels: Vec::new(),
+            value,
+        }
+    }
+
+    /// A counter without labels
+    pub fn counter(name: &'static str, help: &'static str, value: u64) -> Self {
+        Self {
+            kind: Kind::Counter,
+            ..Self::gauge(name, help, value as f64)
+        }
+    }
+
+    /// Adds a label
+    pub fn label(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.labels.push((name, value.into()));
+        self
+    }
+}
+
+/// Distribution of durations over fixed buckets
+///
+/// Exported like a Prometheus histogram: a cumulative count per upper
+/// bound, plus the sum and count of all observations.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    /// Creates a histogram with `bounds`, ascending upper bounds in seconds
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    /// Records one observation
+    pub fn observe(&self, duration: std::time::Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(index) = self.bounds.iter().position(|bound| seconds <= *bound) {
+            self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    /// Returns the number of observations
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Returns the histogram's series, each carrying `labels`
+    pub fn samples(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&'static str, String)],
+    ) -> Vec<Sample> {
+        let series = |suffix, value| Sample {
+            name,
+            suffix,
+            help,
+            kind: Kind::Histogram,
+            labels: labels.to_vec(),
+            value,
+        };
+        let mut samples = Vec::new();
+        let mut cumulative = 0;
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            samples.push(series("_bucket", cumulative as f64).label("le", bound.to_string()));
+        }
+        let count = self.count() as f64;
+        samples.push(series("_bucket", count).label("le", "+Inf"));
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        samples.push(series("_sum", sum));
+        samples.push(series("_count", count));
+        samples
+    }
+}
+
+/// Returns the user store's memory footprint
+pub fn storage_samples(usage: &StorageUsage) -> Vec<Sample> {
+    let mut samples = vec![Sample::gauge(
+        "storage_users",
+        "Users held in the in-memory store",
+        usage.users as f64,
+    )];
+    if let Some(max_users) = usage.max_users {
+        samples.push(Sample::gauge(
+            "storage_max_users",
+            "Maximum number of users the store accepts",
+            max_users as f64,
+        ));
+    }
+    samples.push(Sample::gauge(
+        "storage_serialized_bytes",
+        "Size of all stored users serialized as JSON",
+        usage.serialized_bytes as f64,
+    ));
+    samples.push(Sample::gauge(
+        "storage_index_bytes",
+        "Memory reserved by the tables indexing users by ID",
+        usage.index_bytes as f64,
+    ));
+    samples
+}
+
+/// Renders the user store's memory footprint
+pub fn render_storage(usage: &StorageUsage) -> String {
+    render(&storage_samples(usage))
+}
+
+/// Renders samples in the Prometheus text exposition format
+///
+/// Samples of the same metric have to be adjacent, so that its `HELP` and
+/// `TYPE` lines are written once.
+pub fn render(samples: &[Sample]) -> String {
+    let mut out = String::new();
+    let mut previous = None;
+    for sample in samples {
+        if previous != Some(sample.name) {
+            let kind = match sample.kind {
+                Kind::Gauge => "gauge",
+                Kind::Counter => "counter",
+                Kind::Histogram => "histogram",
+            };
+            out.push_str(&format!(
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n",
+                name = sample.name,
+                help = sample.help,
+            ));
+            previous = Some(sample.name);
+        }
+        out.push_str(sample.name);
+        out.push_str(sample.suffix);
+        if !sample.labels.is_empty() {
+            let labels: Vec<String> = sample
+                .labels
+                .iter()
+                .map(|(name, value)| format!("{}=\"{}\"", name, value))
+                .collect();
+            out.push_str(&format!("{{{}}}", labels.join(",")));
+        }
+        out.push_str(&format!(" {}\n", sample.value));
+    }
+    out
+}
+
+/// Returns the metrics, storage and SLO samples exported by the service
+pub async fn collect(state: &AppState) -> Vec<Sample> {
+    let usage = state.storage.read().await.usage();
+    let mut samples = state.metrics.samples();
+    samples.extend(storage_samples(&usage));
+    samples.extend(state.storage.wait_samples());
+    samples.extend(state.slo.samples());
+    samples.extend(state.shadow.samples());
+    samples.extend(state.shards.samples());
+    samples.extend(state.replication.samples());
+    samples.extend(state.deprecations.samples());
+    samples
+}
+
+/// Decrements the in-flight gauge when the request finishes, including
+/// when the client disconnects and the handler future is dropped
+struct InFlight<'a>(&'a Metrics);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Middleware that tracks the number of in-flight requests
+pub async fn track_in_flight(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    state.metrics.in_flight.fetch_add(1, Ordering::Relaxed);
+    let _guard = InFlight(&state.metrics);
+    next.run(request).await
+}
+
+/// Exports metrics in the Prometheus text format
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "health",
+    responses((status = 200, description = "Prometheus metrics", body = String, content_type = "text/plain"))
+)]
+pub async fn export(State(state): State<AppState>) -> impl IntoResponse {
+    let text = render(&collect(&state).await);
+    ([(CONTENT_TYPE, CONTENT_TYPE_TEXT)], text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.record_shed();
+        metrics.record_shed();
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE http_requests_in_flight gauge\nhttp_requests_in_flight 0\n"));
+        assert!(text.contains("http_requests_shed_total 2\n"));
+        assert!(!text.contains("circuit_breaker_state"));
+    }
+
+    #[test]
+    fn test_render_breakers() {
+        use crate::resilience::{CircuitBreaker, Settings};
+
+        let metrics = Metrics::default();
+        let breaker = Arc::new(CircuitBreaker::new("mailer", &Settings::default()));
+        metrics.register_breaker(breaker.clone());
+        assert!(metrics
+            .render()
+            .contains("circuit_breaker_state{name=\"mailer\"} 0\n"));
+
+        for _ in 0..Settings::default().failure_threshold {
+            breaker.record_failure();
+        }
+        assert!(!breaker.try_acquire());
+        let text = metrics.render();
+        assert!(text.contains("circuit_breaker_state{name=\"mailer\"} 1\n"));
+        assert!(text.contains("circuit_breaker_rejected_total{name=\"mailer\"} 1\n"));
+    }
+
+    #[test]
+    fn test_render_histogram() {
+        let histogram = Histogram::new(&[0.001, 0.01]);
+        histogram.observe(Duration::from_micros(500));
+        histogram.observe(Duration::from_millis(5));
+        histogram.observe(Duration::from_millis(50));
+
+        let labels = [("mode", "read".to_string())];
+        let text = render(&histogram.samples("wait_seconds", "Waits", &labels));
+        assert_eq!(
+            text,
+            "# HELP wait_seconds Waits\n\
+             # TYPE wait_seconds histogram\n\
+             wait_seconds_bucket{mode=\"read\",le=\"0.001\"} 1\n\
+             wait_seconds_bucket{mode=\"read\",le=\"0.01\"} 2\n\
+             wait_seconds_bucket{mode=\"read\",le=\"+Inf\"} 3\n\
+             wait_seconds_sum{mode=\"read\"} 0.0555\n\
+             wait_seconds_count{mode=\"read\"} 3\n"
+        );
+    }
+
+    #[test]
+    fn test_render_storage() {
+        let usage = StorageUsage {
+            users: 3,
+            max_users: None,
+            serialized_bytes: 600,
+            index_bytes: 1024,
+        };
+        let text = render_storage(&usage);
+        assert!(text.contains("# TYPE storage_users gauge\nstorage_users 3\n"));
+        assert!(text.contains("storage_serialized_bytes 600\n"));
+        assert!(text.contains("storage_index_bytes 1024\n"));
+        assert!(!text.contains("storage_max_users"));
+
+        let text = render_storage(&StorageUsage {
+            max_users: Some(10),
+            ..usage
+        });
+        assert!(text.contains("storage_max_users 10\n"));
+    }
+}
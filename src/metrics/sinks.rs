@@ -0,0 +1,530 @@
+//! Pushing metrics to StatsD and the Prometheus Pushgateway
+//!
+//! The pull endpoint at `GET /metrics` needs a Prometheus server scraping
+//! it. Deployments without one configure a [`MetricsSink`] instead: every
+//! `APP_METRICS_EXPORT_INTERVAL_SECONDS`, the [`Exporter`] collects all
+//! samples and hands them to each sink, and once more when the server
+//! shuts down.
+//!
+//! - [`Statsd`] sends UDP datagrams to a StatsD or Datadog agent
+//!   (`APP_STATSD_ADDR`). Labels become DogStatsD tags.
+//! - [`PushGateway`] replaces the service's group on a Prometheus
+//!   Pushgateway (`APP_PUSHGATEWAY_URL`).
+//!
+//! A failing sink is logged and does not affect the others. Both sinks
+//! open sockets and need the `server` feature; edge runtimes add their
+//! own with [`Exporter::sink`].
+
+#[cfg(feature = "server")]
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+#[cfg(feature = "server")]
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures_util::future::{join_all, BoxFuture};
+#[cfg(feature = "server")]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+#[cfg(feature = "server")]
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::task::JoinHandle;
+
+use super::{collect, Sample};
+#[cfg(feature = "server")]
+use super::{render, Kind, CONTENT_TYPE_TEXT};
+use crate::AppState;
+
+/// How long a sink may take to send one batch
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest StatsD datagram sent, safe for common network MTUs
+#[cfg(feature = "server")]
+const MAX_DATAGRAM: usize = 1432;
+
+/// A destination metrics are pushed to
+pub trait MetricsSink: Send + Sync + 'static {
+    /// Identifies the sink in logs
+    fn name(&self) -> &str;
+
+    /// Sends the current samples
+    fn send<'a>(&'a self, samples: &'a [Sample]) -> BoxFuture<'a, io::Result<()>>;
+}
+
+/// Where and how often metrics are pushed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// StatsD agent as `host:port`; nothing is sent over StatsD when unset
+    pub statsd_addr: Option<String>,
+    /// Prefix of StatsD metric names
+    pub statsd_prefix: String,
+    /// Pushgateway to push to; nothing is pushed when unset
+    pub pushgateway: Option<PushGatewayUrl>,
+    /// Job name the metrics are pushed under
+    pub pushgateway_job: String,
+    /// Time between two exports
+    pub interval: Duration,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            statsd_addr: None,
+            statsd_prefix: "rust_api".to_string(),
+            pushgateway: None,
+            pushgateway_job: "rust_api".to_string(),
+            interval: Duration::from_secs(15),
+        }
+    }
+}
+
+/// Sends samples to all configured sinks
+pub struct Exporter {
+    sinks: Vec<Box<dyn MetricsSink>>,
+    interval: Duration,
+}
+
+impl std::fmt::Debug for Exporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Exporter")
+            .field(
+                "sinks",
+                &self
+                    .sinks
+                    .iter()
+                    .map(|sink| sink.name())
+                    .collect::<Vec<_>>(),
+            )
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+impl Exporter {
+    /// Creates an exporter without sinks
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            sinks: Vec::new(),
+            interval,
+        }
+    }
+
+    /// Creates an exporter with the sinks `settings` configure, or `None`
+    /// if there are none
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the StatsD socket cannot be opened.
+    #[cfg(feature = "server")]
+    pub async fn from_settings(settings: &Settings) -> io::Result<Option<Self>> {
+        let mut exporter = Self::new(settings.interval);
+        if let Some(addr) = &settings.statsd_addr {
+            exporter = exporter.sink(Statsd::connect(addr, &settings.statsd_prefix).await?);
+        }
+        if let Some(url) = &settings.pushgateway {
+            exporter = exporter.sink(PushGateway::new(url.clone(), &settings.pushgateway_job));
+        }
+        Ok((!exporter.sinks.is_empty()).then_some(exporter))
+    }
+
+    /// Adds a sink
+    pub fn sink<S: MetricsSink>(mut self, sink: S) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Sends the current samples to every sink concurrently
+    ///
+    /// Each sink is called through its entry in
+    /// [`AppState::outbound`](crate::AppState::outbound), under its name.
+    pub async fn export(&self, state: &AppState) {
+        let samples = collect(state).await;
+        join_all(self.sinks.iter().map(|sink| {
+            let samples = &samples;
+            let resilient = state.outbound.get(sink.name());
+            async move {
+                let sent = resilient
+                    .call(|| async move {
+                        tokio::time::timeout(SEND_TIMEOUT, sink.send(samples))
+                            .await
+                            .unwrap_or_else(|_| {
+                                Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"))
+                            })
+                    })
+                    .await;
+                if let Err(error) = sent {
+                    tracing::warn!(sink = sink.name(), %error, "metrics export failed");
+                }
+            }
+        }))
+        .await;
+    }
+
+    /// Exports every interval until the returned task is aborted
+    pub fn spawn(self: Arc<Self>, state: AppState) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(self.interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            // The first tick completes immediately; nothing has happened yet
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                self.export(&state).await;
+            }
+        })
+    }
+}
+
+/// Sends samples to a StatsD agent over UDP
+///
+/// Gauges are sent as they are. StatsD counters add up what they receive,
+/// so counters are sent as the increase since the previous export.
+#[cfg(feature = "server")]
+#[derive(Debug)]
+pub struct Statsd {
+    socket: UdpSocket,
+    prefix: String,
+    sent: Mutex<HashMap<String, f64>>,
+}
+
+#[cfg(feature = "server")]
+impl Statsd {
+    /// Opens a socket sending to `addr`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `addr` cannot be resolved or no local socket can
+    /// be bound.
+    pub async fn connect(addr: &str, prefix: &str) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0)).await?;
+        socket.connect(addr).await?;
+        Ok(Self {
+            socket,
+            prefix: prefix.to_string(),
+            sent: Mutex::default(),
+        })
+    }
+
+    /// Formats one line per sample
+    fn lines(&self, samples: &[Sample]) -> Vec<String> {
+        let mut sent = self
+            .sent
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        samples
+            .iter()
+            .map(|sample| {
+                let name = if self.prefix.is_empty() {
+                    format!("{}{}", sample.name, sample.suffix)
+                } else {
+                    format!("{}.{}{}", self.prefix, sample.name, sample.suffix)
+                };
+                let tags: Vec<String> = sample
+                    .labels
+                    .iter()
+                    .map(|(name, value)| format!("{}:{}", name, value))
+                    .collect();
+                let (value, kind) = match sample.kind {
+                    Kind::Gauge => (sample.value, "g"),
+                    Kind::Counter | Kind::Histogram => {
+                        let key = format!("{}{}{:?}", sample.name, sample.suffix, sample.labels);
+                        let previous = sent.insert(key, sample.value).unwrap_or_default();
+                        ((sample.value - previous).max(0.0), "c")
+                    }
+                };
+                if tags.is_empty() {
+                    format!("{}:{}|{}", name, value, kind)
+                } else {
+                    format!("{}:{}|{}|#{}", name, value, kind, tags.join(","))
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "server")]
+impl MetricsSink for Statsd {
+    fn name(&self) -> &str {
+        "statsd"
+    }
+
+    fn send<'a>(&'a self, samples: &'a [Sample]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let mut datagram = String::new();
+            for line in self.lines(samples) {
+                if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM {
+                    self.socket.send(datagram.as_bytes()).await?;
+                    datagram.clear();
+                }
+                if !datagram.is_empty() {
+                    datagram.push('\n');
+                }
+                datagram.push_str(&line);
+            }
+            if !datagram.is_empty() {
+                self.socket.send(datagram.as_bytes()).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Address of a Prometheus Pushgateway, as `http://host[:port][/path]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushGatewayUrl {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl FromStr for PushGatewayUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s
+            .trim()
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("'{}' is not an http:// URL", s))?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("invalid port in '{}'", s))?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("missing host in '{}'", s));
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+/// Pushes samples to a Prometheus Pushgateway
+///
+/// Every push replaces all metrics of the job, so series that disappeared
+/// from the service disappear from the gateway as well.
+#[cfg(feature = "server")]
+#[derive(Debug)]
+pub struct PushGateway {
+    url: PushGatewayUrl,
+    job: String,
+}
+
+#[cfg(feature = "server")]
+impl PushGateway {
+    /// Pushes to `url` under `job`
+    pub fn new(url: PushGatewayUrl, job: &str) -> Self {
+        Self {
+            url,
+            job: job.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+impl MetricsSink for PushGateway {
+    fn name(&self) -> &str {
+        "pushgateway"
+    }
+
+    fn send<'a>(&'a self, samples: &'a [Sample]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let body = render(samples);
+            let url = &self.url;
+            let request = format!(
+                "PUT {}/metrics/job/{} HTTP/1.1\r\n\
+                 Host: {}:{}\r\n\
+                 Content-Type: {}\r\n\
+                 Content-Length: {}\r\n\
+                 Connection: close\r\n\r\n",
+                url.path,
+                self.job,
+                url.host,
+                url.port,
+                CONTENT_TYPE_TEXT,
+                body.len()
+            );
+            let mut stream = TcpStream::connect((url.host.as_str(), url.port)).await?;
+            stream.write_all(request.as_bytes()).await?;
+            stream.write_all(body.as_bytes()).await?;
+
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await?;
+            let response = String::from_utf8_lossy(&response);
+            let status = response.lines().next().unwrap_or_default();
+            match status.split_whitespace().nth(1) {
+                Some(code) if code.starts_with('2') => Ok(()),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("pushgateway answered '{}'", status),
+                )),
+            }
+        })
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Reads one request, headers and body, from a client of the gateway
+    async fn read_request(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        loop {
+            let len = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..len]);
+            let text = String::from_utf8_lossy(&request).into_owned();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .and_then(|length| length.parse().ok())
+                    .unwrap_or_default();
+                if len == 0 || body.len() >= length {
+                    return text;
+                }
+            }
+        }
+    }
+
+    fn samples(shed: u64) -> Vec<Sample> {
+        vec![
+            Sample::gauge("http_requests_in_flight", "In flight", 3.0),
+            Sample::counter("http_requests_shed_total", "Shed", shed),
+            Sample::gauge("circuit_breaker_state", "State", 1.0).label("name", "mailer"),
+        ]
+    }
+
+    #[test]
+    fn test_parse_pushgateway_url() {
+        let url: PushGatewayUrl = "http://gateway:9091/prefix/".parse().unwrap();
+        assert_eq!(
+            url,
+            PushGatewayUrl {
+                host: "gateway".to_string(),
+                port: 9091,
+                path: "/prefix".to_string(),
+            }
+        );
+        let url: PushGatewayUrl = "http://gateway".parse().unwrap();
+        assert_eq!((url.port, url.path.as_str()), (80, ""));
+
+        assert!("https://gateway".parse::<PushGatewayUrl>().is_err());
+        assert!("http://gateway:push".parse::<PushGatewayUrl>().is_err());
+        assert!("http://:9091".parse::<PushGatewayUrl>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_statsd_sends_counter_increases() {
+        let agent = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = agent.local_addr().unwrap().to_string();
+        let statsd = Statsd::connect(&addr, "api").await.unwrap();
+
+        statsd.send(&samples(5)).await.unwrap();
+        let mut buf = [0; MAX_DATAGRAM];
+        let len = agent.recv(&mut buf).await.unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buf[..len]).unwrap(),
+            "api.http_requests_in_flight:3|g\n\
+             api.http_requests_shed_total:5|c\n\
+             api.circuit_breaker_state:1|g|#name:mailer"
+        );
+
+        assert_eq!(
+            statsd.lines(&samples(7))[1],
+            "api.http_requests_shed_total:2|c"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pushgateway_replaces_job() {
+        let gateway = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let url = format!("http://{}/base", gateway.local_addr().unwrap());
+        let sink = PushGateway::new(url.parse().unwrap(), "api");
+
+        let received = tokio::spawn(async move {
+            let (mut stream, _) = gateway.accept().await.unwrap();
+            let request = read_request(&mut stream).await;
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            request
+        });
+        sink.send(&samples(5)).await.unwrap();
+
+        let request = received.await.unwrap();
+        assert!(request.starts_with("PUT /base/metrics/job/api HTTP/1.1\r\n"));
+        assert!(request.ends_with("circuit_breaker_state{name=\"mailer\"} 1\n"));
+    }
+
+    #[tokio::test]
+    async fn test_pushgateway_reports_rejection() {
+        let gateway = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let url = format!("http://{}", gateway.local_addr().unwrap());
+        let sink = PushGateway::new(url.parse().unwrap(), "api");
+
+        tokio::spawn(async move {
+            let (mut stream, _) = gateway.accept().await.unwrap();
+            read_request(&mut stream).await;
+            let _ = stream
+                .write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")
+                .await;
+        });
+        let err = sink.send(&samples(5)).await.unwrap_err();
+        assert!(err.to_string().contains("400 Bad Request"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_export_retries_through_breaker() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        use crate::resilience::BreakerState;
+
+        struct Failing(Arc<AtomicU32>);
+
+        impl MetricsSink for Failing {
+            fn name(&self) -> &str {
+                "failing"
+            }
+
+            fn send<'a>(&'a self, _: &'a [Sample]) -> BoxFuture<'a, io::Result<()>> {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                Box::pin(async { Err(io::Error::new(io::ErrorKind::Other, "unreachable")) })
+            }
+        }
+
+        let state = AppState::with_config(crate::config::Config {
+            resilience: crate::resilience::Settings {
+                failure_threshold: 2,
+                max_attempts: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let attempts = Arc::new(AtomicU32::new(0));
+        let exporter = Exporter::new(Duration::from_secs(15)).sink(Failing(attempts.clone()));
+
+        exporter.export(&state).await;
+        assert_eq!(attempts.load(Ordering::Relaxed), 2);
+        let breaker = state.outbound.get("failing").breaker().clone();
+        assert_eq!(breaker.state(), BreakerState::Open);
+
+        // An open breaker skips the sink
+        exporter.export(&state).await;
+        assert_eq!(attempts.load(Ordering::Relaxed), 2);
+        assert_eq!(breaker.rejected(), 1);
+    }
+}
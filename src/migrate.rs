@@ -0,0 +1,704 @@
+//! Moving stored users between storage backends
+//!
+//! [`migrate`] copies the users of one [`UserRepository`] to another in
+//! batches of [`BATCH_SIZE`]. It pages through the source twice: first
+//! every user is validated with the same rules the API applies, and IDs,
+//! emails and phone numbers are checked to be unique, then the users are
+//! created in the destination, which must be empty. Nothing is written if
+//! any check fails, and besides the batch at hand only the unique values
+//! seen so far are held in memory. Progress is reported after every batch.
+//!
+//! [`migrate_into`] copies into the store of a running [`AppState`] and
+//! publishes every created user, so stores kept up to date from its
+//! events, such as a key-value store attached with
+//! [`kv::start`](crate::kv::start), receive them too. Reading from a
+//! key-value store goes through the store it was loaded into.
+//!
+//! `rust-api migrate-data --from <source> --to <destination>` names
+//! backends by [`Location`]:
+//!
+//! - a path to a `.json` file, or a `file://` URL, is a snapshot as
+//!   written by `POST /api/v1/admin/snapshot`, and the way data is moved
+//!   in and out of the in-memory store. The trash and tombstones are
+//!   carried over with the users.
+//!
+//! Database URLs such as `postgres://` are recognized, but no database
+//! backend is built in yet, so they are rejected. Adding one takes a
+//! [`UserRepository`] for it and a `Location` naming it.
+
+use std::collections::HashSet;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::events::Event;
+use crate::models::{Snapshot, StorageError, User};
+use crate::repository::UserRepository;
+use crate::snapshot;
+use crate::validation::name::NameRules;
+use crate::validation::{email, locale, phone, timezone};
+use crate::{AppState, Storage};
+
+/// Number of users read, validated and written at a time
+pub const BATCH_SIZE: usize = 1000;
+
+/// A storage backend data is migrated from or to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    /// A snapshot file
+    Snapshot(PathBuf),
+}
+
+impl FromStr for Location {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.split_once("://") {
+            Some(("file", path)) => Ok(Location::Snapshot(PathBuf::from(path))),
+            Some(("postgres" | "postgresql", _)) => Err(format!(
+                "'{}' names a PostgreSQL database, which is not a storage backend of this build",
+                s
+            )),
+            Some((scheme, _)) => Err(format!("unknown storage backend '{}://'", scheme)),
+            None if s.ends_with(".json") => Ok(Location::Snapshot(PathBuf::from(s))),
+            None => Err(format!(
+                "'{}' is neither a .json snapshot nor a backend URL",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Snapshot(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// A user that cannot be migrated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// ID of the user
+    pub id: Uuid,
+    /// What is wrong with it
+    pub reason: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "user {}: {}", self.id, self.reason)
+    }
+}
+
+/// Errors returned by [`migrate`]
+#[derive(Debug)]
+pub enum MigrationError {
+    /// The source could not be read or the destination written
+    Io(io::Error),
+    /// Users failed validation; nothing was written
+    Invalid(Vec<Problem>),
+    /// The destination already holds users; nothing was written
+    NotEmpty {
+        /// Name of the destination backend
+        backend: String,
+        /// Number of users it holds
+        users: usize,
+    },
+    /// The destination refused a user; the users of earlier batches and
+    /// those before it in its batch were written
+    Refused(Uuid, StorageError),
+}
+
+impl std::fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MigrationError::Io(err) => write!(f, "{}", err),
+            MigrationError::Invalid(problems) => {
+                write!(f, "{} users failed validation", problems.len())
+            }
+            MigrationError::NotEmpty { backend, users } => {
+                write!(
+                    f,
+                    "the {} destination already holds {} users",
+                    backend, users
+                )
+            }
+            MigrationError::Refused(id, err) => {
+                write!(f, "the destination refused user {}: {}", id, err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<io::Error> for MigrationError {
+    fn from(err: io::Error) -> Self {
+        MigrationError::Io(err)
+    }
+}
+
+/// What a migration is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Checking the users of the source
+    Validating,
+    /// Creating the users in the destination
+    Copying,
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phase::Validating => write!(f, "validated"),
+            Phase::Copying => write!(f, "copied"),
+        }
+    }
+}
+
+/// How far a migration got, reported after every batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// What the migration is doing
+    pub phase: Phase,
+    /// Users done in this phase
+    pub done: usize,
+    /// Users in the source
+    pub total: usize,
+}
+
+impl std::fmt::Display for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}/{} users", self.phase, self.done, self.total)
+    }
+}
+
+/// What a migration between locations copied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Users copied
+    pub users: usize,
+    /// Deleted users copied with the trash
+    pub trashed: usize,
+    /// Tombstones copied
+    pub tombstones: usize,
+}
+
+/// Checks a single user against the API's validation rules
+fn check(user: &User, rules: &NameRules) -> Result<(), String> {
+    rules
+        .normalize(&user.name)
+        .map_err(|err| format!("name: {}", err))?;
+    email::normalize(&user.email).map_err(|err| format!("email: {}", err))?;
+    if let Some(number) = &user.phone {
+        phone::normalize(number, None).map_err(|err| format!("phone: {}", err))?;
+    }
+    if let Some(tag) = &user.locale {
+        locale::normalize(tag).map_err(|err| format!("locale: {}", err))?;
+    }
+    if let Some(zone) = &user.timezone {
+        timezone::normalize(zone).map_err(|err| format!("timezone: {}", err))?;
+    }
+    Ok(())
+}
+
+/// Reads the users of `source` a batch at a time, in the order of
+/// [`UserRepository::page`]
+fn batches<R: UserRepository + ?Sized>(source: &R) -> impl Iterator<Item = Vec<User>> + '_ {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let batch = source.page(offset, BATCH_SIZE);
+        offset += batch.len();
+        (!batch.is_empty()).then_some(batch)
+    })
+}
+
+/// Validates every user of `source`, calling `progress` after every batch
+///
+/// Returns the problems found, in the order the users were read.
+pub fn validate<R: UserRepository + ?Sized>(
+    source: &R,
+    rules: &NameRules,
+    mut progress: impl FnMut(Progress),
+) -> Vec<Problem> {
+    let total = source.count();
+    let mut problems = Vec::new();
+    let mut ids = HashSet::new();
+    let mut emails = HashSet::new();
+    let mut phones = HashSet::new();
+    let mut done = 0;
+    for batch in batches(source) {
+        for user in &batch {
+            let unique = if !ids.insert(user.id) {
+                Err("duplicate ID".to_string())
+            } else if !emails.insert(user.email.clone()) {
+                Err(format!("email {} belongs to another user", user.email))
+            } else if user
+                .phone
+                .as_ref()
+                .is_some_and(|number| !phones.insert(number.clone()))
+            {
+                Err("phone belongs to another user".to_string())
+            } else {
+                Ok(())
+            };
+            if let Err(reason) = unique.and_then(|()| check(user, rules)) {
+                problems.push(Problem {
+                    id: user.id,
+                    reason,
+                });
+            }
+        }
+        done += batch.len();
+        progress(Progress {
+            phase: Phase::Validating,
+            done,
+            total,
+        });
+    }
+    problems
+}
+
+/// Validates the users of `source` and fails unless all pass and
+/// `destination` is empty
+fn prepare<S: UserRepository + ?Sized>(
+    source: &S,
+    destination: (&str, usize),
+    rules: &NameRules,
+    progress: &mut impl FnMut(Progress),
+) -> Result<usize, MigrationError> {
+    let (backend, users) = destination;
+    if users > 0 {
+        return Err(MigrationError::NotEmpty {
+            backend: backend.to_string(),
+            users,
+        });
+    }
+    let problems = validate(source, rules, &mut *progress);
+    if !problems.is_empty() {
+        return Err(MigrationError::Invalid(problems));
+    }
+    Ok(source.count())
+}
+
+/// Copies every user of `source` to the empty `destination`
+///
+/// `progress` is called after every batch validated and every batch
+/// written. The source must not change during the migration. Returns the
+/// number of users copied.
+///
+/// # Errors
+///
+/// Returns [`MigrationError::NotEmpty`] if the destination holds users and
+/// [`MigrationError::Invalid`] if any user fails validation, writing
+/// nothing, or [`MigrationError::Refused`] if the destination refuses a
+/// user, such as when it is full.
+pub fn migrate<S, D>(
+    source: &S,
+    destination: &mut D,
+    rules: &NameRules,
+    mut progress: impl FnMut(Progress),
+) -> Result<usize, MigrationError>
+where
+    S: UserRepository + ?Sized,
+    D: UserRepository + ?Sized,
+{
+    let total = prepare(
+        source,
+        (destination.name(), destination.count()),
+        rules,
+        &mut progress,
+    )?;
+    let mut done = 0;
+    for batch in batches(source) {
+        for user in batch {
+            let id = user.id;
+            destination
+                .create(user)
+                .map_err(|err| MigrationError::Refused(id, err))?;
+            done += 1;
+        }
+        progress(Progress {
+            phase: Phase::Copying,
+            done,
+            total,
+        });
+    }
+    Ok(done)
+}
+
+/// Like [`migrate`], but copies into the store of `state`, publishing
+/// every created user
+///
+/// Every batch is written under one write lock, and its users are
+/// published once it is released.
+///
+/// # Errors
+///
+/// Returns the errors of [`migrate`]. Users written before a refused one
+/// are published all the same.
+pub async fn migrate_into<S>(
+    source: &S,
+    state: &AppState,
+    rules: &NameRules,
+    mut progress: impl FnMut(Progress),
+) -> Result<usize, MigrationError>
+where
+    S: UserRepository + Sync + ?Sized,
+{
+    let users = state.storage.read().await.len();
+    let total = prepare(source, ("memory", users), rules, &mut progress)?;
+    let mut done = 0;
+    for batch in batches(source) {
+        let mut created = Vec::with_capacity(batch.len());
+        let mut refused = None;
+        {
+            let mut storage = state.storage.write().await;
+            for user in batch {
+                let id = user.id;
+                match UserRepository::create(&mut *storage, user.clone()) {
+                    Ok(()) => created.push(user),
+                    Err(err) => {
+                        refused = Some(MigrationError::Refused(id, err));
+                        break;
+                    }
+                }
+            }
+        }
+        done += created.len();
+        for user in created {
+            state.events.publish(Event::UserCreated(user));
+        }
+        if let Some(err) = refused {
+            return Err(err);
+        }
+        progress(Progress {
+            phase: Phase::Copying,
+            done,
+            total,
+        });
+    }
+    Ok(done)
+}
+
+/// Loads `snapshot` into an in-memory store to migrate from
+///
+/// # Errors
+///
+/// Returns the users a store cannot hold: all but the first of several
+/// with one ID, and those stored and in the trash at once.
+pub fn load(snapshot: Snapshot) -> Result<Storage, Vec<Problem>> {
+    let mut ids = HashSet::new();
+    let mut problems: Vec<Problem> = snapshot
+        .users
+        .iter()
+        .filter(|user| !ids.insert(user.id))
+        .map(|user| Problem {
+            id: user.id,
+            reason: "duplicate ID".to_string(),
+        })
+        .collect();
+    problems.extend(
+        snapshot
+            .trash
+            .iter()
+            .filter(|trashed| ids.contains(&trashed.user.id))
+            .map(|trashed| Problem {
+                id: trashed.user.id,
+                reason: "stored and in the trash at once".to_string(),
+            }),
+    );
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+    let mut storage = Storage::new();
+    // An unbounded store is never full
+    let _ = storage.load(snapshot);
+    Ok(storage)
+}
+
+/// Reads the store at `location`
+async fn open(location: &Location) -> Result<Storage, MigrationError> {
+    match location {
+        Location::Snapshot(path) => {
+            load(snapshot::load(path).await?).map_err(MigrationError::Invalid)
+        }
+    }
+}
+
+/// Fails if anything is stored at `location`
+async fn check_vacant(location: &Location) -> io::Result<()> {
+    match location {
+        Location::Snapshot(path) => {
+            if crate::fs::try_exists(path).await? {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} already exists", path.display()),
+                ));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Copies all data from `from` to `to`
+///
+/// The users are copied with [`migrate`]; the trash and tombstones are
+/// carried over once all users were.
+///
+/// # Errors
+///
+/// Returns [`MigrationError::Invalid`] if any user fails validation or is
+/// stored and in the trash at once, and [`MigrationError::Io`] if the
+/// source cannot be read or the destination already holds data or cannot
+/// be written.
+pub async fn migrate_locations(
+    from: &Location,
+    to: &Location,
+    rules: &NameRules,
+    progress: impl FnMut(Progress),
+) -> Result<MigrationReport, MigrationError> {
+    let source = open(from).await?;
+    check_vacant(to).await?;
+    // Snapshots hold the trash and tombstones besides the users
+    let carried = source.snapshot(Utc::now());
+    let mut destination = Storage::new();
+    let users = migrate(&source, &mut destination, rules, progress)?;
+    let report = MigrationReport {
+        users,
+        trashed: carried.trash.len(),
+        tombstones: carried.tombstones.len(),
+    };
+    match to {
+        Location::Snapshot(path) => {
+            let snapshot = Snapshot {
+                users: destination.snapshot(carried.taken_at).users,
+                ..carried
+            };
+            snapshot::save(path, &snapshot).await?;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::{self, KvStore, MemoryKv};
+    use crate::models::TrashedUser;
+    use chrono::{DateTime, TimeZone};
+    use std::sync::Arc;
+
+    fn at(second: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000 + second, 0).unwrap()
+    }
+
+    fn snapshot(users: Vec<User>) -> Snapshot {
+        Snapshot {
+            taken_at: Utc::now(),
+            users,
+            trash: Vec::new(),
+            tombstones: Default::default(),
+            rate_limits: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_parse_location() {
+        assert_eq!(
+            "backup.json".parse(),
+            Ok(Location::Snapshot(PathBuf::from("backup.json")))
+        );
+        assert_eq!(
+            "file:///var/backups/users".parse(),
+            Ok(Location::Snapshot(PathBuf::from("/var/backups/users")))
+        );
+        assert!("postgres://localhost/users"
+            .parse::<Location>()
+            .unwrap_err()
+            .contains("PostgreSQL"));
+        assert!("s3://bucket/key".parse::<Location>().is_err());
+        assert!("backup".parse::<Location>().is_err());
+    }
+
+    #[test]
+    fn test_load_rejects_what_a_store_cannot_hold() {
+        let user = User::new("Jane Doe", "jane@example.com", Utc::now());
+        let mut contents = snapshot(vec![user.clone(), user.clone()]);
+        contents.trash.push(TrashedUser {
+            user: Arc::new(user.clone()),
+            deleted_at: Utc::now(),
+            deleted_by: None,
+        });
+
+        let problems = load(contents).unwrap_err();
+        let reasons: Vec<&str> = problems
+            .iter()
+            .map(|problem| problem.reason.as_str())
+            .collect();
+        assert_eq!(
+            reasons,
+            vec!["duplicate ID", "stored and in the trash at once"]
+        );
+    }
+
+    #[test]
+    fn test_validate_reports_every_problem() {
+        let valid = User::new("Jane Doe", "jane@example.com", at(0));
+        let duplicate = User::new("John Doe", "jane@example.com", at(1)).with_phone("+14155552671");
+        let invalid = User::new("Bad Email", "not-an-email", at(2));
+        let source = load(snapshot(vec![valid, duplicate.clone(), invalid.clone()])).unwrap();
+
+        let mut reported = Vec::new();
+        let problems = validate(&source, &NameRules::default(), |progress| {
+            reported.push(progress)
+        });
+        assert_eq!(
+            reported,
+            vec![Progress {
+                phase: Phase::Validating,
+                done: 3,
+                total: 3
+            }]
+        );
+        let ids: Vec<Uuid> = problems.iter().map(|problem| problem.id).collect();
+        assert_eq!(ids, vec![duplicate.id, invalid.id]);
+        assert_eq!(
+            problems[0].reason,
+            "email jane@example.com belongs to another user"
+        );
+        assert!(problems[1].reason.starts_with("email: "));
+    }
+
+    #[test]
+    fn test_migrate_copies_in_batches() {
+        let mut source = Storage::new();
+        for n in 0..=BATCH_SIZE as i64 {
+            let user = User::new(
+                format!("User {}", n),
+                format!("user{}@example.com", n),
+                at(n),
+            );
+            UserRepository::create(&mut source, user).unwrap();
+        }
+
+        let mut destination = Storage::new();
+        let mut reported = Vec::new();
+        let copied = migrate(
+            &source,
+            &mut destination,
+            &NameRules::default(),
+            |progress| reported.push((progress.phase, progress.done)),
+        )
+        .unwrap();
+        assert_eq!(copied, BATCH_SIZE + 1);
+        assert_eq!(
+            reported,
+            vec![
+                (Phase::Validating, BATCH_SIZE),
+                (Phase::Validating, BATCH_SIZE + 1),
+                (Phase::Copying, BATCH_SIZE),
+                (Phase::Copying, BATCH_SIZE + 1),
+            ]
+        );
+        assert_eq!(
+            destination.page(0, BATCH_SIZE + 1),
+            source.page(0, BATCH_SIZE + 1)
+        );
+
+        // A destination holding users is left alone
+        let err = migrate(&source, &mut destination, &NameRules::default(), |_| {}).unwrap_err();
+        assert!(matches!(err, MigrationError::NotEmpty { users, .. } if users == BATCH_SIZE + 1));
+    }
+
+    #[tokio::test]
+    async fn test_migrate_through_key_value_stores() {
+        let mut source = Storage::new();
+        let users: Vec<User> = (0..3)
+            .map(|n| {
+                User::new(
+                    format!("User {}", n),
+                    format!("user{}@example.com", n),
+                    at(n),
+                )
+            })
+            .collect();
+        for user in &users {
+            UserRepository::create(&mut source, user.clone()).unwrap();
+        }
+
+        // Into a key-value store, through the events of the store it backs
+        let store = MemoryKv::default();
+        let state = AppState::new();
+        let persistence = kv::start(&state, store.clone()).await.unwrap();
+        let copied = migrate_into(&source, &state, &NameRules::default(), |_| {})
+            .await
+            .unwrap();
+        persistence.flush().await;
+        assert_eq!(copied, 3);
+        assert_eq!(store.keys(kv::USERS_PREFIX).await.unwrap().len(), 3);
+
+        // And out of it, through the store it is loaded into
+        let loaded = AppState::new();
+        kv::start(&loaded, store).await.unwrap();
+        let mut destination = Storage::new();
+        migrate(
+            &*loaded.storage.read().await,
+            &mut destination,
+            &NameRules::default(),
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(destination.page(0, 10), users);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_between_snapshots() {
+        let dir = std::env::temp_dir().join(format!("migrate-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let from = Location::Snapshot(dir.join("from.json"));
+        let to = Location::Snapshot(dir.join("to.json"));
+        let mut source = snapshot(vec![User::new("Jane Doe", "jane@example.com", at(0))]);
+        source.trash.push(TrashedUser {
+            user: Arc::new(User::new("John Doe", "john@example.com", at(1))),
+            deleted_at: at(2),
+            deleted_by: None,
+        });
+        snapshot::save(&dir.join("from.json"), &source)
+            .await
+            .unwrap();
+
+        let mut progress = Vec::new();
+        let report = migrate_locations(&from, &to, &NameRules::default(), |reported| {
+            progress.push(reported.to_string())
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            report,
+            MigrationReport {
+                users: 1,
+                trashed: 1,
+                tombstones: 0
+            }
+        );
+        assert_eq!(progress, vec!["validated 1/1 users", "copied 1/1 users"]);
+        let written = snapshot::load(&dir.join("to.json")).await.unwrap();
+        assert_eq!((written.users, written.trash), (source.users, source.trash));
+
+        // An existing destination is never overwritten
+        let err = migrate_locations(&from, &to, &NameRules::default(), |_| {})
+            .await
+            .unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            matches!(err, MigrationError::Io(err) if err.kind() == io::ErrorKind::AlreadyExists)
+        );
+    }
+}
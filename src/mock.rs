@@ -0,0 +1,323 @@
+//! Deterministic data for mock mode
+//!
+//! Started with `--mock`, the server seeds its storage with generated users
+//! and replaces the wall clock and random IDs with fixed, reproducible
+//! sources. Every mock instance started with the same `APP_MOCK_SEED` and
+//! `APP_MOCK_USERS` serves identical data, so frontend teams can develop
+//! and write snapshot tests against it.
+//!
+//! Generated users are created through the regular create handler and
+//! therefore pass the same validation as real input.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::{extract::State, Json};
+use chrono::{DateTime, Utc};
+use unicode_normalization::UnicodeNormalization;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::handlers;
+use crate::models::{CreateUserRequest, User, UserStatus};
+use crate::responses::Created;
+use crate::{AppState, Config};
+
+/// The instant mock mode's clock is frozen at: 2024-01-01T00:00:00Z
+pub const MOCK_EPOCH: i64 = 1_704_067_200;
+
+/// Source of the current time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Clock {
+    /// The system clock
+    #[default]
+    System,
+    /// A clock that always reads the given instant
+    Fixed(DateTime<Utc>),
+}
+
+impl Clock {
+    /// Returns the current time according to this clock
+    pub fn now(&self) -> DateTime<Utc> {
+        match self {
+            Clock::System => Utc::now(),
+            Clock::Fixed(instant) => *instant,
+        }
+    }
+}
+
+/// Source of new user IDs
+#[derive(Debug, Default)]
+pub enum IdSource {
+    /// Random version 4 UUIDs
+    #[default]
+    Random,
+    /// Counting UUIDs (`00000000-0000-0000-0000-000000000001`, ...)
+    Sequential(AtomicU64),
+}
+
+impl IdSource {
+    /// Returns a sequential source whose first ID is 1
+    pub fn sequential() -> Self {
+        IdSource::Sequential(AtomicU64::new(1))
+    }
+
+    /// Returns the next ID
+    pub fn next_id(&self) -> Uuid {
+        match self {
+            IdSource::Random => Uuid::new_v4(),
+            IdSource::Sequential(next) => {
+                Uuid::from_u128(u128::from(next.fetch_add(1, Ordering::Relaxed)))
+            }
+        }
+    }
+}
+
+const FIRST_NAMES: &[&str] = &[
+    "Ada",
+    "Björn",
+    "Chloé",
+    "Dmitri",
+    "Elena",
+    "François",
+    "Grace",
+    "Hiroshi",
+    "Inés",
+    "Jonas",
+    "Kofi",
+    "Léa",
+    "Mateo",
+    "Nora",
+    "Oskar",
+    "Priya",
+    "Quentin",
+    "Renée",
+    "Søren",
+    "Tomás",
+    "Uma",
+    "Valentina",
+    "Wei",
+    "Yara",
+    "Zoë",
+];
+
+const LAST_NAMES: &[&str] = &[
+    "Andersen",
+    "Bauer",
+    "Castillo",
+    "Dubois",
+    "Evans",
+    "Fischer",
+    "García",
+    "Hughes",
+    "Ito",
+    "Jensen",
+    "Kowalski",
+    "López",
+    "Martin",
+    "Novak",
+    "O'Brien",
+    "Petrov",
+    "Quinn",
+    "Rossi",
+    "Schmidt",
+    "Tanaka",
+    "Umarov",
+    "Vidal",
+    "Weber",
+    "Yilmaz",
+    "Zimmermann",
+];
+
+const DOMAINS: &[&str] = &["example.com", "example.org", "example.net"];
+
+/// Locale, time zone and a phone number template per region; `#` is
+/// replaced by a digit. The templates stay inside ranges reserved for
+/// fiction (555-01xx in North America, 020 7946 0xxx in London).
+const REGIONS: &[(&str, &str, &str)] = &[
+    ("en-US", "America/New_York", "+1 212 555 01##"),
+    ("en-US", "America/Los_Angeles", "+1 415 555 01##"),
+    ("en-GB", "Europe/London", "+44 20 7946 0###"),
+    ("de-DE", "Europe/Berlin", "+49 30 ########"),
+    ("fr-FR", "Europe/Paris", "+33 6 ## ## ## ##"),
+    ("es-ES", "Europe/Madrid", "+34 91# ### ###"),
+];
+
+/// Seeded generator of realistic user creation requests
+///
+/// The same seed always yields the same sequence. Emails are unique within
+/// a sequence; phone numbers almost always are, and callers should skip
+/// the rare duplicate rejected by the create handler.
+#[derive(Debug, Clone)]
+pub struct FakeUsers {
+    state: u64,
+    index: usize,
+}
+
+impl FakeUsers {
+    /// Creates a generator from a seed
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: seed,
+            index: 0,
+        }
+    }
+
+    /// SplitMix64: small, fast and good enough for fake data
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len())]
+    }
+}
+
+impl Iterator for FakeUsers {
+    type Item = CreateUserRequest;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.index += 1;
+
+        let first = self.pick(FIRST_NAMES);
+        let last = self.pick(LAST_NAMES);
+        let domain = self.pick(DOMAINS);
+        let (locale, timezone, phone_template) = REGIONS[self.below(REGIONS.len())];
+
+        let email = format!(
+            "{}.{}.{}@{}",
+            ascii_slug(first),
+            ascii_slug(last),
+            self.index,
+            domain
+        );
+
+        // Most users have a phone number and a preferred locale
+        let phone = (self.below(4) != 0).then(|| {
+            phone_template
+                .chars()
+                .map(|c| match c {
+                    '#' => char::from(b'0' + self.below(10) as u8),
+                    c => c,
+                })
+                .collect()
+        });
+        let has_locale = self.below(5) != 0;
+        let status = if self.below(10) == 0 {
+            UserStatus::Pending
+        } else {
+            UserStatus::Active
+        };
+
+        Some(CreateUserRequest {
+            name: format!("{} {}", first, last),
+            email,
+            phone,
+            status,
+            locale: has_locale.then(|| locale.to_string()),
+            timezone: has_locale.then(|| timezone.to_string()),
+            custom: Default::default(),
+        })
+    }
+}
+
+/// Lowercase ASCII letters of a name, with accents stripped
+fn ascii_slug(name: &str) -> String {
+    name.nfd()
+        .filter(char::is_ascii_alphanumeric)
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+/// Creates up to `count` generated users through the create handler
+///
+/// Requests rejected by validation or uniqueness checks are skipped, so
+/// fewer than `count` users may be created.
+pub async fn seed(state: &AppState, seed: u64, count: usize) -> Vec<User> {
+    let mut created = Vec::with_capacity(count);
+    for request in FakeUsers::new(seed).take(count) {
+        let result: Result<_, ApiError> =
+            handlers::create_user(State(state.clone()), Json(request)).await;
+        if let Ok(Created { body, .. }) = result {
+            created.push(body.data);
+        }
+    }
+    created
+}
+
+/// Builds the application state for mock mode
+///
+/// The clock is frozen at [`MOCK_EPOCH`], IDs are sequential, and storage
+/// is seeded with `config.mock_users` users generated from
+/// `config.mock_seed`.
+pub async fn state(config: Config) -> AppState {
+    let (seed, count) = (config.mock_seed, config.mock_users);
+    let state = AppState {
+        clock: Clock::Fixed(DateTime::from_timestamp(MOCK_EPOCH, 0).unwrap_or_default()),
+        ids: std::sync::Arc::new(IdSource::sequential()),
+        ..AppState::with_config(config)
+    };
+    self::seed(&state, seed, count).await;
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fake_users_are_deterministic() {
+        let first: Vec<_> = FakeUsers::new(7).take(20).map(|u| u.email).collect();
+        let again: Vec<_> = FakeUsers::new(7).take(20).map(|u| u.email).collect();
+        let other: Vec<_> = FakeUsers::new(8).take(20).map(|u| u.email).collect();
+
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn test_ascii_slug() {
+        assert_eq!(ascii_slug("Zoë"), "zoe");
+        assert_eq!(ascii_slug("O'Brien"), "obrien");
+        assert_eq!(ascii_slug("Søren"), "sren");
+    }
+
+    #[test]
+    fn test_id_sources() {
+        let ids = IdSource::sequential();
+        assert_eq!(ids.next_id(), Uuid::from_u128(1));
+        assert_eq!(ids.next_id(), Uuid::from_u128(2));
+        assert_ne!(IdSource::Random.next_id(), IdSource::Random.next_id());
+    }
+
+    #[tokio::test]
+    async fn test_mock_state_is_reproducible() {
+        let config = Config {
+            mock_seed: 3,
+            mock_users: 40,
+            ..Config::default()
+        };
+        let first = state(config.clone()).await;
+        let second = state(config).await;
+
+        let mut users = first.storage.read().await.get_all();
+        users.sort_by_key(|user| user.id);
+        let mut again = second.storage.read().await.get_all();
+        again.sort_by_key(|user| user.id);
+
+        // Everything generated passes validation
+        assert_eq!(users.len(), 40);
+        assert_eq!(users, again);
+        assert_eq!(users[0].id, Uuid::from_u128(1));
+        assert!(users
+            .iter()
+            .all(|user| user.created_at.timestamp() == MOCK_EPOCH));
+    }
+}
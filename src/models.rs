@@ -5,11 +5,87 @@
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
+use crate::filter::Filter;
+use crate::rate_limit::RateLimit;
+use crate::validation::custom::CustomFields;
+use crate::validation::email::Canonicalization;
+
+/// Lifecycle status of a user account
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum UserStatus {
+    /// Created but not yet activated
+    Pending,
+    /// Normal, usable account
+    #[default]
+    Active,
+    /// Temporarily blocked by an administrator
+    Suspended,
+    /// Permanently closed; no further transitions are allowed
+    Deactivated,
+}
+
+impl UserStatus {
+    /// Returns whether an account may move from this status to `next`
+    ///
+    /// Allowed transitions:
+    ///
+    /// * `pending` → `active`, `deactivated`
+    /// * `active` → `suspended`, `deactivated`
+    /// * `suspended` → `active`, `deactivated`
+    pub fn can_transition_to(self, next: UserStatus) -> bool {
+        use UserStatus::*;
+
+        matches!(
+            (self, next),
+            (Pending, Active)
+                | (Pending, Deactivated)
+                | (Active, Suspended)
+                | (Active, Deactivated)
+                | (Suspended, Active)
+                | (Suspended, Deactivated)
+        )
+    }
+
+    /// Returns whether an account in this status may authenticate
+    ///
+    /// Only active accounts may log in; authentication must reject the
+    /// others, in particular suspended accounts.
+    pub fn can_log_in(self) -> bool {
+        self == UserStatus::Active
+    }
+
+    /// Returns the lowercase name used in JSON and messages
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UserStatus::Pending => "pending",
+            UserStatus::Active => "active",
+            UserStatus::Suspended => "suspended",
+            UserStatus::Deactivated => "deactivated",
+        }
+    }
+}
+
+impl std::fmt::Display for UserStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// Represents a user in the system
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+///
+/// Serialized with its [computed fields](crate::computed) when the current
+/// request asked for them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(remote = "Self")]
 pub struct User {
     /// Unique identifier for the user
     pub id: Uuid,
@@ -17,55 +93,686 @@ pub struct User {
     pub name: String,
     /// User's email address
     pub email: String,
+    /// User's phone number in E.164 format
+    pub phone: Option<String>,
+    /// Lifecycle status of the account
+    #[serde(default)]
+    pub status: UserStatus,
+    /// Preferred locale as a canonical BCP 47 language tag
+    #[serde(default)]
+    pub locale: Option<String>,
+    /// Preferred time zone as an IANA time zone name
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// Values of the deployment's custom fields
+    #[serde(
+        default,
+        skip_serializing_if = "CustomFields::is_empty",
+        serialize_with = "crate::field_case::verbatim"
+    )]
+    #[schema(value_type = Object)]
+    pub custom: CustomFields,
     /// Timestamp when the user was created
-    #[serde(with = "chrono::serde::ts_seconds")]
+    #[serde(with = "crate::timestamps")]
+    #[schema(schema_with = crate::timestamps::schema)]
     pub created_at: DateTime<Utc>,
     /// Timestamp when the user was last updated
-    #[serde(with = "chrono::serde::ts_seconds")]
+    #[serde(with = "crate::timestamps")]
+    #[schema(schema_with = crate::timestamps::schema)]
     pub updated_at: DateTime<Utc>,
+    /// Timestamp of the user's most recent successful login
+    #[serde(default, with = "crate::timestamps::option")]
+    #[schema(schema_with = crate::timestamps::option::schema)]
+    pub last_login_at: Option<DateTime<Utc>>,
+    /// Timestamp of the user's most recent authenticated request
+    #[serde(default, with = "crate::timestamps::option")]
+    #[schema(schema_with = crate::timestamps::option::schema)]
+    pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+impl Serialize for User {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match crate::computed::requested_at() {
+            Some(now) => crate::computed::serialize(self, now, serializer),
+            None => User::serialize(self, serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for User {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        User::deserialize(deserializer)
+    }
+}
+
+impl User {
+    /// Returns the time of the user's most recent activity
+    ///
+    /// Users that have never been seen count as active at creation time.
+    pub fn last_active_at(&self) -> DateTime<Utc> {
+        self.last_seen_at.unwrap_or(self.created_at)
+    }
+
+    /// Creates an active user with a new ID, created and last updated at
+    /// `at`
+    ///
+    /// The optional fields are unset; use the `with_` methods or struct
+    /// update syntax to fill them in.
+    pub fn new(name: impl Into<String>, email: impl Into<String>, at: DateTime<Utc>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name: name.into(),
+            email: email.into(),
+            phone: None,
+            status: UserStatus::Active,
+            locale: None,
+            timezone: None,
+            custom: Default::default(),
+            created_at: at,
+            updated_at: at,
+            last_login_at: None,
+            last_seen_at: None,
+        }
+    }
+
+    /// Sets the user's ID
+    pub fn with_id(mut self, id: Uuid) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Sets the user's phone number, which is stored as given
+    pub fn with_phone(mut self, phone: impl Into<String>) -> Self {
+        self.phone = Some(phone.into());
+        self
+    }
+
+    /// Sets the user's status
+    pub fn with_status(mut self, status: UserStatus) -> Self {
+        self.status = status;
+        self
+    }
 }
 
 /// Request payload for creating a new user
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 pub struct CreateUserRequest {
     /// User's full name
     pub name: String,
     /// User's email address
     pub email: String,
+    /// Optional phone number, in any format parseable to E.164
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>,
+    /// Initial status; only `pending` and `active` (the default) are accepted
+    #[serde(default)]
+    pub status: UserStatus,
+    /// Optional preferred locale (BCP 47 language tag)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    /// Optional preferred time zone (IANA name)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// Values of custom fields, checked against the deployment's schema
+    #[serde(default, skip_serializing_if = "CustomFields::is_empty")]
+    #[schema(value_type = Object)]
+    pub custom: CustomFields,
 }
 
 /// Request payload for updating an existing user
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 pub struct UpdateUserRequest {
     /// Optional new name for the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
     /// Optional new email for the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub email: Option<String>,
+    /// Optional new phone number; an explicit `null` removes the phone number
+    #[serde(
+        default,
+        deserialize_with = "deserialize_explicit_null",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub phone: Option<Option<String>>,
+    /// Optional new locale; an explicit `null` removes the preference
+    #[serde(
+        default,
+        deserialize_with = "deserialize_explicit_null",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub locale: Option<Option<String>>,
+    /// Optional new time zone; an explicit `null` removes the preference
+    #[serde(
+        default,
+        deserialize_with = "deserialize_explicit_null",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timezone: Option<Option<String>>,
+    /// Custom field values to set; an explicit `null` removes a value and
+    /// omitted fields remain unchanged
+    #[serde(default, skip_serializing_if = "CustomFields::is_empty")]
+    #[schema(value_type = Object)]
+    pub custom: CustomFields,
 }
 
-/// Response wrapper for user data
-#[derive(Debug, Serialize)]
-pub struct UserResponse {
-    /// The user data
-    pub user: User,
+/// Query parameters for listing users
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListUsersQuery {
+    /// Only return users matching this filter expression, such as
+    /// `name eq 'John' and created_at gt 2024-01-01`
+    pub filter: Option<String>,
+    /// Only return the user with this phone number (any parseable format)
+    pub phone: Option<String>,
+    /// Only return users with this status
+    pub status: Option<UserStatus>,
+    /// Only return users with no activity since this time, given as
+    /// RFC 3339 or Unix seconds
+    #[serde(default, deserialize_with = "deserialize_timestamp")]
+    #[param(value_type = Option<String>)]
+    pub inactive_since: Option<DateTime<Utc>>,
+    /// Return this page of the matching users, starting at 1, ordered by
+    /// creation time
+    pub page: Option<usize>,
+    /// Number of users per page, up to 1000; 50 if only `page` is given
+    pub per_page: Option<usize>,
 }
 
-/// Response wrapper for a list of users
-#[derive(Debug, Serialize)]
-pub struct UsersResponse {
-    /// List of users
-    pub users: Vec<User>,
-    /// Total count of users
+/// Query parameters for generating fake users
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GenerateUsersQuery {
+    /// Number of users to generate
+    #[serde(default = "GenerateUsersQuery::default_count")]
     pub count: usize,
+    /// Seed for reproducible output; random when omitted
+    pub seed: Option<u64>,
+}
+
+impl GenerateUsersQuery {
+    /// Upper bound on `count` for a single request
+    pub const MAX_COUNT: usize = 10_000;
+
+    fn default_count() -> usize {
+        10
+    }
+}
+
+impl Default for GenerateUsersQuery {
+    fn default() -> Self {
+        Self {
+            count: Self::default_count(),
+            seed: None,
+        }
+    }
+}
+
+/// Deserializes an optional timestamp given either as RFC 3339 or as
+/// Unix seconds, matching the format used in responses
+fn deserialize_timestamp<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let Some(value) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    if let Ok(seconds) = value.parse::<i64>() {
+        return DateTime::from_timestamp(seconds, 0)
+            .map(Some)
+            .ok_or_else(|| D::Error::custom("timestamp out of range"));
+    }
+
+    DateTime::parse_from_rfc3339(&value)
+        .map(|timestamp| Some(timestamp.with_timezone(&Utc)))
+        .map_err(|_| D::Error::custom("expected RFC 3339 timestamp or Unix seconds"))
+}
+
+/// Deserializes a field so that an absent value (`None`) can be told apart
+/// from an explicit `null` (`Some(None)`)
+fn deserialize_explicit_null<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Active log filter, used both as request and response payload
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LogLevel {
+    /// Filter directives in `RUST_LOG` syntax, e.g. `rust_api::handlers=trace,info`
+    pub filter: String,
+}
+
+/// Where a client address is located
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Location {
+    /// ISO 3166-1 alpha-2 country code
+    pub country: Option<String>,
+    /// City name in English
+    pub city: Option<String>,
+}
+
+/// A recorded state-changing request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct AuditEntry {
+    /// Sequence number, increasing with every entry
+    pub id: u64,
+    /// When the request was handled
+    #[serde(with = "crate::timestamps")]
+    #[schema(schema_with = crate::timestamps::schema)]
+    pub at: DateTime<Utc>,
+    /// Authenticated caller; absent when authentication is disabled
+    pub principal: Option<String>,
+    /// Administrator acting as `principal` through an impersonation token
+    pub impersonator: Option<String>,
+    /// HTTP method
+    pub method: String,
+    /// Request path
+    pub path: String,
+    /// Response status code
+    pub status: u16,
+    /// Client address, resolved through trusted proxies
+    #[schema(value_type = Option<String>)]
+    pub client_ip: Option<IpAddr>,
+    /// Location of the client address, if a GeoIP database is configured
+    pub location: Option<Location>,
+}
+
+/// Query parameters for reviewing the audit log
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    /// Only return entries made by this principal
+    pub principal: Option<String>,
+    /// Only return entries from this country (ISO 3166-1 alpha-2 code)
+    pub country: Option<String>,
+    /// Only return entries made at or after this time, given as RFC 3339
+    /// or Unix seconds
+    #[serde(default, deserialize_with = "deserialize_timestamp")]
+    #[param(value_type = Option<String>)]
+    pub since: Option<DateTime<Utc>>,
+    /// Maximum number of entries to return, newest first (default 100)
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    /// Upper bound on `limit`
+    pub const MAX_LIMIT: usize = 1_000;
+
+    /// Returns the effective limit
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(100).min(Self::MAX_LIMIT)
+    }
+}
+
+/// An issued impersonation token
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Impersonation {
+    /// Bearer token acting as the user
+    pub token: String,
+    /// The impersonated user
+    pub user_id: Uuid,
+    /// The administrator the token was issued to
+    pub impersonator: String,
+    /// When the token stops being accepted
+    #[serde(with = "crate::timestamps")]
+    #[schema(schema_with = crate::timestamps::schema)]
+    pub expires_at: DateTime<Utc>,
+}
+
+/// How duplicate accounts are recognized
+///
+/// See [`crate::duplicates`] for the keys each strategy compares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateStrategy {
+    /// Same email address, ignoring case and `+tag` suffixes
+    Email,
+    /// Same words in the name, ignoring order, case and diacritics
+    Name,
+}
+
+impl FromStr for DuplicateStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "email" => Ok(DuplicateStrategy::Email),
+            "name" => Ok(DuplicateStrategy::Name),
+            other => Err(format!(
+                "unknown duplicate strategy '{}' (expected email or name)",
+                other
+            )),
+        }
+    }
+}
+
+/// Query parameters for finding duplicate users
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DuplicatesQuery {
+    /// Only group by this strategy instead of all configured ones
+    pub strategy: Option<DuplicateStrategy>,
+}
+
+/// Users sharing a key under one strategy
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DuplicateGroup {
+    /// The strategy that grouped the users
+    pub strategy: DuplicateStrategy,
+    /// The key the users share
+    pub key: String,
+    /// The users, oldest first
+    #[schema(value_type = Vec<User>)]
+    pub users: Vec<Arc<User>>,
+}
+
+/// Which of two merged users a field is taken from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeSource {
+    /// The user that remains
+    #[default]
+    Keep,
+    /// The user merged into it
+    Remove,
+}
+
+/// Per-field choice of the merged user's values
+///
+/// Optional fields fall back to the other user when the chosen one has no
+/// value. Omitted fields are taken from the kept user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct MergePrecedence {
+    /// Source of the name
+    pub name: MergeSource,
+    /// Source of the email address
+    pub email: MergeSource,
+    /// Source of the phone number
+    pub phone: MergeSource,
+    /// Source of the lifecycle status
+    pub status: MergeSource,
+    /// Source of the locale
+    pub locale: MergeSource,
+    /// Source of the time zone
+    pub timezone: MergeSource,
+}
+
+/// Request payload for merging two users
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MergeUsersRequest {
+    /// The user that remains
+    pub keep: Uuid,
+    /// The user merged into `keep` and deleted
+    pub remove: Uuid,
+    /// Which user each field is taken from
+    #[serde(default)]
+    pub precedence: MergePrecedence,
+}
+
+/// A deleted user kept in the trash
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TrashedUser {
+    /// The user as it was when deleted
+    #[schema(value_type = User)]
+    pub user: Arc<User>,
+    /// When the user was deleted
+    #[serde(with = "crate::timestamps")]
+    #[schema(schema_with = crate::timestamps::schema)]
+    pub deleted_at: DateTime<Utc>,
+    /// The principal that deleted the user, when authentication is enabled
+    pub deleted_by: Option<String>,
+}
+
+/// Request body for restoring deleted users in bulk
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RestoreUsersRequest {
+    /// IDs of the users to restore
+    pub ids: Vec<Uuid>,
+}
+
+/// Request body for creating users in bulk
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ImportUsersRequest {
+    /// The users to create, each as for `POST /api/v1/users`
+    pub users: Vec<CreateUserRequest>,
+}
+
+impl ImportUsersRequest {
+    /// Upper bound on `users` for a single request
+    pub const MAX_USERS: usize = 10_000;
+}
+
+/// A user an import could not create
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ImportFailure {
+    /// Position of the user in the request
+    pub index: usize,
+    /// Why the user was not created, in the language of the import request
+    pub error: String,
+}
+
+/// Outcome of an import
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ImportReport {
+    /// Number of users created
+    pub created: usize,
+    /// Users that were not created, in request order
+    pub failed: Vec<ImportFailure>,
+}
+
+/// Record of a user that no longer exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Tombstone {
+    /// The user this one was merged into, if it was merged
+    pub merged_into: Option<Uuid>,
+    /// When the user was removed
+    #[schema(value_type = String, format = DateTime)]
+    pub at: DateTime<Utc>,
+}
+
+/// Approximate memory footprint of the user store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct StorageUsage {
+    /// Number of stored users
+    pub users: usize,
+    /// Configured maximum number of users, if any
+    pub max_users: Option<usize>,
+    /// Size of all users serialized as JSON
+    pub serialized_bytes: u64,
+    /// Memory reserved by the tables indexing users by ID
+    pub index_bytes: u64,
+}
+
+impl StorageUsage {
+    /// Share of [`StorageUsage::max_users`] in use, if a limit is set
+    pub fn fill_ratio(&self) -> Option<f64> {
+        self.max_users
+            .map(|max_users| self.users as f64 / max_users.max(1) as f64)
+    }
+}
+
+/// Response of the deep health check
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct HealthReport {
+    /// Worst status of the registered health checks; `degraded` when the
+    /// user store is nearly full
+    pub status: crate::health::HealthStatus,
+    /// Service name
+    pub service: String,
+    /// When the report was made
+    #[serde(with = "crate::timestamps")]
+    #[schema(schema_with = crate::timestamps::schema)]
+    pub timestamp: DateTime<Utc>,
+    /// Memory used by the user store
+    pub storage: StorageUsage,
+}
+
+/// What happens when a user is created in a full store
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Eviction {
+    /// The new user is refused
+    #[default]
+    Reject,
+    /// The least recently used user is deleted to make room
+    Lru,
+}
+
+impl FromStr for Eviction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "reject" => Ok(Eviction::Reject),
+            "lru" => Ok(Eviction::Lru),
+            other => Err(format!(
+                "unknown eviction mode '{}' (expected reject or lru)",
+                other
+            )),
+        }
+    }
+}
+
+/// Upper bound on the number of stored users
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capacity {
+    /// Maximum number of users; unbounded when `None`
+    pub max_users: Option<usize>,
+    /// What happens when the limit is reached
+    pub eviction: Eviction,
+}
+
+/// Full contents of the storage at one point in time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Snapshot {
+    /// When the snapshot was taken
+    #[serde(with = "crate::timestamps")]
+    #[schema(schema_with = crate::timestamps::schema)]
+    pub taken_at: DateTime<Utc>,
+    /// Stored users, ordered by ID
+    pub users: Vec<User>,
+    /// Deleted users in the trash, most recently deleted first
+    pub trash: Vec<TrashedUser>,
+    /// Tombstones of removed users, by former ID
+    pub tombstones: BTreeMap<Uuid, Tombstone>,
+    /// Rate limits overriding the default, by principal
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rate_limits: BTreeMap<String, RateLimit>,
+}
+
+/// Everything stored under one user ID
+///
+/// Applying a record to another storage makes it hold the same for that
+/// ID, whether the user exists, was deleted or merged away.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct UserRecord {
+    /// The user ID
+    pub id: Uuid,
+    /// The user, if it exists
+    pub user: Option<User>,
+    /// The user in the trash, if it was deleted
+    pub trashed: Option<TrashedUser>,
+    /// The tombstone left by the user, if it was removed
+    pub tombstone: Option<Tombstone>,
+}
+
+/// Why a storage operation was refused
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum StorageError {
+    /// No user has the ID
+    #[error("no user with ID {0}")]
+    NotFound(Uuid),
+    /// A user with the ID exists already
+    #[error("a user with ID {0} already exists")]
+    DuplicateId(Uuid),
+    /// The email address, or a homograph or alias of it, belongs to
+    /// another user
+    #[error("email {email} belongs to user {holder}")]
+    DuplicateEmail {
+        /// The address that was refused
+        email: String,
+        /// The user holding it
+        holder: Uuid,
+    },
+    /// The phone number belongs to another user
+    #[error("phone {phone} belongs to user {holder}")]
+    DuplicatePhone {
+        /// The number that was refused
+        phone: String,
+        /// The user holding it
+        holder: Uuid,
+    },
+    /// The user changed since the caller read it
+    #[error("user {id} is at revision {current}, not {expected}")]
+    Conflict {
+        /// The user's ID
+        id: Uuid,
+        /// The revision the caller read
+        expected: u64,
+        /// The user's revision now
+        current: u64,
+    },
+}
+
+/// Returned by [`Storage::make_room`] when the store is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageFull {
+    /// The configured limit
+    pub max_users: usize,
 }
 
 /// In-memory storage for users
 ///
 /// In a production environment, this would be replaced with
 /// a proper database connection pool.
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct Storage {
-    users: HashMap<Uuid, User>,
+    /// Shared so that listing hands out references instead of copies
+    users: HashMap<Uuid, Arc<User>>,
+    /// Random per instance, so versions are never reused across restarts
+    epoch: u64,
+    /// Bumped by every mutation
+    version: u64,
+    capacity: Capacity,
+    /// Tick of each user's last access, for least-recently-used eviction
+    accessed: HashMap<Uuid, AtomicU64>,
+    /// Source of access ticks; atomic so reads can record accesses under a
+    /// shared lock
+    ticks: AtomicU64,
+    /// Users that were removed, by former ID
+    tombstones: HashMap<Uuid, Tombstone>,
+    /// Deleted users, kept so they can be restored
+    trash: HashMap<Uuid, TrashedUser>,
+    /// How emails are folded when looking for duplicates
+    canonicalization: Canonicalization,
+    /// Collection version of each user's last write
+    revisions: HashMap<Uuid, u64>,
+    /// Rate limits overriding the default, by principal
+    rate_limits: BTreeMap<String, RateLimit>,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self {
+            users: HashMap::new(),
+            epoch: Uuid::new_v4().as_u64_pair().0,
+            version: 0,
+            capacity: Capacity::default(),
+            accessed: HashMap::new(),
+            ticks: AtomicU64::new(0),
+            tombstones: HashMap::new(),
+            trash: HashMap::new(),
+            canonicalization: Canonicalization::default(),
+            revisions: HashMap::new(),
+            rate_limits: BTreeMap::new(),
+        }
+    }
 }
 
 impl Storage {
@@ -74,11 +781,249 @@ impl Storage {
         Self::default()
     }
 
+    /// Creates a new empty storage instance bounded by `capacity`
+    pub fn with_capacity(capacity: Capacity) -> Self {
+        Self {
+            capacity,
+            ..Self::default()
+        }
+    }
+
+    /// Sets how emails are folded when looking for duplicates
+    pub fn with_email_canonicalization(mut self, canonicalization: Canonicalization) -> Self {
+        self.canonicalization = canonicalization;
+        self
+    }
+
+    /// Returns the key under which an email address must be unique
+    pub fn email_key(&self, email: &str) -> String {
+        self.canonicalization.duplicate_key(email)
+    }
+
+    /// Returns the number of stored users
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    /// Returns `true` if no users are stored
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    /// Estimates the memory used by the store
+    ///
+    /// Serializes every user, so the cost grows with the store; meant for
+    /// metrics scrapes and health checks rather than request paths.
+    pub fn usage(&self) -> StorageUsage {
+        let serialized_bytes = self
+            .users
+            .values()
+            .map(|user| {
+                let mut counter = ByteCounter(0);
+                // Writing to a counter cannot fail
+                let _ = serde_json::to_writer(&mut counter, user);
+                counter.0
+            })
+            .sum();
+        let entry = |value: usize| (std::mem::size_of::<Uuid>() + value) as u64;
+        StorageUsage {
+            users: self.users.len(),
+            max_users: self.capacity.max_users,
+            serialized_bytes,
+            index_bytes: self.users.capacity() as u64 * entry(std::mem::size_of::<Arc<User>>())
+                + self.accessed.capacity() as u64 * entry(std::mem::size_of::<AtomicU64>()),
+        }
+    }
+
+    /// Checks that [`Storage::make_room`] would succeed for `count` new
+    /// users, without evicting anyone
+    pub fn check_room(&self, count: usize) -> Result<(), StorageFull> {
+        let Some(max_users) = self.capacity.max_users else {
+            return Ok(());
+        };
+        let fits = self.users.len() + count <= max_users;
+        if count > max_users || (!fits && self.capacity.eviction == Eviction::Reject) {
+            return Err(StorageFull { max_users });
+        }
+        Ok(())
+    }
+
+    /// Frees slots for `count` new users if the store is too full
+    ///
+    /// In [`Eviction::Reject`] mode a store without enough room is an error.
+    /// In [`Eviction::Lru`] mode the least recently read or updated users
+    /// are deleted instead, and returned so callers can announce the
+    /// deletion; asking for more slots than the limit is still an error.
+    pub fn make_room(&mut self, count: usize) -> Result<Vec<Arc<User>>, StorageFull> {
+        let Some(max_users) = self.capacity.max_users else {
+            return Ok(Vec::new());
+        };
+        if self.users.len() + count <= max_users {
+            return Ok(Vec::new());
+        }
+        if self.capacity.eviction == Eviction::Reject || count > max_users {
+            return Err(StorageFull { max_users });
+        }
+
+        let mut evicted = Vec::new();
+        while self.users.len() + count > max_users {
+            let Some(id) = self
+                .accessed
+                .iter()
+                .min_by_key(|(_, tick)| tick.load(Ordering::Relaxed))
+                .map(|(id, _)| *id)
+            else {
+                break;
+            };
+            self.accessed.remove(&id);
+            self.revisions.remove(&id);
+            if let Some(user) = self.users.remove(&id) {
+                self.version += 1;
+                evicted.push(user);
+            }
+        }
+        Ok(evicted)
+    }
+
+    /// Marks a user as just used
+    fn touch(&self, id: &Uuid) {
+        if let Some(tick) = self.accessed.get(id) {
+            tick.store(
+                self.ticks.fetch_add(1, Ordering::Relaxed) + 1,
+                Ordering::Relaxed,
+            );
+        }
+    }
+
+    /// Returns the collection version
+    ///
+    /// The pair of the instance's random epoch and a counter bumped by
+    /// every mutation, so equal versions mean identical contents.
+    pub fn version(&self) -> (u64, u64) {
+        (self.epoch, self.version)
+    }
+
+    /// Copies the users, the trash and the tombstones
+    pub fn snapshot(&self, taken_at: DateTime<Utc>) -> Snapshot {
+        let mut users: Vec<User> = self.users.values().map(|user| User::clone(user)).collect();
+        users.sort_by_key(|user| user.id);
+        Snapshot {
+            taken_at,
+            users,
+            trash: self.trash(),
+            tombstones: self.tombstones.clone().into_iter().collect(),
+            rate_limits: self.rate_limits.clone(),
+        }
+    }
+
+    /// Replaces all contents with those of `snapshot`
+    ///
+    /// Access history used for eviction starts over. The storage is left
+    /// unchanged if the snapshot holds more users than the capacity allows.
+    pub fn load(&mut self, snapshot: Snapshot) -> Result<(), StorageFull> {
+        if let Some(max_users) = self.capacity.max_users {
+            if snapshot.users.len() > max_users {
+                return Err(StorageFull { max_users });
+            }
+        }
+        self.users.clear();
+        self.accessed.clear();
+        self.revisions.clear();
+        for user in snapshot.users {
+            self.accessed.insert(user.id, AtomicU64::new(0));
+            self.revisions.insert(user.id, self.version + 1);
+            self.users.insert(user.id, Arc::new(user));
+        }
+        self.trash = snapshot
+            .trash
+            .into_iter()
+            .map(|trashed| (trashed.user.id, trashed))
+            .collect();
+        self.tombstones = snapshot.tombstones.into_iter().collect();
+        self.rate_limits = snapshot.rate_limits;
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Returns the rate limit overriding the default for `principal`
+    pub fn rate_limit(&self, principal: &str) -> Option<RateLimit> {
+        self.rate_limits.get(principal).copied()
+    }
+
+    /// Returns every rate limit override, by principal
+    pub fn rate_limits(&self) -> &BTreeMap<String, RateLimit> {
+        &self.rate_limits
+    }
+
+    /// Overrides the default rate limit for `principal`, returning the
+    /// previous override
+    ///
+    /// Overrides are not users, so the storage version is unchanged.
+    pub fn set_rate_limit(&mut self, principal: String, limit: RateLimit) -> Option<RateLimit> {
+        self.rate_limits.insert(principal, limit)
+    }
+
+    /// Removes the rate limit override of `principal`, returning it
+    pub fn remove_rate_limit(&mut self, principal: &str) -> Option<RateLimit> {
+        self.rate_limits.remove(principal)
+    }
+
+    /// Returns everything stored under `id`
+    pub fn record(&self, id: &Uuid) -> UserRecord {
+        UserRecord {
+            id: *id,
+            user: self.users.get(id).map(|user| User::clone(user)),
+            trashed: self.trash.get(id).cloned(),
+            tombstone: self.tombstones.get(id).copied(),
+        }
+    }
+
+    /// Makes the storage hold exactly `record` under its ID
+    ///
+    /// Like [`Storage::load`], this bypasses the capacity limit's eviction:
+    /// the records come from a storage that enforced it already.
+    pub fn apply(&mut self, record: UserRecord) {
+        let id = record.id;
+        match record.user {
+            Some(user) => {
+                self.accessed.entry(id).or_insert_with(|| AtomicU64::new(0));
+                self.revisions.insert(id, self.version + 1);
+                self.users.insert(id, Arc::new(user));
+            }
+            None => {
+                self.users.remove(&id);
+                self.accessed.remove(&id);
+                self.revisions.remove(&id);
+            }
+        }
+        match record.trashed {
+            Some(trashed) => self.trash.insert(id, trashed),
+            None => self.trash.remove(&id),
+        };
+        match record.tombstone {
+            Some(tombstone) => self.tombstones.insert(id, tombstone),
+            None => self.tombstones.remove(&id),
+        };
+        self.version += 1;
+    }
+
     /// Retrieves all users from storage
-    pub fn get_all(&self) -> Vec<User> {
+    ///
+    /// The users are shared with the storage rather than copied; updates
+    /// made afterwards are not visible through them.
+    pub fn get_all(&self) -> Vec<Arc<User>> {
         self.users.values().cloned().collect()
     }
 
+    /// Retrieves the users passing `filter`, shared like [`Storage::get_all`]
+    pub fn filter(&self, filter: &Filter) -> Vec<Arc<User>> {
+        self.users
+            .values()
+            .filter(|user| filter.matches(user))
+            .cloned()
+            .collect()
+    }
+
     /// Retrieves a user by ID
     ///
     /// # Arguments
@@ -89,7 +1034,8 @@ impl Storage {
     ///
     /// Returns `Some(User)` if found, `None` otherwise
     pub fn get(&self, id: &Uuid) -> Option<User> {
-        self.users.get(id).cloned()
+        self.touch(id);
+        self.users.get(id).map(|user| User::clone(user))
     }
 
     /// Creates a new user in storage
@@ -98,16 +1044,20 @@ impl Storage {
     ///
     /// * `user` - The user to store
     ///
-    /// # Returns
+    /// # Errors
     ///
-    /// Returns `true` if the user was created, `false` if a user
-    /// with the same ID already exists
-    pub fn create(&mut self, user: User) -> bool {
+    /// Returns [`StorageError::DuplicateId`] if a user with the same ID
+    /// already exists
+    pub fn create(&mut self, user: User) -> Result<(), StorageError> {
         if self.users.contains_key(&user.id) {
-            return false;
+            return Err(StorageError::DuplicateId(user.id));
         }
-        self.users.insert(user.id, user);
-        true
+        self.accessed.insert(user.id, AtomicU64::new(0));
+        self.touch(&user.id);
+        self.version += 1;
+        self.revisions.insert(user.id, self.version);
+        self.users.insert(user.id, Arc::new(user));
+        Ok(())
     }
 
     /// Updates an existing user in storage
@@ -119,17 +1069,167 @@ impl Storage {
     ///
     /// # Returns
     ///
-    /// Returns `true` if the user was updated, `false` if not found
-    pub fn update<F>(&mut self, id: &Uuid, updater: F) -> bool
+    /// Returns the updated user
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::NotFound`] if no user has the ID
+    pub fn update<F>(&mut self, id: &Uuid, updater: F) -> Result<&User, StorageError>
+    where
+        F: FnOnce(&mut User),
+    {
+        let Some(user) = self.users.get_mut(id) else {
+            return Err(StorageError::NotFound(*id));
+        };
+        // Copies the user only while a listing still holds it
+        updater(Arc::make_mut(user));
+        self.version += 1;
+        self.revisions.insert(*id, self.version);
+        self.touch(id);
+        self.users
+            .get(id)
+            .map(|user| &**user)
+            .ok_or(StorageError::NotFound(*id))
+    }
+
+    /// Returns the revision of a user: the collection version counter
+    /// at its last write
+    ///
+    /// Revisions only grow, so a client holding the revision it read can
+    /// tell whether the user changed since.
+    pub fn revision(&self, id: &Uuid) -> Option<u64> {
+        self.users.get(id).and(self.revisions.get(id).copied())
+    }
+
+    /// Updates a user only if it is still at the revision the caller read
+    ///
+    /// Reading a user and writing it back are separate requests, so
+    /// another writer may have changed it in between; comparing revisions
+    /// under the same exclusive borrow as the write keeps that change from
+    /// being silently overwritten.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The UUID of the user to update
+    /// * `expected` - The [`revision`](Storage::revision) the caller read
+    /// * `updater` - A closure that receives a mutable reference to the user
+    ///
+    /// # Returns
+    ///
+    /// Returns the updated user
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::NotFound`] if no user has the ID, or
+    /// [`StorageError::Conflict`] with the current revision if it no longer
+    /// matches
+    pub fn compare_and_update<F>(
+        &mut self,
+        id: &Uuid,
+        expected: u64,
+        updater: F,
+    ) -> Result<&User, StorageError>
     where
         F: FnOnce(&mut User),
     {
-        if let Some(user) = self.users.get_mut(id) {
-            updater(user);
-            true
-        } else {
-            false
+        let current = self.revision(id).ok_or(StorageError::NotFound(*id))?;
+        if current != expected {
+            return Err(StorageError::Conflict {
+                id: *id,
+                expected,
+                current,
+            });
+        }
+        self.update(id, updater)
+    }
+
+    /// Checks that no other user holds the email address or phone number
+    /// of `user`
+    ///
+    /// A stored user with the same ID is `user` itself and never conflicts.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::DuplicateEmail`] or
+    /// [`StorageError::DuplicatePhone`] naming the user holding the value
+    pub fn check_claims(&self, user: &User) -> Result<(), StorageError> {
+        let key = self.email_key(&user.email);
+        for other in self.users.values().filter(|other| other.id != user.id) {
+            if other.email == user.email || self.email_key(&other.email) == key {
+                return Err(StorageError::DuplicateEmail {
+                    email: user.email.clone(),
+                    holder: other.id,
+                });
+            }
+            if let Some(phone) = user
+                .phone
+                .as_ref()
+                .filter(|phone| other.phone.as_ref() == Some(*phone))
+            {
+                return Err(StorageError::DuplicatePhone {
+                    phone: phone.clone(),
+                    holder: other.id,
+                });
+            }
         }
+        Ok(())
+    }
+
+    /// Creates a user unless its ID, email address or phone number is
+    /// taken
+    ///
+    /// Checking and inserting happen under the same exclusive borrow, so
+    /// two concurrent creations with the same email address cannot both
+    /// succeed.
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - The user to store
+    ///
+    /// # Errors
+    ///
+    /// Returns which unique value is already taken
+    pub fn create_if_email_free(&mut self, user: User) -> Result<(), StorageError> {
+        if self.users.contains_key(&user.id) {
+            return Err(StorageError::DuplicateId(user.id));
+        }
+        self.check_claims(&user)?;
+        self.create(user)
+    }
+
+    /// Updates a user unless the change gives it an email address or phone
+    /// number another user holds
+    ///
+    /// `updater` is applied to a copy, which only replaces the stored user
+    /// once its claims are checked; a refused change leaves the user as it
+    /// was.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The UUID of the user to update
+    /// * `updater` - A closure that receives a mutable reference to the user
+    ///
+    /// # Returns
+    ///
+    /// Returns the updated user
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::NotFound`] if no user has the ID, or which
+    /// unique value is already taken
+    pub fn update_with_email_claim<F>(
+        &mut self,
+        id: &Uuid,
+        updater: F,
+    ) -> Result<&User, StorageError>
+    where
+        F: FnOnce(&mut User),
+    {
+        let mut user = self.get(id).ok_or(StorageError::NotFound(*id))?;
+        updater(&mut user);
+        user.id = *id;
+        self.check_claims(&user)?;
+        self.update(id, |stored| *stored = user)
     }
 
     /// Deletes a user from storage
@@ -138,24 +1238,220 @@ impl Storage {
     ///
     /// * `id` - The UUID of the user to delete
     ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::NotFound`] if no user has the ID
+    pub fn delete(&mut self, id: &Uuid) -> Result<(), StorageError> {
+        self.users.remove(id).ok_or(StorageError::NotFound(*id))?;
+        self.accessed.remove(id);
+        self.revisions.remove(id);
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Records a successful login for a user
+    ///
+    /// Called by [`crate::auth::record_activity`] for a user's first request
+    /// after [`crate::auth::LOGIN_IDLE`] of inactivity; a login also counts
+    /// as activity, so `last_seen_at` is updated as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::NotFound`] if no user has the ID
+    pub fn record_login(&mut self, id: &Uuid, at: DateTime<Utc>) -> Result<&User, StorageError> {
+        self.update(id, |user| {
+            user.last_login_at = Some(at);
+            user.last_seen_at = Some(at);
+        })
+    }
+
+    /// Records an authenticated request made by a user
+    ///
+    /// Called by [`crate::auth::record_activity`] on the user's other
+    /// requests, once `last_seen_at` is [`crate::auth::SEEN_GRANULARITY`]
+    /// old.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::NotFound`] if no user has the ID
+    pub fn record_seen(&mut self, id: &Uuid, at: DateTime<Utc>) -> Result<&User, StorageError> {
+        self.update(id, |user| user.last_seen_at = Some(at))
+    }
+
+    /// Replaces a user with its merge with `remove`, deleting `remove`
+    ///
+    /// `merged` must carry the ID of the user that remains. A tombstone
+    /// pointing at it is left for `remove`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::NotFound`] if either user is missing
+    pub fn merge(
+        &mut self,
+        merged: User,
+        remove: &Uuid,
+        at: DateTime<Utc>,
+    ) -> Result<(), StorageError> {
+        for id in [&merged.id, remove] {
+            if !self.users.contains_key(id) {
+                return Err(StorageError::NotFound(*id));
+            }
+        }
+        let keep = merged.id;
+        self.delete(remove)?;
+        self.update(&keep, |user| *user = merged)?;
+        self.tombstones.insert(
+            *remove,
+            Tombstone {
+                merged_into: Some(keep),
+                at,
+            },
+        );
+        Ok(())
+    }
+
+    /// Deletes a user, moving it to the trash and leaving a tombstone
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The UUID of the user to delete
+    /// * `at` - When the user is deleted
+    /// * `by` - The principal deleting the user, if authenticated
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::NotFound`] if no user has the ID
+    pub fn remove(
+        &mut self,
+        id: &Uuid,
+        at: DateTime<Utc>,
+        by: Option<String>,
+    ) -> Result<(), StorageError> {
+        let user = self
+            .users
+            .get(id)
+            .cloned()
+            .ok_or(StorageError::NotFound(*id))?;
+        self.delete(id)?;
+        self.trash.insert(
+            *id,
+            TrashedUser {
+                user,
+                deleted_at: at,
+                deleted_by: by,
+            },
+        );
+        self.tombstones.insert(
+            *id,
+            Tombstone {
+                merged_into: None,
+                at,
+            },
+        );
+        Ok(())
+    }
+
+    /// Returns a deleted user from the trash
+    pub fn trashed(&self, id: &Uuid) -> Option<TrashedUser> {
+        self.trash.get(id).cloned()
+    }
+
+    /// Returns all deleted users in the trash, most recently deleted first
+    pub fn trash(&self) -> Vec<TrashedUser> {
+        let mut trash: Vec<TrashedUser> = self.trash.values().cloned().collect();
+        trash.sort_by_key(|trashed| std::cmp::Reverse(trashed.deleted_at));
+        trash
+    }
+
+    /// Moves a deleted user back from the trash
+    ///
     /// # Returns
     ///
-    /// Returns `true` if the user was deleted, `false` if not found
-    pub fn delete(&mut self, id: &Uuid) -> bool {
-        self.users.remove(id).is_some()
+    /// Returns the restored user
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::NotFound`] if the user is not in the trash,
+    /// or [`StorageError::DuplicateId`] if its ID was taken since, leaving
+    /// it in the trash
+    pub fn restore(&mut self, id: &Uuid) -> Result<User, StorageError> {
+        let trashed = self.trash.get(id).ok_or(StorageError::NotFound(*id))?;
+        let user = User::clone(&trashed.user);
+        self.create(user.clone())?;
+        self.trash.remove(id);
+        self.tombstones.remove(id);
+        Ok(user)
+    }
+
+    /// Returns the tombstone left by a removed user
+    pub fn tombstone(&self, id: &Uuid) -> Option<Tombstone> {
+        self.tombstones.get(id).copied()
     }
 
     /// Checks if a user with the given email exists
     ///
     /// # Arguments
     ///
-    /// * `email` - The email address to check
+    /// * `email` - The normalized email address to check
     ///
     /// # Returns
     ///
-    /// Returns `true` if a user with this email exists, `false` otherwise
+    /// Returns `true` if a user with this email, or a homograph or alias of
+    /// it, exists, `false` otherwise
     pub fn email_exists(&self, email: &str) -> bool {
-        self.users.values().any(|user| user.email == email)
+        self.find_by_email(email).is_some()
+    }
+
+    /// Finds the user with the given email address
+    ///
+    /// Addresses match when they share their
+    /// [`email_key`](Storage::email_key), so a look-alike spelling or, with
+    /// a [`Canonicalization`] other than `Exact`, an alias finds the user
+    /// as well.
+    ///
+    /// # Arguments
+    ///
+    /// * `email` - The normalized email address to look up
+    ///
+    /// # Returns
+    ///
+    /// Returns the ID of the user with this email address, if any
+    pub fn find_by_email(&self, email: &str) -> Option<Uuid> {
+        let key = self.email_key(email);
+        self.users
+            .values()
+            .find(|user| user.email == email || self.email_key(&user.email) == key)
+            .map(|user| user.id)
+    }
+
+    /// Checks if a user with the given phone number exists
+    ///
+    /// # Arguments
+    ///
+    /// * `phone` - The E.164 phone number to check
+    ///
+    /// # Returns
+    ///
+    /// Returns the ID of the user with this phone number, if any
+    pub fn find_by_phone(&self, phone: &str) -> Option<Uuid> {
+        self.users
+            .values()
+            .find(|user| user.phone.as_deref() == Some(phone))
+            .map(|user| user.id)
+    }
+}
+
+/// Writer discarding its input, counting the bytes
+struct ByteCounter(u64);
+
+impl std::io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
     }
 }
 
@@ -163,53 +1459,61 @@ impl Storage {
 mod tests {
     use super::*;
 
-    fn create_test_user(id: Uuid, name: &str, email: &str) -> User {
-        let now = Utc::now();
-        User {
-            id,
-            name: name.to_string(),
-            email: email.to_string(),
-            created_at: now,
-            updated_at: now,
-        }
-    }
-
     #[test]
     fn test_storage_create_and_get() {
         let mut storage = Storage::new();
         let user_id = Uuid::new_v4();
-        let user = create_test_user(user_id, "Test User", "test@example.com");
+        let user = User::new("Test User", "test@example.com", Utc::now()).with_id(user_id);
 
-        assert!(storage.create(user.clone()));
+        assert!(storage.create(user.clone()).is_ok());
         assert_eq!(storage.get(&user_id), Some(user));
     }
 
     #[test]
     fn test_storage_get_all() {
         let mut storage = Storage::new();
-        let user1 = create_test_user(Uuid::new_v4(), "User 1", "user1@example.com");
-        let user2 = create_test_user(Uuid::new_v4(), "User 2", "user2@example.com");
+        let user1 = User::new("User 1", "user1@example.com", Utc::now());
+        let user2 = User::new("User 2", "user2@example.com", Utc::now());
 
-        storage.create(user1);
-        storage.create(user2);
+        storage.create(user1).unwrap();
+        storage.create(user2).unwrap();
 
         let all_users = storage.get_all();
         assert_eq!(all_users.len(), 2);
     }
 
+    #[test]
+    fn test_storage_get_all_shares_users() {
+        let mut storage = Storage::new();
+        let user_id = Uuid::new_v4();
+        storage
+            .create(User::new("Before", "user@example.com", Utc::now()).with_id(user_id))
+            .unwrap();
+
+        let listed = storage.get_all();
+        assert!(Arc::ptr_eq(&listed[0], &storage.get_all()[0]));
+
+        // Updating copies the user, leaving earlier listings untouched
+        assert!(storage
+            .update(&user_id, |user| user.name = "After".to_string())
+            .is_ok());
+        assert_eq!(listed[0].name, "Before");
+        assert_eq!(storage.get(&user_id).unwrap().name, "After");
+    }
+
     #[test]
     fn test_storage_update() {
         let mut storage = Storage::new();
         let user_id = Uuid::new_v4();
-        let user = create_test_user(user_id, "Original Name", "original@example.com");
+        let user = User::new("Original Name", "original@example.com", Utc::now()).with_id(user_id);
 
-        storage.create(user);
+        storage.create(user).unwrap();
 
         let updated = storage.update(&user_id, |u| {
             u.name = "Updated Name".to_string();
         });
 
-        assert!(updated);
+        assert_eq!(updated.unwrap().name, "Updated Name");
         let updated_user = storage.get(&user_id).unwrap();
         assert_eq!(updated_user.name, "Updated Name");
     }
@@ -218,33 +1522,461 @@ mod tests {
     fn test_storage_delete() {
         let mut storage = Storage::new();
         let user_id = Uuid::new_v4();
-        let user = create_test_user(user_id, "Test User", "test@example.com");
+        let user = User::new("Test User", "test@example.com", Utc::now()).with_id(user_id);
 
-        storage.create(user);
+        storage.create(user).unwrap();
         assert!(storage.get(&user_id).is_some());
 
-        assert!(storage.delete(&user_id));
+        assert!(storage.delete(&user_id).is_ok());
         assert!(storage.get(&user_id).is_none());
     }
 
+    #[test]
+    fn test_storage_version_bumped_by_mutations() {
+        let mut storage = Storage::new();
+        let user_id = Uuid::new_v4();
+        let initial = storage.version();
+
+        storage
+            .create(User::new("Test User", "test@example.com", Utc::now()).with_id(user_id))
+            .unwrap();
+        let created = storage.version();
+        assert_ne!(created, initial);
+
+        // Failed mutations leave the version alone
+        assert!(storage.delete(&Uuid::new_v4()).is_err());
+        assert!(storage.update(&Uuid::new_v4(), |_| {}).is_err());
+        assert_eq!(storage.version(), created);
+
+        storage
+            .update(&user_id, |u| u.name = "Renamed".to_string())
+            .unwrap();
+        assert_ne!(storage.version(), created);
+
+        // Versions of separate instances never collide
+        assert_ne!(Storage::new().version(), initial);
+    }
+
     #[test]
     fn test_storage_email_exists() {
         let mut storage = Storage::new();
-        let user = create_test_user(Uuid::new_v4(), "Test User", "test@example.com");
+        let user = User::new("Test User", "test@example.com", Utc::now());
 
-        storage.create(user);
+        storage.create(user).unwrap();
         assert!(storage.email_exists("test@example.com"));
         assert!(!storage.email_exists("nonexistent@example.com"));
+        // A Cyrillic "е" does not make the address new
+        assert!(storage.email_exists("t\u{435}st@example.com"));
+        assert!(!storage.email_exists("test+tag@example.com"));
+
+        let mut storage = Storage::new().with_email_canonicalization(Canonicalization::Subaddress);
+        storage
+            .create(User::new("Test User", "test@example.com", Utc::now()))
+            .unwrap();
+        assert!(storage.email_exists("test+tag@example.com"));
+    }
+
+    #[test]
+    fn test_storage_writes_check_claims_atomically() {
+        let mut storage = Storage::new();
+        let jane = User::new("Jane", "jane@example.com", Utc::now());
+        let mut john = User::new("John", "john@example.com", Utc::now());
+        john.phone = Some("+14155550123".to_string());
+        assert_eq!(storage.create_if_email_free(jane.clone()), Ok(()));
+        assert_eq!(storage.create_if_email_free(john.clone()), Ok(()));
+
+        assert_eq!(
+            storage.create_if_email_free(jane.clone()),
+            Err(StorageError::DuplicateId(jane.id))
+        );
+        let copy = User::new("Copy", "jane@example.com", Utc::now());
+        assert_eq!(
+            storage.create_if_email_free(copy),
+            Err(StorageError::DuplicateEmail {
+                email: "jane@example.com".to_string(),
+                holder: jane.id
+            })
+        );
+        assert_eq!(storage.len(), 2);
+
+        // A refused update leaves the user untouched
+        let version = storage.version();
+        let claim = storage.update_with_email_claim(&john.id, |user| {
+            user.name = "Johnny".to_string();
+            user.email = "jane@example.com".to_string();
+        });
+        assert_eq!(
+            claim,
+            Err(StorageError::DuplicateEmail {
+                email: "jane@example.com".to_string(),
+                holder: jane.id
+            })
+        );
+        assert_eq!(storage.get(&john.id).unwrap().name, "John");
+        assert_eq!(storage.version(), version);
+
+        let claim = storage.update_with_email_claim(&jane.id, |user| {
+            user.phone = Some("+14155550123".to_string());
+        });
+        assert_eq!(
+            claim,
+            Err(StorageError::DuplicatePhone {
+                phone: "+14155550123".to_string(),
+                holder: john.id
+            })
+        );
+
+        // Keeping its own email is no conflict
+        let updated = storage
+            .update_with_email_claim(&jane.id, |user| user.name = "Janet".to_string())
+            .unwrap();
+        assert_eq!(updated.name, "Janet");
+        assert_eq!(storage.get(&jane.id).unwrap().name, "Janet");
+        let missing = Uuid::new_v4();
+        assert_eq!(
+            storage.update_with_email_claim(&missing, |_| {}),
+            Err(StorageError::NotFound(missing))
+        );
+    }
+
+    #[test]
+    fn test_compare_and_update_prevents_lost_updates() {
+        let mut storage = Storage::new();
+        let id = Uuid::new_v4();
+        storage
+            .create(User::new("Jane", "jane@example.com", Utc::now()).with_id(id))
+            .unwrap();
+        let read = storage.revision(&id).unwrap();
+
+        // Another writer gets in first
+        let other = Uuid::new_v4();
+        storage
+            .create(User::new("John", "john@example.com", Utc::now()).with_id(other))
+            .unwrap();
+        assert_eq!(storage.revision(&id), Some(read));
+        storage
+            .update(&id, |user| user.name = "Janet".to_string())
+            .unwrap();
+        let current = storage.revision(&id).unwrap();
+        assert!(current > read);
+
+        let stale = storage.compare_and_update(&id, read, |user| user.name = "Jo".to_string());
+        assert_eq!(
+            stale,
+            Err(StorageError::Conflict {
+                id,
+                expected: read,
+                current
+            })
+        );
+        assert_eq!(storage.get(&id).unwrap().name, "Janet");
+
+        let updated = storage
+            .compare_and_update(&id, current, |user| user.name = "Jo".to_string())
+            .unwrap();
+        assert_eq!(updated.name, "Jo");
+        assert!(storage.revision(&id).unwrap() > current);
+
+        storage.delete(&id).unwrap();
+        assert_eq!(storage.revision(&id), None);
+        assert_eq!(
+            storage.compare_and_update(&id, current, |_| {}).err(),
+            Some(StorageError::NotFound(id))
+        );
+    }
+
+    #[test]
+    fn test_storage_find_by_phone() {
+        let mut storage = Storage::new();
+        let mut user = User::new("Test User", "test@example.com", Utc::now());
+        user.phone = Some("+14155552671".to_string());
+        let user_id = user.id;
+
+        storage.create(user).unwrap();
+        assert_eq!(storage.find_by_phone("+14155552671"), Some(user_id));
+        assert_eq!(storage.find_by_phone("+442079460958"), None);
+    }
+
+    #[test]
+    fn test_update_request_distinguishes_null_phone() {
+        let absent: UpdateUserRequest = serde_json::from_str(r#"{"name": "A"}"#).unwrap();
+        assert_eq!(absent.phone, None);
+
+        let cleared: UpdateUserRequest = serde_json::from_str(r#"{"phone": null}"#).unwrap();
+        assert_eq!(cleared.phone, Some(None));
+
+        let set: UpdateUserRequest = serde_json::from_str(r#"{"phone": "+1"}"#).unwrap();
+        assert_eq!(set.phone, Some(Some("+1".to_string())));
+    }
+
+    #[test]
+    fn test_user_status_transitions() {
+        use UserStatus::*;
+
+        assert!(Pending.can_transition_to(Active));
+        assert!(Active.can_transition_to(Suspended));
+        assert!(Suspended.can_transition_to(Active));
+        assert!(Suspended.can_transition_to(Deactivated));
+
+        assert!(!Pending.can_transition_to(Suspended));
+        assert!(!Active.can_transition_to(Active));
+        assert!(!Active.can_transition_to(Pending));
+        assert!(!Deactivated.can_transition_to(Active));
+
+        assert!(Active.can_log_in());
+        assert!(!Suspended.can_log_in());
+    }
+
+    #[test]
+    fn test_storage_records_activity() {
+        let mut storage = Storage::new();
+        let user_id = Uuid::new_v4();
+        storage
+            .create(User::new("Test User", "test@example.com", Utc::now()).with_id(user_id))
+            .unwrap();
+
+        let login = Utc::now();
+        assert!(storage.record_login(&user_id, login).is_ok());
+        let user = storage.get(&user_id).unwrap();
+        assert_eq!(user.last_login_at, Some(login));
+        assert_eq!(user.last_seen_at, Some(login));
+
+        let seen = login + chrono::Duration::seconds(30);
+        assert!(storage.record_seen(&user_id, seen).is_ok());
+        let user = storage.get(&user_id).unwrap();
+        assert_eq!(user.last_login_at, Some(login));
+        assert_eq!(user.last_active_at(), seen);
+
+        assert!(storage.record_seen(&Uuid::new_v4(), seen).is_err());
+    }
+
+    #[test]
+    fn test_list_query_inactive_since_formats() {
+        let rfc3339: ListUsersQuery =
+            serde_json::from_str(r#"{"inactive_since": "2024-01-01T00:00:00Z"}"#).unwrap();
+        let seconds: ListUsersQuery =
+            serde_json::from_str(r#"{"inactive_since": "1704067200"}"#).unwrap();
+
+        assert_eq!(rfc3339.inactive_since, seconds.inactive_since);
+        assert_eq!(seconds.inactive_since.unwrap().timestamp(), 1704067200);
+        assert!(serde_json::from_str::<ListUsersQuery>(r#"{"inactive_since": "soon"}"#).is_err());
     }
 
     #[test]
     fn test_storage_duplicate_id() {
         let mut storage = Storage::new();
         let user_id = Uuid::new_v4();
-        let user1 = create_test_user(user_id, "User 1", "user1@example.com");
-        let user2 = create_test_user(user_id, "User 2", "user2@example.com");
+        let user1 = User::new("User 1", "user1@example.com", Utc::now()).with_id(user_id);
+        let user2 = User::new("User 2", "user2@example.com", Utc::now()).with_id(user_id);
+
+        assert!(storage.create(user1).is_ok());
+        assert!(storage.create(user2).is_err()); // Should fail due to duplicate ID
+    }
+
+    #[test]
+    fn test_storage_rejects_when_full() {
+        let mut storage = Storage::with_capacity(Capacity {
+            max_users: Some(1),
+            eviction: Eviction::Reject,
+        });
+        assert_eq!(storage.make_room(1), Ok(Vec::new()));
+        assert!(storage
+            .create(User::new("A", "a@example.com", Utc::now()))
+            .is_ok());
+
+        assert_eq!(storage.check_room(1), Err(StorageFull { max_users: 1 }));
+        assert_eq!(storage.make_room(1), Err(StorageFull { max_users: 1 }));
+        assert_eq!(storage.len(), 1);
+    }
+
+    #[test]
+    fn test_storage_evicts_least_recently_used() {
+        let mut storage = Storage::with_capacity(Capacity {
+            max_users: Some(2),
+            eviction: Eviction::Lru,
+        });
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        assert!(storage
+            .create(User::new("First", "first@example.com", Utc::now()).with_id(first))
+            .is_ok());
+        assert!(storage
+            .create(User::new("Second", "second@example.com", Utc::now()).with_id(second))
+            .is_ok());
+
+        // Checking for room evicts nobody
+        assert_eq!(storage.check_room(1), Ok(()));
+        assert_eq!(storage.len(), 2);
+
+        // Reading the older user makes the newer one the eviction candidate
+        storage.get(&first);
+        let evicted = storage.make_room(1).unwrap();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].id, second);
+        assert!(storage.get(&second).is_none());
+        assert_eq!(storage.len(), 1);
+
+        // Nothing is evicted while there is room
+        assert_eq!(storage.make_room(1), Ok(Vec::new()));
+
+        let evicted = storage.make_room(2).unwrap();
+        assert_eq!(evicted.len(), 1);
+        assert!(storage.is_empty());
+        assert_eq!(storage.make_room(3), Err(StorageFull { max_users: 2 }));
+    }
+
+    #[test]
+    fn test_storage_usage() {
+        let mut storage = Storage::with_capacity(Capacity {
+            max_users: Some(4),
+            eviction: Eviction::Reject,
+        });
+        let empty = storage.usage();
+        assert_eq!(empty.users, 0);
+        assert_eq!(empty.serialized_bytes, 0);
+
+        let user = User::new("Test User", "test@example.com", Utc::now());
+        let size = serde_json::to_vec(&user).unwrap().len() as u64;
+        assert!(storage.create(user).is_ok());
+
+        let usage = storage.usage();
+        assert_eq!(usage.users, 1);
+        assert_eq!(usage.max_users, Some(4));
+        assert_eq!(usage.serialized_bytes, size);
+        assert!(usage.index_bytes > empty.index_bytes);
+        assert_eq!(usage.fill_ratio(), Some(0.25));
+    }
+
+    #[test]
+    fn test_storage_merge_leaves_tombstone() {
+        let mut storage = Storage::new();
+        let (keep, remove) = (Uuid::new_v4(), Uuid::new_v4());
+        assert!(storage
+            .create(User::new("Keep", "keep@example.com", Utc::now()).with_id(keep))
+            .is_ok());
+        assert!(storage
+            .create(User::new("Remove", "remove@example.com", Utc::now()).with_id(remove))
+            .is_ok());
+
+        let mut merged = storage.get(&keep).unwrap();
+        merged.name = "Merged".to_string();
+        let at = Utc::now();
+        assert!(storage.merge(merged.clone(), &Uuid::new_v4(), at).is_err());
+        assert!(storage.merge(merged, &remove, at).is_ok());
+
+        assert_eq!(storage.get(&keep).unwrap().name, "Merged");
+        assert!(storage.get(&remove).is_none());
+        assert_eq!(
+            storage.tombstone(&remove),
+            Some(Tombstone {
+                merged_into: Some(keep),
+                at
+            })
+        );
+        assert_eq!(storage.tombstone(&keep), None);
+
+        assert!(storage.remove(&keep, at, None).is_ok());
+        assert!(storage.remove(&keep, at, None).is_err());
+        assert_eq!(
+            storage.tombstone(&keep),
+            Some(Tombstone {
+                merged_into: None,
+                at
+            })
+        );
+    }
+
+    #[test]
+    fn test_storage_restore_from_trash() {
+        let mut storage = Storage::new();
+        let id = Uuid::new_v4();
+        assert!(storage
+            .create(User::new("Trashed", "trashed@example.com", Utc::now()).with_id(id))
+            .is_ok());
+        let at = Utc::now();
+        assert!(storage.remove(&id, at, Some("ops".to_string())).is_ok());
+        assert!(storage.get(&id).is_none());
+        assert!(!storage.email_exists("trashed@example.com"));
+        let trashed = storage.trashed(&id).unwrap();
+        assert_eq!(trashed.user.name, "Trashed");
+        assert_eq!(trashed.deleted_at, at);
+        assert_eq!(trashed.deleted_by.as_deref(), Some("ops"));
+        assert_eq!(storage.trash(), vec![trashed]);
+
+        let version = storage.version();
+        assert_eq!(storage.restore(&id).unwrap().name, "Trashed");
+        assert!(storage.version() > version);
+        assert_eq!(storage.get(&id).unwrap().name, "Trashed");
+        assert_eq!(storage.tombstone(&id), None);
+        assert!(storage.trashed(&id).is_none());
+        assert!(storage.trash().is_empty());
+        assert_eq!(storage.restore(&id), Err(StorageError::NotFound(id)));
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut storage = Storage::new();
+        let keep = Uuid::new_v4();
+        let remove = Uuid::new_v4();
+        storage
+            .create(User::new("Keep", "keep@example.com", Utc::now()).with_id(keep))
+            .unwrap();
+        storage
+            .create(User::new("Remove", "remove@example.com", Utc::now()).with_id(remove))
+            .unwrap();
+        storage
+            .remove(&remove, Utc::now(), Some("admin".to_string()))
+            .unwrap();
+        let snapshot = storage.snapshot(Utc::now());
+        assert_eq!(snapshot.users.len(), 1);
+        assert_eq!(snapshot.trash.len(), 1);
+        assert_eq!(snapshot.tombstones.len(), 1);
+
+        let mut restored = Storage::new();
+        restored
+            .create(User::new("Other", "other@example.com", Utc::now()))
+            .unwrap();
+        let version = restored.version();
+        restored.load(snapshot.clone()).unwrap();
+        assert_ne!(restored.version(), version);
+        assert_eq!(restored.len(), 1);
+        assert!(restored.get(&keep).is_some());
+        assert!(restored.trashed(&remove).is_some());
+        assert!(restored.tombstone(&remove).is_some());
+        assert_eq!(restored.snapshot(snapshot.taken_at), snapshot);
+
+        let mut small = Storage::with_capacity(Capacity {
+            max_users: Some(1),
+            eviction: Eviction::Reject,
+        });
+        let other = User::new("Other", "other@example.com", Utc::now());
+        let mut full = snapshot;
+        full.users.push(other);
+        assert_eq!(small.load(full), Err(StorageFull { max_users: 1 }));
+        assert!(small.is_empty());
+    }
+
+    #[test]
+    fn test_apply_record() {
+        let mut leader = Storage::new();
+        let mut follower = Storage::new();
+        let id = Uuid::new_v4();
+        leader
+            .create(User::new("Jane", "jane@example.com", Utc::now()).with_id(id))
+            .unwrap();
+        follower.apply(leader.record(&id));
+        assert_eq!(follower.get(&id), leader.get(&id));
+
+        leader.remove(&id, Utc::now(), None).unwrap();
+        let version = follower.version();
+        follower.apply(leader.record(&id));
+        assert_ne!(follower.version(), version);
+        assert!(follower.get(&id).is_none());
+        assert_eq!(follower.trashed(&id), leader.trashed(&id));
+        assert_eq!(follower.tombstone(&id), leader.tombstone(&id));
 
-        assert!(storage.create(user1));
-        assert!(!storage.create(user2)); // Should fail due to duplicate ID
+        leader.restore(&id).unwrap();
+        follower.apply(leader.record(&id));
+        assert_eq!(follower.record(&id), leader.record(&id));
+        assert!(follower.tombstone(&id).is_none());
     }
 }
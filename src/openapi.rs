@@ -0,0 +1,172 @@
+//! OpenAPI description of the API
+//!
+//! The document is generated from the handler annotations and model
+//! schemas, so it cannot drift from the route signatures. Whether the
+//! actual responses match it is checked by [`crate::contract`].
+
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::capture::{CaptureSettings, CaptureStatus};
+use crate::computed::ComputedFields;
+use crate::deprecation::Deprecation;
+use crate::error::{ErrorBody, ErrorCode, ErrorResponse, ViolationBody};
+use crate::handlers;
+use crate::health::{CheckResult, HealthStatus, ReadinessReport};
+use crate::jobs::{Operation, OperationKind, OperationResult, OperationStatus};
+use crate::metrics;
+use crate::models::{
+    AuditEntry, CreateUserRequest, DuplicateGroup, DuplicateStrategy, HealthReport, Impersonation,
+    ImportFailure, ImportReport, ImportUsersRequest, Location, LogLevel, MergePrecedence,
+    MergeSource, MergeUsersRequest, RestoreUsersRequest, Snapshot, StorageUsage, Tombstone,
+    TrashedUser, UpdateUserRequest, User, UserRecord, UserStatus,
+};
+use crate::rate_limit::{RateLimit, RateLimits};
+use crate::replication::{Changes, ReplicationSnapshot};
+use crate::reports::Report;
+use crate::reserved::ReservedLists;
+use crate::responses::{ApiResponse, Links, Meta, Pagination};
+use crate::routes::{RouteInfo, RoutesResponse};
+use crate::slo::{Objective, Objectives, RouteSlo, SloReport};
+use crate::snapshot::{RestorePlan, RestoreRequest, SnapshotInfo};
+use crate::usage::{EndpointUsage, UsageReport};
+
+/// The API's OpenAPI document
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "rust-api"),
+    modifiers(&BearerToken),
+    paths(
+        handlers::health_check,
+        handlers::deep_health_check,
+        handlers::readiness_check,
+        handlers::list_users,
+        handlers::get_user,
+        handlers::create_user,
+        handlers::update_user,
+        handlers::delete_user,
+        handlers::undo,
+        handlers::list_trash,
+        handlers::restore_user,
+        handlers::restore_users,
+        handlers::suspend_user,
+        handlers::activate_user,
+        handlers::deactivate_user,
+        handlers::import_users,
+        handlers::export_users,
+        handlers::get_operation,
+        handlers::api_key_usage,
+        handlers::cancel_operation,
+        handlers::generate_users,
+        handlers::set_log_level,
+        handlers::impersonate,
+        handlers::audit_log,
+        handlers::slo_report,
+        handlers::list_routes,
+        handlers::capture_status,
+        handlers::set_capture,
+        handlers::reserved_lists,
+        handlers::set_reserved_lists,
+        handlers::list_rate_limits,
+        handlers::set_rate_limit,
+        handlers::delete_rate_limit,
+        handlers::create_snapshot,
+        handlers::restore_snapshot,
+        handlers::replication_changes,
+        handlers::replication_snapshot,
+        handlers::create_report,
+        handlers::list_reports,
+        handlers::get_report,
+        handlers::find_duplicates,
+        handlers::merge_users,
+        handlers::merge_user,
+        metrics::export,
+    ),
+    components(schemas(
+        User,
+        UserStatus,
+        ComputedFields,
+        CreateUserRequest,
+        UpdateUserRequest,
+        ApiResponse<User>,
+        ApiResponse<Vec<User>>,
+        ApiResponse<Vec<TrashedUser>>,
+        ApiResponse<Vec<AuditEntry>>,
+        ApiResponse<Vec<DuplicateGroup>>,
+        ApiResponse<Vec<Report>>,
+        ApiResponse<Operation>,
+        ApiResponse<UsageReport>,
+        Meta,
+        Pagination,
+        Links,
+        ErrorResponse,
+        ErrorBody,
+        ErrorCode,
+        ViolationBody,
+        LogLevel,
+        AuditEntry,
+        Impersonation,
+        Location,
+        HealthReport,
+        StorageUsage,
+        HealthStatus,
+        CheckResult,
+        ReadinessReport,
+        DuplicateStrategy,
+        DuplicateGroup,
+        MergeUsersRequest,
+        MergePrecedence,
+        MergeSource,
+        TrashedUser,
+        RestoreUsersRequest,
+        SloReport,
+        RoutesResponse,
+        RouteInfo,
+        Deprecation,
+        CaptureSettings,
+        CaptureStatus,
+        ReservedLists,
+        RateLimit,
+        RateLimits,
+        UsageReport,
+        EndpointUsage,
+        SnapshotInfo,
+        RestoreRequest,
+        RestorePlan,
+        Snapshot,
+        Tombstone,
+        UserRecord,
+        Changes,
+        ReplicationSnapshot,
+        RouteSlo,
+        Objectives,
+        Objective,
+        ImportUsersRequest,
+        ImportReport,
+        ImportFailure,
+        Operation,
+        OperationKind,
+        OperationStatus,
+        OperationResult,
+        Report,
+    ))
+)]
+pub struct ApiDoc;
+
+/// Declares the `bearer_token` scheme referenced by protected operations
+struct BearerToken;
+
+impl Modify for BearerToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// Returns the OpenAPI document as JSON
+pub fn spec() -> serde_json::Value {
+    serde_json::to_value(ApiDoc::openapi()).unwrap_or_default()
+}
@@ -0,0 +1,373 @@
+//! Page-numbered listing of the user collection
+//!
+//! `GET /api/v1/users?page=2&per_page=50` returns the second page of 50
+//! users, ordered by creation time and then ID so that pages neither repeat
+//! nor skip users while the collection is unchanged. Without either
+//! parameter the whole collection is returned as before.
+//!
+//! Every list response carries the collection size in `X-Total-Count`. A
+//! paged response also links its neighbours in an RFC 5988 `Link` header,
+//! so generic clients and `curl` users can follow pages without parsing
+//! the body:
+//!
+//! ```text
+//! Link: </api/v1/users?status=active&page=1&per_page=50>; rel="first",
+//!       </api/v1/users?status=active&page=1&per_page=50>; rel="prev",
+//!       </api/v1/users?status=active&page=3&per_page=50>; rel="next",
+//!       </api/v1/users?status=active&page=4&per_page=50>; rel="last"
+//! ```
+//!
+//! The same links are in the envelope's `links`, and the page number and
+//! size in `meta.pagination`. Both headers are added when the list is
+//! turned into a response, from the envelope's metadata.
+//!
+//! Admin UI frameworks such as react-admin page with a `Range` header
+//! instead, which [`items`] answers: `Range: items=0-49` returns the first
+//! 50 users as `206 Partial Content` with `Content-Range: items 0-49/180`.
+//! A range starting past the end is answered with
+//! `416 Range Not Satisfiable`. The header is ignored when `page` or
+//! `per_page` is given, or when it is not a single range of items.
+
+use std::ops::Range;
+
+use axum::{
+    extract::Request,
+    http::{
+        header::{ACCEPT_RANGES, CONTENT_RANGE, RANGE},
+        HeaderName, HeaderValue, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::error::ApiError;
+use crate::i18n::Message;
+use crate::responses::Links;
+
+/// Header carrying the number of items in the whole collection
+pub const X_TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+
+/// Page size when only `page` is given
+pub const DEFAULT_PER_PAGE: usize = 50;
+
+/// Largest page size accepted
+pub const MAX_PER_PAGE: usize = 1000;
+
+/// A page of a collection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    /// Number of the page, starting at 1
+    pub number: usize,
+    /// Number of items per page
+    pub size: usize,
+}
+
+impl Page {
+    /// Reads the `page` and `per_page` query parameters
+    ///
+    /// Returns `None` if neither is given, so the whole collection is
+    /// listed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ApiError::BadRequest`] for page 0 or a page size outside
+    /// `1..=`[`MAX_PER_PAGE`].
+    pub fn from_query(
+        page: Option<usize>,
+        per_page: Option<usize>,
+    ) -> Result<Option<Self>, ApiError> {
+        if page.is_none() && per_page.is_none() {
+            return Ok(None);
+        }
+        let number = page.unwrap_or(1);
+        if number == 0 {
+            return Err(ApiError::BadRequest(Message::new("page.zero")));
+        }
+        let size = per_page.unwrap_or(DEFAULT_PER_PAGE);
+        if !(1..=MAX_PER_PAGE).contains(&size) {
+            return Err(ApiError::BadRequest(
+                Message::new("page.size").with("max", MAX_PER_PAGE),
+            ));
+        }
+        Ok(Some(Self { number, size }))
+    }
+
+    /// Number of the last page of a collection of `total` items; an empty
+    /// collection has one empty page
+    pub fn last(&self, total: usize) -> usize {
+        ((total + self.size - 1) / self.size).max(1)
+    }
+
+    /// Indices of the page's items in a collection of `total`, empty past
+    /// the last page
+    pub fn range(&self, total: usize) -> Range<usize> {
+        let start = (self.number - 1).saturating_mul(self.size).min(total);
+        start..start.saturating_add(self.size).min(total)
+    }
+
+    /// Links to the first, previous, next and last pages, on the path and
+    /// query of `self_link` minus its paging parameters
+    ///
+    /// There is no previous page on the first page and no next page from
+    /// the last one on; past the last page, the previous page is the last.
+    pub fn links(&self, self_link: &str, total: usize) -> Links {
+        let (path, query) = self_link.split_once('?').unwrap_or((self_link, ""));
+        let kept: Vec<&str> = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .filter(|pair| {
+                let name = pair.split_once('=').map_or(*pair, |(name, _)| name);
+                name != "page" && name != "per_page"
+            })
+            .collect();
+        let link = |number: usize| {
+            let mut link = format!("{}?", path);
+            for pair in &kept {
+                link.push_str(pair);
+                link.push('&');
+            }
+            link.push_str(&format!("page={}&per_page={}", number, self.size));
+            link
+        };
+        let last = self.last(total);
+        Links {
+            self_link: None,
+            first: Some(link(1)),
+            prev: (self.number > 1).then(|| link((self.number - 1).min(last))),
+            next: (self.number < last).then(|| link(self.number + 1)),
+            last: Some(link(last)),
+        }
+    }
+}
+
+/// A range of items requested with `Range: items=<first>-<last>`
+///
+/// The last index may be left out to ask for everything from `first` on,
+/// up to [`MAX_PER_PAGE`] items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Items {
+    /// Index of the first item, starting at 0
+    pub first: usize,
+    /// Index of the last item, inclusive
+    pub last: Option<usize>,
+}
+
+impl Items {
+    /// Parses a `Range` header value, returning `None` for other units,
+    /// several ranges, suffix ranges or a last index before the first
+    pub fn parse(value: &str) -> Option<Self> {
+        let (unit, range) = value.trim().split_once('=')?;
+        if !unit.trim().eq_ignore_ascii_case("items") {
+            return None;
+        }
+        let (first, last) = range.trim().split_once('-')?;
+        let first = first.trim().parse().ok()?;
+        let last = match last.trim() {
+            "" => None,
+            last => Some(last.parse().ok()?),
+        };
+        if last.is_some_and(|last| last < first) {
+            return None;
+        }
+        Some(Self { first, last })
+    }
+
+    /// Indices of the requested items in a collection of `total`, at most
+    /// [`MAX_PER_PAGE`] of them
+    pub fn range(&self, total: usize) -> Range<usize> {
+        let start = self.first.min(total);
+        let end = self
+            .last
+            .map_or(usize::MAX, |last| last.saturating_add(1))
+            .min(start.saturating_add(MAX_PER_PAGE))
+            .min(total);
+        start..end
+    }
+}
+
+tokio::task_local! {
+    static REQUESTED: Items;
+}
+
+/// The items the current request asked for with a `Range` header
+///
+/// Always `None` outside of [`items`].
+pub fn requested_items() -> Option<Items> {
+    REQUESTED.try_with(|items| *items).ok()
+}
+
+/// Middleware answering `Range: items=...` requests for a collection
+///
+/// The handler reads the range with [`requested_items`] and returns the
+/// items in it; this turns its `200 OK` into `206 Partial Content` with a
+/// `Content-Range` computed from `X-Total-Count`. Every response
+/// advertises `Accept-Ranges: items`.
+pub async fn items(request: Request, next: Next) -> Response {
+    let paged = request.uri().query().is_some_and(|query| {
+        query.split('&').any(|pair| {
+            let name = pair.split_once('=').map_or(pair, |(name, _)| name);
+            name == "page" || name == "per_page"
+        })
+    });
+    let items = request
+        .headers()
+        .get(RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(Items::parse)
+        .filter(|_| !paged);
+
+    let mut response = match items {
+        Some(items) => REQUESTED.scope(items, next.run(request)).await,
+        None => next.run(request).await,
+    };
+    response
+        .headers_mut()
+        .insert(ACCEPT_RANGES, HeaderValue::from_static("items"));
+    let Some(items) = items.filter(|_| response.status() == StatusCode::OK) else {
+        return response;
+    };
+    let Some(total) = response
+        .headers()
+        .get(X_TOTAL_COUNT)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|total| total.parse::<usize>().ok())
+    else {
+        return response;
+    };
+
+    let range = items.range(total);
+    if range.is_empty() {
+        let unsatisfied = HeaderValue::from(total);
+        let content_range = format!("items */{}", total);
+        if total > 0 {
+            response = ApiError::RangeNotSatisfiable(
+                Message::new("range.not_satisfiable")
+                    .with("first", items.first)
+                    .with("total", total),
+            )
+            .into_response();
+            response.headers_mut().insert(X_TOTAL_COUNT, unsatisfied);
+        }
+        if let Ok(content_range) = HeaderValue::try_from(content_range) {
+            response.headers_mut().insert(CONTENT_RANGE, content_range);
+        }
+        return response;
+    }
+    let content_range = format!("items {}-{}/{}", range.start, range.end - 1, total);
+    if let Ok(content_range) = HeaderValue::try_from(content_range) {
+        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+        response.headers_mut().insert(CONTENT_RANGE, content_range);
+    }
+    response
+}
+
+/// The `Link` header for the page links in `links`, if there are any
+pub fn link_header(links: &Links) -> Option<HeaderValue> {
+    let value = [
+        ("first", &links.first),
+        ("prev", &links.prev),
+        ("next", &links.next),
+        ("last", &links.last),
+    ]
+    .into_iter()
+    .filter_map(|(rel, link)| Some(format!("<{}>; rel=\"{}\"", link.as_ref()?, rel)))
+    .collect::<Vec<_>>()
+    .join(", ");
+    HeaderValue::try_from(value)
+        .ok()
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(number: usize, size: usize) -> Page {
+        Page { number, size }
+    }
+
+    #[test]
+    fn test_from_query() {
+        assert_eq!(Page::from_query(None, None).unwrap(), None);
+        assert_eq!(
+            Page::from_query(Some(3), None).unwrap(),
+            Some(page(3, DEFAULT_PER_PAGE))
+        );
+        assert_eq!(Page::from_query(None, Some(10)).unwrap(), Some(page(1, 10)));
+        assert!(Page::from_query(Some(0), Some(10)).is_err());
+        assert!(Page::from_query(Some(1), Some(0)).is_err());
+        assert!(Page::from_query(Some(1), Some(MAX_PER_PAGE + 1)).is_err());
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(page(1, 10).range(25), 0..10);
+        assert_eq!(page(3, 10).range(25), 20..25);
+        assert_eq!(page(4, 10).range(25), 25..25);
+        assert_eq!(page(usize::MAX, 10).range(25), 25..25);
+        assert_eq!(page(1, 10).last(0), 1);
+        assert_eq!(page(1, 10).last(30), 3);
+    }
+
+    #[test]
+    fn test_links() {
+        let links = page(2, 10).links("/api/v1/users?status=active&page=2&per_page=10", 35);
+        let url =
+            |number: usize| format!("/api/v1/users?status=active&page={}&per_page=10", number);
+        assert_eq!(links.first, Some(url(1)));
+        assert_eq!(links.prev, Some(url(1)));
+        assert_eq!(links.next, Some(url(3)));
+        assert_eq!(links.last, Some(url(4)));
+
+        let links = page(1, 10).links("/api/v1/users?per_page=10", 5);
+        assert_eq!(links.prev, None);
+        assert_eq!(links.next, None);
+        assert_eq!(
+            links.last,
+            Some("/api/v1/users?page=1&per_page=10".to_string())
+        );
+
+        // Past the end, the way back leads to the last page
+        let links = page(9, 10).links("/api/v1/users", 35);
+        assert_eq!(
+            links.prev,
+            Some("/api/v1/users?page=4&per_page=10".to_string())
+        );
+        assert_eq!(links.next, None);
+    }
+
+    #[test]
+    fn test_parse_items() {
+        let items = |first, last| Some(Items { first, last });
+        assert_eq!(Items::parse("items=0-49"), items(0, Some(49)));
+        assert_eq!(Items::parse(" Items = 10 - 19 "), items(10, Some(19)));
+        assert_eq!(Items::parse("items=25-"), items(25, None));
+        assert_eq!(Items::parse("items=5-5"), items(5, Some(5)));
+        assert_eq!(Items::parse("bytes=0-49"), None);
+        assert_eq!(Items::parse("items=-10"), None);
+        assert_eq!(Items::parse("items=10-5"), None);
+        assert_eq!(Items::parse("items=0-4,10-14"), None);
+    }
+
+    #[test]
+    fn test_items_range() {
+        let items = |first, last| Items { first, last };
+        assert_eq!(items(0, Some(49)).range(180), 0..50);
+        assert_eq!(items(170, Some(199)).range(180), 170..180);
+        assert_eq!(items(200, Some(249)).range(180), 180..180);
+        assert_eq!(items(10, None).range(5000), 10..10 + MAX_PER_PAGE);
+        assert_eq!(items(0, Some(usize::MAX)).range(3), 0..3);
+    }
+
+    #[test]
+    fn test_link_header() {
+        let links = page(1, 10).links("/api/v1/users", 15);
+        assert_eq!(
+            link_header(&links).unwrap(),
+            "</api/v1/users?page=1&per_page=10>; rel=\"first\", \
+             </api/v1/users?page=2&per_page=10>; rel=\"next\", \
+             </api/v1/users?page=2&per_page=10>; rel=\"last\""
+        );
+        assert_eq!(link_header(&Links::default()), None);
+    }
+}
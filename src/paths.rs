@@ -0,0 +1,133 @@
+//! Request path normalization
+//!
+//! Routes are registered without trailing slashes. Rather than answering
+//! `/api/v1/users/` with a 404, the [`normalize_trailing_slash`] middleware
+//! either rewrites the path before routing or redirects the client to the
+//! canonical URL, depending on `APP_TRAILING_SLASH`.
+//!
+//! The middleware has to run before the router picks a route, so it is
+//! applied around the finished [`Router`](axum::Router) instead of with
+//! `Router::layer`.
+
+use std::str::FromStr;
+
+use axum::{
+    extract::{Request, State},
+    http::{header::LOCATION, uri::PathAndQuery, HeaderValue, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::AppState;
+
+/// How requests with a trailing slash are handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// Strip the slash and route the request as if it had none
+    #[default]
+    Rewrite,
+    /// Answer with `308 Permanent Redirect` to the path without the slash
+    Redirect,
+}
+
+impl FromStr for TrailingSlash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "rewrite" => Ok(TrailingSlash::Rewrite),
+            "redirect" => Ok(TrailingSlash::Redirect),
+            other => Err(format!("unknown trailing slash mode '{}'", other)),
+        }
+    }
+}
+
+/// Returns `uri` without trailing slashes on its path, or `None` if the
+/// path has none (the root path `/` is left alone)
+///
+/// Leading slashes and backslashes are collapsed into one slash as well:
+/// browsers read a `Location` of `//evil.com` or `/\evil.com` as another
+/// host, so redirecting there would be an open redirect.
+fn trim_trailing_slash(uri: &Uri) -> Option<Uri> {
+    let path = uri.path();
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.len() == path.len() || path == "/" {
+        return None;
+    }
+
+    let trimmed = format!("/{}", trimmed.trim_start_matches(['/', '\\']));
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{}", trimmed, query),
+        None => trimmed,
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(PathAndQuery::try_from(path_and_query).ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+/// Middleware that makes `/path/` behave like `/path`
+pub async fn normalize_trailing_slash(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(canonical) = trim_trailing_slash(request.uri()) else {
+        return next.run(request).await;
+    };
+
+    match state.config.trailing_slash {
+        TrailingSlash::Rewrite => {
+            *request.uri_mut() = canonical;
+            next.run(request).await
+        }
+        TrailingSlash::Redirect => {
+            let location = canonical
+                .path_and_query()
+                .map_or("/", PathAndQuery::as_str)
+                .to_string();
+            match HeaderValue::try_from(location) {
+                Ok(location) => {
+                    (StatusCode::PERMANENT_REDIRECT, [(LOCATION, location)]).into_response()
+                }
+                Err(_) => next.run(request).await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trim(uri: &str) -> Option<String> {
+        trim_trailing_slash(&uri.parse().unwrap()).map(|uri| uri.to_string())
+    }
+
+    #[test]
+    fn test_trim_trailing_slash() {
+        assert_eq!(trim("/api/v1/users/"), Some("/api/v1/users".to_string()));
+        assert_eq!(
+            trim("/api/v1/users//?status=active"),
+            Some("/api/v1/users?status=active".to_string())
+        );
+        assert_eq!(trim("/api/v1/users"), None);
+        assert_eq!(trim("/"), None);
+        assert_eq!(trim("//"), Some("/".to_string()));
+    }
+
+    #[test]
+    fn test_trim_collapses_leading_slashes() {
+        assert_eq!(trim("//evil.com/"), Some("/evil.com".to_string()));
+        assert_eq!(trim("///evil.com//"), Some("/evil.com".to_string()));
+        assert_eq!(trim("/\\evil.com/"), Some("/evil.com".to_string()));
+        assert_eq!(trim("/\\/evil.com/?a=1"), Some("/evil.com?a=1".to_string()));
+    }
+
+    #[test]
+    fn test_trailing_slash_from_str() {
+        assert_eq!("Redirect".parse(), Ok(TrailingSlash::Redirect));
+        assert_eq!("rewrite".parse(), Ok(TrailingSlash::Rewrite));
+        assert!("ignore".parse::<TrailingSlash>().is_err());
+    }
+}
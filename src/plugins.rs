@@ -0,0 +1,182 @@
+//! Extension hooks for library consumers
+//!
+//! A [`Plugin`] changes the API's behavior without changes to its
+//! handlers: it can check or adjust new users before the built-in
+//! validation runs, react to updated users, inspect or reject any request,
+//! and serve extra routes. Plugins are registered on an [`AppBuilder`] and
+//! called in registration order.
+//!
+//! ```no_run
+//! use rust_api::error::ApiError;
+//! use rust_api::i18n::Message;
+//! use rust_api::models::CreateUserRequest;
+//! use rust_api::plugins::{AppBuilder, Plugin};
+//! use rust_api::AppState;
+//!
+//! struct CompanyEmails;
+//!
+//! impl Plugin for CompanyEmails {
+//!     fn name(&self) -> &str {
+//!         "company-emails"
+//!     }
+//!
+//!     fn before_create_user(&self, request: &mut CreateUserRequest) -> Result<(), ApiError> {
+//!         if request.email.ends_with("@example.com") {
+//!             Ok(())
+//!         } else {
+//!             Err(ApiError::BadRequest(Message::new("email.invalid_domain")))
+//!         }
+//!     }
+//! }
+//!
+//! let app = AppBuilder::new(AppState::new()).plugin(CompanyEmails).build();
+//! ```
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Router,
+};
+
+use crate::error::ApiError;
+use crate::events::Event;
+use crate::models::{CreateUserRequest, User};
+use crate::routes::{RouteGroup, RouterBuilder};
+use crate::AppState;
+
+/// Hooks into the API's behavior
+///
+/// Every hook has a default that does nothing, so plugins implement only
+/// the ones they need.
+pub trait Plugin: Send + Sync + 'static {
+    /// Identifies the plugin in logs
+    fn name(&self) -> &str;
+
+    /// Called with the payload of `POST /api/v1/users` before it is
+    /// validated; an error rejects the request
+    fn before_create_user(&self, _request: &mut CreateUserRequest) -> Result<(), ApiError> {
+        Ok(())
+    }
+
+    /// Called after a user's fields or status changed, with the new state
+    fn after_update_user(&self, _user: &User) {}
+
+    /// Called for every request before its handler runs; an error rejects
+    /// it
+    fn on_request(&self, _request: &mut Request) -> Result<(), ApiError> {
+        Ok(())
+    }
+
+    /// Extra routes served alongside the API's own
+    ///
+    /// A group named like one of the [`crate::routes`] groups replaces it.
+    fn routes(&self) -> Option<RouteGroup> {
+        None
+    }
+}
+
+/// The registered plugins, in registration order
+#[derive(Default, Clone)]
+pub struct Plugins(Vec<Arc<dyn Plugin>>);
+
+impl std::fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|plugin| plugin.name()))
+            .finish()
+    }
+}
+
+impl Plugins {
+    /// Runs every plugin's [`Plugin::before_create_user`], stopping at the
+    /// first error
+    pub fn before_create_user(&self, request: &mut CreateUserRequest) -> Result<(), ApiError> {
+        self.0
+            .iter()
+            .try_for_each(|plugin| plugin.before_create_user(request))
+    }
+
+    /// Runs every plugin's [`Plugin::on_request`], stopping at the first
+    /// error
+    pub fn on_request(&self, request: &mut Request) -> Result<(), ApiError> {
+        self.0
+            .iter()
+            .try_for_each(|plugin| plugin.on_request(request))
+    }
+}
+
+/// Assembles the application from its state and plugins
+pub struct AppBuilder {
+    state: AppState,
+    plugins: Vec<Arc<dyn Plugin>>,
+}
+
+impl AppBuilder {
+    /// Starts from `state`, without plugins
+    pub fn new(state: AppState) -> Self {
+        Self {
+            state,
+            plugins: Vec::new(),
+        }
+    }
+
+    /// Starts from `state`, with already registered plugins
+    #[cfg(feature = "server")]
+    pub(crate) fn with_plugins(state: AppState, plugins: Vec<Arc<dyn Plugin>>) -> Self {
+        Self { state, plugins }
+    }
+
+    /// Registers a plugin
+    pub fn plugin<P: Plugin>(mut self, plugin: P) -> Self {
+        self.plugins.push(Arc::new(plugin));
+        self
+    }
+
+    /// Builds the router with the plugins in place
+    ///
+    /// As with [`crate::router`], path normalization is not included.
+    pub fn build(self) -> Router {
+        self.routes().build()
+    }
+
+    /// Returns the route groups with the plugins in place, to be changed
+    /// further before building the router
+    pub fn routes(self) -> RouterBuilder {
+        for plugin in &self.plugins {
+            let plugin = plugin.clone();
+            self.state.events.subscribe(move |event| {
+                if let Event::UserUpdated(user, _) = event {
+                    plugin.after_update_user(user);
+                }
+            });
+        }
+
+        let groups: Vec<RouteGroup> = self
+            .plugins
+            .iter()
+            .filter_map(|plugin| plugin.routes())
+            .collect();
+        let state = AppState {
+            plugins: Arc::new(Plugins(self.plugins)),
+            ..self.state
+        };
+        groups
+            .into_iter()
+            .fold(RouterBuilder::with_defaults(state), RouterBuilder::group)
+    }
+}
+
+/// Middleware running the plugins' [`Plugin::on_request`] hooks
+pub async fn on_request(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if let Err(err) = state.plugins.on_request(&mut request) {
+        return err.into_response();
+    }
+    next.run(request).await
+}
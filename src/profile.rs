@@ -0,0 +1,157 @@
+//! Deployment profiles
+//!
+//! `APP_ENV` names the environment the server runs in, `dev` (the
+//! default), `staging` or `prod`, and picks the defaults of the settings
+//! whose safe value differs between them. Variables set explicitly always
+//! win over the profile's defaults.
+//!
+//! | Setting | `dev` | `staging` | `prod` |
+//! |---------|-------|-----------|--------|
+//! | CORS ([`crate::cors`]) | any origin | `APP_CORS_ORIGINS` only | `APP_CORS_ORIGINS` only |
+//! | Log filter without `RUST_LOG` | debug | info | info, warnings from `tower_http` |
+//! | `APP_DEV_ENDPOINTS`, `APP_ERROR_DETAILS` | allowed | allowed | refused |
+//!
+//! The server logs the active profile and its security posture at startup,
+//! warning about what a production deployment should not run without.
+
+use std::str::FromStr;
+
+use crate::Config;
+
+/// The environment the server runs in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// Local development
+    #[default]
+    Dev,
+    /// Pre-production, configured like production but less strict
+    Staging,
+    /// Production
+    Prod,
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "dev" | "development" => Ok(Profile::Dev),
+            "staging" => Ok(Profile::Staging),
+            "prod" | "production" => Ok(Profile::Prod),
+            other => Err(format!(
+                "unknown profile '{}' (expected dev, staging or prod)",
+                other
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Profile::Dev => "dev",
+            Profile::Staging => "staging",
+            Profile::Prod => "prod",
+        })
+    }
+}
+
+impl Profile {
+    /// Reads the profile from `APP_ENV` in the process environment
+    ///
+    /// Falls back to [`Profile::Dev`] when it is unset or invalid; the
+    /// configuration reports the invalid value when it is loaded.
+    pub fn from_env() -> Self {
+        std::env::var("APP_ENV")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// Returns the log filter used when `RUST_LOG` is not set
+    pub fn log_filter(self) -> &'static str {
+        match self {
+            Profile::Dev => crate::logging::DEFAULT_FILTER,
+            Profile::Staging => "rust_api=info,tower_http=info",
+            Profile::Prod => "rust_api=info,tower_http=warn",
+        }
+    }
+
+    /// Returns the CORS settings used when `APP_CORS_ORIGINS` is not set
+    pub fn cors(self) -> crate::cors::Settings {
+        match self {
+            Profile::Dev => crate::cors::Settings::Permissive,
+            Profile::Staging | Profile::Prod => crate::cors::Settings::Origins(Vec::new()),
+        }
+    }
+}
+
+/// Describes the security posture of `config` in one line
+pub fn posture(config: &Config) -> String {
+    let on_off = |on: bool| if on { "on" } else { "off" };
+    format!(
+        "authentication {}, TLS {}, CORS allows {}, error details {}, dev endpoints {}, admin endpoints {}",
+        on_off(!config.api_tokens.is_empty()),
+        on_off(config.tls.is_enabled()),
+        config.cors,
+        on_off(config.error_details),
+        on_off(config.dev_endpoints),
+        on_off(config.admin_endpoints),
+    )
+}
+
+/// Returns what a production deployment of `config` should not run
+/// without
+pub fn warnings(config: &Config) -> Vec<&'static str> {
+    let mut warnings = Vec::new();
+    if config.profile != Profile::Prod {
+        return warnings;
+    }
+    if config.api_tokens.is_empty() {
+        warnings.push("authentication is off: every caller has every scope");
+    }
+    if config.cors == crate::cors::Settings::Permissive {
+        warnings.push("CORS allows every origin");
+    }
+    warnings
+}
+
+/// Logs the active profile and its security posture
+pub fn log_banner(config: &Config) {
+    tracing::info!(
+        "Starting with the {} profile: {}",
+        config.profile,
+        posture(config)
+    );
+    for warning in warnings(config) {
+        tracing::warn!("{} profile: {}", config.profile, warning);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profile() {
+        assert_eq!("prod".parse(), Ok(Profile::Prod));
+        assert_eq!(" Production ".parse(), Ok(Profile::Prod));
+        assert_eq!("staging".parse(), Ok(Profile::Staging));
+        assert_eq!("development".parse(), Ok(Profile::Dev));
+        assert!("test".parse::<Profile>().is_err());
+    }
+
+    #[test]
+    fn test_posture_and_warnings() {
+        let config = Config {
+            profile: Profile::Prod,
+            ..Config::default()
+        };
+        assert_eq!(
+            posture(&config),
+            "authentication off, TLS off, CORS allows any origin, error details off, dev endpoints off, admin endpoints off"
+        );
+        assert_eq!(warnings(&config).len(), 2);
+        assert!(warnings(&Config::default()).is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+//! Forwarding requests to other instances
+//!
+//! Instances partitioning users ([`crate::shard`]) or replicating them
+//! ([`crate::replication`]) pass requests they cannot answer themselves on
+//! to a [`Peer`] and return its response unchanged. Forwarding needs an
+//! HTTP client and so the `client` feature; without it every forward
+//! fails.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use axum::{extract::Request, response::Response};
+
+/// How long a peer has to answer a forwarded request
+pub const FORWARD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest request body forwarded to a peer, in bytes
+pub const MAX_FORWARDED_BODY: usize = 2 * 1024 * 1024;
+
+/// Base URL of an instance, as `http://host[:port]` or `https://...`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Peer(String);
+
+impl Peer {
+    /// Returns the base URL, without a trailing slash
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Peer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let url = s.trim().trim_end_matches('/');
+        let host = url
+            .strip_prefix("http://")
+            .or_else(|| url.strip_prefix("https://"))
+            .ok_or_else(|| format!("'{}' is not an http:// or https:// URL", s))?;
+        if host.is_empty() || host.contains('/') {
+            return Err(format!("'{}' must be a base URL without a path", s));
+        }
+        Ok(Peer(url.to_string()))
+    }
+}
+
+impl std::fmt::Display for Peer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Headers that describe a single connection and are not forwarded
+#[cfg(feature = "client")]
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "content-length",
+    "host",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+#[cfg(feature = "client")]
+fn end_to_end(headers: &axum::http::HeaderMap) -> axum::http::HeaderMap {
+    let mut headers = headers.clone();
+    for name in HOP_BY_HOP {
+        headers.remove(*name);
+    }
+    headers
+}
+
+/// HTTP client forwarding requests to peers
+#[derive(Debug, Clone)]
+pub struct Client {
+    #[cfg(feature = "client")]
+    inner: reqwest::Client,
+}
+
+impl Client {
+    /// Creates a client with its own connection pool
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {
+            #[cfg(feature = "client")]
+            inner: reqwest::Client::new(),
+        }
+    }
+
+    /// Returns the underlying HTTP client, for calls other than forwards
+    #[cfg(feature = "client")]
+    pub fn http(&self) -> &reqwest::Client {
+        &self.inner
+    }
+
+    /// Sends `request` to `peer` and returns its response
+    ///
+    /// Bodies larger than [`MAX_FORWARDED_BODY`] are not forwarded.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the failure if the peer cannot be reached
+    /// or does not answer within [`FORWARD_TIMEOUT`], or before the
+    /// current request's [deadline](crate::deadline).
+    #[cfg(feature = "client")]
+    pub async fn forward(&self, peer: &Peer, request: Request) -> Result<Response, String> {
+        let (parts, body) = request.into_parts();
+        let body = axum::body::to_bytes(body, MAX_FORWARDED_BODY)
+            .await
+            .map_err(|err| err.to_string())?;
+        let path = parts
+            .uri
+            .path_and_query()
+            .map_or(parts.uri.path(), |path| path.as_str());
+
+        // The peer gets what is left of the deadline, not the original one
+        let mut headers = end_to_end(&parts.headers);
+        if let Some(remaining) = crate::deadline::remaining() {
+            headers.insert(
+                crate::deadline::X_REQUEST_TIMEOUT,
+                axum::http::HeaderValue::from(remaining.as_millis().max(1) as u64),
+            );
+        }
+        let response = self
+            .inner
+            .request(parts.method, format!("{}{}", peer, path))
+            .headers(headers)
+            .body(body)
+            .timeout(crate::deadline::bound(FORWARD_TIMEOUT))
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+        let status = response.status();
+        let headers = end_to_end(response.headers());
+        let body = response.bytes().await.map_err(|err| err.to_string())?;
+        let mut response = Response::new(axum::body::Body::from(body));
+        *response.status_mut() = status;
+        *response.headers_mut() = headers;
+        Ok(response)
+    }
+
+    /// Fails: forwarding requires the `client` feature
+    #[cfg(not(feature = "client"))]
+    pub async fn forward(&self, _peer: &Peer, _request: Request) -> Result<Response, String> {
+        Err("forwarding requires the `client` feature".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_peer() {
+        assert_eq!(
+            "http://10.0.0.1:3000/".parse::<Peer>().unwrap().as_str(),
+            "http://10.0.0.1:3000"
+        );
+        assert!("10.0.0.1:3000".parse::<Peer>().is_err());
+        assert!("http://10.0.0.1:3000/api".parse::<Peer>().is_err());
+        assert!("https://".parse::<Peer>().is_err());
+    }
+}
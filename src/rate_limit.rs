@@ -0,0 +1,224 @@
+//! Per-caller rate limits
+//!
+//! With `APP_RATE_LIMIT_REQUESTS` set, every authenticated caller may make
+//! that many requests to the routes requiring a scope per window of
+//! `APP_RATE_LIMIT_WINDOW_SECONDS`. Admins give single principals a limit
+//! of their own, such as a higher quota for a partner, through
+//! `PUT /api/v1/admin/limits/:principal`. Overrides are kept in the
+//! [`Storage`](crate::Storage) next to the users, so snapshots carry them;
+//! a principal with an override is limited even without a default limit.
+//!
+//! Requests over the limit are answered with `429 Too Many Requests` and a
+//! `Retry-After` header until the window ends. Responses to limited callers
+//! carry `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`.
+//! Without authentication, callers have no principal and are not limited.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use axum::{
+    extract::{Request, State},
+    http::{header::RETRY_AFTER, HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::auth::Principal;
+use crate::error::ApiError;
+use crate::i18n::Message;
+use crate::AppState;
+
+/// Header giving the number of requests allowed per window
+pub const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+/// Header giving the number of requests left in the current window
+pub const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+/// Header giving the seconds until the current window ends
+pub const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+/// Number of requests allowed per window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RateLimit {
+    /// Requests allowed per window; `0` refuses every request
+    pub requests: u32,
+    /// Length of a window in seconds, at least 1
+    pub window_seconds: u64,
+}
+
+impl RateLimit {
+    /// Returns the length of a window
+    pub fn window(&self) -> chrono::Duration {
+        i64::try_from(self.window_seconds)
+            .ok()
+            .and_then(chrono::Duration::try_seconds)
+            .unwrap_or(chrono::Duration::MAX)
+    }
+}
+
+/// Response of `GET /api/v1/admin/limits`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RateLimits {
+    /// Limit of callers without an override; unlimited when absent
+    pub default: Option<RateLimit>,
+    /// Limits set for single principals, by principal
+    pub overrides: BTreeMap<String, RateLimit>,
+}
+
+/// Whether a request is within its caller's limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    /// The limit applied
+    pub limit: RateLimit,
+    /// Whether the request may proceed
+    pub allowed: bool,
+    /// Requests left in the current window
+    pub remaining: u32,
+    /// Seconds until the current window ends
+    pub reset_seconds: u64,
+}
+
+impl Decision {
+    /// Adds the `RateLimit-*` headers describing the decision
+    fn write_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(RATELIMIT_LIMIT, HeaderValue::from(self.limit.requests));
+        headers.insert(RATELIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(RATELIMIT_RESET, HeaderValue::from(self.reset_seconds));
+    }
+}
+
+/// The current window of one principal
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    started: DateTime<Utc>,
+    count: u32,
+}
+
+/// Requests counted per principal in fixed windows
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+impl RateLimiter {
+    /// Counts a request of `principal` at `now` against `limit`
+    ///
+    /// Refused requests are not counted.
+    pub fn acquire(&self, principal: &str, limit: RateLimit, now: DateTime<Utc>) -> Decision {
+        let mut windows = self
+            .windows
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let window = windows.entry(principal.to_string()).or_insert(Window {
+            started: now,
+            count: 0,
+        });
+        let ends = |window: &Window| {
+            window
+                .started
+                .checked_add_signed(limit.window())
+                .unwrap_or(DateTime::<Utc>::MAX_UTC)
+        };
+        if now >= ends(window) || now < window.started {
+            *window = Window {
+                started: now,
+                count: 0,
+            };
+        }
+        let allowed = window.count < limit.requests;
+        if allowed {
+            window.count += 1;
+        }
+        let left = (ends(window) - now).num_milliseconds();
+        Decision {
+            limit,
+            allowed,
+            remaining: limit.requests.saturating_sub(window.count),
+            reset_seconds: u64::try_from(left.saturating_add(999) / 1000)
+                .unwrap_or(0)
+                .max(1),
+        }
+    }
+}
+
+/// Middleware counting requests of authenticated callers against their
+/// limit
+///
+/// Runs inside [`crate::auth::require`], which identifies the caller.
+pub async fn check(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(principal) = request.extensions().get::<Principal>() else {
+        return next.run(request).await;
+    };
+    let limit = state
+        .storage
+        .read()
+        .await
+        .rate_limit(&principal.name)
+        .or(state.config.rate_limit);
+    let Some(limit) = limit else {
+        return next.run(request).await;
+    };
+
+    let decision = state
+        .rate_limiter
+        .acquire(&principal.name, limit, state.clock.now());
+    let mut response = if decision.allowed {
+        next.run(request).await
+    } else {
+        tracing::info!(principal = %principal.name, "rate limit exceeded");
+        let error = ApiError::TooManyRequests(
+            Message::new("rate_limit.exceeded")
+                .with("requests", limit.requests)
+                .with("seconds", limit.window_seconds),
+        );
+        (
+            [(RETRY_AFTER, HeaderValue::from(decision.reset_seconds))],
+            error,
+        )
+            .into_response()
+    };
+    decision.write_headers(response.headers_mut());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_windows() {
+        let limiter = RateLimiter::default();
+        let limit = RateLimit {
+            requests: 2,
+            window_seconds: 60,
+        };
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        let first = limiter.acquire("partner", limit, start);
+        assert!(first.allowed);
+        assert_eq!((first.remaining, first.reset_seconds), (1, 60));
+        assert!(limiter.acquire("partner", limit, start).allowed);
+
+        let later = start + chrono::Duration::milliseconds(30_500);
+        let refused = limiter.acquire("partner", limit, later);
+        assert!(!refused.allowed);
+        assert_eq!((refused.remaining, refused.reset_seconds), (0, 30));
+        assert!(limiter.acquire("other", limit, later).allowed);
+
+        let next = limiter.acquire("partner", limit, start + limit.window());
+        assert!(next.allowed);
+        assert_eq!(next.remaining, 1);
+    }
+
+    #[test]
+    fn test_zero_requests_refuses_all() {
+        let limit = RateLimit {
+            requests: 0,
+            window_seconds: 1,
+        };
+        let decision = RateLimiter::default().acquire("blocked", limit, Utc::now());
+        assert!(!decision.allowed);
+        assert_eq!(decision.remaining, 0);
+    }
+}
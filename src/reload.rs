@@ -0,0 +1,215 @@
+//! Zero-downtime binary reloads
+//!
+//! Built with the `reload` feature, sending `SIGUSR2` to the server starts
+//! the binary found at its path again, with the same arguments and
+//! environment, and passes it the listening socket the way systemd socket
+//! activation does. Once the successor reports it is ready, this process
+//! stops accepting connections and drains the ones it has, while the
+//! successor keeps accepting on the same socket: no connection is refused
+//! during a deploy, with or without a load balancer in front.
+//!
+//! If the successor exits or is not ready within [`READY_TIMEOUT`], it is
+//! killed and this process keeps serving. Without the feature, or off Unix,
+//! `SIGUSR2` is not handled.
+
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+
+/// How long a successor has to report it is ready before the handover is
+/// abandoned
+pub const READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Environment variable giving a successor the socket to report its
+/// readiness on
+#[cfg_attr(not(all(unix, feature = "reload")), allow(dead_code))]
+const HANDOVER_SOCKET: &str = "APP_HANDOVER_SOCKET";
+
+/// Completes once a successor took over the listener
+#[derive(Clone)]
+pub struct HandedOver(watch::Receiver<bool>);
+
+impl HandedOver {
+    /// Waits for the handover, forever if there never is one
+    pub async fn wait(mut self) {
+        if self.0.wait_for(|handed_over| *handed_over).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Hands `listener` over to a successor on each `SIGUSR2`, until one takes
+/// it
+#[cfg(all(unix, feature = "reload"))]
+pub fn listen(listener: &TcpListener) -> HandedOver {
+    use std::os::fd::AsRawFd;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let fd = listener.as_raw_fd();
+    let (handed_over, receiver) = watch::channel(false);
+    tokio::spawn(async move {
+        let mut signals = match signal(SignalKind::user_defined2()) {
+            Ok(signals) => signals,
+            Err(err) => {
+                tracing::error!(%err, "failed to listen for SIGUSR2");
+                return;
+            }
+        };
+        while signals.recv().await.is_some() {
+            tracing::info!("SIGUSR2 received, starting a successor");
+            match hand_over(fd).await {
+                Ok(pid) => {
+                    tracing::info!(pid, "successor is ready, handing the listener over");
+                    crate::systemd::notify_main_pid(pid);
+                    let _ = handed_over.send(true);
+                    return;
+                }
+                Err(err) => tracing::error!(%err, "handover failed, still serving"),
+            }
+        }
+    });
+    HandedOver(receiver)
+}
+
+/// Hands `listener` over to a successor on each `SIGUSR2`, until one takes
+/// it
+#[cfg(not(all(unix, feature = "reload")))]
+pub fn listen(_listener: &TcpListener) -> HandedOver {
+    let (_, receiver) = watch::channel(false);
+    HandedOver(receiver)
+}
+
+/// Tells the process this one was started by that it can stop serving
+pub fn notify_predecessor() {
+    #[cfg(all(unix, feature = "reload"))]
+    if let Some(path) = std::env::var_os(HANDOVER_SOCKET) {
+        std::env::remove_var(HANDOVER_SOCKET);
+        let sent = std::os::unix::net::UnixDatagram::unbound()
+            .and_then(|socket| socket.send_to(b"READY=1", &path));
+        if let Err(err) = sent {
+            tracing::warn!(%err, "failed to notify the previous process");
+        }
+    }
+}
+
+/// Starts a successor sharing the listener `fd` and returns its PID once
+/// it is ready
+#[cfg(all(unix, feature = "reload"))]
+async fn hand_over(fd: std::os::fd::RawFd) -> std::io::Result<u32> {
+    use std::io;
+    use tokio::net::UnixDatagram;
+
+    let dir = std::env::temp_dir().join(format!("rust-api-handover-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("ready.sock");
+    let _ = std::fs::remove_file(&path);
+    let socket = UnixDatagram::bind(&path)?;
+
+    // The listener is only inheritable while the successor is spawned
+    inheritable(fd, true)?;
+    let spawned = tokio::process::Command::new(std::env::current_exe()?)
+        .args(std::env::args_os().skip(1))
+        .env("LISTEN_FDS", "1")
+        .env("LISTEN_FDS_FIRST_FD", fd.to_string())
+        .env_remove("LISTEN_PID")
+        .env_remove("LISTEN_FDNAMES")
+        .env(HANDOVER_SOCKET, &path)
+        .spawn();
+    inheritable(fd, false)?;
+    let mut child = spawned?;
+
+    let ready = async {
+        let mut buffer = [0; 64];
+        loop {
+            let len = socket.recv(&mut buffer).await?;
+            if &buffer[..len] == b"READY=1" {
+                return Ok(());
+            }
+        }
+    };
+    let result = tokio::select! {
+        ready = ready => ready,
+        status = child.wait() => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("successor exited early with {}", status?),
+        )),
+        () = tokio::time::sleep(READY_TIMEOUT) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "successor was not ready in time",
+        )),
+    };
+    let _ = std::fs::remove_dir_all(&dir);
+
+    match result.and_then(|()| {
+        child
+            .id()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "successor exited"))
+    }) {
+        Ok(pid) => Ok(pid),
+        Err(err) => {
+            let _ = child.kill().await;
+            Err(err)
+        }
+    }
+}
+
+/// Sets whether `fd` stays open in spawned processes
+#[cfg(all(unix, feature = "reload"))]
+fn inheritable(fd: std::os::fd::RawFd, inheritable: bool) -> std::io::Result<()> {
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+
+    let flags = if inheritable {
+        FdFlag::empty()
+    } else {
+        FdFlag::FD_CLOEXEC
+    };
+    fcntl(fd, FcntlArg::F_SETFD(flags))?;
+    Ok(())
+}
+
+#[cfg(all(test, unix, feature = "reload"))]
+mod tests {
+    use super::*;
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+    use std::os::fd::AsRawFd;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn test_predecessor_is_notified_once() {
+        let dir = std::env::temp_dir().join(format!("rust-api-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ready.sock");
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        std::env::set_var(HANDOVER_SOCKET, &path);
+
+        notify_predecessor();
+        let mut buffer = [0; 64];
+        let len = socket.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"READY=1");
+
+        // The variable is consumed, so processes started later do not
+        // report to a predecessor that is gone
+        assert!(std::env::var_os(HANDOVER_SOCKET).is_none());
+        notify_predecessor();
+        assert!(socket.recv(&mut buffer).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_listener_is_inheritable_only_while_spawning() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let fd = listener.as_raw_fd();
+        let cloexec = || {
+            FdFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFD).unwrap())
+                .contains(FdFlag::FD_CLOEXEC)
+        };
+        assert!(cloexec());
+        inheritable(fd, true).unwrap();
+        assert!(!cloexec());
+        inheritable(fd, false).unwrap();
+        assert!(cloexec());
+    }
+}
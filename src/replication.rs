@@ -0,0 +1,575 @@
+//! Replicating the store to follower and replica instances
+//!
+//! With `APP_REPLICATION_ROLE=leader`, an instance numbers every change to
+//! its store and keeps the IDs of the last [`LOG_CAPACITY`] changed users in
+//! a log. Followers (`APP_REPLICATION_ROLE=follower`, with the leader's URL
+//! in `APP_REPLICATION_LEADER`) copy the leader's store once through
+//! `GET /api/v1/admin/replication/snapshot`, then long-poll
+//! `GET /api/v1/admin/replication/changes` for everything stored under the
+//! changed IDs since the last index they applied. A follower that fell
+//! further behind than the log reaches, or whose leader restarted, copies
+//! the full store again.
+//!
+//! Followers answer reads from their copy and forward writes to the leader
+//! (see [`crate::proxy`]). The leader's write responses carry the index of
+//! the change in [`X_REPLICATION_INDEX`], and a follower waits up to
+//! [`CATCH_UP_TIMEOUT`] to apply it before answering, so clients read
+//! their own writes. Admin endpoints are never forwarded: they act on the
+//! instance they are sent to.
+//!
+//! Replicas (`APP_REPLICATION_ROLE=replica`) copy the leader's store the
+//! same way but serve reads only: every other request, admin endpoints
+//! included, is answered with `405 Method Not Allowed` and a `Location`
+//! header pointing at the same path on the leader, so clients can retry
+//! it there.
+//!
+//! The leader is fixed by configuration; there is no election, so writes
+//! fail while the leader is down. Followers call it through the
+//! `replication` entry of [`crate::AppState::outbound`], so a leader that
+//! keeps failing is not polled until its circuit breaker lets a probe
+//! through.
+
+use std::collections::{HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderName, HeaderValue, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::i18n::Message;
+use crate::metrics::Sample;
+use crate::models::{Snapshot, UserRecord};
+use crate::proxy::{self, Peer};
+use crate::AppState;
+
+/// Header carrying the index of the change a write was stored as
+pub const X_REPLICATION_INDEX: HeaderName = HeaderName::from_static("x-replication-index");
+
+/// Changes the leader keeps in its log
+pub const LOG_CAPACITY: usize = 10_000;
+
+/// Longest time a request for changes waits for one to happen
+pub const MAX_WAIT: Duration = Duration::from_secs(30);
+
+/// How long a follower waits to apply a forwarded write before answering
+pub const CATCH_UP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Pause before a follower retries after failing to reach the leader
+pub const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Part an instance plays in replication
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Stores writes and serves its changes to followers
+    Leader,
+    /// Copies the leader's store and forwards writes to it
+    Follower,
+    /// Copies the leader's store and refuses writes
+    Replica,
+}
+
+impl Role {
+    /// Whether this role copies the leader's store
+    pub fn replicates(self) -> bool {
+        matches!(self, Role::Follower | Role::Replica)
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "leader" => Ok(Role::Leader),
+            "follower" => Ok(Role::Follower),
+            "replica" => Ok(Role::Replica),
+            other => Err(format!(
+                "unknown replication role '{}' (expected leader, follower or replica)",
+                other
+            )),
+        }
+    }
+}
+
+/// Replication configuration
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    /// This instance's role; replication is off when unset
+    pub role: Option<Role>,
+    /// The leader followers and replicas replicate from
+    pub leader: Option<Peer>,
+    /// Bearer token followers and replicas present to the leader's admin
+    /// endpoints
+    pub token: Option<String>,
+}
+
+/// Query parameters for reading changes from the leader
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ChangesQuery {
+    /// Index of the last change already applied
+    #[serde(default)]
+    pub after: u64,
+    /// Seconds to wait for a change if there is none yet, up to 30
+    #[serde(default)]
+    pub wait: u64,
+}
+
+/// Changes since a given index, response of
+/// `GET /api/v1/admin/replication/changes`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Changes {
+    /// Identifies the leader's log; it changes when the leader restarts
+    pub log: Uuid,
+    /// Index of the last change included
+    pub index: u64,
+    /// Everything now stored under each changed user ID
+    pub records: Vec<UserRecord>,
+}
+
+/// The leader's full store, response of
+/// `GET /api/v1/admin/replication/snapshot`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ReplicationSnapshot {
+    /// Identifies the leader's log; it changes when the leader restarts
+    pub log: Uuid,
+    /// Index of the last change the snapshot includes
+    pub index: u64,
+    /// The store's contents
+    pub snapshot: Snapshot,
+}
+
+#[derive(Debug, Default)]
+struct Log {
+    /// Changed user IDs by index, oldest first
+    entries: VecDeque<(u64, Uuid)>,
+    /// Index of the newest change dropped from the log
+    truncated: u64,
+}
+
+/// This instance's replication state, in [`AppState::replication`]
+#[derive(Debug)]
+pub struct Replication {
+    role: Option<Role>,
+    leader: Option<Peer>,
+    client: Option<proxy::Client>,
+    /// The leader's log ID; on followers, that of the log last applied
+    log_id: Mutex<Uuid>,
+    log: Mutex<Log>,
+    /// Index of the last change appended (leader) or applied (follower)
+    index: watch::Sender<u64>,
+    synced: AtomicBool,
+    resyncs: AtomicU64,
+}
+
+impl Default for Replication {
+    fn default() -> Self {
+        Self::new(&Settings::default())
+    }
+}
+
+impl Replication {
+    /// Creates the replication state of `settings`
+    pub fn new(settings: &Settings) -> Self {
+        let replicates = settings.role.is_some_and(Role::replicates);
+        Self {
+            role: settings.role,
+            leader: settings.leader.clone(),
+            client: replicates.then(proxy::Client::new),
+            log_id: Mutex::new(Uuid::new_v4()),
+            log: Mutex::default(),
+            index: watch::Sender::new(0),
+            synced: AtomicBool::new(false),
+            resyncs: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns this instance's role, `None` when replication is off
+    pub fn role(&self) -> Option<Role> {
+        self.role
+    }
+
+    /// Returns the index of the last change appended or applied
+    pub fn index(&self) -> u64 {
+        *self.index.borrow()
+    }
+
+    /// Returns the ID of the leader's log
+    pub fn log_id(&self) -> Uuid {
+        *self
+            .log_id
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Whether a follower or replica has copied the leader's store at
+    /// least once
+    pub fn is_synced(&self) -> bool {
+        self.synced.load(Ordering::Relaxed)
+    }
+
+    /// Records a change to user `id` in the leader's log
+    ///
+    /// Called for every event, while the storage write lock is held, so
+    /// indexes follow the order of the changes.
+    pub fn append(&self, id: Uuid) {
+        let mut log = self.lock();
+        let index = self.index() + 1;
+        log.entries.push_back((index, id));
+        if log.entries.len() > LOG_CAPACITY {
+            if let Some((dropped, _)) = log.entries.pop_front() {
+                log.truncated = dropped;
+            }
+        }
+        self.index.send_replace(index);
+    }
+
+    /// Returns the last index and the IDs changed after index `after`,
+    /// each once, or `None` if the log no longer reaches back to `after`
+    ///
+    /// Callers hold the storage read lock, so the IDs match the storage.
+    pub fn changes(&self, after: u64) -> Option<(u64, Vec<Uuid>)> {
+        let log = self.lock();
+        let index = self.index();
+        if after < log.truncated || after > index {
+            return None;
+        }
+        let mut seen = HashSet::new();
+        let ids = log
+            .entries
+            .iter()
+            .filter(|(entry, id)| *entry > after && seen.insert(*id))
+            .map(|(_, id)| *id)
+            .collect();
+        Some((index, ids))
+    }
+
+    /// Waits until the index reaches `index`, for at most `timeout`
+    ///
+    /// Returns whether it was reached.
+    pub async fn wait_for(&self, index: u64, timeout: Duration) -> bool {
+        let mut reached = self.index.subscribe();
+        let waiting = reached.wait_for(|current| *current >= index);
+        let result = tokio::time::timeout(timeout, waiting).await;
+        matches!(result, Ok(Ok(_)))
+    }
+
+    /// Returns the replication gauges, empty when replication is off
+    pub fn samples(&self) -> Vec<Sample> {
+        let mut samples = match self.role {
+            None => return Vec::new(),
+            Some(_) => vec![Sample::gauge(
+                "replication_index",
+                "Index of the last change appended by the leader or applied by a follower",
+                self.index() as f64,
+            )],
+        };
+        if self.role.is_some_and(Role::replicates) {
+            samples.push(Sample::counter(
+                "replication_resyncs_total",
+                "Full copies of the leader's store after the change log was missed",
+                self.resyncs.load(Ordering::Relaxed),
+            ));
+        }
+        samples
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Log> {
+        self.log
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn is_write(method: &Method) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Middleware numbering writes on the leader, forwarding them from
+/// followers and refusing them on replicas
+pub async fn route(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let replication = &state.replication;
+    let write = is_write(request.method());
+    match replication.role {
+        Some(Role::Leader) if write => {
+            let mut response = next.run(request).await;
+            response
+                .headers_mut()
+                .insert(X_REPLICATION_INDEX, HeaderValue::from(replication.index()));
+            response
+        }
+        Some(Role::Follower) if write => {
+            let path = request.uri().path();
+            if !path.starts_with("/api/") || path.starts_with("/api/v1/admin/") {
+                return next.run(request).await;
+            }
+            let (Some(leader), Some(client)) = (&replication.leader, &replication.client) else {
+                return next.run(request).await;
+            };
+            match client.forward(leader, request).await {
+                Ok(response) => {
+                    let index = response
+                        .headers()
+                        .get(X_REPLICATION_INDEX)
+                        .and_then(|value| value.to_str().ok()?.parse().ok());
+                    if let Some(index) = index {
+                        if !replication.wait_for(index, CATCH_UP_TIMEOUT).await {
+                            tracing::debug!(index, "forwarded write not yet replicated");
+                        }
+                    }
+                    response
+                }
+                Err(err) => {
+                    tracing::warn!(leader = %leader, %err, "failed to forward write to leader");
+                    ApiError::ServiceUnavailable(Message::new("replication.leader_unavailable"))
+                        .into_response()
+                }
+            }
+        }
+        Some(Role::Replica) if write => match &replication.leader {
+            Some(leader) => read_only(leader, &request),
+            None => next.run(request).await,
+        },
+        _ => next.run(request).await,
+    }
+}
+
+/// Answers a write sent to a replica with where to send it instead
+fn read_only(leader: &Peer, request: &Request) -> Response {
+    let path = request
+        .uri()
+        .path_and_query()
+        .map_or(request.uri().path(), |path| path.as_str());
+    let mut response =
+        ApiError::MethodNotAllowed(Message::new("replication.read_only").with("leader", leader))
+            .into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::ALLOW, HeaderValue::from_static("GET, HEAD"));
+    if let Ok(location) = HeaderValue::from_str(&format!("{}{}", leader, path)) {
+        headers.insert(header::LOCATION, location);
+    }
+    response
+}
+
+/// Starts copying the leader's store on a follower or replica
+///
+/// Returns the replicating task, or `None` unless this instance is a
+/// follower or a replica.
+#[cfg(feature = "client")]
+pub fn start(state: &AppState) -> Option<tokio::task::JoinHandle<()>> {
+    let replication = &state.replication;
+    let (Some(true), Some(leader), Some(client)) = (
+        replication.role.map(Role::replicates),
+        replication.leader.clone(),
+        replication.client.clone(),
+    ) else {
+        return None;
+    };
+    tracing::info!(leader = %leader, "replicating from leader");
+    let state = state.clone();
+    Some(tokio::spawn(async move {
+        let follower = Follower {
+            state: &state,
+            leader: &leader,
+            http: client.http(),
+        };
+        let mut synced = false;
+        loop {
+            let result = if synced {
+                follower.pull().await
+            } else {
+                follower.sync().await
+            };
+            match result {
+                Ok(current) => {
+                    if synced && !current {
+                        state.replication.resyncs.fetch_add(1, Ordering::Relaxed);
+                        tracing::warn!("missed changes of the leader, copying its store again");
+                    }
+                    synced = current;
+                }
+                Err(err) => {
+                    tracing::warn!(leader = %leader, %err, "failed to replicate from leader");
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(feature = "client")]
+struct Follower<'a> {
+    state: &'a AppState,
+    leader: &'a Peer,
+    http: &'a reqwest::Client,
+}
+
+#[cfg(feature = "client")]
+impl Follower<'_> {
+    fn get(&self, path: &str, timeout: Duration) -> reqwest::RequestBuilder {
+        // Full precision, whatever the leader's default timestamp format
+        let request = self
+            .http
+            .get(format!("{}{}", self.leader, path))
+            .header(
+                axum::http::header::ACCEPT,
+                "application/json; timestamps=rfc3339",
+            )
+            .timeout(timeout);
+        match &self.state.config.replication.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Sends a GET request to the leader through its circuit breaker
+    ///
+    /// Server errors count as failures; other statuses are left to the
+    /// caller.
+    async fn send(&self, path: &str, timeout: Duration) -> Result<reqwest::Response, String> {
+        self.state
+            .outbound
+            .get("replication")
+            .call(|| async move {
+                let response = self.get(path, timeout).send().await?;
+                if response.status().is_server_error() {
+                    response.error_for_status()
+                } else {
+                    Ok(response)
+                }
+            })
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    /// Replaces the local store with the leader's; returns `true` once done
+    async fn sync(&self) -> Result<bool, String> {
+        let response = self
+            .send("/api/v1/admin/replication/snapshot", proxy::FORWARD_TIMEOUT)
+            .await?
+            .error_for_status()
+            .map_err(|err| err.to_string())?;
+        let copy: ReplicationSnapshot = response.json().await.map_err(|err| err.to_string())?;
+
+        let replication = &self.state.replication;
+        let mut storage = self.state.storage.write().await;
+        crate::snapshot::replace(&mut storage, &self.state.events, copy.snapshot)
+            .map_err(|full| format!("the leader holds more than {} users", full.max_users))?;
+        *replication
+            .log_id
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = copy.log;
+        replication.index.send_replace(copy.index);
+        replication.synced.store(true, Ordering::Relaxed);
+        drop(storage);
+        tracing::info!(index = copy.index, "copied the leader's store");
+        Ok(true)
+    }
+
+    /// Applies the leader's next changes; returns `false` if they cannot
+    /// be applied and the store has to be copied again
+    async fn pull(&self) -> Result<bool, String> {
+        let replication = &self.state.replication;
+        let path = format!(
+            "/api/v1/admin/replication/changes?after={}&wait={}",
+            replication.index(),
+            MAX_WAIT.as_secs()
+        );
+        let response = self.send(&path, MAX_WAIT + proxy::FORWARD_TIMEOUT).await?;
+        if response.status() == axum::http::StatusCode::GONE {
+            return Ok(false);
+        }
+        let response = response.error_for_status().map_err(|err| err.to_string())?;
+        let changes: Changes = response.json().await.map_err(|err| err.to_string())?;
+        if changes.log != replication.log_id() {
+            return Ok(false);
+        }
+
+        let mut storage = self.state.storage.write().await;
+        for record in changes.records {
+            let event = match &record.user {
+                Some(user) => {
+                    let diff = match storage.get(&record.id) {
+                        Some(previous) => crate::events::FieldDiff::between(&previous, user),
+                        None => crate::events::FieldDiff::all(user),
+                    };
+                    crate::events::Event::UserUpdated(user.clone(), diff)
+                }
+                None => crate::events::Event::UserDeleted(record.id),
+            };
+            storage.apply(record);
+            self.state.events.publish(event);
+        }
+        replication.index.send_replace(changes.index);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leader() -> Replication {
+        Replication::new(&Settings {
+            role: Some(Role::Leader),
+            ..Settings::default()
+        })
+    }
+
+    #[test]
+    fn test_parse_role() {
+        assert_eq!(" Leader ".parse(), Ok(Role::Leader));
+        assert_eq!("follower".parse(), Ok(Role::Follower));
+        assert_eq!("REPLICA".parse(), Ok(Role::Replica));
+        assert!(Role::Replica.replicates() && !Role::Leader.replicates());
+        assert!("candidate".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn test_changes_since_index() {
+        let replication = leader();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(replication.changes(0), Some((0, Vec::new())));
+        replication.append(a);
+        replication.append(b);
+        replication.append(a);
+        assert_eq!(replication.index(), 3);
+        assert_eq!(replication.changes(0), Some((3, vec![a, b])));
+        assert_eq!(replication.changes(2), Some((3, vec![a])));
+        assert_eq!(replication.changes(3), Some((3, Vec::new())));
+        // A follower ahead of the leader missed a restart
+        assert_eq!(replication.changes(4), None);
+    }
+
+    #[test]
+    fn test_log_is_bounded() {
+        let replication = leader();
+        for _ in 0..LOG_CAPACITY + 2 {
+            replication.append(Uuid::new_v4());
+        }
+        assert_eq!(replication.changes(1), None);
+        let (index, ids) = replication.changes(2).unwrap();
+        assert_eq!(index, LOG_CAPACITY as u64 + 2);
+        assert_eq!(ids.len(), LOG_CAPACITY);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_index() {
+        let replication = std::sync::Arc::new(leader());
+        assert!(!replication.wait_for(1, Duration::from_millis(10)).await);
+        let appending = replication.clone();
+        tokio::spawn(async move { appending.append(Uuid::new_v4()) });
+        assert!(replication.wait_for(1, Duration::from_secs(5)).await);
+    }
+}
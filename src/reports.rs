@@ -0,0 +1,386 @@
+//! Periodic reports on the users
+//!
+//! `POST /api/v1/admin/reports` queues a job writing a report, and with
+//! `APP_REPORT_INTERVAL_SECONDS` set the server also writes one every
+//! interval. Reports are CSV files, which spreadsheets such as Excel open
+//! as they are, stored in [`crate::AppState::blobs`] under [`PREFIX`]
+//! through the `blobs` entry of [`crate::AppState::outbound`].
+//! `GET /api/v1/admin/reports` lists them, newest first, and
+//! `GET /api/v1/admin/reports/:name` downloads one.
+//!
+//! A report has one row for each of the last `APP_REPORT_WEEKS` weeks,
+//! Monday to Sunday in UTC, oldest first:
+//!
+//! * `new_users` counts the users created that week, including those since
+//!   deleted; users merged into another are not counted
+//! * `churned_users` counts the users deleted that week, and those
+//!   deactivated that week that still exist, going by their last update
+
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Days, NaiveDate, Utc};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use utoipa::ToSchema;
+
+use crate::blob::{Blob, BlobInfo, BlobStore};
+use crate::jobs::{Operation, OperationKind, OperationResult};
+use crate::models::{Snapshot, UserStatus};
+use crate::resilience::ResilienceError;
+use crate::AppState;
+
+/// Prefix of the keys reports are stored under in the blob store
+pub const PREFIX: &str = "reports/";
+
+/// Content type of reports
+pub const CONTENT_TYPE: &str = "text/csv; charset=utf-8";
+
+/// When reports are written and what they cover
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    /// Time between two scheduled reports; reports are only written on
+    /// request when `None`
+    pub interval: Option<Duration>,
+    /// Number of weeks a report covers
+    pub weeks: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            interval: None,
+            weeks: 12,
+        }
+    }
+}
+
+/// A stored report, as listed by `GET /api/v1/admin/reports`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Report {
+    /// Name to download the report by
+    pub name: String,
+    /// Media type of the report
+    pub content_type: String,
+    /// Size of the report in bytes
+    pub size: usize,
+    /// When the report was written
+    #[serde(with = "crate::timestamps")]
+    #[schema(schema_with = crate::timestamps::schema)]
+    pub created_at: DateTime<Utc>,
+}
+
+impl Report {
+    fn new(name: String, info: BlobInfo) -> Self {
+        Self {
+            name,
+            content_type: info.content_type,
+            size: info.size,
+            created_at: info.created_at,
+        }
+    }
+}
+
+/// One row of a report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Week {
+    /// The Monday the week starts on
+    pub start: NaiveDate,
+    /// Users created during the week
+    pub new_users: usize,
+    /// Users deleted or deactivated during the week
+    pub churned_users: usize,
+}
+
+/// Returns the Monday of the week `at` falls in
+fn week_of(at: DateTime<Utc>) -> NaiveDate {
+    let date = at.date_naive();
+    date - Days::new(date.weekday().num_days_from_monday().into())
+}
+
+/// Counts the new and churned users of the `weeks` weeks up to the one
+/// `snapshot` was taken in, oldest first
+pub fn weekly(snapshot: &Snapshot, weeks: usize) -> Vec<Week> {
+    let mut new_users: HashMap<NaiveDate, usize> = HashMap::new();
+    let mut churned_users: HashMap<NaiveDate, usize> = HashMap::new();
+    for user in &snapshot.users {
+        *new_users.entry(week_of(user.created_at)).or_default() += 1;
+        if user.status == UserStatus::Deactivated {
+            *churned_users.entry(week_of(user.updated_at)).or_default() += 1;
+        }
+    }
+    for trashed in &snapshot.trash {
+        *new_users
+            .entry(week_of(trashed.user.created_at))
+            .or_default() += 1;
+    }
+    for tombstone in snapshot.tombstones.values() {
+        if tombstone.merged_into.is_none() {
+            *churned_users.entry(week_of(tombstone.at)).or_default() += 1;
+        }
+    }
+
+    let current = week_of(snapshot.taken_at);
+    (0..weeks)
+        .rev()
+        .map(|ago| {
+            let start = current - Days::new(7 * ago as u64);
+            Week {
+                start,
+                new_users: new_users.get(&start).copied().unwrap_or_default(),
+                churned_users: churned_users.get(&start).copied().unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+/// Writes `weeks` as CSV with a header row
+pub fn to_csv(weeks: &[Week]) -> String {
+    let mut csv = String::from("week_start,new_users,churned_users\n");
+    for week in weeks {
+        csv.push_str(&format!(
+            "{},{},{}\n",
+            week.start, week.new_users, week.churned_users
+        ));
+    }
+    csv
+}
+
+/// Returns the name of the report written at `at`
+pub fn name(at: DateTime<Utc>) -> String {
+    format!("users-weekly-{}.csv", at.format("%Y%m%dT%H%M%S%.3fZ"))
+}
+
+/// Writes a report on the current users to the blob store
+///
+/// # Errors
+///
+/// Returns an error if the blob store cannot store the report
+pub async fn generate(state: &AppState) -> io::Result<Report> {
+    let snapshot = state.storage.read().await.snapshot(state.clock.now());
+    let weeks = weekly(&snapshot, state.config.reports.weeks);
+    let blob = Blob {
+        content_type: CONTENT_TYPE.to_string(),
+        created_at: snapshot.taken_at,
+        data: to_csv(&weeks).into_bytes(),
+    };
+    let name = name(snapshot.taken_at);
+    let key = format!("{}{}", PREFIX, name);
+    let report = Report::new(name, blob.info(&key));
+    blobs(state, |blobs| blobs.put(&key, blob.clone())).await?;
+    Ok(report)
+}
+
+/// Lists the stored reports, newest first
+///
+/// # Errors
+///
+/// Returns an error if the blob store cannot be read
+pub async fn list(state: &AppState) -> io::Result<Vec<Report>> {
+    let mut reports: Vec<Report> = blobs(state, |blobs| blobs.list(PREFIX))
+        .await?
+        .into_iter()
+        .filter_map(|info| {
+            let name = info.key.strip_prefix(PREFIX)?.to_string();
+            Some(Report::new(name, info))
+        })
+        .collect();
+    reports.sort_by(|a, b| (b.created_at, &b.name).cmp(&(a.created_at, &a.name)));
+    Ok(reports)
+}
+
+/// Reads the report called `name`
+///
+/// # Errors
+///
+/// Returns an error if the blob store cannot be read
+pub async fn get(state: &AppState, name: &str) -> io::Result<Option<Blob>> {
+    let key = format!("{}{}", PREFIX, name);
+    blobs(state, |blobs| blobs.get(&key)).await
+}
+
+/// Calls the blob store through the `blobs` entry of
+/// [`AppState::outbound`]
+async fn blobs<'a, T>(
+    state: &'a AppState,
+    mut call: impl FnMut(&'a dyn BlobStore) -> BoxFuture<'a, io::Result<T>>,
+) -> io::Result<T> {
+    state
+        .outbound
+        .get("blobs")
+        .call(|| call(state.blobs.as_ref()))
+        .await
+        .map_err(|err| match err {
+            ResilienceError::Open => {
+                io::Error::new(io::ErrorKind::Other, "the blob store is unavailable")
+            }
+            ResilienceError::Failed(err) => err,
+        })
+}
+
+/// Queues a job writing a report
+pub fn submit(state: &AppState) -> Operation {
+    let jobs = state.jobs.clone();
+    let state = state.clone();
+    jobs.submit(
+        state.clock,
+        OperationKind::Report,
+        1,
+        |progress| async move {
+            let report = generate(&state).await.map_err(|err| err.to_string())?;
+            progress.advance(1);
+            tracing::info!(report = %report.name, "report written");
+            Ok(OperationResult::Report { report })
+        },
+    )
+}
+
+/// Queues a report every `APP_REPORT_INTERVAL_SECONDS` until the returned
+/// task is aborted; returns `None` when no interval is set
+pub fn spawn(state: AppState) -> Option<JoinHandle<()>> {
+    let interval = state.config.reports.interval?;
+    Some(tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // The first tick completes immediately; report after a full interval
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            submit(&state);
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::models::{Tombstone, TrashedUser, User};
+
+    fn at(day: u32) -> DateTime<Utc> {
+        // 2024-01-01 is a Monday
+        Utc.with_ymd_and_hms(2024, 1, day, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_week_of() {
+        assert_eq!(week_of(at(1)), at(1).date_naive());
+        assert_eq!(week_of(at(7)), at(1).date_naive());
+        assert_eq!(week_of(at(8)), at(8).date_naive());
+    }
+
+    #[test]
+    fn test_weekly() {
+        let mut deactivated = User::new("Ada", "ada@example.com", at(2));
+        deactivated.status = UserStatus::Deactivated;
+        deactivated.updated_at = at(16);
+        let deleted = User::new("Ada", "ada@example.com", at(9));
+        let merged = User::new("Ada", "ada@example.com", at(10));
+        let snapshot = Snapshot {
+            taken_at: at(17),
+            users: vec![
+                User::new("Ada", "ada@example.com", at(3)),
+                User::new("Ada", "ada@example.com", at(15)),
+                deactivated,
+            ],
+            trash: vec![TrashedUser {
+                user: Arc::new(deleted.clone()),
+                deleted_at: at(11),
+                deleted_by: None,
+            }],
+            tombstones: BTreeMap::from([
+                (
+                    deleted.id,
+                    Tombstone {
+                        merged_into: None,
+                        at: at(11),
+                    },
+                ),
+                (
+                    merged.id,
+                    Tombstone {
+                        merged_into: Some(deleted.id),
+                        at: at(12),
+                    },
+                ),
+            ]),
+            rate_limits: BTreeMap::new(),
+        };
+
+        let weeks = weekly(&snapshot, 4);
+        assert_eq!(
+            to_csv(&weeks),
+            "week_start,new_users,churned_users\n\
+             2023-12-25,0,0\n\
+             2024-01-01,2,0\n\
+             2024-01-08,1,1\n\
+             2024-01-15,1,1\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generate_and_list() {
+        let state = AppState::new();
+        assert!(list(&state).await.unwrap().is_empty());
+
+        let report = generate(&state).await.unwrap();
+        assert!(report.name.starts_with("users-weekly-"));
+        assert_eq!(report.content_type, CONTENT_TYPE);
+
+        let listed = list(&state).await.unwrap();
+        assert_eq!(listed, vec![report.clone()]);
+        let blob = get(&state, &report.name).await.unwrap().unwrap();
+        let csv = String::from_utf8(blob.data).unwrap();
+        assert_eq!(csv.lines().count(), 1 + Settings::default().weeks);
+        assert!(get(&state, "missing.csv").await.unwrap().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_generate_retries_blob_store() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        use crate::blob::MemoryBlobs;
+
+        /// Fails the first write, then stores in memory
+        #[derive(Default)]
+        struct Flaky {
+            failed: AtomicBool,
+            blobs: MemoryBlobs,
+        }
+
+        impl BlobStore for Flaky {
+            fn name(&self) -> &str {
+                "flaky"
+            }
+
+            fn put<'a>(&'a self, key: &'a str, blob: Blob) -> BoxFuture<'a, io::Result<()>> {
+                if self.failed.swap(true, Ordering::Relaxed) {
+                    self.blobs.put(key, blob)
+                } else {
+                    Box::pin(async { Err(io::Error::new(io::ErrorKind::Other, "unavailable")) })
+                }
+            }
+
+            fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Option<Blob>>> {
+                self.blobs.get(key)
+            }
+
+            fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, io::Result<Vec<BlobInfo>>> {
+                self.blobs.list(prefix)
+            }
+        }
+
+        let state = AppState {
+            blobs: Arc::new(Flaky::default()),
+            ..AppState::new()
+        };
+        let report = generate(&state).await.unwrap();
+        assert!(get(&state, &report.name).await.unwrap().is_some());
+    }
+}
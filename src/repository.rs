@@ -0,0 +1,165 @@
+//! The operations a user storage backend provides
+//!
+//! [`UserRepository`] is the part of [`Storage`] a new backend has to get
+//! right for the API to behave the same on it: creating, reading, replacing
+//! and deleting users while keeping email addresses and phone numbers
+//! unique. It is what the checks of the `testing` module run against, with
+//! the `testing` feature, so a backend implementing it can be certified
+//! before it stores real users.
+//!
+//! Implementations are used through an exclusive borrow for writes, so
+//! each method is one atomic step: a refused write must leave the store
+//! exactly as it was.
+
+use uuid::Uuid;
+
+use crate::models::{StorageError, User};
+use crate::Storage;
+
+/// A store of users
+pub trait UserRepository {
+    /// Identifies the backend in reports
+    fn name(&self) -> &str;
+
+    /// Creates a user unless its ID, email address or phone number is
+    /// taken
+    ///
+    /// # Errors
+    ///
+    /// Returns which unique value is already taken, leaving the store
+    /// unchanged.
+    fn create(&mut self, user: User) -> Result<(), StorageError>;
+
+    /// Reads a user
+    fn get(&self, id: &Uuid) -> Option<User>;
+
+    /// Returns every stored user, in no particular order
+    fn list(&self) -> Vec<User>;
+
+    /// Returns up to `limit` users after skipping `offset`, ordered by
+    /// creation time and then ID
+    ///
+    /// The order is stable, so consecutive pages neither repeat nor skip
+    /// users while the store is unchanged. By default [`list`] is sorted;
+    /// backends that can should page where the users are stored.
+    ///
+    /// [`list`]: UserRepository::list
+    fn page(&self, offset: usize, limit: usize) -> Vec<User> {
+        let mut users = self.list();
+        users.sort_by_key(|user| (user.created_at, user.id));
+        users.into_iter().skip(offset).take(limit).collect()
+    }
+
+    /// Returns the number of stored users
+    ///
+    /// By default the users of [`list`] are counted; backends that can
+    /// should count where the users are stored.
+    ///
+    /// [`list`]: UserRepository::list
+    fn count(&self) -> usize {
+        self.list().len()
+    }
+
+    /// Returns the revision of a user, which grows with every write to it
+    fn revision(&self, id: &Uuid) -> Option<u64>;
+
+    /// Replaces the stored user with the ID of `user` unless the change
+    /// gives it an email address or phone number another user holds
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::NotFound`] if no user has the ID, or which
+    /// unique value is already taken, leaving the store unchanged.
+    fn update(&mut self, user: User) -> Result<(), StorageError>;
+
+    /// Like [`update`](UserRepository::update), but only if the stored
+    /// user is still at the `expected` revision
+    ///
+    /// Comparing and writing must be one atomic step.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::Conflict`] with the current revision if it
+    /// no longer matches, or the errors of `update`, leaving the store
+    /// unchanged.
+    fn compare_and_update(&mut self, user: User, expected: u64) -> Result<(), StorageError>;
+
+    /// Deletes a user
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StorageError::NotFound`] if no user has the ID.
+    fn delete(&mut self, id: &Uuid) -> Result<(), StorageError>;
+
+    /// Finds the user holding an email address, or a homograph or alias of
+    /// it
+    fn find_by_email(&self, email: &str) -> Option<Uuid>;
+}
+
+impl UserRepository for Storage {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn create(&mut self, user: User) -> Result<(), StorageError> {
+        self.create_if_email_free(user)
+    }
+
+    fn get(&self, id: &Uuid) -> Option<User> {
+        Storage::get(self, id)
+    }
+
+    fn list(&self) -> Vec<User> {
+        self.get_all()
+            .into_iter()
+            .map(|user| User::clone(&user))
+            .collect()
+    }
+
+    fn page(&self, offset: usize, limit: usize) -> Vec<User> {
+        // Sorting the shared users only clones those on the page
+        let mut users = self.get_all();
+        users.sort_by_key(|user| (user.created_at, user.id));
+        users
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .map(|user| User::clone(&user))
+            .collect()
+    }
+
+    fn count(&self) -> usize {
+        self.len()
+    }
+
+    fn revision(&self, id: &Uuid) -> Option<u64> {
+        Storage::revision(self, id)
+    }
+
+    fn update(&mut self, user: User) -> Result<(), StorageError> {
+        let id = user.id;
+        self.update_with_email_claim(&id, |stored| *stored = user)
+            .map(|_| ())
+    }
+
+    fn compare_and_update(&mut self, user: User, expected: u64) -> Result<(), StorageError> {
+        let id = user.id;
+        let current = Storage::revision(self, &id).ok_or(StorageError::NotFound(id))?;
+        if current != expected {
+            return Err(StorageError::Conflict {
+                id,
+                expected,
+                current,
+            });
+        }
+        UserRepository::update(self, user)
+    }
+
+    fn delete(&mut self, id: &Uuid) -> Result<(), StorageError> {
+        Storage::delete(self, id)
+    }
+
+    fn find_by_email(&self, email: &str) -> Option<Uuid> {
+        Storage::find_by_email(self, email)
+    }
+}
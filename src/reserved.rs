@@ -0,0 +1,260 @@
+//! Reserved names and emails, and terms blocked in names
+//!
+//! Creating a user, or changing its name or email, is refused with
+//! `422 Unprocessable Entity` and the `reserved` error code when:
+//!
+//! - the email is reserved: entries ending in `@`, such as `admin@`, match
+//!   that mailbox on every domain, others match one address. Mailboxes are
+//!   compared without their `+tag` and homographs, so neither
+//!   `admin+x@example.com` nor a Cyrillic `аdmin@example.com` slips
+//!   through.
+//! - the whole name is reserved, ignoring case, spacing and punctuation
+//! - the name contains a blocked term as a whole word, or as consecutive
+//!   words for terms of several words. Words are matched whole, so a term
+//!   never blocks the longer, harmless words it happens to be part of.
+//!
+//! The lists start from `APP_RESERVED_EMAILS`, `APP_RESERVED_NAMES` and
+//! `APP_BLOCKED_TERMS`, and are replaced at runtime through
+//! `PUT /api/v1/admin/reserved`. Users stored before an entry was added
+//! keep their names and emails.
+
+use std::sync::RwLock;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::error::ApiError;
+use crate::i18n::Message;
+use crate::validation::email::{homograph_key, Canonicalization};
+
+/// The reserved and blocked entries, body of `GET` and
+/// `PUT /api/v1/admin/reserved`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ReservedLists {
+    /// Reserved addresses, or mailboxes on every domain when ending in `@`
+    #[serde(default)]
+    pub emails: Vec<String>,
+    /// Names no user may have
+    #[serde(default)]
+    pub names: Vec<String>,
+    /// Words or phrases no name may contain
+    #[serde(default)]
+    pub blocked_terms: Vec<String>,
+}
+
+impl Default for ReservedLists {
+    fn default() -> Self {
+        Self {
+            emails: vec![
+                "admin@".to_string(),
+                "root@".to_string(),
+                "support@".to_string(),
+            ],
+            names: vec![
+                "admin".to_string(),
+                "root".to_string(),
+                "support".to_string(),
+            ],
+            blocked_terms: Vec::new(),
+        }
+    }
+}
+
+impl ReservedLists {
+    /// Returns the lists with entries trimmed, lowercased, sorted and
+    /// without duplicates or blanks
+    pub fn normalized(self) -> Self {
+        let normalize = |entries: Vec<String>| {
+            let mut entries: Vec<String> = entries
+                .iter()
+                .map(|entry| words(entry).join(" "))
+                .filter(|entry| !entry.is_empty())
+                .collect();
+            entries.sort();
+            entries.dedup();
+            entries
+        };
+        let mut emails: Vec<String> = self
+            .emails
+            .iter()
+            .map(|entry| entry.trim().to_lowercase())
+            .filter(|entry| !entry.is_empty())
+            .collect();
+        emails.sort();
+        emails.dedup();
+        Self {
+            emails,
+            names: normalize(self.names),
+            blocked_terms: normalize(self.blocked_terms),
+        }
+    }
+
+    /// Checks a normalized email address against the reserved emails
+    pub fn check_email(&self, email: &str) -> Result<(), Message> {
+        let key = Canonicalization::Subaddress.duplicate_key(&email.to_lowercase());
+        let mailbox = key
+            .rsplit_once('@')
+            .map_or(key.as_str(), |(local, _)| local);
+        let reserved = self
+            .emails
+            .iter()
+            .any(|entry| match entry.strip_suffix('@') {
+                Some(reserved) => homograph_key(reserved) == mailbox,
+                None => Canonicalization::Subaddress.duplicate_key(entry) == key,
+            });
+        if reserved {
+            return Err(Message::new("reserved.email").with("email", email));
+        }
+        Ok(())
+    }
+
+    /// Checks a normalized name against the reserved names and blocked
+    /// terms
+    pub fn check_name(&self, name: &str) -> Result<(), Message> {
+        let words = words(name);
+        if self
+            .names
+            .iter()
+            .any(|reserved| *reserved == words.join(" "))
+        {
+            return Err(Message::new("reserved.name").with("name", name));
+        }
+        let blocked = self.blocked_terms.iter().any(|term| {
+            let term: Vec<&str> = term.split(' ').collect();
+            words
+                .windows(term.len())
+                .any(|window| window.iter().map(String::as_str).eq(term.iter().copied()))
+        });
+        if blocked {
+            return Err(Message::new("reserved.blocked_term"));
+        }
+        Ok(())
+    }
+}
+
+/// Splits `text` into lowercase words of letters and digits
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// The lists in effect, shared by the handlers checking them and the admin
+/// endpoint replacing them
+#[derive(Debug, Default)]
+pub struct Reserved(RwLock<ReservedLists>);
+
+impl Reserved {
+    /// Creates the lists in effect from `lists`
+    pub fn new(lists: ReservedLists) -> Self {
+        Self(RwLock::new(lists.normalized()))
+    }
+
+    /// Returns the lists in effect
+    pub fn lists(&self) -> ReservedLists {
+        self.0
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Replaces the lists in effect, returning the normalized lists
+    pub fn replace(&self, lists: ReservedLists) -> ReservedLists {
+        let lists = lists.normalized();
+        *self
+            .0
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = lists.clone();
+        lists
+    }
+
+    /// Checks a new or changed name and email, either of which may be
+    /// left unchanged
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ApiError::Reserved`] for the first one that is reserved
+    /// or, for names, contains a blocked term.
+    pub fn check(&self, name: Option<&str>, email: Option<&str>) -> Result<(), ApiError> {
+        let lists = self
+            .0
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(name) = name {
+            lists.check_name(name).map_err(ApiError::Reserved)?;
+        }
+        if let Some(email) = email {
+            lists.check_email(email).map_err(ApiError::Reserved)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lists() -> ReservedLists {
+        ReservedLists {
+            emails: vec![" Admin@ ".to_string(), "noreply@example.com".to_string()],
+            names: vec!["Root".to_string(), "system  administrator".to_string()],
+            blocked_terms: vec!["darn".to_string(), "heck  no".to_string()],
+        }
+        .normalized()
+    }
+
+    #[test]
+    fn test_reserved_emails() {
+        let lists = lists();
+        assert_eq!(lists.emails, ["admin@", "noreply@example.com"]);
+        for email in [
+            "admin@example.com",
+            "ADMIN@other.org",
+            "admin+ops@example.com",
+            "\u{430}dmin@example.com",
+            "noreply@example.com",
+        ] {
+            assert!(lists.check_email(email).is_err(), "{}", email);
+        }
+        for email in ["administrator@example.com", "noreply@example.org"] {
+            assert!(lists.check_email(email).is_ok(), "{}", email);
+        }
+        assert_eq!(
+            lists.check_email("admin@example.com").unwrap_err().key(),
+            "reserved.email"
+        );
+    }
+
+    #[test]
+    fn test_reserved_names_and_blocked_terms() {
+        let lists = lists();
+        assert_eq!(lists.names, ["root", "system administrator"]);
+        assert!(lists.check_name("ROOT").is_err());
+        assert!(lists.check_name("System Administrator").is_err());
+        assert!(lists.check_name("Root Beer").is_ok());
+
+        let err = lists.check_name("Darn Smith").unwrap_err();
+        assert_eq!(err.key(), "reserved.blocked_term");
+        assert!(lists.check_name("Oh heck, no").is_err());
+        assert!(lists.check_name("Darnell Heck").is_ok());
+    }
+
+    #[test]
+    fn test_replace() {
+        let reserved = Reserved::new(ReservedLists::default());
+        assert!(reserved.check(Some("Support"), None).is_err());
+        assert!(reserved.check(None, Some("root@example.com")).is_err());
+
+        let lists = reserved.replace(ReservedLists {
+            emails: Vec::new(),
+            names: vec!["Support".to_string(), "support".to_string()],
+            blocked_terms: Vec::new(),
+        });
+        assert_eq!(lists.names, ["support"]);
+        assert_eq!(reserved.lists(), lists);
+        assert!(reserved.check(None, Some("root@example.com")).is_ok());
+        let err = reserved.check(Some("Support"), None).unwrap_err();
+        assert_eq!(err.code(), crate::error::ErrorCode::Reserved);
+    }
+}
@@ -0,0 +1,488 @@
+//! Circuit breaking and retries for outbound calls
+//!
+//! Every downstream dependency (webhook receivers, the mail relay, other
+//! services) gets its own [`CircuitBreaker`], wrapped together with a
+//! [`RetryPolicy`] in a [`Resilient`]. Failed calls are retried with
+//! exponential backoff. Once a dependency keeps failing, its breaker opens
+//! and calls fail fast, without touching the network, until the open
+//! period has passed. Then a single probe call is let through: if it
+//! succeeds the breaker closes again, otherwise it reopens.
+//!
+//! [`crate::AppState::outbound`] hands out one [`Resilient`] per dependency
+//! name: the metrics sinks, the replication client, the blob store of the
+//! reports. Their breakers report their state at `GET /metrics` and in
+//! `GET /readyz`.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Thresholds shared by all breakers and retry policies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    /// Consecutive failures that open a breaker
+    pub failure_threshold: u32,
+    /// How long an open breaker rejects calls before probing
+    pub open_duration: Duration,
+    /// Attempts per call, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled for every further retry
+    pub backoff: Duration,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+            max_attempts: 3,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+/// State of a circuit breaker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Calls pass through
+    Closed,
+    /// Calls are rejected without being attempted
+    Open,
+    /// A single probe call decides whether the breaker closes
+    HalfOpen,
+}
+
+impl BreakerState {
+    /// Numeric value reported in metrics
+    pub fn as_gauge(self) -> i64 {
+        match self {
+            BreakerState::Closed => 0,
+            BreakerState::Open => 1,
+            BreakerState::HalfOpen => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: BreakerState,
+    failures: u32,
+    opened_at: Option<Instant>,
+    probing: bool,
+    rejected: u64,
+}
+
+/// Circuit breaker guarding one downstream dependency
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    name: String,
+    failure_threshold: u32,
+    open_duration: Duration,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    /// Creates a closed breaker
+    pub fn new(name: impl Into<String>, settings: &Settings) -> Self {
+        Self {
+            name: name.into(),
+            failure_threshold: settings.failure_threshold.max(1),
+            open_duration: settings.open_duration,
+            inner: Mutex::new(Inner {
+                state: BreakerState::Closed,
+                failures: 0,
+                opened_at: None,
+                probing: false,
+                rejected: 0,
+            }),
+        }
+    }
+
+    /// Returns the name of the guarded dependency
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the current state
+    ///
+    /// An open breaker whose open period has passed reports
+    /// [`BreakerState::HalfOpen`].
+    pub fn state(&self) -> BreakerState {
+        let mut inner = self.lock();
+        self.refresh(&mut inner);
+        inner.state
+    }
+
+    /// Returns the number of calls rejected while open
+    pub fn rejected(&self) -> u64 {
+        self.lock().rejected
+    }
+
+    /// Asks permission for a call
+    ///
+    /// Returns `false` if the call must not be attempted. In the half-open
+    /// state only one probe call is permitted at a time.
+    pub fn try_acquire(&self) -> bool {
+        let mut inner = self.lock();
+        self.refresh(&mut inner);
+        let permitted = match inner.state {
+            BreakerState::Closed => true,
+            BreakerState::Open => false,
+            BreakerState::HalfOpen => !std::mem::replace(&mut inner.probing, true),
+        };
+        if !permitted {
+            inner.rejected += 1;
+        }
+        permitted
+    }
+
+    /// Records a successful call, closing the breaker
+    pub fn record_success(&self) {
+        let mut inner = self.lock();
+        inner.state = BreakerState::Closed;
+        inner.failures = 0;
+        inner.opened_at = None;
+        inner.probing = false;
+    }
+
+    /// Records a failed call, opening the breaker once the threshold is
+    /// reached or when a probe fails
+    pub fn record_failure(&self) {
+        let mut inner = self.lock();
+        inner.failures = inner.failures.saturating_add(1);
+        if inner.state == BreakerState::HalfOpen || inner.failures >= self.failure_threshold {
+            if inner.state != BreakerState::Open {
+                tracing::warn!(breaker = %self.name, "circuit breaker opened");
+            }
+            inner.state = BreakerState::Open;
+            inner.opened_at = Some(Instant::now());
+            inner.probing = false;
+        }
+    }
+
+    /// Gives back the probe of a half-open breaker whose call ended with
+    /// neither success nor failure, e.g. because it was cancelled
+    fn release(&self) {
+        let mut inner = self.lock();
+        if inner.state == BreakerState::HalfOpen {
+            inner.probing = false;
+        }
+    }
+
+    /// Moves an open breaker to half-open once its open period has passed
+    fn refresh(&self, inner: &mut Inner) {
+        if inner.state == BreakerState::Open
+            && inner
+                .opened_at
+                .is_some_and(|at| at.elapsed() >= self.open_duration)
+        {
+            inner.state = BreakerState::HalfOpen;
+            inner.probing = false;
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Exponential backoff between attempts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts per call, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Creates a policy from the shared settings
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            max_attempts: settings.max_attempts.max(1),
+            backoff: settings.backoff,
+        }
+    }
+
+    /// Returns the delay before retry number `retry` (starting at 1)
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+    }
+}
+
+/// Error returned by [`Resilient::call`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResilienceError<E> {
+    /// The breaker is open; the call was not attempted
+    Open,
+    /// Every attempt failed; holds the last error
+    Failed(E),
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for ResilienceError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResilienceError::Open => write!(f, "circuit breaker is open"),
+            ResilienceError::Failed(err) => err.fmt(f),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for ResilienceError<E> {}
+
+/// A call permitted by [`CircuitBreaker::try_acquire`]
+///
+/// Dropping it before recording an outcome releases the probe, so a
+/// cancelled probe does not leave the breaker half-open for good.
+struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    recorded: bool,
+}
+
+impl Permit<'_> {
+    fn record(mut self, success: bool) {
+        self.recorded = true;
+        if success {
+            self.breaker.record_success();
+        } else {
+            self.breaker.record_failure();
+        }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.breaker.release();
+        }
+    }
+}
+
+/// A circuit breaker combined with a retry policy
+#[derive(Debug)]
+pub struct Resilient {
+    breaker: Arc<CircuitBreaker>,
+    retry: RetryPolicy,
+}
+
+impl Resilient {
+    /// Creates a wrapper for the dependency `name`
+    pub fn new(name: impl Into<String>, settings: &Settings) -> Self {
+        Self {
+            breaker: Arc::new(CircuitBreaker::new(name, settings)),
+            retry: RetryPolicy::new(settings),
+        }
+    }
+
+    /// Returns the breaker, e.g. to register it for metrics
+    pub fn breaker(&self) -> &Arc<CircuitBreaker> {
+        &self.breaker
+    }
+
+    /// Runs `operation`, retrying failures with backoff
+    ///
+    /// Retries stop as soon as the breaker opens; the error of the failure
+    /// that opened it is returned.
+    pub async fn call<F, Fut, T, E>(&self, mut operation: F) -> Result<T, ResilienceError<E>>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 1;
+        loop {
+            if !self.breaker.try_acquire() {
+                return Err(ResilienceError::Open);
+            }
+            let permit = Permit {
+                breaker: &self.breaker,
+                recorded: false,
+            };
+            match operation().await {
+                Ok(value) => {
+                    permit.record(true);
+                    return Ok(value);
+                }
+                Err(err) => {
+                    permit.record(false);
+                    let open = self.breaker.state() != BreakerState::Closed;
+                    if open || attempt >= self.retry.max_attempts {
+                        return Err(ResilienceError::Failed(err));
+                    }
+                    tokio::time::sleep(self.retry.delay(attempt)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// The [`Resilient`] wrappers of all outbound dependencies, by name
+///
+/// A wrapper is created on first use, and its breaker is then reported by
+/// `GET /metrics` and checked by `GET /readyz`.
+#[derive(Debug)]
+pub struct Outbound {
+    settings: Settings,
+    metrics: Arc<crate::metrics::Metrics>,
+    health: Arc<crate::health::HealthRegistry>,
+    dependencies: Mutex<HashMap<String, Arc<Resilient>>>,
+}
+
+impl Outbound {
+    /// Creates wrappers with `settings`, registering their breakers with
+    /// `metrics` and `health`
+    pub fn new(
+        settings: Settings,
+        metrics: Arc<crate::metrics::Metrics>,
+        health: Arc<crate::health::HealthRegistry>,
+    ) -> Self {
+        Self {
+            settings,
+            metrics,
+            health,
+            dependencies: Mutex::default(),
+        }
+    }
+
+    /// Returns the wrapper of the dependency `name`
+    pub fn get(&self, name: &str) -> Arc<Resilient> {
+        let mut dependencies = self
+            .dependencies
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(resilient) = dependencies.get(name) {
+            return resilient.clone();
+        }
+        let resilient = Arc::new(Resilient::new(name, &self.settings));
+        self.metrics.register_breaker(resilient.breaker().clone());
+        self.health.register_breaker(resilient.breaker().clone());
+        dependencies.insert(name.to_string(), resilient.clone());
+        resilient
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn settings() -> Settings {
+        Settings {
+            failure_threshold: 3,
+            open_duration: Duration::from_secs(10),
+            max_attempts: 2,
+            backoff: Duration::from_millis(50),
+        }
+    }
+
+    #[test]
+    fn test_retry_delay_doubles() {
+        let policy = RetryPolicy::new(&settings());
+        assert_eq!(policy.delay(1), Duration::from_millis(50));
+        assert_eq!(policy.delay(2), Duration::from_millis(100));
+        assert_eq!(policy.delay(4), Duration::from_millis(400));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_breaker_opens_and_probes() {
+        let breaker = CircuitBreaker::new("test", &settings());
+
+        for _ in 0..3 {
+            assert!(breaker.try_acquire());
+            breaker.record_failure();
+        }
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(!breaker.try_acquire());
+        assert_eq!(breaker.rejected(), 1);
+
+        // After the open period one probe at a time is allowed
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert!(breaker.try_acquire());
+        assert!(!breaker.try_acquire());
+
+        // A failed probe reopens the breaker
+        breaker.record_failure();
+        assert_eq!(breaker.state(), BreakerState::Open);
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(breaker.try_acquire());
+        breaker.record_success();
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resilient_call_retries_then_fails_fast() {
+        let resilient = Resilient::new("test", &settings());
+        let attempts = AtomicU32::new(0);
+        let failing = || async {
+            attempts.fetch_add(1, Ordering::Relaxed);
+            Err::<(), _>("unavailable")
+        };
+
+        // Two attempts per call; the third failure opens the breaker
+        assert_eq!(
+            resilient.call(failing).await,
+            Err(ResilienceError::Failed("unavailable"))
+        );
+        assert_eq!(
+            resilient.call(failing).await,
+            Err(ResilienceError::Failed("unavailable"))
+        );
+        assert_eq!(attempts.load(Ordering::Relaxed), 3);
+        assert_eq!(resilient.call(failing).await, Err(ResilienceError::Open));
+        assert_eq!(attempts.load(Ordering::Relaxed), 3);
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(resilient.call(|| async { Ok::<_, &str>(7) }).await, Ok(7));
+        assert_eq!(resilient.breaker().state(), BreakerState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancelled_probe_is_released() {
+        let resilient = Resilient::new("test", &settings());
+        for _ in 0..2 {
+            let _ = resilient
+                .call(|| async { Err::<(), _>("unavailable") })
+                .await;
+        }
+        assert_eq!(resilient.breaker().state(), BreakerState::Open);
+
+        // The probe never completes and is dropped by the timeout
+        tokio::time::advance(Duration::from_secs(10)).await;
+        let probe = resilient.call(std::future::pending::<Result<(), &str>>);
+        assert!(tokio::time::timeout(Duration::from_secs(1), probe)
+            .await
+            .is_err());
+        assert_eq!(resilient.breaker().state(), BreakerState::HalfOpen);
+
+        assert_eq!(resilient.call(|| async { Ok::<_, &str>(1) }).await, Ok(1));
+        assert_eq!(resilient.breaker().state(), BreakerState::Closed);
+    }
+
+    #[test]
+    fn test_outbound_registers_breakers_once() {
+        let metrics = Arc::new(crate::metrics::Metrics::default());
+        let health = Arc::new(crate::health::HealthRegistry::default());
+        let outbound = Outbound::new(settings(), metrics.clone(), health.clone());
+
+        let first = outbound.get("pushgateway");
+        assert!(Arc::ptr_eq(&first, &outbound.get("pushgateway")));
+        outbound.get("replication");
+        assert_eq!(health.names(), vec!["pushgateway", "replication"]);
+        assert_eq!(
+            metrics
+                .samples()
+                .iter()
+                .filter(|sample| sample.name == "circuit_breaker_state")
+                .count(),
+            2
+        );
+    }
+}
@@ -0,0 +1,353 @@
+//! Reusable response types
+//!
+//! Handlers return these instead of assembling status codes and headers by
+//! hand, so every resource answers the same way.
+//!
+//! Users, and the other resources and lists handlers answer with, are
+//! returned in an [`ApiResponse`] envelope:
+//!
+//! ```json
+//! {
+//!   "data": { "id": "...", "name": "Jane Doe" },
+//!   "meta": { "request_id": "..." },
+//!   "links": { "self": "/api/v1/users/..." }
+//! }
+//! ```
+//!
+//! Clients that want the bare resource ask for it with a parameter on the
+//! media type they accept, `Accept: application/json; envelope=false`, and
+//! `APP_RESPONSE_ENVELOPE` sets what is answered without one. The choice is
+//! applied by [`negotiate`], which stores it in a task-local read when an
+//! [`ApiResponse`] is serialized.
+
+use axum::{
+    extract::{Request, State},
+    http::{
+        header::{ACCEPT, LOCATION},
+        HeaderValue, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize, Serializer};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::field_case::Cased;
+use crate::AppState;
+
+/// A successful response: the resource, metadata about the response, and
+/// links to related resources
+#[derive(Debug, Clone, PartialEq, Deserialize, ToSchema)]
+pub struct ApiResponse<T> {
+    /// The resource
+    pub data: T,
+    /// Metadata about the response
+    pub meta: Meta,
+    /// Links to related resources
+    pub links: Links,
+}
+
+/// Metadata of a response envelope
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Meta {
+    /// ID of the request, as in its `X-Request-Id` header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<Uuid>,
+    /// Size of a collection; only present for lists
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pagination: Option<Pagination>,
+}
+
+/// Size of a collection and of the part of it returned
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Pagination {
+    /// Number of items in `data`
+    pub count: usize,
+    /// Number of items in the collection
+    pub total: usize,
+    /// Number of the page returned, starting at 1; only present when paged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<usize>,
+    /// Number of items per page; only present when paged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_page: Option<usize>,
+}
+
+/// Links of a response envelope
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Links {
+    /// The path and query the response was requested at
+    #[serde(rename = "self", default, skip_serializing_if = "Option::is_none")]
+    pub self_link: Option<String>,
+    /// The first page of a paged collection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first: Option<String>,
+    /// The previous page of a paged collection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+    /// The next page of a paged collection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    /// The last page of a paged collection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last: Option<String>,
+}
+
+impl<T> ApiResponse<T> {
+    /// Wraps `data` with the current request's metadata and links
+    pub fn new(data: T) -> Self {
+        let context = CONTEXT.try_with(Context::clone).ok();
+        Self {
+            data,
+            meta: Meta {
+                request_id: crate::error::request_id(),
+                pagination: None,
+            },
+            links: Links {
+                self_link: context.map(|context| context.path),
+                ..Links::default()
+            },
+        }
+    }
+
+    /// Sets the size of the collection `data` holds
+    pub fn with_pagination(mut self, pagination: Pagination) -> Self {
+        self.meta.pagination = Some(pagination);
+        self
+    }
+
+    /// Sets the links to other pages of the collection, keeping `self`
+    pub fn with_page_links(mut self, links: Links) -> Self {
+        self.links = Links {
+            self_link: self.links.self_link,
+            ..links
+        };
+        self
+    }
+}
+
+impl<T> ApiResponse<Vec<T>> {
+    /// Wraps a whole collection
+    pub fn collection(items: Vec<T>) -> Self {
+        let count = items.len();
+        Self::new(items).with_pagination(Pagination {
+            count,
+            total: count,
+            ..Pagination::default()
+        })
+    }
+}
+
+/// The enveloped form of an [`ApiResponse`]
+#[derive(Serialize)]
+struct Envelope<'a, T> {
+    data: &'a T,
+    meta: &'a Meta,
+    links: &'a Links,
+}
+
+impl<T: Serialize> Serialize for ApiResponse<T> {
+    /// Serializes the envelope, or only `data` if the current request asked
+    /// for the bare resource, with fields in the request's case
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if is_enveloped() {
+            let envelope = Envelope {
+                data: &self.data,
+                meta: &self.meta,
+                links: &self.links,
+            };
+            Cased(&envelope).serialize(serializer)
+        } else {
+            Cased(&self.data).serialize(serializer)
+        }
+    }
+}
+
+/// How the current request is answered
+#[derive(Debug, Clone)]
+struct Context {
+    /// Whether resources are wrapped in the envelope
+    envelope: bool,
+    /// Path and query of the request
+    path: String,
+}
+
+tokio::task_local! {
+    static CONTEXT: Context;
+}
+
+/// Whether the current request is answered with the envelope
+///
+/// Always `true` outside of [`negotiate`].
+pub fn is_enveloped() -> bool {
+    CONTEXT.try_with(|context| context.envelope).unwrap_or(true)
+}
+
+/// Reads the `envelope` parameter from an `Accept` header
+fn requested_envelope(accept: &str) -> Option<bool> {
+    accept
+        .split(',')
+        .flat_map(|range| range.split(';').skip(1))
+        .filter_map(|param| param.split_once('='))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("envelope"))
+        .find_map(|(_, value)| value.trim().trim_matches('"').parse().ok())
+}
+
+/// Middleware applying the client's envelope preference
+///
+/// Falls back to `APP_RESPONSE_ENVELOPE` when the `Accept` header does not
+/// specify one.
+pub async fn negotiate(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let envelope = request
+        .headers()
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .and_then(requested_envelope)
+        .unwrap_or(state.config.response_envelope);
+    let path = request
+        .uri()
+        .path_and_query()
+        .map_or_else(|| request.uri().path().to_string(), ToString::to_string);
+    CONTEXT
+        .scope(Context { envelope, path }, next.run(request))
+        .await
+}
+
+/// A `201 Created` response carrying the new resource as JSON and its path
+/// in the `Location` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Created<T> {
+    /// Path of the created resource; no `Location` header is sent without it
+    pub location: Option<String>,
+    /// The response body
+    pub body: T,
+}
+
+impl<T> Created<T> {
+    /// Creates a response without a location
+    pub fn new(body: T) -> Self {
+        Self {
+            location: None,
+            body,
+        }
+    }
+
+    /// Sets the path of the created resource
+    pub fn with_location(mut self, location: impl Into<String>) -> Self {
+        self.location = Some(location.into());
+        self
+    }
+}
+
+impl<T: Serialize> IntoResponse for Created<T> {
+    fn into_response(self) -> Response {
+        respond(StatusCode::CREATED, self.location, self.body)
+    }
+}
+
+/// A `202 Accepted` response for work carried on in the background,
+/// carrying its status as JSON and the path to follow it at in the
+/// `Location` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Accepted<T> {
+    /// Path at which the work's status is reported
+    pub location: String,
+    /// The response body
+    pub body: T,
+}
+
+impl<T> Accepted<T> {
+    /// Creates a response pointing to `location`
+    pub fn new(location: impl Into<String>, body: T) -> Self {
+        Self {
+            location: location.into(),
+            body,
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Accepted<T> {
+    fn into_response(self) -> Response {
+        respond(StatusCode::ACCEPTED, Some(self.location), self.body)
+    }
+}
+
+fn respond<T: Serialize>(status: StatusCode, location: Option<String>, body: T) -> Response {
+    let mut response = (status, Json(body)).into_response();
+    let location = location.and_then(|location| HeaderValue::try_from(location).ok());
+    if let Some(location) = location {
+        response.headers_mut().insert(LOCATION, location);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_created_sets_location() {
+        let response = Created::new(json!({ "id": 1 }))
+            .with_location("/things/1")
+            .into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()[LOCATION], "/things/1");
+
+        let response = Created::new(json!({})).into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert!(!response.headers().contains_key(LOCATION));
+    }
+
+    #[test]
+    fn test_requested_envelope() {
+        assert_eq!(requested_envelope("application/json"), None);
+        assert_eq!(
+            requested_envelope("application/json; timestamps=rfc3339; envelope=false"),
+            Some(false)
+        );
+        assert_eq!(requested_envelope("*/*;Envelope=\"true\""), Some(true));
+        assert_eq!(requested_envelope("application/json; envelope=maybe"), None);
+    }
+
+    #[tokio::test]
+    async fn test_api_response_envelope() {
+        let response = ApiResponse::collection(vec![1, 2]);
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            json!({ "data": [1, 2], "meta": { "pagination": { "count": 2, "total": 2 } }, "links": {} })
+        );
+
+        let context = Context {
+            envelope: false,
+            path: "/things?page=2".to_string(),
+        };
+        let bare = CONTEXT
+            .scope(context.clone(), async {
+                serde_json::to_value(ApiResponse::new(json!({ "id": 1 }))).unwrap()
+            })
+            .await;
+        assert_eq!(bare, json!({ "id": 1 }));
+
+        let enveloped = CONTEXT
+            .scope(
+                Context {
+                    envelope: true,
+                    ..context
+                },
+                async { serde_json::to_value(ApiResponse::new(1)).unwrap() },
+            )
+            .await;
+        assert_eq!(enveloped["links"]["self"], "/things?page=2");
+    }
+
+    #[test]
+    fn test_accepted_sets_location() {
+        let response =
+            Accepted::new("/operations/1", json!({ "status": "pending" })).into_response();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(response.headers()[LOCATION], "/operations/1");
+    }
+}
@@ -0,0 +1,739 @@
+//! Route groups and the router builder
+//!
+//! The API's routes are organized in named groups, each carrying its own
+//! middleware: health checks are open, reads and writes of users require
+//! their scopes, and so on. [`RouterBuilder`] assembles the groups and
+//! wraps them in the middleware every request goes through.
+//!
+//! Embedders can start from [`RouterBuilder::with_defaults`] and add their
+//! own groups, layer extra middleware onto one group (for example a limit
+//! on [`USER_WRITES`] only), or drop groups they do not serve:
+//!
+//! ```no_run
+//! use axum::{http::Method, routing::get};
+//! use rust_api::routes::{self, RouteGroup, RouterBuilder};
+//! use rust_api::AppState;
+//! use tower::limit::ConcurrencyLimitLayer;
+//!
+//! let app = RouterBuilder::with_defaults(AppState::new())
+//!     .map_group(routes::USER_WRITES, |group| {
+//!         group.layer(ConcurrencyLimitLayer::new(8))
+//!     })
+//!     .group(RouteGroup::new("status").route("/status", &[Method::GET], get(|| async { "up" })))
+//!     .without_group(routes::DEV)
+//!     .build();
+//! ```
+//!
+//! Every route is added with the methods it serves, and groups remember
+//! them along with the scope and deadline the route requires. The built
+//! router is listed from that table at `GET /api/v1/_routes`, and it
+//! answers the `Allow` header of `405` responses and `OPTIONS` requests.
+
+use std::convert::Infallible;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use axum::{
+    extract::Request,
+    http::{HeaderValue, Method},
+    middleware,
+    response::IntoResponse,
+    routing::{delete, get, post, put, MethodRouter, Route},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use tower::{Layer, Service};
+use utoipa::ToSchema;
+
+use crate::auth::{self, Scope};
+use crate::deprecation::{self, Deprecation};
+use crate::{
+    audit, cache, capture, chaos, computed, consistency, cors, deadline, dry_run, error, etag,
+    field_case, handlers, i18n, ip_filter, load_shed, metrics, paging, plugins, rate_limit,
+    replication, responses, shard, slo, timestamps, timing, usage, AppState,
+};
+
+/// Liveness, deep health, readiness and metrics, served without
+/// authentication
+pub const HEALTH: &str = "health";
+/// Listing and fetching users
+pub const USER_READS: &str = "user_reads";
+/// Creating, changing, deleting and restoring users
+pub const USER_WRITES: &str = "user_writes";
+/// Operator endpoints: duplicates, merges, the trash and `/api/v1/admin`
+pub const ADMIN: &str = "admin";
+/// Development helpers
+pub const DEV: &str = "dev";
+/// The embedded admin dashboard at `/admin` (`dashboard` feature)
+pub const DASHBOARD: &str = "dashboard";
+
+/// Methods in the order they are listed in an `Allow` header
+const METHODS: [Method; 9] = [
+    Method::GET,
+    Method::HEAD,
+    Method::POST,
+    Method::PUT,
+    Method::PATCH,
+    Method::DELETE,
+    Method::OPTIONS,
+    Method::TRACE,
+    Method::CONNECT,
+];
+
+/// A route added to a group, with what the group requires of it
+#[derive(Debug, Clone)]
+struct Mounted {
+    path: String,
+    methods: Vec<Method>,
+    scope: Option<Scope>,
+    deadline: Option<Duration>,
+}
+
+/// A named set of routes sharing middleware
+pub struct RouteGroup {
+    name: String,
+    router: Router<AppState>,
+    routes: Vec<Mounted>,
+}
+
+impl RouteGroup {
+    /// Creates an empty group
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            router: Router::new(),
+            routes: Vec::new(),
+        }
+    }
+
+    /// Returns the group's name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Adds a route serving `methods` to the group
+    ///
+    /// `methods` lists what `method_router` serves; `HEAD` need not be
+    /// listed where `GET` answers it. They are what `GET /api/v1/_routes`
+    /// reports and what `Allow` headers list.
+    pub fn route(
+        mut self,
+        path: &str,
+        methods: &[Method],
+        method_router: MethodRouter<AppState>,
+    ) -> Self {
+        self.routes.push(Mounted {
+            path: path.to_string(),
+            methods: methods.to_vec(),
+            scope: None,
+            deadline: None,
+        });
+        self.router = self.router.route(path, method_router);
+        self
+    }
+
+    /// Requires `scope` for every route added so far
+    ///
+    /// Callers authenticate as described in [`crate::auth`] and are held
+    /// to their [`crate::rate_limit`]. As with [`layer`](Self::layer), only
+    /// routes added before apply.
+    pub fn require(mut self, state: &AppState, scope: Scope) -> Self {
+        for route in &mut self.routes {
+            route.scope.get_or_insert(scope);
+        }
+        // The caller's rate limit is checked once it is authenticated, and
+        // its activity recorded once it passed both
+        self.layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::record_activity,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::check,
+        ))
+        .layer(middleware::from_fn_with_state(
+            auth::Permission::new(state, scope),
+            auth::require,
+        ))
+    }
+
+    /// Wraps the group's routes in a middleware layer
+    ///
+    /// As with [`Router::route_layer`], the layer only applies to routes
+    /// added before it and only runs for requests matching one of them.
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request> + Clone + Send + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.router = self.router.route_layer(layer);
+        self
+    }
+
+    /// Answers the group's requests within `limit`, or sooner if their
+    /// deadline is sooner; see [`crate::deadline`]
+    ///
+    /// As with [`layer`](Self::layer), only routes added before apply.
+    pub fn deadline(mut self, limit: Duration) -> Self {
+        for route in &mut self.routes {
+            route.deadline = Some(route.deadline.map_or(limit, |deadline| deadline.min(limit)));
+        }
+        self.layer(middleware::from_fn_with_state(limit, deadline::limit))
+    }
+}
+
+/// Assembles route groups into the application router
+pub struct RouterBuilder {
+    state: AppState,
+    groups: Vec<RouteGroup>,
+}
+
+impl RouterBuilder {
+    /// Creates a builder without any groups
+    pub fn new(state: AppState) -> Self {
+        Self {
+            state,
+            groups: Vec::new(),
+        }
+    }
+
+    /// Creates a builder with all of the API's groups
+    pub fn with_defaults(state: AppState) -> Self {
+        #[allow(unused_mut)]
+        let mut groups = vec![
+            health(),
+            user_reads(&state),
+            user_writes(&state),
+            admin(&state),
+            dev(&state),
+        ];
+        #[cfg(feature = "dashboard")]
+        groups.push(dashboard());
+        Self { state, groups }
+    }
+
+    /// Adds a group, replacing any group of the same name
+    pub fn group(mut self, group: RouteGroup) -> Self {
+        self.groups.retain(|existing| existing.name != group.name);
+        self.groups.push(group);
+        self
+    }
+
+    /// Changes the group named `name`, for example to add routes or layers
+    ///
+    /// Does nothing if there is no such group.
+    pub fn map_group<F>(mut self, name: &str, f: F) -> Self
+    where
+        F: FnOnce(RouteGroup) -> RouteGroup,
+    {
+        if let Some(index) = self.groups.iter().position(|group| group.name == name) {
+            let group = self.groups.remove(index);
+            self.groups.insert(index, f(group));
+        }
+        self
+    }
+
+    /// Removes the group named `name`
+    pub fn without_group(mut self, name: &str) -> Self {
+        self.groups.retain(|group| group.name != name);
+        self
+    }
+
+    /// Marks the route `method path` deprecated until its sunset
+    ///
+    /// Its responses announce the deprecation in their headers; see
+    /// [`crate::deprecation`]. `path` is the pattern the route was added
+    /// with, such as `/api/v1/users/:id`.
+    pub fn deprecate(self, method: Method, path: &str, deprecation: Deprecation) -> Self {
+        self.state.deprecations.deprecate(method, path, deprecation);
+        self
+    }
+
+    /// Returns the names of the groups, in the order they were added
+    pub fn group_names(&self) -> Vec<&str> {
+        self.groups.iter().map(RouteGroup::name).collect()
+    }
+
+    /// Builds the router, wrapping all groups in the shared middleware
+    ///
+    /// Path normalization is not included: it has to run before routing, so
+    /// callers wrap the returned router with
+    /// [`crate::paths::normalize_trailing_slash`].
+    pub fn build(self) -> Router {
+        let state = self.state;
+        state.routes.mount(&self.groups);
+        let routes = self
+            .groups
+            .into_iter()
+            .fold(Router::new(), |routes, group| routes.merge(group.router))
+            .fallback(handlers::not_found)
+            .method_not_allowed_fallback(handlers::method_not_allowed)
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                plugins::on_request,
+            ))
+            .layer(middleware::from_fn_with_state(state.clone(), chaos::inject))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                consistency::track,
+            ))
+            .layer(middleware::from_fn_with_state(state.clone(), audit::record))
+            .layer(middleware::from_fn_with_state(state.clone(), usage::record))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                metrics::track_in_flight,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                deprecation::announce,
+            ))
+            .layer(middleware::from_fn_with_state(state.clone(), slo::track))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                timing::log_slow,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                capture::record,
+            ))
+            .layer(middleware::from_fn_with_state(state.clone(), shard::route))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                replication::route,
+            ));
+
+        load_shed::apply(routes, &state)
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                deadline::enforce,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                ip_filter::check,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                computed::negotiate,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                responses::negotiate,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                timestamps::negotiate,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                i18n::localize,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                field_case::negotiate,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                error::context,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.config.cors.layer(),
+                cors::apply,
+            ))
+            .with_state(state)
+    }
+}
+
+/// A mounted route, as listed by `GET /api/v1/_routes`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RouteInfo {
+    /// HTTP method
+    pub method: String,
+    /// Route pattern, e.g. `/api/v1/users/:id`
+    pub path: String,
+    /// Route group the route belongs to
+    pub group: String,
+    /// Scope a caller needs, when authentication is enabled
+    pub scope: Option<String>,
+    /// Longest time the route is given to answer, in milliseconds, before
+    /// any shorter client deadline
+    pub deadline_ms: Option<u64>,
+    /// The route's deprecation, if it is deprecated
+    pub deprecation: Option<Deprecation>,
+}
+
+/// Response of `GET /api/v1/_routes`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RoutesResponse {
+    /// Whether callers have to authenticate for routes requiring a scope
+    pub authentication: bool,
+    /// Requests handled at once across all routes
+    /// (`APP_MAX_CONCURRENT_REQUESTS`); excess requests are shed
+    pub concurrency_limit: Option<usize>,
+    /// Number of routes
+    pub count: usize,
+    /// The routes, by path and method
+    pub routes: Vec<RouteInfo>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    group: String,
+    route: Mounted,
+}
+
+/// The routes mounted by [`RouterBuilder::build`]
+#[derive(Debug, Default)]
+pub struct RouteTable {
+    entries: RwLock<Vec<Entry>>,
+}
+
+impl RouteTable {
+    /// Replaces the table with the routes of `groups`
+    fn mount(&self, groups: &[RouteGroup]) {
+        let entries = groups
+            .iter()
+            .flat_map(|group| {
+                group.routes.iter().map(|route| Entry {
+                    group: group.name.clone(),
+                    route: route.clone(),
+                })
+            })
+            .collect();
+        *self
+            .entries
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = entries;
+    }
+
+    /// Returns the `Allow` header of the route added as `path`, such as
+    /// `/api/v1/users/:id`, or `None` if no such route is mounted
+    ///
+    /// Routes added under the same path by several groups are merged.
+    /// `HEAD` is listed where `GET` is served, and `OPTIONS` always.
+    pub fn allow(&self, path: &str) -> Option<HeaderValue> {
+        let entries = self
+            .entries
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut served: Vec<&Method> = entries
+            .iter()
+            .filter(|entry| entry.route.path == path)
+            .flat_map(|entry| &entry.route.methods)
+            .collect();
+        if served.is_empty() {
+            return None;
+        }
+        if served.contains(&&Method::GET) {
+            served.push(&Method::HEAD);
+        }
+        served.push(&Method::OPTIONS);
+        let allow: Vec<&str> = METHODS
+            .iter()
+            .filter(|method| served.contains(method))
+            .map(Method::as_str)
+            .collect();
+        HeaderValue::from_str(&allow.join(", ")).ok()
+    }
+
+    /// Lists the mounted routes with the deprecations and limits currently
+    /// in effect
+    pub fn report(&self, state: &AppState) -> RoutesResponse {
+        let config = &state.config;
+        let entries = self
+            .entries
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut routes: Vec<RouteInfo> = entries
+            .iter()
+            .flat_map(|entry| {
+                let route = &entry.route;
+                let deadline = match (route.deadline, config.request_timeout) {
+                    (Some(deadline), Some(timeout)) => Some(deadline.min(timeout)),
+                    (deadline, timeout) => deadline.or(timeout),
+                };
+                route.methods.iter().map(move |method| RouteInfo {
+                    method: method.to_string(),
+                    path: route.path.clone(),
+                    group: entry.group.clone(),
+                    scope: route.scope.map(|scope| scope.to_string()),
+                    deadline_ms: deadline.map(|deadline| deadline.as_millis() as u64),
+                    deprecation: state.deprecations.get(method, &route.path),
+                })
+            })
+            .collect();
+        routes.sort_by(|a, b| (&a.path, &a.method).cmp(&(&b.path, &b.method)));
+        RoutesResponse {
+            authentication: auth::is_enabled(config),
+            concurrency_limit: config.max_concurrent_requests,
+            count: routes.len(),
+            routes,
+        }
+    }
+}
+
+/// The [`HEALTH`] group
+pub fn health() -> RouteGroup {
+    RouteGroup::new(HEALTH)
+        .route("/", &[Method::GET], get(handlers::health_check))
+        .route(
+            "/health/deep",
+            &[Method::GET],
+            get(handlers::deep_health_check),
+        )
+        .route("/readyz", &[Method::GET], get(handlers::readiness_check))
+        .route("/metrics", &[Method::GET], get(metrics::export))
+}
+
+/// The [`USER_READS`] group
+pub fn user_reads(state: &AppState) -> RouteGroup {
+    let cached =
+        |ttl| middleware::from_fn_with_state(cache::Policy::new(state, ttl), cache::respond);
+
+    let group = RouteGroup::new(USER_READS)
+        .route(
+            "/api/v1/users",
+            &[Method::GET],
+            get(handlers::list_users)
+                .layer(middleware::from_fn(paging::items))
+                .layer(cached(state.config.cache.list_ttl))
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    etag::collection,
+                )),
+        )
+        .route(
+            "/api/v1/users/:id",
+            &[Method::GET],
+            get(handlers::get_user).layer(cached(state.config.cache.user_ttl)),
+        )
+        .route(
+            "/api/v1/users/export",
+            &[Method::POST],
+            post(handlers::export_users),
+        )
+        .route(
+            "/api/v1/operations/:id",
+            &[Method::GET],
+            get(handlers::get_operation),
+        )
+        .route(
+            "/api/v1/api-keys/:id/usage",
+            &[Method::GET],
+            get(handlers::api_key_usage),
+        );
+    group.require(state, Scope::UsersRead)
+}
+
+/// The [`USER_WRITES`] group
+pub fn user_writes(state: &AppState) -> RouteGroup {
+    let group = RouteGroup::new(USER_WRITES)
+        .route(
+            "/api/v1/users",
+            &[Method::POST],
+            post(handlers::create_user),
+        )
+        .route(
+            "/api/v1/users/:id",
+            &[Method::PUT, Method::DELETE],
+            put(handlers::update_user).delete(handlers::delete_user),
+        )
+        .route(
+            "/api/v1/users/:id/suspend",
+            &[Method::POST],
+            post(handlers::suspend_user),
+        )
+        .route(
+            "/api/v1/users/:id/activate",
+            &[Method::POST],
+            post(handlers::activate_user),
+        )
+        .route(
+            "/api/v1/users/:id/deactivate",
+            &[Method::POST],
+            post(handlers::deactivate_user),
+        )
+        .route("/api/v1/undo/:token", &[Method::POST], post(handlers::undo))
+        .route(
+            "/api/v1/users/import",
+            &[Method::POST],
+            post(handlers::import_users).layer(middleware::from_fn(dry_run::refuse)),
+        )
+        .route(
+            "/api/v1/operations/:id",
+            &[Method::DELETE],
+            delete(handlers::cancel_operation).layer(middleware::from_fn(dry_run::refuse)),
+        )
+        .layer(middleware::from_fn(dry_run::honor));
+    group.require(state, Scope::UsersWrite)
+}
+
+/// The [`ADMIN`] group
+///
+/// Dry runs are honored by the routes changing users and refused by the
+/// `/api/v1/admin` endpoints.
+pub fn admin(state: &AppState) -> RouteGroup {
+    let group = RouteGroup::new(ADMIN)
+        .route(
+            "/api/v1/admin/log-level",
+            &[Method::PUT],
+            put(handlers::set_log_level),
+        )
+        .route(
+            "/api/v1/admin/impersonate/:id",
+            &[Method::POST],
+            post(handlers::impersonate),
+        )
+        .route(
+            "/api/v1/admin/audit",
+            &[Method::GET],
+            get(handlers::audit_log),
+        )
+        .route(
+            "/api/v1/admin/slo",
+            &[Method::GET],
+            get(handlers::slo_report),
+        )
+        .route(
+            "/api/v1/_routes",
+            &[Method::GET],
+            get(handlers::list_routes),
+        )
+        .route(
+            "/api/v1/admin/capture",
+            &[Method::GET, Method::PUT],
+            get(handlers::capture_status).put(handlers::set_capture),
+        )
+        .route(
+            "/api/v1/admin/reserved",
+            &[Method::GET, Method::PUT],
+            get(handlers::reserved_lists).put(handlers::set_reserved_lists),
+        )
+        .route(
+            "/api/v1/admin/limits",
+            &[Method::GET],
+            get(handlers::list_rate_limits),
+        )
+        .route(
+            "/api/v1/admin/limits/:principal",
+            &[Method::PUT, Method::DELETE],
+            put(handlers::set_rate_limit).delete(handlers::delete_rate_limit),
+        )
+        .route(
+            "/api/v1/admin/snapshot",
+            &[Method::POST],
+            post(handlers::create_snapshot),
+        )
+        .route(
+            "/api/v1/admin/restore",
+            &[Method::POST],
+            post(handlers::restore_snapshot),
+        )
+        .route(
+            "/api/v1/admin/reports",
+            &[Method::GET, Method::POST],
+            get(handlers::list_reports).post(handlers::create_report),
+        )
+        .route(
+            "/api/v1/admin/reports/:name",
+            &[Method::GET],
+            get(handlers::get_report),
+        )
+        .route(
+            "/api/v1/admin/replication/changes",
+            &[Method::GET],
+            get(handlers::replication_changes),
+        )
+        .route(
+            "/api/v1/admin/replication/snapshot",
+            &[Method::GET],
+            get(handlers::replication_snapshot),
+        )
+        .layer(middleware::from_fn(dry_run::refuse))
+        .route(
+            "/api/v1/users/duplicates",
+            &[Method::GET],
+            get(handlers::find_duplicates),
+        )
+        .route(
+            "/api/v1/users/merge",
+            &[Method::POST],
+            post(handlers::merge_users),
+        )
+        .route(
+            "/api/v1/users/:id/merge/:remove_id",
+            &[Method::POST],
+            post(handlers::merge_user),
+        )
+        .route(
+            "/api/v1/users/trash",
+            &[Method::GET],
+            get(handlers::list_trash),
+        )
+        .route(
+            "/api/v1/users/trash/restore",
+            &[Method::POST],
+            post(handlers::restore_users),
+        )
+        .route(
+            "/api/v1/users/trash/:id/restore",
+            &[Method::POST],
+            post(handlers::restore_user),
+        )
+        .layer(middleware::from_fn(dry_run::honor));
+    group.require(state, Scope::Admin)
+}
+
+/// The [`DEV`] group
+pub fn dev(state: &AppState) -> RouteGroup {
+    let group = RouteGroup::new(DEV)
+        .route(
+            "/api/v1/dev/generate-users",
+            &[Method::POST],
+            post(handlers::generate_users),
+        )
+        .layer(middleware::from_fn(dry_run::refuse));
+    group.require(state, Scope::UsersWrite)
+}
+
+/// The [`DASHBOARD`] group
+///
+/// The assets are public; the data the page shows comes from the admin
+/// API, which requires the admin scope. See [`crate::dashboard`].
+#[cfg(feature = "dashboard")]
+pub fn dashboard() -> RouteGroup {
+    use crate::dashboard;
+
+    RouteGroup::new(DASHBOARD)
+        .route(dashboard::PATH, &[Method::GET], get(dashboard::serve))
+        .route("/admin/*asset", &[Method::GET], get(dashboard::serve))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allow() {
+        let table = RouteTable::default();
+        table.mount(&[
+            RouteGroup::new("reads").route("/items/:id", &[Method::GET], get(|| async {})),
+            RouteGroup::new("writes")
+                .route(
+                    "/items/:id",
+                    &[Method::PUT, Method::DELETE],
+                    put(|| async {}).delete(|| async {}),
+                )
+                .route("/items", &[Method::POST], post(|| async {})),
+        ]);
+
+        assert_eq!(
+            table.allow("/items/:id").unwrap(),
+            "GET, HEAD, PUT, DELETE, OPTIONS"
+        );
+        assert_eq!(table.allow("/items").unwrap(), "POST, OPTIONS");
+        assert_eq!(table.allow("/other"), None);
+    }
+}
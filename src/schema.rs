@@ -0,0 +1,239 @@
+//! JSON Schema and TypeScript definitions for API payloads
+//!
+//! Both are derived from the component schemas of the OpenAPI document, so
+//! clients generated from them match what the contract tests enforce.
+//! They are printed by the `schema` subcommand:
+//!
+//! ```text
+//! rust-api schema json-schema > api.schema.json
+//! rust-api schema typescript > api.d.ts
+//! ```
+
+use serde_json::{json, Map, Value};
+
+use crate::openapi;
+
+const OPENAPI_REFS: &str = "#/components/schemas/";
+const JSON_SCHEMA_REFS: &str = "#/$defs/";
+
+/// Output formats of the `schema` subcommand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaFormat {
+    /// A JSON Schema (2020-12) document with every payload under `$defs`
+    JsonSchema,
+    /// TypeScript type declarations
+    TypeScript,
+}
+
+impl std::str::FromStr for SchemaFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "json-schema" | "jsonschema" | "json" => Ok(SchemaFormat::JsonSchema),
+            "typescript" | "ts" => Ok(SchemaFormat::TypeScript),
+            other => Err(format!(
+                "unknown schema format '{}' (expected json-schema or typescript)",
+                other
+            )),
+        }
+    }
+}
+
+/// Renders the payload definitions in the given format
+pub fn render(format: SchemaFormat) -> String {
+    match format {
+        SchemaFormat::JsonSchema => {
+            serde_json::to_string_pretty(&json_schema()).unwrap_or_default()
+        }
+        SchemaFormat::TypeScript => typescript(),
+    }
+}
+
+/// Returns the component schemas of the OpenAPI document by name
+fn components() -> Map<String, Value> {
+    match openapi::spec().pointer("/components/schemas") {
+        Some(Value::Object(schemas)) => schemas.clone(),
+        _ => Map::new(),
+    }
+}
+
+/// Returns a JSON Schema document defining every API payload
+pub fn json_schema() -> Value {
+    let mut defs = Value::Object(components());
+    rewrite_refs(&mut defs);
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "rust-api",
+        "$defs": defs,
+    })
+}
+
+/// Points OpenAPI component references at `$defs`
+fn rewrite_refs(schema: &mut Value) {
+    match schema {
+        Value::Object(map) => {
+            if let Some(Value::String(target)) = map.get_mut("$ref") {
+                if let Some(name) = target.strip_prefix(OPENAPI_REFS) {
+                    *target = format!("{}{}", JSON_SCHEMA_REFS, name);
+                }
+            }
+            map.values_mut().for_each(rewrite_refs);
+        }
+        Value::Array(items) => items.iter_mut().for_each(rewrite_refs),
+        _ => {}
+    }
+}
+
+/// Returns TypeScript declarations for every API payload
+pub fn typescript() -> String {
+    let mut out = String::from("// Generated by `rust-api schema typescript`. Do not edit.\n");
+
+    for (name, schema) in components() {
+        out.push('\n');
+        if let Some(description) = schema.get("description").and_then(Value::as_str) {
+            out.push_str(&format!("/** {} */\n", description));
+        }
+
+        match schema.get("properties").and_then(Value::as_object) {
+            Some(properties) => {
+                let required = required_fields(&schema);
+                out.push_str(&format!("export interface {} {{\n", name));
+                for (field, property) in properties {
+                    if let Some(description) = property.get("description").and_then(Value::as_str) {
+                        out.push_str(&format!("  /** {} */\n", description));
+                    }
+                    let optional = if required.contains(&field.as_str()) {
+                        ""
+                    } else {
+                        "?"
+                    };
+                    out.push_str(&format!(
+                        "  {}{}: {};\n",
+                        field,
+                        optional,
+                        ts_type(property)
+                    ));
+                }
+                out.push_str("}\n");
+            }
+            None => out.push_str(&format!("export type {} = {};\n", name, ts_type(&schema))),
+        }
+    }
+
+    out
+}
+
+fn required_fields(schema: &Value) -> Vec<&str> {
+    schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|fields| fields.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default()
+}
+
+/// Translates a JSON Schema into a TypeScript type expression
+fn ts_type(schema: &Value) -> String {
+    if let Some(target) = schema.get("$ref").and_then(Value::as_str) {
+        return target.rsplit('/').next().unwrap_or("unknown").to_string();
+    }
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        return union(values.iter().map(Value::to_string));
+    }
+    if let Some(variants) = ["oneOf", "anyOf"]
+        .iter()
+        .find_map(|key| schema.get(*key).and_then(Value::as_array))
+    {
+        return union(variants.iter().map(ts_type));
+    }
+
+    match schema.get("type") {
+        Some(Value::String(kind)) => ts_primitive(kind, schema),
+        Some(Value::Array(kinds)) => union(
+            kinds
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|kind| ts_primitive(kind, schema)),
+        ),
+        _ => "unknown".to_string(),
+    }
+}
+
+fn ts_primitive(kind: &str, schema: &Value) -> String {
+    match kind {
+        "string" => "string".to_string(),
+        "integer" | "number" => "number".to_string(),
+        "boolean" => "boolean".to_string(),
+        "null" => "null".to_string(),
+        "array" => {
+            let items = schema.get("items").map_or("unknown".to_string(), ts_type);
+            if items.contains(' ') {
+                format!("({})[]", items)
+            } else {
+                format!("{}[]", items)
+            }
+        }
+        "object" => "Record<string, unknown>".to_string(),
+        _ => "unknown".to_string(),
+    }
+}
+
+fn union(types: impl Iterator<Item = String>) -> String {
+    let mut seen: Vec<String> = Vec::new();
+    for ty in types {
+        if !seen.contains(&ty) {
+            seen.push(ty);
+        }
+    }
+    seen.join(" | ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_schema_uses_defs() {
+        let schema = json_schema();
+        let defs = schema["$defs"].as_object().unwrap();
+        for name in [
+            "User",
+            "CreateUserRequest",
+            "UpdateUserRequest",
+            "ErrorResponse",
+        ] {
+            assert!(defs.contains_key(name), "missing {}", name);
+        }
+
+        let text = schema.to_string();
+        assert!(!text.contains(OPENAPI_REFS));
+        assert_eq!(
+            schema["$defs"]["User"]["properties"]["status"]["$ref"],
+            "#/$defs/UserStatus"
+        );
+    }
+
+    #[test]
+    fn test_typescript_declarations() {
+        let ts = typescript();
+
+        assert!(ts.contains("export interface User {"));
+        assert!(ts.contains("  phone?: string | null;"));
+        assert!(ts.contains("  email: string;"));
+        assert!(ts.contains("  created_at: number | string;"));
+        assert!(ts.contains("  last_seen_at?: number | string | null;"));
+        assert!(ts.contains("  users: User[];"));
+        assert!(ts.contains(
+            "export type UserStatus = \"pending\" | \"active\" | \"suspended\" | \"deactivated\";"
+        ));
+        assert!(ts.contains("export interface ErrorResponse {"));
+    }
+
+    #[test]
+    fn test_schema_format_from_str() {
+        assert_eq!("ts".parse(), Ok(SchemaFormat::TypeScript));
+        assert_eq!("JSON-Schema".parse(), Ok(SchemaFormat::JsonSchema));
+        assert!("yaml".parse::<SchemaFormat>().is_err());
+    }
+}
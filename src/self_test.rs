@@ -0,0 +1,184 @@
+//! Startup self-test
+//!
+//! `rust-api --self-test` builds the application the way the server does,
+//! with the default configuration and an empty in-memory store, and sends
+//! a scripted create, read, update, list and delete flow through its
+//! router, ending with the deleted user answering `410 Gone`. Nothing
+//! listens on a port and nothing outside the process is needed, so a
+//! container image or package can be smoke-tested right after it is
+//! built. The binary exits non-zero if a step fails.
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+use crate::service::App;
+use crate::{Config, Server};
+
+/// A step of the flow that did not get the expected answer
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("self-test failed at '{step}': {reason}")]
+pub struct Failure {
+    /// Name of the step
+    pub step: &'static str,
+    /// What went wrong
+    pub reason: String,
+}
+
+/// Runs the flow against a freshly built application
+///
+/// Returns the names of the steps, all of which passed.
+///
+/// # Errors
+///
+/// Returns the first step that failed, or a failure of the `boot` step if
+/// the application could not be built.
+pub async fn run() -> Result<Vec<&'static str>, Failure> {
+    let app = Server::from_config(Config::default())
+        .into_service()
+        .await
+        .map_err(|err| Failure {
+            step: "boot",
+            reason: err.to_string(),
+        })?;
+    let mut steps = vec!["boot"];
+    let mut flow = Flow {
+        app,
+        steps: &mut steps,
+    };
+
+    flow.send("health", Method::GET, "/", None, StatusCode::OK)
+        .await?;
+    let (location, _) = flow
+        .send(
+            "create user",
+            Method::POST,
+            "/api/v1/users",
+            Some(json!({ "name": "Self Test", "email": "self-test@example.com" })),
+            StatusCode::CREATED,
+        )
+        .await?;
+    let Some(user) = location else {
+        return Err(Failure {
+            step: "create user",
+            reason: "no Location header".to_string(),
+        });
+    };
+    let (_, body) = flow
+        .send("get user", Method::GET, &user, None, StatusCode::OK)
+        .await?;
+    expect("get user", &body, "email", "self-test@example.com")?;
+    let (_, body) = flow
+        .send(
+            "update user",
+            Method::PUT,
+            &user,
+            Some(json!({ "name": "Self Tested" })),
+            StatusCode::OK,
+        )
+        .await?;
+    expect("update user", &body, "name", "Self Tested")?;
+    flow.send(
+        "list users",
+        Method::GET,
+        "/api/v1/users",
+        None,
+        StatusCode::OK,
+    )
+    .await?;
+    flow.send(
+        "delete user",
+        Method::DELETE,
+        &user,
+        None,
+        StatusCode::NO_CONTENT,
+    )
+    .await?;
+    flow.send("user is gone", Method::GET, &user, None, StatusCode::GONE)
+        .await?;
+    Ok(steps)
+}
+
+/// The application and the steps passed so far
+struct Flow<'a> {
+    app: App,
+    steps: &'a mut Vec<&'static str>,
+}
+
+impl Flow<'_> {
+    /// Sends a request, expecting `status`
+    ///
+    /// Returns the `Location` header and the JSON body, unwrapped from the
+    /// response envelope.
+    async fn send(
+        &mut self,
+        step: &'static str,
+        method: Method,
+        path: &str,
+        body: Option<Value>,
+        status: StatusCode,
+    ) -> Result<(Option<String>, Value), Failure> {
+        let fail = |reason: String| Failure { step, reason };
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+            .map_err(|err| fail(err.to_string()))?;
+        let response = self
+            .app
+            .clone()
+            .oneshot(request)
+            .await
+            .unwrap_or_else(|never| match never {});
+        if response.status() != status {
+            return Err(fail(format!(
+                "expected {}, got {}",
+                status,
+                response.status()
+            )));
+        }
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .map_err(|err| fail(err.to_string()))?;
+        let body: Value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        let body = match body.get("data") {
+            Some(data) => data.clone(),
+            None => body,
+        };
+        self.steps.push(step);
+        Ok((location, body))
+    }
+}
+
+/// Checks that `body` holds `value` in `field`
+fn expect(step: &'static str, body: &Value, field: &str, value: &str) -> Result<(), Failure> {
+    if body[field] == value {
+        return Ok(());
+    }
+    Err(Failure {
+        step,
+        reason: format!("expected {} '{}', got {}", field, value, body[field]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_flow_passes() {
+        let steps = run().await.unwrap();
+        assert_eq!(steps.first(), Some(&"boot"));
+        assert_eq!(steps.last(), Some(&"user is gone"));
+        assert_eq!(steps.len(), 8);
+    }
+}
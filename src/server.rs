@@ -0,0 +1,564 @@
+//! The whole server, constructible from the library
+//!
+//! [`Server`] runs what the `rust-api` binary runs: it builds the state,
+//! runs the startup tasks, serves the router with all its middleware over
+//! plain HTTP or TLS, drains connections on shutdown and runs the shutdown
+//! tasks. Other binaries can embed the API as a component instead of
+//! starting the binary:
+//!
+//! ```no_run
+//! use rust_api::{Config, Server};
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let config = Config::from_env()?;
+//! Server::from_config(config)
+//!     .bind(([127, 0, 0, 1], 8080).into())
+//!     .run()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Tracing is not set up by the server, so embedders keep their own
+//! subscriber; pass the binary's [`LogFilter`] with [`Server::log_filter`]
+//! to make it adjustable through the admin API.
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use axum::{extract::Request, response::Response, ServiceExt};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tower::Service;
+
+use crate::lifecycle::{Lifecycle, LifecycleError};
+use crate::logging::LogFilter;
+use crate::metrics::sinks::Exporter;
+use crate::plugins::{AppBuilder, Plugin};
+use crate::routes::RouterBuilder;
+use crate::service::App;
+use crate::{geoip, mock, profile, reload, shadow, systemd, AppState, Config};
+
+/// Address served on unless another one or a listener is given
+pub const DEFAULT_ADDR: SocketAddr =
+    SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED), 3000);
+
+type Plugins = Vec<Arc<dyn Plugin>>;
+type Routes = Box<dyn FnOnce(RouterBuilder) -> RouterBuilder + Send>;
+type Shutdown = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Why the server could not start or stopped with an error
+#[derive(Debug, thiserror::Error)]
+pub enum ServerError {
+    /// The configuration needs a feature the crate was built without
+    #[error("{0}")]
+    Unsupported(&'static str),
+    /// The GeoIP database could not be opened
+    #[error("APP_GEOIP_DATABASE {}: {source}", path.display())]
+    GeoIp {
+        /// Path of the database
+        path: PathBuf,
+        /// Why it could not be opened
+        source: maxminddb::MaxMindDBError,
+    },
+    /// A startup task failed
+    #[error(transparent)]
+    Startup(#[from] LifecycleError),
+    /// Binding, accepting or exporting failed
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// A listener task panicked
+    #[error(transparent)]
+    Task(#[from] tokio::task::JoinError),
+}
+
+/// The API server, with its configuration and where it listens
+pub struct Server {
+    config: Config,
+    mock: bool,
+    log_filter: Option<LogFilter>,
+    lifecycle: Lifecycle,
+    plugins: Plugins,
+    routes: Option<Routes>,
+    addr: SocketAddr,
+    listener: Option<TcpListener>,
+    shutdown: Option<Shutdown>,
+}
+
+impl Server {
+    /// Creates a server for `config`, listening on [`DEFAULT_ADDR`]
+    pub fn from_config(config: Config) -> Self {
+        Self {
+            config,
+            mock: false,
+            log_filter: None,
+            lifecycle: Lifecycle::default(),
+            plugins: Vec::new(),
+            routes: None,
+            addr: DEFAULT_ADDR,
+            listener: None,
+            shutdown: None,
+        }
+    }
+
+    /// Serves generated users with a frozen clock; see [`crate::mock`]
+    pub fn mock(mut self, mock: bool) -> Self {
+        self.mock = mock;
+        self
+    }
+
+    /// Makes `filter` adjustable through `PUT /api/v1/admin/log-level`
+    pub fn log_filter(mut self, filter: LogFilter) -> Self {
+        self.log_filter = Some(filter);
+        self
+    }
+
+    /// Runs the startup and shutdown tasks of `lifecycle` along with the
+    /// server's own
+    pub fn lifecycle(mut self, lifecycle: Lifecycle) -> Self {
+        self.lifecycle = lifecycle;
+        self
+    }
+
+    /// Registers a plugin; see [`crate::plugins`]
+    pub fn plugin<P: Plugin>(mut self, plugin: P) -> Self {
+        self.plugins.push(Arc::new(plugin));
+        self
+    }
+
+    /// Changes the route groups served, for example to add the embedder's
+    /// own
+    pub fn routes<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(RouterBuilder) -> RouterBuilder + Send + 'static,
+    {
+        self.routes = Some(Box::new(f));
+        self
+    }
+
+    /// Listens on `addr` instead of [`DEFAULT_ADDR`]
+    ///
+    /// A socket passed by systemd socket activation still takes precedence.
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.addr = addr;
+        self
+    }
+
+    /// Serves on a listener bound by the caller
+    pub fn listener(mut self, listener: TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Stops the server when `signal` completes, instead of on Ctrl+C or
+    /// SIGTERM
+    pub fn with_graceful_shutdown<F>(mut self, signal: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown = Some(Box::pin(signal));
+        self
+    }
+
+    /// Builds the application without listening anywhere, for serverless
+    /// platforms; see [`crate::service`]
+    ///
+    /// Runs the startup tasks. Shutdown tasks and the background work of a
+    /// long-running server (pushing metrics, replication, shadow reads) are
+    /// not started, as serverless runtimes freeze the process between
+    /// requests and give no notice before stopping it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration needs a missing feature or a
+    /// startup task fails.
+    pub async fn into_service(self) -> Result<App, ServerError> {
+        let Server {
+            config,
+            mock,
+            log_filter,
+            mut lifecycle,
+            plugins,
+            routes,
+            ..
+        } = self;
+        check(&config)?;
+        let app_state = state(config, mock, log_filter).await?;
+        lifecycle.start(&app_state).await?;
+        Ok(app(&app_state, plugins, routes))
+    }
+
+    /// Runs the server until it is shut down and its connections are
+    /// drained
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration needs a missing feature, a
+    /// startup task fails, or the listener cannot be bound.
+    pub async fn run(self) -> Result<(), ServerError> {
+        let Server {
+            config,
+            mock,
+            log_filter,
+            mut lifecycle,
+            plugins,
+            routes,
+            addr,
+            listener,
+            shutdown,
+        } = self;
+
+        check(&config)?;
+        let app_state = state(config, mock, log_filter).await?;
+
+        // Startup tasks run before the listener is bound, so a failing one
+        // keeps the service out of rotation
+        let exporter = Exporter::from_settings(&app_state.config.metrics_export)
+            .await?
+            .map(Arc::new);
+        if let Some(exporter) = &exporter {
+            // Push the final values, so the last interval is not lost
+            let exporter = exporter.clone();
+            lifecycle = lifecycle.on_shutdown(
+                "metrics-export",
+                crate::metrics::sinks::SEND_TIMEOUT,
+                |state| async move {
+                    exporter.export(&state).await;
+                    Ok(())
+                },
+            );
+        }
+        let shadowing = match app_state.config.shadow.backend {
+            Some(shadow::Backend::Memory) => {
+                shadow::start(&app_state, shadow::MemoryStore::default()).await
+            }
+            None => None,
+        };
+        if shadowing.is_some() {
+            // Compare what was read before the server stopped
+            lifecycle =
+                lifecycle.on_shutdown("shadow", shadow::SHADOW_TIMEOUT, |state| async move {
+                    state.shadow.flush().await;
+                    Ok(())
+                });
+        }
+        lifecycle.start(&app_state).await?;
+        #[cfg(feature = "client")]
+        let replicating = crate::replication::start(&app_state);
+        let exporting = exporter.map(|exporter| exporter.spawn(app_state.clone()));
+        let reporting = crate::reports::spawn(app_state.clone());
+
+        let app = app(&app_state, plugins, routes);
+
+        // Serve on the given listener, the socket systemd passed, or bind
+        // our own
+        let listener = match listener {
+            Some(listener) => listener,
+            None => match systemd::inherited_listener()? {
+                Some(listener) => listener,
+                None => TcpListener::bind(addr).await?,
+            },
+        };
+        let watchdog = systemd::spawn_watchdog();
+        let stopping = stop_on(shutdown, reload::listen(&listener));
+
+        #[cfg(feature = "tls")]
+        let served = if app_state.config.tls.is_enabled() {
+            serve_tls(listener, app, &app_state.config.tls, &stopping).await
+        } else {
+            serve(listener, app, &stopping).await
+        };
+        #[cfg(not(feature = "tls"))]
+        let served = serve(listener, app, &stopping).await;
+
+        // In-flight requests have been answered; shutdown tasks run last
+        tracing::info!("Server stopped, running shutdown tasks");
+        if let Some(exporting) = exporting {
+            exporting.abort();
+        }
+        if let Some(reporting) = reporting {
+            reporting.abort();
+        }
+        if let Some(watchdog) = watchdog {
+            watchdog.abort();
+        }
+        lifecycle.stop(&app_state).await;
+        if let Some(shadowing) = shadowing {
+            shadowing.abort();
+        }
+        #[cfg(feature = "client")]
+        if let Some(replicating) = replicating {
+            replicating.abort();
+        }
+        served
+    }
+}
+
+/// Fails if `config` needs a feature the crate was built without
+pub(crate) fn check(config: &Config) -> Result<(), ServerError> {
+    #[cfg(not(feature = "client"))]
+    if config.shard.is_enabled() {
+        return Err(ServerError::Unsupported(
+            "APP_SHARD_PEERS is set, but the server was built without the `client` feature",
+        ));
+    }
+    #[cfg(not(feature = "client"))]
+    if config
+        .replication
+        .role
+        .is_some_and(crate::replication::Role::replicates)
+    {
+        return Err(ServerError::Unsupported(
+            "APP_REPLICATION_ROLE=follower or replica requires the server to be built with the `client` feature",
+        ));
+    }
+    #[cfg(not(feature = "tls"))]
+    if config.tls.is_enabled() {
+        return Err(ServerError::Unsupported(
+            "APP_TLS_CERT is set, but the server was built without the `tls` feature",
+        ));
+    }
+    #[cfg(not(feature = "http3"))]
+    if config.tls.http3 {
+        return Err(ServerError::Unsupported(
+            "APP_TLS_HTTP3 is set, but the server was built without the `http3` feature",
+        ));
+    }
+    #[cfg(all(feature = "client", feature = "http3"))]
+    let _ = config;
+    Ok(())
+}
+
+/// Builds the state for `config`, opening the GeoIP database
+async fn state(
+    config: Config,
+    mock: bool,
+    log_filter: Option<LogFilter>,
+) -> Result<AppState, ServerError> {
+    profile::log_banner(&config);
+    let app_state = if mock {
+        tracing::warn!(
+            "Mock mode: serving {} generated users (seed {}) with a frozen clock",
+            config.mock_users,
+            config.mock_seed
+        );
+        mock::state(config).await
+    } else {
+        AppState::with_config(config)
+    };
+    let geoip = match &app_state.config.geoip_database {
+        Some(path) => geoip::GeoIp::open(path).map_err(|source| ServerError::GeoIp {
+            path: path.clone(),
+            source,
+        })?,
+        None => geoip::GeoIp::Disabled,
+    };
+    Ok(AppState {
+        log_filter: log_filter.map_or(app_state.log_filter, Arc::new),
+        geoip: Arc::new(geoip),
+        ..app_state
+    })
+}
+
+/// Composes the router with the plugins and route changes
+fn app(state: &AppState, plugins: Plugins, routes: Option<Routes>) -> App {
+    let mut builder = AppBuilder::with_plugins(state.clone(), plugins).routes();
+    if let Some(routes) = routes {
+        builder = routes(builder);
+    }
+    App::from_router(state, builder.build())
+}
+
+/// Serves `app` over plain HTTP until `stopping` is cancelled
+async fn serve<S>(
+    listener: TcpListener,
+    app: S,
+    stopping: &CancellationToken,
+) -> Result<(), ServerError>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    tracing::info!("Server listening on {}", listener.local_addr()?);
+    systemd::notify_ready("serving");
+    reload::notify_predecessor();
+    let app = ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(app);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(stopping.clone().cancelled_owned())
+        .await?;
+    Ok(())
+}
+
+/// Serves `app` over TLS, and HTTP/3 if enabled, until `stopping` is
+/// cancelled
+#[cfg(feature = "tls")]
+async fn serve_tls<S>(
+    listener: TcpListener,
+    app: S,
+    tls: &crate::tls::Settings,
+    stopping: &CancellationToken,
+) -> Result<(), ServerError>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    let addr = listener.local_addr()?;
+    #[cfg(feature = "http3")]
+    let quic = tls.http3.then(|| {
+        tracing::info!("Server listening on {} (HTTP/3, experimental)", addr);
+        let (app, tls, stopping) = (app.clone(), tls.clone(), stopping.clone());
+        tokio::spawn(async move {
+            crate::tls::serve_h3(addr, app, &tls, stopping.cancelled_owned()).await
+        })
+    });
+    tracing::info!("Server listening on {} (TLS)", addr);
+    systemd::notify_ready("serving over TLS");
+    reload::notify_predecessor();
+    crate::tls::serve(listener, app, tls, stopping.clone().cancelled_owned()).await?;
+    #[cfg(feature = "http3")]
+    if let Some(quic) = quic {
+        quic.await??;
+    }
+    Ok(())
+}
+
+/// Returns a token cancelled once `shutdown` (by default Ctrl+C or
+/// SIGTERM) completes or a successor took over the listener
+fn stop_on(shutdown: Option<Shutdown>, handed_over: reload::HandedOver) -> CancellationToken {
+    let stopping = CancellationToken::new();
+    let cancel = stopping.clone();
+    tokio::spawn(async move {
+        let signal = async {
+            match shutdown {
+                Some(shutdown) => shutdown.await,
+                None => shutdown_signal().await,
+            }
+            tracing::info!("Shutdown signal received, draining connections");
+            systemd::notify_stopping();
+        };
+        tokio::select! {
+            () = signal => {}
+            // The service keeps running in the successor, so systemd is not
+            // told it stops
+            () = handed_over.wait() => {
+                tracing::info!("Successor took over, draining connections");
+            }
+        }
+        cancel.cancel();
+    });
+    stopping
+}
+
+/// Completes on Ctrl+C or, on Unix, SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!(%err, "failed to listen for Ctrl+C");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::error!(%err, "failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_embedded_server_serves_until_shut_down() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(
+            Server::from_config(Config::default())
+                .listener(listener)
+                .with_graceful_shutdown(async {
+                    let _ = stopped.await;
+                })
+                .run(),
+        );
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        tokio::io::AsyncWriteExt::write_all(
+            &mut stream,
+            b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+        let mut response = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut response)
+            .await
+            .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_service_runs_startup_tasks_without_listening() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use tower::ServiceExt;
+
+        let started = Arc::new(AtomicBool::new(false));
+        let lifecycle =
+            Lifecycle::default().on_startup("warm-up", std::time::Duration::from_secs(1), {
+                let started = started.clone();
+                |_| async move {
+                    started.store(true, Ordering::SeqCst);
+                    Ok(())
+                }
+            });
+        let app = Server::from_config(Config::default())
+            .lifecycle(lifecycle)
+            .into_service()
+            .await
+            .unwrap();
+        assert!(started.load(Ordering::SeqCst));
+
+        let request = axum::http::Request::get("/api/v1/users")
+            .body(String::new())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_failing_startup_task_stops_the_server() {
+        let lifecycle = Lifecycle::default().on_startup(
+            "warm-up",
+            std::time::Duration::from_secs(1),
+            |_| async { Err("cache unreachable".into()) },
+        );
+        let err = Server::from_config(Config::default())
+            .bind(([127, 0, 0, 1], 0).into())
+            .lifecycle(lifecycle)
+            .run()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ServerError::Startup(_)), "{}", err);
+    }
+}
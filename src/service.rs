@@ -0,0 +1,110 @@
+//! The API as a plain `tower::Service`
+//!
+//! [`App`] is the composed application, routes and middleware including
+//! path normalization, without any listener. It accepts requests with any
+//! body type, so adapters for serverless platforms can call it directly:
+//!
+//! ```no_run
+//! use rust_api::{Config, Server};
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let app = Server::from_config(Config::from_env()?)
+//!     .into_service()
+//!     .await?;
+//! // Hand `app` to the platform's adapter, e.g. `lambda_http::run(app)`
+//! # drop(app);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Requests carry no peer address unless the adapter inserts
+//! `ConnectInfo<SocketAddr>` into their extensions, or a plain
+//! `SocketAddr` when built without the `server` feature; until then, IP
+//! filtering sees an unknown client and audit entries have no client
+//! address.
+
+use std::convert::Infallible;
+use std::task::{Context, Poll};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::Request,
+    middleware,
+    response::Response,
+    BoxError, Router,
+};
+use tower::{util::BoxCloneService, Layer, Service};
+
+use crate::{paths, AppState};
+
+/// The composed application as a `tower::Service`
+#[derive(Clone)]
+pub struct App(BoxCloneService<Request, Response, Infallible>);
+
+impl App {
+    /// Serves all of the API's routes for `state`
+    pub fn new(state: AppState) -> Self {
+        Self::from_router(&state, crate::router(state.clone()))
+    }
+
+    /// Serves `router`, as built by [`crate::routes::RouterBuilder`],
+    /// adding the path normalization that has to run before routing
+    pub fn from_router(state: &AppState, router: Router) -> Self {
+        let app = middleware::from_fn_with_state(state.clone(), paths::normalize_trailing_slash)
+            .layer(router);
+        Self(BoxCloneService::new(app))
+    }
+}
+
+impl std::fmt::Debug for App {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("App").finish_non_exhaustive()
+    }
+}
+
+impl<B> Service<axum::http::Request<B>> for App
+where
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = <BoxCloneService<Request, Response, Infallible> as Service<Request>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: axum::http::Request<B>) -> Self::Future {
+        // Readiness was checked on this clone, so it has to serve the call
+        let mut app = self.0.clone();
+        std::mem::swap(&mut self.0, &mut app);
+        app.call(request.map(Body::new))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_app_accepts_foreign_body_types() {
+        let app = App::new(AppState::new());
+
+        // A body type other than axum's, as serverless adapters bring;
+        // the trailing slash is normalized as well
+        let request = axum::http::Request::get("/api/v1/users/")
+            .body(String::new())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = axum::http::Request::get("/missing")
+            .body(String::new())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,378 @@
+//! Shadow traffic for migrating between storage backends
+//!
+//! While a new storage backend is introduced, it runs as a shadow of the
+//! current store: every change to stored users is also applied to the
+//! [`ShadowStore`], and reads of single users are repeated against it and
+//! compared. Mismatches are logged and counted at `GET /metrics`. Clients
+//! are always answered from the primary store, so a failing or diverging
+//! shadow never affects them.
+//!
+//! Writes reach the shadow through the [`crate::events`] bus, in the order
+//! they were made. Comparisons are queued behind them, so a read is only
+//! compared once every earlier write has been applied to the shadow.
+//!
+//! The mode is switched on with `APP_SHADOW_BACKEND`, naming the store to
+//! shadow writes to, and reads are compared unless
+//! `APP_SHADOW_COMPARE_READS=false`. Library consumers start it with
+//! their own store through [`start`].
+
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::events::Event;
+use crate::metrics::Sample;
+use crate::models::User;
+use crate::AppState;
+
+/// How long the shadow store may take for one operation
+pub const SHADOW_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A storage backend receiving shadow traffic
+pub trait ShadowStore: Send + Sync + 'static {
+    /// Identifies the store in logs
+    fn name(&self) -> &str;
+
+    /// Applies a change made to the primary store
+    ///
+    /// Called at most once per change, in order. A user created while the
+    /// shadow was starting may be applied twice, so creating an existing
+    /// user replaces it.
+    fn apply<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Reads a user
+    fn get(&self, id: Uuid) -> BoxFuture<'_, io::Result<Option<User>>>;
+}
+
+/// Backends shadow traffic can be sent to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// A second in-memory store, to try the mode itself
+    Memory,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "memory" => Ok(Backend::Memory),
+            other => Err(format!(
+                "unknown shadow backend '{}' (expected memory)",
+                other
+            )),
+        }
+    }
+}
+
+/// Whether and where shadow traffic is sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    /// Store writes are shadowed to; shadowing is off when unset
+    pub backend: Option<Backend>,
+    /// Whether reads are repeated against the shadow and compared
+    pub compare_reads: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            backend: None,
+            compare_reads: true,
+        }
+    }
+}
+
+/// Users held in memory, shadowing the primary store
+///
+/// Clones share the same users.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    users: Arc<Mutex<HashMap<Uuid, User>>>,
+}
+
+impl MemoryStore {
+    /// Replaces or inserts a user, bypassing the shadow traffic
+    pub fn put(&self, user: User) {
+        self.lock().insert(user.id, user);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, User>> {
+        self.users
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl ShadowStore for MemoryStore {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn apply<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, io::Result<()>> {
+        match event {
+            Event::UserCreated(user) | Event::UserUpdated(user, _) => self.put(user.clone()),
+            Event::UserDeleted(id) => {
+                self.lock().remove(id);
+            }
+        }
+        Box::pin(std::future::ready(Ok(())))
+    }
+
+    fn get(&self, id: Uuid) -> BoxFuture<'_, io::Result<Option<User>>> {
+        let user = self.lock().get(&id).cloned();
+        Box::pin(std::future::ready(Ok(user)))
+    }
+}
+
+enum Op {
+    Write(Event),
+    Compare(Uuid, Option<User>),
+    Flush(oneshot::Sender<()>),
+}
+
+#[derive(Debug)]
+struct Running {
+    queue: mpsc::UnboundedSender<Op>,
+    compare_reads: bool,
+}
+
+/// Handle to the shadow traffic; inactive until [`start`] is called
+#[derive(Debug, Default)]
+pub struct Shadow {
+    running: OnceLock<Running>,
+    writes: AtomicU64,
+    comparisons: AtomicU64,
+    mismatches: AtomicU64,
+    failures: AtomicU64,
+}
+
+impl Shadow {
+    /// Returns `true` if shadow traffic is sent
+    pub fn is_active(&self) -> bool {
+        self.running.get().is_some()
+    }
+
+    /// Queues a comparison of the shadow's copy of user `id` with
+    /// `primary`, the primary store's
+    ///
+    /// Call while still holding the storage lock the user was read under,
+    /// so the comparison is queued behind the writes the read observed.
+    pub fn compare(&self, id: Uuid, primary: Option<&User>) {
+        if let Some(running) = self.running.get().filter(|running| running.compare_reads) {
+            let _ = running.queue.send(Op::Compare(id, primary.cloned()));
+        }
+    }
+
+    /// Waits until every queued write and comparison has been processed
+    pub async fn flush(&self) {
+        let Some(running) = self.running.get() else {
+            return;
+        };
+        let (done, flushed) = oneshot::channel();
+        if running.queue.send(Op::Flush(done)).is_ok() {
+            let _ = flushed.await;
+        }
+    }
+
+    /// Returns the shadow traffic counters as metrics
+    pub fn samples(&self) -> Vec<Sample> {
+        if !self.is_active() {
+            return Vec::new();
+        }
+        vec![
+            Sample::counter(
+                "shadow_writes_total",
+                "Changes applied to the shadow store",
+                self.writes.load(Ordering::Relaxed),
+            ),
+            Sample::counter(
+                "shadow_comparisons_total",
+                "Reads compared between the primary and the shadow store",
+                self.comparisons.load(Ordering::Relaxed),
+            ),
+            Sample::counter(
+                "shadow_mismatches_total",
+                "Reads answered differently by the shadow store",
+                self.mismatches.load(Ordering::Relaxed),
+            ),
+            Sample::counter(
+                "shadow_failures_total",
+                "Shadow store operations that failed or timed out",
+                self.failures.load(Ordering::Relaxed),
+            ),
+        ]
+    }
+
+    async fn process<S: ShadowStore>(&self, store: &S, op: Op) {
+        match op {
+            Op::Write(event) => {
+                let error = match tokio::time::timeout(SHADOW_TIMEOUT, store.apply(&event)).await {
+                    Ok(Ok(())) => {
+                        self.writes.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                    Ok(Err(err)) => err.to_string(),
+                    Err(_) => "timed out".to_string(),
+                };
+                self.fail(store, event.user_id(), "write", &error);
+            }
+            Op::Compare(id, primary) => {
+                let shadow = match tokio::time::timeout(SHADOW_TIMEOUT, store.get(id)).await {
+                    Ok(Ok(shadow)) => shadow,
+                    Ok(Err(err)) => return self.fail(store, id, "read", &err.to_string()),
+                    Err(_) => return self.fail(store, id, "read", "timed out"),
+                };
+                self.comparisons.fetch_add(1, Ordering::Relaxed);
+                let fields = differences(primary.as_ref(), shadow.as_ref());
+                if !fields.is_empty() {
+                    self.mismatches.fetch_add(1, Ordering::Relaxed);
+                    tracing::warn!(
+                        store = store.name(),
+                        user_id = %id,
+                        fields = fields.join(","),
+                        "shadow read mismatch"
+                    );
+                }
+            }
+            Op::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+
+    fn fail<S: ShadowStore>(&self, store: &S, id: Uuid, operation: &str, error: &str) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!(
+            store = store.name(),
+            user_id = %id,
+            operation,
+            error,
+            "shadow operation failed"
+        );
+    }
+}
+
+/// Names the fields in which two copies of a user differ, or `user` if
+/// only one of them exists
+fn differences(primary: Option<&User>, shadow: Option<&User>) -> Vec<String> {
+    match (primary, shadow) {
+        (None, None) => Vec::new(),
+        (Some(primary), Some(shadow)) if primary == shadow => Vec::new(),
+        (Some(primary), Some(shadow)) => {
+            let (Ok(serde_json::Value::Object(primary)), Ok(serde_json::Value::Object(shadow))) =
+                (serde_json::to_value(primary), serde_json::to_value(shadow))
+            else {
+                return vec!["user".to_string()];
+            };
+            let mut fields: Vec<String> = primary
+                .keys()
+                .chain(shadow.keys())
+                .filter(|field| primary.get(*field) != shadow.get(*field))
+                .cloned()
+                .collect();
+            fields.sort();
+            fields.dedup();
+            fields
+        }
+        _ => vec!["user".to_string()],
+    }
+}
+
+/// Starts shadowing writes and reads of `state` to `store`
+///
+/// The users already stored are copied to the shadow first. Returns the
+/// task applying the shadow traffic, or `None` if shadowing was already
+/// started.
+pub async fn start<S: ShadowStore>(state: &AppState, store: S) -> Option<JoinHandle<()>> {
+    let (queue, mut ops) = mpsc::unbounded_channel();
+    let running = Running {
+        queue: queue.clone(),
+        compare_reads: state.config.shadow.compare_reads,
+    };
+    // Holding the lock, no write can be made between the copy and the
+    // subscription
+    let storage = state.storage.read().await;
+    state.shadow.running.set(running).ok()?;
+    for user in storage.get_all() {
+        let _ = queue.send(Op::Write(Event::UserCreated(User::clone(&user))));
+    }
+    state.events.subscribe(move |event| {
+        let _ = queue.send(Op::Write(event.clone()));
+    });
+    drop(storage);
+    tracing::info!(store = store.name(), "shadowing storage traffic");
+
+    let shadow = state.shadow.clone();
+    Some(tokio::spawn(async move {
+        while let Some(op) = ops.recv().await {
+            shadow.process(&store, op).await;
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+    use chrono::Utc;
+
+    #[test]
+    fn test_differences() {
+        let primary = User::new("Primary", "primary@example.com", Utc::now());
+        let shadow = User {
+            name: "Shadow".to_string(),
+            email: "other@example.com".to_string(),
+            ..primary.clone()
+        };
+        assert!(differences(Some(&primary), Some(&primary)).is_empty());
+        assert!(differences(None, None).is_empty());
+        assert_eq!(
+            differences(Some(&primary), Some(&shadow)),
+            vec!["email", "name"]
+        );
+        assert_eq!(differences(Some(&primary), None), vec!["user"]);
+    }
+
+    #[tokio::test]
+    async fn test_writes_are_shadowed_and_reads_compared() {
+        let state = AppState::with_config(Config::default());
+        let existing = User::new("Existing", "existing@example.com", Utc::now());
+        state
+            .storage
+            .write()
+            .await
+            .create(existing.clone())
+            .unwrap();
+        let store = MemoryStore::default();
+        assert!(start(&state, store.clone()).await.is_some());
+        assert!(start(&state, MemoryStore::default()).await.is_none());
+
+        let created = User::new("Created", "created@example.com", Utc::now());
+        state.events.publish(Event::UserCreated(created.clone()));
+        state.events.publish(Event::UserDeleted(existing.id));
+        state.shadow.compare(created.id, Some(&created));
+        state.shadow.compare(existing.id, None);
+        state.shadow.flush().await;
+        assert_eq!(state.shadow.mismatches.load(Ordering::Relaxed), 0);
+        assert_eq!(state.shadow.writes.load(Ordering::Relaxed), 3);
+
+        store.put(User {
+            name: "Diverged".to_string(),
+            ..created.clone()
+        });
+        state.shadow.compare(created.id, Some(&created));
+        state.shadow.flush().await;
+        let text = crate::metrics::render(&state.shadow.samples());
+        assert!(text.contains("shadow_comparisons_total 3\n"));
+        assert!(text.contains("shadow_mismatches_total 1\n"));
+    }
+}
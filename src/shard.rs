@@ -0,0 +1,296 @@
+//! Partitioning users across instances
+//!
+//! With `APP_SHARD_PEERS` set, every instance in the list owns a share of
+//! the user ID space, assigned by consistent hashing: each peer is placed
+//! at [`VIRTUAL_NODES`] points of a hash ring, and a user belongs to the
+//! first point following the hash of its ID. Adding or removing a peer
+//! only moves the users of the ring segments it takes over or gives up.
+//!
+//! Requests addressing a single user (`/api/v1/users/:id/...` and
+//! `/api/v1/users/trash/:id/restore`) that arrive at an instance not owning
+//! the user are forwarded to the owner (see [`crate::proxy`]) and its
+//! response is passed through. New users are only given IDs the creating
+//! instance owns. Everything else, including lists, duplicates, merges
+//! and admin endpoints, is served from the local shard only.
+//!
+//! Forwarded requests carry the peers' shared token in
+//! [`X_SHARD_FORWARDED`] and are always served locally, so peers with
+//! differing peer lists cannot forward requests in circles. The header is
+//! removed from every request, and only honoured with the token, so
+//! clients cannot have an instance serve a user it does not own.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::i18n::Message;
+use crate::metrics::Sample;
+use crate::mock::IdSource;
+use crate::proxy;
+use crate::AppState;
+
+pub use crate::proxy::Peer;
+
+/// Header marking a request forwarded by a peer, carrying
+/// [`Settings::token`]
+pub const X_SHARD_FORWARDED: HeaderName = HeaderName::from_static("x-shard-forwarded");
+
+/// Points of the hash ring per peer; more points spread users more evenly
+pub const VIRTUAL_NODES: usize = 64;
+
+/// Peers sharing the user ID space
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    /// All instances, including this one; sharding is off when empty
+    pub peers: Vec<Peer>,
+    /// This instance's entry of `peers`
+    pub this: Option<Peer>,
+    /// Secret shared by the peers, marking the requests they forward
+    pub token: Option<String>,
+}
+
+impl Settings {
+    /// Whether users are partitioned across peers
+    pub fn is_enabled(&self) -> bool {
+        !self.peers.is_empty()
+    }
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+    let digest = Sha256::digest(bytes);
+    let mut prefix = [0; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(prefix)
+}
+
+/// Consistent hash ring assigning user IDs to peers
+#[derive(Debug, Clone)]
+pub struct Ring {
+    points: BTreeMap<u64, Peer>,
+}
+
+impl Ring {
+    /// Places every peer at [`VIRTUAL_NODES`] points
+    ///
+    /// The ring depends on the set of peers only, not on their order, so
+    /// all instances agree on owners as long as their lists hold the same
+    /// peers.
+    pub fn new(peers: &[Peer]) -> Self {
+        let points = peers
+            .iter()
+            .flat_map(|peer| {
+                (0..VIRTUAL_NODES)
+                    .map(move |node| (hash(format!("{}#{}", peer, node).as_bytes()), peer.clone()))
+            })
+            .collect();
+        Self { points }
+    }
+
+    /// Returns the peer owning `id`, or `None` if the ring is empty
+    pub fn owner(&self, id: Uuid) -> Option<&Peer> {
+        let point = hash(id.as_bytes());
+        self.points
+            .range(point..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, peer)| peer)
+    }
+}
+
+/// This instance's view of the shards, in [`AppState::shards`]
+#[derive(Debug, Default)]
+pub struct Shards {
+    ring: Option<(Ring, Peer)>,
+    token: Option<String>,
+    client: Option<proxy::Client>,
+    forwarded: AtomicU64,
+    failures: AtomicU64,
+}
+
+impl Shards {
+    /// Creates the shards of `settings`; sharding is off unless peers and
+    /// this instance are both set
+    pub fn new(settings: &Settings) -> Self {
+        let ring = match &settings.this {
+            Some(this) if settings.is_enabled() => Some((Ring::new(&settings.peers), this.clone())),
+            _ => None,
+        };
+        Self {
+            client: ring.as_ref().map(|_| proxy::Client::new()),
+            token: ring.as_ref().and(settings.token.clone()),
+            ring,
+            ..Self::default()
+        }
+    }
+
+    /// Whether a request's [`X_SHARD_FORWARDED`] value comes from a peer
+    fn is_forwarded(&self, value: &HeaderValue) -> bool {
+        self.token
+            .as_ref()
+            .is_some_and(|token| value.as_bytes() == token.as_bytes())
+    }
+
+    /// Whether users are partitioned across peers
+    pub fn is_enabled(&self) -> bool {
+        self.ring.is_some()
+    }
+
+    /// Returns the peer owning `id` if it is not this instance
+    pub fn remote_owner(&self, id: Uuid) -> Option<&Peer> {
+        let (ring, this) = self.ring.as_ref()?;
+        ring.owner(id).filter(|owner| *owner != this)
+    }
+
+    /// Returns a new user ID owned by this instance
+    pub fn next_id(&self, ids: &IdSource) -> Uuid {
+        loop {
+            let id = ids.next_id();
+            if self.remote_owner(id).is_none() {
+                return id;
+            }
+        }
+    }
+
+    /// Returns the forwarding counters, empty when sharding is off
+    pub fn samples(&self) -> Vec<Sample> {
+        if !self.is_enabled() {
+            return Vec::new();
+        }
+        vec![
+            Sample::counter(
+                "shard_forwarded_total",
+                "Requests forwarded to the peer owning the user",
+                self.forwarded.load(Ordering::Relaxed),
+            ),
+            Sample::counter(
+                "shard_forward_failures_total",
+                "Forwarded requests the owning peer did not answer",
+                self.failures.load(Ordering::Relaxed),
+            ),
+        ]
+    }
+}
+
+/// Returns the ID of the single user `path` addresses, if any
+fn user_id(path: &str) -> Option<Uuid> {
+    let rest = path.strip_prefix("/api/v1/users/")?;
+    let rest = rest
+        .strip_prefix("trash/")
+        .and_then(|rest| rest.strip_suffix("/restore"))
+        .unwrap_or(rest);
+    let id = rest.split('/').next()?;
+    id.parse().ok()
+}
+
+/// Middleware forwarding requests for users owned by another peer
+pub async fn route(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    // Clients may send the header too; it only counts with the token
+    let forwarded = request
+        .headers_mut()
+        .remove(X_SHARD_FORWARDED)
+        .is_some_and(|value| state.shards.is_forwarded(&value));
+    let owner = user_id(request.uri().path())
+        .filter(|_| !forwarded)
+        .and_then(|id| state.shards.remote_owner(id));
+    let (Some(owner), Some(client)) = (owner, &state.shards.client) else {
+        return next.run(request).await;
+    };
+    state.shards.forwarded.fetch_add(1, Ordering::Relaxed);
+    if let Some(Ok(value)) = state.shards.token.as_deref().map(HeaderValue::from_str) {
+        request.headers_mut().insert(X_SHARD_FORWARDED, value);
+    }
+    match client.forward(owner, request).await {
+        Ok(response) => response,
+        Err(err) => {
+            state.shards.failures.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!(peer = %owner, %err, "failed to forward request to owning peer");
+            ApiError::ServiceUnavailable(Message::new("shard.unavailable")).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peers(urls: &[&str]) -> Vec<Peer> {
+        urls.iter().map(|url| url.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_ring_is_balanced_and_stable() {
+        let three = peers(&["http://a:3000", "http://b:3000", "http://c:3000"]);
+        let ring = Ring::new(&three);
+        let ids: Vec<Uuid> = (0..3000).map(|_| Uuid::new_v4()).collect();
+        for peer in &three {
+            let owned = ids
+                .iter()
+                .filter(|id| ring.owner(**id) == Some(peer))
+                .count();
+            assert!((600..1400).contains(&owned), "{} owns {}", peer, owned);
+        }
+
+        // The order of the list does not matter
+        let reversed: Vec<Peer> = three.iter().rev().cloned().collect();
+        let same = Ring::new(&reversed);
+        assert!(ids.iter().all(|id| ring.owner(*id) == same.owner(*id)));
+
+        // A new peer only takes users over, it never moves them between
+        // the others
+        let four = Ring::new(&peers(&[
+            "http://a:3000",
+            "http://b:3000",
+            "http://c:3000",
+            "http://d:3000",
+        ]));
+        for id in &ids {
+            let owner = four.owner(*id).unwrap();
+            assert!(owner.as_str() == "http://d:3000" || Some(owner) == ring.owner(*id));
+        }
+    }
+
+    #[test]
+    fn test_new_ids_are_owned_locally() {
+        let settings = Settings {
+            peers: peers(&["http://a:3000", "http://b:3000"]),
+            this: Some("http://a:3000".parse().unwrap()),
+            token: Some("p33r".to_string()),
+        };
+        let shards = Shards::new(&settings);
+        assert!(shards.is_enabled());
+        assert!(shards.is_forwarded(&HeaderValue::from_static("p33r")));
+        assert!(!shards.is_forwarded(&HeaderValue::from_static("http://a:3000")));
+        let ids = IdSource::sequential();
+        for _ in 0..20 {
+            assert_eq!(shards.remote_owner(shards.next_id(&ids)), None);
+        }
+
+        let off = Shards::new(&Settings::default());
+        assert!(!off.is_enabled());
+        assert!(!off.is_forwarded(&HeaderValue::from_static("p33r")));
+        assert_eq!(off.remote_owner(Uuid::new_v4()), None);
+    }
+
+    #[test]
+    fn test_user_id_from_path() {
+        let id = Uuid::new_v4();
+        assert_eq!(user_id(&format!("/api/v1/users/{}", id)), Some(id));
+        assert_eq!(user_id(&format!("/api/v1/users/{}/suspend", id)), Some(id));
+        assert_eq!(
+            user_id(&format!("/api/v1/users/trash/{}/restore", id)),
+            Some(id)
+        );
+        assert_eq!(user_id("/api/v1/users"), None);
+        assert_eq!(user_id("/api/v1/users/duplicates"), None);
+        assert_eq!(user_id("/api/v1/users/trash"), None);
+    }
+}
@@ -0,0 +1,394 @@
+//! Per-route latency and error rate objectives
+//!
+//! Every routed request is recorded under its method and route pattern
+//! (`GET /api/v1/users/:id`). Over the last `APP_SLO_WINDOW_SECONDS`, the
+//! tracker computes each route's p50, p95 and p99 latency and its share of
+//! `5xx` responses, and compares them with the configured objectives.
+//! Client errors do not count against the error rate.
+//!
+//! Breaches are exported at `GET /metrics` and listed by
+//! `GET /api/v1/admin/slo`.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use utoipa::ToSchema;
+
+use crate::metrics::Sample;
+use crate::AppState;
+
+/// Observations kept per route; the oldest are dropped beyond it
+const MAX_OBSERVATIONS: usize = 10_000;
+
+/// Objectives and the window they are evaluated over
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// How far back requests are considered
+    pub window: Duration,
+    /// Highest acceptable median latency
+    pub p50: Option<Duration>,
+    /// Highest acceptable 95th percentile latency
+    pub p95: Option<Duration>,
+    /// Highest acceptable 99th percentile latency
+    pub p99: Option<Duration>,
+    /// Highest acceptable share of `5xx` responses, between 0 and 1
+    pub error_rate: Option<f64>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(300),
+            p50: None,
+            p95: None,
+            p99: Some(Duration::from_secs(1)),
+            error_rate: Some(0.01),
+        }
+    }
+}
+
+/// An objective a route can breach
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Objective {
+    /// Median latency
+    P50,
+    /// 95th percentile latency
+    P95,
+    /// 99th percentile latency
+    P99,
+    /// Share of `5xx` responses
+    ErrorRate,
+}
+
+impl Objective {
+    fn as_str(self) -> &'static str {
+        match self {
+            Objective::P50 => "p50",
+            Objective::P95 => "p95",
+            Objective::P99 => "p99",
+            Objective::ErrorRate => "error_rate",
+        }
+    }
+}
+
+/// The configured objectives, as reported
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Objectives {
+    /// Highest acceptable median latency, in milliseconds
+    pub p50_ms: Option<f64>,
+    /// Highest acceptable 95th percentile latency, in milliseconds
+    pub p95_ms: Option<f64>,
+    /// Highest acceptable 99th percentile latency, in milliseconds
+    pub p99_ms: Option<f64>,
+    /// Highest acceptable share of `5xx` responses
+    pub error_rate: Option<f64>,
+}
+
+/// A route's performance over the window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RouteSlo {
+    /// Method and route pattern, e.g. `GET /api/v1/users/:id`
+    pub route: String,
+    /// Requests answered within the window
+    pub requests: usize,
+    /// Of those, requests answered with a `5xx` status
+    pub errors: usize,
+    /// Share of `5xx` responses
+    pub error_rate: f64,
+    /// Median latency, in milliseconds
+    pub p50_ms: f64,
+    /// 95th percentile latency, in milliseconds
+    pub p95_ms: f64,
+    /// 99th percentile latency, in milliseconds
+    pub p99_ms: f64,
+    /// Objectives the route currently misses
+    pub breaches: Vec<Objective>,
+}
+
+/// Response of `GET /api/v1/admin/slo`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SloReport {
+    /// Length of the window, in seconds
+    pub window_seconds: u64,
+    /// The configured objectives
+    pub objectives: Objectives,
+    /// Every route with requests in the window, breaching routes first
+    pub routes: Vec<RouteSlo>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Observation {
+    at: Instant,
+    latency: Duration,
+    error: bool,
+}
+
+/// Recent requests per route
+#[derive(Debug, Default)]
+pub struct SloTracker {
+    settings: Settings,
+    routes: Mutex<HashMap<String, VecDeque<Observation>>>,
+}
+
+impl SloTracker {
+    /// Creates a tracker evaluating `settings`
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            settings,
+            routes: Mutex::default(),
+        }
+    }
+
+    /// Records a request to `route` that took `latency`
+    pub fn record(&self, route: &str, latency: Duration, error: bool) {
+        let now = Instant::now();
+        let mut routes = self.lock();
+        let observations = routes.entry(route.to_string()).or_default();
+        if observations.len() == MAX_OBSERVATIONS {
+            observations.pop_front();
+        }
+        observations.push_back(Observation {
+            at: now,
+            latency,
+            error,
+        });
+    }
+
+    /// Evaluates every route against the objectives
+    pub fn report(&self) -> SloReport {
+        let settings = &self.settings;
+        let now = Instant::now();
+        let mut routes: Vec<RouteSlo> = {
+            let mut routes = self.lock();
+            routes.retain(|_, observations| {
+                while observations
+                    .front()
+                    .is_some_and(|observation| now.duration_since(observation.at) > settings.window)
+                {
+                    observations.pop_front();
+                }
+                !observations.is_empty()
+            });
+            routes
+                .iter()
+                .map(|(route, observations)| self.evaluate(route, observations))
+                .collect()
+        };
+        routes.sort_by(|a, b| {
+            a.breaches
+                .is_empty()
+                .cmp(&b.breaches.is_empty())
+                .then_with(|| a.route.cmp(&b.route))
+        });
+
+        let ms = |limit: Option<Duration>| limit.map(millis);
+        SloReport {
+            window_seconds: settings.window.as_secs(),
+            objectives: Objectives {
+                p50_ms: ms(settings.p50),
+                p95_ms: ms(settings.p95),
+                p99_ms: ms(settings.p99),
+                error_rate: settings.error_rate,
+            },
+            routes,
+        }
+    }
+
+    /// Returns the report as metrics
+    pub fn samples(&self) -> Vec<Sample> {
+        let report = self.report();
+        let mut samples = Vec::new();
+        for route in &report.routes {
+            for (quantile, ms) in [
+                ("0.5", route.p50_ms),
+                ("0.95", route.p95_ms),
+                ("0.99", route.p99_ms),
+            ] {
+                samples.push(
+                    Sample::gauge(
+                        "slo_latency_seconds",
+                        "Request latency quantiles per route over the SLO window",
+                        ms / 1000.0,
+                    )
+                    .label("route", route.route.clone())
+                    .label("quantile", quantile),
+                );
+            }
+        }
+        for route in &report.routes {
+            samples.push(
+                Sample::gauge(
+                    "slo_error_ratio",
+                    "Share of 5xx responses per route over the SLO window",
+                    route.error_rate,
+                )
+                .label("route", route.route.clone()),
+            );
+        }
+        for route in &report.routes {
+            for objective in &route.breaches {
+                samples.push(
+                    Sample::gauge(
+                        "slo_breach",
+                        "Objectives a route currently misses (1 per breached objective)",
+                        1.0,
+                    )
+                    .label("route", route.route.clone())
+                    .label("objective", objective.as_str()),
+                );
+            }
+        }
+        samples
+    }
+
+    fn evaluate(&self, route: &str, observations: &VecDeque<Observation>) -> RouteSlo {
+        let settings = &self.settings;
+        let mut latencies: Vec<Duration> = observations.iter().map(|o| o.latency).collect();
+        latencies.sort_unstable();
+        let requests = latencies.len();
+        let errors = observations.iter().filter(|o| o.error).count();
+        let error_rate = errors as f64 / requests as f64;
+        let [p50, p95, p99] = [0.5, 0.95, 0.99].map(|q| percentile(&latencies, q));
+
+        let breaches = [
+            (
+                Objective::P50,
+                settings.p50.is_some_and(|limit| p50 > limit),
+            ),
+            (
+                Objective::P95,
+                settings.p95.is_some_and(|limit| p95 > limit),
+            ),
+            (
+                Objective::P99,
+                settings.p99.is_some_and(|limit| p99 > limit),
+            ),
+            (
+                Objective::ErrorRate,
+                settings.error_rate.is_some_and(|limit| error_rate > limit),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(objective, breached)| breached.then_some(objective))
+        .collect();
+
+        RouteSlo {
+            route: route.to_string(),
+            requests,
+            errors,
+            error_rate,
+            p50_ms: millis(p50),
+            p95_ms: millis(p95),
+            p99_ms: millis(p99),
+            breaches,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, VecDeque<Observation>>> {
+        self.routes
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Converts to fractional milliseconds
+fn millis(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
+
+/// Nearest-rank percentile of sorted, non-empty `latencies`
+fn percentile(latencies: &[Duration], quantile: f64) -> Duration {
+    let rank = (quantile * latencies.len() as f64).ceil() as usize;
+    latencies[rank.clamp(1, latencies.len()) - 1]
+}
+
+/// Middleware recording the latency and outcome of routed requests
+///
+/// Requests matching no route are not recorded, so probing random paths
+/// cannot create an unbounded number of routes.
+pub async fn track(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(path) = request.extensions().get::<MatchedPath>() else {
+        return next.run(request).await;
+    };
+    let route = format!("{} {}", request.method(), path.as_str());
+    let started = Instant::now();
+    let response = next.run(request).await;
+    state.slo.record(
+        &route,
+        started.elapsed(),
+        response.status().is_server_error(),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn test_percentile() {
+        let latencies: Vec<Duration> = (1..=100).map(|ms| MS * ms).collect();
+        assert_eq!(percentile(&latencies, 0.5), MS * 50);
+        assert_eq!(percentile(&latencies, 0.95), MS * 95);
+        assert_eq!(percentile(&latencies, 0.99), MS * 99);
+        assert_eq!(percentile(&latencies[..1], 0.99), MS);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_report_flags_breaches() {
+        let tracker = SloTracker::new(Settings {
+            p95: Some(MS * 100),
+            p99: None,
+            ..Settings::default()
+        });
+        for ms in 1..=100 {
+            tracker.record("GET /api/v1/users", MS * ms, false);
+        }
+        for ms in 1..=100 {
+            tracker.record("GET /api/v1/users/:id", MS * ms * 2, ms == 1);
+        }
+
+        let report = tracker.report();
+        assert_eq!(report.objectives.p95_ms, Some(100.0));
+        assert_eq!(report.routes.len(), 2);
+        let slow = &report.routes[0];
+        assert_eq!(slow.route, "GET /api/v1/users/:id");
+        assert_eq!((slow.requests, slow.errors), (100, 1));
+        assert_eq!(slow.p95_ms, 190.0);
+        assert_eq!(slow.breaches, vec![Objective::P95]);
+        assert!(report.routes[1].breaches.is_empty());
+
+        let text = crate::metrics::render(&tracker.samples());
+        assert!(text
+            .contains("slo_latency_seconds{route=\"GET /api/v1/users\",quantile=\"0.5\"} 0.05\n"));
+        assert!(text.contains("slo_error_ratio{route=\"GET /api/v1/users/:id\"} 0.01\n"));
+        assert!(text.contains("slo_breach{route=\"GET /api/v1/users/:id\",objective=\"p95\"} 1\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_old_requests_leave_the_window() {
+        let tracker = SloTracker::new(Settings::default());
+        tracker.record("DELETE /api/v1/users/:id", MS, true);
+        assert_eq!(
+            tracker.report().routes[0].breaches,
+            vec![Objective::ErrorRate]
+        );
+
+        tokio::time::advance(Settings::default().window + MS).await;
+        tracker.record("GET /api/v1/users", MS, false);
+        let report = tracker.report();
+        assert_eq!(report.routes.len(), 1);
+        assert_eq!(report.routes[0].route, "GET /api/v1/users");
+    }
+}
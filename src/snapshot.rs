@@ -0,0 +1,313 @@
+//! Backups of the user store
+//!
+//! `POST /api/v1/admin/snapshot` writes the full contents of the store
+//! (users, trash and tombstones) to a JSON file in `APP_SNAPSHOT_DIR`.
+//! `POST /api/v1/admin/restore` replaces the store with a snapshot in two
+//! steps: the first call reports what the snapshot holds and issues a
+//! confirmation token, and only a second call presenting the token within
+//! [`CONFIRMATION_TTL`] restores it. A token confirms one snapshot, once.
+//!
+//! Timestamps are written as RFC 3339 strings, so snapshots keep their
+//! sub-second precision whatever `APP_TIMESTAMP_FORMAT` is.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::events::{Event, EventBus, FieldDiff};
+use crate::models::{Snapshot, StorageFull, User};
+use crate::timestamps::{TimestampFormat, TimestampOptions};
+use crate::Storage;
+
+/// How long a restore confirmation token is valid
+pub const CONFIRMATION_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Prefix of confirmation tokens, distinguishing them from other tokens in
+/// logs
+const TOKEN_PREFIX: &str = "restore_";
+
+/// A snapshot file, response of `POST /api/v1/admin/snapshot` and of a
+/// confirmed restore
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SnapshotInfo {
+    /// File name within `APP_SNAPSHOT_DIR`
+    pub snapshot: String,
+    /// When the snapshot was taken
+    #[serde(with = "crate::timestamps")]
+    #[schema(schema_with = crate::timestamps::schema)]
+    pub taken_at: DateTime<Utc>,
+    /// Users in the snapshot
+    pub users: usize,
+    /// Deleted users in the snapshot's trash
+    pub trashed: usize,
+}
+
+impl SnapshotInfo {
+    fn new(snapshot: String, contents: &Snapshot) -> Self {
+        Self {
+            snapshot,
+            taken_at: contents.taken_at,
+            users: contents.users.len(),
+            trashed: contents.trash.len(),
+        }
+    }
+}
+
+/// Body of `POST /api/v1/admin/restore`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RestoreRequest {
+    /// File name of the snapshot within `APP_SNAPSHOT_DIR`
+    pub snapshot: String,
+    /// Token issued by a previous call for the same snapshot; without it,
+    /// nothing is restored
+    #[serde(default)]
+    pub confirmation_token: Option<String>,
+}
+
+/// What a restore would do, answered to an unconfirmed restore
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RestorePlan {
+    /// The snapshot that would be restored
+    pub snapshot: SnapshotInfo,
+    /// Users currently stored, all of which would be replaced
+    pub current_users: usize,
+    /// Token confirming the restore
+    pub confirmation_token: String,
+    /// When the token expires
+    #[serde(with = "crate::timestamps")]
+    #[schema(schema_with = crate::timestamps::schema)]
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Returns the path of snapshot `name` in `dir`
+///
+/// # Errors
+///
+/// Returns [`io::ErrorKind::InvalidInput`] unless `name` is a plain
+/// `.json` file name, so requests cannot reach outside of `dir`.
+pub fn path(dir: &Path, name: &str) -> io::Result<PathBuf> {
+    let valid = name.ends_with(".json")
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' is not a snapshot file name", name),
+        ));
+    }
+    Ok(dir.join(name))
+}
+
+/// Writes `snapshot` to a new file in `dir`
+///
+/// The file is written through [`save`], so a snapshot file is never
+/// partially written.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be written.
+pub async fn write(dir: &Path, snapshot: &Snapshot) -> io::Result<SnapshotInfo> {
+    let name = format!(
+        "snapshot-{}.json",
+        snapshot.taken_at.format("%Y%m%dT%H%M%S%.3fZ")
+    );
+    crate::fs::create_dir_all(dir).await?;
+    save(&path(dir, &name)?, snapshot).await?;
+    Ok(SnapshotInfo::new(name, snapshot))
+}
+
+/// Writes `snapshot` to `path`, replacing the file if it exists
+///
+/// The file is written under a temporary name next to `path` and renamed
+/// when complete.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be written.
+pub async fn save(path: &Path, snapshot: &Snapshot) -> io::Result<()> {
+    let options = TimestampOptions {
+        format: TimestampFormat::Rfc3339,
+        timezone: None,
+    };
+    let contents = options.sync_scope(|| serde_json::to_vec_pretty(snapshot))?;
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    crate::fs::write(&partial, contents).await?;
+    crate::fs::rename(&partial, path).await
+}
+
+/// Reads a snapshot from `path`
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or does not hold a
+/// snapshot.
+pub async fn load(path: &Path) -> io::Result<Snapshot> {
+    let contents = crate::fs::read(path).await?;
+    Ok(serde_json::from_slice(&contents)?)
+}
+
+/// Reads snapshot `name` from `dir`
+///
+/// # Errors
+///
+/// Returns an error if the name is invalid, the file cannot be read or
+/// it does not hold a snapshot.
+pub async fn read(dir: &Path, name: &str) -> io::Result<(SnapshotInfo, Snapshot)> {
+    let snapshot = load(&path(dir, name)?).await?;
+    Ok((SnapshotInfo::new(name.to_string(), &snapshot), snapshot))
+}
+
+/// Replaces the contents of `storage` with `snapshot`
+///
+/// Subscribers such as the response cache see the change as deletions of
+/// the users the snapshot lacks and writes of all others.
+///
+/// # Errors
+///
+/// Returns an error, leaving `storage` unchanged, if the snapshot holds
+/// more users than the capacity allows.
+pub fn replace(
+    storage: &mut Storage,
+    events: &EventBus,
+    snapshot: Snapshot,
+) -> Result<(), StorageFull> {
+    let previous: HashMap<Uuid, Arc<User>> = storage
+        .get_all()
+        .into_iter()
+        .map(|user| (user.id, user))
+        .collect();
+    let restored: Vec<User> = snapshot.users.clone();
+    storage.load(snapshot)?;
+    let restored_ids: HashSet<Uuid> = restored.iter().map(|user| user.id).collect();
+    for id in previous.keys() {
+        if !restored_ids.contains(id) {
+            events.publish(Event::UserDeleted(*id));
+        }
+    }
+    for user in restored {
+        let diff = match previous.get(&user.id) {
+            Some(old) => FieldDiff::between(old, &user),
+            None => FieldDiff::all(&user),
+        };
+        events.publish(Event::UserUpdated(user, diff));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+struct Pending {
+    snapshot: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// Issued restore confirmation tokens
+#[derive(Debug, Default)]
+pub struct Confirmations {
+    inner: Mutex<HashMap<String, Pending>>,
+}
+
+impl Confirmations {
+    /// Issues a token confirming the restore of `snapshot` until
+    /// `expires_at`
+    ///
+    /// Tokens expired at `now` are dropped on the way.
+    pub fn issue(&self, snapshot: &str, now: DateTime<Utc>, expires_at: DateTime<Utc>) -> String {
+        let token = format!("{}{}", TOKEN_PREFIX, Uuid::new_v4().simple());
+        let mut inner = self.lock();
+        inner.retain(|_, pending| pending.expires_at > now);
+        inner.insert(
+            token.clone(),
+            Pending {
+                snapshot: snapshot.to_string(),
+                expires_at,
+            },
+        );
+        token
+    }
+
+    /// Spends `token` if it confirms the restore of `snapshot` at `now`
+    pub fn confirm(&self, token: &str, snapshot: &str, now: DateTime<Utc>) -> bool {
+        let mut inner = self.lock();
+        let valid = inner
+            .get(token)
+            .is_some_and(|pending| pending.snapshot == snapshot && pending.expires_at > now);
+        if valid {
+            inner.remove(token);
+        }
+        valid
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Pending>> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_path_stays_in_directory() {
+        let dir = Path::new("/var/backups");
+        assert_eq!(
+            path(dir, "snapshot-20260101T000000.000Z.json").unwrap(),
+            dir.join("snapshot-20260101T000000.000Z.json")
+        );
+        for name in [
+            "../etc/passwd.json",
+            "a/b.json",
+            ".hidden.json",
+            "notes.txt",
+        ] {
+            assert_eq!(
+                path(dir, name).unwrap_err().kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+    }
+
+    #[test]
+    fn test_token_confirms_one_snapshot_once() {
+        let confirmations = Confirmations::default();
+        let now = Utc::now();
+        let token = confirmations.issue("a.json", now, now + Duration::seconds(60));
+        assert!(token.starts_with(TOKEN_PREFIX));
+
+        assert!(!confirmations.confirm(&token, "b.json", now));
+        assert!(!confirmations.confirm(&token, "a.json", now + Duration::seconds(61)));
+        assert!(confirmations.confirm(&token, "a.json", now));
+        assert!(!confirmations.confirm(&token, "a.json", now));
+    }
+
+    #[tokio::test]
+    async fn test_write_and_read_keep_precision() {
+        let dir = std::env::temp_dir().join(format!("snapshots-{}", Uuid::new_v4()));
+        let snapshot = Snapshot {
+            taken_at: DateTime::from_timestamp(1_700_000_000, 123_456_789).unwrap(),
+            users: Vec::new(),
+            trash: Vec::new(),
+            tombstones: Default::default(),
+            rate_limits: Default::default(),
+        };
+
+        let info = write(&dir, &snapshot).await.unwrap();
+        assert_eq!(info.snapshot, "snapshot-20231114T221320.123Z.json");
+        let (read_info, read_snapshot) = read(&dir, &info.snapshot).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(read_info, info);
+        assert_eq!(read_snapshot, snapshot);
+    }
+}
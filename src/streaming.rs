@@ -0,0 +1,150 @@
+//! Incremental JSON bodies for user lists
+//!
+//! An [`ApiResponse`] of users is written to the client in chunks of
+//! [`CHUNK_USERS`] users as the body is polled, rather than serialized
+//! into one buffer up front. Together with storage handing out shared
+//! users, memory use for a listing stays proportional to one chunk plus a
+//! pointer per user, even for stores of 100k users and more.
+//!
+//! The body reads exactly like the buffered one,
+//! `{"data":[...],"meta":{...},"links":{...}}`, or just the array when the
+//! client asked for no envelope. Since chunks are serialized after the
+//! handler returns, the request's [`TimestampOptions`], field case and the
+//! time of computed fields are captured up front and reapplied to each
+//! chunk. The collection size and page links of the envelope are also
+//! sent as `X-Total-Count` and `Link` headers.
+
+use std::sync::Arc;
+
+use axum::{
+    body::{Body, Bytes},
+    http::{
+        header::{CONTENT_TYPE, LINK},
+        HeaderValue,
+    },
+    response::{IntoResponse, Response},
+};
+use futures_util::stream::{self, StreamExt};
+
+use crate::computed;
+use crate::field_case::{Cased, FieldCase};
+use crate::models::User;
+use crate::paging::{self, X_TOTAL_COUNT};
+use crate::responses::{self, ApiResponse};
+use crate::timestamps::TimestampOptions;
+
+/// Number of users serialized per body chunk
+pub const CHUNK_USERS: usize = 256;
+
+impl IntoResponse for ApiResponse<Vec<Arc<User>>> {
+    fn into_response(self) -> Response {
+        let options = TimestampOptions::current();
+        let now = computed::requested_at();
+        let case = FieldCase::current();
+        let ApiResponse {
+            data: users,
+            meta,
+            links,
+        } = self;
+        let total = meta.pagination.map(|pagination| pagination.total);
+        let link = paging::link_header(&links);
+        let (head, tail) = if responses::is_enveloped() {
+            let tail = format!(
+                "],\"meta\":{},\"links\":{}}}",
+                serde_json::to_string(&Cased(&meta)).unwrap_or_else(|_| "{}".to_string()),
+                serde_json::to_string(&Cased(&links)).unwrap_or_else(|_| "{}".to_string()),
+            );
+            (Bytes::from_static(b"{\"data\":["), Bytes::from(tail))
+        } else {
+            (Bytes::from_static(b"["), Bytes::from_static(b"]"))
+        };
+
+        let mut chunks = Vec::new();
+        let mut users = users.into_iter().peekable();
+        while users.peek().is_some() {
+            chunks.push(users.by_ref().take(CHUNK_USERS).collect::<Vec<_>>());
+        }
+
+        let head = stream::once(async { Ok(head) });
+        let body = stream::iter(chunks.into_iter().enumerate()).map(move |(index, chunk)| {
+            options.sync_scope(|| {
+                computed::sync_scope(now, || case.sync_scope(|| encode(index == 0, &chunk)))
+            })
+        });
+        let tail = stream::once(async { Ok(tail) });
+
+        let mut response = Body::from_stream(head.chain(body).chain(tail)).into_response();
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        if let Some(total) = total {
+            headers.insert(X_TOTAL_COUNT, HeaderValue::from(total));
+        }
+        if let Some(link) = link {
+            headers.insert(LINK, link);
+        }
+        response
+    }
+}
+
+/// Serializes a chunk of array elements, preceded by a comma unless it is
+/// the first
+fn encode(first: bool, users: &[Arc<User>]) -> Result<Bytes, serde_json::Error> {
+    let mut buf = Vec::new();
+    for (index, user) in users.iter().enumerate() {
+        if !first || index > 0 {
+            buf.push(b',');
+        }
+        serde_json::to_writer(&mut buf, &Cased(user.as_ref()))?;
+    }
+    Ok(buf.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timestamps::TimestampFormat;
+    use chrono::Utc;
+
+    fn users(count: usize) -> Vec<Arc<User>> {
+        let now = Utc::now();
+        (0..count)
+            .map(|n| {
+                let user = User::new(format!("User {}", n), format!("user{}@example.com", n), now);
+                Arc::new(user)
+            })
+            .collect()
+    }
+
+    async fn body(response: Response) -> serde_json::Value {
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body_matches_buffered() {
+        for count in [0, 1, CHUNK_USERS, CHUNK_USERS * 2 + 3] {
+            let users = users(count);
+            let response = ApiResponse::collection(users);
+            let buffered = serde_json::to_value(&response).unwrap();
+            let streamed = body(response.into_response()).await;
+            assert_eq!(streamed, buffered);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chunks_keep_timestamp_options() {
+        let options = TimestampOptions {
+            format: TimestampFormat::Rfc3339,
+            timezone: None,
+        };
+        // The body is only polled after leaving the request's scope
+        let response = options
+            .scope(async { ApiResponse::collection(users(CHUNK_USERS + 1)).into_response() })
+            .await;
+        let streamed = body(response).await;
+        assert!(streamed["data"][CHUNK_USERS]["created_at"].is_string());
+    }
+}
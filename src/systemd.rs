@@ -0,0 +1,168 @@
+//! Running as a systemd service
+//!
+//! Built with the `systemd` feature, the server integrates with
+//! socket-activated, supervised units:
+//!
+//! - With socket activation (`LISTEN_FDS`), it serves on the first socket
+//!   systemd passes instead of binding its own, so connections arriving
+//!   during a restart queue up instead of being refused.
+//! - Under `Type=notify`, it reports `READY=1` once its startup tasks have
+//!   run and it accepts connections, and `STOPPING=1` when it starts
+//!   draining them.
+//! - With `WatchdogSec=` set, it pings the watchdog at half the interval for
+//!   as long as its runtime keeps scheduling tasks, so a wedged process is
+//!   restarted.
+//!
+//! Outside of systemd all of this does nothing. Without the feature the
+//! functions are no-ops too, and the binary refuses to start when systemd
+//! passes it sockets it cannot use.
+
+use std::io;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+
+/// Returns the listening socket passed by systemd socket activation, if
+/// any
+///
+/// # Errors
+///
+/// Returns an error if the passed socket is not a TCP listener.
+#[cfg(feature = "systemd")]
+pub fn inherited_listener() -> io::Result<Option<tokio::net::TcpListener>> {
+    let mut fds = listenfd::ListenFd::from_env();
+    let Some(listener) = fds.take_tcp_listener(0)? else {
+        return Ok(None);
+    };
+    if fds.len() > 1 {
+        tracing::warn!(
+            passed = fds.len(),
+            "systemd passed several sockets, serving on the first only"
+        );
+    }
+    listener.set_nonblocking(true)?;
+    tokio::net::TcpListener::from_std(listener).map(Some)
+}
+
+/// Returns the listening socket passed by systemd socket activation, if
+/// any
+///
+/// # Errors
+///
+/// Socket activation requires the `systemd` feature; fails if systemd
+/// passed sockets anyway.
+#[cfg(not(feature = "systemd"))]
+pub fn inherited_listener() -> io::Result<Option<tokio::net::TcpListener>> {
+    if std::env::var_os("LISTEN_FDS").is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "LISTEN_FDS is set, but the server was built without the `systemd` feature",
+        ));
+    }
+    Ok(None)
+}
+
+/// Tells systemd the service is ready, along with a status line
+pub fn notify_ready(status: &str) {
+    #[cfg(feature = "systemd")]
+    notify(&[
+        sd_notify::NotifyState::Ready,
+        sd_notify::NotifyState::Status(status),
+    ]);
+    #[cfg(not(feature = "systemd"))]
+    let _ = status;
+}
+
+/// Tells systemd the service is draining connections and shutting down
+pub fn notify_stopping() {
+    #[cfg(feature = "systemd")]
+    notify(&[
+        sd_notify::NotifyState::Stopping,
+        sd_notify::NotifyState::Status("draining connections"),
+    ]);
+}
+
+/// Tells systemd another process took over as the service's main process
+pub fn notify_main_pid(pid: u32) {
+    #[cfg(feature = "systemd")]
+    notify(&[sd_notify::NotifyState::MainPid(pid)]);
+    #[cfg(not(feature = "systemd"))]
+    let _ = pid;
+}
+
+#[cfg(feature = "systemd")]
+fn notify(states: &[sd_notify::NotifyState]) {
+    if let Err(err) = sd_notify::notify(false, states) {
+        tracing::warn!(%err, "failed to notify systemd");
+    }
+}
+
+/// Returns how often the watchdog expects a ping: half its timeout, or
+/// `None` if the unit has no watchdog
+pub fn watchdog_interval() -> Option<Duration> {
+    #[cfg(feature = "systemd")]
+    {
+        let mut usec = 0;
+        if sd_notify::watchdog_enabled(false, &mut usec) && usec > 0 {
+            return Some(Duration::from_micros(usec) / 2);
+        }
+    }
+    None
+}
+
+/// Pings the watchdog until the returned task is aborted, if the unit has
+/// one
+pub fn spawn_watchdog() -> Option<JoinHandle<()>> {
+    let interval = watchdog_interval()?;
+    tracing::info!(?interval, "pinging the systemd watchdog");
+    Some(tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            #[cfg(feature = "systemd")]
+            notify(&[sd_notify::NotifyState::Watchdog]);
+        }
+    }))
+}
+
+#[cfg(all(test, feature = "systemd"))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn test_notifications_reach_the_notify_socket() {
+        let dir = std::env::temp_dir().join(format!("rust-api-notify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        std::env::set_var("NOTIFY_SOCKET", &path);
+
+        notify_ready("serving");
+        let mut buffer = [0; 256];
+        let len = socket.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"READY=1\nSTATUS=serving\n");
+
+        notify_stopping();
+        let len = socket.recv(&mut buffer).unwrap();
+        assert!(buffer[..len].starts_with(b"STOPPING=1\n"));
+
+        std::env::remove_var("NOTIFY_SOCKET");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_watchdog_interval() {
+        assert_eq!(watchdog_interval(), None);
+        std::env::set_var("WATCHDOG_USEC", "10000000");
+        std::env::set_var("WATCHDOG_PID", std::process::id().to_string());
+        assert_eq!(watchdog_interval(), Some(Duration::from_secs(5)));
+
+        // Watchdogs meant for another process are not ours to ping
+        std::env::set_var("WATCHDOG_PID", "1");
+        assert_eq!(watchdog_interval(), None);
+        std::env::remove_var("WATCHDOG_USEC");
+        std::env::remove_var("WATCHDOG_PID");
+    }
+}
@@ -0,0 +1,207 @@
+//! Loom models of the storage locking
+//!
+//! Available with the `loom` feature. The server keeps its [`Storage`]
+//! behind one read-write lock, and whether concurrent writes can break
+//! uniqueness depends on what is checked under which guard: checking an
+//! email address under a shared guard and creating the user under a later
+//! exclusive one lets two requests both see the address free. [`explore`]
+//! runs writers concurrently under [loom](https://docs.rs/loom/), which
+//! tries every interleaving of their lock acquisitions, and applies
+//! [`invariants::check`](super::invariants::check) to the store after
+//! each. A race is found deterministically instead of once in a few
+//! thousand runs.
+//!
+//! ```
+//! use chrono::Utc;
+//! use rust_api::models::User;
+//! use rust_api::testing::concurrency::{self, Locking};
+//! use rust_api::Storage;
+//!
+//! let jane = || User::new("Jane Doe", "jane@example.com", Utc::now());
+//! let writers = [
+//!     concurrency::create(jane(), Locking::Atomic),
+//!     concurrency::create(jane(), Locking::Atomic),
+//! ];
+//! concurrency::explore(Storage::new, &writers).unwrap();
+//! ```
+
+use std::panic::{self, AssertUnwindSafe};
+
+use loom::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+use crate::models::{StorageError, User};
+use crate::Storage;
+
+/// The store shared by the writers of a model
+pub type Shared = Arc<RwLock<Storage>>;
+
+/// A write run on its own thread; refused writes are fine, only the state
+/// they leave behind is checked
+pub type Writer = std::sync::Arc<dyn Fn(&Shared) + Send + Sync>;
+
+/// How a write is checked against the users already stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locking {
+    /// Claims are checked under a shared guard, which is released before
+    /// the write takes an exclusive one
+    CheckThenWrite,
+    /// Claims are checked and the write made under one exclusive guard, as
+    /// the handlers do
+    Atomic,
+}
+
+/// A writer creating `user`
+pub fn create(user: User, locking: Locking) -> Writer {
+    std::sync::Arc::new(move |storage| {
+        let _ = create_user(storage, user.clone(), locking);
+    })
+}
+
+/// A writer giving the user with `id` the email address `email`
+pub fn change_email(id: Uuid, email: &str, locking: Locking) -> Writer {
+    let email = email.to_string();
+    std::sync::Arc::new(move |storage| {
+        let _ = set_email(storage, &id, &email, locking);
+    })
+}
+
+fn create_user(storage: &Shared, user: User, locking: Locking) -> Result<(), StorageError> {
+    match locking {
+        Locking::CheckThenWrite => {
+            storage.read().unwrap().check_claims(&user)?;
+            storage.write().unwrap().create(user)
+        }
+        Locking::Atomic => storage.write().unwrap().create_if_email_free(user),
+    }
+}
+
+fn set_email(
+    storage: &Shared,
+    id: &Uuid,
+    email: &str,
+    locking: Locking,
+) -> Result<(), StorageError> {
+    let set = |user: &mut User| user.email = email.to_string();
+    match locking {
+        Locking::CheckThenWrite => {
+            let mut user = storage
+                .read()
+                .unwrap()
+                .get(id)
+                .ok_or(StorageError::NotFound(*id))?;
+            set(&mut user);
+            storage.read().unwrap().check_claims(&user)?;
+            storage.write().unwrap().update(id, set).map(|_| ())
+        }
+        Locking::Atomic => storage
+            .write()
+            .unwrap()
+            .update_with_email_claim(id, set)
+            .map(|_| ()),
+    }
+}
+
+/// Runs `writers` concurrently on a store returned by `setup`, in every
+/// interleaving loom finds, checking the store once all of them finished
+///
+/// # Errors
+///
+/// Returns why the store was inconsistent after the first failing
+/// interleaving.
+pub fn explore<S>(setup: S, writers: &[Writer]) -> Result<(), String>
+where
+    S: Fn() -> Storage + Send + Sync + 'static,
+{
+    let writers = writers.to_vec();
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        loom::model(move || {
+            let storage: Shared = Arc::new(RwLock::new(setup()));
+            let threads: Vec<_> = writers
+                .iter()
+                .map(|writer| {
+                    let storage = storage.clone();
+                    let writer = writer.clone();
+                    loom::thread::spawn(move || writer(&storage))
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            let storage = storage.read().unwrap();
+            if let Err(reason) = super::invariants::check(&*storage) {
+                panic!("{}", reason);
+            }
+        })
+    }))
+    .map_err(|payload| {
+        payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_else(|| "a writer panicked".to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_atomic_creates_keep_emails_unique() {
+        let writers = [
+            create(
+                User::new("Jane Doe", "jane@example.com", Utc::now()),
+                Locking::Atomic,
+            ),
+            create(
+                User::new("Jane Doe", "jane@example.com", Utc::now()),
+                Locking::Atomic,
+            ),
+        ];
+        explore(Storage::new, &writers).unwrap();
+    }
+
+    #[test]
+    fn test_check_then_write_creates_race() {
+        let writers = [
+            create(
+                User::new("Jane Doe", "jane@example.com", Utc::now()),
+                Locking::CheckThenWrite,
+            ),
+            create(
+                User::new("Jane Doe", "jane@example.com", Utc::now()),
+                Locking::CheckThenWrite,
+            ),
+        ];
+        let reason = explore(Storage::new, &writers).unwrap_err();
+        assert!(
+            reason.contains("share email jane@example.com"),
+            "{}",
+            reason
+        );
+    }
+
+    #[test]
+    fn test_email_changes_race_a_create() {
+        let jane = User::new("Jane Doe", "jane@example.com", Utc::now());
+        let id = jane.id;
+        let setup = move || {
+            let mut storage = Storage::new();
+            storage.create(jane.clone()).unwrap();
+            storage
+        };
+        let writers = |locking| {
+            [
+                change_email(id, "john@example.com", locking),
+                create(
+                    User::new("John Doe", "john@example.com", Utc::now()),
+                    locking,
+                ),
+            ]
+        };
+        explore(setup.clone(), &writers(Locking::Atomic)).unwrap();
+        assert!(explore(setup, &writers(Locking::CheckThenWrite)).is_err());
+    }
+}
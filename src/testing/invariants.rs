@@ -0,0 +1,307 @@
+//! Property-based storage invariants
+//!
+//! [`certify`] runs [`proptest`]-generated sequences of creations,
+//! updates and deletions against fresh repositories. Emails and phone
+//! numbers are drawn from small pools, including a homograph and an alias
+//! of the same address, so sequences keep colliding on them. After every
+//! operation:
+//!
+//! - every listed user reads back the same through `get`
+//! - every user's email address finds that user through `find_by_email`
+//! - no two users share an email address or phone number
+//! - a refused operation left the store as it was, and an accepted
+//!   creation or deletion is visible
+//!
+//! Failing sequences are shrunk to the shortest one still failing.
+
+use chrono::{DateTime, Utc};
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestCaseError, TestRunner};
+use uuid::Uuid;
+
+use crate::models::User;
+use crate::repository::UserRepository;
+
+/// Sequences run by [`certify`]
+pub const CASES: u32 = 256;
+
+/// Longest sequence generated
+pub const MAX_OPERATIONS: usize = 32;
+
+/// Email addresses users are given; the second is a homograph of the
+/// first
+pub const EMAILS: &[&str] = &[
+    "jane@example.com",
+    "j\u{430}ne@example.com",
+    "jane+news@example.com",
+    "john@example.com",
+    "ann@example.org",
+];
+
+/// Phone numbers users are given
+pub const PHONES: &[&str] = &["+14155552671", "+14155552672", "+442079460958"];
+
+/// A change made to the repository
+///
+/// Users are picked by index among those created so far, wrapping around;
+/// operations on users when none exist use an unknown ID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    /// Creates a user with an email of [`EMAILS`] and maybe a phone number
+    /// of [`PHONES`]
+    Create {
+        /// Index into [`EMAILS`]
+        email: usize,
+        /// Index into [`PHONES`]
+        phone: Option<usize>,
+    },
+    /// Gives a user another email address
+    ChangeEmail {
+        /// Index among the created users
+        user: usize,
+        /// Index into [`EMAILS`]
+        email: usize,
+    },
+    /// Gives a user another phone number, or removes it
+    ChangePhone {
+        /// Index among the created users
+        user: usize,
+        /// Index into [`PHONES`]
+        phone: Option<usize>,
+    },
+    /// Deletes a user
+    Delete {
+        /// Index among the created users
+        user: usize,
+    },
+}
+
+/// Generates operation sequences of up to [`MAX_OPERATIONS`]
+pub fn operations() -> impl Strategy<Value = Vec<Operation>> {
+    let email = 0..EMAILS.len();
+    let phone = proptest::option::of(0..PHONES.len());
+    let user = 0..MAX_OPERATIONS;
+    let operation = prop_oneof![
+        3 => (email.clone(), phone.clone())
+            .prop_map(|(email, phone)| Operation::Create { email, phone }),
+        2 => (user.clone(), email).prop_map(|(user, email)| Operation::ChangeEmail { user, email }),
+        1 => (user.clone(), phone).prop_map(|(user, phone)| Operation::ChangePhone { user, phone }),
+        1 => user.prop_map(|user| Operation::Delete { user }),
+    ];
+    proptest::collection::vec(operation, 0..=MAX_OPERATIONS)
+}
+
+/// Checks that `repository` is consistent
+///
+/// # Errors
+///
+/// Returns the first inconsistency found.
+pub fn check<R: UserRepository + ?Sized>(repository: &R) -> Result<(), String> {
+    let users = repository.list();
+    for (index, user) in users.iter().enumerate() {
+        if repository.get(&user.id).as_ref() != Some(user) {
+            return Err(format!("user {} is listed but reads differently", user.id));
+        }
+        let found = repository.find_by_email(&user.email);
+        if found != Some(user.id) {
+            return Err(format!(
+                "email {} of user {} finds {:?}",
+                user.email, user.id, found
+            ));
+        }
+        for other in &users[index + 1..] {
+            if other.id == user.id {
+                return Err(format!("user {} is listed twice", user.id));
+            }
+            if other.email == user.email {
+                return Err(format!(
+                    "users {} and {} share email {}",
+                    user.id, other.id, user.email
+                ));
+            }
+            if user.phone.is_some() && other.phone == user.phone {
+                return Err(format!("users {} and {} share a phone", user.id, other.id));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies `operations` to `repository` in order, [`check`]ing it after
+/// each
+///
+/// # Errors
+///
+/// Returns the index of the operation after which the repository was
+/// inconsistent, and why.
+pub fn run<R: UserRepository + ?Sized>(
+    repository: &mut R,
+    operations: &[Operation],
+) -> Result<(), (usize, String)> {
+    let at = DateTime::<Utc>::UNIX_EPOCH;
+    let mut created: Vec<Uuid> = Vec::new();
+    for (index, operation) in operations.iter().enumerate() {
+        let fail = |reason: String| (index, reason);
+        let pick = |user: usize| match created.len() {
+            0 => Uuid::nil(),
+            len => created[user % len],
+        };
+        let before = sorted(repository.list());
+        let (result, id) = match *operation {
+            Operation::Create { email, phone } => {
+                let user = User {
+                    phone: phone.map(|phone| PHONES[phone].to_string()),
+                    ..User::new("Jane Doe", EMAILS[email], at)
+                };
+                let id = user.id;
+                let result = repository.create(user);
+                if result.is_ok() {
+                    created.push(id);
+                }
+                (result, id)
+            }
+            Operation::ChangeEmail { user, email } => {
+                let id = pick(user);
+                let result = match repository.get(&id) {
+                    Some(mut user) => {
+                        user.email = EMAILS[email].to_string();
+                        repository.update(user)
+                    }
+                    None => repository.delete(&id),
+                };
+                (result, id)
+            }
+            Operation::ChangePhone { user, phone } => {
+                let id = pick(user);
+                let result = match repository.get(&id) {
+                    Some(mut user) => {
+                        user.phone = phone.map(|phone| PHONES[phone].to_string());
+                        repository.update(user)
+                    }
+                    None => repository.delete(&id),
+                };
+                (result, id)
+            }
+            Operation::Delete { user } => {
+                let id = pick(user);
+                (repository.delete(&id), id)
+            }
+        };
+
+        match (&result, operation) {
+            (Err(err), _) if sorted(repository.list()) != before => {
+                return Err(fail(format!(
+                    "refused with '{}' but changed the store",
+                    err
+                )));
+            }
+            (Ok(()), Operation::Create { .. }) if repository.get(&id).is_none() => {
+                return Err(fail(format!("created user {} cannot be read", id)));
+            }
+            (Ok(()), Operation::Delete { .. }) if repository.get(&id).is_some() => {
+                return Err(fail(format!("deleted user {} can still be read", id)));
+            }
+            _ => {}
+        }
+        check(repository).map_err(fail)?;
+    }
+    Ok(())
+}
+
+fn sorted(mut users: Vec<User>) -> Vec<User> {
+    users.sort_by_key(|user| user.id);
+    users
+}
+
+/// Runs [`CASES`] generated sequences, each against a repository returned
+/// by `new`
+///
+/// # Errors
+///
+/// Returns the shortest failing sequence found and why it fails.
+pub fn certify<R, F>(new: F) -> Result<(), String>
+where
+    R: UserRepository,
+    F: Fn() -> R,
+{
+    let mut runner = TestRunner::new(Config {
+        cases: CASES,
+        failure_persistence: None,
+        ..Config::default()
+    });
+    let name = new().name().to_string();
+    runner
+        .run(&operations(), |operations| {
+            let mut repository = new();
+            run(&mut repository, &operations).map_err(|(index, reason)| {
+                TestCaseError::fail(format!("after operation {}: {}", index, reason))
+            })
+        })
+        .map_err(|err| format!("{} repository: {}", name, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::StorageError;
+    use crate::Storage;
+
+    #[test]
+    fn test_storage_is_certified() {
+        certify(Storage::new).unwrap();
+    }
+
+    /// Creates users without checking their claims
+    struct Careless(Storage);
+
+    impl UserRepository for Careless {
+        fn name(&self) -> &str {
+            "careless"
+        }
+
+        fn create(&mut self, user: User) -> Result<(), StorageError> {
+            self.0.create(user)
+        }
+
+        fn get(&self, id: &Uuid) -> Option<User> {
+            self.0.get(id)
+        }
+
+        fn list(&self) -> Vec<User> {
+            UserRepository::list(&self.0)
+        }
+
+        fn revision(&self, id: &Uuid) -> Option<u64> {
+            self.0.revision(id)
+        }
+
+        fn update(&mut self, user: User) -> Result<(), StorageError> {
+            UserRepository::update(&mut self.0, user)
+        }
+
+        fn compare_and_update(&mut self, user: User, expected: u64) -> Result<(), StorageError> {
+            UserRepository::compare_and_update(&mut self.0, user, expected)
+        }
+
+        fn delete(&mut self, id: &Uuid) -> Result<(), StorageError> {
+            self.0.delete(id)
+        }
+
+        fn find_by_email(&self, email: &str) -> Option<Uuid> {
+            self.0.find_by_email(email)
+        }
+    }
+
+    #[test]
+    fn test_duplicate_emails_are_found() {
+        let create = Operation::Create {
+            email: 0,
+            phone: None,
+        };
+        let err = run(&mut Careless(Storage::new()), &[create.clone(), create]).unwrap_err();
+        assert_eq!(err.0, 1);
+
+        let err = certify(|| Careless(Storage::new())).unwrap_err();
+        assert!(err.starts_with("careless repository"), "{}", err);
+    }
+}
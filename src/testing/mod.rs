@@ -0,0 +1,30 @@
+//! Checks for certifying storage backends
+//!
+//! Available with the `testing` feature. [`invariants`] drives any
+//! [`UserRepository`](crate::repository::UserRepository) through
+//! generated operation sequences and checks after every step that the
+//! store is still consistent, so a new backend can be held to the same
+//! guarantees as the in-memory [`Storage`](crate::Storage), and
+//! [`repository_tests`] checks the expected answer of each operation in
+//! hand-written cases:
+//!
+//! ```
+//! use rust_api::testing::{invariants, repository_tests};
+//! use rust_api::Storage;
+//!
+//! invariants::certify(Storage::new).unwrap();
+//! repository_tests::run::<Storage>().unwrap();
+//! ```
+//!
+//! [`snapshot`] keeps golden files of API responses, with IDs and
+//! timestamps normalized, so contract changes show up as reviewable
+//! diffs.
+//!
+//! With the `loom` feature, [`concurrency`] also explores every
+//! interleaving of concurrent writes to a locked store.
+
+#[cfg(feature = "loom")]
+pub mod concurrency;
+pub mod invariants;
+pub mod repository_tests;
+pub mod snapshot;
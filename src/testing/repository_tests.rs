@@ -0,0 +1,444 @@
+//! Conformance suite for repositories
+//!
+//! [`run`] puts a repository through hand-written cases covering what
+//! every backend must answer the same: creating, reading, replacing and
+//! deleting users, refusing taken IDs, email addresses and phone numbers,
+//! paging in a stable order, and comparing revisions atomically with a
+//! write. Each case gets a fresh, empty repository. Where
+//! [`invariants`](super::invariants) checks that a store stays consistent,
+//! these cases check the exact answer of each operation, so a new backend
+//! behaves like [`Storage`](crate::Storage) behind the API.
+
+use std::fmt::Debug;
+
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::models::{StorageError, User};
+use crate::repository::UserRepository;
+
+/// A case the repository did not pass
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{repository} repository failed '{case}': {reason}")]
+pub struct Failure {
+    /// Name of the repository, as it reports it
+    pub repository: String,
+    /// Name of the case
+    pub case: &'static str,
+    /// What went wrong
+    pub reason: String,
+}
+
+type Case = fn(&mut dyn UserRepository) -> Result<(), String>;
+
+/// Every case, by name
+pub const CASES: &[(&str, Case)] = &[
+    ("create and read", create_and_read),
+    ("duplicate ID", duplicate_id),
+    ("duplicate email", duplicate_email),
+    ("duplicate phone", duplicate_phone),
+    ("update", update),
+    ("update keeping own claims", update_keeping_own_claims),
+    ("update unknown user", update_unknown_user),
+    ("update to taken values", update_to_taken_values),
+    ("delete", delete),
+    ("pagination", pagination),
+    ("revisions", revisions),
+    ("compare and update", compare_and_update),
+];
+
+/// Runs every case against a default repository
+///
+/// Returns the names of the cases, all of which passed.
+///
+/// # Errors
+///
+/// Returns the first case that failed.
+pub fn run<R: UserRepository + Default>() -> Result<Vec<&'static str>, Failure> {
+    run_with(R::default)
+}
+
+/// Runs every case, each against a repository returned by `new`
+///
+/// For backends that need a connection or a scratch database to start
+/// from; `new` must return an empty repository.
+///
+/// # Errors
+///
+/// Returns the first case that failed.
+pub fn run_with<R, F>(new: F) -> Result<Vec<&'static str>, Failure>
+where
+    R: UserRepository,
+    F: Fn() -> R,
+{
+    let mut passed = Vec::with_capacity(CASES.len());
+    for (case, check) in CASES {
+        let mut repository = new();
+        check(&mut repository).map_err(|reason| Failure {
+            repository: repository.name().to_string(),
+            case,
+            reason,
+        })?;
+        passed.push(*case);
+    }
+    Ok(passed)
+}
+
+/// `seconds` after the Unix epoch
+fn at(seconds: i64) -> DateTime<Utc> {
+    DateTime::<Utc>::UNIX_EPOCH + Duration::seconds(seconds)
+}
+
+fn expect<T: PartialEq + Debug>(what: &str, actual: T, expected: T) -> Result<(), String> {
+    if actual == expected {
+        return Ok(());
+    }
+    Err(format!(
+        "{}: expected {:?}, got {:?}",
+        what, expected, actual
+    ))
+}
+
+fn create_and_read(repository: &mut dyn UserRepository) -> Result<(), String> {
+    let user = User::new("Jane Doe", "jane@example.com", at(0)).with_phone("+14155552671");
+    expect("create", repository.create(user.clone()), Ok(()))?;
+    expect("read", repository.get(&user.id), Some(user.clone()))?;
+    expect("list", repository.list(), vec![user.clone()])?;
+    expect(
+        "find by email",
+        repository.find_by_email(&user.email),
+        Some(user.id),
+    )?;
+    expect(
+        "find unknown email",
+        repository.find_by_email("john@example.com"),
+        None,
+    )?;
+    expect("read unknown user", repository.get(&Uuid::new_v4()), None)
+}
+
+fn duplicate_id(repository: &mut dyn UserRepository) -> Result<(), String> {
+    let jane = User::new("Jane Doe", "jane@example.com", at(0));
+    repository
+        .create(jane.clone())
+        .map_err(|err| err.to_string())?;
+    let mut john = User::new("John Doe", "john@example.com", at(1));
+    john.id = jane.id;
+    expect(
+        "create",
+        repository.create(john),
+        Err(StorageError::DuplicateId(jane.id)),
+    )?;
+    expect("read", repository.get(&jane.id), Some(jane))
+}
+
+fn duplicate_email(repository: &mut dyn UserRepository) -> Result<(), String> {
+    let jane = User::new("Jane Doe", "jane@example.com", at(0));
+    repository
+        .create(jane.clone())
+        .map_err(|err| err.to_string())?;
+    for email in ["jane@example.com", "j\u{430}ne@example.com"] {
+        expect(
+            &format!("create with {}", email),
+            repository.create(User::new("Jane Doe", email, at(1))),
+            Err(StorageError::DuplicateEmail {
+                email: email.to_string(),
+                holder: jane.id,
+            }),
+        )?;
+    }
+    expect("list", repository.list(), vec![jane])
+}
+
+fn duplicate_phone(repository: &mut dyn UserRepository) -> Result<(), String> {
+    let phone = "+14155552671";
+    let jane = User::new("Jane Doe", "jane@example.com", at(0)).with_phone(phone);
+    repository
+        .create(jane.clone())
+        .map_err(|err| err.to_string())?;
+    expect(
+        "create",
+        repository.create(User::new("John Doe", "john@example.com", at(1)).with_phone(phone)),
+        Err(StorageError::DuplicatePhone {
+            phone: phone.to_string(),
+            holder: jane.id,
+        }),
+    )?;
+    expect("list", repository.list(), vec![jane])
+}
+
+fn update(repository: &mut dyn UserRepository) -> Result<(), String> {
+    let mut user = User::new("Jane Doe", "jane@example.com", at(0));
+    repository
+        .create(user.clone())
+        .map_err(|err| err.to_string())?;
+    user.name = "Jane Smith".to_string();
+    user.email = "jane.smith@example.com".to_string();
+    user.phone = Some("+14155552671".to_string());
+    user.updated_at = at(1);
+    expect("update", repository.update(user.clone()), Ok(()))?;
+    expect("read", repository.get(&user.id), Some(user.clone()))?;
+    expect(
+        "find old email",
+        repository.find_by_email("jane@example.com"),
+        None,
+    )?;
+    expect(
+        "find new email",
+        repository.find_by_email(&user.email),
+        Some(user.id),
+    )
+}
+
+fn update_keeping_own_claims(repository: &mut dyn UserRepository) -> Result<(), String> {
+    let mut user = User::new("Jane Doe", "jane@example.com", at(0)).with_phone("+14155552671");
+    repository
+        .create(user.clone())
+        .map_err(|err| err.to_string())?;
+    user.name = "Jane Smith".to_string();
+    expect("update", repository.update(user.clone()), Ok(()))?;
+    expect("read", repository.get(&user.id), Some(user))
+}
+
+fn update_unknown_user(repository: &mut dyn UserRepository) -> Result<(), String> {
+    let user = User::new("Jane Doe", "jane@example.com", at(0));
+    expect(
+        "update",
+        repository.update(user.clone()),
+        Err(StorageError::NotFound(user.id)),
+    )?;
+    expect("list", repository.list(), Vec::new())
+}
+
+fn update_to_taken_values(repository: &mut dyn UserRepository) -> Result<(), String> {
+    let jane = User::new("Jane Doe", "jane@example.com", at(0)).with_phone("+14155552671");
+    let john = User::new("John Doe", "john@example.com", at(1));
+    repository
+        .create(jane.clone())
+        .map_err(|err| err.to_string())?;
+    repository
+        .create(john.clone())
+        .map_err(|err| err.to_string())?;
+
+    let mut changed = john.clone();
+    changed.email = jane.email.clone();
+    expect(
+        "update email",
+        repository.update(changed),
+        Err(StorageError::DuplicateEmail {
+            email: jane.email.clone(),
+            holder: jane.id,
+        }),
+    )?;
+    let mut changed = john.clone();
+    changed.phone = jane.phone.clone();
+    expect(
+        "update phone",
+        repository.update(changed),
+        Err(StorageError::DuplicatePhone {
+            phone: "+14155552671".to_string(),
+            holder: jane.id,
+        }),
+    )?;
+    expect("read", repository.get(&john.id), Some(john))
+}
+
+fn delete(repository: &mut dyn UserRepository) -> Result<(), String> {
+    let jane = User::new("Jane Doe", "jane@example.com", at(0)).with_phone("+14155552671");
+    repository
+        .create(jane.clone())
+        .map_err(|err| err.to_string())?;
+    expect("delete", repository.delete(&jane.id), Ok(()))?;
+    expect("read", repository.get(&jane.id), None)?;
+    expect("find by email", repository.find_by_email(&jane.email), None)?;
+    expect(
+        "delete again",
+        repository.delete(&jane.id),
+        Err(StorageError::NotFound(jane.id)),
+    )?;
+    expect(
+        "reuse email and phone",
+        repository
+            .create(User::new("Jane Doe", "jane@example.com", at(1)).with_phone("+14155552671")),
+        Ok(()),
+    )
+}
+
+fn pagination(repository: &mut dyn UserRepository) -> Result<(), String> {
+    // Created out of order, so pages cannot follow insertion order
+    let mut users: Vec<_> = [4, 0, 6, 2, 5, 1, 3]
+        .into_iter()
+        .map(|second| {
+            User::new(
+                format!("User {}", second),
+                format!("user{}@example.com", second),
+                at(second),
+            )
+        })
+        .collect();
+    for user in &users {
+        repository
+            .create(user.clone())
+            .map_err(|err| err.to_string())?;
+    }
+    users.sort_by_key(|user| user.created_at);
+    expect("count", repository.count(), users.len())?;
+
+    let ids = |page: Vec<User>| page.into_iter().map(|user| user.id).collect();
+    let expected = |range: std::ops::Range<usize>| -> Vec<Uuid> {
+        users[range].iter().map(|user| user.id).collect()
+    };
+    expect("first page", ids(repository.page(0, 3)), expected(0..3))?;
+    expect("second page", ids(repository.page(3, 3)), expected(3..6))?;
+    expect("last page", ids(repository.page(6, 3)), expected(6..7))?;
+    expect("past the end", ids(repository.page(7, 3)), Vec::new())?;
+    expect("empty page", ids(repository.page(0, 0)), Vec::new())?;
+    expect("one page", ids(repository.page(0, 100)), expected(0..7))
+}
+
+fn revisions(repository: &mut dyn UserRepository) -> Result<(), String> {
+    let mut user = User::new("Jane Doe", "jane@example.com", at(0));
+    repository
+        .create(user.clone())
+        .map_err(|err| err.to_string())?;
+    let created = repository
+        .revision(&user.id)
+        .ok_or("created user has no revision")?;
+    user.name = "Jane Smith".to_string();
+    repository
+        .update(user.clone())
+        .map_err(|err| err.to_string())?;
+    let updated = repository
+        .revision(&user.id)
+        .ok_or("updated user has no revision")?;
+    if updated <= created {
+        return Err(format!(
+            "revision went from {} to {} on update",
+            created, updated
+        ));
+    }
+    expect("unknown user", repository.revision(&Uuid::new_v4()), None)?;
+    repository.delete(&user.id).map_err(|err| err.to_string())?;
+    expect("deleted user", repository.revision(&user.id), None)
+}
+
+fn compare_and_update(repository: &mut dyn UserRepository) -> Result<(), String> {
+    let jane = User::new("Jane Doe", "jane@example.com", at(0));
+    let john = User::new("John Doe", "john@example.com", at(1));
+    repository
+        .create(jane.clone())
+        .map_err(|err| err.to_string())?;
+    repository
+        .create(john.clone())
+        .map_err(|err| err.to_string())?;
+    let read = repository
+        .revision(&john.id)
+        .ok_or("user has no revision")?;
+
+    let mut changed = john.clone();
+    changed.name = "John Smith".to_string();
+    expect(
+        "at the read revision",
+        repository.compare_and_update(changed.clone(), read),
+        Ok(()),
+    )?;
+    let current = repository
+        .revision(&john.id)
+        .ok_or("user has no revision")?;
+
+    let mut stale = john.clone();
+    stale.name = "Johnny".to_string();
+    expect(
+        "at a stale revision",
+        repository.compare_and_update(stale, read),
+        Err(StorageError::Conflict {
+            id: john.id,
+            expected: read,
+            current,
+        }),
+    )?;
+    let mut taken = changed.clone();
+    taken.email = jane.email.clone();
+    expect(
+        "to a taken email",
+        repository.compare_and_update(taken, current),
+        Err(StorageError::DuplicateEmail {
+            email: jane.email.clone(),
+            holder: jane.id,
+        }),
+    )?;
+    expect("read", repository.get(&john.id), Some(changed))?;
+    expect(
+        "revision after refusals",
+        repository.revision(&john.id),
+        Some(current),
+    )?;
+
+    let unknown = User::new("Ann Doe", "ann@example.org", at(2));
+    expect(
+        "unknown user",
+        repository.compare_and_update(unknown.clone(), current),
+        Err(StorageError::NotFound(unknown.id)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Storage;
+
+    #[test]
+    fn test_storage_conforms() {
+        let passed = run::<Storage>().unwrap();
+        assert_eq!(passed.len(), CASES.len());
+    }
+
+    /// Writes without comparing revisions
+    struct LastWriteWins(Storage);
+
+    impl UserRepository for LastWriteWins {
+        fn name(&self) -> &str {
+            "last-write-wins"
+        }
+
+        fn create(&mut self, user: User) -> Result<(), StorageError> {
+            UserRepository::create(&mut self.0, user)
+        }
+
+        fn get(&self, id: &Uuid) -> Option<User> {
+            self.0.get(id)
+        }
+
+        fn list(&self) -> Vec<User> {
+            UserRepository::list(&self.0)
+        }
+
+        fn revision(&self, id: &Uuid) -> Option<u64> {
+            self.0.revision(id)
+        }
+
+        fn update(&mut self, user: User) -> Result<(), StorageError> {
+            UserRepository::update(&mut self.0, user)
+        }
+
+        fn compare_and_update(&mut self, user: User, _: u64) -> Result<(), StorageError> {
+            UserRepository::update(&mut self.0, user)
+        }
+
+        fn delete(&mut self, id: &Uuid) -> Result<(), StorageError> {
+            self.0.delete(id)
+        }
+
+        fn find_by_email(&self, email: &str) -> Option<Uuid> {
+            self.0.find_by_email(email)
+        }
+    }
+
+    #[test]
+    fn test_lost_updates_are_found() {
+        let failure = run_with(|| LastWriteWins(Storage::new())).unwrap_err();
+        assert_eq!(failure.repository, "last-write-wins");
+        assert_eq!(failure.case, "compare and update");
+        assert!(failure.reason.starts_with("at a stale revision"));
+    }
+}
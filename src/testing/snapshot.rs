@@ -0,0 +1,432 @@
+//! Golden-file snapshots of API responses
+//!
+//! [`capture`] sends a request through the router and returns its status,
+//! `Location` header and body as one JSON value, and [`Snapshots`]
+//! compares such values with files committed under `tests/snapshots/`.
+//! Before comparing, [`normalize`] replaces what differs between runs:
+//! UUIDs become `[uuid-1]`, `[uuid-2]` and so on in order of first
+//! appearance, so a user's ID still matches its `Location` and links, and
+//! timestamps become `[timestamp]`: RFC 3339 strings anywhere, and any
+//! non-null value of a `timestamp` or `*_at` field, which may be Unix
+//! seconds depending on the negotiated format. A changed response fails with
+//! a line diff and leaves the new snapshot next to the old one as
+//! `<name>.json.new` for review; running with `UPDATE_SNAPSHOTS=1`
+//! accepts every new snapshot instead.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request},
+};
+use chrono::DateTime;
+use regex::Regex;
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+use crate::service::App;
+
+/// Where snapshots are kept, relative to the crate being tested
+pub const DIR: &str = "tests/snapshots";
+
+/// Environment variable that accepts new snapshots when set to `1`
+pub const UPDATE: &str = "UPDATE_SNAPSHOTS";
+
+/// Sends a request to `app` and returns the normalized exchange
+///
+/// The value holds the request line, the response status, the `Location`
+/// header if there is one, and the JSON body, or `null` if the body is
+/// empty or not JSON.
+pub async fn capture(app: &App, method: Method, path: &str, body: Option<Value>) -> Value {
+    let request = Request::builder()
+        .method(method.clone())
+        .uri(path)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+        .expect("snapshot requests are well-formed");
+    let response = app
+        .clone()
+        .oneshot(request)
+        .await
+        .unwrap_or_else(|never| match never {});
+    let status = response.status().as_u16();
+    let location = response
+        .headers()
+        .get(header::LOCATION)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap_or_default();
+    let mut exchange = json!({
+        "request": format!("{} {}", method, path),
+        "status": status,
+        "body": serde_json::from_slice::<Value>(&bytes).unwrap_or(Value::Null),
+    });
+    if let Some(location) = location {
+        exchange["location"] = Value::String(location);
+    }
+    normalize(&exchange)
+}
+
+/// Replaces UUIDs and timestamps in `value` with stable placeholders
+pub fn normalize(value: &Value) -> Value {
+    Normalizer::default().value(value)
+}
+
+fn uuid_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r"[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}")
+            .expect("the UUID pattern is valid")
+    })
+}
+
+/// Numbers UUIDs by first appearance across one value
+#[derive(Default)]
+struct Normalizer {
+    labels: HashMap<String, usize>,
+}
+
+impl Normalizer {
+    fn value(&mut self, value: &Value) -> Value {
+        match value {
+            Value::String(text) => Value::String(self.text(text)),
+            Value::Array(items) => {
+                Value::Array(items.iter().map(|item| self.value(item)).collect())
+            }
+            Value::Object(fields) => Value::Object(
+                fields
+                    .iter()
+                    .map(|(key, value)| {
+                        let timestamp = key == "timestamp" || key.ends_with("_at");
+                        let value = match value {
+                            Value::Null => Value::Null,
+                            _ if timestamp => Value::String("[timestamp]".to_string()),
+                            value => self.value(value),
+                        };
+                        (key.clone(), value)
+                    })
+                    .collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+
+    fn text(&mut self, text: &str) -> String {
+        if DateTime::parse_from_rfc3339(text).is_ok() {
+            return "[timestamp]".to_string();
+        }
+        uuid_pattern()
+            .replace_all(text, |uuid: &regex::Captures<'_>| {
+                let next = self.labels.len() + 1;
+                let label = self
+                    .labels
+                    .entry(uuid[0].to_ascii_lowercase())
+                    .or_insert(next);
+                format!("[uuid-{}]", label)
+            })
+            .into_owned()
+    }
+}
+
+/// A snapshot that differs from the value checked against it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The snapshot file
+    pub path: PathBuf,
+    /// Its contents, or `None` if there was no snapshot yet
+    pub expected: Option<String>,
+    /// The value checked, as it would be stored
+    pub actual: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(expected) = &self.expected else {
+            return write!(
+                f,
+                "no snapshot at {}; review {}.new and rerun with {}=1 to accept it",
+                self.path.display(),
+                self.path.display(),
+                UPDATE
+            );
+        };
+        writeln!(
+            f,
+            "snapshot {} changed (- stored, + new); rerun with {}=1 to accept:",
+            self.path.display(),
+            UPDATE
+        )?;
+        for line in diff(expected, &self.actual) {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// Lines of `old` and `new`, prefixed with `-`, `+` or a space, from
+/// their longest common subsequence
+fn diff(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    // common[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..]
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = match old[i] == new[j] {
+                true => common[i + 1][j + 1] + 1,
+                false => common[i + 1][j].max(common[i][j + 1]),
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(format!("  {}", old[i]));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] >= common[i + 1][j]) {
+            lines.push(format!("+ {}", new[j]));
+            j += 1;
+        } else {
+            lines.push(format!("- {}", old[i]));
+            i += 1;
+        }
+    }
+    lines
+}
+
+/// A directory of snapshots
+#[derive(Debug, Clone)]
+pub struct Snapshots {
+    dir: PathBuf,
+    update: bool,
+}
+
+impl Default for Snapshots {
+    /// [`DIR`] of the crate being tested, accepting new snapshots if
+    /// [`UPDATE`] is `1`
+    fn default() -> Self {
+        let root = std::env::var_os("CARGO_MANIFEST_DIR").map_or_else(PathBuf::new, PathBuf::from);
+        Self::in_dir(root.join(DIR)).accept(std::env::var(UPDATE).is_ok_and(|update| update == "1"))
+    }
+}
+
+impl Snapshots {
+    /// Snapshots kept in `dir`
+    pub fn in_dir(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            update: false,
+        }
+    }
+
+    /// Whether [`check`](Snapshots::check) stores changed values instead
+    /// of failing
+    pub fn accept(mut self, update: bool) -> Self {
+        self.update = update;
+        self
+    }
+
+    /// The directory snapshots are kept in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Compares `value`, pretty-printed, with the snapshot `name`
+    ///
+    /// # Errors
+    ///
+    /// Returns how the snapshot differs, after writing the new one to
+    /// `<name>.json.new`. Failing to write is reported as a mismatch too,
+    /// so a read-only checkout still shows the diff.
+    pub fn check(&self, name: &str, value: &Value) -> Result<(), Mismatch> {
+        let path = self.dir.join(format!("{}.json", name));
+        let mut actual = serde_json::to_string_pretty(value).expect("JSON values always serialize");
+        actual.push('\n');
+        let expected = fs::read_to_string(&path).ok();
+        if expected.as_deref() == Some(actual.as_str()) {
+            let _ = fs::remove_file(path.with_extension("json.new"));
+            return Ok(());
+        }
+        let target = match self.update {
+            true => path.clone(),
+            false => path.with_extension("json.new"),
+        };
+        let written = fs::create_dir_all(&self.dir).and_then(|()| fs::write(&target, &actual));
+        if self.update && written.is_ok() {
+            let _ = fs::remove_file(path.with_extension("json.new"));
+            return Ok(());
+        }
+        Err(Mismatch {
+            path,
+            expected,
+            actual,
+        })
+    }
+
+    /// Like [`check`](Snapshots::check), panicking with the diff
+    ///
+    /// # Panics
+    ///
+    /// Panics if the snapshot differs.
+    #[track_caller]
+    pub fn assert(&self, name: &str, value: &Value) {
+        if let Err(mismatch) = self.check(name, value) {
+            panic!("{}", mismatch);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppState;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_normalize() {
+        let id = Uuid::new_v4();
+        let other = Uuid::new_v4();
+        let value = json!({
+            "id": id.to_string(),
+            "links": { "self": format!("/api/v1/users/{}", id) },
+            "merged": [other.to_string().to_uppercase(), id.to_string()],
+            "created_at": "2024-05-01T12:30:00.123Z",
+            "updated_at": 1714566600,
+            "last_login_at": null,
+            "note": "since 2024-05-01T12:30:00Z",
+            "expires": "2024-05-01T12:30:00Z",
+            "name": "Jane",
+            "count": 2
+        });
+        assert_eq!(
+            normalize(&value),
+            json!({
+                "count": 2,
+                "created_at": "[timestamp]",
+                "expires": "[timestamp]",
+                "id": "[uuid-1]",
+                "last_login_at": null,
+                "links": { "self": "/api/v1/users/[uuid-1]" },
+                "merged": ["[uuid-2]", "[uuid-1]"],
+                "name": "Jane",
+                "note": "since 2024-05-01T12:30:00Z",
+                "updated_at": "[timestamp]"
+            })
+        );
+    }
+
+    #[test]
+    fn test_check_writes_new_snapshots_for_review() {
+        let dir = std::env::temp_dir().join(format!("snapshots-{}", Uuid::new_v4()));
+        let snapshots = Snapshots::in_dir(&dir);
+        let value = json!({ "status": 200 });
+
+        let mismatch = snapshots.check("health", &value).unwrap_err();
+        assert_eq!(mismatch.expected, None);
+        assert!(dir.join("health.json.new").exists());
+
+        snapshots
+            .clone()
+            .accept(true)
+            .check("health", &value)
+            .unwrap();
+        assert!(!dir.join("health.json.new").exists());
+        snapshots.check("health", &value).unwrap();
+
+        let mismatch = snapshots
+            .check("health", &json!({ "status": 503 }))
+            .unwrap_err();
+        let report = mismatch.to_string();
+        assert!(report.contains("-   \"status\": 200"), "{}", report);
+        assert!(report.contains("+   \"status\": 503"), "{}", report);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_user_endpoints_match_snapshots() {
+        let state = AppState::new();
+        let app = App::new(state.clone());
+        let snapshots = Snapshots::default();
+        let users = "/api/v1/users";
+
+        let created = capture(
+            &app,
+            Method::POST,
+            users,
+            Some(json!({ "name": "Jane Doe", "email": "jane@example.com" })),
+        )
+        .await;
+        snapshots.assert("create_user", &created);
+        // Placeholders are numbered per capture, so take the real ID
+        let id = state.storage.read().await.get_all()[0].id;
+        let user = format!("{}/{}", users, id);
+
+        let exchanges = [
+            ("list_users", capture(&app, Method::GET, users, None).await),
+            ("health", capture(&app, Method::GET, "/", None).await),
+            ("get_user", capture(&app, Method::GET, &user, None).await),
+            (
+                "update_user",
+                capture(
+                    &app,
+                    Method::PUT,
+                    &user,
+                    Some(json!({ "name": "Jane Smith" })),
+                )
+                .await,
+            ),
+            (
+                "create_user_invalid",
+                capture(
+                    &app,
+                    Method::POST,
+                    users,
+                    Some(json!({ "name": "", "email": "jane" })),
+                )
+                .await,
+            ),
+            (
+                "create_user_duplicate",
+                capture(
+                    &app,
+                    Method::POST,
+                    users,
+                    Some(json!({ "name": "Jane Doe", "email": "jane@example.com" })),
+                )
+                .await,
+            ),
+            (
+                "delete_user",
+                capture(&app, Method::DELETE, &user, None).await,
+            ),
+            (
+                "get_user_deleted",
+                capture(&app, Method::GET, &user, None).await,
+            ),
+            (
+                "get_user_unknown",
+                capture(
+                    &app,
+                    Method::GET,
+                    &format!("{}/{}", users, Uuid::nil()),
+                    None,
+                )
+                .await,
+            ),
+        ];
+        for (name, exchange) in &exchanges {
+            snapshots.assert(name, exchange);
+        }
+    }
+}
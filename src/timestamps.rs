@@ -0,0 +1,301 @@
+//! Timestamp serialization options
+//!
+//! Timestamps are serialized as Unix seconds by default, which is what
+//! existing consumers expect. Clients can instead ask for RFC 3339 strings,
+//! which keep sub-second precision and parse natively in JavaScript, by
+//! adding a `timestamps` parameter to the `Accept` header:
+//!
+//! ```text
+//! Accept: application/json; timestamps=rfc3339; tz=Europe/Berlin
+//! ```
+//!
+//! The optional `tz` parameter renders RFC 3339 timestamps with the offset
+//! of an IANA time zone (typically the user's stored `timezone`) instead of
+//! UTC. The server-wide default is set with `APP_TIMESTAMP_FORMAT`.
+//!
+//! The choice is applied per request by [`negotiate`], which stores it in a
+//! task-local read by the field serializers in this module. Deserialization
+//! accepts both representations.
+
+use std::str::FromStr;
+
+use axum::{
+    extract::{Request, State},
+    http::header::ACCEPT,
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use utoipa::openapi::schema::{
+    KnownFormat, ObjectBuilder, OneOfBuilder, Schema, SchemaFormat, Type,
+};
+
+use crate::validation::timezone;
+use crate::AppState;
+
+/// Wire representation of timestamps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// Integer seconds since the Unix epoch (the historical format)
+    #[default]
+    Unix,
+    /// RFC 3339 strings with sub-second precision
+    Rfc3339,
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "unix" => Ok(TimestampFormat::Unix),
+            "rfc3339" => Ok(TimestampFormat::Rfc3339),
+            other => Err(format!("unknown timestamp format '{}'", other)),
+        }
+    }
+}
+
+/// Serialization options in effect for the current request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimestampOptions {
+    /// Wire representation
+    pub format: TimestampFormat,
+    /// Time zone whose offset RFC 3339 timestamps are rendered in;
+    /// `None` renders UTC with a `Z` suffix
+    pub timezone: Option<Tz>,
+}
+
+impl TimestampOptions {
+    /// Runs `f` with these options applied to all timestamp serialization
+    pub async fn scope<F: std::future::Future>(self, f: F) -> F::Output {
+        CURRENT.scope(self, f).await
+    }
+
+    /// Runs `f` synchronously with these options applied
+    pub fn sync_scope<R>(self, f: impl FnOnce() -> R) -> R {
+        CURRENT.sync_scope(self, f)
+    }
+
+    /// Returns the options for the current request, or the defaults
+    /// outside of a request scope
+    pub fn current() -> Self {
+        CURRENT.try_with(|options| *options).unwrap_or_default()
+    }
+
+    /// Reads `timestamps` and `tz` parameters from an `Accept` header,
+    /// starting from `self` for anything not specified
+    pub fn with_accept(mut self, accept: &str) -> Self {
+        let params = accept
+            .split(',')
+            .flat_map(|range| range.split(';').skip(1))
+            .filter_map(|param| param.split_once('='));
+
+        for (name, value) in params {
+            let value = value.trim().trim_matches('"');
+            match name.trim().to_ascii_lowercase().as_str() {
+                "timestamps" => {
+                    if let Ok(format) = value.parse() {
+                        self.format = format;
+                    }
+                }
+                "tz" => {
+                    if let Ok(tz) = timezone::parse(value) {
+                        self.timezone = Some(tz);
+                    }
+                }
+                _ => {}
+            }
+        }
+        self
+    }
+
+    fn render(&self, timestamp: &DateTime<Utc>) -> String {
+        match self.timezone {
+            Some(tz) => timestamp
+                .with_timezone(&tz)
+                .to_rfc3339_opts(SecondsFormat::AutoSi, false),
+            None => timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        }
+    }
+}
+
+tokio::task_local! {
+    static CURRENT: TimestampOptions;
+}
+
+/// Middleware that applies the client's timestamp preferences
+///
+/// Falls back to the configured default format when the `Accept` header
+/// does not specify one.
+pub async fn negotiate(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let defaults = TimestampOptions {
+        format: state.config.timestamp_format,
+        timezone: None,
+    };
+    let options = request
+        .headers()
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .map_or(defaults, |accept| defaults.with_accept(accept));
+
+    options.scope(next.run(request)).await
+}
+
+/// Either accepted input representation
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Timestamp {
+    Seconds(i64),
+    Text(String),
+}
+
+impl Timestamp {
+    fn into_datetime<E: serde::de::Error>(self) -> Result<DateTime<Utc>, E> {
+        match self {
+            Timestamp::Seconds(seconds) => DateTime::from_timestamp(seconds, 0)
+                .ok_or_else(|| E::custom("timestamp out of range")),
+            Timestamp::Text(text) => DateTime::parse_from_rfc3339(&text)
+                .map(|timestamp| timestamp.with_timezone(&Utc))
+                .map_err(E::custom),
+        }
+    }
+}
+
+/// Serializes a timestamp according to the current [`TimestampOptions`]
+pub fn serialize<S: Serializer>(
+    timestamp: &DateTime<Utc>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let options = TimestampOptions::current();
+    match options.format {
+        TimestampFormat::Unix => serializer.serialize_i64(timestamp.timestamp()),
+        TimestampFormat::Rfc3339 => serializer.serialize_str(&options.render(timestamp)),
+    }
+}
+
+/// OpenAPI schema for a timestamp in either representation
+pub fn schema() -> Schema {
+    representations().into()
+}
+
+fn representations() -> OneOfBuilder {
+    OneOfBuilder::new()
+        .item(
+            ObjectBuilder::new()
+                .schema_type(Type::Integer)
+                .format(Some(SchemaFormat::KnownFormat(KnownFormat::Int64)))
+                .description(Some("Seconds since the Unix epoch")),
+        )
+        .item(
+            ObjectBuilder::new()
+                .schema_type(Type::String)
+                .format(Some(SchemaFormat::KnownFormat(KnownFormat::DateTime)))
+                .description(Some("RFC 3339 timestamp")),
+        )
+}
+
+/// Deserializes a timestamp given as Unix seconds or an RFC 3339 string
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+    Timestamp::deserialize(deserializer)?.into_datetime()
+}
+
+/// The same conversions for optional timestamps
+pub mod option {
+    use super::*;
+
+    /// Serializes an optional timestamp according to the current options
+    pub fn serialize<S: Serializer>(
+        timestamp: &Option<DateTime<Utc>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match timestamp {
+            Some(timestamp) => super::serialize(timestamp, serializer),
+            None => None::<i64>.serialize(serializer),
+        }
+    }
+
+    /// OpenAPI schema for an optional timestamp
+    pub fn schema() -> Schema {
+        representations()
+            .item(ObjectBuilder::new().schema_type(Type::Null))
+            .into()
+    }
+
+    /// Deserializes an optional timestamp in either representation
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DateTime<Utc>>, D::Error> {
+        Option::<Timestamp>::deserialize(deserializer)?
+            .map(Timestamp::into_datetime)
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Stamped {
+        #[serde(with = "super")]
+        at: DateTime<Utc>,
+        #[serde(default, with = "super::option")]
+        seen: Option<DateTime<Utc>>,
+    }
+
+    fn sample() -> Stamped {
+        Stamped {
+            at: DateTime::parse_from_rfc3339("2024-03-01T12:30:45.250Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            seen: None,
+        }
+    }
+
+    #[test]
+    fn test_unix_by_default() {
+        let json = serde_json::to_value(sample()).unwrap();
+        assert_eq!(json["at"], 1709296245);
+        assert!(json["seen"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_rfc3339_keeps_precision() {
+        let options =
+            TimestampOptions::default().with_accept("application/json; timestamps=rfc3339");
+        let json = options
+            .scope(async { serde_json::to_value(sample()).unwrap() })
+            .await;
+        assert_eq!(json["at"], "2024-03-01T12:30:45.250Z");
+    }
+
+    #[tokio::test]
+    async fn test_rfc3339_in_timezone() {
+        let options = TimestampOptions::default()
+            .with_accept("application/json;timestamps=rfc3339;tz=\"Europe/Berlin\"");
+        let json = options
+            .scope(async { serde_json::to_value(sample()).unwrap() })
+            .await;
+        assert_eq!(json["at"], "2024-03-01T13:30:45.250+01:00");
+    }
+
+    #[test]
+    fn test_deserialize_either_representation() {
+        let unix: Stamped = serde_json::from_str(r#"{"at": 1709296245, "seen": null}"#).unwrap();
+        let text: Stamped =
+            serde_json::from_str(r#"{"at": "2024-03-01T13:30:45+01:00", "seen": 1709296245}"#)
+                .unwrap();
+        assert_eq!(unix.at, text.at);
+        assert_eq!(text.seen, Some(unix.at));
+        assert!(serde_json::from_str::<Stamped>(r#"{"at": "yesterday"}"#).is_err());
+    }
+
+    #[test]
+    fn test_with_accept_ignores_unknown_values() {
+        let options =
+            TimestampOptions::default().with_accept("application/json; timestamps=iso; tz=Nowhere");
+        assert_eq!(options, TimestampOptions::default());
+    }
+}
@@ -0,0 +1,214 @@
+//! Request timing and slow-request logging
+//!
+//! All handlers share one [`TimedLock`] around the user store, so a slow
+//! request is often not slow itself but waiting for another request's
+//! write lock. The lock adds the time spent waiting for it to the current
+//! request's timings, and [`log_slow`] logs every request slower than
+//! `APP_SLOW_REQUEST_MS` with its route, parameters and how much of its
+//! time went to waiting for the lock versus running the handler.
+//!
+//! Only time spent while the request's handler and middleware run is
+//! attributed; lock waits in spawned tasks are not.
+//!
+//! Independently of requests, every wait is recorded in a histogram per
+//! lock and mode, exported at `GET /metrics` as `lock_wait_seconds`, to
+//! quantify contention across changes to the storage.
+
+use std::cell::Cell;
+use std::time::Duration;
+
+use axum::{
+    extract::{MatchedPath, RawPathParams, Request, State},
+    middleware::Next,
+    response::Response,
+    RequestExt,
+};
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tokio::time::Instant;
+
+use crate::metrics::{Histogram, Sample};
+use crate::AppState;
+
+/// Upper bounds of the lock wait histogram buckets, in seconds
+pub const LOCK_WAIT_BUCKETS: &[f64] = &[
+    0.000_01, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0,
+];
+
+tokio::task_local! {
+    static TIMINGS: Timings;
+}
+
+/// Time a request spent waiting for locks
+#[derive(Debug, Default)]
+struct Timings {
+    lock_wait: Cell<Duration>,
+    lock_acquisitions: Cell<u32>,
+}
+
+/// A read-write lock recording how long acquiring it took
+///
+/// Within a request, the waits add up to the `lock_wait_ms` logged for
+/// slow requests. All waits are also recorded in the lock's histograms.
+#[derive(Debug)]
+pub struct TimedLock<T> {
+    name: &'static str,
+    inner: RwLock<T>,
+    read_wait: Histogram,
+    write_wait: Histogram,
+}
+
+impl<T> TimedLock<T> {
+    /// Wraps `value` in a lock reported as `name`
+    pub fn new(name: &'static str, value: T) -> Self {
+        Self {
+            name,
+            inner: RwLock::new(value),
+            read_wait: Histogram::new(LOCK_WAIT_BUCKETS),
+            write_wait: Histogram::new(LOCK_WAIT_BUCKETS),
+        }
+    }
+
+    /// Locks for reading, waiting while a writer holds the lock
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        let started = Instant::now();
+        let guard = self.inner.read().await;
+        let wait = started.elapsed();
+        self.read_wait.observe(wait);
+        record_wait(wait);
+        guard
+    }
+
+    /// Locks for writing, waiting while anyone holds the lock
+    pub async fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let started = Instant::now();
+        let guard = self.inner.write().await;
+        let wait = started.elapsed();
+        self.write_wait.observe(wait);
+        record_wait(wait);
+        guard
+    }
+
+    /// Returns the wait histograms, labeled with the lock's name and mode
+    pub fn wait_samples(&self) -> Vec<Sample> {
+        let mut samples = Vec::new();
+        for (mode, histogram) in [("read", &self.read_wait), ("write", &self.write_wait)] {
+            samples.extend(histogram.samples(
+                "lock_wait_seconds",
+                "Time spent waiting to acquire a lock",
+                &[("lock", self.name.to_string()), ("mode", mode.to_string())],
+            ));
+        }
+        samples
+    }
+}
+
+fn record_wait(wait: Duration) {
+    // Outside of a request there is nothing to attribute the wait to
+    let _ = TIMINGS.try_with(|timings| {
+        timings.lock_wait.set(timings.lock_wait.get() + wait);
+        timings
+            .lock_acquisitions
+            .set(timings.lock_acquisitions.get() + 1);
+    });
+}
+
+/// Runs `future` with fresh timings, returning its output, the total lock
+/// wait and the number of lock acquisitions
+async fn timed<F: std::future::Future>(future: F) -> (F::Output, Duration, u32) {
+    TIMINGS
+        .scope(Timings::default(), async move {
+            let output = future.await;
+            let (wait, acquisitions) =
+                TIMINGS.with(|timings| (timings.lock_wait.get(), timings.lock_acquisitions.get()));
+            (output, wait, acquisitions)
+        })
+        .await
+}
+
+/// Middleware logging requests slower than `APP_SLOW_REQUEST_MS`
+pub async fn log_slow(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let Some(threshold) = state.config.slow_request_threshold else {
+        return next.run(request).await;
+    };
+    let method = request.method().clone();
+    let uri = request.uri().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    let params = request
+        .extract_parts::<RawPathParams>()
+        .await
+        .map(|params| {
+            params
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .unwrap_or_default();
+
+    let started = Instant::now();
+    let (response, lock_wait, lock_acquisitions) = timed(next.run(request)).await;
+    let total = started.elapsed();
+    if total >= threshold {
+        tracing::warn!(
+            %method,
+            route = route.as_deref().unwrap_or("unrouted"),
+            path = uri.path(),
+            query = uri.query().unwrap_or_default(),
+            params,
+            status = response.status().as_u16(),
+            total_ms = millis(total),
+            lock_wait_ms = millis(lock_wait),
+            handler_ms = millis(total.saturating_sub(lock_wait)),
+            lock_acquisitions,
+            "slow request"
+        );
+    }
+    response
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lock_wait_is_attributed_to_the_request() {
+        let lock = TimedLock::new("test", 0);
+        // Taken outside of the request, so not attributed to it
+        let writer = lock.write().await;
+        let release = async {
+            tokio::time::sleep(Duration::from_millis(40)).await;
+            drop(writer);
+        };
+        let request = timed(async {
+            let value = *lock.read().await;
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            *lock.write().await += 1;
+            value
+        });
+
+        let ((value, wait, acquisitions), ()) = tokio::join!(request, release);
+
+        assert_eq!(value, 0);
+        assert_eq!(wait, Duration::from_millis(40));
+        assert_eq!(acquisitions, 2);
+        assert_eq!(*lock.read().await, 1);
+
+        let text = crate::metrics::render(&lock.wait_samples());
+        assert!(text.contains("lock_wait_seconds_count{lock=\"test\",mode=\"read\"} 2\n"));
+        assert!(text.contains("lock_wait_seconds_count{lock=\"test\",mode=\"write\"} 2\n"));
+        // The read waited for the writer: 40ms, in the 50ms bucket
+        assert!(
+            text.contains("lock_wait_seconds_bucket{lock=\"test\",mode=\"read\",le=\"0.01\"} 1\n")
+        );
+        assert!(
+            text.contains("lock_wait_seconds_bucket{lock=\"test\",mode=\"read\",le=\"0.05\"} 2\n")
+        );
+    }
+}
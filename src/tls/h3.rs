@@ -0,0 +1,295 @@
+//! Experimental HTTP/3 listener built on quinn and h3
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, Request},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use futures_util::StreamExt;
+use h3::server::RequestStream;
+use hyper::body::Buf;
+use quinn::crypto::rustls::QuicServerConfig;
+use rustls::pki_types::CertificateDer;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tower::{Service, ServiceExt};
+
+use super::server::{client_certificate, invalid, server_config};
+use super::Settings;
+
+/// Largest request body read from an HTTP/3 stream, in bytes
+pub const MAX_BODY: usize = 2 * 1024 * 1024;
+
+/// Serves `app` over HTTP/3 on the UDP port `addr` until `shutdown`
+/// completes
+///
+/// Uses the same certificates and client authentication as
+/// [`serve`](super::serve). On shutdown, connections are told to stop
+/// sending new requests and closed once their in-flight requests were
+/// answered.
+///
+/// # Errors
+///
+/// Returns an error if the certificate, key or client CA files cannot be
+/// loaded or the port cannot be bound.
+pub async fn serve_h3<S, F>(
+    addr: SocketAddr,
+    app: S,
+    settings: &Settings,
+    shutdown: F,
+) -> io::Result<()>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send,
+    F: Future<Output = ()>,
+{
+    let crypto = QuicServerConfig::try_from(server_config(settings, &[b"h3"])?).map_err(invalid)?;
+    let config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    let endpoint = quinn::Endpoint::server(config, addr)?;
+    let (draining, drain) = watch::channel(false);
+    let mut connections = JoinSet::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let incoming = tokio::select! {
+            incoming = endpoint.accept() => match incoming {
+                Some(incoming) => incoming,
+                None => break,
+            },
+            () = &mut shutdown => break,
+        };
+        let app = app.clone();
+        let mut drain = drain.clone();
+
+        connections.spawn(async move {
+            let peer = incoming.remote_address();
+            let connection = match incoming.await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    tracing::debug!(%peer, %err, "QUIC handshake failed");
+                    return;
+                }
+            };
+            let certificate = connection
+                .peer_identity()
+                .and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok())
+                .and_then(|chain| chain.first().and_then(client_certificate));
+            let mut connection =
+                match h3::server::Connection::new(h3_quinn::Connection::new(connection)).await {
+                    Ok(connection) => connection,
+                    Err(err) => {
+                        tracing::debug!(%peer, %err, "HTTP/3 connection failed");
+                        return;
+                    }
+                };
+
+            let mut requests = JoinSet::new();
+            loop {
+                let resolver = tokio::select! {
+                    accepted = connection.accept() => match accepted {
+                        Ok(Some(resolver)) => resolver,
+                        Ok(None) => break,
+                        Err(err) => {
+                            tracing::debug!(%peer, %err, "HTTP/3 connection closed with an error");
+                            break;
+                        }
+                    },
+                    _ = drain.changed() => {
+                        let _ = connection.shutdown(0).await;
+                        break;
+                    }
+                };
+                let app = app.clone();
+                let certificate = certificate.clone();
+                requests.spawn(async move {
+                    let (request, stream) = match resolver.resolve_request().await {
+                        Ok(resolved) => resolved,
+                        Err(err) => {
+                            tracing::debug!(%peer, %err, "invalid HTTP/3 request");
+                            return;
+                        }
+                    };
+                    let mut request = request.map(|()| Body::empty());
+                    request.extensions_mut().insert(ConnectInfo(peer));
+                    if let Some(certificate) = certificate {
+                        request.extensions_mut().insert(certificate);
+                    }
+                    if let Err(err) = respond(app, request, stream).await {
+                        tracing::debug!(%peer, %err, "HTTP/3 request failed");
+                    }
+                });
+                while requests.try_join_next().is_some() {}
+            }
+            while requests.join_next().await.is_some() {}
+        });
+        // Reap finished connections so the set does not grow unbounded
+        while connections.try_join_next().is_some() {}
+    }
+
+    let _ = draining.send(true);
+    while connections.join_next().await.is_some() {}
+    endpoint.wait_idle().await;
+    Ok(())
+}
+
+/// Reads the body of `request` from `stream`, passes it to `app` and
+/// streams the response back
+///
+/// Bodies over [`MAX_BODY`] are answered with `413 Payload Too Large`
+/// without reaching `app`.
+async fn respond<S, T>(
+    app: S,
+    request: Request,
+    mut stream: RequestStream<T, Bytes>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: Service<Request, Response = Response, Error = Infallible>,
+    T: h3::quic::BidiStream<Bytes>,
+{
+    let mut body = Vec::new();
+    while let Some(mut chunk) = stream.recv_data().await? {
+        if body.len() + chunk.remaining() > MAX_BODY {
+            // Answer before the client finished sending, then tell it to
+            // stop (RFC 9114, section 4.1.1)
+            let (parts, _) = StatusCode::PAYLOAD_TOO_LARGE.into_response().into_parts();
+            stream
+                .send_response(Response::from_parts(parts, ()))
+                .await?;
+            stream.stop_sending(h3::error::Code::H3_NO_ERROR);
+            stream.finish().await?;
+            return Ok(());
+        }
+        while chunk.has_remaining() {
+            let bytes = chunk.chunk();
+            body.extend_from_slice(bytes);
+            let read = bytes.len();
+            chunk.advance(read);
+        }
+    }
+    let (parts, _) = request.into_parts();
+    let request = Request::from_parts(parts, Body::from(body));
+
+    let response = match app.oneshot(request).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    };
+    let (parts, body) = response.into_parts();
+    stream
+        .send_response(Response::from_parts(parts, ()))
+        .await?;
+    let mut body = body.into_data_stream();
+    while let Some(chunk) = body.next().await {
+        stream.send_data(chunk?).await?;
+    }
+    stream.finish().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Bytes,
+        http,
+        routing::{get, post},
+        Router,
+    };
+    use quinn::crypto::rustls::QuicClientConfig;
+    use tokio::sync::oneshot;
+
+    use crate::tls::tests::{client_config, localhost};
+
+    /// Sends `request` with `body` and returns the response's status and
+    /// body
+    async fn send(
+        client: &mut h3::client::SendRequest<h3_quinn::OpenStreams, Bytes>,
+        request: http::Request<()>,
+        body: &[u8],
+    ) -> (StatusCode, Vec<u8>) {
+        let mut stream = client.send_request(request).await.unwrap();
+        for chunk in body.chunks(64 * 1024) {
+            // The server stops reading once it rejected the request
+            if stream
+                .send_data(Bytes::copy_from_slice(chunk))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+        let _ = stream.finish().await;
+
+        let response = stream.recv_response().await.unwrap();
+        let mut body = Vec::new();
+        while let Some(mut chunk) = stream.recv_data().await.unwrap() {
+            while chunk.has_remaining() {
+                let bytes = chunk.chunk();
+                body.extend_from_slice(bytes);
+                let read = bytes.len();
+                chunk.advance(read);
+            }
+        }
+        (response.status(), body)
+    }
+
+    #[tokio::test]
+    async fn test_serves_requests_over_quic() {
+        let addr = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let app = Router::new()
+            .route("/", get(|| async { "hello" }))
+            .route("/echo", post(|body: Bytes| async move { body }));
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            serve_h3(addr, app, &localhost(), async {
+                let _ = stopped.await;
+            })
+            .await
+        });
+
+        let crypto = QuicClientConfig::try_from(client_config(&[b"h3"])).unwrap();
+        let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+        let connection = endpoint.connect(addr, "localhost").unwrap().await.unwrap();
+        let (mut driver, mut client) = h3::client::new(h3_quinn::Connection::new(connection))
+            .await
+            .unwrap();
+        let driver = tokio::spawn(async move { driver.wait_idle().await });
+
+        let request = http::Request::get("https://localhost/").body(()).unwrap();
+        assert_eq!(
+            send(&mut client, request, b"").await,
+            (StatusCode::OK, b"hello".to_vec())
+        );
+
+        let request = http::Request::post("https://localhost/echo")
+            .body(())
+            .unwrap();
+        let (status, body) = send(&mut client, request, b"ping").await;
+        assert_eq!(
+            (status, body.as_slice()),
+            (StatusCode::OK, b"ping".as_slice())
+        );
+
+        let request = http::Request::post("https://localhost/echo")
+            .body(())
+            .unwrap();
+        let (status, _) = send(&mut client, request, &vec![0; MAX_BODY + 1]).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        drop(client);
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        let _ = driver.await;
+        endpoint.wait_idle().await;
+    }
+}
@@ -0,0 +1,148 @@
+//! TLS termination
+//!
+//! With `APP_TLS_CERT` and `APP_TLS_KEY` set, the server speaks HTTPS
+//! itself instead of relying on a proxy. `APP_TLS_CLIENT_AUTH` makes it
+//! ask for client certificates, verified against the CA bundle in
+//! `APP_TLS_CLIENT_CA`, for zero-trust deployments where every internal
+//! caller holds a certificate. The subject of a verified certificate is
+//! attached to the connection's requests as an
+//! [`auth::certificate::ClientCertificate`](crate::auth::certificate::ClientCertificate).
+//!
+//! Connections negotiate HTTP/2 or HTTP/1.1 through ALPN. With
+//! `APP_TLS_HTTP3` set, an experimental HTTP/3 listener additionally
+//! accepts QUIC on the same port over UDP, and responses over TCP
+//! advertise it in an `Alt-Svc` header so clients can switch on their next
+//! request.
+//!
+//! Serving TLS requires the `tls` feature and HTTP/3 the `http3` feature;
+//! the settings are always available so that a misconfigured build fails
+//! at startup.
+
+#[cfg(feature = "http3")]
+mod h3;
+#[cfg(feature = "tls")]
+mod server;
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[cfg(feature = "http3")]
+pub use h3::serve_h3;
+#[cfg(feature = "tls")]
+pub use server::{certificate_expiry, serve};
+
+/// How long clients may remember the HTTP/3 listener advertised in
+/// `Alt-Svc`, in seconds
+pub const ALT_SVC_MAX_AGE: u32 = 24 * 60 * 60;
+
+/// Returns the `Alt-Svc` value advertising HTTP/3 on `port`
+pub fn alt_svc(port: u16) -> String {
+    format!("h3=\":{}\"; ma={}", port, ALT_SVC_MAX_AGE)
+}
+
+/// Whether clients are asked for a certificate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClientAuth {
+    /// No certificate is requested
+    #[default]
+    Off,
+    /// A certificate is requested and verified if presented
+    Optional,
+    /// Connections without a valid certificate are refused
+    Required,
+}
+
+impl FromStr for ClientAuth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" => Ok(ClientAuth::Off),
+            "optional" => Ok(ClientAuth::Optional),
+            "required" => Ok(ClientAuth::Required),
+            other => Err(format!(
+                "unknown client auth mode '{}' (expected off, optional or required)",
+                other
+            )),
+        }
+    }
+}
+
+/// Certificate files and client authentication mode
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    /// PEM file with the server's certificate chain
+    pub certificate: Option<PathBuf>,
+    /// PEM file with the server's private key
+    pub private_key: Option<PathBuf>,
+    /// PEM file with the CAs client certificates must chain to
+    pub client_ca: Option<PathBuf>,
+    /// Whether clients are asked for a certificate
+    pub client_auth: ClientAuth,
+    /// Whether HTTP/3 is served over QUIC next to TLS over TCP
+    pub http3: bool,
+}
+
+impl Settings {
+    /// Returns `true` if the server terminates TLS
+    pub fn is_enabled(&self) -> bool {
+        self.certificate.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns settings serving the `localhost` certificate from
+    /// `tests/fixtures/tls`
+    #[cfg(feature = "tls")]
+    pub(super) fn localhost() -> Settings {
+        let fixture = |name: &str| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures/tls")
+                .join(name)
+        };
+        Settings {
+            certificate: Some(fixture("localhost.pem")),
+            private_key: Some(fixture("localhost.key")),
+            ..Settings::default()
+        }
+    }
+
+    /// Returns a client configuration trusting the fixtures' CA and
+    /// offering the `alpn` protocols
+    #[cfg(feature = "tls")]
+    pub(super) fn client_config(alpn: &[&[u8]]) -> rustls::ClientConfig {
+        use rustls::pki_types::pem::PemObject;
+        use rustls::pki_types::CertificateDer;
+
+        let ca = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls/ca.pem");
+        let mut roots = rustls::RootCertStore::empty();
+        roots
+            .add(CertificateDer::from_pem_file(ca).unwrap())
+            .unwrap();
+        let mut config = rustls::ClientConfig::builder_with_provider(std::sync::Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+        config
+    }
+
+    #[test]
+    fn test_parse_client_auth() {
+        assert_eq!("off".parse(), Ok(ClientAuth::Off));
+        assert_eq!("Optional".parse(), Ok(ClientAuth::Optional));
+        assert_eq!(" required ".parse(), Ok(ClientAuth::Required));
+        assert!("always".parse::<ClientAuth>().is_err());
+    }
+
+    #[test]
+    fn test_alt_svc() {
+        assert_eq!(alt_svc(3000), "h3=\":3000\"; ma=86400");
+    }
+}
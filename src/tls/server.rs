@@ -0,0 +1,248 @@
+//! HTTPS listener built on rustls
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request},
+    http::{header, HeaderValue},
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+use tower::{Service, ServiceExt};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use super::{ClientAuth, Settings};
+use crate::auth::certificate::ClientCertificate;
+
+/// Serves `app` over TLS on `listener` until `shutdown` completes or the
+/// listener fails
+///
+/// On shutdown, no new connections are accepted and open ones are closed
+/// once their in-flight requests were answered. Handshake and connection
+/// errors only end the affected connection. With HTTP/3 enabled, every
+/// response advertises it in `Alt-Svc` for the listener's port.
+///
+/// # Errors
+///
+/// Returns an error if the certificate, key or client CA files cannot be
+/// loaded.
+pub async fn serve<S, F>(
+    listener: TcpListener,
+    app: S,
+    settings: &Settings,
+    shutdown: F,
+) -> io::Result<()>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send,
+    F: Future<Output = ()>,
+{
+    let config = server_config(settings, &[b"h2", b"http/1.1"])?;
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let alt_svc = match settings.http3 {
+        true => Some(
+            HeaderValue::try_from(super::alt_svc(listener.local_addr()?.port()))
+                .map_err(invalid)?,
+        ),
+        false => None,
+    };
+    let (draining, drain) = watch::channel(false);
+    let mut connections = JoinSet::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            () = &mut shutdown => break,
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        let alt_svc = alt_svc.clone();
+        let mut drain = drain.clone();
+
+        connections.spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    tracing::debug!(%peer, %err, "TLS handshake failed");
+                    return;
+                }
+            };
+            let certificate = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|chain| chain.first())
+                .and_then(client_certificate);
+
+            let service = hyper::service::service_fn(move |request: Request<Incoming>| {
+                let mut request = request.map(Body::new);
+                request.extensions_mut().insert(ConnectInfo(peer));
+                if let Some(certificate) = &certificate {
+                    request.extensions_mut().insert(certificate.clone());
+                }
+                let response = app.clone().oneshot(request);
+                let alt_svc = alt_svc.clone();
+                async move {
+                    let mut response = response.await?;
+                    if let Some(alt_svc) = alt_svc {
+                        response.headers_mut().insert(header::ALT_SVC, alt_svc);
+                    }
+                    Ok::<_, Infallible>(response)
+                }
+            });
+            let builder = Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+            tokio::pin!(connection);
+            let result = tokio::select! {
+                result = connection.as_mut() => result,
+                _ = drain.changed() => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+            if let Err(err) = result {
+                tracing::debug!(%peer, %err, "connection closed with an error");
+            }
+        });
+        // Reap finished connections so the set does not grow unbounded
+        while connections.try_join_next().is_some() {}
+    }
+
+    let _ = draining.send(true);
+    while connections.join_next().await.is_some() {}
+    Ok(())
+}
+
+/// Builds the rustls configuration for `settings`, offering the `alpn`
+/// protocols in order of preference
+pub(super) fn server_config(settings: &Settings, alpn: &[&[u8]]) -> io::Result<ServerConfig> {
+    let (Some(certificate), Some(private_key)) = (&settings.certificate, &settings.private_key)
+    else {
+        return Err(invalid("a certificate and a private key are required"));
+    };
+    let chain = load_certificates(certificate)?;
+    let key = PrivateKeyDer::from_pem_file(private_key)
+        .map_err(|err| invalid(format!("{}: {}", private_key.display(), err)))?;
+
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(invalid)?;
+    let builder = match (settings.client_auth, &settings.client_ca) {
+        (ClientAuth::Off, _) => builder.with_no_client_auth(),
+        (_, None) => return Err(invalid("client authentication requires a client CA")),
+        (mode, Some(client_ca)) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in load_certificates(client_ca)? {
+                roots.add(certificate).map_err(invalid)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(roots.into(), provider);
+            let verifier = if mode == ClientAuth::Optional {
+                verifier.allow_unauthenticated()
+            } else {
+                verifier
+            };
+            builder.with_client_cert_verifier(verifier.build().map_err(invalid)?)
+        }
+    };
+
+    let mut config = builder.with_single_cert(chain, key).map_err(invalid)?;
+    config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+    Ok(config)
+}
+
+fn load_certificates(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| invalid(format!("{}: {}", path.display(), err)))?;
+    if certificates.is_empty() {
+        return Err(invalid(format!("{}: no certificate found", path.display())));
+    }
+    Ok(certificates)
+}
+
+/// Returns when the first certificate in the PEM file at `path` expires
+///
+/// # Errors
+///
+/// Returns an error if the file holds no certificate that can be parsed.
+pub fn certificate_expiry(path: &Path) -> io::Result<DateTime<Utc>> {
+    let certificates = load_certificates(path)?;
+    let (_, certificate) = X509Certificate::from_der(certificates[0].as_ref())
+        .map_err(|err| invalid(format!("{}: {}", path.display(), err)))?;
+    let not_after = certificate.validity().not_after.timestamp();
+    DateTime::from_timestamp(not_after, 0)
+        .ok_or_else(|| invalid(format!("{}: expiry out of range", path.display())))
+}
+
+/// Reads the subject common name of a verified client certificate
+pub(super) fn client_certificate(certificate: &CertificateDer<'_>) -> Option<ClientCertificate> {
+    let (_, certificate) = X509Certificate::from_der(certificate.as_ref()).ok()?;
+    let common_name = certificate.subject().iter_common_name().next()?;
+    Some(ClientCertificate {
+        common_name: common_name.as_str().ok()?.to_string(),
+    })
+}
+
+pub(super) fn invalid(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+    use rustls::pki_types::ServerName;
+    use tokio::sync::oneshot;
+    use tokio_rustls::TlsConnector;
+
+    use crate::tls::tests::{client_config, localhost};
+
+    #[tokio::test]
+    async fn test_negotiates_http2_and_http1() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/", get(|| async { "ok" }));
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            serve(listener, app, &localhost(), async {
+                let _ = stopped.await;
+            })
+            .await
+        });
+
+        for (offered, negotiated) in [
+            (&[b"h2".as_slice(), b"http/1.1"][..], b"h2".as_slice()),
+            (&[b"http/1.1".as_slice()][..], b"http/1.1".as_slice()),
+        ] {
+            let connector = TlsConnector::from(Arc::new(client_config(offered)));
+            let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let stream = connector
+                .connect(ServerName::try_from("localhost").unwrap(), stream)
+                .await
+                .unwrap();
+            assert_eq!(stream.get_ref().1.alpn_protocol(), Some(negotiated));
+        }
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+//! Tokens for undoing deletions
+//!
+//! With `APP_UNDO_WINDOW_SECONDS` set, `DELETE /api/v1/users/:id` answers
+//! with an `X-Undo-Token` header. Deleted users are kept in the storage's
+//! trash, and `POST /api/v1/undo/:token` puts the user back as long as the
+//! token has not expired. A token is spent once the user is restored.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// Header carrying the undo token of a deletion
+pub const X_UNDO_TOKEN: &str = "x-undo-token";
+
+/// Prefix of undo tokens, distinguishing them from other tokens in logs
+const TOKEN_PREFIX: &str = "undo_";
+
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+    user_id: Uuid,
+    expires_at: DateTime<Utc>,
+}
+
+/// Issued undo tokens
+#[derive(Debug, Default)]
+pub struct UndoTokens {
+    inner: Mutex<HashMap<String, Pending>>,
+}
+
+impl UndoTokens {
+    /// Issues a token restoring `user_id` until `expires_at`
+    ///
+    /// Tokens expired at `now` are dropped on the way.
+    pub fn issue(&self, user_id: Uuid, now: DateTime<Utc>, expires_at: DateTime<Utc>) -> String {
+        let token = format!("{}{}", TOKEN_PREFIX, Uuid::new_v4().simple());
+        let mut inner = self.lock();
+        inner.retain(|_, pending| pending.expires_at > now);
+        inner.insert(
+            token.clone(),
+            Pending {
+                user_id,
+                expires_at,
+            },
+        );
+        token
+    }
+
+    /// Returns the user an unexpired token restores
+    pub fn user_id(&self, token: &str, now: DateTime<Utc>) -> Option<Uuid> {
+        self.lock()
+            .get(token)
+            .filter(|pending| pending.expires_at > now)
+            .map(|pending| pending.user_id)
+    }
+
+    /// Spends a token, so it cannot be used again
+    pub fn spend(&self, token: &str) {
+        self.lock().remove(token);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Pending>> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_token_restores_user_until_expiry_or_spent() {
+        let tokens = UndoTokens::default();
+        let user_id = Uuid::new_v4();
+        let now = Utc::now();
+        let token = tokens.issue(user_id, now, now + Duration::seconds(30));
+        assert!(token.starts_with(TOKEN_PREFIX));
+
+        assert_eq!(tokens.user_id(&token, now), Some(user_id));
+        assert_eq!(tokens.user_id(&token, now + Duration::seconds(31)), None);
+        assert_eq!(tokens.user_id("undo_unknown", now), None);
+
+        tokens.spend(&token);
+        assert_eq!(tokens.user_id(&token, now), None);
+    }
+
+    #[test]
+    fn test_issue_drops_expired_tokens() {
+        let tokens = UndoTokens::default();
+        let now = Utc::now();
+        let old = tokens.issue(Uuid::new_v4(), now, now + Duration::seconds(1));
+        tokens.issue(
+            Uuid::new_v4(),
+            now + Duration::seconds(2),
+            now + Duration::seconds(3),
+        );
+        assert!(!tokens.lock().contains_key(&old));
+    }
+}
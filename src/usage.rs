@@ -0,0 +1,264 @@
+//! Usage analytics per API key
+//!
+//! Every routed request of an authenticated caller is counted under its
+//! principal, the name its key is configured with, in one-minute buckets
+//! covering the last [`WINDOW_MINUTES`]. Older buckets are dropped as new
+//! ones start, so the store stays bounded by the number of keys and routes.
+//!
+//! `GET /api/v1/api-keys/:id/usage` reports a key's requests, its client
+//! and server errors and the routes it calls most, so the owner of an
+//! integration can see what it sends and what fails. Callers see the usage
+//! of their own key; admins see every key's.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::auth::Principal;
+use crate::AppState;
+
+/// Minutes of usage kept and reported
+pub const WINDOW_MINUTES: usize = 60;
+
+/// Routes listed in [`UsageReport::top_endpoints`]
+pub const TOP_ENDPOINTS: usize = 10;
+
+/// Requests and errors counted for one route
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Counts {
+    requests: u64,
+    client_errors: u64,
+    server_errors: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: Counts) {
+        self.requests += other.requests;
+        self.client_errors += other.client_errors;
+        self.server_errors += other.server_errors;
+    }
+}
+
+/// Requests of one key within one minute
+#[derive(Debug, Clone, Default)]
+struct Bucket {
+    /// Minutes since the Unix epoch
+    minute: i64,
+    routes: HashMap<String, Counts>,
+}
+
+/// Usage of a route by one key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct EndpointUsage {
+    /// Method and route pattern, e.g. `GET /api/v1/users/:id`
+    pub route: String,
+    /// Requests within the window
+    pub requests: u64,
+    /// `4xx` responses within the window
+    pub client_errors: u64,
+    /// `5xx` responses within the window
+    pub server_errors: u64,
+}
+
+/// Response of `GET /api/v1/api-keys/:id/usage`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct UsageReport {
+    /// Principal of the key
+    pub key: String,
+    /// Length of the window reported, in seconds
+    pub window_seconds: u64,
+    /// Requests within the window
+    pub requests: u64,
+    /// `4xx` responses within the window
+    pub client_errors: u64,
+    /// `5xx` responses within the window
+    pub server_errors: u64,
+    /// Share of responses that were errors, between 0 and 1; 0 without
+    /// requests
+    pub error_rate: f64,
+    /// The most requested routes, most requested first
+    pub top_endpoints: Vec<EndpointUsage>,
+}
+
+/// Recent requests per key
+#[derive(Debug, Default)]
+pub struct UsageTracker {
+    keys: Mutex<HashMap<String, VecDeque<Bucket>>>,
+}
+
+impl UsageTracker {
+    /// Records a request of `key` to `route` answered with `status` at
+    /// `now`
+    pub fn record(&self, key: &str, route: &str, status: StatusCode, now: DateTime<Utc>) {
+        let minute = now.timestamp().div_euclid(60);
+        let mut keys = self.lock();
+        let buckets = keys.entry(key.to_string()).or_default();
+        if buckets.back().map_or(true, |bucket| bucket.minute < minute) {
+            buckets.push_back(Bucket {
+                minute,
+                ..Bucket::default()
+            });
+        }
+        expire(buckets, minute);
+        // A request finishing after a later one still lands in the newest
+        // bucket
+        let Some(bucket) = buckets.back_mut() else {
+            return;
+        };
+        bucket
+            .routes
+            .entry(route.to_string())
+            .or_default()
+            .add(Counts {
+                requests: 1,
+                client_errors: u64::from(status.is_client_error()),
+                server_errors: u64::from(status.is_server_error()),
+            });
+    }
+
+    /// Reports the usage of `key` within the window ending at `now`
+    pub fn report(&self, key: &str, now: DateTime<Utc>) -> UsageReport {
+        let minute = now.timestamp().div_euclid(60);
+        let mut routes: HashMap<String, Counts> = HashMap::new();
+        if let Some(buckets) = self.lock().get_mut(key) {
+            expire(buckets, minute);
+            for bucket in buckets.iter() {
+                for (route, counts) in &bucket.routes {
+                    routes.entry(route.clone()).or_default().add(*counts);
+                }
+            }
+        }
+
+        let mut total = Counts::default();
+        for counts in routes.values() {
+            total.add(*counts);
+        }
+        let mut top_endpoints: Vec<EndpointUsage> = routes
+            .into_iter()
+            .map(|(route, counts)| EndpointUsage {
+                route,
+                requests: counts.requests,
+                client_errors: counts.client_errors,
+                server_errors: counts.server_errors,
+            })
+            .collect();
+        top_endpoints.sort_by(|a, b| {
+            b.requests
+                .cmp(&a.requests)
+                .then_with(|| a.route.cmp(&b.route))
+        });
+        top_endpoints.truncate(TOP_ENDPOINTS);
+
+        let errors = total.client_errors + total.server_errors;
+        UsageReport {
+            key: key.to_string(),
+            window_seconds: WINDOW_MINUTES as u64 * 60,
+            requests: total.requests,
+            client_errors: total.client_errors,
+            server_errors: total.server_errors,
+            error_rate: if total.requests == 0 {
+                0.0
+            } else {
+                errors as f64 / total.requests as f64
+            },
+            top_endpoints,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, VecDeque<Bucket>>> {
+        self.keys
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Drops the buckets that started before the window ending at `minute`
+fn expire(buckets: &mut VecDeque<Bucket>, minute: i64) {
+    let oldest = minute - WINDOW_MINUTES as i64 + 1;
+    while buckets.front().is_some_and(|bucket| bucket.minute < oldest) {
+        buckets.pop_front();
+    }
+}
+
+/// Middleware counting the routed requests of authenticated callers
+///
+/// The principal is read from the response, where [`crate::auth::require`]
+/// leaves it, since authentication runs inside this layer.
+pub async fn record(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(path) = request.extensions().get::<MatchedPath>() else {
+        return next.run(request).await;
+    };
+    let route = format!("{} {}", request.method(), path.as_str());
+    let response = next.run(request).await;
+    if let Some(principal) = response.extensions().get::<Principal>() {
+        state.usage.record(
+            &principal.name,
+            &route,
+            response.status(),
+            state.clock.now(),
+        );
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_report_counts_errors_and_top_endpoints() {
+        let tracker = UsageTracker::default();
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        for status in [StatusCode::OK, StatusCode::NOT_FOUND, StatusCode::OK] {
+            tracker.record("partner", "GET /api/v1/users/:id", status, now);
+        }
+        tracker.record(
+            "partner",
+            "POST /api/v1/users",
+            StatusCode::INTERNAL_SERVER_ERROR,
+            now,
+        );
+        tracker.record("other", "GET /api/v1/users", StatusCode::OK, now);
+
+        let report = tracker.report("partner", now);
+        assert_eq!(report.requests, 4);
+        assert_eq!((report.client_errors, report.server_errors), (1, 1));
+        assert_eq!(report.error_rate, 0.5);
+        let routes: Vec<_> = report
+            .top_endpoints
+            .iter()
+            .map(|endpoint| (endpoint.route.as_str(), endpoint.requests))
+            .collect();
+        assert_eq!(
+            routes,
+            [("GET /api/v1/users/:id", 3), ("POST /api/v1/users", 1)]
+        );
+
+        let unknown = tracker.report("unknown", now);
+        assert_eq!((unknown.requests, unknown.error_rate), (0, 0.0));
+    }
+
+    #[test]
+    fn test_old_minutes_roll_out_of_the_window() {
+        let tracker = UsageTracker::default();
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        tracker.record("partner", "GET /api/v1/users", StatusCode::OK, start);
+        let later = start + Duration::minutes(30);
+        tracker.record("partner", "GET /api/v1/users", StatusCode::OK, later);
+        assert_eq!(tracker.report("partner", later).requests, 2);
+
+        let end = start + Duration::minutes(WINDOW_MINUTES as i64);
+        assert_eq!(tracker.report("partner", end).requests, 1);
+        assert_eq!(tracker.lock()["partner"].len(), 1);
+    }
+}
@@ -0,0 +1,353 @@
+//! Custom field validation
+//!
+//! Deployments define extra fields for their users in `APP_CUSTOM_FIELDS`,
+//! a JSON array of field definitions:
+//!
+//! ```text
+//! [{"name": "department", "type": "string", "required": true},
+//!  {"name": "employee_id", "type": "string", "pattern": "^E[0-9]{6}$"},
+//!  {"name": "floor", "type": "integer"}]
+//! ```
+//!
+//! Users carry the values in a `custom` object. Every value in a request
+//! must belong to a defined field and have its type, strings must match
+//! the field's pattern, and new users need a value for every required
+//! field. Violations are reported for the `custom` field, naming the
+//! custom field in the message.
+
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::i18n::Message;
+
+use super::Violations;
+
+/// Values of a user's custom fields, by field name
+pub type CustomFields = BTreeMap<String, Value>;
+
+/// JSON type of a custom field's values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    /// Any string
+    String,
+    /// A number without fraction
+    Integer,
+    /// Any number
+    Number,
+    /// `true` or `false`
+    Boolean,
+}
+
+impl FieldType {
+    /// Returns the lowercase name used in definitions and messages
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FieldType::String => "string",
+            FieldType::Integer => "integer",
+            FieldType::Number => "number",
+            FieldType::Boolean => "boolean",
+        }
+    }
+
+    /// Whether `value` is of this type
+    fn admits(self, value: &Value) -> bool {
+        match self {
+            FieldType::String => value.is_string(),
+            FieldType::Integer => value.is_i64() || value.is_u64(),
+            FieldType::Number => value.is_number(),
+            FieldType::Boolean => value.is_boolean(),
+        }
+    }
+}
+
+impl std::fmt::Display for FieldType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Regular expression the values of a string field must match
+///
+/// The pattern is not anchored; write `^...$` to match whole values.
+#[derive(Debug, Clone)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    /// Compiles `pattern`
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(pattern).map(Self)
+    }
+
+    /// Returns the pattern as written
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    /// Whether `value` matches the pattern
+    pub fn is_match(&self, value: &str) -> bool {
+        self.0.is_match(value)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Pattern::new(&pattern).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Definition of one custom field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldDefinition {
+    /// Key of the field in the `custom` object
+    pub name: String,
+    /// Type of its values
+    #[serde(rename = "type")]
+    pub kind: FieldType,
+    /// Whether every user must have a value
+    #[serde(default)]
+    pub required: bool,
+    /// Pattern the values must match; only for string fields
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<Pattern>,
+}
+
+/// The custom fields defined for a deployment
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<FieldDefinition>")]
+pub struct Schema(Vec<FieldDefinition>);
+
+impl Schema {
+    /// Creates a schema of `fields`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a name is empty or defined twice, or a pattern
+    /// is given for a field that is not a string.
+    pub fn new(fields: Vec<FieldDefinition>) -> Result<Self, String> {
+        let mut names = HashSet::new();
+        for field in &fields {
+            if field.name.trim().is_empty() {
+                return Err("custom field names cannot be empty".to_string());
+            }
+            if !names.insert(field.name.as_str()) {
+                return Err(format!("custom field '{}' is defined twice", field.name));
+            }
+            if field.pattern.is_some() && field.kind != FieldType::String {
+                return Err(format!(
+                    "custom field '{}' is a {} and cannot have a pattern",
+                    field.name, field.kind
+                ));
+            }
+        }
+        Ok(Self(fields))
+    }
+
+    /// Returns the field definitions, in definition order
+    pub fn fields(&self) -> &[FieldDefinition] {
+        &self.0
+    }
+
+    /// Returns the definition of the field named `name`
+    pub fn get(&self, name: &str) -> Option<&FieldDefinition> {
+        self.0.iter().find(|field| field.name == name)
+    }
+
+    /// Checks the custom values of a new user and returns them without
+    /// `null`s, recording every violated rule
+    pub fn check_new(&self, values: &CustomFields, violations: &mut Violations) -> CustomFields {
+        let mut valid = CustomFields::new();
+        for (name, value) in values {
+            if self.check(name, value, violations) && !value.is_null() {
+                valid.insert(name.clone(), value.clone());
+            }
+        }
+        for field in self.0.iter().filter(|field| field.required) {
+            if values.get(&field.name).map_or(true, Value::is_null) {
+                violations.add("custom", required(&field.name));
+            }
+        }
+        valid
+    }
+
+    /// Checks changes to a user's custom values, recording every violated
+    /// rule
+    ///
+    /// A `null` value removes the stored value, which required fields do
+    /// not allow.
+    pub fn check_changes(&self, changes: &CustomFields, violations: &mut Violations) {
+        for (name, value) in changes {
+            if self.check(name, value, violations)
+                && value.is_null()
+                && self.get(name).is_some_and(|field| field.required)
+            {
+                violations.add("custom", required(name));
+            }
+        }
+    }
+
+    /// Checks one value, which may be `null`; returns whether it is valid
+    fn check(&self, name: &str, value: &Value, violations: &mut Violations) -> bool {
+        let Some(field) = self.get(name) else {
+            violations.add("custom", Message::new("custom.unknown").with("name", name));
+            return false;
+        };
+        if value.is_null() {
+            return true;
+        }
+        if !field.kind.admits(value) {
+            violations.add(
+                "custom",
+                Message::new("custom.invalid_type")
+                    .with("name", name)
+                    .with("type", field.kind),
+            );
+            return false;
+        }
+        match (&field.pattern, value.as_str()) {
+            (Some(pattern), Some(value)) if !pattern.is_match(value) => {
+                violations.add(
+                    "custom",
+                    Message::new("custom.pattern_mismatch")
+                        .with("name", name)
+                        .with("pattern", pattern.as_str()),
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+}
+
+impl TryFrom<Vec<FieldDefinition>> for Schema {
+    type Error = String;
+
+    fn try_from(fields: Vec<FieldDefinition>) -> Result<Self, Self::Error> {
+        Self::new(fields)
+    }
+}
+
+impl FromStr for Schema {
+    type Err = String;
+
+    /// Parses a JSON array of field definitions
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s).map_err(|err| format!("invalid custom field schema: {}", err))
+    }
+}
+
+fn required(name: &str) -> Message {
+    Message::new("custom.required").with("name", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema() -> Schema {
+        r#"[
+            {"name": "department", "type": "string", "required": true},
+            {"name": "employee_id", "type": "string", "pattern": "^E[0-9]{6}$"},
+            {"name": "floor", "type": "integer"}
+        ]"#
+        .parse()
+        .unwrap()
+    }
+
+    fn values(value: Value) -> CustomFields {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn keys(violations: &Violations) -> Vec<&'static str> {
+        violations
+            .iter()
+            .map(|violation| violation.message.key())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_schema() {
+        let schema = schema();
+        assert_eq!(schema.fields().len(), 3);
+        assert_eq!(schema.get("floor").unwrap().kind, FieldType::Integer);
+        assert!(!schema.get("floor").unwrap().required);
+
+        assert!("[]".parse::<Schema>().unwrap().fields().is_empty());
+        for invalid in [
+            r#"[{"name": "a", "type": "date"}]"#,
+            r#"[{"name": "a", "type": "string", "pattern": "("}]"#,
+            r#"[{"name": "a", "type": "integer", "pattern": "^1$"}]"#,
+            r#"[{"name": "a", "type": "string"}, {"name": "a", "type": "number"}]"#,
+            r#"[{"name": " ", "type": "string"}]"#,
+            r#"[{"name": "a", "type": "string", "unique": true}]"#,
+        ] {
+            assert!(invalid.parse::<Schema>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_check_new() {
+        let schema = schema();
+        let mut violations = Violations::new();
+        let valid = schema.check_new(
+            &values(json!({"department": "R&D", "floor": 3, "employee_id": null})),
+            &mut violations,
+        );
+        assert!(violations.is_empty());
+        assert_eq!(valid, values(json!({"department": "R&D", "floor": 3})));
+
+        let mut violations = Violations::new();
+        schema.check_new(
+            &values(json!({"employee_id": "X1", "floor": 2.5, "desk": 12})),
+            &mut violations,
+        );
+        assert_eq!(
+            keys(&violations),
+            [
+                "custom.unknown",
+                "custom.pattern_mismatch",
+                "custom.invalid_type",
+                "custom.required"
+            ]
+        );
+        assert!(violations
+            .iter()
+            .all(|violation| violation.field == "custom"));
+        assert!(violations
+            .to_string()
+            .contains("custom: employee_id must match ^E[0-9]{6}$"));
+    }
+
+    #[test]
+    fn test_check_changes() {
+        let schema = schema();
+        let mut violations = Violations::new();
+        schema.check_changes(
+            &values(json!({"floor": null, "employee_id": "E123456"})),
+            &mut violations,
+        );
+        assert!(violations.is_empty());
+
+        schema.check_changes(&values(json!({"department": null})), &mut violations);
+        assert_eq!(keys(&violations), ["custom.required"]);
+    }
+}
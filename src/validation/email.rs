@@ -0,0 +1,623 @@
+//! Email address validation and normalization
+//!
+//! Addresses are parsed according to the `addr-spec` grammar of RFC 5322,
+//! with the internationalized extensions of RFC 6531 for the local part
+//! and IDNA (UTS #46) processing for the domain. Valid addresses are
+//! returned in a canonical form suitable for storage.
+//!
+//! Uniqueness is checked on the [`homograph_key`] of an address instead, so
+//! an address spelled with look-alike characters from another script, such
+//! as `jоhn@example.com` with a Cyrillic `о`, cannot pose as a new one. A
+//! [`Canonicalization`] may additionally fold the aliases many providers
+//! deliver to the same mailbox; the stored address is never changed.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::skeleton;
+
+use crate::error::ApiError;
+use crate::i18n::Message;
+
+/// Maximum length of a complete address in octets (RFC 5321 path limit)
+pub const MAX_EMAIL_LENGTH: usize = 254;
+
+/// Maximum length of the local part in octets (RFC 5321)
+pub const MAX_LOCAL_PART_LENGTH: usize = 64;
+
+/// Maximum length of a single domain label in octets (RFC 1035)
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// Reasons an email address can be rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmailError {
+    /// The address is empty or only whitespace
+    Empty,
+    /// The address exceeds [`MAX_EMAIL_LENGTH`]
+    TooLong,
+    /// The address has no `@` separating local part and domain
+    MissingAt,
+    /// The local part is empty, too long, or contains invalid characters
+    InvalidLocalPart,
+    /// The domain is empty, malformed, or fails IDNA processing
+    InvalidDomain,
+    /// The domain has no mail exchanger (only with the `mx-lookup` feature)
+    Undeliverable,
+}
+
+impl EmailError {
+    /// Returns the localizable message for this error
+    pub fn message(&self) -> Message {
+        Message::new(match self {
+            EmailError::Empty => "email.empty",
+            EmailError::TooLong => "email.too_long",
+            EmailError::MissingAt => "email.invalid_format",
+            EmailError::InvalidLocalPart => "email.invalid_local_part",
+            EmailError::InvalidDomain => "email.invalid_domain",
+            EmailError::Undeliverable => "email.undeliverable",
+        })
+    }
+}
+
+impl std::fmt::Display for EmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for EmailError {}
+
+impl From<EmailError> for Message {
+    fn from(err: EmailError) -> Self {
+        err.message()
+    }
+}
+
+impl From<EmailError> for ApiError {
+    fn from(err: EmailError) -> Self {
+        ApiError::BadRequest(err.message())
+    }
+}
+
+/// Validates an email address and returns its canonical form
+///
+/// Surrounding whitespace is trimmed, the local part is NFC-normalized and
+/// lowercased, and the domain is converted to its lowercase ASCII (punycode)
+/// form. Domain literals such as `user@[192.0.2.1]` are accepted.
+///
+/// # Arguments
+///
+/// * `input` - The raw email address supplied by the client
+///
+/// # Returns
+///
+/// Returns the normalized address, or an [`EmailError`] describing why
+/// the address was rejected
+pub fn normalize(input: &str) -> Result<String, EmailError> {
+    let trimmed = input.trim();
+    if trimmed.is_empty() {
+        return Err(EmailError::Empty);
+    }
+
+    // The local part may itself contain a quoted `@`, so split on the last one
+    let (local, domain) = trimmed.rsplit_once('@').ok_or(EmailError::MissingAt)?;
+
+    let local = normalize_local_part(local)?;
+    let domain = normalize_domain(domain)?;
+
+    let email = format!("{}@{}", local, domain);
+    if email.len() > MAX_EMAIL_LENGTH {
+        return Err(EmailError::TooLong);
+    }
+
+    Ok(email)
+}
+
+/// Returns the domain of an already normalized address
+pub fn domain(email: &str) -> Option<&str> {
+    email.rsplit_once('@').map(|(_, domain)| domain)
+}
+
+/// Returns the key two normalized addresses share if one could pass for
+/// the other
+///
+/// Non-ASCII characters of the local part and of the Unicode form of the
+/// domain are replaced by their confusable skeleton from UTS #39, so
+/// homographs from other scripts map to the Latin letters they imitate.
+/// ASCII characters are kept as they are: `john1` and `johnl` stay
+/// distinct, since both are what their owners typed.
+pub fn homograph_key(email: &str) -> String {
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return unconfuse(email);
+    };
+    let domain = if domain.starts_with('[') {
+        domain.to_string()
+    } else {
+        idna::domain_to_unicode(domain).0
+    };
+    format!("{}@{}", unconfuse(local), unconfuse(&domain))
+}
+
+/// Domains whose mailboxes ignore dots in the local part
+const GMAIL_DOMAINS: [&str; 2] = ["gmail.com", "googlemail.com"];
+
+/// How addresses are folded before checking them for duplicates
+///
+/// Only uniqueness checks use the folded form; users keep the address they
+/// gave, so mail is still delivered to the alias they chose.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Canonicalization {
+    /// Addresses are only compared by their [`homograph_key`]
+    #[default]
+    Exact,
+    /// A `+tag` suffix of the local part is ignored on every domain
+    Subaddress,
+    /// Like `Subaddress`, and dots in the local part are ignored for Gmail,
+    /// where `googlemail.com` is also the same domain as `gmail.com`
+    Gmail,
+}
+
+impl FromStr for Canonicalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "exact" => Ok(Canonicalization::Exact),
+            "subaddress" => Ok(Canonicalization::Subaddress),
+            "gmail" => Ok(Canonicalization::Gmail),
+            other => Err(format!(
+                "unknown email canonicalization '{}' (expected exact, subaddress or gmail)",
+                other
+            )),
+        }
+    }
+}
+
+impl Canonicalization {
+    /// Returns the key two normalized addresses share if they are
+    /// duplicates under this strategy
+    ///
+    /// Quoted local parts are only compared by their [`homograph_key`].
+    pub fn duplicate_key(self, email: &str) -> String {
+        let Some((local, domain)) = email.rsplit_once('@') else {
+            return homograph_key(email);
+        };
+        if self == Canonicalization::Exact || local.starts_with('"') {
+            return homograph_key(email);
+        }
+
+        let mut local = match local.split_once('+') {
+            Some((mailbox, _)) if !mailbox.is_empty() => mailbox,
+            _ => local,
+        }
+        .to_string();
+        let mut domain = domain;
+        if self == Canonicalization::Gmail && GMAIL_DOMAINS.contains(&domain) {
+            local.retain(|c| c != '.');
+            domain = GMAIL_DOMAINS[0];
+        }
+        homograph_key(&format!("{}@{}", local, domain))
+    }
+}
+
+/// Maps every non-ASCII character to its lowercase confusable skeleton
+fn unconfuse(text: &str) -> String {
+    if text.is_ascii() {
+        return text.to_string();
+    }
+    let mut key = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii() {
+            key.push(c);
+        } else {
+            key.extend(skeleton(c.encode_utf8(&mut [0; 4])).flat_map(char::to_lowercase));
+        }
+    }
+    key
+}
+
+fn normalize_local_part(local: &str) -> Result<String, EmailError> {
+    let local: String = local.nfc().collect::<String>().to_lowercase();
+
+    if local.is_empty() || local.len() > MAX_LOCAL_PART_LENGTH {
+        return Err(EmailError::InvalidLocalPart);
+    }
+
+    let valid = if local.starts_with('"') {
+        is_quoted_string(&local)
+    } else {
+        is_dot_atom(&local)
+    };
+
+    if valid {
+        Ok(local)
+    } else {
+        Err(EmailError::InvalidLocalPart)
+    }
+}
+
+/// `atext` from RFC 5322, extended with non-ASCII characters per RFC 6531
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric()
+        || "!#$%&'*+-/=?^_`{|}~".contains(c)
+        || (!c.is_ascii() && !c.is_control())
+}
+
+fn is_dot_atom(s: &str) -> bool {
+    s.split('.')
+        .all(|atom| !atom.is_empty() && atom.chars().all(is_atext))
+}
+
+fn is_quoted_string(s: &str) -> bool {
+    if s.len() < 2 || !s.ends_with('"') {
+        return false;
+    }
+
+    let mut chars = s[1..s.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped) if escaped == ' ' || escaped == '\t' || is_vchar(escaped) => {}
+                _ => return false,
+            },
+            '"' => return false,
+            c if c == ' ' || c == '\t' || is_vchar(c) => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Visible characters, including UTF-8 per RFC 6532
+fn is_vchar(c: char) -> bool {
+    c.is_ascii_graphic() || (!c.is_ascii() && !c.is_control())
+}
+
+fn normalize_domain(domain: &str) -> Result<String, EmailError> {
+    if domain.is_empty() {
+        return Err(EmailError::InvalidDomain);
+    }
+
+    if let Some(literal) = domain.strip_prefix('[') {
+        return normalize_domain_literal(literal);
+    }
+
+    let ascii = idna::domain_to_ascii(domain).map_err(|_| EmailError::InvalidDomain)?;
+
+    let labels: Vec<&str> = ascii.split('.').collect();
+    if labels.len() < 2 {
+        return Err(EmailError::InvalidDomain);
+    }
+
+    for label in &labels {
+        if label.is_empty()
+            || label.len() > MAX_LABEL_LENGTH
+            || label.starts_with('-')
+            || label.ends_with('-')
+            || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(EmailError::InvalidDomain);
+        }
+    }
+
+    // An all-numeric top-level label would be indistinguishable from an IPv4 address
+    if labels
+        .last()
+        .is_some_and(|tld| tld.chars().all(|c| c.is_ascii_digit()))
+    {
+        return Err(EmailError::InvalidDomain);
+    }
+
+    Ok(ascii)
+}
+
+fn normalize_domain_literal(literal: &str) -> Result<String, EmailError> {
+    let inner = literal.strip_suffix(']').ok_or(EmailError::InvalidDomain)?;
+
+    if let Some(v6) = inner
+        .strip_prefix("IPv6:")
+        .or_else(|| inner.strip_prefix("ipv6:"))
+    {
+        let addr: Ipv6Addr = v6.parse().map_err(|_| EmailError::InvalidDomain)?;
+        return Ok(format!("[IPv6:{}]", addr));
+    }
+
+    let addr: Ipv4Addr = inner.parse().map_err(|_| EmailError::InvalidDomain)?;
+    Ok(format!("[{}]", addr))
+}
+
+/// Domains reserved for documentation and testing by RFC 2606 and RFC 6761
+///
+/// Subdomains of these are reserved too.
+const RESERVED_DOMAINS: &[&str] = &[
+    "example",
+    "example.com",
+    "example.net",
+    "example.org",
+    "invalid",
+    "localhost",
+    "test",
+];
+
+/// Returns whether `domain` is reserved for documentation and testing, so
+/// that it never resolves to a real mail exchanger
+pub fn is_reserved_domain(domain: &str) -> bool {
+    RESERVED_DOMAINS.iter().any(|reserved| {
+        domain == *reserved
+            || domain
+                .strip_suffix(reserved)
+                .is_some_and(|sub| sub.ends_with('.'))
+    })
+}
+
+/// Looks up whether a domain publishes an MX record, in
+/// [`crate::AppState::mx_lookup`]
+///
+/// The system resolver is used unless replaced, for instance by a stub in
+/// tests that must not depend on the network.
+#[cfg(feature = "mx-lookup")]
+pub trait MxLookup: Send + Sync + 'static {
+    /// Returns whether `domain` has at least one MX record, or an error if
+    /// the lookup failed for another reason than an empty answer
+    fn has_mx<'a>(
+        &'a self,
+        domain: &'a str,
+    ) -> futures_util::future::BoxFuture<'a, std::io::Result<bool>>;
+}
+
+/// Looks up MX records with the resolver configured on the system
+#[cfg(feature = "mx-lookup")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+#[cfg(feature = "mx-lookup")]
+impl MxLookup for SystemResolver {
+    fn has_mx<'a>(
+        &'a self,
+        domain: &'a str,
+    ) -> futures_util::future::BoxFuture<'a, std::io::Result<bool>> {
+        use hickory_resolver::error::ResolveErrorKind;
+        use hickory_resolver::TokioAsyncResolver;
+
+        Box::pin(async move {
+            let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+            match resolver.mx_lookup(format!("{}.", domain)).await {
+                Ok(records) => Ok(records.iter().next().is_some()),
+                Err(err) => match err.kind() {
+                    ResolveErrorKind::NoRecordsFound { .. } => Ok(false),
+                    _ => Err(std::io::Error::new(std::io::ErrorKind::Other, err)),
+                },
+            }
+        })
+    }
+}
+
+/// Checks that the domain of a normalized address publishes an MX record
+///
+/// Domain literals and [reserved domains](is_reserved_domain) are accepted
+/// without a lookup. Lookup failures other than an empty answer are treated
+/// as deliverable so that a DNS outage does not block user creation.
+#[cfg(feature = "mx-lookup")]
+pub async fn verify_mx(email: &str, lookup: &dyn MxLookup) -> Result<(), EmailError> {
+    let domain = domain(email).ok_or(EmailError::MissingAt)?;
+    if domain.starts_with('[') || is_reserved_domain(domain) {
+        return Ok(());
+    }
+
+    match lookup.has_mx(domain).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(EmailError::Undeliverable),
+        Err(err) => {
+            tracing::warn!("MX lookup for {} failed: {}", domain, err);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_simple_address() {
+        assert_eq!(
+            normalize("  John.Doe@Example.COM "),
+            Ok("john.doe@example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn test_normalize_rejects_malformed_addresses() {
+        assert_eq!(normalize(""), Err(EmailError::Empty));
+        assert_eq!(normalize("invalid-email"), Err(EmailError::MissingAt));
+        assert_eq!(normalize("@example.com"), Err(EmailError::InvalidLocalPart));
+        assert_eq!(
+            normalize("a..b@example.com"),
+            Err(EmailError::InvalidLocalPart)
+        );
+        assert_eq!(
+            normalize(".a@example.com"),
+            Err(EmailError::InvalidLocalPart)
+        );
+        assert_eq!(
+            normalize("a b@example.com"),
+            Err(EmailError::InvalidLocalPart)
+        );
+        assert_eq!(normalize("user@"), Err(EmailError::InvalidDomain));
+        assert_eq!(normalize("user@localhost"), Err(EmailError::InvalidDomain));
+        assert_eq!(normalize("user@-bad.com"), Err(EmailError::InvalidDomain));
+        assert_eq!(
+            normalize("user@example.123"),
+            Err(EmailError::InvalidDomain)
+        );
+        assert_eq!(
+            normalize("user@exa mple.com"),
+            Err(EmailError::InvalidDomain)
+        );
+    }
+
+    #[test]
+    fn test_normalize_enforces_length_limits() {
+        let local = "a".repeat(MAX_LOCAL_PART_LENGTH + 1);
+        assert_eq!(
+            normalize(&format!("{}@example.com", local)),
+            Err(EmailError::InvalidLocalPart)
+        );
+
+        let label = "a".repeat(60);
+        let domain = format!("{0}.{0}.{0}.{0}.{0}.com", label);
+        assert_eq!(
+            normalize(&format!("user@{}", domain)),
+            Err(EmailError::TooLong)
+        );
+    }
+
+    #[test]
+    fn test_normalize_quoted_local_part() {
+        assert_eq!(
+            normalize("\"john doe\"@example.com"),
+            Ok("\"john doe\"@example.com".to_string())
+        );
+        assert_eq!(
+            normalize("\"a@b\"@example.com"),
+            Ok("\"a@b\"@example.com".to_string())
+        );
+        assert_eq!(
+            normalize("\"unterminated@example.com"),
+            Err(EmailError::InvalidLocalPart)
+        );
+    }
+
+    #[test]
+    fn test_normalize_internationalized_address() {
+        // Decomposed "é" (e + combining acute) is composed to a single code point
+        assert_eq!(
+            normalize("Jose\u{301}@Bücher.example"),
+            Ok("jos\u{e9}@xn--bcher-kva.example".to_string())
+        );
+    }
+
+    #[test]
+    fn test_homograph_key_unmasks_look_alikes() {
+        let latin = normalize("john@example.com").unwrap();
+        let cyrillic = normalize("j\u{43e}hn@example.com").unwrap();
+        assert_ne!(latin, cyrillic);
+        assert_eq!(homograph_key(&cyrillic), homograph_key(&latin));
+
+        // Look-alikes in the domain are caught through its Unicode form
+        let domain = normalize("john@ex\u{430}mple.com").unwrap();
+        assert!(domain.ends_with("@xn--exmple-4nf.com"));
+        assert_eq!(homograph_key(&domain), homograph_key(&latin));
+
+        // Distinct ASCII and accented addresses keep distinct keys
+        for other in ["johnl@example.com", "john1@example.com", "jóhn@example.com"] {
+            let other = normalize(other).unwrap();
+            assert_ne!(homograph_key(&other), homograph_key(&latin), "{}", other);
+        }
+    }
+
+    #[test]
+    fn test_canonicalization_folds_aliases() {
+        let key = |canonicalization: Canonicalization, email: &str| {
+            canonicalization.duplicate_key(&normalize(email).unwrap())
+        };
+
+        let exact = Canonicalization::Exact;
+        assert_ne!(
+            key(exact, "jane+news@example.com"),
+            key(exact, "jane@example.com")
+        );
+
+        let subaddress = Canonicalization::Subaddress;
+        assert_eq!(
+            key(subaddress, "jane+news@example.com"),
+            key(subaddress, "jane@example.com")
+        );
+        assert_ne!(
+            key(subaddress, "ja.ne@gmail.com"),
+            key(subaddress, "jane@gmail.com")
+        );
+
+        let gmail = Canonicalization::Gmail;
+        assert_eq!(
+            key(gmail, "Ja.Ne+news@googlemail.com"),
+            key(gmail, "jane@gmail.com")
+        );
+        assert_ne!(
+            key(gmail, "ja.ne@example.com"),
+            key(gmail, "jane@example.com")
+        );
+        // A bare tag or a quoted local part is not an alias
+        assert_ne!(key(gmail, "+x@example.com"), key(gmail, "x@example.com"));
+        assert_ne!(
+            key(gmail, "\"a+b\"@example.com"),
+            key(gmail, "\"a\"@example.com")
+        );
+
+        assert_eq!("Gmail".parse(), Ok(Canonicalization::Gmail));
+        assert!("dots".parse::<Canonicalization>().is_err());
+    }
+
+    #[test]
+    fn test_normalize_domain_literals() {
+        assert_eq!(
+            normalize("user@[192.0.2.1]"),
+            Ok("user@[192.0.2.1]".to_string())
+        );
+        assert_eq!(
+            normalize("user@[IPv6:2001:DB8::1]"),
+            Ok("user@[IPv6:2001:db8::1]".to_string())
+        );
+        assert_eq!(
+            normalize("user@[999.0.0.1]"),
+            Err(EmailError::InvalidDomain)
+        );
+    }
+
+    #[test]
+    fn test_reserved_domains() {
+        for domain in [
+            "example.com",
+            "mail.example.org",
+            "blocked.example",
+            "host.test",
+        ] {
+            assert!(is_reserved_domain(domain), "{}", domain);
+        }
+        for domain in ["gmail.com", "notexample.com", "example.com.au", "test.dev"] {
+            assert!(!is_reserved_domain(domain), "{}", domain);
+        }
+    }
+
+    #[cfg(feature = "mx-lookup")]
+    #[tokio::test]
+    async fn test_verify_mx() {
+        struct Stub;
+
+        impl MxLookup for Stub {
+            fn has_mx<'a>(
+                &'a self,
+                domain: &'a str,
+            ) -> futures_util::future::BoxFuture<'a, std::io::Result<bool>> {
+                assert!(!is_reserved_domain(domain), "{} was looked up", domain);
+                let answer = match domain {
+                    "mail.dev" => Ok(true),
+                    "nomail.dev" => Ok(false),
+                    _ => Err(std::io::ErrorKind::TimedOut.into()),
+                };
+                Box::pin(std::future::ready(answer))
+            }
+        }
+
+        assert_eq!(verify_mx("a@mail.dev", &Stub).await, Ok(()));
+        assert_eq!(
+            verify_mx("a@nomail.dev", &Stub).await,
+            Err(EmailError::Undeliverable)
+        );
+        // A failed lookup does not block the address
+        assert_eq!(verify_mx("a@down.dev", &Stub).await, Ok(()));
+        assert_eq!(verify_mx("a@example.com", &Stub).await, Ok(()));
+        assert_eq!(verify_mx("a@[192.0.2.1]", &Stub).await, Ok(()));
+    }
+}
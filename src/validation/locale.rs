@@ -0,0 +1,78 @@
+//! Locale validation
+//!
+//! Locales are BCP 47 language tags (RFC 5646), checked against the IANA
+//! Language Subtag Registry and stored in canonical form, so `EN-us` and
+//! `en-Latn-US` are both stored as `en-US`.
+
+use language_tags::LanguageTag;
+
+use crate::error::ApiError;
+use crate::i18n::Message;
+
+/// Error returned for a locale that is not a valid BCP 47 tag
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocaleError;
+
+impl LocaleError {
+    /// Returns the localizable message for this error
+    pub fn message(&self) -> Message {
+        Message::new("locale.invalid")
+    }
+}
+
+impl std::fmt::Display for LocaleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for LocaleError {}
+
+impl From<LocaleError> for Message {
+    fn from(err: LocaleError) -> Self {
+        err.message()
+    }
+}
+
+impl From<LocaleError> for ApiError {
+    fn from(err: LocaleError) -> Self {
+        ApiError::BadRequest(err.message())
+    }
+}
+
+/// Validates a language tag and returns its canonical form
+///
+/// # Arguments
+///
+/// * `input` - The raw language tag supplied by the client
+///
+/// # Returns
+///
+/// Returns the canonical tag, or [`LocaleError`] if the tag is malformed
+/// or uses subtags missing from the IANA registry
+pub fn normalize(input: &str) -> Result<String, LocaleError> {
+    let tag = LanguageTag::parse(input.trim()).map_err(|_| LocaleError)?;
+    tag.validate().map_err(|_| LocaleError)?;
+    let canonical = tag.canonicalize().map_err(|_| LocaleError)?;
+    Ok(canonical.into_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_canonicalizes_tags() {
+        assert_eq!(normalize(" EN-us "), Ok("en-US".to_string()));
+        assert_eq!(normalize("en-Latn-US"), Ok("en-US".to_string()));
+        assert_eq!(normalize("zh-Hant-TW"), Ok("zh-Hant-TW".to_string()));
+    }
+
+    #[test]
+    fn test_normalize_rejects_invalid_tags() {
+        assert_eq!(normalize(""), Err(LocaleError));
+        assert_eq!(normalize("english"), Err(LocaleError));
+        assert_eq!(normalize("en_US"), Err(LocaleError));
+        assert_eq!(normalize("xx-QQ"), Err(LocaleError));
+    }
+}
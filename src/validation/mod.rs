@@ -0,0 +1,102 @@
+//! Input validation and normalization
+//!
+//! This module centralizes the rules applied to user-supplied data so
+//! that every handler accepting the same field validates it identically.
+//!
+//! Request types implement [`Validate`], which checks every field before
+//! reporting, so a client learns about all the rules its request violates
+//! in a single response instead of one per attempt.
+
+pub mod custom;
+pub mod email;
+pub mod locale;
+pub mod name;
+pub mod phone;
+pub mod request;
+pub mod timezone;
+
+use crate::config::Config;
+use crate::i18n::{Language, Message};
+
+/// A request whose fields are validated as a whole
+pub trait Validate {
+    /// The normalized values of a valid request
+    type Valid;
+
+    /// Validates every field and returns their normalized values, or every
+    /// rule the request violates
+    fn validate(&self, config: &Config) -> Result<Self::Valid, Violations>;
+}
+
+/// A validation rule violated by one field of a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// Name of the field, as in the request body
+    pub field: &'static str,
+    /// What is wrong with it; the catalog key names the violated rule
+    pub message: Message,
+}
+
+/// Every rule a request violates, in field order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Violations(Vec<Violation>);
+
+impl Violations {
+    /// Creates an empty list
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `field` violates the rule described by `message`
+    pub fn add(&mut self, field: &'static str, message: impl Into<Message>) {
+        self.0.push(Violation {
+            field,
+            message: message.into(),
+        });
+    }
+
+    /// Returns the value of `result`, or records its error for `field`
+    pub fn check<T, E: Into<Message>>(
+        &mut self,
+        field: &'static str,
+        result: Result<T, E>,
+    ) -> Option<T> {
+        result.map_err(|err| self.add(field, err)).ok()
+    }
+
+    /// Whether no rule is violated
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the number of violations
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Iterates over the violations
+    pub fn iter(&self) -> std::slice::Iter<'_, Violation> {
+        self.0.iter()
+    }
+
+    /// Lists the violations as `field: message`, rendered in `language`
+    pub fn render(&self, language: Language) -> String {
+        self.0
+            .iter()
+            .map(|violation| {
+                format!(
+                    "{}: {}",
+                    violation.field,
+                    violation.message.render(language)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+impl std::fmt::Display for Violations {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.render(Language::En))
+    }
+}
@@ -0,0 +1,279 @@
+//! Display name validation and normalization
+//!
+//! Names are checked against a configurable [`NameRules`] policy: a length
+//! range measured in characters, the Unicode general categories a name may
+//! draw from, and whether runs of internal whitespace are collapsed.
+//! Control characters are always rejected.
+
+use std::str::FromStr;
+
+use unicode_general_category::{get_general_category, GeneralCategory};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error::ApiError;
+use crate::i18n::Message;
+
+/// Groups of Unicode general categories that may appear in a name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharClass {
+    /// Letters of any script (`L*`)
+    Letter,
+    /// Combining marks (`M*`)
+    Mark,
+    /// Digits and other numerals (`N*`)
+    Number,
+    /// Punctuation such as apostrophes, hyphens and periods (`P*`)
+    Punctuation,
+    /// Currency, math and other symbols (`S*`)
+    Symbol,
+    /// Space separators (`Zs`)
+    Space,
+}
+
+impl CharClass {
+    /// Returns the class a character belongs to, or `None` for control,
+    /// format, line/paragraph separator, private-use and unassigned characters
+    pub fn of(c: char) -> Option<Self> {
+        use GeneralCategory::*;
+
+        match get_general_category(c) {
+            UppercaseLetter | LowercaseLetter | TitlecaseLetter | ModifierLetter | OtherLetter => {
+                Some(CharClass::Letter)
+            }
+            NonspacingMark | SpacingMark | EnclosingMark => Some(CharClass::Mark),
+            DecimalNumber | LetterNumber | OtherNumber => Some(CharClass::Number),
+            ConnectorPunctuation | DashPunctuation | OpenPunctuation | ClosePunctuation
+            | InitialPunctuation | FinalPunctuation | OtherPunctuation => {
+                Some(CharClass::Punctuation)
+            }
+            MathSymbol | CurrencySymbol | ModifierSymbol | OtherSymbol => Some(CharClass::Symbol),
+            SpaceSeparator => Some(CharClass::Space),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for CharClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "letter" => Ok(CharClass::Letter),
+            "mark" => Ok(CharClass::Mark),
+            "number" => Ok(CharClass::Number),
+            "punctuation" => Ok(CharClass::Punctuation),
+            "symbol" => Ok(CharClass::Symbol),
+            "space" => Ok(CharClass::Space),
+            other => Err(format!("unknown character class '{}'", other)),
+        }
+    }
+}
+
+/// Policy applied to user names on create and update
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameRules {
+    /// Minimum length in characters, after normalization
+    pub min_length: usize,
+    /// Maximum length in characters, after normalization
+    pub max_length: usize,
+    /// Character classes a name may contain
+    pub allowed: Vec<CharClass>,
+    /// Whether runs of internal whitespace are collapsed to a single space
+    pub collapse_whitespace: bool,
+}
+
+impl Default for NameRules {
+    fn default() -> Self {
+        Self {
+            min_length: 1,
+            max_length: 100,
+            allowed: vec![
+                CharClass::Letter,
+                CharClass::Mark,
+                CharClass::Number,
+                CharClass::Punctuation,
+                CharClass::Symbol,
+                CharClass::Space,
+            ],
+            collapse_whitespace: true,
+        }
+    }
+}
+
+/// Reasons a name can be rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameError {
+    /// The name is empty or only whitespace
+    Empty,
+    /// The name is shorter than the configured minimum
+    TooShort(usize),
+    /// The name is longer than the configured maximum
+    TooLong(usize),
+    /// The name contains a control or other non-printable character
+    ControlCharacter,
+    /// The name contains a character outside the allowed classes
+    DisallowedCharacter(char),
+}
+
+impl NameError {
+    /// Returns the localizable message for this error
+    pub fn message(&self) -> Message {
+        match self {
+            NameError::Empty => Message::new("name.empty"),
+            NameError::TooShort(min) => Message::new("name.too_short").with("min", min),
+            NameError::TooLong(max) => Message::new("name.too_long").with("max", max),
+            NameError::ControlCharacter => Message::new("name.control_character"),
+            NameError::DisallowedCharacter(c) => {
+                Message::new("name.disallowed_character").with("char", c)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for NameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for NameError {}
+
+impl From<NameError> for Message {
+    fn from(err: NameError) -> Self {
+        err.message()
+    }
+}
+
+impl From<NameError> for ApiError {
+    fn from(err: NameError) -> Self {
+        ApiError::BadRequest(err.message())
+    }
+}
+
+impl NameRules {
+    /// Validates a name and returns its normalized form
+    ///
+    /// The name is NFC-normalized, so precomposed and decomposed spellings
+    /// of the same name are stored identically. Leading and trailing
+    /// whitespace is always trimmed; internal runs of whitespace are
+    /// collapsed when `collapse_whitespace` is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The raw name supplied by the client
+    ///
+    /// # Returns
+    ///
+    /// Returns the normalized name, or a [`NameError`] describing the
+    /// first rule it violates
+    pub fn normalize(&self, input: &str) -> Result<String, NameError> {
+        let composed: String = input.nfc().collect();
+        let trimmed = composed.trim();
+        if trimmed.is_empty() {
+            return Err(NameError::Empty);
+        }
+
+        for c in trimmed.chars() {
+            match CharClass::of(c) {
+                None => return Err(NameError::ControlCharacter),
+                Some(class) if !self.allowed.contains(&class) => {
+                    return Err(NameError::DisallowedCharacter(c))
+                }
+                Some(_) => {}
+            }
+        }
+
+        let name = if self.collapse_whitespace {
+            trimmed.split_whitespace().collect::<Vec<_>>().join(" ")
+        } else {
+            trimmed.to_string()
+        };
+
+        let length = name.chars().count();
+        if length < self.min_length {
+            return Err(NameError::TooShort(self.min_length));
+        }
+        if length > self.max_length {
+            return Err(NameError::TooLong(self.max_length));
+        }
+
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_accepts_international_names() {
+        let rules = NameRules::default();
+        assert_eq!(
+            rules.normalize("  Zoë O'Brien-Smith "),
+            Ok("Zoë O'Brien-Smith".to_string())
+        );
+        assert_eq!(rules.normalize("山田 太郎"), Ok("山田 太郎".to_string()));
+        // Decomposed "ë" (e + combining diaeresis) is composed to a single code point
+        assert_eq!(rules.normalize("Zoe\u{308}"), Ok("Zo\u{eb}".to_string()));
+    }
+
+    #[test]
+    fn test_normalize_collapses_whitespace() {
+        let mut rules = NameRules::default();
+        assert_eq!(rules.normalize("John   Doe"), Ok("John Doe".to_string()));
+
+        rules.collapse_whitespace = false;
+        assert_eq!(rules.normalize("John   Doe"), Ok("John   Doe".to_string()));
+    }
+
+    #[test]
+    fn test_normalize_rejects_control_characters() {
+        let rules = NameRules::default();
+        assert_eq!(
+            rules.normalize("John\u{0}Doe"),
+            Err(NameError::ControlCharacter)
+        );
+        assert_eq!(
+            rules.normalize("John\tDoe"),
+            Err(NameError::ControlCharacter)
+        );
+        assert_eq!(
+            rules.normalize("John\u{202E}Doe"),
+            Err(NameError::ControlCharacter)
+        );
+    }
+
+    #[test]
+    fn test_normalize_enforces_allowed_classes() {
+        let mut rules = NameRules::default();
+        assert_eq!(rules.normalize("User 1"), Ok("User 1".to_string()));
+        assert_eq!(rules.normalize("Agent 007"), Ok("Agent 007".to_string()));
+        assert_eq!(rules.normalize("John $"), Ok("John $".to_string()));
+
+        rules
+            .allowed
+            .retain(|class| !matches!(class, CharClass::Number | CharClass::Symbol));
+        assert_eq!(
+            rules.normalize("User 1"),
+            Err(NameError::DisallowedCharacter('1'))
+        );
+        assert_eq!(
+            rules.normalize("John $"),
+            Err(NameError::DisallowedCharacter('$'))
+        );
+    }
+
+    #[test]
+    fn test_normalize_enforces_length() {
+        let rules = NameRules {
+            min_length: 2,
+            max_length: 5,
+            ..NameRules::default()
+        };
+        assert_eq!(rules.normalize("   "), Err(NameError::Empty));
+        assert_eq!(rules.normalize("J"), Err(NameError::TooShort(2)));
+        assert_eq!(rules.normalize("Jonathan"), Err(NameError::TooLong(5)));
+        // Length is counted in characters, not bytes
+        assert_eq!(rules.normalize("Zoë"), Ok("Zoë".to_string()));
+    }
+}
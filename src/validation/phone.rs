@@ -0,0 +1,122 @@
+//! Phone number validation and E.164 normalization
+//!
+//! Numbers are parsed with libphonenumber metadata, so national formatting
+//! such as spaces, dashes and parentheses is accepted on input. Valid
+//! numbers are stored in E.164 form (`+14155552671`), which makes
+//! uniqueness checks and filtering a plain string comparison.
+
+use phonenumber::country;
+use phonenumber::Mode;
+
+use crate::error::ApiError;
+use crate::i18n::Message;
+
+/// Reasons a phone number can be rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PhoneError {
+    /// The number is empty or only whitespace
+    Empty,
+    /// The number could not be parsed, or has no country code and no
+    /// default region is configured
+    Unparseable,
+    /// The number parsed but is not assigned in its region's numbering plan
+    Invalid,
+}
+
+impl PhoneError {
+    /// Returns the localizable message for this error
+    pub fn message(&self) -> Message {
+        Message::new(match self {
+            PhoneError::Empty => "phone.empty",
+            PhoneError::Unparseable => "phone.invalid_format",
+            PhoneError::Invalid => "phone.invalid",
+        })
+    }
+}
+
+impl std::fmt::Display for PhoneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for PhoneError {}
+
+impl From<PhoneError> for Message {
+    fn from(err: PhoneError) -> Self {
+        err.message()
+    }
+}
+
+impl From<PhoneError> for ApiError {
+    fn from(err: PhoneError) -> Self {
+        ApiError::BadRequest(err.message())
+    }
+}
+
+/// Validates a phone number and returns it in E.164 form
+///
+/// # Arguments
+///
+/// * `input` - The raw phone number supplied by the client
+/// * `default_region` - Region assumed for numbers without a `+` country
+///   code; when `None`, such numbers are rejected
+///
+/// # Returns
+///
+/// Returns the E.164 formatted number, or a [`PhoneError`] describing
+/// why the number was rejected
+pub fn normalize(input: &str, default_region: Option<country::Id>) -> Result<String, PhoneError> {
+    let trimmed = input.trim();
+    if trimmed.is_empty() {
+        return Err(PhoneError::Empty);
+    }
+
+    let number =
+        phonenumber::parse(default_region, trimmed).map_err(|_| PhoneError::Unparseable)?;
+
+    if !phonenumber::is_valid(&number) {
+        return Err(PhoneError::Invalid);
+    }
+
+    Ok(number.format().mode(Mode::E164).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_international_formats() {
+        assert_eq!(
+            normalize("+1 (415) 555-2671", None),
+            Ok("+14155552671".to_string())
+        );
+        assert_eq!(
+            normalize(" +44 20 7946 0958 ", None),
+            Ok("+442079460958".to_string())
+        );
+    }
+
+    #[test]
+    fn test_normalize_uses_default_region() {
+        assert_eq!(
+            normalize("(415) 555-2671", None),
+            Err(PhoneError::Unparseable)
+        );
+        assert_eq!(
+            normalize("(415) 555-2671", Some(country::Id::US)),
+            Ok("+14155552671".to_string())
+        );
+    }
+
+    #[test]
+    fn test_normalize_rejects_invalid_numbers() {
+        assert_eq!(normalize("  ", None), Err(PhoneError::Empty));
+        assert_eq!(
+            normalize("not a number", None),
+            Err(PhoneError::Unparseable)
+        );
+        assert_eq!(normalize("+1 999 555 2671", None), Err(PhoneError::Invalid));
+    }
+}
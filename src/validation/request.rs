@@ -0,0 +1,232 @@
+//! Validation of user request payloads
+//!
+//! Each field is checked with the same rules as everywhere else in
+//! [`crate::validation`]; the payload is only valid if all of them pass.
+
+use crate::config::Config;
+use crate::i18n::Message;
+use crate::models::{CreateUserRequest, UpdateUserRequest, UserStatus};
+
+use super::custom::CustomFields;
+use super::{email, locale, phone, timezone, Validate, Violations};
+
+/// Normalized fields of a valid [`CreateUserRequest`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewUser {
+    /// Normalized name
+    pub name: String,
+    /// Normalized email address
+    pub email: String,
+    /// Phone number in E.164 form
+    pub phone: Option<String>,
+    /// Canonical language tag
+    pub locale: Option<String>,
+    /// Canonical time zone name
+    pub timezone: Option<String>,
+    /// Custom field values, without `null`s
+    pub custom: CustomFields,
+}
+
+/// Normalized fields of a valid [`UpdateUserRequest`]
+///
+/// Absent fields are `None`; for nullable fields, `Some(None)` removes the
+/// stored value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserChanges {
+    /// New normalized name
+    pub name: Option<String>,
+    /// New normalized email address
+    pub email: Option<String>,
+    /// New phone number in E.164 form
+    pub phone: Option<Option<String>>,
+    /// New canonical language tag
+    pub locale: Option<Option<String>>,
+    /// New canonical time zone name
+    pub timezone: Option<Option<String>>,
+    /// Custom field values to set; `null` removes a value
+    pub custom: CustomFields,
+}
+
+impl Validate for CreateUserRequest {
+    type Valid = NewUser;
+
+    fn validate(&self, config: &Config) -> Result<NewUser, Violations> {
+        let mut violations = Violations::new();
+        let name = violations.check("name", config.name_rules.normalize(&self.name));
+        let email = violations.check("email", email::normalize(&self.email));
+        let phone = violations.check(
+            "phone",
+            self.phone
+                .as_deref()
+                .map(|phone| phone::normalize(phone, config.phone_default_region))
+                .transpose(),
+        );
+        if !matches!(self.status, UserStatus::Pending | UserStatus::Active) {
+            violations.add(
+                "status",
+                Message::new("user.invalid_initial_status").with("status", self.status),
+            );
+        }
+        let locale = violations.check(
+            "locale",
+            self.locale.as_deref().map(locale::normalize).transpose(),
+        );
+        let timezone = violations.check(
+            "timezone",
+            self.timezone
+                .as_deref()
+                .map(timezone::normalize)
+                .transpose(),
+        );
+        let custom = config
+            .custom_fields
+            .check_new(&self.custom, &mut violations);
+
+        match (name, email, phone, locale, timezone) {
+            (Some(name), Some(email), Some(phone), Some(locale), Some(timezone))
+                if violations.is_empty() =>
+            {
+                Ok(NewUser {
+                    name,
+                    email,
+                    phone,
+                    locale,
+                    timezone,
+                    custom,
+                })
+            }
+            _ => Err(violations),
+        }
+    }
+}
+
+impl Validate for UpdateUserRequest {
+    type Valid = UserChanges;
+
+    fn validate(&self, config: &Config) -> Result<UserChanges, Violations> {
+        let mut violations = Violations::new();
+        let name = violations.check(
+            "name",
+            self.name
+                .as_deref()
+                .map(|name| config.name_rules.normalize(name))
+                .transpose(),
+        );
+        let email = violations.check(
+            "email",
+            self.email.as_deref().map(email::normalize).transpose(),
+        );
+        let phone = violations.check(
+            "phone",
+            normalize_nullable(&self.phone, |phone| {
+                phone::normalize(phone, config.phone_default_region)
+            }),
+        );
+        let locale = violations.check(
+            "locale",
+            normalize_nullable(&self.locale, locale::normalize),
+        );
+        let timezone = violations.check(
+            "timezone",
+            normalize_nullable(&self.timezone, timezone::normalize),
+        );
+        config
+            .custom_fields
+            .check_changes(&self.custom, &mut violations);
+
+        match (name, email, phone, locale, timezone) {
+            (Some(name), Some(email), Some(phone), Some(locale), Some(timezone))
+                if violations.is_empty() =>
+            {
+                Ok(UserChanges {
+                    name,
+                    email,
+                    phone,
+                    locale,
+                    timezone,
+                    custom: self.custom.clone(),
+                })
+            }
+            _ => Err(violations),
+        }
+    }
+}
+
+/// Normalizes an optional, nullable field from an update request
+///
+/// Absent fields stay absent and explicit `null`s are passed through;
+/// only present values are run through `normalize`.
+fn normalize_nullable<F, E>(
+    value: &Option<Option<String>>,
+    normalize: F,
+) -> Result<Option<Option<String>>, E>
+where
+    F: Fn(&str) -> Result<String, E>,
+{
+    value
+        .as_ref()
+        .map(|value| value.as_deref().map(&normalize).transpose())
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_request_reports_every_violation() {
+        let request = CreateUserRequest {
+            name: " ".to_string(),
+            email: "not-an-email".to_string(),
+            status: UserStatus::Suspended,
+            timezone: Some("Mars/Olympus".to_string()),
+            ..Default::default()
+        };
+        let violations = request.validate(&Config::default()).unwrap_err();
+
+        let rules: Vec<_> = violations
+            .iter()
+            .map(|violation| (violation.field, violation.message.key()))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                ("name", "name.empty"),
+                ("email", "email.invalid_format"),
+                ("status", "user.invalid_initial_status"),
+                ("timezone", "timezone.invalid"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_valid_requests_are_normalized() {
+        let request = CreateUserRequest {
+            name: "  Ada  Lovelace ".to_string(),
+            email: "Ada@Example.COM".to_string(),
+            locale: Some("EN-us".to_string()),
+            ..Default::default()
+        };
+        let user = request.validate(&Config::default()).unwrap();
+        assert_eq!(user.email, "ada@example.com");
+        assert_eq!(user.locale.as_deref(), Some("en-US"));
+
+        let request = UpdateUserRequest {
+            phone: Some(None),
+            locale: Some(Some("not a tag".to_string())),
+            ..Default::default()
+        };
+        let violations = request.validate(&Config::default()).unwrap_err();
+        assert_eq!(violations.len(), 1);
+        assert!(violations.to_string().starts_with("locale: Invalid locale"));
+
+        let changes = UpdateUserRequest {
+            phone: Some(None),
+            ..Default::default()
+        }
+        .validate(&Config::default())
+        .unwrap();
+        assert_eq!(changes.phone, Some(None));
+        assert_eq!(changes.name, None);
+    }
+}
@@ -0,0 +1,90 @@
+//! Time zone validation
+//!
+//! Time zones are names from the IANA Time Zone Database (such as
+//! `Europe/Berlin`), matched case-insensitively and stored with the
+//! database's canonical spelling.
+
+use std::str::FromStr;
+
+use chrono_tz::Tz;
+
+use crate::error::ApiError;
+use crate::i18n::Message;
+
+/// Error returned for a name missing from the IANA Time Zone Database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimezoneError;
+
+impl TimezoneError {
+    /// Returns the localizable message for this error
+    pub fn message(&self) -> Message {
+        Message::new("timezone.invalid")
+    }
+}
+
+impl std::fmt::Display for TimezoneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for TimezoneError {}
+
+impl From<TimezoneError> for Message {
+    fn from(err: TimezoneError) -> Self {
+        err.message()
+    }
+}
+
+impl From<TimezoneError> for ApiError {
+    fn from(err: TimezoneError) -> Self {
+        ApiError::BadRequest(err.message())
+    }
+}
+
+/// Validates a time zone name and returns its canonical spelling
+///
+/// # Arguments
+///
+/// * `input` - The raw time zone name supplied by the client
+///
+/// # Returns
+///
+/// Returns the canonical name, or [`TimezoneError`] if it is unknown
+pub fn normalize(input: &str) -> Result<String, TimezoneError> {
+    parse(input).map(|tz| tz.name().to_string())
+}
+
+/// Parses a time zone name into a [`Tz`]
+pub fn parse(input: &str) -> Result<Tz, TimezoneError> {
+    let trimmed = input.trim();
+    Tz::from_str(trimmed).or_else(|_| {
+        chrono_tz::TZ_VARIANTS
+            .iter()
+            .find(|tz| tz.name().eq_ignore_ascii_case(trimmed))
+            .copied()
+            .ok_or(TimezoneError)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_accepts_iana_names() {
+        assert_eq!(normalize("Europe/Berlin"), Ok("Europe/Berlin".to_string()));
+        assert_eq!(
+            normalize(" america/new_york "),
+            Ok("America/New_York".to_string())
+        );
+        assert_eq!(normalize("UTC"), Ok("UTC".to_string()));
+    }
+
+    #[test]
+    fn test_normalize_rejects_unknown_names() {
+        assert_eq!(normalize(""), Err(TimezoneError));
+        assert_eq!(normalize("Mars/Olympus_Mons"), Err(TimezoneError));
+        assert_eq!(normalize("+02:00"), Err(TimezoneError));
+    }
+}
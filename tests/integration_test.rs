@@ -3,6 +3,9 @@
 //! These tests verify the API endpoints work correctly end-to-end.
 
 use axum::http::StatusCode;
+use rust_api::extract::UserId;
+use rust_api::models::UserStatus;
+use rust_api::responses::Created;
 use rust_api::{handlers, AppState};
 use serde_json::json;
 
@@ -10,6 +13,35 @@ fn create_test_state() -> AppState {
     AppState::new()
 }
 
+/// Answers every MX lookup with a record
+#[cfg(feature = "mx-lookup")]
+struct AcceptAllMx;
+
+#[cfg(feature = "mx-lookup")]
+impl rust_api::validation::email::MxLookup for AcceptAllMx {
+    fn has_mx<'a>(
+        &'a self,
+        _domain: &'a str,
+    ) -> futures_util::future::BoxFuture<'a, std::io::Result<bool>> {
+        Box::pin(std::future::ready(Ok(true)))
+    }
+}
+
+/// Keeps `state` from looking up the domains of real providers, so tests
+/// using them do not depend on the network
+#[cfg(feature = "mx-lookup")]
+fn offline(state: AppState) -> AppState {
+    AppState {
+        mx_lookup: std::sync::Arc::new(AcceptAllMx),
+        ..state
+    }
+}
+
+#[cfg(not(feature = "mx-lookup"))]
+fn offline(state: AppState) -> AppState {
+    state
+}
+
 #[tokio::test]
 async fn test_health_check() {
     let response = handlers::health_check().await;
@@ -34,10 +66,166 @@ async fn test_create_user() {
     .await;
 
     assert!(response.is_ok());
-    let (status, body) = response.unwrap();
-    assert_eq!(status, StatusCode::CREATED);
-    assert_eq!(body.user.name, "John Doe");
-    assert_eq!(body.user.email, "john@example.com");
+    let Created { location, body } = response.unwrap();
+    assert_eq!(location, Some(format!("/api/v1/users/{}", body.data.id)));
+    assert_eq!(body.data.name, "John Doe");
+    assert_eq!(body.data.email, "john@example.com");
+}
+
+#[tokio::test]
+async fn test_create_user_returns_location() {
+    use axum::{
+        body::Body,
+        http::{header, Request},
+    };
+    use tower::ServiceExt;
+
+    let app = rust_api::router(create_test_state());
+    let request = Request::post("/api/v1/users")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({ "name": "Located User", "email": "located@example.com" }).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let location = response.headers()[header::LOCATION]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        location,
+        format!("/api/v1/users/{}", body["data"]["id"].as_str().unwrap())
+    );
+
+    let request = Request::get(&location).body(Body::empty()).unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_background_operations() {
+    use axum::{
+        body::Body,
+        http::{header, Request},
+        Router,
+    };
+    use tower::ServiceExt;
+
+    async fn send(
+        app: &Router,
+        request: Request<Body>,
+    ) -> (StatusCode, Option<String>, serde_json::Value) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .map(|location| location.to_str().unwrap().to_string());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, location, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn finished(app: &Router, location: &str) -> serde_json::Value {
+        for _ in 0..100 {
+            let request = Request::get(location).body(Body::empty()).unwrap();
+            let (status, _, body) = send(app, request).await;
+            assert_eq!(status, StatusCode::OK);
+            let operation = &body["data"];
+            if operation["status"] == "succeeded" || operation["status"] == "failed" {
+                return operation.clone();
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("operation at {} did not finish", location);
+    }
+
+    let app = rust_api::router(create_test_state());
+    let request = Request::post("/api/v1/users/import")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCEPT_LANGUAGE, "de")
+        .body(Body::from(
+            json!({ "users": [
+                { "name": "Imported User", "email": "imported@example.com" },
+                { "name": "", "email": "nameless@example.com" }
+            ] })
+            .to_string(),
+        ))
+        .unwrap();
+    let (status, location, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let operation = &body["data"];
+    assert_eq!(operation["kind"], "import");
+    assert_eq!(operation["total"], 2);
+    let location = location.unwrap();
+    assert_eq!(
+        location,
+        format!("/api/v1/operations/{}", operation["id"].as_str().unwrap())
+    );
+
+    let operation = finished(&app, &location).await;
+    assert_eq!(operation["status"], "succeeded");
+    assert_eq!(operation["done"], 2);
+    assert_eq!(operation["result"]["created"], 1);
+    assert_eq!(operation["result"]["failed"][0]["index"], 1);
+    // Failures are reported in the language the import was requested in
+    assert!(operation["result"]["failed"][0]["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("Die Anfrage verletzt 1 Validierungsregel(n): name: "));
+
+    let request = Request::post("/api/v1/users/export")
+        .body(Body::empty())
+        .unwrap();
+    let (status, location, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(body["data"]["kind"], "export");
+    let operation = finished(&app, &location.unwrap()).await;
+    assert_eq!(operation["result"]["count"], 1);
+    assert_eq!(
+        operation["result"]["users"][0]["email"],
+        "imported@example.com"
+    );
+
+    // Finished operations can no longer be cancelled
+    let request = Request::delete(format!(
+        "/api/v1/operations/{}",
+        operation["id"].as_str().unwrap()
+    ))
+    .body(Body::empty())
+    .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::CONFLICT);
+
+    // Imports cannot be previewed
+    let request = Request::post("/api/v1/users/import?dry_run=true")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "users": [] }).to_string()))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::BAD_REQUEST);
+
+    let request = Request::get(format!("/api/v1/operations/{}", uuid::Uuid::new_v4()))
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::NOT_FOUND);
+    let request = Request::delete(format!("/api/v1/operations/{}", uuid::Uuid::new_v4()))
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::NOT_FOUND);
+    let request = Request::get("/api/v1/operations/nope")
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["error"]["message"],
+        "Invalid operation id 'nope': expected a UUID"
+    );
 }
 
 #[tokio::test]
@@ -78,8 +266,7 @@ async fn test_get_user_not_found() {
     let state = create_test_state();
     let user_id = uuid::Uuid::new_v4();
 
-    let response =
-        handlers::get_user(axum::extract::Path(user_id), axum::extract::State(state)).await;
+    let response = handlers::get_user(UserId(user_id), axum::extract::State(state)).await;
 
     assert!(response.is_err());
 }
@@ -88,10 +275,5238 @@ async fn test_get_user_not_found() {
 async fn test_list_users_empty() {
     let state = create_test_state();
 
-    let response = handlers::list_users(axum::extract::State(state)).await;
+    let response = handlers::list_users(
+        axum::extract::State(state),
+        axum::extract::Query(Default::default()),
+    )
+    .await;
 
     assert!(response.is_ok());
     let body = response.unwrap();
-    assert_eq!(body.count, 0);
-    assert!(body.users.is_empty());
+    assert_eq!(body.data.len(), 0);
+    assert!(body.data.is_empty());
+}
+
+#[tokio::test]
+async fn test_create_user_normalizes_email() {
+    let state = create_test_state();
+    let payload = json!({
+        "name": "Jane Doe",
+        "email": "  Jane.Doe@Bücher.Example "
+    });
+
+    let response = handlers::create_user(
+        axum::extract::State(state.clone()),
+        axum::Json(serde_json::from_value(payload).unwrap()),
+    )
+    .await;
+
+    let Created { body, .. } = response.unwrap();
+    assert_eq!(body.data.email, "jane.doe@xn--bcher-kva.example");
+
+    // The same address in a different form is a duplicate
+    let payload = json!({
+        "name": "Jane Again",
+        "email": "JANE.DOE@xn--bcher-kva.example"
+    });
+
+    let response = handlers::create_user(
+        axum::extract::State(state.clone()),
+        axum::Json(serde_json::from_value(payload).unwrap()),
+    )
+    .await;
+
+    assert_eq!(response.unwrap_err().status_code(), StatusCode::CONFLICT);
+
+    // So is a homograph spelled with a Cyrillic "е"
+    let payload = json!({
+        "name": "Jane Homograph",
+        "email": "jane.do\u{435}@bücher.example"
+    });
+
+    let response = handlers::create_user(
+        axum::extract::State(state),
+        axum::Json(serde_json::from_value(payload).unwrap()),
+    )
+    .await;
+
+    assert_eq!(response.unwrap_err().status_code(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn test_validation_reports_every_violation() {
+    use axum::{
+        body::Body,
+        http::{header, Request},
+    };
+    use tower::ServiceExt;
+
+    let app = rust_api::router(create_test_state());
+    let request = Request::post("/api/v1/users")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCEPT_LANGUAGE, "de")
+        .body(Body::from(
+            json!({ "name": "", "email": "not-an-email", "locale": "not a tag" }).to_string(),
+        ))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let error = &body["error"];
+    assert_eq!(error["code"], "validation_failed");
+    assert_eq!(error["retryable"], false);
+    assert_eq!(
+        error["message"],
+        "Die Anfrage verletzt 3 Validierungsregel(n)"
+    );
+
+    let violations = error["violations"].as_array().unwrap();
+    let rules: Vec<_> = violations
+        .iter()
+        .map(|violation| (violation["field"].as_str(), violation["rule"].as_str()))
+        .collect();
+    assert_eq!(
+        rules,
+        vec![
+            (Some("name"), Some("name.empty")),
+            (Some("email"), Some("email.invalid_format")),
+            (Some("locale"), Some("locale.invalid")),
+        ]
+    );
+    assert!(violations[1]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Ungültig"));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_writes_cannot_duplicate_emails() {
+    let state = create_test_state();
+    let creations = (0..16).map(|_| {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let payload = json!({ "name": "Racer", "email": "race@example.com" });
+            handlers::create_user(
+                axum::extract::State(state),
+                axum::Json(serde_json::from_value(payload).unwrap()),
+            )
+            .await
+        })
+    });
+    let mut created = 0;
+    for creation in creations.collect::<Vec<_>>() {
+        match creation.await.unwrap() {
+            Ok(_) => created += 1,
+            Err(err) => assert_eq!(err.status_code(), StatusCode::CONFLICT),
+        }
+    }
+    assert_eq!(created, 1);
+
+    // Users racing to take the same new email: exactly one wins
+    let mut ids = Vec::new();
+    for i in 0..8 {
+        let payload = json!({ "name": "Mover", "email": format!("mover{}@example.com", i) });
+        let Created { body, .. } = handlers::create_user(
+            axum::extract::State(state.clone()),
+            axum::Json(serde_json::from_value(payload).unwrap()),
+        )
+        .await
+        .unwrap();
+        ids.push(body.data.id);
+    }
+    let updates = ids.into_iter().map(|id| {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let payload = json!({ "email": "target@example.com" });
+            handlers::update_user(
+                UserId(id),
+                axum::extract::State(state),
+                axum::Json(serde_json::from_value(payload).unwrap()),
+            )
+            .await
+        })
+    });
+    let mut moved = 0;
+    for update in updates.collect::<Vec<_>>() {
+        match update.await.unwrap() {
+            Ok(_) => moved += 1,
+            Err(err) => assert_eq!(err.status_code(), StatusCode::CONFLICT),
+        }
+    }
+    assert_eq!(moved, 1);
+    let storage = state.storage.read().await;
+    let holders = storage
+        .get_all()
+        .iter()
+        .filter(|user| user.email == "target@example.com")
+        .count();
+    assert_eq!(holders, 1);
+}
+
+#[tokio::test]
+async fn test_email_aliases_are_duplicates_when_canonicalized() {
+    use rust_api::validation::email::Canonicalization;
+    use rust_api::Config;
+
+    let create = |state: &AppState, email: &str| {
+        let payload = json!({ "name": "Jane Doe", "email": email });
+        handlers::create_user(
+            axum::extract::State(state.clone()),
+            axum::Json(serde_json::from_value(payload).unwrap()),
+        )
+    };
+
+    // By default an alias is a different address
+    let state = offline(create_test_state());
+    assert!(create(&state, "jane.doe@gmail.com").await.is_ok());
+    assert!(create(&state, "jane.doe+news@gmail.com").await.is_ok());
+
+    let state = offline(AppState::with_config(Config {
+        email_canonicalization: Canonicalization::Gmail,
+        ..Config::default()
+    }));
+    let Created { body, .. } = create(&state, "Jane.Doe+Signup@gmail.com").await.unwrap();
+    // The address is stored as given, tag and dots included
+    assert_eq!(body.data.email, "jane.doe+signup@gmail.com");
+
+    for alias in ["janedoe@gmail.com", "j.a.n.e.doe+x@googlemail.com"] {
+        let err = create(&state, alias).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::CONFLICT, "{}", alias);
+    }
+    assert!(create(&state, "jane.doe@example.com").await.is_ok());
+}
+
+#[tokio::test]
+async fn test_create_user_name_rules() {
+    let state = create_test_state();
+    let payload = json!({
+        "name": "  Zoë   O'Brien ",
+        "email": "zoe@example.com"
+    });
+
+    let response = handlers::create_user(
+        axum::extract::State(state.clone()),
+        axum::Json(serde_json::from_value(payload).unwrap()),
+    )
+    .await;
+
+    let Created { body, .. } = response.unwrap();
+    assert_eq!(body.data.name, "Zoë O'Brien");
+
+    // Control characters are rejected on update as well
+    let payload = json!({ "name": "Zoë\u{0}" });
+
+    let response = handlers::update_user(
+        UserId(body.data.id),
+        axum::extract::State(state),
+        axum::Json(serde_json::from_value(payload).unwrap()),
+    )
+    .await;
+
+    assert_eq!(
+        response.unwrap_err().status_code(),
+        StatusCode::UNPROCESSABLE_ENTITY
+    );
+}
+
+#[tokio::test]
+async fn test_user_phone_normalization_and_filter() {
+    let state = create_test_state();
+    let payload = json!({
+        "name": "Phone User",
+        "email": "phone@example.com",
+        "phone": "+1 (415) 555-2671"
+    });
+
+    let response = handlers::create_user(
+        axum::extract::State(state.clone()),
+        axum::Json(serde_json::from_value(payload).unwrap()),
+    )
+    .await;
+
+    let Created { body, .. } = response.unwrap();
+    assert_eq!(body.data.phone.as_deref(), Some("+14155552671"));
+
+    // The same number in another format is a duplicate
+    let payload = json!({
+        "name": "Other User",
+        "email": "other@example.com",
+        "phone": "+14155552671"
+    });
+
+    let response = handlers::create_user(
+        axum::extract::State(state.clone()),
+        axum::Json(serde_json::from_value(payload).unwrap()),
+    )
+    .await;
+
+    assert_eq!(response.unwrap_err().status_code(), StatusCode::CONFLICT);
+
+    let query = serde_json::from_value(json!({ "phone": "+1 415-555-2671" })).unwrap();
+    let response =
+        handlers::list_users(axum::extract::State(state), axum::extract::Query(query)).await;
+
+    let body = response.unwrap();
+    assert_eq!(body.data.len(), 1);
+    assert_eq!(body.data[0].email, "phone@example.com");
+}
+
+#[tokio::test]
+async fn test_user_status_lifecycle() {
+    let state = create_test_state();
+    let payload = json!({
+        "name": "Status User",
+        "email": "status@example.com"
+    });
+
+    let response = handlers::create_user(
+        axum::extract::State(state.clone()),
+        axum::Json(serde_json::from_value(payload).unwrap()),
+    )
+    .await;
+
+    let Created { body, .. } = response.unwrap();
+    let user_id = body.data.id;
+    assert_eq!(body.data.status, UserStatus::Active);
+
+    let response =
+        handlers::suspend_user(UserId(user_id), axum::extract::State(state.clone())).await;
+    assert_eq!(response.unwrap().data.status, UserStatus::Suspended);
+
+    // Suspending twice is not an allowed transition
+    let response =
+        handlers::suspend_user(UserId(user_id), axum::extract::State(state.clone())).await;
+    assert_eq!(response.unwrap_err().status_code(), StatusCode::CONFLICT);
+
+    let query = serde_json::from_value(json!({ "status": "suspended" })).unwrap();
+    let response = handlers::list_users(
+        axum::extract::State(state.clone()),
+        axum::extract::Query(query),
+    )
+    .await;
+    assert_eq!(response.unwrap().data.len(), 1);
+
+    let response =
+        handlers::activate_user(UserId(user_id), axum::extract::State(state.clone())).await;
+    assert_eq!(response.unwrap().data.status, UserStatus::Active);
+
+    let query = serde_json::from_value(json!({ "status": "suspended" })).unwrap();
+    let response =
+        handlers::list_users(axum::extract::State(state), axum::extract::Query(query)).await;
+    assert_eq!(response.unwrap().data.len(), 0);
+}
+
+#[tokio::test]
+async fn test_list_users_inactive_since() {
+    let state = create_test_state();
+    let mut ids = Vec::new();
+    for (name, email) in [
+        ("Idle User", "idle@example.com"),
+        ("Busy User", "busy@example.com"),
+    ] {
+        let payload = json!({ "name": name, "email": email });
+        let Created { body, .. } = handlers::create_user(
+            axum::extract::State(state.clone()),
+            axum::Json(serde_json::from_value(payload).unwrap()),
+        )
+        .await
+        .unwrap();
+        ids.push(body.data.id);
+    }
+
+    let cutoff = chrono::Utc::now() + chrono::Duration::seconds(60);
+    state
+        .storage
+        .write()
+        .await
+        .record_login(&ids[1], cutoff + chrono::Duration::seconds(1))
+        .unwrap();
+
+    let query = serde_json::from_value(json!({ "inactive_since": cutoff.to_rfc3339() })).unwrap();
+    let body = handlers::list_users(axum::extract::State(state), axum::extract::Query(query))
+        .await
+        .unwrap();
+
+    assert_eq!(body.data.len(), 1);
+    assert_eq!(body.data[0].id, ids[0]);
+    assert!(body.data[0].last_login_at.is_none());
+}
+
+#[tokio::test]
+async fn test_user_locale_and_timezone() {
+    let state = create_test_state();
+    let payload = json!({
+        "name": "Locale User",
+        "email": "locale@example.com",
+        "locale": "de-de",
+        "timezone": "europe/berlin"
+    });
+
+    let Created { body, .. } = handlers::create_user(
+        axum::extract::State(state.clone()),
+        axum::Json(serde_json::from_value(payload).unwrap()),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(body.data.locale.as_deref(), Some("de-DE"));
+    assert_eq!(body.data.timezone.as_deref(), Some("Europe/Berlin"));
+
+    let payload = json!({ "timezone": "Mars/Olympus_Mons" });
+    let response = handlers::update_user(
+        UserId(body.data.id),
+        axum::extract::State(state.clone()),
+        axum::Json(serde_json::from_value(payload).unwrap()),
+    )
+    .await;
+    assert_eq!(
+        response.unwrap_err().status_code(),
+        StatusCode::UNPROCESSABLE_ENTITY
+    );
+
+    let payload = json!({ "locale": null });
+    let response = handlers::update_user(
+        UserId(body.data.id),
+        axum::extract::State(state),
+        axum::Json(serde_json::from_value(payload).unwrap()),
+    )
+    .await;
+    let user = response.unwrap().data.clone();
+    assert_eq!(user.locale, None);
+    assert_eq!(user.timezone.as_deref(), Some("Europe/Berlin"));
+}
+
+#[tokio::test]
+async fn test_user_custom_fields() {
+    use axum::{
+        body::Body,
+        http::{header, Request},
+    };
+    use rust_api::models::User;
+    use rust_api::responses::ApiResponse;
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let state = AppState::with_config(Config {
+        custom_fields: r#"[
+            {"name": "department", "type": "string", "required": true},
+            {"name": "employee_id", "type": "string", "pattern": "^E[0-9]{6}$"},
+            {"name": "remote", "type": "boolean"}
+        ]"#
+        .parse()
+        .unwrap(),
+        ..Config::default()
+    });
+    let app = rust_api::router(state);
+
+    let payload = json!({
+        "name": "Custom User",
+        "email": "custom@example.com",
+        "custom": { "employee_id": "42", "remote": "yes", "desk": 7 }
+    });
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/api/v1/users")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(payload.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let violations: Vec<_> = body["error"]["violations"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|violation| violation["message"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        violations,
+        [
+            "desk is not a defined custom field",
+            "employee_id must match ^E[0-9]{6}$",
+            "remote must be a boolean",
+            "department is required"
+        ]
+    );
+
+    let payload = json!({
+        "name": "Custom User",
+        "email": "custom@example.com",
+        "custom": { "department": "Research", "employee_id": "E000042" }
+    });
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/api/v1/users")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(payload.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let created: ApiResponse<User> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(created.data.custom["department"], "Research");
+
+    // Updates merge into the stored values; null removes one
+    for (custom, status) in [
+        (
+            json!({ "department": null }),
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ),
+        (
+            json!({ "employee_id": null, "remote": true }),
+            StatusCode::OK,
+        ),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::put(format!("/api/v1/users/{}", created.data.id))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(json!({ "custom": custom }).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status);
+        if status == StatusCode::OK {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let updated: ApiResponse<User> = serde_json::from_slice(&body).unwrap();
+            assert_eq!(
+                serde_json::to_value(&updated.data.custom).unwrap(),
+                json!({ "department": "Research", "remote": true })
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_update_events_carry_changed_fields() {
+    use axum::{
+        body::Body,
+        http::{header, Request},
+    };
+    use rust_api::events::{Event, EventFilter};
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+
+    let state = create_test_state();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let recorded = seen.clone();
+    state
+        .events
+        .subscribe_filtered(EventFilter::changed(["email"]), move |event| {
+            if let Event::UserUpdated(user, diff) = event {
+                let fields: Vec<String> = diff.iter().map(str::to_string).collect();
+                recorded.lock().unwrap().push((user.email.clone(), fields));
+            }
+        });
+    let app = rust_api::router(state);
+
+    let send = |request: Request<Body>| {
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            (status, body)
+        }
+    };
+    let json = |method: &str, uri: &str, payload: serde_json::Value| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(payload.to_string()))
+            .unwrap()
+    };
+
+    let (status, body) = send(json(
+        "POST",
+        "/api/v1/users",
+        json!({ "name": "Event User", "email": "event@example.com" }),
+    ))
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let uri = format!("/api/v1/users/{}", body["data"]["id"].as_str().unwrap());
+
+    let (status, _) = send(json("PUT", &uri, json!({ "name": "Renamed User" }))).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(json(
+        "PUT",
+        &uri,
+        json!({ "name": "Renamed Again", "email": "moved@example.com" }),
+    ))
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(json("POST", &format!("{}/suspend", uri), json!({}))).await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![(
+            "moved@example.com".to_string(),
+            vec!["email".to_string(), "name".to_string()]
+        )]
+    );
+}
+
+#[tokio::test]
+async fn test_computed_fields_on_request() {
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    let state = create_test_state();
+    let payload = json!({ "name": "Ada Lovelace", "email": "ada@example.com" });
+    let Created { body, .. } = handlers::create_user(
+        axum::extract::State(state.clone()),
+        axum::Json(serde_json::from_value(payload).unwrap()),
+    )
+    .await
+    .unwrap();
+    let app = rust_api::router(state);
+
+    let get = |query: &str| {
+        Request::get(format!("/api/v1/users/{}{}", body.data.id, query))
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = app.clone().oneshot(get("")).await.unwrap();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let user: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert!(user["data"].get("initials").is_none());
+
+    let response = app
+        .clone()
+        .oneshot(get("?include_computed=true"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let user: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(user["data"]["display_name"], "Ada Lovelace");
+    assert_eq!(user["data"]["initials"], "AL");
+    assert_eq!(user["data"]["account_age_days"], 0);
+    assert!(user["data"]["gravatar_url"]
+        .as_str()
+        .unwrap()
+        .starts_with("https://gravatar.com/avatar/"));
+
+    let response = app.oneshot(get("?include_computed=yes")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_response_envelope_negotiation() {
+    use axum::{
+        body::Body,
+        http::{header, Request},
+    };
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let get = |app: axum::Router, path: String, accept: &'static str| async move {
+        let request = Request::get(path)
+            .header(header::ACCEPT, accept)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let request_id = response.headers()["x-request-id"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        (request_id, body)
+    };
+
+    let state = create_test_state();
+    let payload = json!({ "name": "Ada Lovelace", "email": "ada@example.com" });
+    let Created { body, .. } = handlers::create_user(
+        axum::extract::State(state.clone()),
+        axum::Json(serde_json::from_value(payload).unwrap()),
+    )
+    .await
+    .unwrap();
+    let path = format!("/api/v1/users/{}", body.data.id);
+    let app = rust_api::router(state);
+
+    let (request_id, body) = get(app.clone(), path.clone(), "application/json").await;
+    assert_eq!(body["data"]["name"], "Ada Lovelace");
+    assert_eq!(body["meta"]["request_id"], request_id.as_str());
+    assert_eq!(body["links"]["self"], path.as_str());
+
+    let list = "/api/v1/users?status=active".to_string();
+    let (_, body) = get(app.clone(), list.clone(), "application/json").await;
+    assert_eq!(body["data"][0]["name"], "Ada Lovelace");
+    assert_eq!(
+        body["meta"]["pagination"],
+        json!({ "count": 1, "total": 1 })
+    );
+    assert_eq!(body["links"]["self"], list.as_str());
+
+    let bare = "application/json; envelope=false";
+    let (_, body) = get(app.clone(), path.clone(), bare).await;
+    assert_eq!(body["name"], "Ada Lovelace");
+    assert!(body.get("data").is_none());
+    let (_, body) = get(app, list, bare).await;
+    assert_eq!(body[0]["name"], "Ada Lovelace");
+
+    // The default can be turned around, and asked for explicitly again
+    let state = AppState::with_config(Config {
+        response_envelope: false,
+        ..Config::default()
+    });
+    let app = rust_api::router(state);
+    let (_, body) = get(app.clone(), "/api/v1/users".into(), "application/json").await;
+    assert_eq!(body, json!([]));
+    let (_, body) = get(
+        app,
+        "/api/v1/users".into(),
+        "application/json; envelope=true",
+    )
+    .await;
+    assert_eq!(body["data"], json!([]));
+}
+
+#[tokio::test]
+async fn test_field_case_negotiation() {
+    use axum::{body::Body, http::Request};
+    use rust_api::field_case::{FieldCase, X_FIELD_CASE};
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let get = |app: axum::Router, path: String, case: Option<&'static str>| async move {
+        let mut request = Request::get(path);
+        if let Some(case) = case {
+            request = request.header(X_FIELD_CASE, case);
+        }
+        let response = app
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+    };
+
+    let state = AppState::with_config(Config {
+        custom_fields: r#"[{"name": "cost_center", "type": "string"}]"#.parse().unwrap(),
+        ..Config::default()
+    });
+    let payload = json!({
+        "name": "Ada Lovelace",
+        "email": "ada@example.com",
+        "custom": { "cost_center": "R&D" }
+    });
+    let Created { body, .. } = handlers::create_user(
+        axum::extract::State(state.clone()),
+        axum::Json(serde_json::from_value(payload).unwrap()),
+    )
+    .await
+    .unwrap();
+    let path = format!("/api/v1/users/{}", body.data.id);
+    let app = rust_api::router(state);
+
+    let body = get(app.clone(), path.clone(), None).await;
+    assert!(body["data"]["created_at"].is_number());
+    assert!(body["meta"]["request_id"].is_string());
+
+    let body = get(app.clone(), path.clone(), Some("camelCase")).await;
+    assert!(body["data"]["createdAt"].is_number());
+    assert!(body["data"].get("created_at").is_none());
+    assert!(body["meta"]["requestId"].is_string());
+    // Custom field names are data and keep their case
+    assert_eq!(body["data"]["custom"]["cost_center"], "R&D");
+
+    let list = "/api/v1/users?include_computed=true".to_string();
+    let body = get(app.clone(), list, Some("camelCase")).await;
+    assert!(body["data"][0]["lastLoginAt"].is_null());
+    assert_eq!(body["data"][0]["displayName"], "Ada Lovelace");
+
+    let missing = format!("/api/v1/users/{}", uuid::Uuid::new_v4());
+    let body = get(app.clone(), missing, Some("camelCase")).await;
+    assert_eq!(body["error"]["code"], "not_found");
+
+    // Unknown cases fall back to the configured one
+    let body = get(app, path, Some("kebab-case")).await;
+    assert!(body["data"]["created_at"].is_number());
+
+    let app = rust_api::router(AppState::with_config(Config {
+        field_case: FieldCase::Camel,
+        ..Config::default()
+    }));
+    let body = get(app.clone(), "/api/v1/users".into(), None).await;
+    assert_eq!(body["meta"]["pagination"]["count"], 0);
+    let body = get(app, "/api/v1/users".into(), Some("snake_case")).await;
+    assert!(body["meta"].get("request_id").is_some());
+}
+
+#[tokio::test]
+async fn test_list_users_filter_expression() {
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    let state = create_test_state();
+    for (name, email) in [
+        ("John Smith", "john@example.com"),
+        ("John Doe", "doe@example.org"),
+        ("Ada Lovelace", "ada@example.com"),
+    ] {
+        let payload = json!({ "name": name, "email": email });
+        handlers::create_user(
+            axum::extract::State(state.clone()),
+            axum::Json(serde_json::from_value(payload).unwrap()),
+        )
+        .await
+        .unwrap();
+    }
+    let app = rust_api::router(state);
+    let list = |filter: &str| {
+        let filter = filter.replace(' ', "%20").replace('\'', "%27");
+        let request = Request::get(format!("/api/v1/users?filter={}", filter))
+            .header("accept-language", "de")
+            .body(Body::empty())
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            (status, body)
+        }
+    };
+
+    let (status, body) = list(
+        "startswith(name, 'John') and not endswith(email, '.org') and created_at gt 2024-01-01",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["meta"]["pagination"]["count"], 1);
+    assert_eq!(body["data"][0]["name"], "John Smith");
+
+    let (_, body) = list("name eq 'Ada Lovelace' or email eq 'doe@example.org'").await;
+    assert_eq!(body["meta"]["pagination"]["count"], 2);
+
+    let (status, body) = list("name eq 'John' and").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["message"], "Der Filter endet unerwartet");
+
+    let (status, body) = list("age gt 30").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["message"], "Unbekanntes Filterfeld 'age'");
+}
+
+#[tokio::test]
+async fn test_list_users_pages_with_link_headers() {
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    let state = create_test_state();
+    for name in ["Ada", "Bob", "Cyd", "Dee", "Eve"] {
+        let payload = json!({ "name": name, "email": format!("{}@example.com", name) });
+        handlers::create_user(
+            axum::extract::State(state.clone()),
+            axum::Json(serde_json::from_value(payload).unwrap()),
+        )
+        .await
+        .unwrap();
+    }
+    let app = rust_api::router(state);
+    let list = |query: &str| {
+        let request = Request::get(format!("/api/v1/users?{}", query))
+            .body(Body::empty())
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let header = |name: &str| {
+                response
+                    .headers()
+                    .get(name)
+                    .map(|value| value.to_str().unwrap().to_string())
+            };
+            let (total, link) = (header("x-total-count"), header("link"));
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            (status, total, link, body)
+        }
+    };
+
+    let (status, total, link, body) = list("status=active&page=2&per_page=2").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(total.as_deref(), Some("5"));
+    let url = |page: usize| format!("/api/v1/users?status=active&page={}&per_page=2", page);
+    assert_eq!(
+        link.unwrap(),
+        format!(
+            "<{}>; rel=\"first\", <{}>; rel=\"prev\", <{}>; rel=\"next\", <{}>; rel=\"last\"",
+            url(1),
+            url(1),
+            url(3),
+            url(3)
+        )
+    );
+    assert_eq!(
+        body["meta"]["pagination"],
+        json!({ "count": 2, "total": 5, "page": 2, "per_page": 2 })
+    );
+    assert_eq!(body["links"]["next"], url(3));
+
+    // Pages follow creation order without repeating anyone
+    let mut seen = Vec::new();
+    for page in 1..=3 {
+        let (_, _, _, body) = list(&format!("page={}&per_page=2", page)).await;
+        for user in body["data"].as_array().unwrap() {
+            seen.push(user["id"].as_str().unwrap().to_string());
+        }
+    }
+    seen.sort();
+    seen.dedup();
+    assert_eq!(seen.len(), 5);
+
+    let (_, total, link, body) = list("status=active").await;
+    assert_eq!(total.as_deref(), Some("5"));
+    assert_eq!(link, None);
+    assert_eq!(
+        body["meta"]["pagination"],
+        json!({ "count": 5, "total": 5 })
+    );
+
+    let (status, _, _, _) = list("page=0").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _, _, _) = list("per_page=1001").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_list_users_range_header() {
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    let state = create_test_state();
+    let app = rust_api::router(state.clone());
+    let list = |query: &str, range: &str| {
+        let request = Request::get(format!("/api/v1/users{}", query))
+            .header("range", range)
+            .body(Body::empty())
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let content_range = response
+                .headers()
+                .get("content-range")
+                .map(|value| value.to_str().unwrap().to_string());
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            (status, content_range, body)
+        }
+    };
+
+    let (status, content_range, body) = list("", "items=0-24").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_range.as_deref(), Some("items */0"));
+    assert_eq!(body["data"], json!([]));
+
+    for name in ["Ada", "Bob", "Cyd", "Dee", "Eve"] {
+        let payload = json!({ "name": name, "email": format!("{}@example.com", name) });
+        handlers::create_user(
+            axum::extract::State(state.clone()),
+            axum::Json(serde_json::from_value(payload).unwrap()),
+        )
+        .await
+        .unwrap();
+    }
+
+    let (status, content_range, body) = list("", "items=1-2").await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(content_range.as_deref(), Some("items 1-2/5"));
+    assert_eq!(
+        body["meta"]["pagination"],
+        json!({ "count": 2, "total": 5 })
+    );
+
+    let (status, content_range, body) = list("", "items=3-99").await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(content_range.as_deref(), Some("items 3-4/5"));
+    assert_eq!(body["data"].as_array().unwrap().len(), 2);
+
+    let (status, content_range, body) = list("", "items=5-9").await;
+    assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(content_range.as_deref(), Some("items */5"));
+    assert_eq!(body["error"]["code"], "range_not_satisfiable");
+
+    // Other units are ignored, and page parameters take precedence
+    let (status, content_range, body) = list("", "bytes=0-10").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_range, None);
+    assert_eq!(body["data"].as_array().unwrap().len(), 5);
+    let (status, content_range, body) = list("?page=1&per_page=4", "items=0-0").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_range, None);
+    assert_eq!(body["data"].as_array().unwrap().len(), 4);
+}
+
+#[tokio::test]
+async fn test_errors_localized_from_accept_language() {
+    use axum::{body::Body, http::Request, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    let state = create_test_state();
+    let app = Router::new()
+        .route("/api/v1/users/:id", get(handlers::get_user))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            rust_api::i18n::localize,
+        ))
+        .with_state(state);
+    let user_id = uuid::Uuid::new_v4();
+
+    let request = Request::get(format!("/api/v1/users/{}", user_id))
+        .header("accept-language", "fr-CA, en;q=0.5")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()["content-language"], "fr");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        body["error"]["message"],
+        format!("Utilisateur avec l'identifiant {} introuvable", user_id)
+    );
+    assert_eq!(body["error"]["status"], 404);
+
+    // Unsupported languages fall back to English
+    let request = Request::get(format!("/api/v1/users/{}", user_id))
+        .header("accept-language", "ja")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        body["error"]["message"],
+        format!("User with id {} not found", user_id)
+    );
+}
+
+#[tokio::test]
+async fn test_timestamps_negotiated_from_accept() {
+    use axum::{body::Body, http::Request, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    let state = create_test_state();
+    let payload = json!({ "name": "Clock User", "email": "clock@example.com" });
+    let Created { body, .. } = handlers::create_user(
+        axum::extract::State(state.clone()),
+        axum::Json(serde_json::from_value(payload).unwrap()),
+    )
+    .await
+    .unwrap();
+    let created_at = body.data.created_at;
+
+    let app = Router::new()
+        .route("/api/v1/users/:id", get(handlers::get_user))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            rust_api::timestamps::negotiate,
+        ))
+        .with_state(state);
+
+    let fetch = |accept: &'static str| {
+        let request = Request::get(format!("/api/v1/users/{}", body.data.id))
+            .header("accept", accept)
+            .body(Body::empty())
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+    };
+
+    // Existing consumers keep getting Unix seconds
+    let body = fetch("application/json").await;
+    assert_eq!(body["data"]["created_at"], created_at.timestamp());
+
+    let body = fetch("application/json; timestamps=rfc3339").await;
+    let text = body["data"]["created_at"].as_str().unwrap();
+    assert!(text.ends_with('Z'));
+    assert_eq!(
+        chrono::DateTime::parse_from_rfc3339(text).unwrap(),
+        created_at
+    );
+    assert!(body["data"]["last_seen_at"].is_null());
+}
+
+#[tokio::test]
+async fn test_unknown_routes_and_methods_return_json() {
+    use axum::{
+        body::Body,
+        http::{Method, Request},
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    let app = Router::new()
+        .route("/api/v1/users", get(handlers::list_users))
+        .fallback(handlers::not_found)
+        .method_not_allowed_fallback(handlers::method_not_allowed)
+        .with_state(create_test_state());
+
+    let request = Request::get("/api/v1/nothing-here")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        body["error"]["message"],
+        "No route matches /api/v1/nothing-here"
+    );
+    assert_eq!(body["error"]["status"], 404);
+    assert_eq!(body["error"]["code"], "not_found");
+    assert_eq!(body["error"]["retryable"], false);
+
+    let request = Request::builder()
+        .method(Method::PATCH)
+        .uri("/api/v1/users")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(response.headers()["allow"], "GET,HEAD");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        body["error"]["message"],
+        "Method PATCH is not allowed for /api/v1/users"
+    );
+    assert_eq!(body["error"]["status"], 405);
+}
+
+#[tokio::test]
+async fn test_trailing_slash_and_uuid_case() {
+    use axum::{body::Body, extract::Request, middleware, routing::get, Router};
+    use rust_api::paths::{self, TrailingSlash};
+    use rust_api::Config;
+    use tower::{Layer, ServiceExt};
+
+    let state = AppState::with_config(Config {
+        trailing_slash: TrailingSlash::Rewrite,
+        ..Config::default()
+    });
+    let payload = json!({ "name": "Case User", "email": "case@example.com" });
+    let Created { body, .. } = handlers::create_user(
+        axum::extract::State(state.clone()),
+        axum::Json(serde_json::from_value(payload).unwrap()),
+    )
+    .await
+    .unwrap();
+    let id = body.data.id.to_string().to_uppercase();
+
+    let app_for = |state: AppState| {
+        let router = Router::new()
+            .route("/api/v1/users/:id", get(handlers::get_user))
+            .with_state(state.clone());
+        middleware::from_fn_with_state(state, paths::normalize_trailing_slash).layer(router)
+    };
+
+    // Rewritten and routed as if the slash were absent; IDs ignore case
+    let request = Request::get(format!("/api/v1/users/{}/", id))
+        .body(Body::empty())
+        .unwrap();
+    let response = app_for(state.clone()).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["data"]["email"], "case@example.com");
+
+    let state = AppState {
+        config: std::sync::Arc::new(Config {
+            trailing_slash: TrailingSlash::Redirect,
+            ..Config::default()
+        }),
+        ..state
+    };
+    let request = Request::get(format!("/api/v1/users/{}/?fields=all", id))
+        .body(Body::empty())
+        .unwrap();
+    let response = app_for(state.clone()).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(
+        response.headers()["location"],
+        format!("/api/v1/users/{}?fields=all", id).as_str()
+    );
+
+    // Redirects never leave the host
+    for path in ["//evil.com/", "/\\evil.com/", "///evil.com//"] {
+        let request = Request::get(path).body(Body::empty()).unwrap();
+        let response = app_for(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(
+            response.status(),
+            StatusCode::PERMANENT_REDIRECT,
+            "{}",
+            path
+        );
+        assert_eq!(response.headers()["location"], "/evil.com", "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn test_invalid_user_id_returns_json_error() {
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    let app = Router::new()
+        .route("/api/v1/users/:id", get(handlers::get_user))
+        .with_state(create_test_state());
+
+    let request = Request::get("/api/v1/users/not-a-uuid")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        body["error"]["message"],
+        "Invalid user id 'not-a-uuid': expected a UUID"
+    );
+    assert_eq!(body["error"]["status"], 400);
+}
+
+#[tokio::test]
+async fn test_responses_match_openapi_contract() {
+    use axum::{body::Body, http::Method, http::Request};
+    use rust_api::contract::Contract;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let app = rust_api::router(create_test_state());
+
+    let call = |method: Method, path: String, body: Option<serde_json::Value>, accept: &str| {
+        let mut request = Request::builder()
+            .method(method.clone())
+            .uri(&path)
+            .header("accept", accept);
+        let body = match body {
+            Some(body) => {
+                request = request.header("content-type", "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        let request = request.body(body).unwrap();
+        let app = app.clone();
+        let contract = contract.clone();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let (status, body) = contract
+                .check_response(&method, &path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err));
+            let body = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+            (status, body)
+        }
+    };
+
+    for accept in ["application/json", "application/json; timestamps=rfc3339"] {
+        let (status, _) = call(Method::GET, "/".into(), None, accept).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let email = format!("contract-{}@example.com", accept.len());
+        let payload = json!({
+            "name": "Contract User",
+            "email": email,
+            "phone": "+1 415 555 2671",
+            "locale": "en-US",
+            "timezone": "Europe/Berlin"
+        });
+        let (status, body) = call(
+            Method::POST,
+            "/api/v1/users".into(),
+            Some(payload.clone()),
+            accept,
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let id = body["data"]["id"].as_str().unwrap().to_string();
+        let user = format!("/api/v1/users/{}", id);
+
+        let (status, _) = call(Method::POST, "/api/v1/users".into(), Some(payload), accept).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = call(
+            Method::POST,
+            "/api/v1/users".into(),
+            Some(json!({ "name": "", "email": "x@example.com" })),
+            accept,
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let (status, _) = call(Method::GET, "/api/v1/users".into(), None, accept).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = call(Method::GET, user.clone(), None, accept).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = call(Method::GET, "/api/v1/users/nope".into(), None, accept).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = call(
+            Method::PUT,
+            user.clone(),
+            Some(json!({ "name": "Renamed", "phone": null })),
+            accept,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        for (action, expected) in [
+            ("suspend", StatusCode::OK),
+            ("activate", StatusCode::OK),
+            ("deactivate", StatusCode::OK),
+            ("activate", StatusCode::CONFLICT),
+        ] {
+            let path = format!("{}/{}", user, action);
+            let (status, _) = call(Method::POST, path, None, accept).await;
+            assert_eq!(status, expected);
+        }
+
+        let (status, _) = call(Method::DELETE, user.clone(), None, accept).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = call(Method::GET, user, None, accept).await;
+        assert_eq!(status, StatusCode::GONE);
+    }
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn test_client_round_trip() {
+    use rust_api::client::{Client, ClientError, UserFilter};
+    use rust_api::error::ErrorCode;
+    use rust_api::models::{CreateUserRequest, UpdateUserRequest};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = axum::serve(listener, rust_api::router(create_test_state()));
+    tokio::spawn(async move { server.await.unwrap() });
+
+    let client = Client::new(&format!("http://{}", addr)).unwrap();
+    let user = client
+        .create_user(&CreateUserRequest {
+            name: "Client User".to_string(),
+            email: "client@example.com".to_string(),
+            phone: Some("+1 415 555 2671".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(user.phone.as_deref(), Some("+14155552671"));
+
+    let user = client
+        .update_user(
+            user.id,
+            &UpdateUserRequest {
+                phone: Some(None),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(user.phone, None);
+    assert_eq!(user.name, "Client User");
+
+    client.suspend_user(user.id).await.unwrap();
+    let suspended = client
+        .list_users(&UserFilter {
+            status: Some(UserStatus::Suspended),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(suspended.len(), 1);
+    let matching = client
+        .list_users(&UserFilter {
+            filter: Some("name eq 'Client User' and status ne 'active'".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(matching.len(), 1);
+
+    let err = client.suspend_user(user.id).await.unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::Api { status, .. } if status == StatusCode::CONFLICT
+    ));
+    assert_eq!(err.code(), Some(ErrorCode::Conflict));
+    assert!(!err.is_retryable());
+
+    client.delete_user(user.id).await.unwrap();
+    let err = client.get_user(user.id).await.unwrap_err();
+    assert!(err.is_not_found());
+    assert_eq!(
+        err.to_string(),
+        format!("410 Gone: User {} was deleted", user.id)
+    );
+}
+
+#[tokio::test]
+async fn test_generate_users_dev_endpoint() {
+    use axum::{body::Body, http::Method, http::Request};
+    use rust_api::contract::Contract;
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let generate = |state: AppState, query: &'static str| {
+        let contract = contract.clone();
+        async move {
+            let path = format!("/api/v1/dev/generate-users{}", query);
+            let request = Request::post(&path).body(Body::empty()).unwrap();
+            let response = rust_api::router(state).oneshot(request).await.unwrap();
+            let (status, body) = contract
+                .check_response(&Method::POST, &path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err));
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            )
+        }
+    };
+
+    // Disabled by default
+    let (status, _) = generate(create_test_state(), "?count=5").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let state = AppState::with_config(Config {
+        dev_endpoints: true,
+        ..Config::default()
+    });
+    let (status, body) = generate(state.clone(), "?count=25&seed=11").await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["meta"]["pagination"]["count"], 25);
+    assert_eq!(state.storage.read().await.get_all().len(), 25);
+
+    // Same seed again: every email is taken, so nothing new is created
+    let (status, body) = generate(state.clone(), "?count=25&seed=11").await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["meta"]["pagination"]["count"], 0);
+
+    let (status, _) = generate(state, "?count=10001").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_requests_shed_when_saturated() {
+    use axum::{body::Body, http::header::RETRY_AFTER, http::Request};
+    use rust_api::Config;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    let state = AppState::with_config(Config {
+        max_concurrent_requests: Some(1),
+        retry_after: Duration::from_secs(3),
+        ..Config::default()
+    });
+    let app = rust_api::router(state.clone());
+    let list = || Request::get("/api/v1/users").body(Body::empty()).unwrap();
+
+    // Holding the storage lock parks the first request inside its handler
+    let lock = state.storage.write().await;
+    let first = tokio::spawn(app.clone().oneshot(list()));
+    while state.metrics.in_flight() < 1 {
+        tokio::task::yield_now().await;
+    }
+
+    let response = app.clone().oneshot(list()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[RETRY_AFTER], "3");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"]["status"], 503);
+    assert_eq!(body["error"]["code"], "unavailable");
+    assert_eq!(body["error"]["retryable"], true);
+    assert_eq!(state.metrics.in_flight(), 1);
+    assert_eq!(state.metrics.requests_shed(), 1);
+
+    drop(lock);
+    assert_eq!(first.await.unwrap().unwrap().status(), StatusCode::OK);
+    assert_eq!(state.metrics.in_flight(), 0);
+
+    // Capacity is available again
+    let request = Request::get("/metrics").body(Body::empty()).unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(text.contains("http_requests_in_flight 1\n"));
+    assert!(text.contains("http_requests_shed_total 1\n"));
+}
+
+#[tokio::test]
+async fn test_set_log_level_admin_endpoint() {
+    use axum::{body::Body, http::Method, http::Request};
+    use rust_api::contract::Contract;
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let set_level = |state: AppState, filter: &'static str| {
+        let contract = contract.clone();
+        async move {
+            let path = "/api/v1/admin/log-level";
+            let request = Request::put(path)
+                .header("content-type", "application/json")
+                .body(Body::from(json!({ "filter": filter }).to_string()))
+                .unwrap();
+            let response = rust_api::router(state).oneshot(request).await.unwrap();
+            let (status, body) = contract
+                .check_response(&Method::PUT, path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err));
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            )
+        }
+    };
+
+    // Disabled by default
+    let (status, _) = set_level(create_test_state(), "debug").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let state = AppState::with_config(Config {
+        admin_endpoints: true,
+        ..Config::default()
+    });
+    let (status, body) = set_level(state.clone(), "rust_api::handlers=trace,info").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["filter"], "rust_api::handlers=trace,info");
+    assert_eq!(
+        state.log_filter.directives(),
+        "rust_api::handlers=trace,info"
+    );
+
+    let (status, _) = set_level(state.clone(), "rust_api=loud").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        state.log_filter.directives(),
+        "rust_api::handlers=trace,info"
+    );
+}
+
+#[tokio::test]
+async fn test_reserved_names_and_emails() {
+    use axum::{body::Body, http::Method, http::Request};
+    use rust_api::contract::Contract;
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let app = rust_api::router(AppState::with_config(Config {
+        admin_endpoints: true,
+        ..Config::default()
+    }));
+    let send = |method: Method, path: String, payload: serde_json::Value| {
+        let (app, contract) = (app.clone(), contract.clone());
+        async move {
+            let request = Request::builder()
+                .method(method.clone())
+                .uri(&path)
+                .header("content-type", "application/json")
+                .body(Body::from(payload.to_string()))
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let (status, body) = contract
+                .check_response(&method, &path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err));
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            )
+        }
+    };
+    let create = |name: &str, email: &str| {
+        send(
+            Method::POST,
+            "/api/v1/users".to_string(),
+            json!({ "name": name, "email": email }),
+        )
+    };
+
+    // Reserved by default
+    let (status, body) = create("Ops Team", "Admin+ops@example.com").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["code"], "reserved");
+    assert_eq!(
+        body["error"]["message"],
+        "The email address admin+ops@example.com is reserved"
+    );
+    let (status, body) = create("Root", "ops@example.com").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["message"], "The name 'Root' is reserved");
+
+    let (status, body) = send(
+        Method::PUT,
+        "/api/v1/admin/reserved".to_string(),
+        json!({ "emails": ["noreply@example.com"], "blocked_terms": ["Darn"] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        json!({ "emails": ["noreply@example.com"], "names": [], "blocked_terms": ["darn"] })
+    );
+    let (status, body) = send(Method::GET, "/api/v1/admin/reserved".to_string(), json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["blocked_terms"], json!(["darn"]));
+
+    let (status, body) = create("Root", "admin@example.com").await;
+    assert_eq!(status, StatusCode::CREATED);
+    let path = format!("/api/v1/users/{}", body["data"]["id"].as_str().unwrap());
+
+    // Only the fields an update changes are checked
+    let (status, _) = send(Method::PUT, path.clone(), json!({ "phone": null })).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = send(Method::PUT, path.clone(), json!({ "name": "Darn It" })).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["code"], "reserved");
+    assert_eq!(body["error"]["message"], "The name contains a blocked term");
+    let (status, _) = send(
+        Method::PUT,
+        path,
+        json!({ "name": "Darnell", "email": "noreply@example.com" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn test_get_responses_cached_until_mutation() {
+    use axum::{body::Body, http::Request};
+    use rust_api::{cache, Config};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    let state = AppState::with_config(Config {
+        cache: cache::Settings {
+            list_ttl: Duration::from_secs(60),
+            user_ttl: Duration::from_secs(60),
+        },
+        ..Config::default()
+    });
+    let app = rust_api::router(state.clone());
+    let list = |app: axum::Router, auth: &'static str| async move {
+        let request = Request::get("/api/v1/users")
+            .header("authorization", auth)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let cache = response.headers()[cache::X_CACHE]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        (cache, body["meta"]["pagination"]["count"].as_u64().unwrap())
+    };
+
+    assert_eq!(list(app.clone(), "Bearer a").await, ("miss".into(), 0));
+    assert_eq!(list(app.clone(), "Bearer a").await, ("hit".into(), 0));
+    // Another caller never shares the first caller's entry
+    assert_eq!(list(app.clone(), "Bearer b").await, ("miss".into(), 0));
+
+    let request = Request::post("/api/v1/users")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            json!({ "name": "Cache Test", "email": "cache@example.com" }).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // The creation invalidated the cached lists
+    assert_eq!(list(app.clone(), "Bearer a").await, ("miss".into(), 1));
+    assert_eq!(list(app, "Bearer a").await, ("hit".into(), 1));
+    assert_eq!(state.metrics.cache_hits(), 2);
+    assert_eq!(state.metrics.cache_misses(), 3);
+}
+
+#[tokio::test]
+async fn test_list_users_etag_revalidation() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::contract::Contract;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let state = create_test_state();
+    let list = |if_none_match: Option<String>| {
+        let contract = contract.clone();
+        let app = rust_api::router(state.clone());
+        async move {
+            let mut request = Request::get("/api/v1/users");
+            if let Some(etag) = if_none_match {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            let response = app
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let etag = response.headers()[header::ETAG]
+                .to_str()
+                .unwrap()
+                .to_string();
+            let (status, body) = contract
+                .check_response(&Method::GET, "/api/v1/users", response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err));
+            (status, etag, body)
+        }
+    };
+
+    let (status, etag, _) = list(None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(etag.starts_with('"') && etag.ends_with('"'));
+
+    let (status, same, body) = list(Some(etag.clone())).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert_eq!(same, etag);
+    assert!(body.is_empty());
+
+    let created = handlers::create_user(
+        axum::extract::State(state.clone()),
+        axum::Json(
+            serde_json::from_value(json!({ "name": "Etag Test", "email": "etag@example.com" }))
+                .unwrap(),
+        ),
+    )
+    .await
+    .unwrap();
+    assert!(created.location.is_some());
+
+    // Any mutation changes the tag
+    let (status, changed, body) = list(Some(etag.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(changed, etag);
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["meta"]["pagination"]["count"], 1);
+}
+
+#[tokio::test]
+async fn test_head_matches_get_without_body() {
+    use axum::{
+        body::Body,
+        http::{header, Request},
+    };
+    use rust_api::{cache, Config};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    let state = AppState::with_config(Config {
+        cache: cache::Settings {
+            list_ttl: Duration::from_secs(60),
+            user_ttl: Duration::from_secs(60),
+        },
+        ..Config::default()
+    });
+    let Created { body: created, .. } = handlers::create_user(
+        axum::extract::State(state.clone()),
+        axum::Json(
+            serde_json::from_value(json!({ "name": "Head Test", "email": "head@example.com" }))
+                .unwrap(),
+        ),
+    )
+    .await
+    .unwrap();
+    let app = rust_api::router(state);
+    let send = |method: &'static str, path: String| {
+        let app = app.clone();
+        async move {
+            let request = Request::builder()
+                .method(method)
+                .uri(path)
+                .body(Body::empty())
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (status, headers, body)
+        }
+    };
+
+    let user_path = format!("/api/v1/users/{}", created.data.id);
+    for path in ["/".to_string(), "/api/v1/users".to_string(), user_path] {
+        // The second round is answered from the response cache, which a
+        // HEAD miss must not have filled with an empty body
+        for round in 0..2 {
+            let (head_status, head_headers, head_body) = send("HEAD", path.clone()).await;
+            let (status, get_headers, get_body) = send("GET", path.clone()).await;
+
+            assert_eq!(head_status, status, "{}", path);
+            assert!(head_body.is_empty(), "{}", path);
+            assert!(!get_body.is_empty(), "{}", path);
+            assert_eq!(
+                head_headers[header::CONTENT_LENGTH],
+                get_body.len().to_string(),
+                "{}",
+                path
+            );
+            assert_eq!(
+                head_headers.get(header::ETAG),
+                get_headers.get(header::ETAG),
+                "{}",
+                path
+            );
+            if round == 1 && get_headers.contains_key(cache::X_CACHE) {
+                assert_eq!(head_headers[cache::X_CACHE], "hit", "{}", path);
+                assert_eq!(get_headers[cache::X_CACHE], "hit", "{}", path);
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_scoped_tokens_enforced_per_route() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::contract::Contract;
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let state = AppState::with_config(Config {
+        api_tokens: [
+            "reporting:read-token=users:read".parse().unwrap(),
+            "ops:admin-token=admin".parse().unwrap(),
+        ]
+        .into_iter()
+        .collect(),
+        ..Config::default()
+    });
+    let app = rust_api::router(state);
+    let send = |method: Method, path: &'static str, token: Option<&'static str>| {
+        let contract = contract.clone();
+        let app = app.clone();
+        async move {
+            let mut request = Request::builder()
+                .method(method.clone())
+                .uri(path)
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            let body = if method == Method::POST {
+                json!({ "name": "Token Test", "email": "token@example.com" }).to_string()
+            } else {
+                String::new()
+            };
+            let response = app
+                .oneshot(request.body(Body::from(body)).unwrap())
+                .await
+                .unwrap();
+            let challenge = response
+                .headers()
+                .get(header::WWW_AUTHENTICATE)
+                .map(|value| value.to_str().unwrap().to_string());
+            let (status, _) = contract
+                .check_response(&method, path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err));
+            (status, challenge)
+        }
+    };
+
+    // Public routes need no token
+    let (status, _) = send(Method::GET, "/", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, challenge) = send(Method::GET, "/api/v1/users", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(challenge.as_deref(), Some("Bearer"));
+
+    let (status, challenge) = send(Method::GET, "/api/v1/users", Some("guess")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(challenge.as_deref(), Some("Bearer error=\"invalid_token\""));
+
+    let (status, _) = send(Method::GET, "/api/v1/users", Some("read-token")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // A read-only token cannot write
+    let (status, challenge) = send(Method::POST, "/api/v1/users", Some("read-token")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        challenge.as_deref(),
+        Some("Bearer error=\"insufficient_scope\", scope=\"users:write\"")
+    );
+
+    // Admin implies every scope
+    let (status, _) = send(Method::POST, "/api/v1/users", Some("admin-token")).await;
+    assert_eq!(status, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn test_rate_limits_with_per_principal_overrides() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::contract::Contract;
+    use rust_api::rate_limit::RateLimit;
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let app = rust_api::router(AppState::with_config(Config {
+        api_tokens: [
+            "reporting:read-token=users:read".parse().unwrap(),
+            "partner:partner-token=users:read".parse().unwrap(),
+            "ops:admin-token=admin".parse().unwrap(),
+        ]
+        .into_iter()
+        .collect(),
+        rate_limit: Some(RateLimit {
+            requests: 2,
+            window_seconds: 60,
+        }),
+        admin_endpoints: true,
+        ..Config::default()
+    }));
+    let send = |method: Method, path: &'static str, token: &'static str, body: String| {
+        let (app, contract) = (app.clone(), contract.clone());
+        async move {
+            let request = Request::builder()
+                .method(method.clone())
+                .uri(path)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::from(body))
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let header = |name: &str| {
+                response
+                    .headers()
+                    .get(name)
+                    .map(|value| value.to_str().unwrap().to_string())
+            };
+            let (limit, remaining) = (header("ratelimit-limit"), header("ratelimit-remaining"));
+            let retry_after = header("retry-after");
+            let (status, body) = if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, body.to_vec())
+            } else {
+                let (status, body) = contract
+                    .check_response(&method, path, response)
+                    .await
+                    .unwrap_or_else(|err| panic!("{}", err));
+                (status, body.to_vec())
+            };
+            let body = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+            (status, body, limit, remaining, retry_after)
+        }
+    };
+    let list = |token| send(Method::GET, "/api/v1/users", token, String::new());
+
+    // The default limit
+    for remaining in ["1", "0"] {
+        let (status, _, limit, left, _) = list("read-token").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(limit.as_deref(), Some("2"));
+        assert_eq!(left.as_deref(), Some(remaining));
+    }
+    let (status, body, _, _, retry_after) = list("read-token").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["error"]["code"], "rate_limited");
+    assert_eq!(body["error"]["retryable"], true);
+    assert!(retry_after.is_some());
+
+    // Overrides, including one lifting the admin's own limit
+    let limit = |requests: u32, window_seconds: u64| {
+        json!({ "requests": requests, "window_seconds": window_seconds }).to_string()
+    };
+    for (path, body) in [
+        ("/api/v1/admin/limits/ops", limit(100, 60)),
+        ("/api/v1/admin/limits/partner", limit(5, 60)),
+    ] {
+        let (status, _, _, _, _) = send(Method::PUT, path, "admin-token", body).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (status, _, _, _, _) = send(
+        Method::PUT,
+        "/api/v1/admin/limits/partner",
+        "admin-token",
+        limit(5, 0),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, body, limit, _, _) = send(
+        Method::GET,
+        "/api/v1/admin/limits",
+        "admin-token",
+        String::new(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(limit.as_deref(), Some("100"));
+    assert_eq!(
+        body["default"],
+        json!({ "requests": 2, "window_seconds": 60 })
+    );
+    assert_eq!(
+        body["overrides"]["partner"],
+        json!({ "requests": 5, "window_seconds": 60 })
+    );
+
+    for _ in 0..3 {
+        let (status, _, limit, _, _) = list("partner-token").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(limit.as_deref(), Some("5"));
+    }
+
+    // Without its override, the partner is over the default limit again
+    let (status, _, _, _, _) = send(
+        Method::DELETE,
+        "/api/v1/admin/limits/partner",
+        "admin-token",
+        String::new(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _, _, _, _) = send(
+        Method::DELETE,
+        "/api/v1/admin/limits/partner",
+        "admin-token",
+        String::new(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _, _, _, _) = list("partner-token").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn test_api_key_usage() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::contract::Contract;
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let app = rust_api::router(AppState::with_config(Config {
+        api_tokens: [
+            "partner:partner-token=users:read".parse().unwrap(),
+            "other:other-token=users:read".parse().unwrap(),
+            "ops:admin-token=admin".parse().unwrap(),
+        ]
+        .into_iter()
+        .collect(),
+        ..Config::default()
+    }));
+    let get = |path: String, token: &'static str| {
+        let (app, contract) = (app.clone(), contract.clone());
+        async move {
+            let request = Request::builder()
+                .uri(&path)
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let (status, body) = contract
+                .check_response(&Method::GET, &path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err));
+            let body = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+            (status, body)
+        }
+    };
+
+    get("/api/v1/users".to_string(), "partner-token").await;
+    get("/api/v1/users".to_string(), "partner-token").await;
+    let missing = format!("/api/v1/users/{}", uuid::Uuid::new_v4());
+    let (status, _) = get(missing, "partner-token").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    get("/api/v1/users".to_string(), "other-token").await;
+
+    let usage = "/api/v1/api-keys/partner/usage".to_string();
+    let (status, body) = get(usage.clone(), "partner-token").await;
+    assert_eq!(status, StatusCode::OK);
+    let report = &body["data"];
+    assert_eq!(report["key"], "partner");
+    assert_eq!(report["requests"], 3);
+    assert_eq!(report["client_errors"], 1);
+    assert_eq!(report["top_endpoints"][0]["route"], "GET /api/v1/users");
+    assert_eq!(report["top_endpoints"][0]["requests"], 2);
+    assert_eq!(report["top_endpoints"][1]["route"], "GET /api/v1/users/:id");
+
+    // Other callers' keys are only visible to admins
+    let (status, body) = get(usage.clone(), "other-token").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"]["code"], "forbidden");
+    let (status, body) = get(usage, "admin-token").await;
+    assert_eq!(status, StatusCode::OK);
+    // The report itself was counted
+    assert_eq!(body["data"]["requests"], 4);
+}
+
+#[tokio::test]
+async fn test_cors_follows_the_profile() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let load = |vars: &'static [(&'static str, &'static str)]| {
+        Config::from_lookup(|key| {
+            vars.iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value.to_string())
+        })
+        .unwrap()
+    };
+    let preflight = |config: Config, origin: &'static str| async move {
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/v1/users")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap();
+        let response = rust_api::router(AppState::with_config(config))
+            .oneshot(request)
+            .await
+            .unwrap();
+        response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|value| value.to_str().unwrap().to_string())
+    };
+
+    let dev = load(&[]);
+    assert_eq!(
+        preflight(dev, "http://localhost:5173").await.as_deref(),
+        Some("*")
+    );
+    let prod = load(&[("APP_ENV", "prod")]);
+    assert_eq!(preflight(prod, "http://localhost:5173").await, None);
+
+    let prod = || {
+        load(&[
+            ("APP_ENV", "prod"),
+            ("APP_CORS_ORIGINS", "https://admin.example.com"),
+        ])
+    };
+    assert_eq!(
+        preflight(prod(), "https://admin.example.com")
+            .await
+            .as_deref(),
+        Some("https://admin.example.com")
+    );
+    assert_eq!(preflight(prod(), "https://evil.example.com").await, None);
+}
+
+#[tokio::test]
+async fn test_signed_requests_verified() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request, Uri},
+    };
+    use rust_api::auth::signing;
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let state = AppState::with_config(Config {
+        signing_keys: ["billing:s3cret=users:read+users:write".parse().unwrap()]
+            .into_iter()
+            .collect(),
+        ..Config::default()
+    });
+    let app = rust_api::router(state);
+    let send = |method: Method, nonce: &'static str, secret: &'static str, body: &'static str| {
+        let app = app.clone();
+        async move {
+            let uri = Uri::from_static("/api/v1/users");
+            let timestamp = chrono::Utc::now().timestamp();
+            let canonical =
+                signing::canonical_string(timestamp, nonce, &method, &uri, body.as_bytes());
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(signing::X_KEY_ID, "billing")
+                .header(signing::X_TIMESTAMP, timestamp)
+                .header(signing::X_NONCE, nonce)
+                .header(signing::X_SIGNATURE, signing::sign(secret, &canonical))
+                .body(Body::from(body))
+                .unwrap();
+            app.oneshot(request).await.unwrap().status()
+        }
+    };
+
+    let payload = r#"{"name": "Signed", "email": "signed@example.com"}"#;
+    assert_eq!(
+        send(Method::POST, "n-1", "s3cret", payload).await,
+        StatusCode::CREATED
+    );
+    // The same nonce cannot be used twice
+    assert_eq!(
+        send(Method::POST, "n-1", "s3cret", payload).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        send(Method::GET, "n-2", "guess", "").await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(send(Method::GET, "n-3", "s3cret", "").await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_client_certificate_principals() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::auth::certificate::ClientCertificate;
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let state = AppState::with_config(Config {
+        client_cert_principals: ["billing:billing.internal=users:read".parse().unwrap()]
+            .into_iter()
+            .collect(),
+        ..Config::default()
+    });
+    let app = rust_api::router(state);
+    let send = |method: Method, common_name: Option<&'static str>| {
+        let app = app.clone();
+        async move {
+            let mut request = Request::builder()
+                .method(method)
+                .uri("/api/v1/users")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    json!({ "name": "Cert Test", "email": "cert@example.com" }).to_string(),
+                ))
+                .unwrap();
+            // Set by the TLS listener for verified client certificates
+            if let Some(common_name) = common_name {
+                request.extensions_mut().insert(ClientCertificate {
+                    common_name: common_name.to_string(),
+                });
+            }
+            app.oneshot(request).await.unwrap().status()
+        }
+    };
+
+    assert_eq!(
+        send(Method::GET, Some("billing.internal")).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        send(Method::POST, Some("billing.internal")).await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        send(Method::GET, Some("unknown.internal")).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(send(Method::GET, None).await, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_ip_allow_and_deny_lists() {
+    use axum::{
+        body::Body,
+        extract::connect_info::MockConnectInfo,
+        http::{header, Request},
+    };
+    use rust_api::ip_filter::Settings;
+    use rust_api::Config;
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    let parse = |ranges: &[&str]| ranges.iter().map(|range| range.parse().unwrap()).collect();
+    let state = AppState::with_config(Config {
+        ip_filter: Settings {
+            allow: parse(&["203.0.113.0/24"]),
+            deny: parse(&["203.0.113.66"]),
+        },
+        trusted_proxies: parse(&["10.0.0.1"]).into_iter().collect(),
+        ..Config::default()
+    });
+    let metrics = state.metrics.clone();
+    let app = rust_api::router(state);
+    let send = |peer: &'static str, forwarded: Option<(&'static str, &'static str)>| {
+        let app = app
+            .clone()
+            .layer(MockConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        async move {
+            let mut request = Request::get("/api/v1/users").header(header::ACCEPT_LANGUAGE, "de");
+            if let Some((name, value)) = forwarded {
+                request = request.header(name, value);
+            }
+            let response = app
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let language = response
+                .headers()
+                .get(header::CONTENT_LANGUAGE)
+                .map(|value| value.to_str().unwrap().to_string());
+            (response.status(), language)
+        }
+    };
+
+    let (status, _) = send("203.0.113.5:4000", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, language) = send("198.51.100.1:4000", None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(language.as_deref(), Some("de"));
+
+    let (status, _) = send("203.0.113.66:4000", None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Forwarded addresses count only behind a trusted proxy
+    let forwarded_for = |ip| Some(("x-forwarded-for", ip));
+    let (status, _) = send("10.0.0.1:4000", forwarded_for("203.0.113.5")).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send("10.0.0.1:4000", forwarded_for("203.0.113.66")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = send("198.51.100.1:4000", forwarded_for("203.0.113.5")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = send("10.0.0.1:4000", Some(("forwarded", "for=203.0.113.5:443"))).await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert_eq!(metrics.ip_denied(), 4);
+}
+
+#[tokio::test]
+async fn test_audit_log_records_changes_with_location() {
+    use axum::{
+        body::Body,
+        extract::connect_info::MockConnectInfo,
+        http::{header, Method, Request},
+    };
+    use rust_api::contract::Contract;
+    use rust_api::geoip::GeoIp;
+    use rust_api::models::Location;
+    use rust_api::Config;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let state = AppState {
+        geoip: Arc::new(GeoIp::Table(vec![(
+            "203.0.113.0/24".parse().unwrap(),
+            Location {
+                country: Some("DE".to_string()),
+                city: Some("Berlin".to_string()),
+            },
+        )])),
+        ..AppState::with_config(Config {
+            admin_endpoints: true,
+            api_tokens: ["ops:admin-token=admin".parse().unwrap()]
+                .into_iter()
+                .collect(),
+            ..Config::default()
+        })
+    };
+    let app = rust_api::router(state).layer(MockConnectInfo(
+        "203.0.113.5:4000".parse::<SocketAddr>().unwrap(),
+    ));
+    let send = |method: Method, path: &'static str, body: String| {
+        let app = app.clone();
+        let contract = contract.clone();
+        async move {
+            let request = Request::builder()
+                .method(method.clone())
+                .uri(path)
+                .header(header::AUTHORIZATION, "Bearer admin-token")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            contract
+                .check_response(&method, path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err))
+        }
+    };
+
+    let payload = json!({ "name": "Audited", "email": "audited@example.com" }).to_string();
+    let (status, _) = send(Method::POST, "/api/v1/users", payload).await;
+    assert_eq!(status, StatusCode::CREATED);
+    // Reads and failed requests are not recorded
+    send(Method::GET, "/api/v1/users", String::new()).await;
+    let invalid = json!({ "name": "Audited", "email": "not-an-email" }).to_string();
+    let (status, _) = send(Method::POST, "/api/v1/users", invalid).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, body) = send(Method::GET, "/api/v1/admin/audit", String::new()).await;
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["meta"]["pagination"]["count"], 1);
+    let entry = &body["data"][0];
+    assert_eq!(entry["principal"], "ops");
+    assert_eq!(entry["method"], "POST");
+    assert_eq!(entry["path"], "/api/v1/users");
+    assert_eq!(entry["status"], 201);
+    assert_eq!(entry["client_ip"], "203.0.113.5");
+    assert_eq!(entry["location"]["country"], "DE");
+    assert_eq!(entry["location"]["city"], "Berlin");
+
+    let (_, body) = send(Method::GET, "/api/v1/admin/audit?country=fr", String::new()).await;
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["meta"]["pagination"]["count"], 0);
+}
+
+#[tokio::test]
+async fn test_admin_impersonation_is_audited() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::contract::Contract;
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let state = AppState::with_config(Config {
+        admin_endpoints: true,
+        api_tokens: ["ops:admin-token=admin".parse().unwrap()]
+            .into_iter()
+            .collect(),
+        ..Config::default()
+    });
+    let app = rust_api::router(state);
+    let send = |method: Method, path: String, token: String, body: serde_json::Value| {
+        let app = app.clone();
+        let contract = contract.clone();
+        async move {
+            let request = Request::builder()
+                .method(method.clone())
+                .uri(&path)
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let (status, body) = contract
+                .check_response(&method, &path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err));
+            let body = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+            (status, body)
+        }
+    };
+    let admin = || "admin-token".to_string();
+
+    let (_, body) = send(
+        Method::POST,
+        "/api/v1/users".to_string(),
+        admin(),
+        json!({ "name": "Impersonated", "email": "impersonated@example.com" }),
+    )
+    .await;
+    let user_id = body["data"]["id"].as_str().unwrap().to_string();
+
+    let (status, _) = send(
+        Method::POST,
+        format!("/api/v1/admin/impersonate/{}", uuid::Uuid::new_v4()),
+        admin(),
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, body) = send(
+        Method::POST,
+        format!("/api/v1/admin/impersonate/{}", user_id),
+        admin(),
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["impersonator"], "ops");
+    let token = body["token"].as_str().unwrap().to_string();
+
+    let (status, _) = send(
+        Method::PUT,
+        format!("/api/v1/users/{}", user_id),
+        token.clone(),
+        json!({ "name": "Renamed" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Impersonation never grants admin access
+    let (status, _) = send(
+        Method::GET,
+        "/api/v1/admin/audit".to_string(),
+        token,
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (_, body) = send(
+        Method::GET,
+        "/api/v1/admin/audit".to_string(),
+        admin(),
+        serde_json::Value::Null,
+    )
+    .await;
+    let update = &body["data"][0];
+    assert_eq!(update["method"], "PUT");
+    assert_eq!(update["principal"], user_id.as_str());
+    assert_eq!(update["impersonator"], "ops");
+    let issue = &body["data"][1];
+    assert_eq!(issue["principal"], "ops");
+    assert_eq!(issue["impersonator"], serde_json::Value::Null);
+}
+
+/// An app with an admin token, and a token acting as the first user created
+fn user_principal_app() -> axum::Router {
+    use rust_api::mock::IdSource;
+    use rust_api::Config;
+
+    let state = AppState::with_config(Config {
+        admin_endpoints: true,
+        api_tokens: [
+            "ops:admin-token=admin".parse().unwrap(),
+            "00000000-0000-0000-0000-000000000001:user-token=users:read+users:write"
+                .parse()
+                .unwrap(),
+        ]
+        .into_iter()
+        .collect(),
+        ..Config::default()
+    });
+    rust_api::router(AppState {
+        ids: std::sync::Arc::new(IdSource::sequential()),
+        ..state
+    })
+}
+
+/// Sends a request with a bearer token, checking the response against the
+/// OpenAPI document
+async fn send_as(
+    app: &axum::Router,
+    method: axum::http::Method,
+    path: String,
+    token: &str,
+    body: serde_json::Value,
+) -> (StatusCode, serde_json::Value) {
+    use axum::{body::Body, http::header, http::Request};
+    use tower::ServiceExt;
+
+    let request = Request::builder()
+        .method(method.clone())
+        .uri(&path)
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let (status, body) = rust_api::contract::Contract::new()
+        .check_response(&method, &path, response)
+        .await
+        .unwrap_or_else(|err| panic!("{}", err));
+    let body = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+    (status, body)
+}
+
+#[tokio::test]
+async fn test_suspended_users_cannot_authenticate() {
+    use axum::http::Method;
+
+    let app = user_principal_app();
+    let (status, body) = send_as(
+        &app,
+        Method::POST,
+        "/api/v1/users".to_string(),
+        "admin-token",
+        json!({ "name": "Token Holder", "email": "holder@example.com" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let id = body["data"]["id"].as_str().unwrap().to_string();
+    let path = format!("/api/v1/users/{}", id);
+
+    let (status, body) = send_as(
+        &app,
+        Method::POST,
+        format!("/api/v1/admin/impersonate/{}", id),
+        "admin-token",
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let session = body["token"].as_str().unwrap().to_string();
+    for token in ["user-token", session.as_str()] {
+        let (status, _) = send_as(&app, Method::GET, path.clone(), token, json!(null)).await;
+        assert_eq!(status, StatusCode::OK, "{}", token);
+    }
+
+    let (status, _) = send_as(
+        &app,
+        Method::POST,
+        format!("{}/suspend", path),
+        "admin-token",
+        json!(null),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    for token in ["user-token", session.as_str()] {
+        let (status, body) = send_as(&app, Method::GET, path.clone(), token, json!(null)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}", token);
+        assert_eq!(
+            body["error"]["message"],
+            "The account is suspended and may not log in"
+        );
+    }
+    // Other principals are not affected
+    let (status, _) = send_as(&app, Method::GET, path.clone(), "admin-token", json!(null)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send_as(
+        &app,
+        Method::POST,
+        format!("{}/activate", path),
+        "admin-token",
+        json!(null),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send_as(&app, Method::GET, path, "user-token", json!(null)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_authenticated_requests_record_activity() {
+    use axum::http::Method;
+    use chrono::{Duration, TimeZone, Utc};
+    use rust_api::mock::{Clock, IdSource};
+    use rust_api::Config;
+
+    let state = AppState {
+        ids: std::sync::Arc::new(IdSource::sequential()),
+        ..AppState::with_config(Config {
+            api_tokens: [
+                "ops:admin-token=admin".parse().unwrap(),
+                "00000000-0000-0000-0000-000000000001:user-token=users:read"
+                    .parse()
+                    .unwrap(),
+            ]
+            .into_iter()
+            .collect(),
+            ..Config::default()
+        })
+    };
+    let start = Utc.with_ymd_and_hms(2024, 1, 1, 9, 0, 0).unwrap();
+    let at = |minutes| {
+        rust_api::router(AppState {
+            clock: Clock::Fixed(start + Duration::minutes(minutes)),
+            ..state.clone()
+        })
+    };
+    let (_, body) = send_as(
+        &at(0),
+        Method::POST,
+        "/api/v1/users".to_string(),
+        "admin-token",
+        json!({ "name": "Active User", "email": "active@example.com" }),
+    )
+    .await;
+    let id: uuid::Uuid = body["data"]["id"].as_str().unwrap().parse().unwrap();
+    let path = format!("/api/v1/users/{}", id);
+    let activity = || async {
+        let user = state.storage.read().await.get(&id).unwrap();
+        (user.last_login_at, user.last_seen_at)
+    };
+    // Requests of other principals are not the user's
+    assert_eq!(activity().await, (None, None));
+
+    // Seen again only once the last record is 5 minutes old
+    for (minutes, login, seen) in [(1, 1, 1), (3, 1, 1), (20, 1, 20), (45, 1, 45), (76, 76, 76)] {
+        let (status, _) = send_as(
+            &at(minutes),
+            Method::GET,
+            path.clone(),
+            "user-token",
+            json!(null),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            activity().await,
+            (
+                Some(start + Duration::minutes(login)),
+                Some(start + Duration::minutes(seen))
+            ),
+            "after {} minutes",
+            minutes
+        );
+    }
+
+    // Requests refused for their scope are not the user's activity
+    let (status, _) = send_as(
+        &at(90),
+        Method::POST,
+        "/api/v1/users".to_string(),
+        "user-token",
+        json!({ "name": "Refused User", "email": "refused@example.com" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let last = Some(start + Duration::minutes(76));
+    assert_eq!(activity().await, (last, last));
+}
+
+#[tokio::test]
+async fn test_user_principal_revalidates_users_list() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use chrono::{Duration, TimeZone, Utc};
+    use rust_api::mock::{Clock, IdSource};
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let state = AppState {
+        ids: std::sync::Arc::new(IdSource::sequential()),
+        ..AppState::with_config(Config {
+            api_tokens: [
+                "ops:admin-token=admin".parse().unwrap(),
+                "00000000-0000-0000-0000-000000000001:user-token=users:read"
+                    .parse()
+                    .unwrap(),
+            ]
+            .into_iter()
+            .collect(),
+            ..Config::default()
+        })
+    };
+    let start = Utc.with_ymd_and_hms(2024, 1, 1, 9, 0, 0).unwrap();
+    let at = |minutes| {
+        rust_api::router(AppState {
+            clock: Clock::Fixed(start + Duration::minutes(minutes)),
+            ..state.clone()
+        })
+    };
+    send_as(
+        &at(0),
+        Method::POST,
+        "/api/v1/users".to_string(),
+        "admin-token",
+        json!({ "name": "Revalidating User", "email": "revalidating@example.com" }),
+    )
+    .await;
+    let list = |minutes, etag: Option<&str>| {
+        let mut request =
+            Request::get("/api/v1/users").header(header::AUTHORIZATION, "Bearer user-token");
+        if let Some(etag) = etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        at(minutes).oneshot(request.body(Body::empty()).unwrap())
+    };
+
+    let response = list(1, None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers()[header::ETAG]
+        .to_str()
+        .unwrap()
+        .to_string();
+    for minutes in [1, 2, 5] {
+        let response = list(minutes, Some(&etag)).await.unwrap();
+        assert_eq!(
+            response.status(),
+            StatusCode::NOT_MODIFIED,
+            "after {} minutes",
+            minutes
+        );
+    }
+    // Seen again, which changes the list
+    let response = list(6, Some(&etag)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_impersonating_suspended_user_conflicts() {
+    use axum::http::Method;
+
+    let app = user_principal_app();
+    let (_, body) = send_as(
+        &app,
+        Method::POST,
+        "/api/v1/users".to_string(),
+        "admin-token",
+        json!({ "name": "Suspended User", "email": "suspended@example.com" }),
+    )
+    .await;
+    let id = body["data"]["id"].as_str().unwrap().to_string();
+    let (status, _) = send_as(
+        &app,
+        Method::POST,
+        format!("/api/v1/users/{}/suspend", id),
+        "admin-token",
+        json!(null),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = send_as(
+        &app,
+        Method::POST,
+        format!("/api/v1/admin/impersonate/{}", id),
+        "admin-token",
+        json!(null),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(
+        body["error"]["message"],
+        format!("User {} is suspended and may not log in", id)
+    );
+}
+
+#[tokio::test]
+async fn test_storage_capacity_limits() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::contract::Contract;
+    use rust_api::models::{Capacity, Eviction};
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let send = |app: axum::Router, method: Method, path: String, body: serde_json::Value| {
+        let contract = contract.clone();
+        async move {
+            let request = Request::builder()
+                .method(method.clone())
+                .uri(&path)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let (status, body) = contract
+                .check_response(&method, &path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err));
+            let body = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+            (status, body)
+        }
+    };
+    let app = |eviction| {
+        rust_api::router(AppState::with_config(Config {
+            storage_capacity: Capacity {
+                max_users: Some(2),
+                eviction,
+            },
+            ..Config::default()
+        }))
+    };
+    let user = |n: usize| {
+        let name = ["Ada", "Grace", "Edsger"][n - 1];
+        json!({ "name": name, "email": format!("{}@example.com", name.to_lowercase()) })
+    };
+
+    // A full store refuses new users
+    let rejecting = app(Eviction::Reject);
+    for n in 1..=2 {
+        let (status, _) = send(
+            rejecting.clone(),
+            Method::POST,
+            "/api/v1/users".to_string(),
+            user(n),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+    let (status, body) = send(
+        rejecting,
+        Method::POST,
+        "/api/v1/users".to_string(),
+        user(3),
+    )
+    .await;
+    assert_eq!(status, StatusCode::INSUFFICIENT_STORAGE);
+    assert_eq!(body["error"]["message"], "The user store is full (2 users)");
+
+    // In LRU mode the least recently used user makes room
+    let evicting = app(Eviction::Lru);
+    let mut ids = Vec::new();
+    for n in 1..=2 {
+        let (_, body) = send(
+            evicting.clone(),
+            Method::POST,
+            "/api/v1/users".to_string(),
+            user(n),
+        )
+        .await;
+        ids.push(body["data"]["id"].as_str().unwrap().to_string());
+    }
+    let (status, _) = send(
+        evicting.clone(),
+        Method::GET,
+        format!("/api/v1/users/{}", ids[0]),
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(
+        evicting.clone(),
+        Method::POST,
+        "/api/v1/users".to_string(),
+        user(3),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    for (id, expected) in [(&ids[0], StatusCode::OK), (&ids[1], StatusCode::NOT_FOUND)] {
+        let (status, _) = send(
+            evicting.clone(),
+            Method::GET,
+            format!("/api/v1/users/{}", id),
+            serde_json::Value::Null,
+        )
+        .await;
+        assert_eq!(status, expected);
+    }
+}
+
+#[tokio::test]
+async fn test_storage_usage_reported() {
+    use axum::{
+        body::Body,
+        http::{Method, Request},
+    };
+    use rust_api::contract::Contract;
+    use rust_api::models::Capacity;
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let state = AppState::with_config(Config {
+        storage_capacity: Capacity {
+            max_users: Some(2),
+            ..Capacity::default()
+        },
+        ..Config::default()
+    });
+    for (name, email) in [("Ada", "ada@example.com"), ("Grace", "grace@example.com")] {
+        let _ = handlers::create_user(
+            axum::extract::State(state.clone()),
+            axum::Json(serde_json::from_value(json!({ "name": name, "email": email })).unwrap()),
+        )
+        .await
+        .unwrap();
+    }
+    let app = rust_api::router(state);
+
+    let request = Request::get("/health/deep").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let (status, body) = Contract::new()
+        .check_response(&Method::GET, "/health/deep", response)
+        .await
+        .unwrap_or_else(|err| panic!("{}", err));
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["storage"]["users"], 2);
+    assert_eq!(body["storage"]["max_users"], 2);
+    assert!(body["storage"]["serialized_bytes"].as_u64().unwrap() > 0);
+
+    let request = Request::get("/metrics").body(Body::empty()).unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(text.contains("storage_users 2\n"));
+    assert!(text.contains("storage_max_users 2\n"));
+}
+
+#[tokio::test]
+async fn test_consistency_tokens() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::consistency::X_CONSISTENCY_TOKEN;
+    use rust_api::contract::Contract;
+    use rust_api::Config;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let app = rust_api::router(AppState::with_config(Config {
+        cache: rust_api::cache::Settings {
+            list_ttl: Duration::from_secs(60),
+            user_ttl: Duration::from_secs(60),
+        },
+        ..Config::default()
+    }));
+    let send = |method: Method, path: &str, token: Option<&str>, body: serde_json::Value| {
+        let app = app.clone();
+        let contract = contract.clone();
+        let path = path.to_string();
+        let mut request = Request::builder()
+            .method(method.clone())
+            .uri(&path)
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(token) = token {
+            request = request.header(X_CONSISTENCY_TOKEN, token);
+        }
+        let request = request.body(Body::from(body.to_string())).unwrap();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let headers = response.headers().clone();
+            let (status, _) = contract
+                .check_response(&method, &path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err));
+            (status, headers)
+        }
+    };
+
+    let (status, headers) = send(
+        Method::POST,
+        "/api/v1/users",
+        None,
+        json!({ "name": "Ada", "email": "ada@example.com" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let token = headers[X_CONSISTENCY_TOKEN].to_str().unwrap().to_string();
+
+    let (status, headers) = send(Method::GET, "/api/v1/users", Some(&token), json!(null)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!headers.contains_key(X_CONSISTENCY_TOKEN));
+
+    // A version the storage has not reached cannot be served
+    let (epoch, version) = token.split_once('-').unwrap();
+    let ahead = format!(
+        "{}-{:x}",
+        epoch,
+        u64::from_str_radix(version, 16).unwrap() + 1
+    );
+    let (status, headers) = send(Method::GET, "/api/v1/users", Some(&ahead), json!(null)).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(headers.contains_key(header::RETRY_AFTER));
+
+    let (status, _) = send(Method::GET, "/api/v1/users", Some("soon"), json!(null)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Tokens from another instance are ignored
+    let (status, _) = send(Method::GET, "/api/v1/users", Some("1-ff"), json!(null)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_find_and_merge_duplicates() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::contract::Contract;
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let app = rust_api::router(AppState::with_config(Config {
+        api_tokens: [
+            "ops:admin-token=admin".parse().unwrap(),
+            "app:app-token=users:read+users:write".parse().unwrap(),
+        ]
+        .into_iter()
+        .collect(),
+        ..Config::default()
+    }));
+    let send = |method: Method, path: &str, token: &str, body: serde_json::Value| {
+        let app = app.clone();
+        let contract = contract.clone();
+        let path = path.to_string();
+        let request = Request::builder()
+            .method(method.clone())
+            .uri(&path)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let (status, body) = contract
+                .check_response(&method, &path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err));
+            let body = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+            (status, body)
+        }
+    };
+
+    let mut ids = Vec::new();
+    for payload in [
+        json!({ "name": "Jane Doe", "email": "jane@example.com" }),
+        json!({ "name": "Doe Jane", "email": "jane+work@example.com", "phone": "+14155552671" }),
+        json!({ "name": "John Roe", "email": "john@example.com" }),
+    ] {
+        let (status, body) = send(Method::POST, "/api/v1/users", "app-token", payload).await;
+        assert_eq!(status, StatusCode::CREATED);
+        ids.push(body["data"]["id"].as_str().unwrap().to_string());
+    }
+
+    let (status, _) = send(
+        Method::GET,
+        "/api/v1/users/duplicates",
+        "app-token",
+        json!(null),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, body) = send(
+        Method::GET,
+        "/api/v1/users/duplicates",
+        "admin-token",
+        json!(null),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["meta"]["pagination"]["count"], 2);
+    assert_eq!(body["data"][0]["strategy"], "email");
+    assert_eq!(body["data"][0]["users"][0]["id"], ids[0].as_str());
+    assert_eq!(body["data"][1]["key"], "doe jane");
+
+    let (_, body) = send(
+        Method::GET,
+        "/api/v1/users/duplicates?strategy=name",
+        "admin-token",
+        json!(null),
+    )
+    .await;
+    assert_eq!(body["meta"]["pagination"]["count"], 1);
+
+    let merge = |keep: &str, remove: &str| json!({ "keep": keep, "remove": remove });
+    let (status, _) = send(
+        Method::POST,
+        "/api/v1/users/merge",
+        "admin-token",
+        merge(&ids[0], &ids[0]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, body) = send(
+        Method::POST,
+        "/api/v1/users/merge",
+        "admin-token",
+        merge(&ids[0], &ids[1]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["email"], "jane@example.com");
+    assert_eq!(body["data"]["phone"], "+14155552671");
+
+    let (status, _) = send(
+        Method::GET,
+        &format!("/api/v1/users/{}", ids[1]),
+        "admin-token",
+        json!(null),
+    )
+    .await;
+    assert_eq!(status, StatusCode::GONE);
+
+    let (status, _) = send(
+        Method::POST,
+        "/api/v1/users/merge",
+        "admin-token",
+        merge(&ids[0], &ids[1]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (_, body) = send(
+        Method::GET,
+        "/api/v1/users/duplicates",
+        "admin-token",
+        json!(null),
+    )
+    .await;
+    assert_eq!(body["meta"]["pagination"]["count"], 0);
+}
+
+#[tokio::test]
+async fn test_merge_by_path_redirects_removed_user() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::contract::Contract;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let app = rust_api::router(create_test_state());
+    let send = |method: Method, path: String, body: serde_json::Value| {
+        let app = app.clone();
+        let contract = contract.clone();
+        let request = Request::builder()
+            .method(method.clone())
+            .uri(&path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let headers = response.headers().clone();
+            let (status, body) = contract
+                .check_response(&method, &path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err));
+            let body = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+            (status, headers, body)
+        }
+    };
+
+    let mut ids = Vec::new();
+    for payload in [
+        json!({ "name": "Jane Doe", "email": "jane@example.com", "locale": "en-US" }),
+        json!({ "name": "Jane D", "email": "jane.doe@example.com", "locale": "de-DE" }),
+    ] {
+        let (_, _, body) = send(Method::POST, "/api/v1/users".to_string(), payload).await;
+        ids.push(body["data"]["id"].as_str().unwrap().to_string());
+    }
+
+    let (status, _, _) = send(
+        Method::POST,
+        format!("/api/v1/users/{}/merge/not-a-uuid", ids[0]),
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _, body) = send(
+        Method::POST,
+        format!("/api/v1/users/{}/merge/{}", ids[0], ids[1]),
+        json!({ "email": "remove", "locale": "remove" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["id"], ids[0].as_str());
+    assert_eq!(body["data"]["name"], "Jane Doe");
+    assert_eq!(body["data"]["email"], "jane.doe@example.com");
+    assert_eq!(body["data"]["locale"], "de-DE");
+
+    let (status, headers, body) = send(
+        Method::GET,
+        format!("/api/v1/users/{}", ids[1]),
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::GONE);
+    assert_eq!(
+        headers[header::LOCATION],
+        format!("/api/v1/users/{}", ids[0]).as_str()
+    );
+    assert!(body["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains(ids[0].as_str()));
+
+    // Deleted users are gone too, without a successor
+    let (status, _, _) = send(
+        Method::DELETE,
+        format!("/api/v1/users/{}", ids[0]),
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, headers, _) = send(
+        Method::GET,
+        format!("/api/v1/users/{}", ids[0]),
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::GONE);
+    assert!(!headers.contains_key(header::LOCATION));
+    let (status, _, _) = send(
+        Method::GET,
+        format!("/api/v1/users/{}", uuid::Uuid::new_v4()),
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_undo_delete() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::{contract::Contract, Config};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let app = rust_api::router(AppState::with_config(Config {
+        undo_window: Some(Duration::from_secs(60)),
+        ..Config::default()
+    }));
+    let send = |method: Method, path: String, body: serde_json::Value| {
+        let app = app.clone();
+        let contract = contract.clone();
+        let request = Request::builder()
+            .method(method.clone())
+            .uri(&path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let headers = response.headers().clone();
+            let (status, body) = contract
+                .check_response(&method, &path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err));
+            let body = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+            (status, headers, body)
+        }
+    };
+
+    let (_, _, body) = send(
+        Method::POST,
+        "/api/v1/users".to_string(),
+        json!({ "name": "Ada Lovelace", "email": "ada@example.com" }),
+    )
+    .await;
+    let user = format!("/api/v1/users/{}", body["data"]["id"].as_str().unwrap());
+
+    let (status, headers, _) = send(Method::DELETE, user.clone(), json!(null)).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let token = headers["x-undo-token"].to_str().unwrap().to_string();
+    let (status, _, _) = send(Method::GET, user.clone(), json!(null)).await;
+    assert_eq!(status, StatusCode::GONE);
+
+    // Restoring is refused while the email address is taken again
+    let (_, _, body) = send(
+        Method::POST,
+        "/api/v1/users".to_string(),
+        json!({ "name": "Ada King", "email": "ada@example.com" }),
+    )
+    .await;
+    let other = format!("/api/v1/users/{}", body["data"]["id"].as_str().unwrap());
+    let undo = format!("/api/v1/undo/{}", token);
+    let (status, _, _) = send(Method::POST, undo.clone(), json!(null)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, _, _) = send(Method::DELETE, other, json!(null)).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _, body) = send(Method::POST, undo.clone(), json!(null)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["name"], "Ada Lovelace");
+    let (status, _, _) = send(Method::GET, user, json!(null)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Tokens are spent by a successful undo
+    let (status, _, _) = send(Method::POST, undo, json!(null)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Without an undo window, deletions carry no token
+    let app = rust_api::router(create_test_state());
+    let request = Request::post("/api/v1/users")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({ "name": "Grace Hopper", "email": "grace@example.com" }).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let request = Request::delete(format!(
+        "/api/v1/users/{}",
+        body["data"]["id"].as_str().unwrap()
+    ))
+    .body(Body::empty())
+    .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(!response.headers().contains_key("x-undo-token"));
+}
+
+#[tokio::test]
+async fn test_dry_runs_preview_writes() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::{contract::Contract, Config};
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let state = AppState::with_config(Config {
+        admin_endpoints: true,
+        ..Config::default()
+    });
+    let app = rust_api::router(state.clone());
+    let send =
+        |method: Method, path: String, prefer: Option<&'static str>, body: serde_json::Value| {
+            let app = app.clone();
+            let contract = contract.clone();
+            let mut request = Request::builder()
+                .method(method.clone())
+                .uri(&path)
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(prefer) = prefer {
+                request = request.header("prefer", prefer);
+            }
+            let request = request.body(Body::from(body.to_string())).unwrap();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let applied = response.headers().get("preference-applied").cloned();
+                let (status, body) = contract
+                    .check_response(&method, &path, response)
+                    .await
+                    .unwrap_or_else(|err| panic!("{}", err));
+                let body = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+                (status, applied, body)
+            }
+        };
+    let ada = json!({ "name": "Ada Lovelace", "email": "ada@example.com" });
+
+    // A previewed user is validated but not stored
+    let (status, applied, body) = send(
+        Method::POST,
+        "/api/v1/users?dry_run=true".to_string(),
+        None,
+        ada.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(applied.unwrap(), "dry-run");
+    assert_eq!(body["data"]["name"], "Ada Lovelace");
+    assert_eq!(body["data"]["id"], uuid::Uuid::nil().to_string());
+    assert!(state.storage.read().await.is_empty());
+    let invalid = json!({ "name": "Ada", "email": "not-an-email" });
+    let (status, applied, _) = send(
+        Method::POST,
+        "/api/v1/users".to_string(),
+        Some("dry-run"),
+        invalid,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(applied.is_none());
+
+    let (_, _, body) = send(Method::POST, "/api/v1/users".to_string(), None, ada.clone()).await;
+    let user = format!("/api/v1/users/{}", body["data"]["id"].as_str().unwrap());
+
+    // Conflicts are reported as they would be
+    let (status, _, _) = send(
+        Method::POST,
+        "/api/v1/users".to_string(),
+        Some("return=minimal, dry-run"),
+        ada,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, applied, body) = send(
+        Method::PUT,
+        format!("{}?dry_run=true", user),
+        None,
+        json!({ "name": "Ada King" }),
+    )
+    .await;
+    assert_eq!((status, applied.is_some()), (StatusCode::OK, true));
+    assert_eq!(body["data"]["name"], "Ada King");
+    let (status, _, _) = send(
+        Method::POST,
+        format!("{}/deactivate", user),
+        Some("dry-run"),
+        json!(null),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, applied, _) =
+        send(Method::DELETE, user.clone(), Some("dry-run"), json!(null)).await;
+    assert_eq!((status, applied.is_some()), (StatusCode::NO_CONTENT, true));
+
+    let (status, _, body) = send(Method::GET, user, None, json!(null)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["name"], "Ada Lovelace");
+    assert_eq!(body["data"]["status"], "active");
+
+    // Endpoints that cannot preview their effects refuse dry runs
+    let (status, _, _) = send(
+        Method::PUT,
+        "/api/v1/admin/log-level?dry_run=true".to_string(),
+        None,
+        json!({ "filter": "debug" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _, _) = send(
+        Method::POST,
+        "/api/v1/users?dry_run=maybe".to_string(),
+        None,
+        json!(null),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Only the real write is audited
+    let (_, _, body) = send(
+        Method::GET,
+        "/api/v1/admin/audit".to_string(),
+        None,
+        json!(null),
+    )
+    .await;
+    assert_eq!(body["data"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_trash_lists_and_restores_deleted_users() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::contract::Contract;
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let app = rust_api::router(AppState::with_config(Config {
+        api_tokens: [
+            "ops:admin-token=admin".parse().unwrap(),
+            "app:app-token=users:read+users:write".parse().unwrap(),
+        ]
+        .into_iter()
+        .collect(),
+        ..Config::default()
+    }));
+    let send = |method: Method, path: &str, token: &str, body: serde_json::Value| {
+        let app = app.clone();
+        let contract = contract.clone();
+        let path = path.to_string();
+        let request = Request::builder()
+            .method(method.clone())
+            .uri(&path)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        async move {
+            let response = app.oneshot(request).await.unwrap();
+            let (status, body) = contract
+                .check_response(&method, &path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err));
+            let body = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+            (status, body)
+        }
+    };
+
+    let mut ids = Vec::new();
+    for payload in [
+        json!({ "name": "Ada Lovelace", "email": "ada@example.com" }),
+        json!({ "name": "Grace Hopper", "email": "grace@example.com" }),
+        json!({ "name": "Edsger Dijkstra", "email": "edsger@example.com" }),
+    ] {
+        let (_, body) = send(Method::POST, "/api/v1/users", "app-token", payload).await;
+        let id = body["data"]["id"].as_str().unwrap().to_string();
+        let (status, _) = send(
+            Method::DELETE,
+            &format!("/api/v1/users/{}", id),
+            "app-token",
+            json!(null),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        ids.push(id);
+    }
+
+    // Listing and restoring are admin-only
+    let (status, _) = send(Method::GET, "/api/v1/users/trash", "app-token", json!(null)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, body) = send(
+        Method::GET,
+        "/api/v1/users/trash",
+        "admin-token",
+        json!(null),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["meta"]["pagination"]["count"], 3);
+    assert_eq!(body["data"][0]["deleted_by"], "app");
+    assert!(body["data"][0]["deleted_at"].is_number());
+
+    let (status, body) = send(
+        Method::POST,
+        &format!("/api/v1/users/trash/{}/restore", ids[0]),
+        "admin-token",
+        json!(null),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["name"], "Ada Lovelace");
+    let (status, _) = send(
+        Method::POST,
+        &format!("/api/v1/users/trash/{}/restore", ids[0]),
+        "admin-token",
+        json!(null),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // A bulk restore naming a user that is not in the trash restores nothing
+    let (status, _) = send(
+        Method::POST,
+        "/api/v1/users/trash/restore",
+        "admin-token",
+        json!({ "ids": [ids[1], ids[0]] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, body) = send(
+        Method::GET,
+        "/api/v1/users/trash",
+        "admin-token",
+        json!(null),
+    )
+    .await;
+    assert_eq!(body["meta"]["pagination"]["count"], 2);
+
+    let (status, body) = send(
+        Method::POST,
+        "/api/v1/users/trash/restore",
+        "admin-token",
+        json!({ "ids": [ids[1], ids[2], ids[1]] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["meta"]["pagination"]["count"], 2);
+    let (_, body) = send(
+        Method::GET,
+        "/api/v1/users/trash",
+        "admin-token",
+        json!(null),
+    )
+    .await;
+    assert_eq!(body["meta"]["pagination"]["count"], 0);
+    let (_, body) = send(Method::GET, "/api/v1/users", "app-token", json!(null)).await;
+    assert_eq!(body["meta"]["pagination"]["count"], 3);
+}
+
+#[tokio::test]
+async fn test_router_builder_composes_groups() {
+    use axum::{
+        body::Body,
+        http::{header, HeaderValue, Method, Request},
+        middleware::map_response,
+        response::Response,
+        routing::get,
+    };
+    use rust_api::routes::{self, RouteGroup, RouterBuilder};
+    use tower::ServiceExt;
+
+    async fn mark(mut response: Response) -> Response {
+        response
+            .headers_mut()
+            .insert("x-write", HeaderValue::from_static("1"));
+        response
+    }
+
+    let builder = RouterBuilder::with_defaults(create_test_state())
+        .map_group(routes::USER_WRITES, |group| group.layer(map_response(mark)))
+        .group(RouteGroup::new("status").route("/status", &[Method::GET], get(|| async { "up" })))
+        .without_group(routes::DEV)
+        .without_group(routes::DASHBOARD);
+    assert_eq!(
+        builder.group_names(),
+        vec![
+            routes::HEALTH,
+            routes::USER_READS,
+            routes::USER_WRITES,
+            routes::ADMIN,
+            "status"
+        ]
+    );
+    let app = builder.build();
+    let send = |method: Method, path: &str, body: Body| {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .unwrap();
+        app.clone().oneshot(request)
+    };
+
+    // Layers added to one group leave the others alone, even on a shared path
+    let response = send(
+        Method::POST,
+        "/api/v1/users",
+        Body::from(json!({ "name": "Ada Lovelace", "email": "ada@example.com" }).to_string()),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(response.headers()["x-write"], "1");
+    let response = send(Method::GET, "/api/v1/users", Body::empty())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key("x-write"));
+
+    let response = send(Method::GET, "/status", Body::empty()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send(Method::POST, "/api/v1/dev/generate-users", Body::empty())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_plugins_extend_behavior() {
+    use axum::{
+        body::Body,
+        extract::Request,
+        http::{header, Method},
+        routing::get,
+    };
+    use rust_api::error::ApiError;
+    use rust_api::i18n::Message;
+    use rust_api::models::{CreateUserRequest, User};
+    use rust_api::plugins::{AppBuilder, Plugin};
+    use rust_api::routes::RouteGroup;
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+
+    struct Moderation {
+        updated: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Plugin for Moderation {
+        fn name(&self) -> &str {
+            "moderation"
+        }
+
+        fn before_create_user(&self, request: &mut CreateUserRequest) -> Result<(), ApiError> {
+            if request.email.ends_with("@blocked.example") {
+                return Err(ApiError::BadRequest(Message::new("email.invalid_domain")));
+            }
+            request.name = request.name.trim_start_matches("Dr ").to_string();
+            Ok(())
+        }
+
+        fn after_update_user(&self, user: &User) {
+            self.updated.lock().unwrap().push(user.name.clone());
+        }
+
+        fn on_request(&self, request: &mut Request) -> Result<(), ApiError> {
+            if request.headers().contains_key("x-banned") {
+                return Err(ApiError::Forbidden(Message::new("auth.invalid_token")));
+            }
+            Ok(())
+        }
+
+        fn routes(&self) -> Option<RouteGroup> {
+            Some(RouteGroup::new("moderation").route(
+                "/moderation",
+                &[Method::GET],
+                get(|| async { "on" }),
+            ))
+        }
+    }
+
+    let updated = Arc::new(Mutex::new(Vec::new()));
+    let app = AppBuilder::new(create_test_state())
+        .plugin(Moderation {
+            updated: updated.clone(),
+        })
+        .build();
+    let send = |method: Method, path: String, body: serde_json::Value| {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        app.clone().oneshot(request)
+    };
+
+    let response = send(
+        Method::POST,
+        "/api/v1/users".to_string(),
+        json!({ "name": "Ada", "email": "ada@blocked.example" }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = send(
+        Method::POST,
+        "/api/v1/users".to_string(),
+        json!({ "name": "Dr Ada Lovelace", "email": "ada@example.com" }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["data"]["name"], "Ada Lovelace");
+
+    let id = body["data"]["id"].as_str().unwrap();
+    let response = send(
+        Method::PUT,
+        format!("/api/v1/users/{}", id),
+        json!({ "name": "Ada King" }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(*updated.lock().unwrap(), vec!["Ada King".to_string()]);
+
+    let response = send(Method::GET, "/moderation".to_string(), json!(null))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = Request::get("/api/v1/users")
+        .header("x-banned", "1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_readiness_aggregates_registered_checks() {
+    use axum::{body::Body, http::Method, http::Request};
+    use rust_api::contract::Contract;
+    use rust_api::health::CheckResult;
+    use tower::ServiceExt;
+
+    let state = create_test_state();
+    let app = rust_api::router(state.clone());
+    let readyz = || async {
+        let request = Request::get("/readyz").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let (status, body) = Contract::new()
+            .check_response(&Method::GET, "/readyz", response)
+            .await
+            .unwrap_or_else(|err| panic!("{}", err));
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        (status, body)
+    };
+
+    let (status, body) = readyz().await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "healthy");
+    assert_eq!(body["checks"]["storage"]["status"], "healthy");
+
+    // Degraded subsystems keep the instance ready
+    state.health.register("queue", |_| async {
+        CheckResult::degraded("1000 webhooks pending")
+    });
+    let (status, body) = readyz().await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["checks"]["queue"]["detail"], "1000 webhooks pending");
+
+    state.health.register("mailer", |_| async {
+        CheckResult::unhealthy("connection refused")
+    });
+    let (status, body) = readyz().await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["status"], "unhealthy");
+}
+
+#[tokio::test]
+async fn test_slo_report_tracks_routes() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::contract::Contract;
+    use rust_api::{slo, Config};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let app = rust_api::router(AppState::with_config(Config {
+        admin_endpoints: true,
+        slo: slo::Settings {
+            // Every request takes longer than this
+            p50: Some(Duration::from_nanos(1)),
+            ..slo::Settings::default()
+        },
+        ..Config::default()
+    }));
+    let send = |path: String| {
+        let app = app.clone();
+        let contract = contract.clone();
+        async move {
+            let request = Request::builder()
+                .uri(&path)
+                .header(header::ACCEPT, "application/json")
+                .body(Body::empty())
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            contract
+                .check_response(&Method::GET, &path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err))
+        }
+    };
+
+    send("/api/v1/users".to_string()).await;
+    send("/api/v1/users".to_string()).await;
+    let missing = format!("/api/v1/users/{}", uuid::Uuid::new_v4());
+    let (status, _) = send(missing).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let unrouted = Request::builder()
+        .uri("/api/v1/nothing")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(unrouted).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let (status, body) = send("/api/v1/admin/slo".to_string()).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["window_seconds"], 300);
+    let routes = body["routes"].as_array().unwrap();
+    // Unrouted requests are not tracked; the report itself is not yet
+    assert_eq!(routes.len(), 2);
+    assert_eq!(routes[0]["route"], "GET /api/v1/users");
+    assert_eq!(routes[0]["requests"], 2);
+    assert_eq!(routes[0]["breaches"], json!(["p50"]));
+    // Client errors do not count against the error rate
+    assert_eq!(routes[1]["route"], "GET /api/v1/users/:id");
+    assert_eq!(routes[1]["errors"], 0);
+
+    let request = Request::builder()
+        .uri("/metrics")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(text.contains("slo_breach{route=\"GET /api/v1/users\",objective=\"p50\"} 1\n"));
+}
+
+#[tokio::test]
+async fn test_chaos_header_injects_faults() {
+    use axum::{
+        body::Body,
+        http::{header, Request},
+    };
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let send = |app: axum::Router, path: &'static str, chaos: &'static str| async move {
+        let request = Request::builder()
+            .uri(path)
+            .header("x-chaos", chaos)
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap()
+    };
+    let app = rust_api::router(AppState::with_config(Config {
+        dev_endpoints: true,
+        ..Config::default()
+    }));
+
+    let response = send(app.clone(), "/api/v1/users", "storage_error_rate=1").await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+    let response = send(app.clone(), "/api/v1/users", "error_rate=1").await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    // Internal errors are redacted, pointing to the request instead
+    let request_id = response.headers()["x-request-id"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"]["message"], "An internal error occurred");
+    assert_eq!(body["error"]["request_id"], request_id);
+    let response = send(app.clone(), "/api/v1/users", "error_rate=0").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send(app.clone(), "/api/v1/users", "error_rate=often").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    // Health checks stay reliable
+    let response = send(app, "/", "error_rate=1").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Without development endpoints, the header is ignored
+    let app = rust_api::router(AppState::new());
+    let response = send(app, "/api/v1/users", "error_rate=1").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Details are shown when enabled
+    let app = rust_api::router(AppState::with_config(Config {
+        dev_endpoints: true,
+        error_details: true,
+        ..Config::default()
+    }));
+    let response = send(app, "/api/v1/users", "error_rate=1").await;
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"]["message"], "Injected fault: internal error");
+    assert_eq!(body["error"]["details"], json!([]));
+}
+
+#[tokio::test]
+async fn test_capture_records_sanitized_requests() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::capture::CapturedRequest;
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let file = std::env::temp_dir().join(format!("capture-{}.jsonl", uuid::Uuid::new_v4()));
+    let app = rust_api::router(AppState::with_config(Config {
+        admin_endpoints: true,
+        capture_file: Some(file.clone()),
+        ..Config::default()
+    }));
+    let send = |method: Method, path: &'static str, body: Option<serde_json::Value>| {
+        let app = app.clone();
+        async move {
+            let request = Request::builder()
+                .method(method)
+                .uri(path)
+                .header(header::AUTHORIZATION, "Bearer secret")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).ok(),
+            )
+        }
+    };
+
+    // Not recorded: capture is still disabled
+    send(Method::GET, "/api/v1/users", None).await;
+    let (status, body) = send(
+        Method::PUT,
+        "/api/v1/admin/capture",
+        Some(json!({"enabled": true})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.unwrap()["enabled"], true);
+
+    let user = json!({"name": "Captured User", "email": "captured@example.com"});
+    let (status, _) = send(Method::POST, "/api/v1/users", Some(user.clone())).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = send(Method::GET, "/api/v1/users?limit=5", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = send(
+        Method::PUT,
+        "/api/v1/admin/capture",
+        Some(json!({"enabled": false})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.unwrap()["captured"], 2);
+
+    let contents = std::fs::read_to_string(&file).unwrap();
+    std::fs::remove_file(&file).unwrap();
+    assert!(!contents.contains("secret"));
+    let recorded: Vec<CapturedRequest> = contents
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(recorded.len(), 2);
+    assert_eq!(
+        (recorded[0].method.as_str(), recorded[0].status),
+        ("POST", 201)
+    );
+    let body: serde_json::Value =
+        serde_json::from_str(recorded[0].body.as_deref().unwrap()).unwrap();
+    assert_eq!(body, user);
+    assert_eq!(
+        recorded[0].headers,
+        vec![("content-type".to_string(), "application/json".to_string())]
+    );
+    assert_eq!(recorded[1].path, "/api/v1/users?limit=5");
+    assert_eq!(recorded[1].body, None);
+
+    let unconfigured = rust_api::router(AppState::with_config(Config {
+        admin_endpoints: true,
+        ..Config::default()
+    }));
+    let request = Request::builder()
+        .method(Method::PUT)
+        .uri("/api/v1/admin/capture")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({"enabled": true}).to_string()))
+        .unwrap();
+    let response = unconfigured.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn test_replay_reports_status_mismatches() {
+    use rust_api::capture::{replay, CapturedRequest};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = axum::serve(listener, rust_api::router(create_test_state()));
+    tokio::spawn(async move { server.await.unwrap() });
+
+    let captured = |method: &str, path: &str, body: Option<&str>, status: u16| CapturedRequest {
+        at: chrono::Utc::now(),
+        method: method.to_string(),
+        path: path.to_string(),
+        headers: vec![("content-type".to_string(), "application/json".to_string())],
+        body: body.map(str::to_string),
+        status,
+    };
+    let missing = format!("/api/v1/users/{}", uuid::Uuid::new_v4());
+    let lines: Vec<String> = [
+        captured(
+            "POST",
+            "/api/v1/users",
+            Some(r#"{"name":"Replayed User","email":"replayed@example.com"}"#),
+            201,
+        ),
+        captured("GET", "/api/v1/users", None, 200),
+        // Answered 404 here, unlike on the recording instance
+        captured("GET", &missing, None, 200),
+    ]
+    .iter()
+    .map(|request| serde_json::to_string(request).unwrap())
+    .collect();
+    let file = std::env::temp_dir().join(format!("replay-{}.jsonl", uuid::Uuid::new_v4()));
+    std::fs::write(&file, lines.join("\n")).unwrap();
+
+    let report = replay(&file, &format!("http://{}", addr), None).await;
+    std::fs::remove_file(&file).unwrap();
+    let report = report.unwrap();
+    assert_eq!(report.replayed, 3);
+    assert_eq!(report.mismatches.len(), 1);
+    assert_eq!(report.mismatches[0].line, 3);
+    assert_eq!(report.mismatches[0].actual, Ok(404));
+    assert_eq!(
+        report.mismatches[0].to_string(),
+        format!("line 3: GET {} expected 200, got 404", missing)
+    );
+}
+
+#[tokio::test]
+async fn test_shadow_compares_reads() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::models::User;
+    use rust_api::shadow::{self, MemoryStore};
+    use tower::ServiceExt;
+
+    let state = create_test_state();
+    let store = MemoryStore::default();
+    shadow::start(&state, store.clone()).await.unwrap();
+    let app = rust_api::router(state.clone());
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/v1/users")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({"name": "Shadowed User", "email": "shadowed@example.com"}).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let user: User = serde_json::from_value(
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"].clone(),
+    )
+    .unwrap();
+
+    let get = |app: axum::Router| async move {
+        let request = Request::builder()
+            .uri(format!("/api/v1/users/{}", user.id))
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap().status()
+    };
+    assert_eq!(get(app.clone()).await, StatusCode::OK);
+    state.shadow.flush().await;
+    // The shadow drifts; clients are still answered from the primary store
+    store.put(User {
+        name: "Drifted".to_string(),
+        ..user.clone()
+    });
+    assert_eq!(get(app.clone()).await, StatusCode::OK);
+    state.shadow.flush().await;
+
+    let request = Request::builder()
+        .uri("/metrics")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(text.contains("shadow_writes_total 1\n"));
+    assert!(text.contains("shadow_comparisons_total 2\n"));
+    assert!(text.contains("shadow_mismatches_total 1\n"));
+}
+
+#[tokio::test]
+async fn test_snapshot_and_confirmed_restore() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::contract::Contract;
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let dir = std::env::temp_dir().join(format!("snapshots-{}", uuid::Uuid::new_v4()));
+    let contract = Contract::new();
+    let app = rust_api::router(AppState::with_config(Config {
+        admin_endpoints: true,
+        snapshot_dir: Some(dir.clone()),
+        ..Config::default()
+    }));
+    let send = |method: Method, path: String, body: Option<serde_json::Value>| {
+        let app = app.clone();
+        let contract = contract.clone();
+        async move {
+            let request = Request::builder()
+                .method(method.clone())
+                .uri(&path)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let (status, body) = contract
+                .check_response(&method, &path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err));
+            let body = serde_json::from_slice::<serde_json::Value>(&body).unwrap_or_default();
+            (status, body)
+        }
+    };
+
+    let (status, body) = send(
+        Method::POST,
+        "/api/v1/users".to_string(),
+        Some(json!({"name": "Backed Up", "email": "backed-up@example.com"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let user = format!("/api/v1/users/{}", body["data"]["id"].as_str().unwrap());
+
+    let (status, snapshot) = send(Method::POST, "/api/v1/admin/snapshot".to_string(), None).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(snapshot["users"], 1);
+    let name = snapshot["snapshot"].clone();
+
+    let (status, _) = send(Method::DELETE, user.clone(), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    // Without a token nothing is restored
+    let restore = "/api/v1/admin/restore".to_string();
+    let (status, plan) = send(
+        Method::POST,
+        restore.clone(),
+        Some(json!({"snapshot": name})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(plan["snapshot"], snapshot);
+    assert_eq!(plan["current_users"], 0);
+    let (status, _) = send(Method::GET, user.clone(), None).await;
+    assert_eq!(status, StatusCode::GONE);
+
+    let (status, _) = send(
+        Method::POST,
+        restore.clone(),
+        Some(json!({"snapshot": name, "confirmation_token": "restore_guess"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let confirmed = json!({"snapshot": name, "confirmation_token": plan["confirmation_token"]});
+    let (status, restored) = send(Method::POST, restore.clone(), Some(confirmed.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(restored, snapshot);
+    let (status, body) = send(Method::GET, user, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["name"], "Backed Up");
+
+    // Tokens are spent once used
+    let (status, _) = send(Method::POST, restore.clone(), Some(confirmed)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(
+        Method::POST,
+        restore.clone(),
+        Some(json!({"snapshot": "missing.json"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(
+        Method::POST,
+        restore,
+        Some(json!({"snapshot": "../secrets.json"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn test_shards_forward_requests_to_owner() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::{shard, Config};
+    use tower::ServiceExt;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let remote: shard::Peer = format!("http://{}", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let local: shard::Peer = "http://127.0.0.1:1".parse().unwrap();
+    let sharded = |this: &shard::Peer| {
+        AppState::with_config(Config {
+            shard: shard::Settings {
+                peers: vec![local.clone(), remote.clone()],
+                this: Some(this.clone()),
+                token: Some("p33r".to_string()),
+            },
+            ..Config::default()
+        })
+    };
+    let server = axum::serve(listener, rust_api::router(sharded(&remote)));
+    tokio::spawn(async move { server.await.unwrap() });
+    let state = sharded(&local);
+    let app = rust_api::router(state.clone());
+
+    let create = |name: &str, email: &str| {
+        Request::builder()
+            .method(Method::POST)
+            .uri("/api/v1/users")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                json!({"name": name, "email": email}).to_string(),
+            ))
+            .unwrap()
+    };
+
+    // Users created here get IDs owned here
+    let response = app
+        .clone()
+        .oneshot(create("Local User", "local@example.com"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let local_user: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let local_id: uuid::Uuid = local_user["data"]["id"].as_str().unwrap().parse().unwrap();
+    assert_eq!(state.shards.remote_owner(local_id), None);
+
+    // Users owned by the peer are read and changed through it
+    let client = reqwest::Client::new();
+    let remote_user: serde_json::Value = client
+        .post(format!("{}/api/v1/users", remote))
+        .json(&json!({"name": "Remote User", "email": "remote@example.com"}))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let remote_id: uuid::Uuid = remote_user["data"]["id"].as_str().unwrap().parse().unwrap();
+    assert_eq!(state.shards.remote_owner(remote_id), Some(&remote));
+
+    let request = Request::builder()
+        .uri(format!("/api/v1/users/{}", remote_id))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let fetched: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(fetched["data"], remote_user["data"]);
+
+    // Clients cannot pass their requests off as forwarded
+    for forwarded in [local.as_str(), "p33r-guess"] {
+        let request = Request::builder()
+            .uri(format!("/api/v1/users/{}", remote_id))
+            .header(shard::X_SHARD_FORWARDED, forwarded)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{}", forwarded);
+    }
+    let request = Request::builder()
+        .uri(format!("/api/v1/users/{}", remote_id))
+        .header(shard::X_SHARD_FORWARDED, "p33r")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("/api/v1/users/{}/suspend", remote_id))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(state.storage.read().await.get(&remote_id).is_none());
+
+    // The local list holds the local shard only
+    let request = Request::builder()
+        .uri("/api/v1/users")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let list: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(list["meta"]["pagination"]["count"], 1);
+}
+
+#[tokio::test]
+async fn test_replication_leader_serves_changes() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::contract::Contract;
+    use rust_api::replication::{self, Role};
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let contract = Contract::new();
+    let app = rust_api::router(AppState::with_config(Config {
+        replication: replication::Settings {
+            role: Some(Role::Leader),
+            ..replication::Settings::default()
+        },
+        ..Config::default()
+    }));
+    let send = |method: Method, path: String, body: Option<serde_json::Value>| {
+        let app = app.clone();
+        let contract = contract.clone();
+        async move {
+            let request = Request::builder()
+                .method(method.clone())
+                .uri(&path)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let index = response
+                .headers()
+                .get(replication::X_REPLICATION_INDEX)
+                .map(|value| value.to_str().unwrap().to_string());
+            let (status, body) = contract
+                .check_response(&method, &path, response)
+                .await
+                .unwrap_or_else(|err| panic!("{}", err));
+            let body = serde_json::from_slice::<serde_json::Value>(&body).unwrap_or_default();
+            (status, body, index)
+        }
+    };
+
+    let (status, body, index) = send(
+        Method::POST,
+        "/api/v1/users".to_string(),
+        Some(json!({"name": "Replicated", "email": "replicated@example.com"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(index.as_deref(), Some("1"));
+    let id = body["data"]["id"].as_str().unwrap().to_string();
+    let (status, _, index) = send(Method::DELETE, format!("/api/v1/users/{}", id), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(index.as_deref(), Some("2"));
+
+    let (status, body, _) = send(
+        Method::GET,
+        "/api/v1/admin/replication/snapshot".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["index"], 2);
+    assert_eq!(body["snapshot"]["trash"][0]["user"]["id"], id.as_str());
+    let log = body["log"].clone();
+
+    // The user's latest record, once, whatever happened to it since
+    let (status, body, _) = send(
+        Method::GET,
+        "/api/v1/admin/replication/changes?after=0".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["log"], log);
+    assert_eq!(body["index"], 2);
+    assert_eq!(body["records"].as_array().unwrap().len(), 1);
+    assert_eq!(body["records"][0]["user"], serde_json::Value::Null);
+    assert_eq!(body["records"][0]["trashed"]["user"]["id"], id.as_str());
+
+    let (status, body, _) = send(
+        Method::GET,
+        "/api/v1/admin/replication/changes?after=2&wait=0".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["records"], json!([]));
+
+    let (status, _, _) = send(
+        Method::GET,
+        "/api/v1/admin/replication/changes?after=3".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::GONE);
+
+    // Instances that are not leaders do not serve changes
+    let request = Request::builder()
+        .uri("/api/v1/admin/replication/changes")
+        .body(Body::empty())
+        .unwrap();
+    let response = rust_api::router(create_test_state())
+        .oneshot(request)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn test_follower_replicates_and_forwards_writes() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::replication::{self, Role};
+    use rust_api::Config;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let leader_url = format!("http://{}", listener.local_addr().unwrap());
+    let leader = AppState::with_config(Config {
+        replication: replication::Settings {
+            role: Some(Role::Leader),
+            ..replication::Settings::default()
+        },
+        ..Config::default()
+    });
+    let server = axum::serve(listener, rust_api::router(leader.clone()));
+    tokio::spawn(async move { server.await.unwrap() });
+
+    let client = reqwest::Client::new();
+    let existing: serde_json::Value = client
+        .post(format!("{}/api/v1/users", leader_url))
+        .json(&json!({"name": "Existing User", "email": "existing@example.com"}))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let existing_id = existing["data"]["id"].as_str().unwrap().to_string();
+
+    let follower = AppState::with_config(Config {
+        replication: replication::Settings {
+            role: Some(Role::Follower),
+            leader: Some(leader_url.parse().unwrap()),
+            token: None,
+        },
+        ..Config::default()
+    });
+    let replicating = replication::start(&follower).unwrap();
+    assert!(
+        follower
+            .replication
+            .wait_for(1, Duration::from_secs(5))
+            .await
+    );
+    assert!(follower.replication.is_synced());
+    let app = rust_api::router(follower.clone());
+    let get = |id: String| {
+        let app = app.clone();
+        async move {
+            let request = Request::builder()
+                .uri(format!("/api/v1/users/{}", id))
+                .body(Body::empty())
+                .unwrap();
+            app.oneshot(request).await.unwrap().status()
+        }
+    };
+    assert_eq!(get(existing_id.clone()).await, StatusCode::OK);
+
+    // Writes go to the leader and can be read back right away
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/v1/users")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({"name": "Forwarded User", "email": "forwarded@example.com"}).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let created: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let created_id = created["data"]["id"].as_str().unwrap().to_string();
+    assert_eq!(get(created_id.clone()).await, StatusCode::OK);
+    let id: uuid::Uuid = created_id.parse().unwrap();
+    assert!(leader.storage.read().await.get(&id).is_some());
+
+    // Changes made on the leader reach the follower
+    let response = client
+        .delete(format!("{}/api/v1/users/{}", leader_url, existing_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+    let index = leader.replication.index();
+    assert!(
+        follower
+            .replication
+            .wait_for(index, Duration::from_secs(5))
+            .await
+    );
+    assert_eq!(get(existing_id).await, StatusCode::GONE);
+    replicating.abort();
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn test_replica_serves_reads_and_refuses_writes() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use rust_api::replication::{self, Role};
+    use rust_api::Config;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let leader_url = format!("http://{}", listener.local_addr().unwrap());
+    let leader = AppState::with_config(Config {
+        replication: replication::Settings {
+            role: Some(Role::Leader),
+            ..replication::Settings::default()
+        },
+        ..Config::default()
+    });
+    let server = axum::serve(listener, rust_api::router(leader.clone()));
+    tokio::spawn(async move { server.await.unwrap() });
+
+    let created: serde_json::Value = reqwest::Client::new()
+        .post(format!("{}/api/v1/users", leader_url))
+        .json(&json!({"name": "Primary User", "email": "primary@example.com"}))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = created["data"]["id"].as_str().unwrap().to_string();
+
+    let replica = AppState::with_config(Config {
+        replication: replication::Settings {
+            role: Some(Role::Replica),
+            leader: Some(leader_url.parse().unwrap()),
+            token: None,
+        },
+        ..Config::default()
+    });
+    let replicating = replication::start(&replica).unwrap();
+    assert!(
+        replica
+            .replication
+            .wait_for(1, Duration::from_secs(5))
+            .await
+    );
+    let app = rust_api::router(replica.clone());
+    let request = Request::builder()
+        .uri(format!("/api/v1/users/{}", id))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Writes are refused with the primary's URL, and never stored
+    let request = Request::builder()
+        .method(Method::PUT)
+        .uri(format!("/api/v1/users/{}?dry_run=true", id))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({"name": "Renamed User"}).to_string()))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        format!("{}/api/v1/users/{}?dry_run=true", leader_url, id).as_str()
+    );
+    assert_eq!(response.headers()[header::ALLOW], "GET, HEAD");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(body["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains(&leader_url));
+    assert_eq!(leader.replication.index(), 1);
+    replicating.abort();
+}
+
+#[tokio::test]
+async fn test_requests_are_abandoned_at_their_deadline() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+        routing::get,
+    };
+    use rust_api::config::Config;
+    use rust_api::routes::{RouteGroup, RouterBuilder};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    let state = AppState::with_config(Config {
+        request_timeout: Some(Duration::from_millis(200)),
+        ..Config::default()
+    });
+    let app = RouterBuilder::with_defaults(state.clone())
+        .group(
+            RouteGroup::new("slow")
+                .route(
+                    "/slow",
+                    &[Method::GET],
+                    get(|| async {
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        "done"
+                    }),
+                )
+                .deadline(Duration::from_millis(20)),
+        )
+        .build();
+    let create = |timeout: &str| {
+        Request::post("/api/v1/users")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("x-request-timeout", timeout)
+            .body(Body::from(
+                json!({ "name": "Patient User", "email": "patient@example.com" }).to_string(),
+            ))
+            .unwrap()
+    };
+
+    // Waiting for the storage lock is given up, and nothing is stored
+    let held = state.storage.write().await;
+    let response = app.clone().oneshot(create("50ms")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"]["code"], "deadline_exceeded");
+    assert_eq!(body["error"]["retryable"], true);
+
+    // Clients cannot ask for more than the configured limit
+    let started = std::time::Instant::now();
+    let response = app.clone().oneshot(create("10s")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    drop(held);
+    assert!(state.storage.read().await.get_all().is_empty());
+
+    let response = app.clone().oneshot(create("soon")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = app.clone().oneshot(create("1s")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Route groups can set a shorter deadline of their own
+    let request = Request::get("/slow").body(Body::empty()).unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+}
+
+#[cfg(feature = "dashboard")]
+#[tokio::test]
+async fn test_admin_dashboard_is_served_with_admin_endpoints() {
+    use axum::{
+        body::Body,
+        http::{header, Request},
+    };
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let get = |state: AppState, path: &'static str, etag: Option<header::HeaderValue>| async move {
+        let mut request = Request::get(path);
+        if let Some(etag) = etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        let response = rust_api::router(state)
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, headers, body)
+    };
+
+    // Disabled by default
+    let (status, _, _) = get(create_test_state(), "/admin", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // The page is public, even with tokens configured; its data is not
+    let state = AppState::with_config(Config {
+        admin_endpoints: true,
+        api_tokens: ["ops:admin-token=admin".parse().unwrap()]
+            .into_iter()
+            .collect(),
+        ..Config::default()
+    });
+    let (status, headers, body) = get(state.clone(), "/admin", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::CONTENT_TYPE], "text/html; charset=utf-8");
+    assert!(String::from_utf8_lossy(&body).contains("/admin/app.js"));
+    let (status, _, _) = get(state.clone(), "/api/v1/admin/audit", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, headers, _) = get(state.clone(), "/admin/app.js", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        headers[header::CONTENT_TYPE],
+        "text/javascript; charset=utf-8"
+    );
+    let etag = headers[header::ETAG].clone();
+    let (status, _, body) = get(state.clone(), "/admin/app.js", Some(etag)).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert!(body.is_empty());
+
+    let (status, _, _) = get(state, "/admin/missing.js", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_deprecated_routes_announce_their_sunset() {
+    use axum::{body::Body, http::Method, http::Request};
+    use chrono::TimeZone;
+    use rust_api::deprecation::Deprecation;
+    use rust_api::routes::RouterBuilder;
+    use tower::ServiceExt;
+
+    let state = create_test_state();
+    let sunset = chrono::Utc.with_ymd_and_hms(2027, 6, 30, 0, 0, 0).unwrap();
+    let app = RouterBuilder::with_defaults(state.clone())
+        .deprecate(
+            Method::GET,
+            "/api/v1/users/:id",
+            Deprecation::new(sunset).successor("/api/v2/users/:id"),
+        )
+        .build();
+
+    // Error responses of the route announce it as well
+    let uri = format!("/api/v1/users/{}", uuid::Uuid::new_v4());
+    let response = app
+        .clone()
+        .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(response.headers()["deprecation"]
+        .to_str()
+        .unwrap()
+        .starts_with('@'));
+    assert_eq!(
+        response.headers()["sunset"],
+        "Wed, 30 Jun 2027 00:00:00 GMT"
+    );
+    assert_eq!(
+        response.headers()["link"],
+        "</api/v2/users/:id>; rel=\"successor-version\""
+    );
+
+    // Other routes are not
+    let response = app
+        .clone()
+        .oneshot(Request::get("/api/v1/users").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert!(!response.headers().contains_key("deprecation"));
+
+    let response = app
+        .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let metrics = String::from_utf8(body.to_vec()).unwrap();
+    assert!(metrics.contains("http_deprecated_requests_total{route=\"GET /api/v1/users/:id\"} 1\n"));
+}
+
+#[tokio::test]
+async fn test_routes_are_listed() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use chrono::TimeZone;
+    use rust_api::contract::Contract;
+    use rust_api::deprecation::Deprecation;
+    use rust_api::routes::{self, RouterBuilder};
+    use rust_api::Config;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    let state = AppState::with_config(Config {
+        admin_endpoints: true,
+        max_concurrent_requests: Some(64),
+        request_timeout: Some(Duration::from_secs(10)),
+        ..Config::default()
+    });
+    let sunset = chrono::Utc.with_ymd_and_hms(2027, 6, 30, 0, 0, 0).unwrap();
+    let app = RouterBuilder::with_defaults(state)
+        .map_group(routes::ADMIN, |group| {
+            group.deadline(Duration::from_secs(2))
+        })
+        .deprecate(
+            Method::POST,
+            "/api/v1/users/:id/merge/:remove_id",
+            Deprecation::new(sunset).successor("/api/v1/users/merge"),
+        )
+        .build();
+    let request = Request::get("/api/v1/_routes")
+        .header(header::ACCEPT, "application/json")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let (status, body) = Contract::new()
+        .check_response(&Method::GET, "/api/v1/_routes", response)
+        .await
+        .unwrap_or_else(|err| panic!("{}", err));
+    assert_eq!(status, StatusCode::OK);
+
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["authentication"], false);
+    assert_eq!(body["concurrency_limit"], 64);
+    let routes = body["routes"].as_array().unwrap();
+    assert_eq!(body["count"], routes.len());
+    let find = |method: &str, path: &str| {
+        routes
+            .iter()
+            .find(|route| route["method"] == method && route["path"] == path)
+            .unwrap_or_else(|| panic!("{} {} is not listed", method, path))
+    };
+
+    let route = find("GET", "/api/v1/users/:id");
+    assert_eq!(route["group"], "user_reads");
+    assert_eq!(route["scope"], "users:read");
+    assert_eq!(route["deadline_ms"], 10_000);
+    assert!(route["deprecation"].is_null());
+    assert_eq!(find("DELETE", "/api/v1/users/:id")["scope"], "users:write");
+    assert!(find("GET", "/metrics")["scope"].is_null());
+
+    let route = find("POST", "/api/v1/users/:id/merge/:remove_id");
+    assert_eq!(route["group"], "admin");
+    assert_eq!(route["deadline_ms"], 2_000);
+    assert_eq!(route["deprecation"]["successor"], "/api/v1/users/merge");
+    assert!(routes.iter().all(|route| route["method"] != "HEAD"));
+}
+
+#[tokio::test]
+async fn test_allowed_methods_come_from_the_route_table() {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use tower::ServiceExt;
+
+    let app = rust_api::router(create_test_state());
+    let send = |method: Method, uri: &str| {
+        app.clone().oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap(),
+        )
+    };
+
+    // Reads and writes of a user are added by different groups
+    let id = uuid::Uuid::new_v4();
+    let response = send(Method::PATCH, &format!("/api/v1/users/{}", id))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(
+        response.headers()[header::ALLOW],
+        "GET, HEAD, PUT, DELETE, OPTIONS"
+    );
+
+    let response = send(Method::OPTIONS, "/api/v1/users").await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        response.headers()[header::ALLOW],
+        "GET, HEAD, POST, OPTIONS"
+    );
+
+    let response = send(Method::OPTIONS, "/api/v1/nothing-here").await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_responses_name_their_language() {
+    use axum::{
+        body::Body,
+        http::{header, Request},
+    };
+    use rust_api::i18n::Language;
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let app = rust_api::router(AppState::with_config(Config {
+        default_language: Language::Fr,
+        ..Config::default()
+    }));
+
+    let request = Request::get("/api/v1/users").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_LANGUAGE], "fr");
+
+    // Without Accept-Language, errors use the configured default
+    let request = Request::get(format!("/api/v1/users/{}", uuid::Uuid::new_v4()))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.headers()[header::CONTENT_LANGUAGE], "fr");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(body["error"]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Utilisateur avec l'identifiant"));
+
+    let request = Request::get("/api/v1/users")
+        .header(header::ACCEPT_LANGUAGE, "es")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.headers()[header::CONTENT_LANGUAGE], "es");
+}
+
+#[tokio::test]
+async fn test_admin_reports() {
+    use axum::{body::Body, http::header, http::Method, http::Request};
+    use rust_api::Config;
+    use tower::ServiceExt;
+
+    let state = AppState::with_config(Config {
+        admin_endpoints: true,
+        ..Config::default()
+    });
+    let app = rust_api::router(state);
+    let send = |method: Method, path: String| {
+        let app = app.clone();
+        async move {
+            let request = Request::builder()
+                .method(method)
+                .uri(path)
+                .body(Body::empty())
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let content_type = response.headers()[header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .to_string();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (status, content_type, body)
+        }
+    };
+    let json = |body: &[u8]| serde_json::from_slice::<serde_json::Value>(body).unwrap();
+
+    let (status, _, body) = send(Method::GET, "/api/v1/admin/reports".to_string()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json(&body)["data"], json!([]));
+
+    let (status, _, body) = send(Method::POST, "/api/v1/admin/reports".to_string()).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let operation = json(&body)["data"].clone();
+    assert_eq!(operation["kind"], "report");
+    let location = format!("/api/v1/operations/{}", operation["id"].as_str().unwrap());
+    let mut operation = operation;
+    for _ in 0..100 {
+        if operation["status"] == "succeeded" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        operation = json(&send(Method::GET, location.clone()).await.2)["data"].clone();
+    }
+    assert_eq!(operation["status"], "succeeded");
+    let name = operation["result"]["report"]["name"].as_str().unwrap();
+    assert!(name.starts_with("users-weekly-"));
+
+    let (_, _, body) = send(Method::GET, "/api/v1/admin/reports".to_string()).await;
+    assert_eq!(json(&body)["data"][0]["name"], name);
+
+    let (status, content_type, body) =
+        send(Method::GET, format!("/api/v1/admin/reports/{}", name)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(content_type.starts_with("text/csv"));
+    let csv = String::from_utf8(body.to_vec()).unwrap();
+    assert!(csv.starts_with("week_start,new_users,churned_users\n"));
+    assert_eq!(csv.lines().count(), 13);
+
+    let (status, _, body) =
+        send(Method::GET, "/api/v1/admin/reports/missing.csv".to_string()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        json(&body)["error"]["message"],
+        "Report 'missing.csv' not found"
+    );
+
+    // Disabled by default
+    let app = rust_api::router(create_test_state());
+    let request = Request::get("/api/v1/admin/reports")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
 }